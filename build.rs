@@ -0,0 +1,13 @@
+//! Generates the gRPC service of the `server` feature from `proto/archive.proto`. The proto is
+//! compiled with protox, so building doesn't need `protoc` installed.
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=proto");
+    #[cfg(feature = "server")]
+    {
+        let descriptors = protox::compile(["proto/archive.proto"], ["proto"])?;
+        tonic_build::configure().compile_fds(descriptors)?;
+    }
+    Ok(())
+}
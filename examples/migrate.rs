@@ -0,0 +1,63 @@
+//! Copies an archive from one backend to another, resuming from its checkpoint file if it was
+//! interrupted, e.g.
+//!
+//! ```text
+//! cargo run --example migrate --features postgres -- \
+//!     mongodb "mongodb://localhost:27017" postgres "postgres://localhost/archive" \
+//!     lasr_archive migrate.checkpoint
+//! ```
+//!
+//! Backends are named `mongodb`, `postgres`, `sqlite`, `s3`, `rocksdb` or `filesystem`, for which
+//! the URI is the root directory.
+use anyhow::{bail, Context, Result};
+use lasr_archive::{ArchiveBackends, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder};
+use std::env;
+
+/// Builds a store for the named backend.
+fn store(backend: &str, uri: &str, datastore: &str) -> Result<ArchiveStore> {
+    // The filesystem backend takes its root directory in place of a URI.
+    let (backend, uri) = match backend {
+        #[cfg(feature = "mongodb")]
+        "mongodb" => (ArchiveBackends::MongoDB, uri),
+        #[cfg(feature = "postgres")]
+        "postgres" => (ArchiveBackends::Postgres, uri),
+        #[cfg(feature = "sqlite")]
+        "sqlite" => (ArchiveBackends::Sqlite, uri),
+        #[cfg(feature = "s3")]
+        "s3" => (ArchiveBackends::S3, uri),
+        #[cfg(feature = "rocksdb")]
+        "rocksdb" => (ArchiveBackends::RocksDb, uri),
+        "filesystem" => (ArchiveBackends::Filesystem { root: uri.into() }, ""),
+        other => bail!("Unknown backend '{}', or its feature isn't enabled", other),
+    };
+    Ok(ArchiveStoreBuilder::default()
+        .backend(backend)
+        .uri(uri.to_string())
+        .datastore(datastore.to_string())
+        .build()?)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let [from_backend, from_uri, to_backend, to_uri, datastore, checkpoint] = &args[..] else {
+        bail!(
+            "Usage: migrate <from-backend> <from-uri> <to-backend> <to-uri> <datastore> \
+            <checkpoint>"
+        );
+    };
+    let from = store(from_backend, from_uri, datastore).context("Invalid source store")?;
+    let to = store(to_backend, to_uri, datastore).context("Invalid destination store")?;
+
+    let rec_types = [
+        ArchiveRecordType::Account,
+        ArchiveRecordType::TransactionBatch,
+        ArchiveRecordType::Block,
+        ArchiveRecordType::Receipt,
+    ];
+    let copied = from.migrate_to(&to, &rec_types, checkpoint).await?;
+    println!("Copied {} records", copied);
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use anyhow::Result;
+use lasr_archive::{ArchiveRecord, ArchiveRecordType, ArchiveStore, ACCOUNT_ADDRESS_FIELD};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Account {
+    owner_address: String,
+    nonce: u64,
+}
+
+impl ArchiveRecord for Account {
+    const KEY_FIELD: Option<&'static str> = Some(ACCOUNT_ADDRESS_FIELD);
+
+    fn record_type() -> ArchiveRecordType {
+        ArchiveRecordType::Account
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+
+    // Get a handle on the persistence store, configured by the environment, e.g.
+    // LASR_ARCHIVE_URI=mongodb://localhost:27017 LASR_ARCHIVE_DATASTORE=lasr_archive
+    let store = ArchiveStore::from_env()?;
+    let accounts = store.typed::<Account>();
+
+    // Archive an account, then update it by its address
+    let account = Account {
+        owner_address: "0x0000000000000000000000000000000000000001".to_string(),
+        nonce: 0,
+    };
+    println!(
+        "Create outcome: {}",
+        accounts.create(account.clone()).await?
+    );
+    let id = accounts
+        .upsert(Account {
+            nonce: 1,
+            ..account.clone()
+        })
+        .await?;
+    println!("Upserted: {}", id);
+
+    // Read it back by its address
+    let found = accounts.find_by_key(account.owner_address.as_str()).await?;
+    println!("Found: {:?}", found);
+
+    Ok(())
+}
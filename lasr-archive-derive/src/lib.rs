@@ -0,0 +1,227 @@
+/// `#[derive(ArchiveRecord)]` for the `lasr-archive` crate, re-exported there behind its `derive`
+/// feature. The record type, key field, indexes and schema version of the implementation are
+/// taken from `#[archive(...)]` attributes, e.g.
+/// `#[archive(collection = "receipts", key = "tx_hash", version = 2)]` on the type.
+///
+/// On the type:
+/// - `collection = "name"` (required): the collection the records are stored in. The built-in
+///   `accounts`, `transaction_data`, `blocks` and `receipts` collections map to their
+///   `ArchiveRecordType`, other names to a custom record type, which must be a valid custom
+///   record type name.
+/// - `key = "field"`: the (possibly dotted) field records are looked up and upserted by.
+/// - `version = N`: the schema version records are written with, 1 by default.
+/// - `index = "field"` and `unique_index = "field"`: a secondary index, compound if the fields
+///   are separated by commas, e.g. `index = "owner_address,block_height"`. May be repeated.
+///
+/// On a field, which is named as serde names it, including `#[serde(rename = "...")]`:
+/// - `key`: the field is the key field.
+/// - `index` or `unique`: the field has a secondary (unique) index.
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Error, Fields, LitInt, LitStr, Result};
+
+/// Longest custom record type name `lasr-archive` allows
+const MAX_RECORD_TYPE_LEN: usize = 48;
+
+/// Derives `lasr_archive::ArchiveRecord` from `#[archive(...)]` attributes, see the crate docs.
+#[proc_macro_derive(ArchiveRecord, attributes(archive))]
+pub fn derive_archive_record(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// An index declared by an attribute: its fields and whether it's unique.
+struct Index {
+    fields: Vec<String>,
+    unique: bool,
+}
+
+fn expand(input: DeriveInput) -> Result<proc_macro2::TokenStream> {
+    let mut collection = None;
+    let mut key = None;
+    let mut version = None;
+    let mut indexes = Vec::new();
+
+    for attr in archive_attrs(&input.attrs) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("collection") {
+                let name: LitStr = meta.value()?.parse()?;
+                collection = Some(record_type(&name)?);
+            } else if meta.path.is_ident("key") {
+                let field: LitStr = meta.value()?.parse()?;
+                set_key(&mut key, field.value(), field.span())?;
+            } else if meta.path.is_ident("version") {
+                let lit: LitInt = meta.value()?.parse()?;
+                version = Some(lit.base10_parse::<u32>()?);
+            } else if meta.path.is_ident("index") || meta.path.is_ident("unique_index") {
+                let fields: LitStr = meta.value()?.parse()?;
+                indexes.push(Index {
+                    fields: index_fields(&fields)?,
+                    unique: meta.path.is_ident("unique_index"),
+                });
+            } else {
+                return Err(meta
+                    .error("expected `collection`, `key`, `version`, `index` or `unique_index`"));
+            }
+            Ok(())
+        })?;
+    }
+
+    if let Data::Struct(data) = &input.data {
+        if let Fields::Named(fields) = &data.fields {
+            for field in &fields.named {
+                let name = serde_name(&field.attrs)?.unwrap_or_else(|| {
+                    let ident = field.ident.as_ref().expect("named field").to_string();
+                    ident.trim_start_matches("r#").to_string()
+                });
+                for attr in archive_attrs(&field.attrs) {
+                    attr.parse_nested_meta(|meta| {
+                        if meta.path.is_ident("key") {
+                            set_key(&mut key, name.clone(), meta.path.require_ident()?.span())
+                        } else if meta.path.is_ident("index") || meta.path.is_ident("unique") {
+                            indexes.push(Index {
+                                fields: vec![name.clone()],
+                                unique: meta.path.is_ident("unique"),
+                            });
+                            Ok(())
+                        } else {
+                            Err(meta.error("expected `key`, `index` or `unique`"))
+                        }
+                    })?;
+                }
+            }
+        }
+    }
+
+    let Some(record_type) = collection else {
+        return Err(Error::new(
+            Span::call_site(),
+            "missing `#[archive(collection = \"...\")]` attribute",
+        ));
+    };
+    let key = match key {
+        Some(key) => quote!(::core::option::Option::Some(#key)),
+        None => quote!(::core::option::Option::None),
+    };
+    let version = match version {
+        Some(version) => quote!(#version),
+        None => quote!(::lasr_archive::DEFAULT_SCHEMA_VERSION),
+    };
+    let indexes = indexes.iter().map(|index| {
+        let fields = &index.fields;
+        let unique = index.unique.then(|| quote!(.unique()));
+        quote!(::lasr_archive::IndexSpec::compound(&[#(#fields),*]) #unique)
+    });
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::lasr_archive::ArchiveRecord for #name #ty_generics #where_clause {
+            const KEY_FIELD: ::core::option::Option<&'static str> = #key;
+            const SCHEMA_VERSION: u32 = #version;
+
+            fn record_type() -> ::lasr_archive::ArchiveRecordType {
+                #record_type
+            }
+
+            fn indexes() -> ::std::vec::Vec<::lasr_archive::IndexSpec> {
+                ::std::vec![#(#indexes),*]
+            }
+        }
+    })
+}
+
+fn archive_attrs(attrs: &[Attribute]) -> impl Iterator<Item = &Attribute> {
+    attrs.iter().filter(|attr| attr.path().is_ident("archive"))
+}
+
+fn set_key(key: &mut Option<String>, field: String, span: Span) -> Result<()> {
+    if key.is_some() {
+        return Err(Error::new(span, "the key field is declared more than once"));
+    }
+    *key = Some(field);
+    Ok(())
+}
+
+/// The record type stored in the named collection, checking custom names as
+/// `ArchiveRecordType::validate` would so that mistakes fail to compile.
+fn record_type(name: &LitStr) -> Result<proc_macro2::TokenStream> {
+    let value = name.value();
+    let variant = match value.as_str() {
+        "accounts" => quote!(Account),
+        "transaction_data" => quote!(TransactionBatch),
+        "blocks" => quote!(Block),
+        "receipts" => quote!(Receipt),
+        _ => {
+            let valid = !value.is_empty()
+                && value.len() <= MAX_RECORD_TYPE_LEN
+                && value.starts_with(|c: char| c.is_ascii_lowercase())
+                && value
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+                && !value.ends_with("_chunks");
+            if !valid {
+                return Err(Error::new(
+                    name.span(),
+                    format!(
+                        "collection must be 1 to {} lowercase letters, digits and underscores, \
+                         start with a letter and not end with `_chunks`",
+                        MAX_RECORD_TYPE_LEN
+                    ),
+                ));
+            }
+            quote!(Custom(::std::string::String::from(#value)))
+        }
+    };
+    Ok(quote!(::lasr_archive::ArchiveRecordType::#variant))
+}
+
+/// The fields of an index attribute, separated by commas.
+fn index_fields(lit: &LitStr) -> Result<Vec<String>> {
+    let fields: Vec<String> = lit
+        .value()
+        .split(',')
+        .map(|field| field.trim().to_string())
+        .collect();
+    if fields.iter().any(String::is_empty) {
+        return Err(Error::new(lit.span(), "empty index field"));
+    }
+    Ok(fields)
+}
+
+/// The name given to a field by `#[serde(rename = "...")]`, if any.
+fn serde_name(attrs: &[Attribute]) -> Result<Option<String>> {
+    let mut name = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                // `rename(serialize = "...", deserialize = "...")` names the field differently
+                // each way, records are stored as serialised.
+                if meta.input.peek(syn::Token![=]) {
+                    let lit: LitStr = meta.value()?.parse()?;
+                    name = Some(lit.value());
+                } else {
+                    meta.parse_nested_meta(|inner| {
+                        if inner.path.is_ident("serialize") {
+                            let lit: LitStr = inner.value()?.parse()?;
+                            name = Some(lit.value());
+                        } else {
+                            let _: LitStr = inner.value()?.parse()?;
+                        }
+                        Ok(())
+                    })?;
+                }
+            } else if meta.input.peek(syn::Token![=]) {
+                // Other serde attributes are irrelevant, but their values must be consumed.
+                let _: syn::Expr = meta.value()?.parse()?;
+            } else if meta.input.peek(syn::token::Paren) {
+                let _: proc_macro2::Group = meta.input.parse()?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(name)
+}
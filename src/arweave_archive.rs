@@ -0,0 +1,1155 @@
+/// An implementation of an archive datastore that stores records permanently, enabled with the
+/// `arweave` feature, for operators who must keep an immutable, externally verifiable copy of
+/// chain history. It's meant for write-once record types such as finalized transaction batches.
+/// Records are uploaded to a [PermanentStore]. By default this is Arweave, through the gateway
+/// at the URI, e.g. `https://arweave.net?wallet=/etc/lasr/arweave.json`. The `wallet` query
+/// parameter is the path of the JWK wallet file that pays for and signs transactions. The
+/// optional `max_fee` parameter is the most a single transaction may cost, in winston.
+///
+/// Every permanent upload is paid for, so the records of each write are bundled: the records
+/// passed together to `create_many`, e.g. by an [crate::ArchiveWriter], are uploaded as a few
+/// transactions of up to [MAX_BUNDLE_BYTES] each. A record's id is the id of its transaction and
+/// its position in the bundle, e.g. `{tx}.3`. Each transaction is tagged with the app, datastore
+/// and record type, so anyone can find and verify the bundles. The fee of a write can be estimated
+/// beforehand with [crate::ArchiveStore::estimate_fee].
+///
+/// A permaweb can't be listed, so the records of each [ArchiveRecordType] are kept in a small
+/// local index: a JSON lines file per record type in the directory given by
+/// [crate::ArchiveBackends::Arweave], named after the [ACCOUNT_INDEX] and [TRANSACTION_INDEX]
+/// constants or the custom record type's name. The index lists records in the order they were
+/// archived. Counts of every record and statistics only read the index. Queries fetch every bundle
+/// of the type and filter the records here.
+///
+/// Records are permanent, so they can't be updated or deleted, including by retention. Any `_id`
+/// a record is given is replaced by its id. Operations that need transactions or take MongoDB
+/// specific arguments fail with [Unsupported]. Records are never chunked.
+use crate::filter::lookup;
+use crate::stats::{aggregate, tally};
+use crate::{
+    AggregationSpec, ArchiveBackend, ArchiveCollectionStats, ArchiveError, ArchiveEvent,
+    ArchiveRecordType, ArchiveStore, Filter, GroupBy, IndexSpec, MergeMode, Page, PageRequest,
+    Unsupported,
+};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use bson::{doc, Bson, Document};
+use core::fmt;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use log::debug;
+use reqwest::{StatusCode, Url};
+use rsa::pss::BlindedSigningKey;
+use rsa::signature::{RandomizedSigner, SignatureEncoding};
+use rsa::{BigUint, RsaPrivateKey};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256, Sha384};
+use std::borrow::Borrow;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Mutex, OnceCell};
+
+/// Index of account data
+const ACCOUNT_INDEX: &str = "accounts";
+/// Index of transaction data
+const TRANSACTION_INDEX: &str = "transaction_data";
+/// Index of blocks
+const BLOCK_INDEX: &str = "blocks";
+/// Index of transaction receipts
+const RECEIPT_INDEX: &str = "receipts";
+/// Largest bundle of records uploaded in one transaction, small enough for gateways to accept
+/// the data in the transaction itself
+pub(crate) const MAX_BUNDLE_BYTES: usize = 10 * 1024 * 1024;
+/// Room left in a bundle for the document wrapping its records
+const BUNDLE_OVERHEAD: usize = 1024;
+/// Field of a bundle holding its records
+const RECORDS_FIELD: &str = "records";
+/// Value of the `App-Name` tag of every transaction
+const APP_NAME: &str = "lasr-archive";
+/// Number of bundles fetched at once
+const CONCURRENCY: usize = 8;
+/// Largest chunk of a transaction's data hashed into its data root
+const MAX_CHUNK_SIZE: usize = 256 * 1024;
+/// Smallest chunk of a transaction's data, other than its last
+const MIN_CHUNK_SIZE: usize = 32 * 1024;
+
+/// Permanent storage the Arweave backend uploads bundles of records to, such as Arweave or
+/// another permaweb. Implement it to archive somewhere other than an Arweave gateway. Set it
+/// with [crate::ArchiveStoreBuilder::permanent_store].
+#[async_trait]
+pub trait PermanentStore: fmt::Debug + Send + Sync {
+    /// The fee of permanently storing `bytes` bytes, in the store's smallest unit, e.g. winston.
+    async fn estimate_fee(&self, bytes: u64) -> Result<u128, ArchiveError>;
+
+    /// Stores `data` permanently with the given tags, returning the id of the upload.
+    async fn upload(
+        &self,
+        data: Vec<u8>,
+        tags: Vec<(String, String)>,
+    ) -> Result<String, ArchiveError>;
+
+    /// The data uploaded under `id`, or `None` if it isn't available yet, e.g. because its
+    /// upload hasn't been confirmed.
+    async fn download(&self, id: &str) -> Result<Option<Vec<u8>>, ArchiveError>;
+}
+
+/// The settings of an Arweave gateway URI.
+#[derive(Debug, PartialEq)]
+struct GatewayUri {
+    /// The gateway, without the settings
+    url: Url,
+    /// Path of the JWK wallet file
+    wallet: Option<PathBuf>,
+    /// Most a transaction may cost, in winston
+    max_fee: Option<u128>,
+}
+
+impl GatewayUri {
+    fn parse(uri: &str) -> std::result::Result<Self, String> {
+        let mut url = Url::parse(uri).map_err(|e| format!("Invalid Arweave gateway URI: {}", e))?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err("Arweave gateway URI must start with http:// or https://".to_string());
+        }
+        let mut wallet = None;
+        let mut max_fee = None;
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "wallet" => wallet = Some(PathBuf::from(value.as_ref())),
+                "max_fee" => {
+                    max_fee = Some(value.parse().map_err(|_| {
+                        format!("Invalid max_fee '{}', expected an amount in winston", value)
+                    })?)
+                }
+                other => return Err(format!("Unknown Arweave gateway URI parameter '{}'", other)),
+            }
+        }
+        url.set_query(None);
+        Ok(GatewayUri {
+            url,
+            wallet,
+            max_fee,
+        })
+    }
+}
+
+/// An Arweave wallet's key, as a JWK file.
+#[derive(serde_derive::Deserialize)]
+struct Jwk {
+    n: String,
+    e: String,
+    d: String,
+    p: String,
+    q: String,
+}
+
+/// A wallet transactions are signed and paid for with.
+struct Wallet {
+    key: BlindedSigningKey<Sha256>,
+    /// The public modulus, identifying the wallet
+    owner: Vec<u8>,
+}
+
+impl Wallet {
+    /// Reads a JWK wallet file.
+    async fn read(path: &Path) -> Result<Self> {
+        let contents = fs::read(path)
+            .await
+            .with_context(|| format!("Reading Arweave wallet {}", path.display()))?;
+        let jwk: Jwk = serde_json::from_slice(&contents)
+            .with_context(|| format!("Invalid Arweave wallet {}", path.display()))?;
+        let owner = decode_b64(&jwk.n)?;
+        let number = |value: &str| decode_b64(value).map(|bytes| BigUint::from_bytes_be(&bytes));
+        let key = RsaPrivateKey::from_components(
+            BigUint::from_bytes_be(&owner),
+            number(&jwk.e)?,
+            number(&jwk.d)?,
+            vec![number(&jwk.p)?, number(&jwk.q)?],
+        )
+        .with_context(|| format!("Invalid Arweave wallet key {}", path.display()))?;
+        Ok(Wallet {
+            key: BlindedSigningKey::new(key),
+            owner,
+        })
+    }
+}
+
+/// An Arweave gateway, e.g. `arweave.net`, or a node.
+pub(crate) struct ArweaveGateway {
+    uri: std::result::Result<GatewayUri, String>,
+    client: reqwest::Client,
+    /// The wallet, read on the first upload
+    wallet: OnceCell<Wallet>,
+}
+
+impl fmt::Debug for ArweaveGateway {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Never print the wallet's key.
+        f.debug_struct("ArweaveGateway")
+            .field("uri", &self.uri)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ArweaveGateway {
+    pub(crate) fn new(uri: &str) -> Self {
+        ArweaveGateway {
+            uri: GatewayUri::parse(uri),
+            client: reqwest::Client::new(),
+            wallet: OnceCell::new(),
+        }
+    }
+
+    fn uri(&self) -> Result<&GatewayUri, ArchiveError> {
+        self.uri
+            .as_ref()
+            .map_err(|e| ArchiveError::invalid_input(e.clone()))
+    }
+
+    /// The URL of a path of the gateway's API.
+    fn url(&self, path: &str) -> Result<Url, ArchiveError> {
+        Ok(self
+            .uri()?
+            .url
+            .join(path)
+            .with_context(|| format!("Invalid Arweave gateway path {}", path))?)
+    }
+
+    /// The wallet transactions are signed with.
+    async fn wallet(&self) -> Result<&Wallet, ArchiveError> {
+        let path = self.uri()?.wallet.as_ref().ok_or_else(|| {
+            ArchiveError::invalid_input(
+                "Uploading to Arweave needs a wallet, given as the gateway URI's wallet parameter",
+            )
+        })?;
+        Ok(self.wallet.get_or_try_init(|| Wallet::read(path)).await?)
+    }
+
+    /// GETs a path of the gateway's API as text.
+    async fn get_text(&self, path: &str) -> Result<String, ArchiveError> {
+        let response = self
+            .client
+            .get(self.url(path)?)
+            .send()
+            .await
+            .with_context(|| format!("Calling Arweave gateway /{}", path))?;
+        let response = check(response, path).await?;
+        Ok(response
+            .text()
+            .await
+            .with_context(|| format!("Reading Arweave gateway /{}", path))?)
+    }
+}
+
+/// Fails with the body of an unsuccessful response. Gateway failures are retryable.
+async fn check(response: reqwest::Response, path: &str) -> Result<reqwest::Response, ArchiveError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    let e = anyhow!("Arweave gateway /{} failed with {}: {}", path, status, body);
+    Err(
+        match status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            true => ArchiveError::BackendUnavailable(e),
+            false => ArchiveError::Other(e),
+        },
+    )
+}
+
+#[async_trait]
+impl PermanentStore for ArweaveGateway {
+    async fn estimate_fee(&self, bytes: u64) -> Result<u128, ArchiveError> {
+        let price = self.get_text(&format!("price/{}", bytes)).await?;
+        Ok(price
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid Arweave price '{}'", price))?)
+    }
+
+    /// Signs a format 2 transaction holding the data and posts it to the gateway, failing if
+    /// its fee exceeds the URI's `max_fee`.
+    async fn upload(
+        &self,
+        data: Vec<u8>,
+        tags: Vec<(String, String)>,
+    ) -> Result<String, ArchiveError> {
+        let wallet = self.wallet().await?;
+        let reward = self.estimate_fee(data.len() as u64).await?;
+        if let Some(max_fee) = self.uri()?.max_fee {
+            if reward > max_fee {
+                return Err(ArchiveError::invalid_input(format!(
+                    "Storing {} bytes on Arweave costs {} winston, over the maximum fee of {}",
+                    data.len(),
+                    reward,
+                    max_fee
+                )));
+            }
+        }
+        let anchor = decode_b64(self.get_text("tx_anchor").await?.trim())?;
+        let data_root = data_root(&data);
+        let reward = reward.to_string();
+        let data_size = data.len().to_string();
+
+        let tag_items = tags
+            .iter()
+            .map(|(name, value)| {
+                DeepHash::List(vec![
+                    DeepHash::Blob(name.as_bytes()),
+                    DeepHash::Blob(value.as_bytes()),
+                ])
+            })
+            .collect();
+        let message = deep_hash(&DeepHash::List(vec![
+            DeepHash::Blob(b"2"),
+            DeepHash::Blob(&wallet.owner),
+            DeepHash::Blob(&[]),
+            DeepHash::Blob(b"0"),
+            DeepHash::Blob(reward.as_bytes()),
+            DeepHash::Blob(&anchor),
+            DeepHash::List(tag_items),
+            DeepHash::Blob(data_size.as_bytes()),
+            DeepHash::Blob(&data_root),
+        ]));
+        let signature = wallet
+            .key
+            .sign_with_rng(&mut rand::thread_rng(), &message)
+            .to_bytes();
+        let id = encode_b64(&Sha256::digest(&signature));
+
+        let tags: Vec<_> = tags
+            .iter()
+            .map(|(name, value)| {
+                json!({
+                    "name": encode_b64(name.as_bytes()),
+                    "value": encode_b64(value.as_bytes()),
+                })
+            })
+            .collect();
+        let transaction = json!({
+            "format": 2,
+            "id": id,
+            "last_tx": encode_b64(&anchor),
+            "owner": encode_b64(&wallet.owner),
+            "tags": tags,
+            "target": "",
+            "quantity": "0",
+            "data": encode_b64(&data),
+            "data_size": data_size,
+            "data_root": encode_b64(&data_root),
+            "reward": reward,
+            "signature": encode_b64(&signature),
+        });
+        let response = self
+            .client
+            .post(self.url("tx")?)
+            .json(&transaction)
+            .send()
+            .await
+            .context("Posting Arweave transaction")?;
+        check(response, "tx").await?;
+        Ok(id)
+    }
+
+    async fn download(&self, id: &str) -> Result<Option<Vec<u8>>, ArchiveError> {
+        let response = self
+            .client
+            .get(self.url(id)?)
+            .send()
+            .await
+            .with_context(|| format!("Fetching Arweave transaction {}", id))?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = check(response, id).await?;
+        let data = response
+            .bytes()
+            .await
+            .with_context(|| format!("Fetching Arweave transaction {}", id))?;
+        Ok(Some(data.to_vec()))
+    }
+}
+
+fn encode_b64(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn decode_b64(value: &str) -> Result<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(value)
+        .with_context(|| format!("Invalid base64url '{}'", value))
+}
+
+/// An item hashed into a transaction's signature.
+enum DeepHash<'a> {
+    Blob(&'a [u8]),
+    List(Vec<DeepHash<'a>>),
+}
+
+/// Arweave's deep hash of an item, which is what transactions sign.
+fn deep_hash(item: &DeepHash) -> [u8; 48] {
+    match item {
+        DeepHash::Blob(data) => {
+            let mut tagged = Sha384::digest(format!("blob{}", data.len())).to_vec();
+            tagged.extend_from_slice(&Sha384::digest(data));
+            Sha384::digest(&tagged).into()
+        }
+        DeepHash::List(items) => {
+            let mut hash: [u8; 48] = Sha384::digest(format!("list{}", items.len())).into();
+            for item in items {
+                let mut pair = hash.to_vec();
+                pair.extend_from_slice(&deep_hash(item));
+                hash = Sha384::digest(&pair).into();
+            }
+            hash
+        }
+    }
+}
+
+/// An offset into a transaction's data as hashed into its data root.
+fn note(offset: usize) -> [u8; 32] {
+    let mut note = [0; 32];
+    note[24..].copy_from_slice(&(offset as u64).to_be_bytes());
+    note
+}
+
+/// SHA-256 of the concatenation of `parts`.
+fn sha256(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// The root of the Merkle tree of a transaction's data chunks, which its signature covers in
+/// place of the data itself.
+fn data_root(data: &[u8]) -> [u8; 32] {
+    // The last two chunks are balanced so that no chunk but the last is under the minimum size.
+    let mut leaves = Vec::new();
+    let mut rest = data;
+    let mut end = 0;
+    while rest.len() >= MAX_CHUNK_SIZE {
+        let mut size = MAX_CHUNK_SIZE;
+        let remaining = rest.len() - MAX_CHUNK_SIZE;
+        if remaining > 0 && remaining < MIN_CHUNK_SIZE {
+            size = rest.len().div_ceil(2);
+        }
+        end += size;
+        leaves.push((leaf(&rest[..size], end), end));
+        rest = &rest[size..];
+    }
+    end += rest.len();
+    leaves.push((leaf(rest, end), end));
+
+    // Nodes are (id, end of the data they cover), and a node without a pair is carried up.
+    let mut nodes = leaves;
+    while nodes.len() > 1 {
+        nodes = nodes
+            .chunks(2)
+            .map(|pair| match pair {
+                [(left, left_end), (right, right_end)] => (
+                    sha256(&[
+                        &sha256(&[left]),
+                        &sha256(&[right]),
+                        &sha256(&[&note(*left_end)]),
+                    ]),
+                    *right_end,
+                ),
+                [node] => *node,
+                _ => unreachable!("chunks of two"),
+            })
+            .collect();
+    }
+    nodes[0].0
+}
+
+/// The id of the leaf of a chunk ending at `end`.
+fn leaf(chunk: &[u8], end: usize) -> [u8; 32] {
+    sha256(&[&sha256(&[&sha256(&[chunk])]), &sha256(&[&note(end)])])
+}
+
+/// Splits records into bundles of up to [MAX_BUNDLE_BYTES], keeping their order, failing if a
+/// record is too large for a bundle by itself.
+pub(crate) fn bundle(docs: Vec<Document>) -> Result<Vec<Vec<u8>>, ArchiveError> {
+    let limit = MAX_BUNDLE_BYTES - BUNDLE_OVERHEAD;
+    let mut bundles = Vec::new();
+    let mut records = Vec::new();
+    let mut size = 0;
+    for mut doc in docs {
+        doc.remove("_id");
+        let bytes = bson::to_vec(&doc).context("Failed to serialise record to BSON")?;
+        if bytes.len() > limit {
+            return Err(ArchiveError::invalid_input(format!(
+                "A record of {} bytes is too large for an Arweave bundle of at most {} bytes",
+                bytes.len(),
+                limit
+            )));
+        }
+        if size + bytes.len() > limit {
+            bundles.push(bundle_bytes(std::mem::take(&mut records))?);
+            size = 0;
+        }
+        size += bytes.len();
+        records.push(Bson::Document(doc));
+    }
+    if !records.is_empty() {
+        bundles.push(bundle_bytes(records)?);
+    }
+    Ok(bundles)
+}
+
+fn bundle_bytes(records: Vec<Bson>) -> Result<Vec<u8>, ArchiveError> {
+    Ok(bson::to_vec(&doc! { RECORDS_FIELD: records }).context("Failed to serialise bundle")?)
+}
+
+/// A record listed in the index of its record type.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+struct IndexEntry {
+    /// Transaction holding the record's bundle
+    tx: String,
+    /// Position of the record in the bundle
+    n: usize,
+    /// Size of the record's BSON
+    bytes: u64,
+    /// When the record was archived, in milliseconds since the epoch
+    archived_at: i64,
+}
+
+impl IndexEntry {
+    /// The record's id.
+    fn id(&self) -> String {
+        format!("{}.{}", self.tx, self.n)
+    }
+}
+
+/// Reads an index file. A missing file lists no records.
+async fn read_index(path: &Path) -> Result<Vec<IndexEntry>> {
+    let contents = match fs::read_to_string(path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Reading Arweave index {}", path.display()))
+        }
+    };
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("Invalid entry on line {} of {}", i + 1, path.display()))
+        })
+        .collect()
+}
+
+/// Decodes the documents fetched from the bundles into records.
+fn decode<T: DeserializeOwned>(docs: Vec<Document>) -> Result<Vec<T>> {
+    docs.into_iter()
+        .map(|doc| bson::from_document(doc).context("Failed to deserialise record"))
+        .collect()
+}
+
+/// Records are permanent, so can't be changed or removed.
+fn permanent(operation: &'static str) -> ArchiveError {
+    Unsupported {
+        operation,
+        reason: "records in the Arweave backend are permanent".to_string(),
+    }
+    .into()
+}
+
+#[derive(Debug)]
+pub struct ArweaveBackend {
+    /// Directory the index of each record type is kept in
+    pub index_dir: PathBuf,
+    /// Datastore the transactions are tagged with
+    pub datastore: String,
+    store: Arc<dyn PermanentStore>,
+    /// The index of each record type read so far. Writes to an index hold the lock until its
+    /// file is written, so they never interleave.
+    indexes: Mutex<HashMap<ArchiveRecordType, Vec<IndexEntry>>>,
+}
+
+impl ArweaveBackend {
+    pub fn new(index_dir: &Path, datastore: &str, store: Arc<dyn PermanentStore>) -> Self {
+        ArweaveBackend {
+            index_dir: index_dir.to_path_buf(),
+            datastore: datastore.to_string(),
+            store,
+            indexes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks that the URI, if given, is an Arweave gateway URI with valid settings. It may be
+    /// left empty when records are stored in another [PermanentStore].
+    pub fn validate_uri(uri: &str) -> std::result::Result<(), String> {
+        if uri.is_empty() {
+            return Ok(());
+        }
+        GatewayUri::parse(uri).map(|_| ())
+    }
+
+    /// Checks that the datastore name is usable as a transaction tag.
+    pub fn validate_datastore(datastore: &str) -> std::result::Result<(), String> {
+        if datastore.is_empty() {
+            return Err("Datastore name must not be empty".to_string());
+        }
+        Ok(())
+    }
+
+    /// The `Record-Type` tag of the given record type's transactions, also naming its index.
+    fn name(rec_type: &ArchiveRecordType) -> &str {
+        match rec_type {
+            ArchiveRecordType::Account => ACCOUNT_INDEX,
+            ArchiveRecordType::TransactionBatch => TRANSACTION_INDEX,
+            ArchiveRecordType::Block => BLOCK_INDEX,
+            ArchiveRecordType::Receipt => RECEIPT_INDEX,
+            ArchiveRecordType::Custom(name) => name,
+        }
+    }
+
+    /// File holding the index of the given record type.
+    fn index_path(&self, rec_type: &ArchiveRecordType) -> PathBuf {
+        self.index_dir
+            .join(format!("{}.jsonl", Self::name(rec_type)))
+    }
+
+    /// The index of the given record type, read from its file the first time it's needed.
+    async fn index<'a>(
+        &self,
+        indexes: &'a mut HashMap<ArchiveRecordType, Vec<IndexEntry>>,
+        rec_type: &ArchiveRecordType,
+    ) -> Result<&'a mut Vec<IndexEntry>> {
+        Ok(match indexes.entry(rec_type.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(read_index(&self.index_path(rec_type)).await?),
+        })
+    }
+
+    /// The records of the given type listed in its index, in the order they were archived.
+    async fn entries(&self, rec_type: &ArchiveRecordType) -> Result<Vec<IndexEntry>> {
+        let mut indexes = self.indexes.lock().await;
+        Ok(self.index(&mut indexes, rec_type).await?.clone())
+    }
+
+    /// Appends records to the index of their type.
+    async fn add(&self, rec_type: &ArchiveRecordType, entries: Vec<IndexEntry>) -> Result<()> {
+        let mut indexes = self.indexes.lock().await;
+        let index = self.index(&mut indexes, rec_type).await?;
+
+        let mut lines = String::new();
+        for entry in &entries {
+            lines.push_str(&serde_json::to_string(entry).context("Serialising index entry")?);
+            lines.push('\n');
+        }
+        let path = self.index_path(rec_type);
+        fs::create_dir_all(&self.index_dir)
+            .await
+            .with_context(|| format!("Creating index directory {}", self.index_dir.display()))?;
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("Opening Arweave index {}", path.display()))?;
+        file.write_all(lines.as_bytes()).await?;
+        file.sync_all()
+            .await
+            .with_context(|| format!("Writing Arweave index {}", path.display()))?;
+
+        index.extend(entries);
+        Ok(())
+    }
+
+    /// Uploads records in bundles and lists them in the index, returning their ids in order.
+    async fn write(
+        &self,
+        rec_type: &ArchiveRecordType,
+        docs: Vec<Document>,
+    ) -> Result<Vec<String>> {
+        let tags = vec![
+            ("Content-Type".to_string(), "application/bson".to_string()),
+            ("App-Name".to_string(), APP_NAME.to_string()),
+            ("Datastore".to_string(), self.datastore.clone()),
+            ("Record-Type".to_string(), Self::name(rec_type).to_string()),
+        ];
+        let mut ids = Vec::with_capacity(docs.len());
+        for data in bundle(docs)? {
+            let records = bson::from_slice::<Document>(&data)?
+                .get_array(RECORDS_FIELD)?
+                .iter()
+                .map(|rec| bson::to_vec(&rec).map(|bytes| bytes.len() as u64))
+                .collect::<Result<Vec<_>, _>>()?;
+            let tx = self.store.upload(data, tags.clone()).await?;
+            let archived_at = bson::DateTime::now().timestamp_millis();
+            let entries: Vec<IndexEntry> = records
+                .into_iter()
+                .enumerate()
+                .map(|(n, bytes)| IndexEntry {
+                    tx: tx.clone(),
+                    n,
+                    bytes,
+                    archived_at,
+                })
+                .collect();
+            ids.extend(entries.iter().map(IndexEntry::id));
+            self.add(rec_type, entries).await?;
+            debug!("Uploaded bundle {} to Arweave", tx);
+        }
+        Ok(ids)
+    }
+
+    /// Fetches the records of a bundle.
+    async fn fetch_bundle(&self, tx: &str) -> Result<Vec<Bson>> {
+        let data = self.store.download(tx).await?.ok_or_else(|| {
+            ArchiveError::NotFound(anyhow!(
+                "Arweave transaction {} isn't available yet, or was never confirmed",
+                tx
+            ))
+        })?;
+        let mut bundle: Document =
+            bson::from_slice(&data).with_context(|| format!("Invalid Arweave bundle {}", tx))?;
+        match bundle.remove(RECORDS_FIELD) {
+            Some(Bson::Array(records)) => Ok(records),
+            _ => Err(anyhow!("Arweave bundle {} holds no records", tx)),
+        }
+    }
+
+    /// Fetches the listed records, with their ids as their `_id`, keeping their order. Each
+    /// bundle is only fetched once for records listed together.
+    fn fetch_all(&self, entries: Vec<IndexEntry>) -> BoxStream<'_, Result<Document>> {
+        let mut groups: Vec<(String, Vec<usize>)> = Vec::new();
+        for entry in entries {
+            match groups.last_mut() {
+                Some((tx, positions)) if *tx == entry.tx => positions.push(entry.n),
+                _ => groups.push((entry.tx, vec![entry.n])),
+            }
+        }
+        stream::iter(groups)
+            .map(move |(tx, positions)| async move {
+                let mut records = self.fetch_bundle(&tx).await?;
+                positions
+                    .into_iter()
+                    .map(|n| match records.get_mut(n).map(std::mem::take) {
+                        Some(Bson::Document(content)) => {
+                            let mut doc = doc! { "_id": format!("{}.{}", tx, n) };
+                            doc.extend(content);
+                            Ok(doc)
+                        }
+                        _ => Err(anyhow!("Arweave bundle {} has no record {}", tx, n)),
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .buffered(CONCURRENCY)
+            .map_ok(|docs| stream::iter(docs.into_iter().map(Ok)))
+            .try_flatten()
+            .boxed()
+    }
+
+    /// Fetches the records of the given type matching a filter.
+    async fn matching(
+        &self,
+        rec_type: &ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<Vec<Document>> {
+        let entries = self.entries(rec_type).await?;
+        self.fetch_all(entries)
+            .try_filter(|doc| futures::future::ready(filter.matches(doc)))
+            .try_collect()
+            .await
+    }
+}
+
+impl ArchiveStore {
+    /// Estimates the fee of archiving the records as [ArchiveRecordType] in the Arweave
+    /// backend, in the [PermanentStore]'s smallest unit, e.g. winston. Records are encoded and
+    /// bundled as `create_many` would, so pass the records of a single write. Fails with
+    /// [ArchiveError::Unsupported] for other backends.
+    pub async fn estimate_fee<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        recs: &[T],
+    ) -> Result<u128, ArchiveError>
+    where
+        T: Serialize,
+    {
+        let crate::ArchiveBackends::Arweave { ref index } = self.inner.backend else {
+            return Err(Unsupported {
+                operation: "estimate_fee",
+                reason: format!("the {} backend charges no fees", self.inner.backend),
+            }
+            .into());
+        };
+        let docs = recs
+            .iter()
+            .map(|rec| self.encode(&rec_type, rec))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut fee = 0;
+        for data in bundle(docs)? {
+            fee += self
+                .arweave(index)
+                .store
+                .estimate_fee(data.len() as u64)
+                .await?;
+        }
+        Ok(fee)
+    }
+}
+
+#[async_trait]
+impl ArchiveBackend for ArweaveBackend {
+    /// Uploads the record in a bundle of its own.
+    async fn create<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+        let mut ids = self.write(&rec_type, vec![doc]).await?;
+        Ok(ids.remove(0))
+    }
+
+    /// Uploads the records in as few bundles as fit them. Bundles uploaded before a failure are
+    /// kept, whatever the ordering setting.
+    async fn create_many<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        recs: Vec<T>,
+    ) -> Result<Vec<String>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let docs = recs
+            .iter()
+            .map(|rec| bson::to_document(rec).context("Failed to serialise record to BSON"))
+            .collect::<Result<Vec<_>>>()?;
+        let ids = self.write(&rec_type, docs).await?;
+        debug!("Uploaded {} records to Arweave", ids.len());
+        Ok(ids)
+    }
+
+    /// Bundles only hold records of one type.
+    async fn create_atomic(
+        &self,
+        _records: Vec<(ArchiveRecordType, Document)>,
+    ) -> Result<Vec<String>, ArchiveError> {
+        Err(Unsupported {
+            operation: "create_atomic",
+            reason: "the Arweave backend has no transactions spanning record types".to_string(),
+        }
+        .into())
+    }
+
+    async fn find_all<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let entries = self.entries(&rec_type).await?;
+        Ok(decode(self.fetch_all(entries).try_collect().await?)?)
+    }
+
+    /// Streams the records, fetching their bundles as they are needed.
+    async fn find_all_stream<'a, T: DeserializeOwned>(
+        &'a self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'a, Result<T, ArchiveError>>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin + 'a,
+    {
+        let entries = self.entries(&rec_type).await?;
+        Ok(self
+            .fetch_all(entries)
+            .and_then(|doc| async move {
+                bson::from_document(doc).context("Failed to deserialise record")
+            })
+            .map_err(ArchiveError::from)
+            .boxed())
+    }
+
+    /// Pages through the records in the order they were archived, the token being the id of the
+    /// last record of the page.
+    async fn find_page<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        request: &PageRequest,
+    ) -> Result<Page<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let mut entries = self.entries(&rec_type).await?;
+        if let Some(after) = &request.after_token {
+            let position = entries
+                .iter()
+                .position(|entry| entry.id() == *after)
+                .ok_or_else(|| {
+                    ArchiveError::invalid_input(format!("Unknown page token '{}'", after))
+                })?;
+            entries.drain(..=position);
+        }
+        let next_token = match entries.len() > request.limit {
+            true => Some(entries[request.limit - 1].id()),
+            false => None,
+        };
+        entries.truncate(request.limit);
+
+        Ok(Page {
+            items: decode(self.fetch_all(entries).try_collect().await?)?,
+            next_token,
+        })
+    }
+
+    /// Query data store for the records of the given type matching a [Filter]. Every bundle of
+    /// the type is fetched and its records filtered here.
+    async fn query<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        Ok(decode(self.matching(&rec_type, filter).await?)?)
+    }
+
+    /// Bundles are fetched whole, so whole documents are returned.
+    async fn find_projected(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        _fields: &[String],
+    ) -> Result<Vec<Document>, ArchiveError> {
+        self.query::<Document>(rec_type, filter).await
+    }
+
+    /// Only records listed in the index of the type are found.
+    async fn find_by_id<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<Option<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let entries = self.entries(&rec_type).await?;
+        let Some(entry) = entries.into_iter().find(|entry| entry.id() == id) else {
+            return Ok(None);
+        };
+        let doc = self.fetch_all(vec![entry]).try_next().await?;
+        Ok(doc
+            .map(|doc| bson::from_document(doc).context("Failed to deserialise record"))
+            .transpose()?)
+    }
+
+    async fn delete_by_id(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _id: &str,
+    ) -> Result<bool, ArchiveError> {
+        Err(permanent("delete_by_id"))
+    }
+
+    async fn delete_where(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        Err(permanent("delete_where"))
+    }
+
+    async fn update_by_id<T: Serialize>(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _id: &str,
+        _rec: T,
+    ) -> Result<bool, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        Err(permanent("update_by_id"))
+    }
+
+    async fn upsert<T: Serialize>(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _filter: &Filter,
+        _rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        Err(permanent("upsert"))
+    }
+
+    /// Returns a random sample of roughly `rate` (0.0 to 1.0) of the records of the given type,
+    /// selecting each record independently with probability `rate`. Only the bundles of the
+    /// selected records are fetched.
+    async fn find_sampled<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rate: f64,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let mut entries = self.entries(&rec_type).await?;
+        entries.retain(|_| rand::random::<f64>() < rate);
+        Ok(decode(self.fetch_all(entries).try_collect().await?)?)
+    }
+
+    /// Records are never chunked, so there are never orphaned chunks.
+    async fn find_orphaned_chunks(
+        &self,
+        _rec_type: ArchiveRecordType,
+    ) -> Result<Vec<String>, ArchiveError> {
+        Ok(Vec::new())
+    }
+
+    /// Records are never chunked, so there are never orphaned chunks.
+    async fn cleanup_orphans(&self, _rec_type: ArchiveRecordType) -> Result<u64, ArchiveError> {
+        Ok(0)
+    }
+
+    /// Uploads are permanent, so there is nothing to reclaim.
+    async fn reclaim_space(&self, _rec_type: ArchiveRecordType) -> Result<(), ArchiveError> {
+        Ok(())
+    }
+
+    /// Aggregation pipelines are MongoDB specific.
+    async fn merge_into(
+        &self,
+        _source: ArchiveRecordType,
+        _pipeline: Vec<Document>,
+        _target: ArchiveRecordType,
+        _mode: MergeMode,
+    ) -> Result<u64, ArchiveError> {
+        Err(Unsupported {
+            operation: "merge_into",
+            reason: "aggregation pipelines are only supported by the MongoDB backend".to_string(),
+        }
+        .into())
+    }
+
+    /// Changes are only reported by the store that made them.
+    async fn watch(
+        &self,
+        _rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'static, Result<ArchiveEvent, ArchiveError>>, ArchiveError> {
+        Err(Unsupported {
+            operation: "watch",
+            reason: "change streams are only supported by the MongoDB backend".to_string(),
+        }
+        .into())
+    }
+
+    /// [Filter::All] counts the records in the index. Otherwise every bundle of the type is
+    /// fetched and its records filtered here.
+    async fn count(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        if let Filter::All = filter {
+            return Ok(self.entries(&rec_type).await?.len() as u64);
+        }
+        Ok(self.matching(&rec_type, filter).await?.len() as u64)
+    }
+
+    /// [Filter::All] only reads the index. Otherwise bundles of the type are fetched and their
+    /// records filtered here until one matches.
+    async fn exists(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<bool, ArchiveError> {
+        let entries = self.entries(&rec_type).await?;
+        if let Filter::All = filter {
+            return Ok(!entries.is_empty());
+        }
+
+        Ok(self
+            .fetch_all(entries)
+            .try_filter(|doc| futures::future::ready(filter.matches(doc)))
+            .try_next()
+            .await?
+            .is_some())
+    }
+
+    /// Counts the records in the index and totals their sizes.
+    async fn stats(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<ArchiveCollectionStats, ArchiveError> {
+        let entries = self.entries(&rec_type).await?;
+        let document_count = entries.len() as u64;
+        let storage_bytes = entries.iter().map(|entry| entry.bytes).sum::<u64>();
+
+        Ok(ArchiveCollectionStats {
+            document_count,
+            storage_bytes,
+            avg_doc_bytes: storage_bytes.checked_div(document_count).unwrap_or(0),
+        })
+    }
+
+    /// Groups by the time each record was uploaded when grouping by archive time, which only
+    /// needs the index. Grouping by a field fetches every bundle.
+    async fn group_count(
+        &self,
+        rec_type: ArchiveRecordType,
+        group_by: GroupBy,
+    ) -> Result<Vec<(Bson, u64)>, ArchiveError> {
+        let entries = self.entries(&rec_type).await?;
+        let keys: Vec<Bson> = match group_by {
+            GroupBy::Field(field) => {
+                self.fetch_all(entries)
+                    .map_ok(|doc| lookup(&doc, &field).cloned().unwrap_or(Bson::Null))
+                    .try_collect()
+                    .await?
+            }
+            GroupBy::ArchivedAt(granularity) => entries
+                .iter()
+                .map(|entry| {
+                    Bson::String(granularity.bucket(bson::DateTime::from_millis(entry.archived_at)))
+                })
+                .collect(),
+        };
+        Ok(tally(keys))
+    }
+
+    /// Aggregates the records matching the filter in process.
+    async fn aggregate(
+        &self,
+        rec_type: ArchiveRecordType,
+        spec: &AggregationSpec,
+    ) -> Result<Vec<Document>, ArchiveError> {
+        let docs = self.matching(&rec_type, &spec.filter).await?;
+        Ok(aggregate(spec, docs))
+    }
+
+    /// There are no secondary indexes: queries fetch every bundle, so non-unique indexes are
+    /// ignored.
+    async fn ensure_indexes(
+        &self,
+        _rec_type: ArchiveRecordType,
+        indexes: &[IndexSpec],
+    ) -> Result<(), ArchiveError> {
+        if indexes.iter().any(|index| index.unique) {
+            return Err(Unsupported {
+                operation: "ensure_indexes",
+                reason: "the Arweave backend has no secondary indexes".to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
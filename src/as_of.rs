@@ -0,0 +1,121 @@
+/// Time-travel reads, returning the records of a type as they stood at a block height or a time,
+/// e.g. what an account looked like at block 1,234,567. The state at a point is the latest
+/// version of each record written up to it, judged by the record's [BLOCK_HEIGHT_FIELD] or by when
+/// it was archived, see [crate::ArchiveEnvelope]. Only types registered with
+/// [crate::ArchiveStoreBuilder::versioned], or whose records are only ever created and never
+/// updated, keep every version of a record. Other types' history is incomplete, as updating a
+/// record replaces its earlier version.
+use crate::envelope::ARCHIVED_AT_FIELD;
+use crate::filter::{compare, integer, lookup};
+use crate::{
+    ArchiveEnvelope, ArchiveError, ArchiveRecordType, ArchiveStore, Filter, ACCOUNT_ADDRESS_FIELD,
+    BLOCK_HEIGHT_FIELD, RECEIPT_TX_HASH_FIELD,
+};
+use anyhow::Context;
+use bson::{Bson, DateTime, Document};
+use serde::de::DeserializeOwned;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// A point in the archive's history to read records as of, see [ArchiveStore::find_as_of].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsOf {
+    /// The state at a block height, from the records whose [BLOCK_HEIGHT_FIELD] is at most the
+    /// height. Records without the field are left out.
+    Height(i64),
+    /// The state at a time, from the records archived at or before it. Records archived before
+    /// provenance was recorded are left out.
+    Timestamp(DateTime),
+}
+
+impl ArchiveStore {
+    /// Retrieves the records of [ArchiveRecordType] matching a [Filter] as they stood at a point
+    /// in the archive's history, returning the latest version of each record archived up to it,
+    /// e.g. `find_as_of(ArchiveRecordType::Account, Filter::eq(ACCOUNT_ADDRESS_FIELD, address),
+    /// AsOf::Height(1_234_567))`. Versions of the same record are those sharing the field the type
+    /// is versioned by, see [crate::ArchiveStoreBuilder::versioned], else its
+    /// [ACCOUNT_ADDRESS_FIELD], [BLOCK_HEIGHT_FIELD] or [RECEIPT_TX_HASH_FIELD], for accounts,
+    /// blocks and receipts, the latest being the one with the greatest height then the latest
+    /// archived, for [AsOf::Height], or the other way round for [AsOf::Timestamp]. Records of
+    /// other types, and records missing the field, have no other versions, so every one archived
+    /// up to the point is returned.
+    ///
+    /// Records are returned in order of the field they're versioned by, followed by those
+    /// without it. Like [ArchiveStore::query] every version matching the filter is read, and
+    /// tombstoned versions are skipped, unless read through [ArchiveStore::with_tombstones], so
+    /// the latest version that isn't tombstoned is returned.
+    pub async fn find_as_of<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+        as_of: AsOf,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: DeserializeOwned,
+    {
+        let bound = match as_of {
+            AsOf::Height(height) => Filter::lte(BLOCK_HEIGHT_FIELD, height),
+            AsOf::Timestamp(time) => Filter::lte(ARCHIVED_AT_FIELD, time.timestamp_millis()),
+        };
+        let key_field = self.version_key(&rec_type).or(match rec_type {
+            ArchiveRecordType::Account => Some(ACCOUNT_ADDRESS_FIELD),
+            ArchiveRecordType::Block => Some(BLOCK_HEIGHT_FIELD),
+            ArchiveRecordType::Receipt => Some(RECEIPT_TX_HASH_FIELD),
+            _ => None,
+        });
+        let envelopes = self
+            .query_envelopes::<Document>(rec_type, filter.and(bound))
+            .await?;
+
+        let mut latest: HashMap<String, (Bson, ArchiveEnvelope<Document>)> = HashMap::new();
+        let mut unversioned = Vec::new();
+        for envelope in envelopes {
+            let key = match key_field.and_then(|field| lookup(&envelope.record, field)) {
+                Some(key) => key.clone(),
+                None => {
+                    unversioned.push(envelope);
+                    continue;
+                }
+            };
+            // Numbers of different types are the same key, as backends may read either back.
+            let name = integer(&key).map_or_else(|| key.to_string(), |n| n.to_string());
+            // Versions that can't be told apart are taken in the order they were read.
+            match latest.get(&name) {
+                Some((_, current)) if order(&envelope, current, &as_of).is_lt() => {}
+                _ => {
+                    latest.insert(name, (key, envelope));
+                }
+            }
+        }
+
+        let mut versions: Vec<_> = latest.into_values().collect();
+        versions.sort_by(|(a, _), (b, _)| compare(a, b).unwrap_or(Ordering::Equal));
+        versions
+            .into_iter()
+            .map(|(_, envelope)| envelope)
+            .chain(unversioned)
+            .map(|envelope| {
+                bson::from_document(envelope.record)
+                    .context("Failed to deserialise record")
+                    .map_err(ArchiveError::from)
+            })
+            .collect()
+    }
+}
+
+/// Orders two versions of a record as of a point: by height then when they were archived when
+/// reading as of a height, and the other way round when reading as of a time.
+fn order(a: &ArchiveEnvelope<Document>, b: &ArchiveEnvelope<Document>, as_of: &AsOf) -> Ordering {
+    let heights = match (
+        lookup(&a.record, BLOCK_HEIGHT_FIELD),
+        lookup(&b.record, BLOCK_HEIGHT_FIELD),
+    ) {
+        (Some(a), Some(b)) => compare(a, b).unwrap_or(Ordering::Equal),
+        _ => Ordering::Equal,
+    };
+    let archived = a.archived_at.cmp(&b.archived_at);
+    match as_of {
+        AsOf::Height(_) => heights.then(archived),
+        AsOf::Timestamp(_) => archived.then(heights),
+    }
+}
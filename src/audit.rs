@@ -0,0 +1,135 @@
+/// Completeness audits of sequentially keyed record types, such as blocks by height, finding the
+/// keys that were never archived, e.g. after an incident, so that they can be backfilled. See
+/// [ArchiveStore::audit_sequence].
+use crate::filter::integer;
+use crate::stats::KEY_FIELD;
+use crate::{AggregationSpec, ArchiveError, ArchiveRecordType, ArchiveStore, Filter, GroupBy};
+use core::fmt;
+use std::collections::BTreeSet;
+use std::ops::{Bound, RangeBounds};
+
+/// Number of keys whose records are looked for at once
+const WINDOW: i64 = 10_000;
+
+/// A run of consecutive keys with no archived record, from `start` to `end` inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MissingRange {
+    /// First missing key
+    pub start: i64,
+    /// Last missing key
+    pub end: i64,
+}
+
+impl MissingRange {
+    /// Number of missing keys.
+    pub fn count(&self) -> u64 {
+        self.end.abs_diff(self.start) + 1
+    }
+}
+
+impl fmt::Display for MissingRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+impl ArchiveStore {
+    /// Finds the keys in `expected` that no record of [ArchiveRecordType] has as its integer
+    /// `key_field`, e.g. the block heights that were never archived with
+    /// `audit_sequence(ArchiveRecordType::Block, BLOCK_HEIGHT_FIELD, 0..=tip)`. Returns the
+    /// missing keys as runs of consecutive keys, in order. Both tiers of a tiered store are
+    /// searched. Tombstoned records count as missing, unless audited through a handle returned by
+    /// [ArchiveStore::with_tombstones]. As with [ArchiveStore::query], fields of compressed or
+    /// chunked records can't be read, so such records count as missing too.
+    ///
+    /// Keys are looked for a window at a time, by an aggregation grouping the records in the
+    /// window by key, see [ArchiveStore::aggregate], so on MongoDB only the keys are read. The
+    /// range must be bounded at both ends.
+    pub async fn audit_sequence<R>(
+        &self,
+        rec_type: ArchiveRecordType,
+        key_field: &str,
+        expected: R,
+    ) -> Result<Vec<MissingRange>, ArchiveError>
+    where
+        R: RangeBounds<i64>,
+    {
+        let start = match expected.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => start.saturating_add(1),
+            Bound::Unbounded => {
+                return Err(ArchiveError::invalid_input(
+                    "The expected range of keys must have a start",
+                ))
+            }
+        };
+        let end = match expected.end_bound() {
+            Bound::Included(end) => *end,
+            Bound::Excluded(end) => end.saturating_sub(1),
+            Bound::Unbounded => {
+                return Err(ArchiveError::invalid_input(
+                    "The expected range of keys must have an end",
+                ))
+            }
+        };
+
+        let mut missing = Vec::new();
+        // The first key not yet known to be archived or missing
+        let mut next = start;
+        let mut window_start = start;
+        while window_start <= end {
+            let window_end = window_start.saturating_add(WINDOW - 1).min(end);
+            for key in self
+                .archived_keys(&rec_type, key_field, window_start, window_end)
+                .await?
+            {
+                if key > next {
+                    missing.push(MissingRange {
+                        start: next,
+                        end: key - 1,
+                    });
+                }
+                next = key.saturating_add(1);
+            }
+            if window_end == end {
+                break;
+            }
+            window_start = window_end + 1;
+        }
+        if next <= end {
+            missing.push(MissingRange { start: next, end });
+        }
+        Ok(missing)
+    }
+
+    /// The distinct integer values of `key_field` from `start` to `end` inclusive of the records
+    /// of [ArchiveRecordType] in either tier, in order.
+    async fn archived_keys(
+        &self,
+        rec_type: &ArchiveRecordType,
+        key_field: &str,
+        start: i64,
+        end: i64,
+    ) -> Result<BTreeSet<i64>, ArchiveError> {
+        let spec = AggregationSpec::grouped_by(GroupBy::Field(key_field.to_string()))
+            .filter(Filter::range(key_field, start..=end))
+            .count("records");
+        let mut groups = self.aggregate(rec_type.clone(), spec.clone()).await?;
+        if let Some(cold) = self.cold_tier() {
+            let cold = match self.include_tombstones {
+                true => cold.with_tombstones(),
+                false => cold.clone(),
+            };
+            groups.extend(cold.aggregate(rec_type.clone(), spec).await?);
+        }
+        Ok(groups
+            .iter()
+            .filter_map(|group| group.get(KEY_FIELD).and_then(integer))
+            .filter(|key| (start..=end).contains(key))
+            .collect())
+    }
+}
@@ -0,0 +1,390 @@
+/// Backfills of sequentially keyed record types, such as blocks by height, rebuilding history
+/// that was never archived, e.g. the ranges found by [ArchiveStore::audit_sequence]. A
+/// [Backfiller] archives the records of a range of keys from a source, in key order, under ids
+/// derived from their keys, and checkpoints its progress in the archive itself, so a backfill
+/// that was interrupted resumes where it left off when run again.
+use crate::filter::{integer, lookup};
+use crate::{filter, ArchiveError, ArchiveErrorKind, ArchiveRecordType, ArchiveStore};
+use anyhow::Context;
+use bson::{doc, Bson, DateTime, Document};
+use core::fmt;
+use futures::{Stream, StreamExt};
+use log::debug;
+use serde::Serialize;
+use std::ops::{Bound, RangeBounds, RangeInclusive};
+use std::sync::Arc;
+
+/// Name of the record type the checkpoints of backfill jobs are archived as, by job name
+pub const BACKFILL_JOBS: &str = "backfill_jobs";
+
+/// How far a backfill has got, reported after every checkpoint and returned once it completes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BackfillProgress {
+    /// Records read from the source
+    pub read: u64,
+    /// Records archived, including those that already were
+    pub written: u64,
+    /// Records skipped because their key was outside the job's range, or already backfilled
+    pub skipped: u64,
+    /// The last key whose records have all been archived
+    pub checkpoint: Option<i64>,
+}
+
+/// How records are backfilled by a [Backfiller].
+#[derive(Clone)]
+pub struct BackfillOptions {
+    /// Number of records archived between checkpoints. Checkpoints are only taken between keys,
+    /// so keys with many records may stretch the interval. Defaults to 1000.
+    pub checkpoint_every: usize,
+    /// Called with the progress of the backfill after every checkpoint
+    pub progress: Option<Arc<ProgressFn>>,
+}
+
+/// A callback reporting the progress of a backfill
+type ProgressFn = dyn Fn(&BackfillProgress) + Send + Sync;
+
+impl BackfillOptions {
+    /// Calls `progress` with the progress of the backfill after every checkpoint.
+    pub fn on_progress<F: Fn(&BackfillProgress) + Send + Sync + 'static>(
+        mut self,
+        progress: F,
+    ) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+}
+
+impl Default for BackfillOptions {
+    fn default() -> Self {
+        BackfillOptions {
+            checkpoint_every: 1000,
+            progress: None,
+        }
+    }
+}
+
+impl fmt::Debug for BackfillOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BackfillOptions")
+            .field("checkpoint_every", &self.checkpoint_every)
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
+}
+
+/// A backfill job, archiving the records of [ArchiveRecordType] whose integer `key_field` is in
+/// a range of keys, created with [ArchiveStore::backfiller].
+///
+/// Records are archived with [ArchiveStore::create_with_id], under their own `_id` if they have
+/// one, or else `{key_field}-{key}-{n}` for the `n`th record with that key, so archiving a record
+/// again finds it already archived rather than storing a second copy. The job's checkpoint is
+/// archived as a record of the [BACKFILL_JOBS] record type, whose id is the job's name.
+#[derive(Debug, Clone)]
+pub struct Backfiller {
+    store: ArchiveStore,
+    job: String,
+    rec_type: ArchiveRecordType,
+    key_field: String,
+    keys: RangeInclusive<i64>,
+    options: BackfillOptions,
+}
+
+impl ArchiveStore {
+    /// Creates a backfill job named `job` archiving the records of [ArchiveRecordType] whose
+    /// integer `key_field` is in `keys`, e.g. the missing blocks with
+    /// `backfiller("blocks-2024", ArchiveRecordType::Block, BLOCK_HEIGHT_FIELD, 0..=tip, options)`.
+    /// Job names are 1 to 64 letters, digits, '-' and '_'. The range must be bounded at both
+    /// ends. Nothing is read or written until the job is run, see [Backfiller::run].
+    pub fn backfiller<R>(
+        &self,
+        job: &str,
+        rec_type: ArchiveRecordType,
+        key_field: &str,
+        keys: R,
+        options: BackfillOptions,
+    ) -> Result<Backfiller, ArchiveError>
+    where
+        R: RangeBounds<i64>,
+    {
+        if job.is_empty()
+            || job.len() > 64
+            || !job
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(ArchiveError::invalid_input(format!(
+                "Backfill job name '{}' must be 1 to 64 letters, digits, '-' and '_'",
+                job
+            )));
+        }
+        if key_field.is_empty() {
+            return Err(ArchiveError::invalid_input(
+                "Backfill key field must not be empty",
+            ));
+        }
+        if options.checkpoint_every == 0 {
+            return Err(ArchiveError::invalid_input(
+                "Backfill checkpoint interval must be greater than zero",
+            ));
+        }
+        let start = match keys.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => start.saturating_add(1),
+            Bound::Unbounded => {
+                return Err(ArchiveError::invalid_input(
+                    "The range of keys to backfill must have a start",
+                ))
+            }
+        };
+        let end = match keys.end_bound() {
+            Bound::Included(end) => *end,
+            Bound::Excluded(end) => end.saturating_sub(1),
+            Bound::Unbounded => {
+                return Err(ArchiveError::invalid_input(
+                    "The range of keys to backfill must have an end",
+                ))
+            }
+        };
+        rec_type.validate().map_err(ArchiveError::invalid_input)?;
+        Ok(Backfiller {
+            store: self.clone(),
+            job: job.to_string(),
+            rec_type,
+            key_field: key_field.to_string(),
+            keys: start..=end,
+            options,
+        })
+    }
+}
+
+impl Backfiller {
+    /// Returns the last key whose records have all been archived by previous runs of the job, if
+    /// any.
+    pub async fn checkpoint(&self) -> Result<Option<i64>, ArchiveError> {
+        Ok(self
+            .load()
+            .await?
+            .and_then(|job| job.get("checkpoint").and_then(integer)))
+    }
+
+    /// Returns the keys still to be backfilled, `None` once the job has completed. Sources that
+    /// can start part way, e.g. a node's block range API, should start from the first of these
+    /// rather than reading records again only for them to be skipped.
+    pub async fn remaining(&self) -> Result<Option<RangeInclusive<i64>>, ArchiveError> {
+        let start = match self.checkpoint().await? {
+            Some(checkpoint) if checkpoint >= *self.keys.end() => return Ok(None),
+            Some(checkpoint) => checkpoint + 1,
+            None => *self.keys.start(),
+        };
+        Ok(Some(start..=*self.keys.end()))
+    }
+
+    /// Deletes the job's checkpoint, so that the next run starts again from the first key,
+    /// returning whether there was one. Records already archived are kept.
+    pub async fn reset(&self) -> Result<bool, ArchiveError> {
+        self.store.delete_by_id(jobs(), &self.job).await
+    }
+
+    /// Archives the records of the job's range from `source`, resuming after the job's
+    /// checkpoint, and returns the progress of this run. The source yields records in ascending
+    /// order of their keys, and several records may share a key. The source ending completes the
+    /// job, as every key up to the end of the range has then been backfilled.
+    ///
+    /// Records without an integer `key_field` fail the run, as does an error yielded by the
+    /// source or returned by the store, after checkpointing the keys whose records were all
+    /// archived, so running the job again resumes after them. Records out of key order fail it
+    /// without a checkpoint. A job whose checkpoint was taken for a different record type, key
+    /// field or range fails until [Backfiller::reset].
+    pub async fn run<S, T>(&self, source: S) -> Result<BackfillProgress, ArchiveError>
+    where
+        S: Stream<Item = Result<T, ArchiveError>>,
+        T: Serialize,
+    {
+        let mut run = Run {
+            backfiller: self,
+            progress: BackfillProgress {
+                checkpoint: self.resume().await?,
+                ..BackfillProgress::default()
+            },
+            key: None,
+            key_records: 0,
+            since_checkpoint: 0,
+        };
+        // Boxed, as the store's writes nest deeply enough for callers' futures to otherwise
+        // overflow the compiler's query depth limit.
+        let result = Box::pin(run.archive(source)).await;
+        match result {
+            Ok(()) => {
+                run.progress.checkpoint = Some(*self.keys.end());
+                self.save(&run.progress, true).await?;
+                run.report();
+                debug!("Backfill {} completed", self.job);
+                Ok(run.progress)
+            }
+            Err(e) => {
+                // Checkpoint the keys before the one whose records were being archived.
+                if let Some(key) = run.key.filter(|key| key > self.keys.start()) {
+                    run.progress.checkpoint = run.progress.checkpoint.max(Some(key - 1));
+                    self.save(&run.progress, false).await?;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Archives the records of the job's range from an iterator, like [Backfiller::run].
+    pub async fn run_iter<I, T>(&self, source: I) -> Result<BackfillProgress, ArchiveError>
+    where
+        I: IntoIterator<Item = T>,
+        T: Serialize,
+    {
+        self.run(futures::stream::iter(source.into_iter().map(Ok)))
+            .await
+    }
+
+    /// Returns the checkpoint to resume after, checking that the job's archived checkpoint, if
+    /// any, was taken for the same backfill.
+    async fn resume(&self) -> Result<Option<i64>, ArchiveError> {
+        let Some(job) = self.load().await? else {
+            return Ok(None);
+        };
+        let same = job.get("record_type") == Some(&self.record_type()?)
+            && job.get_str("key_field").ok() == Some(self.key_field.as_str())
+            && job.get("start").and_then(integer) == Some(*self.keys.start())
+            && job.get("end").and_then(integer) == Some(*self.keys.end());
+        if !same {
+            return Err(ArchiveError::invalid_input(format!(
+                "Backfill job '{}' was checkpointed for a different record type, key field or \
+                range of keys",
+                self.job
+            )));
+        }
+        Ok(job.get("checkpoint").and_then(integer))
+    }
+
+    /// The job's archived checkpoint, if it has one.
+    async fn load(&self) -> Result<Option<Document>, ArchiveError> {
+        self.store.find_by_id(jobs(), &self.job).await
+    }
+
+    /// Archives the job's checkpoint, replacing the previous one.
+    async fn save(&self, progress: &BackfillProgress, completed: bool) -> Result<(), ArchiveError> {
+        let mut job = doc! {
+            "record_type": self.record_type()?,
+            "key_field": &self.key_field,
+            "start": *self.keys.start(),
+            "end": *self.keys.end(),
+            "updated_at": DateTime::now(),
+            "completed": completed,
+        };
+        if let Some(checkpoint) = progress.checkpoint {
+            job.insert("checkpoint", checkpoint);
+        }
+        if !self
+            .store
+            .update_by_id(jobs(), &self.job, job.clone())
+            .await?
+        {
+            self.store.create_with_id(jobs(), &self.job, job).await?;
+        }
+        debug!(
+            "Backfill {} checkpointed at {:?}",
+            self.job, progress.checkpoint
+        );
+        Ok(())
+    }
+
+    fn record_type(&self) -> Result<Bson, ArchiveError> {
+        Ok(bson::to_bson(&self.rec_type).context("Failed to serialise record type")?)
+    }
+}
+
+/// The record type of the checkpoints of backfill jobs.
+fn jobs() -> ArchiveRecordType {
+    ArchiveRecordType::Custom(BACKFILL_JOBS.to_string())
+}
+
+/// The state of a run of a [Backfiller].
+struct Run<'a> {
+    backfiller: &'a Backfiller,
+    progress: BackfillProgress,
+    /// The key of the last record read within the range
+    key: Option<i64>,
+    /// Number of records with that key archived so far
+    key_records: u64,
+    /// Number of records archived since the last checkpoint
+    since_checkpoint: usize,
+}
+
+impl Run<'_> {
+    /// Archives the records from the source that are within the range and after the checkpoint.
+    async fn archive<S, T>(&mut self, source: S) -> Result<(), ArchiveError>
+    where
+        S: Stream<Item = Result<T, ArchiveError>>,
+        T: Serialize,
+    {
+        let backfiller = self.backfiller;
+        let mut source = std::pin::pin!(source);
+        while let Some(rec) = source.next().await {
+            let mut rec = bson::to_document(&rec?).context("Failed to serialise record to BSON")?;
+            self.progress.read += 1;
+            let key = match lookup(&rec, &backfiller.key_field).and_then(integer) {
+                Some(key) => key,
+                None => {
+                    return Err(ArchiveError::invalid_input(format!(
+                        "Record {} read by backfill '{}' has no integer '{}'",
+                        self.progress.read, backfiller.job, backfiller.key_field
+                    )))
+                }
+            };
+            if let Some(last) = self.key.filter(|last| key < *last) {
+                // The keys before the last one may not be complete after all.
+                self.key = None;
+                return Err(ArchiveError::invalid_input(format!(
+                    "Record {} read by backfill '{}' has key {}, after key {}",
+                    self.progress.read, backfiller.job, key, last
+                )));
+            }
+            let done = self.progress.checkpoint.is_some_and(|done| key <= done);
+            if !backfiller.keys.contains(&key) || done {
+                self.progress.skipped += 1;
+                continue;
+            }
+
+            if self.key != Some(key) {
+                // Every record of the previous key has been archived.
+                if self.since_checkpoint >= backfiller.options.checkpoint_every {
+                    self.progress.checkpoint = self.key;
+                    backfiller.save(&self.progress, false).await?;
+                    self.since_checkpoint = 0;
+                    self.report();
+                }
+                self.key = Some(key);
+                self.key_records = 0;
+            }
+            let id = match rec.remove("_id") {
+                Some(id) => filter::id_to_string(&id),
+                None => format!("{}-{}-{}", backfiller.key_field, key, self.key_records),
+            };
+            match backfiller
+                .store
+                .create_with_id(backfiller.rec_type.clone(), &id, rec)
+                .await
+            {
+                Ok(_) => {}
+                Err(e) if e.kind() == ArchiveErrorKind::DuplicateKey => {}
+                Err(e) => return Err(e),
+            }
+            self.key_records += 1;
+            self.since_checkpoint += 1;
+            self.progress.written += 1;
+        }
+        Ok(())
+    }
+
+    fn report(&self) {
+        if let Some(progress) = &self.backfiller.options.progress {
+            progress(&self.progress);
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! Command line tool for inspecting and maintaining an archive without writing a program against
+//! the crate. The store to use is configured with the `--backend`, `--uri`, `--root` and
+//! `--datastore` options, the `LASR_ARCHIVE_*` environment variables, or a TOML or YAML `--config`
+//! file, in that order of precedence, see [lasr_archive::ArchiveConfig]. Keeping the URI out of
+//! the command line keeps its credentials out of the shell history.
+//!
+//! ```text
+//! export LASR_ARCHIVE_URI=mongodb://localhost:27017 LASR_ARCHIVE_DATASTORE=lasr_archive
+//! lasr-archive get account 6650f0f5a1b2c3d4e5f60718
+//! lasr-archive query block --where block_height=1024
+//! lasr-archive export transaction_batch --format csv --output batches.csv
+//! lasr-archive serve --listen 0.0.0.0:50051
+//! ```
+use anyhow::{bail, Context, Result};
+use bson::{Bson, Document};
+use clap::{Parser, Subcommand, ValueEnum};
+use lasr_archive::{
+    ArchiveConfig, ArchiveRecordType, ArchiveStats, ArchiveStore, ExportFormat, Filter,
+};
+use std::path::PathBuf;
+use tokio::io::AsyncReadExt;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "lasr-archive",
+    version,
+    about = "Inspect and maintain a LASR archive"
+)]
+struct Cli {
+    /// TOML or YAML file of the store's settings
+    #[arg(long, global = true, env = "LASR_ARCHIVE_CONFIG")]
+    config: Option<PathBuf>,
+    /// Archive backend: mongodb, postgres, sqlite, s3, rocksdb, filesystem, ipfs or arweave
+    #[arg(long, global = true)]
+    backend: Option<String>,
+    /// URI of the backend
+    #[arg(long, global = true)]
+    uri: Option<String>,
+    /// Root directory of the filesystem backend, or index directory of the ipfs and arweave
+    /// backends
+    #[arg(long, global = true)]
+    root: Option<PathBuf>,
+    /// Name of the datastore
+    #[arg(long, global = true)]
+    datastore: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Archives a record given as a JSON object, or read from stdin, and prints its id
+    Put {
+        record_type: String,
+        /// The record, as relaxed or canonical extended JSON
+        record: Option<String>,
+    },
+    /// Prints the record with the given id as JSON
+    Get { record_type: String, id: String },
+    /// Prints the records matching every `--where` condition, one JSON object per line
+    Query {
+        record_type: String,
+        /// Condition of the form `field=value`, the value being JSON or else a string
+        #[arg(long = "where", value_name = "FIELD=VALUE")]
+        conditions: Vec<String>,
+    },
+    /// Exports the records matching every `--where` condition
+    Export {
+        record_type: String,
+        #[arg(long, value_enum, default_value_t = Format::JsonLines)]
+        format: Format,
+        /// File to write to, instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Condition of the form `field=value`, the value being JSON or else a string
+        #[arg(long = "where", value_name = "FIELD=VALUE")]
+        conditions: Vec<String>,
+    },
+    /// Verifies every record against its checksum, failing if any don't match
+    Verify { record_type: String },
+    /// Prints the runs of keys from `--from` to `--to` that no record has, e.g. block heights
+    /// that were never archived, failing if there are any
+    Audit {
+        record_type: String,
+        /// Integer field keying the records, e.g. `block_height`
+        #[arg(long)]
+        field: String,
+        /// First expected key
+        #[arg(long)]
+        from: i64,
+        /// Last expected key
+        #[arg(long)]
+        to: i64,
+    },
+    /// Prints storage statistics for the records of a type, or of every type if none is given
+    Stats { record_type: Option<String> },
+    /// Serves the archive over gRPC until interrupted, see `proto/archive.proto`
+    #[cfg(feature = "server")]
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:50051")]
+        listen: std::net::SocketAddr,
+    },
+    /// Serves the JSON-RPC read endpoint over HTTP until interrupted
+    #[cfg(feature = "jsonrpc")]
+    ServeJsonrpc {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8545")]
+        listen: std::net::SocketAddr,
+    },
+    /// Serves the GraphQL endpoint over HTTP until interrupted
+    #[cfg(feature = "graphql")]
+    ServeGraphql {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8000")]
+        listen: std::net::SocketAddr,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    JsonLines,
+    Csv,
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl From<Format> for ExportFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::JsonLines => ExportFormat::JsonLines,
+            Format::Csv => ExportFormat::Csv,
+            #[cfg(feature = "parquet")]
+            Format::Parquet => ExportFormat::Parquet,
+        }
+    }
+}
+
+/// Builds the store configured by the options, environment and config file.
+fn store(cli: &Cli) -> Result<ArchiveStore> {
+    let mut config = match &cli.config {
+        Some(path) => ArchiveConfig::from_file(path)?,
+        None => ArchiveConfig::default(),
+    }
+    .with_env()?;
+    if let Some(backend) = &cli.backend {
+        config.backend = Some(backend.clone());
+    }
+    if let Some(uri) = &cli.uri {
+        config.uri = Some(uri.clone());
+    }
+    if let Some(root) = &cli.root {
+        config.root = Some(root.clone());
+    }
+    if let Some(datastore) = &cli.datastore {
+        config.datastore = Some(datastore.clone());
+    }
+    Ok(config.builder()?.build()?)
+}
+
+/// Parses a record type name, e.g. `transaction_batch`. Other names are custom record types.
+fn record_type(name: &str) -> ArchiveRecordType {
+    match name {
+        "account" => ArchiveRecordType::Account,
+        "transaction_batch" => ArchiveRecordType::TransactionBatch,
+        "block" => ArchiveRecordType::Block,
+        "receipt" => ArchiveRecordType::Receipt,
+        other => ArchiveRecordType::Custom(other.to_string()),
+    }
+}
+
+/// The name of a record type on the command line, as parsed by [record_type].
+fn record_type_name(rec_type: &ArchiveRecordType) -> &str {
+    match rec_type {
+        ArchiveRecordType::Account => "account",
+        ArchiveRecordType::TransactionBatch => "transaction_batch",
+        ArchiveRecordType::Block => "block",
+        ArchiveRecordType::Receipt => "receipt",
+        ArchiveRecordType::Custom(name) => name,
+    }
+}
+
+/// One line summarising statistics.
+fn summary(stats: &ArchiveStats) -> String {
+    format!(
+        "records: {}, storage bytes: {}",
+        stats.document_count, stats.storage_bytes
+    )
+}
+
+/// Parses a value as extended JSON, falling back to a string.
+fn value(text: &str) -> Bson {
+    serde_json::from_str::<serde_json::Value>(text)
+        .ok()
+        .and_then(|json| Bson::try_from(json).ok())
+        .unwrap_or_else(|| Bson::String(text.to_string()))
+}
+
+/// The filter matching every `field=value` condition.
+fn filter(conditions: &[String]) -> Result<Filter> {
+    conditions
+        .iter()
+        .try_fold(Filter::All, |filter, condition| {
+            let (field, text) = condition
+                .split_once('=')
+                .with_context(|| format!("Condition '{}' is not field=value", condition))?;
+            Ok(filter.and(Filter::eq(field, value(text))))
+        })
+}
+
+/// A record as a line of relaxed extended JSON.
+fn json(rec: Document) -> String {
+    Bson::Document(rec).into_relaxed_extjson().to_string()
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let cli = Cli::parse();
+    let store = store(&cli)?;
+
+    match cli.command {
+        Command::Put {
+            record_type: name,
+            record,
+        } => {
+            let record = match record {
+                Some(record) => record,
+                None => {
+                    let mut record = String::new();
+                    tokio::io::stdin()
+                        .read_to_string(&mut record)
+                        .await
+                        .context("Reading record from stdin")?;
+                    record
+                }
+            };
+            let rec = match value(record.trim()) {
+                Bson::Document(rec) => rec,
+                _ => bail!("The record must be a JSON object"),
+            };
+            let outcome = store.create(record_type(&name), rec).await?;
+            println!("{}", outcome);
+        }
+        Command::Get {
+            record_type: name,
+            id,
+        } => match store
+            .find_by_id::<Document>(record_type(&name), &id)
+            .await?
+        {
+            Some(rec) => println!("{}", json(rec)),
+            None => bail!("No {} record with id {}", name, id),
+        },
+        Command::Query {
+            record_type: name,
+            conditions,
+        } => {
+            let recs: Vec<Document> = store
+                .query(record_type(&name), filter(&conditions)?)
+                .await?;
+            for rec in recs {
+                println!("{}", json(rec));
+            }
+        }
+        Command::Export {
+            record_type: name,
+            format,
+            output,
+            conditions,
+        } => {
+            let rec_type = record_type(&name);
+            let filter = filter(&conditions)?;
+            let exported = match output {
+                Some(path) => {
+                    let file = tokio::fs::File::create(&path)
+                        .await
+                        .with_context(|| format!("Creating {}", path.display()))?;
+                    store.export(rec_type, filter, format.into(), file).await?
+                }
+                None => {
+                    store
+                        .export(rec_type, filter, format.into(), tokio::io::stdout())
+                        .await?
+                }
+            };
+            eprintln!("Exported {} records", exported);
+        }
+        Command::Verify { record_type: name } => {
+            let summary = store.verify_all(record_type(&name)).await?;
+            println!(
+                "checked: {}, matched: {}, without checksum: {}, mismatched: {}",
+                summary.checked,
+                summary.matched,
+                summary.unchecksummed,
+                summary.mismatched.len()
+            );
+            for id in &summary.mismatched {
+                println!("mismatched: {}", id);
+            }
+            if !summary.mismatched.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        Command::Audit {
+            record_type: name,
+            field,
+            from,
+            to,
+        } => {
+            let missing = store
+                .audit_sequence(record_type(&name), &field, from..=to)
+                .await?;
+            let count: u64 = missing.iter().map(|range| range.count()).sum();
+            println!("missing keys: {}", count);
+            for range in &missing {
+                println!("missing: {}", range);
+            }
+            if !missing.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        Command::Stats {
+            record_type: Some(name),
+        } => {
+            let stats = store.archive_stats(record_type(&name)).await?;
+            println!("records: {}", stats.document_count);
+            println!("storage bytes: {}", stats.storage_bytes);
+            println!("average record bytes: {}", stats.avg_doc_bytes());
+            if let Some(oldest) = stats.oldest_archived_at {
+                println!("oldest archived at: {}", oldest);
+            }
+            if let Some(newest) = stats.newest_archived_at {
+                println!("newest archived at: {}", newest);
+            }
+            for (namespace, stats) in &stats.namespaces {
+                println!("namespace {}: {}", namespace, summary(stats));
+            }
+        }
+        Command::Stats { record_type: None } => {
+            let report = store.stats_all().await?;
+            for (rec_type, stats) in &report.record_types {
+                println!("{}: {}", record_type_name(rec_type), summary(stats));
+            }
+            println!("total: {}", summary(&report.total));
+            for (namespace, stats) in &report.total.namespaces {
+                println!("namespace {}: {}", namespace, summary(stats));
+            }
+        }
+        #[cfg(feature = "server")]
+        Command::Serve { listen } => {
+            eprintln!("Serving the archive on {}", listen);
+            store
+                .serve(listen, async {
+                    let _ = tokio::signal::ctrl_c().await;
+                })
+                .await?;
+        }
+        #[cfg(feature = "jsonrpc")]
+        Command::ServeJsonrpc { listen } => {
+            eprintln!("Serving the JSON-RPC endpoint on {}", listen);
+            store
+                .serve_jsonrpc(listen, async {
+                    let _ = tokio::signal::ctrl_c().await;
+                })
+                .await?;
+        }
+        #[cfg(feature = "graphql")]
+        Command::ServeGraphql { listen } => {
+            eprintln!("Serving the GraphQL endpoint on {}", listen);
+            store
+                .serve_graphql(listen, async {
+                    let _ = tokio::signal::ctrl_c().await;
+                })
+                .await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,702 @@
+/// A synchronous facade over an [ArchiveStore], for tooling such as scripts, test harnesses and
+/// build-time generators that would otherwise need a tokio runtime of their own just to read a
+/// record. A [BlockingArchiveStore] runs the store's operations to completion on an internal
+/// runtime, whose single worker thread also runs the store's background tasks, such as pruning.
+/// Requires the `blocking` feature.
+///
+/// Its methods block the calling thread, so must not be called from within an async runtime,
+/// where they panic, and the last clone of a [BlockingArchiveStore] must not be dropped there
+/// either. Operations returning streams, such as [ArchiveStore::subscribe], or working on async
+/// readers and writers, such as [ArchiveStore::export], can be run with
+/// [BlockingArchiveStore::block_on].
+use crate::{
+    AggregationSpec, ArchiveCollectionStats, ArchiveEnvelope, ArchiveError, ArchiveRecordType,
+    ArchiveStats, ArchiveStatsReport, ArchiveStore, AsOf, CreateOutcome, DeadLetter, Filter,
+    GroupBy, InclusionProof, IndexSpec, LifecycleState, MergeMode, MerkleRoot, MissingRange, Page,
+    PageRequest, ReadOptions, SnapshotManifest, VerificationResult, VerificationSummary, Versioned,
+    WriteOptions,
+};
+use anyhow::Context;
+use bson::{Bson, Document};
+use serde::{de::DeserializeOwned, Serialize};
+use std::borrow::Borrow;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::ops::RangeBounds;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+
+/// A handle on an [ArchiveStore] whose operations block until they complete, e.g.
+/// `BlockingArchiveStore::from_env()?.find_by_id::<Account>(ArchiveRecordType::Account, id)`.
+/// Handles are cheap to clone and share the store and its runtime.
+#[derive(Debug, Clone)]
+pub struct BlockingArchiveStore {
+    store: ArchiveStore,
+    runtime: Arc<Runtime>,
+}
+
+impl BlockingArchiveStore {
+    /// Wraps a store, starting the runtime its operations are run on.
+    pub fn new(store: ArchiveStore) -> Result<Self, ArchiveError> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("lasr-archive-blocking")
+            .enable_all()
+            .build()
+            .context("Failed to start the archive runtime")?;
+        Ok(BlockingArchiveStore {
+            store,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// Builds a store configured by environment variables, see [ArchiveStore::from_env].
+    pub fn from_env() -> Result<Self, ArchiveError> {
+        BlockingArchiveStore::new(ArchiveStore::from_env()?)
+    }
+
+    /// Builds a store configured by a config file, see [ArchiveStore::from_config_file].
+    pub fn from_config_file(path: impl AsRef<Path>) -> Result<Self, ArchiveError> {
+        BlockingArchiveStore::new(ArchiveStore::from_config_file(path)?)
+    }
+
+    /// The underlying store.
+    pub fn store(&self) -> &ArchiveStore {
+        &self.store
+    }
+
+    /// Runs a future on the store's runtime until it completes, e.g. an operation without a
+    /// blocking equivalent with `blocking.block_on(blocking.store().export(...))`.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    /// A handle on the same store and runtime for a derived store handle.
+    fn derived(&self, store: ArchiveStore) -> Self {
+        BlockingArchiveStore {
+            store,
+            runtime: self.runtime.clone(),
+        }
+    }
+
+    /// Returns a handle adding labels, see [ArchiveStore::with_labels].
+    pub fn with_labels(&self, labels: &[(&str, &str)]) -> Self {
+        self.derived(self.store.with_labels(labels))
+    }
+
+    /// Returns a handle tagging the records it writes, see [ArchiveStore::with_tags].
+    pub fn with_tags(&self, tags: &[&str]) -> Self {
+        self.derived(self.store.with_tags(tags))
+    }
+
+    /// Returns a handle whose reads include tombstoned records, see
+    /// [ArchiveStore::with_tombstones].
+    pub fn with_tombstones(&self) -> Self {
+        self.derived(self.store.with_tombstones())
+    }
+
+    /// Returns a handle on a namespace of the store, see [ArchiveStore::namespace].
+    pub fn namespace(&self, name: &str) -> Result<Self, ArchiveError> {
+        Ok(self.derived(self.store.namespace(name)?))
+    }
+
+    /// Archives a record, see [ArchiveStore::create].
+    pub fn create<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+    ) -> Result<CreateOutcome, ArchiveError>
+    where
+        T: Serialize + Borrow<T> + Send + Sync,
+    {
+        self.block_on(self.store.create(rec_type, rec))
+    }
+
+    /// Archives a record with per-call options, see [ArchiveStore::create_with_opts].
+    pub fn create_with_opts<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+        options: &WriteOptions,
+    ) -> Result<CreateOutcome, ArchiveError>
+    where
+        T: Serialize + Borrow<T> + Send + Sync,
+    {
+        self.block_on(self.store.create_with_opts(rec_type, rec, options))
+    }
+
+    /// Archives several records in one write, see [ArchiveStore::create_many].
+    pub fn create_many<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        recs: Vec<T>,
+    ) -> Result<Vec<String>, ArchiveError>
+    where
+        T: Serialize + Borrow<T> + Send + Sync,
+    {
+        self.block_on(self.store.create_many(rec_type, recs))
+    }
+
+    /// Archives several records in one write with per-call options, see
+    /// [ArchiveStore::create_many_with_opts].
+    pub fn create_many_with_opts<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        recs: Vec<T>,
+        options: &WriteOptions,
+    ) -> Result<Vec<String>, ArchiveError>
+    where
+        T: Serialize + Borrow<T> + Send + Sync,
+    {
+        self.block_on(self.store.create_many_with_opts(rec_type, recs, options))
+    }
+
+    /// Archives records of several types all or nothing, see [ArchiveStore::create_atomic].
+    pub fn create_atomic(
+        &self,
+        records: Vec<(ArchiveRecordType, Document)>,
+    ) -> Result<Vec<String>, ArchiveError> {
+        self.block_on(self.store.create_atomic(records))
+    }
+
+    /// Archives records of several types all or nothing, see
+    /// [ArchiveStore::create_batch_atomic].
+    pub fn create_batch_atomic<T>(
+        &self,
+        records: Vec<(ArchiveRecordType, T)>,
+    ) -> Result<Vec<String>, ArchiveError>
+    where
+        T: Serialize,
+    {
+        self.block_on(self.store.create_batch_atomic(records))
+    }
+
+    /// Writes atomic batches left staged by a crash, see [ArchiveStore::recover_atomic_batches].
+    pub fn recover_atomic_batches(&self) -> Result<u64, ArchiveError> {
+        self.block_on(self.store.recover_atomic_batches())
+    }
+
+    /// Archives a record along with its checksum, see [ArchiveStore::create_with_checksum].
+    pub fn create_with_checksum<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+    ) -> Result<(String, String), ArchiveError>
+    where
+        T: Serialize + Borrow<T> + Send + Sync,
+    {
+        self.block_on(self.store.create_with_checksum(rec_type, rec))
+    }
+
+    /// Archives a record under a caller-supplied id, see [ArchiveStore::create_with_id].
+    pub fn create_with_id<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Serialize + Borrow<T> + Send + Sync,
+    {
+        self.block_on(self.store.create_with_id(rec_type, id, rec))
+    }
+
+    /// Writes spilled records to the backend, see [ArchiveStore::drain_spill].
+    pub fn drain_spill(&self) -> Result<u64, ArchiveError> {
+        self.block_on(self.store.drain_spill())
+    }
+
+    /// Lists the dead-lettered records, see [ArchiveStore::dead_letters].
+    pub fn dead_letters(&self) -> Result<Vec<DeadLetter>, ArchiveError> {
+        self.block_on(self.store.dead_letters())
+    }
+
+    /// Retrieves a dead letter, see [ArchiveStore::dead_letter].
+    pub fn dead_letter(&self, id: &str) -> Result<Option<DeadLetter>, ArchiveError> {
+        self.block_on(self.store.dead_letter(id))
+    }
+
+    /// Archives a dead-lettered record again, see [ArchiveStore::retry_dead_letter].
+    pub fn retry_dead_letter(&self, id: &str) -> Result<Option<String>, ArchiveError> {
+        self.block_on(self.store.retry_dead_letter(id))
+    }
+
+    /// Removes a dead letter, see [ArchiveStore::discard_dead_letter].
+    pub fn discard_dead_letter(&self, id: &str) -> Result<bool, ArchiveError> {
+        self.block_on(self.store.discard_dead_letter(id))
+    }
+
+    /// Checks a record against its checksum, see [ArchiveStore::verify].
+    pub fn verify(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<VerificationResult, ArchiveError> {
+        self.block_on(self.store.verify(rec_type, id))
+    }
+
+    /// Checks every checksummed record, see [ArchiveStore::verify_all].
+    pub fn verify_all(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<VerificationSummary, ArchiveError> {
+        self.block_on(self.store.verify_all(rec_type))
+    }
+
+    /// Retrieves every record, see [ArchiveStore::find_all].
+    pub fn find_all<T>(&self, rec_type: ArchiveRecordType) -> Result<Vec<T>, ArchiveError>
+    where
+        T: DeserializeOwned + Borrow<T> + Send + Sync + Clone + Unpin,
+    {
+        self.block_on(self.store.find_all(rec_type))
+    }
+
+    /// Iterates over every record a page of `page_size` records at a time, reading each page
+    /// with [ArchiveStore::find_page] once the records before it have been taken, in place of
+    /// [ArchiveStore::find_all_stream]. A failure ends the iteration after it's yielded.
+    pub fn find_all_iter<'a, T>(
+        &'a self,
+        rec_type: ArchiveRecordType,
+        page_size: usize,
+    ) -> impl Iterator<Item = Result<T, ArchiveError>> + 'a
+    where
+        T: DeserializeOwned + Borrow<T> + Send + Sync + Clone + Unpin + 'a,
+    {
+        let mut records = VecDeque::new();
+        let mut next = Some(PageRequest::first(page_size));
+        std::iter::from_fn(move || loop {
+            if let Some(rec) = records.pop_front() {
+                return Some(Ok(rec));
+            }
+            let request = next.take()?;
+            match self.find_page(rec_type.clone(), request) {
+                Ok(page) => {
+                    records.extend(page.items);
+                    next = page
+                        .next_token
+                        .map(|token| PageRequest::after(page_size, token));
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        })
+    }
+
+    /// Retrieves a page of records, see [ArchiveStore::find_page].
+    pub fn find_page<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        request: PageRequest,
+    ) -> Result<Page<T>, ArchiveError>
+    where
+        T: DeserializeOwned + Borrow<T> + Send + Sync + Clone + Unpin,
+    {
+        self.block_on(self.store.find_page(rec_type, request))
+    }
+
+    /// Retrieves a page of records along with their provenance, see
+    /// [ArchiveStore::find_envelope_page].
+    pub fn find_envelope_page<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        request: PageRequest,
+    ) -> Result<Page<ArchiveEnvelope<T>>, ArchiveError>
+    where
+        T: DeserializeOwned + Borrow<T> + Send + Sync + Clone + Unpin,
+    {
+        self.block_on(self.store.find_envelope_page(rec_type, request))
+    }
+
+    /// Retrieves the records matching a [Filter], see [ArchiveStore::query].
+    pub fn query<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: DeserializeOwned + Borrow<T> + Send + Sync + Clone + Unpin,
+    {
+        self.block_on(self.store.query(rec_type, filter))
+    }
+
+    /// Retrieves the records matching a [Filter] with per-call options, see
+    /// [ArchiveStore::query_with_opts].
+    pub fn query_with_opts<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+        options: &ReadOptions,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: DeserializeOwned + Borrow<T> + Send + Sync + Clone + Unpin,
+    {
+        self.block_on(self.store.query_with_opts(rec_type, filter, options))
+    }
+
+    /// Retrieves the records whose field is within a range, see [ArchiveStore::find_range].
+    pub fn find_range<T, V, R>(
+        &self,
+        rec_type: ArchiveRecordType,
+        field: &str,
+        range: R,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: DeserializeOwned,
+        V: Into<Bson> + Clone,
+        R: RangeBounds<V>,
+    {
+        self.block_on(self.store.find_range(rec_type, field, range))
+    }
+
+    /// Retrieves the records matching a [Filter] along with their provenance, see
+    /// [ArchiveStore::query_envelopes].
+    pub fn query_envelopes<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<Vec<ArchiveEnvelope<T>>, ArchiveError>
+    where
+        T: DeserializeOwned + Borrow<T> + Send + Sync + Clone + Unpin,
+    {
+        self.block_on(self.store.query_envelopes(rec_type, filter))
+    }
+
+    /// Retrieves only the given fields of the records matching a [Filter], see
+    /// [ArchiveStore::find_projected].
+    pub fn find_projected(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+        fields: &[&str],
+    ) -> Result<Vec<Document>, ArchiveError> {
+        self.block_on(self.store.find_projected(rec_type, filter, fields))
+    }
+
+    /// Retrieves the records as they stood at a block height or time, see
+    /// [ArchiveStore::find_as_of].
+    pub fn find_as_of<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+        as_of: AsOf,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: DeserializeOwned,
+    {
+        self.block_on(self.store.find_as_of(rec_type, filter, as_of))
+    }
+
+    /// Retrieves every version of a record of a versioned record type, see
+    /// [ArchiveStore::history].
+    pub fn history<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        key: impl Into<Bson>,
+    ) -> Result<Vec<Versioned<T>>, ArchiveError>
+    where
+        T: DeserializeOwned,
+    {
+        self.block_on(self.store.history(rec_type, key))
+    }
+
+    /// Retrieves the newest version of a record of a versioned record type, see
+    /// [ArchiveStore::latest].
+    pub fn latest<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        key: impl Into<Bson>,
+    ) -> Result<Option<Versioned<T>>, ArchiveError>
+    where
+        T: DeserializeOwned,
+    {
+        self.block_on(self.store.latest(rec_type, key))
+    }
+
+    /// Retrieves the record with the given id, see [ArchiveStore::find_by_id].
+    pub fn find_by_id<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<Option<T>, ArchiveError>
+    where
+        T: DeserializeOwned + Borrow<T> + Send + Sync + Clone + Unpin,
+    {
+        self.block_on(self.store.find_by_id(rec_type, id))
+    }
+
+    /// Retrieves the record with the given id with per-call options, see
+    /// [ArchiveStore::find_by_id_with_opts].
+    pub fn find_by_id_with_opts<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        options: &ReadOptions,
+    ) -> Result<Option<T>, ArchiveError>
+    where
+        T: DeserializeOwned + Borrow<T> + Send + Sync + Clone + Unpin,
+    {
+        self.block_on(self.store.find_by_id_with_opts(rec_type, id, options))
+    }
+
+    /// Retrieves the record with the given id along with its provenance, see
+    /// [ArchiveStore::find_envelope_by_id].
+    pub fn find_envelope_by_id<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<Option<ArchiveEnvelope<T>>, ArchiveError>
+    where
+        T: DeserializeOwned + Borrow<T> + Send + Sync + Clone + Unpin,
+    {
+        self.block_on(self.store.find_envelope_by_id(rec_type, id))
+    }
+
+    /// Retrieves a random sample of records, see [ArchiveStore::find_sampled].
+    pub fn find_sampled<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rate: f64,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: DeserializeOwned + Borrow<T> + Send + Sync + Clone + Unpin,
+    {
+        self.block_on(self.store.find_sampled(rec_type, rate))
+    }
+
+    /// Deletes the record with the given id, see [ArchiveStore::delete_by_id].
+    pub fn delete_by_id(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<bool, ArchiveError> {
+        self.block_on(self.store.delete_by_id(rec_type, id))
+    }
+
+    /// Deletes the records matching a [Filter], see [ArchiveStore::delete_where].
+    pub fn delete_where(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<u64, ArchiveError> {
+        self.block_on(self.store.delete_where(rec_type, filter))
+    }
+
+    /// Replaces the record with the given id, see [ArchiveStore::update_by_id].
+    pub fn update_by_id<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        rec: T,
+    ) -> Result<bool, ArchiveError>
+    where
+        T: Serialize + Borrow<T> + Send + Sync,
+    {
+        self.block_on(self.store.update_by_id(rec_type, id, rec))
+    }
+
+    /// Replaces the first record matching a [Filter], or archives a new one, see
+    /// [ArchiveStore::upsert].
+    pub fn upsert<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Serialize + Borrow<T> + Send + Sync,
+    {
+        self.block_on(self.store.upsert(rec_type, filter, rec))
+    }
+
+    /// Sets the lifecycle state of a record, see [ArchiveStore::set_lifecycle].
+    pub fn set_lifecycle(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        state: LifecycleState,
+        reason: Option<&str>,
+    ) -> Result<bool, ArchiveError> {
+        self.block_on(self.store.set_lifecycle(rec_type, id, state, reason))
+    }
+
+    /// Tombstones the records matching a [Filter], see [ArchiveStore::tombstone_where].
+    pub fn tombstone_where(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+        reason: &str,
+    ) -> Result<u64, ArchiveError> {
+        self.block_on(self.store.tombstone_where(rec_type, filter, reason))
+    }
+
+    /// Deletes tombstoned records and reclaims the space of deleted ones, see
+    /// [ArchiveStore::compact].
+    pub fn compact(&self, rec_type: ArchiveRecordType) -> Result<u64, ArchiveError> {
+        self.block_on(self.store.compact(rec_type))
+    }
+
+    /// Deletes the records past their retention, see [ArchiveStore::prune].
+    pub fn prune(&self) -> Result<u64, ArchiveError> {
+        self.block_on(self.store.prune())
+    }
+
+    /// Moves aged records to the cold tier, see [ArchiveStore::migrate_to_cold_tier].
+    pub fn migrate_to_cold_tier(&self) -> Result<u64, ArchiveError> {
+        self.block_on(self.store.migrate_to_cold_tier())
+    }
+
+    /// Rewrites records written with older schema versions, see
+    /// [ArchiveStore::rewrite_migrated].
+    pub fn rewrite_migrated(&self, rec_type: ArchiveRecordType) -> Result<u64, ArchiveError> {
+        self.block_on(self.store.rewrite_migrated(rec_type))
+    }
+
+    /// Finds chunks whose record is missing, see [ArchiveStore::find_orphaned_chunks].
+    pub fn find_orphaned_chunks(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Vec<String>, ArchiveError> {
+        self.block_on(self.store.find_orphaned_chunks(rec_type))
+    }
+
+    /// Deletes chunks whose record is missing, see [ArchiveStore::cleanup_orphans].
+    pub fn cleanup_orphans(&self, rec_type: ArchiveRecordType) -> Result<u64, ArchiveError> {
+        self.block_on(self.store.cleanup_orphans(rec_type))
+    }
+
+    /// Writes the output of an aggregation pipeline to another record type, see
+    /// [ArchiveStore::merge_into].
+    pub fn merge_into(
+        &self,
+        source: ArchiveRecordType,
+        pipeline: Vec<Document>,
+        target: ArchiveRecordType,
+        mode: MergeMode,
+    ) -> Result<u64, ArchiveError> {
+        self.block_on(self.store.merge_into(source, pipeline, target, mode))
+    }
+
+    /// Counts the records matching a [Filter], see [ArchiveStore::count].
+    pub fn count(&self, rec_type: ArchiveRecordType, filter: Filter) -> Result<u64, ArchiveError> {
+        self.block_on(self.store.count(rec_type, filter))
+    }
+
+    /// Returns whether any record matches a [Filter], see [ArchiveStore::exists].
+    pub fn exists(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<bool, ArchiveError> {
+        self.block_on(self.store.exists(rec_type, filter))
+    }
+
+    /// Returns the backend's statistics for a record type, see [ArchiveStore::stats].
+    pub fn stats(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<ArchiveCollectionStats, ArchiveError> {
+        self.block_on(self.store.stats(rec_type))
+    }
+
+    /// Returns statistics for a record type across namespaces, see [ArchiveStore::archive_stats].
+    pub fn archive_stats(&self, rec_type: ArchiveRecordType) -> Result<ArchiveStats, ArchiveError> {
+        self.block_on(self.store.archive_stats(rec_type))
+    }
+
+    /// Returns statistics for every record type, see [ArchiveStore::stats_all].
+    pub fn stats_all(&self) -> Result<ArchiveStatsReport, ArchiveError> {
+        self.block_on(self.store.stats_all())
+    }
+
+    /// Counts records by the value of a field or by time, see [ArchiveStore::group_count].
+    pub fn group_count(
+        &self,
+        rec_type: ArchiveRecordType,
+        group_by: GroupBy,
+    ) -> Result<Vec<(Bson, u64)>, ArchiveError> {
+        self.block_on(self.store.group_count(rec_type, group_by))
+    }
+
+    /// Runs an aggregation, see [ArchiveStore::aggregate].
+    pub fn aggregate(
+        &self,
+        rec_type: ArchiveRecordType,
+        spec: AggregationSpec,
+    ) -> Result<Vec<Document>, ArchiveError> {
+        self.block_on(self.store.aggregate(rec_type, spec))
+    }
+
+    /// Finds the keys missing from a sequence, see [ArchiveStore::audit_sequence].
+    pub fn audit_sequence<R>(
+        &self,
+        rec_type: ArchiveRecordType,
+        key_field: &str,
+        expected: R,
+    ) -> Result<Vec<MissingRange>, ArchiveError>
+    where
+        R: RangeBounds<i64>,
+    {
+        self.block_on(self.store.audit_sequence(rec_type, key_field, expected))
+    }
+
+    /// Creates secondary indexes, see [ArchiveStore::ensure_indexes].
+    pub fn ensure_indexes(
+        &self,
+        rec_type: ArchiveRecordType,
+        indexes: Vec<IndexSpec>,
+    ) -> Result<(), ArchiveError> {
+        self.block_on(self.store.ensure_indexes(rec_type, indexes))
+    }
+
+    /// Computes the Merkle root of a record type, see [ArchiveStore::merkle_root].
+    pub fn merkle_root(&self, rec_type: ArchiveRecordType) -> Result<MerkleRoot, ArchiveError> {
+        self.block_on(self.store.merkle_root(rec_type))
+    }
+
+    /// Proves that a record is archived, see [ArchiveStore::prove].
+    pub fn prove(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<InclusionProof, ArchiveError> {
+        self.block_on(self.store.prove(rec_type, id))
+    }
+
+    /// Lists the store's namespaces, see [ArchiveStore::namespaces].
+    pub fn namespaces(&self) -> Result<Vec<String>, ArchiveError> {
+        self.block_on(self.store.namespaces())
+    }
+
+    /// Copies records into another namespace, see [ArchiveStore::copy_namespace].
+    pub fn copy_namespace(
+        &self,
+        to: &str,
+        rec_types: &[ArchiveRecordType],
+    ) -> Result<u64, ArchiveError> {
+        self.block_on(self.store.copy_namespace(to, rec_types))
+    }
+
+    /// Writes a snapshot of record types to a directory, see [ArchiveStore::snapshot].
+    pub fn snapshot(
+        &self,
+        rec_types: &[ArchiveRecordType],
+        destination: impl AsRef<Path>,
+    ) -> Result<SnapshotManifest, ArchiveError> {
+        self.block_on(self.store.snapshot(rec_types, destination))
+    }
+
+    /// Restores a snapshot, see [ArchiveStore::restore].
+    pub fn restore(&self, source: impl AsRef<Path>) -> Result<u64, ArchiveError> {
+        self.block_on(self.store.restore(source))
+    }
+
+    /// Copies records to another store, see [ArchiveStore::migrate_to].
+    pub fn migrate_to(
+        &self,
+        to: &ArchiveStore,
+        rec_types: &[ArchiveRecordType],
+        checkpoint: impl AsRef<Path>,
+    ) -> Result<u64, ArchiveError> {
+        self.block_on(self.store.migrate_to(to, rec_types, checkpoint))
+    }
+}
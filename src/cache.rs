@@ -0,0 +1,118 @@
+/// Read-through caching of records looked up by id. [RecordCache] is an in-process LRU cache of
+/// the documents the backend returned for recent [crate::ArchiveStore::find_by_id] calls, keyed by
+/// record type and id, so repeated lookups of hot records, such as the same accounts queried
+/// over and over through the network endpoints, are served without a round trip to the backend.
+/// Entries are dropped when the record is updated or deleted through the store (or its clones),
+/// and expire after a fixed time to live, which bounds how stale they can get when other processes
+/// write to the same archive. Only records that were found are cached.
+use crate::ArchiveRecordType;
+use bson::Document;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+type Key = (ArchiveRecordType, String);
+
+#[derive(Debug, Clone)]
+pub(crate) struct RecordCache {
+    /// Most records held at once
+    capacity: usize,
+    /// How long a record is served from the cache after it was read from the backend
+    ttl: Duration,
+    entries: Arc<Mutex<Entries>>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    /// Each cached record's document, when it was read from the backend and when it was last used
+    records: HashMap<Key, (Document, Instant, u64)>,
+    /// The cached records by when they were last used, least recently used first
+    recency: BTreeMap<u64, Key>,
+    /// Incremented on every use, ordering uses
+    clock: u64,
+}
+
+impl Entries {
+    fn remove(&mut self, key: &Key) {
+        if let Some((_, _, used)) = self.records.remove(key) {
+            self.recency.remove(&used);
+        }
+    }
+}
+
+impl RecordCache {
+    pub(crate) fn new(capacity: usize, ttl: Duration) -> Self {
+        RecordCache {
+            capacity,
+            ttl,
+            entries: Arc::new(Mutex::new(Entries::default())),
+        }
+    }
+
+    /// An empty cache with the same settings, for a store derived from this cache's store.
+    pub(crate) fn emptied(&self) -> Self {
+        RecordCache::new(self.capacity, self.ttl)
+    }
+
+    /// Returns the cached document of the record of type `rec_type` with the given id, if it was
+    /// read within the time to live.
+    pub(crate) fn get(&self, rec_type: &ArchiveRecordType, id: &str) -> Option<Document> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let entries = &mut *entries;
+        let key = (rec_type.clone(), id.to_string());
+        let (doc, read, used) = entries.records.get_mut(&key)?;
+        if read.elapsed() >= self.ttl {
+            entries.remove(&key);
+            return None;
+        }
+        entries.clock += 1;
+        entries.recency.remove(used);
+        *used = entries.clock;
+        let doc = doc.clone();
+        entries.recency.insert(entries.clock, key);
+        Some(doc)
+    }
+
+    /// Caches the document read from the backend for the record of type `rec_type` with the
+    /// given id, evicting the least recently used records if the cache is full.
+    pub(crate) fn insert(&self, rec_type: &ArchiveRecordType, id: &str, doc: Document) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let key = (rec_type.clone(), id.to_string());
+        entries.remove(&key);
+        while entries.records.len() >= self.capacity {
+            let Some((_, evicted)) = entries.recency.pop_first() else {
+                break;
+            };
+            entries.records.remove(&evicted);
+        }
+        entries.clock += 1;
+        let used = entries.clock;
+        entries.recency.insert(used, key.clone());
+        entries.records.insert(key, (doc, Instant::now(), used));
+    }
+
+    /// Drops the record of type `rec_type` with the given id, after it was updated or deleted.
+    pub(crate) fn invalidate(&self, rec_type: &ArchiveRecordType, id: &str) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.remove(&(rec_type.clone(), id.to_string()));
+    }
+
+    /// Drops every record of type `rec_type`, after an operation that may have changed any of
+    /// them.
+    pub(crate) fn invalidate_type(&self, rec_type: &ArchiveRecordType) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let Entries {
+            records, recency, ..
+        } = &mut *entries;
+        records.retain(|(cached_type, _), (_, _, used)| {
+            let keep = cached_type != rec_type;
+            if !keep {
+                recency.remove(used);
+            }
+            keep
+        });
+    }
+}
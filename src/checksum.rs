@@ -0,0 +1,90 @@
+/// Integrity checksums of archived records. A checksummed record has a SHA-256 hash of its
+/// canonical serialised form stored alongside it, which can later be recomputed from the stored
+/// copy to prove it is byte-identical to what was archived. The canonical form is the record's
+/// relaxed extended JSON representation with the keys of every object sorted, so reordering of
+/// fields by the backend or the driver does not change the hash.
+use crate::chunking;
+use bson::{Bson, Document};
+use serde_json::{Map, Value};
+
+/// Name of the field holding a record's checksum
+pub(crate) const CHECKSUM_FIELD: &str = "_checksum";
+
+/// The outcome of verifying a single archived record against its stored checksum.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerificationResult {
+    /// The stored record hashes to its stored checksum.
+    Match,
+    /// The stored record no longer hashes to its stored checksum.
+    Mismatch {
+        /// The checksum stored when the record was archived
+        expected: String,
+        /// The checksum of the record as it is stored now
+        actual: String,
+    },
+    /// There is no record with the given id.
+    Missing,
+    /// The record exists but was not archived with a checksum.
+    NoChecksum,
+}
+
+/// Summary of verifying every record of a record type, e.g. for a periodic audit job.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerificationSummary {
+    /// Number of records examined
+    pub checked: usize,
+    /// Number of records whose checksum matched
+    pub matched: usize,
+    /// Number of records that were archived without a checksum
+    pub unchecksummed: usize,
+    /// Ids of the records whose checksum did not match
+    pub mismatched: Vec<String>,
+}
+
+/// Computes the checksum of a record document. The backend assigned `_id` is excluded, so the
+/// checksum is the same before and after the record was stored.
+pub(crate) fn compute(doc: &Document) -> String {
+    chunking::checksum(&canonical_bytes(doc))
+}
+
+/// The canonical serialised form of a record document that checksums are computed over, without
+/// its `_id`.
+pub(crate) fn canonical_bytes(doc: &Document) -> Vec<u8> {
+    let mut doc = doc.clone();
+    doc.remove("_id");
+    let canonical = canonicalize(Bson::Document(doc).into_relaxed_extjson());
+    serde_json::to_vec(&canonical).unwrap_or_default()
+}
+
+/// Verifies a decoded record document against the checksum it was stored with, if any.
+pub(crate) fn verify(doc: &Document, expected: Option<String>) -> VerificationResult {
+    match expected {
+        None => VerificationResult::NoChecksum,
+        Some(expected) => {
+            let actual = compute(doc);
+            if actual == expected {
+                VerificationResult::Match
+            } else {
+                VerificationResult::Mismatch { expected, actual }
+            }
+        }
+    }
+}
+
+/// Recursively sorts the keys of every object within a JSON value.
+fn canonicalize(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<(String, Value)> = map.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k, canonicalize(v)))
+                    .collect::<Map<String, Value>>(),
+            )
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(canonicalize).collect()),
+        other => other,
+    }
+}
@@ -0,0 +1,164 @@
+/// Chunked storage for records too large to fit in a single backend document. A record whose
+/// serialised form is larger than the configured threshold is split into chunk documents, each
+/// carrying the id shared with the record (`files_id`), its index (`n`), the total number of
+/// chunks and a SHA-256 checksum of the whole payload. A small manifest document is stored in the
+/// record's place so reads can find, verify and reassemble the chunks. The layout intentionally
+/// mirrors GridFS, with the manifest playing the role of the files entry. A record replacing a
+/// stored one keeps that record's id, so its manifest records the fresh id its chunks share
+/// instead.
+use anyhow::Result;
+use bson::{doc, oid::ObjectId, spec::BinarySubtype, Binary, Bson, Document};
+use core::fmt;
+use sha2::{Digest, Sha256};
+
+/// Name of the manifest field describing the chunks of a chunked record
+pub(crate) const MANIFEST_FIELD: &str = "archive_chunks";
+/// Name of the chunk field holding the id of the record the chunk belongs to
+pub(crate) const FILES_ID_FIELD: &str = "files_id";
+/// Name of the chunk field holding the index of the chunk within its record
+pub(crate) const INDEX_FIELD: &str = "n";
+/// Name of the chunk field holding the payload bytes
+const DATA_FIELD: &str = "data";
+
+/// Returned when the chunks of a chunked record are missing or fail checksum verification.
+#[derive(Debug, Clone)]
+pub struct ChunkIntegrityError {
+    /// The id of the chunked record
+    pub id: String,
+    /// What was wrong with the record's chunks
+    pub reason: String,
+}
+
+impl fmt::Display for ChunkIntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Integrity check failed for chunked record {}: {}",
+            self.id, self.reason
+        )
+    }
+}
+
+impl std::error::Error for ChunkIntegrityError {}
+
+/// Hex encoded SHA-256 checksum of a serialised payload.
+pub(crate) fn checksum(raw: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(raw))
+}
+
+/// Splits a serialised record into chunk documents of at most `chunk_size` bytes each, and returns
+/// them together with the manifest document to store in place of the record. All documents share
+/// a freshly generated id.
+pub(crate) fn split(raw: &[u8], chunk_size: usize) -> (Document, Vec<Document>) {
+    let id = ObjectId::new();
+    let sum = checksum(raw);
+    let total = raw.len().div_ceil(chunk_size) as i64;
+
+    let chunks = raw
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(n, data)| {
+            doc! {
+                FILES_ID_FIELD: id,
+                INDEX_FIELD: n as i64,
+                "total": total,
+                "checksum": &sum,
+                DATA_FIELD: Binary { subtype: BinarySubtype::Generic, bytes: data.to_vec() },
+            }
+        })
+        .collect();
+
+    let manifest = doc! {
+        "_id": id,
+        MANIFEST_FIELD: {
+            "total": total,
+            "length": raw.len() as i64,
+            "checksum": sum,
+        },
+    };
+
+    (manifest, chunks)
+}
+
+/// Splits a serialised record replacing a stored one like [split], returning a manifest without
+/// an `_id`, which records the id the chunks share, so that it can keep the replaced record's.
+pub(crate) fn split_replacement(raw: &[u8], chunk_size: usize) -> (Document, Vec<Document>) {
+    let (mut manifest, chunks) = split(raw, chunk_size);
+    let id = manifest.remove("_id").unwrap_or(Bson::Null);
+    if let Ok(info) = manifest.get_document_mut(MANIFEST_FIELD) {
+        info.insert(FILES_ID_FIELD, id);
+    }
+    (manifest, chunks)
+}
+
+/// If `doc` is the manifest of a chunked record, returns the id its chunks are stored under.
+pub(crate) fn manifest_id(doc: &Document) -> Option<ObjectId> {
+    let info = match doc.get(MANIFEST_FIELD) {
+        Some(Bson::Document(info)) => info,
+        _ => return None,
+    };
+    match (info.get(FILES_ID_FIELD), doc.get("_id")) {
+        (Some(Bson::ObjectId(id)), _) | (None, Some(Bson::ObjectId(id))) => Some(*id),
+        _ => None,
+    }
+}
+
+/// Reassembles the payload of a chunked record from its manifest and chunk documents (in any
+/// order), verifying that every chunk is present and that the checksum matches.
+pub(crate) fn reassemble(manifest: &Document, mut chunks: Vec<Document>) -> Result<Vec<u8>> {
+    let id = manifest
+        .get("_id")
+        .cloned()
+        .unwrap_or(Bson::Null)
+        .to_string();
+    let integrity = |reason: String| ChunkIntegrityError {
+        id: id.clone(),
+        reason,
+    };
+
+    let info = manifest
+        .get_document(MANIFEST_FIELD)
+        .map_err(|_| integrity("malformed manifest".to_string()))?;
+    let total = info
+        .get_i64("total")
+        .map_err(|_| integrity("manifest is missing the chunk count".to_string()))?;
+    let length = info
+        .get_i64("length")
+        .map_err(|_| integrity("manifest is missing the payload length".to_string()))?;
+    let expected = info
+        .get_str("checksum")
+        .map_err(|_| integrity("manifest is missing the checksum".to_string()))?;
+
+    chunks.sort_by_key(|chunk| chunk.get_i64(INDEX_FIELD).unwrap_or(i64::MAX));
+
+    let mut raw = Vec::with_capacity(length as usize);
+    for n in 0..total {
+        let chunk = chunks
+            .get(n as usize)
+            .filter(|chunk| chunk.get_i64(INDEX_FIELD).ok() == Some(n))
+            .ok_or_else(|| integrity(format!("chunk {} of {} is missing", n, total)))?;
+        let data = chunk
+            .get_binary_generic(DATA_FIELD)
+            .map_err(|_| integrity(format!("chunk {} of {} has no data", n, total)))?;
+        raw.extend_from_slice(data);
+    }
+
+    if raw.len() as i64 != length {
+        return Err(integrity(format!(
+            "reassembled {} bytes but expected {}",
+            raw.len(),
+            length
+        ))
+        .into());
+    }
+    let actual = checksum(&raw);
+    if actual != expected {
+        return Err(integrity(format!(
+            "checksum mismatch, expected {} but got {}",
+            expected, actual
+        ))
+        .into());
+    }
+
+    Ok(raw)
+}
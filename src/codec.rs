@@ -0,0 +1,191 @@
+/// Serialisation formats for archived records. Records are stored as BSON documents by default,
+/// which every backend understands and can filter on. A store built with another [Codec] instead
+/// serialises each record to bytes in that format and stores them in the same kind of wrapper
+/// document as compression, see [crate::Compression], with a `_codec` field naming the format, so
+/// that records written with different codecs, or before one was chosen, are each decoded
+/// correctly. Compression, if enabled, is applied to the encoded bytes.
+///
+/// Like compressed records, encoded records can only be found by id: their fields are opaque to
+/// backend filters.
+use anyhow::{Context, Result};
+use bson::{oid::ObjectId, spec::BinarySubtype, Binary, Bson, DateTime, Decimal128, Document};
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// List of supported serialisation formats for archived records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Codec {
+    /// Records are stored as plain BSON documents.
+    #[default]
+    Bson,
+    /// Records are serialised to relaxed extended JSON, as written by the
+    /// [crate::FileFormat::Json] filesystem format. Readable by any tool, but integers may be read
+    /// back as a different width.
+    Json,
+    /// Records are serialised to CBOR (RFC 8949), with BSON specific values such as object ids
+    /// as extended JSON maps. Integers are read back as 64-bit integers.
+    Cbor,
+    /// Records are serialised with bincode, the most compact and fastest encoding, for large
+    /// batches. Not readable outside of this crate.
+    Bincode,
+}
+
+impl Codec {
+    /// The value stored in the `_codec` field for this codec, `None` for plain BSON documents.
+    pub(crate) fn name(&self) -> Option<&'static str> {
+        match self {
+            Codec::Bson => None,
+            Codec::Json => Some("json"),
+            Codec::Cbor => Some("cbor"),
+            Codec::Bincode => Some("bincode"),
+        }
+    }
+
+    /// The codec stored in a `_codec` field as `name`.
+    pub(crate) fn from_name(name: &str) -> Option<Codec> {
+        match name {
+            "bson" => Some(Codec::Bson),
+            "json" => Some(Codec::Json),
+            "cbor" => Some(Codec::Cbor),
+            "bincode" => Some(Codec::Bincode),
+            _ => None,
+        }
+    }
+
+    /// Serialises a record document to bytes.
+    pub(crate) fn encode(&self, doc: &Document) -> Result<Vec<u8>> {
+        match self {
+            Codec::Bson => bson::to_vec(doc).context("Failed to serialise record to BSON"),
+            Codec::Json => serde_json::to_vec(&Bson::Document(doc.clone()).into_relaxed_extjson())
+                .context("Failed to serialise record to JSON"),
+            Codec::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(doc, &mut bytes)
+                    .context("Failed to serialise record to CBOR")?;
+                Ok(bytes)
+            }
+            Codec::Bincode => bincode::serialize(&Value::from(Bson::Document(doc.clone())))
+                .context("Failed to serialise record with bincode"),
+        }
+    }
+
+    /// Deserialises a record document from bytes written by [Codec::encode].
+    pub(crate) fn decode(&self, bytes: &[u8]) -> Result<Document> {
+        match self {
+            Codec::Bson => bson::from_slice(bytes).context("Failed to deserialise BSON record"),
+            Codec::Json => {
+                let json: serde_json::Value =
+                    serde_json::from_slice(bytes).context("Failed to deserialise JSON record")?;
+                match Bson::try_from(json).context("Failed to deserialise JSON record")? {
+                    Bson::Document(doc) => Ok(doc),
+                    _ => anyhow::bail!("JSON record is not an object"),
+                }
+            }
+            Codec::Cbor => {
+                ciborium::from_reader(bytes).context("Failed to deserialise CBOR record")
+            }
+            Codec::Bincode => {
+                let value: Value =
+                    bincode::deserialize(bytes).context("Failed to deserialise bincode record")?;
+                match Bson::try_from(value)? {
+                    Bson::Document(doc) => Ok(doc),
+                    _ => anyhow::bail!("Bincode record is not a document"),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name().unwrap_or("bson"))
+    }
+}
+
+/// A BSON value in a form bincode can serialise. Bincode isn't self-describing, so it can't
+/// deserialise [Bson] itself, which relies on the format to say which type comes next.
+#[derive(Serialize, Deserialize)]
+enum Value {
+    Null,
+    Boolean(bool),
+    Int32(i32),
+    Int64(i64),
+    Double(f64),
+    String(String),
+    Array(Vec<Value>),
+    Document(Vec<(String, Value)>),
+    ObjectId([u8; 12]),
+    DateTime(i64),
+    Binary(u8, Vec<u8>),
+    Decimal128([u8; 16]),
+    /// Any other, rarely archived, value as the BSON bytes of a `{ "v": value }` document
+    Other(Vec<u8>),
+}
+
+impl From<Bson> for Value {
+    fn from(value: Bson) -> Self {
+        match value {
+            Bson::Null => Value::Null,
+            Bson::Boolean(b) => Value::Boolean(b),
+            Bson::Int32(i) => Value::Int32(i),
+            Bson::Int64(i) => Value::Int64(i),
+            Bson::Double(f) => Value::Double(f),
+            Bson::String(s) => Value::String(s),
+            Bson::Array(values) => Value::Array(values.into_iter().map(Value::from).collect()),
+            Bson::Document(doc) => Value::Document(
+                doc.into_iter()
+                    .map(|(key, value)| (key, Value::from(value)))
+                    .collect(),
+            ),
+            Bson::ObjectId(id) => Value::ObjectId(id.bytes()),
+            Bson::DateTime(time) => Value::DateTime(time.timestamp_millis()),
+            Bson::Binary(binary) => Value::Binary(binary.subtype.into(), binary.bytes),
+            Bson::Decimal128(decimal) => Value::Decimal128(decimal.bytes()),
+            other => {
+                let mut doc = Document::new();
+                doc.insert("v", other);
+                // Serialising a document of a single valid value can't fail.
+                Value::Other(bson::to_vec(&doc).unwrap_or_default())
+            }
+        }
+    }
+}
+
+impl TryFrom<Value> for Bson {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Value) -> Result<Self> {
+        Ok(match value {
+            Value::Null => Bson::Null,
+            Value::Boolean(b) => Bson::Boolean(b),
+            Value::Int32(i) => Bson::Int32(i),
+            Value::Int64(i) => Bson::Int64(i),
+            Value::Double(f) => Bson::Double(f),
+            Value::String(s) => Bson::String(s),
+            Value::Array(values) => Bson::Array(
+                values
+                    .into_iter()
+                    .map(Bson::try_from)
+                    .collect::<Result<_>>()?,
+            ),
+            Value::Document(fields) => Bson::Document(
+                fields
+                    .into_iter()
+                    .map(|(key, value)| Ok((key, Bson::try_from(value)?)))
+                    .collect::<Result<_>>()?,
+            ),
+            Value::ObjectId(bytes) => Bson::ObjectId(ObjectId::from_bytes(bytes)),
+            Value::DateTime(millis) => Bson::DateTime(DateTime::from_millis(millis)),
+            Value::Binary(subtype, bytes) => Bson::Binary(Binary {
+                subtype: BinarySubtype::from(subtype),
+                bytes,
+            }),
+            Value::Decimal128(bytes) => Bson::Decimal128(Decimal128::from_bytes(bytes)),
+            Value::Other(bytes) => {
+                let mut doc: Document =
+                    bson::from_slice(&bytes).context("Failed to deserialise bincode record")?;
+                doc.remove("v").unwrap_or(Bson::Null)
+            }
+        })
+    }
+}
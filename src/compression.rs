@@ -0,0 +1,194 @@
+/// Optional compression of archived records. A compressed record is serialised to bytes with the
+/// store's [Codec], BSON by default, compressed, and stored as a small wrapper document of the form
+/// `{ _encoding: "zstd", _data: Binary }`, the `_encoding` naming the compression. Records
+/// serialised with another codec are stored in the same wrapper whether compressed or not, with a
+/// `_codec` field naming the codec and no `_encoding` if they aren't compressed. A record's own
+/// `_id`, if it has one, is kept on the wrapper document too, so it can still be found by id. On
+/// read, any document carrying a known `_encoding` or `_codec` is decompressed and decoded before
+/// being deserialised, while documents without either (e.g. those written before compression was
+/// enabled) are deserialised as they are, so mixed collections read correctly.
+use crate::Codec;
+use anyhow::{anyhow, Context, Result};
+use bson::{spec::BinarySubtype, Binary, Bson, Document};
+use core::fmt;
+use flate2::{read::GzDecoder, write::GzEncoder};
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::{Read, Write};
+
+/// Name of the field recording how a compressed record's payload is encoded
+const ENCODING_FIELD: &str = "_encoding";
+/// Name of the field recording the [Codec] a wrapped record's payload is serialised with
+const CODEC_FIELD: &str = "_codec";
+/// Name of the field holding a compressed record's payload
+const DATA_FIELD: &str = "_data";
+/// The fields of a compressed record's wrapper document, needed to decompress it
+pub(crate) const WRAPPER_FIELDS: [&str; 3] = [ENCODING_FIELD, CODEC_FIELD, DATA_FIELD];
+/// zstd compression level used for archived records. Level 3 is zstd's own default and is a good
+/// trade off between speed and ratio for the JSON-like data we archive.
+const ZSTD_LEVEL: i32 = 3;
+
+/// List of supported compression modes for archived records.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Records are stored as plain documents.
+    #[default]
+    None,
+    /// Records are serialised to BSON and compressed with zstd.
+    Zstd,
+    /// Records are serialised to BSON and compressed with gzip. Slower and larger than zstd, for
+    /// archives read by tools that only understand gzip.
+    Gzip,
+}
+
+impl Compression {
+    /// The value stored in the `_encoding` field for this compression mode.
+    fn encoding(&self) -> Option<&'static str> {
+        match *self {
+            Compression::None => None,
+            Compression::Zstd => Some("zstd"),
+            Compression::Gzip => Some("gzip"),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Compression::None => write!(f, "none"),
+            Compression::Zstd => write!(f, "zstd"),
+            Compression::Gzip => write!(f, "gzip"),
+        }
+    }
+}
+
+/// Returned when a record is too large for the backend to store, even after compression.
+#[derive(Debug, Clone)]
+pub struct RecordTooLarge {
+    /// Size of the record in bytes, as it would have been sent to the backend
+    pub size: usize,
+    /// Largest document size in bytes the backend accepts
+    pub limit: usize,
+    /// Compression that was applied to the record
+    pub compression: Compression,
+}
+
+impl fmt::Display for RecordTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Record of {} bytes (compression: {}) exceeds the backend document size limit of {} \
+            bytes",
+            self.size, self.compression, self.limit
+        )
+    }
+}
+
+impl std::error::Error for RecordTooLarge {}
+
+/// Serialises and compresses a record into the wrapper document stored by the backend. A `limit`
+/// of `Some(n)` rejects records whose compressed form would still be larger than `n` bytes.
+pub(crate) fn compress<T: Serialize>(
+    rec: &T,
+    compression: &Compression,
+    codec: Codec,
+    limit: Option<usize>,
+) -> Result<Document> {
+    let doc = bson::to_document(rec).context("Failed to serialise record to BSON")?;
+    let raw = codec.encode(&doc)?;
+
+    let data = match compression {
+        // Nothing to wrap, store the record as a plain document.
+        Compression::None if codec == Codec::Bson => {
+            check_size(raw.len(), compression, limit)?;
+            return Ok(doc);
+        }
+        Compression::None => raw,
+        Compression::Zstd => {
+            zstd::encode_all(raw.as_slice(), ZSTD_LEVEL).context("Failed to compress record")?
+        }
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder
+                .write_all(&raw)
+                .and_then(|_| encoder.finish())
+                .context("Failed to compress record")?
+        }
+    };
+    check_size(data.len(), compression, limit)?;
+
+    let mut wrapper = Document::new();
+    if let Some(encoding) = compression.encoding() {
+        wrapper.insert(ENCODING_FIELD, encoding);
+    }
+    if let Some(name) = codec.name() {
+        wrapper.insert(CODEC_FIELD, name);
+    }
+    wrapper.insert(
+        DATA_FIELD,
+        Binary {
+            subtype: BinarySubtype::Generic,
+            bytes: data,
+        },
+    );
+    if let Some(id) = doc.get("_id") {
+        wrapper.insert("_id", id.clone());
+    }
+    Ok(wrapper)
+}
+
+/// Deserialises a document read from the backend, transparently decompressing and decoding it if
+/// it is a wrapper document.
+pub(crate) fn decompress<T: DeserializeOwned>(doc: Document) -> Result<T> {
+    let encoding = match doc.get(ENCODING_FIELD) {
+        Some(Bson::String(encoding)) => Some(encoding.as_str()),
+        _ => None,
+    };
+    let codec = match doc.get(CODEC_FIELD) {
+        Some(Bson::String(codec)) => Some(codec.as_str()),
+        _ => None,
+    };
+    let data = match doc.get(DATA_FIELD) {
+        Some(Bson::Binary(data)) if encoding.is_some() || codec.is_some() => &data.bytes,
+        // Not a wrapper document, so the record was stored as it is.
+        _ => return bson::from_document(doc).context("Failed to deserialise record"),
+    };
+
+    let raw = match encoding {
+        None => data.clone(),
+        Some("zstd") => {
+            zstd::decode_all(data.as_slice()).context("Failed to decompress zstd record")?
+        }
+        Some("gzip") => {
+            let mut raw = Vec::new();
+            GzDecoder::new(data.as_slice())
+                .read_to_end(&mut raw)
+                .context("Failed to decompress gzip record")?;
+            raw
+        }
+        Some(other) => anyhow::bail!("Unknown archived record encoding: '{}'", other),
+    };
+    let codec = match codec {
+        None => Codec::Bson,
+        Some(name) => Codec::from_name(name)
+            .ok_or_else(|| anyhow!("Unknown archived record codec: '{}'", name))?,
+    };
+    let mut rec = codec.decode(&raw)?;
+    // Records without an id of their own were given one by the backend.
+    if let (false, Some(id)) = (rec.contains_key("_id"), doc.get("_id")) {
+        rec.insert("_id", id.clone());
+    }
+    bson::from_document(rec).context("Failed to deserialise decompressed record")
+}
+
+/// Fails with [RecordTooLarge] if `size` is over the backend's document size `limit`.
+fn check_size(size: usize, compression: &Compression, limit: Option<usize>) -> Result<()> {
+    match limit {
+        Some(limit) if size > limit => Err(RecordTooLarge {
+            size,
+            limit,
+            compression: compression.clone(),
+        }
+        .into()),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,357 @@
+/// Loading a store's configuration from a TOML or YAML file, or from `LASR_ARCHIVE_*` environment
+/// variables, so that deployments don't hardcode connection details, and the credentials in them,
+/// into the code that archives records.
+use crate::{
+    custom_archive, uri, ArchiveBackends, ArchiveError, ArchiveStore, ArchiveStoreBuilder, Codec,
+    Compression, Credentials, RateLimits, RetryPolicy,
+};
+use anyhow::Context;
+use core::fmt;
+use serde_derive::Deserialize;
+use std::env::{self, VarError};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Environment variable naming a config file for [ArchiveConfig::from_env] to read first
+const CONFIG_VAR: &str = "LASR_ARCHIVE_CONFIG";
+
+/// The settings of an [ArchiveStore] that can be loaded from a config file or the environment,
+/// e.g. with [ArchiveStore::from_env]. Every setting is optional in the file, so that a file can
+/// hold the settings shared by every deployment and the environment the rest. A TOML file looks
+/// like:
+///
+/// ```toml
+/// backend = "postgres"
+/// uri = "postgres://archive.internal:5432/lasr"
+/// datastore = "lasr_archive"
+/// compression = "zstd"
+///
+/// [retry]
+/// max_attempts = 5
+///
+/// [limits]
+/// max_in_flight = 8
+/// ```
+///
+/// Each setting can also be set by the environment variable named in its description, which
+/// takes precedence over the file. Use [ArchiveConfig::builder] to set anything else on the store.
+#[derive(Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArchiveConfig {
+    /// The backend: `mongodb`, `postgres`, `sqlite`, `s3`, `rocksdb`, `filesystem`, `memory`,
+    /// `ipfs`, `arweave`, or the name of a backend registered with
+    /// [ArchiveStore::register_backend].
+    /// Defaults to `mongodb`. `LASR_ARCHIVE_BACKEND`
+    pub backend: Option<String>,
+    /// The backend-specific URI to connect to. `LASR_ARCHIVE_URI`
+    pub uri: Option<String>,
+    /// Root directory of the `filesystem` backend, or the index directory of the `ipfs` and
+    /// `arweave` backends. `LASR_ARCHIVE_ROOT`
+    pub root: Option<PathBuf>,
+    /// Name of the datastore. `LASR_ARCHIVE_DATASTORE`
+    pub datastore: Option<String>,
+    /// User to connect as, in place of any in the URI, so that the URI needn't hold credentials.
+    /// `LASR_ARCHIVE_USERNAME`
+    pub username: Option<String>,
+    /// Password of the [ArchiveConfig::username], or without a username, a token used as the
+    /// password of the user named in the URI, see [Credentials]. `LASR_ARCHIVE_PASSWORD`
+    pub password: Option<String>,
+    /// Compression applied to records: `none`, `zstd` or `gzip`. Defaults to `none`.
+    /// `LASR_ARCHIVE_COMPRESSION`
+    pub compression: Option<String>,
+    /// Format records are serialised in: `bson`, `json`, `cbor` or `bincode`, see [Codec].
+    /// Defaults to `bson`. `LASR_ARCHIVE_CODEC`
+    pub codec: Option<String>,
+    /// Retrying of operations that fail with transient errors. Failed operations are not retried
+    /// unless this is set, by the file or by any of its variables.
+    pub retry: Option<RetryConfig>,
+    /// Limits on the operations performed against the backend, see [RateLimits]. Operations are
+    /// unlimited unless this is set, by the file or by any of its variables.
+    pub limits: Option<RateLimits>,
+}
+
+impl fmt::Debug for ArchiveConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Never print the credentials.
+        f.debug_struct("ArchiveConfig")
+            .field("backend", &self.backend)
+            .field("uri", &self.uri.as_deref().map(uri::redact))
+            .field("root", &self.root)
+            .field("datastore", &self.datastore)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "***"))
+            .field("compression", &self.compression)
+            .field("codec", &self.codec)
+            .field("retry", &self.retry)
+            .field("limits", &self.limits)
+            .finish()
+    }
+}
+
+/// The [RetryPolicy] of an [ArchiveConfig]. Settings left out take their value from
+/// [RetryPolicy::default].
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    /// See [RetryPolicy::max_attempts]. `LASR_ARCHIVE_RETRY_MAX_ATTEMPTS`
+    pub max_attempts: Option<u32>,
+    /// See [RetryPolicy::initial_backoff], in milliseconds.
+    /// `LASR_ARCHIVE_RETRY_INITIAL_BACKOFF_MS`
+    pub initial_backoff_ms: Option<u64>,
+    /// See [RetryPolicy::max_backoff], in milliseconds. `LASR_ARCHIVE_RETRY_MAX_BACKOFF_MS`
+    pub max_backoff_ms: Option<u64>,
+    /// See [RetryPolicy::multiplier]. `LASR_ARCHIVE_RETRY_MULTIPLIER`
+    pub multiplier: Option<f64>,
+    /// See [RetryPolicy::jitter]. `LASR_ARCHIVE_RETRY_JITTER`
+    pub jitter: Option<bool>,
+}
+
+impl ArchiveConfig {
+    /// Reads a config file, in TOML if its name ends in `.toml` or YAML if it ends in `.yaml` or
+    /// `.yml`.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ArchiveError> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        if !matches!(extension, Some("toml" | "yaml" | "yml")) {
+            return Err(ArchiveError::invalid_input(format!(
+                "Config file {} must end in .toml, .yaml or .yml",
+                path.display()
+            )));
+        }
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Reading config file {}", path.display()))?;
+        let config = match extension {
+            Some("toml") => toml::from_str(&contents).map_err(|e| e.to_string()),
+            _ => serde_yaml::from_str(&contents).map_err(|e| e.to_string()),
+        };
+        config.map_err(|e| {
+            ArchiveError::invalid_input(format!("Invalid config file {}: {}", path.display(), e))
+        })
+    }
+
+    /// Reads the config file named by `LASR_ARCHIVE_CONFIG`, if set, then overrides its settings
+    /// with those set by environment variables, as by [ArchiveConfig::with_env].
+    pub fn from_env() -> Result<Self, ArchiveError> {
+        let config = match var(CONFIG_VAR)? {
+            Some(path) => ArchiveConfig::from_file(path)?,
+            None => ArchiveConfig::default(),
+        };
+        config.with_env()
+    }
+
+    /// Overrides the settings with those set by environment variables. Variables set to an
+    /// empty string are treated as unset.
+    pub fn with_env(mut self) -> Result<Self, ArchiveError> {
+        override_with(&mut self.backend, "LASR_ARCHIVE_BACKEND")?;
+        override_with(&mut self.uri, "LASR_ARCHIVE_URI")?;
+        override_with(&mut self.root, "LASR_ARCHIVE_ROOT")?;
+        override_with(&mut self.datastore, "LASR_ARCHIVE_DATASTORE")?;
+        override_with(&mut self.username, "LASR_ARCHIVE_USERNAME")?;
+        override_with(&mut self.password, "LASR_ARCHIVE_PASSWORD")?;
+        override_with(&mut self.compression, "LASR_ARCHIVE_COMPRESSION")?;
+        override_with(&mut self.codec, "LASR_ARCHIVE_CODEC")?;
+
+        let mut retry = self.retry.take().unwrap_or_default();
+        override_with(&mut retry.max_attempts, "LASR_ARCHIVE_RETRY_MAX_ATTEMPTS")?;
+        override_with(
+            &mut retry.initial_backoff_ms,
+            "LASR_ARCHIVE_RETRY_INITIAL_BACKOFF_MS",
+        )?;
+        override_with(
+            &mut retry.max_backoff_ms,
+            "LASR_ARCHIVE_RETRY_MAX_BACKOFF_MS",
+        )?;
+        override_with(&mut retry.multiplier, "LASR_ARCHIVE_RETRY_MULTIPLIER")?;
+        override_with(&mut retry.jitter, "LASR_ARCHIVE_RETRY_JITTER")?;
+        if retry != RetryConfig::default() {
+            self.retry = Some(retry);
+        }
+
+        let mut limits = self.limits.take().unwrap_or_default();
+        override_with(&mut limits.max_in_flight, "LASR_ARCHIVE_MAX_IN_FLIGHT")?;
+        override_with(&mut limits.ops_per_sec, "LASR_ARCHIVE_OPS_PER_SEC")?;
+        override_with(&mut limits.bytes_per_sec, "LASR_ARCHIVE_BYTES_PER_SEC")?;
+        if limits != RateLimits::default() {
+            self.limits = Some(limits);
+        }
+        Ok(self)
+    }
+
+    /// A builder configured with these settings, to set anything else on the store before it's
+    /// built. Fails if a setting is invalid or a required one is missing.
+    pub fn builder(&self) -> Result<ArchiveStoreBuilder, ArchiveError> {
+        let mut builder = ArchiveStoreBuilder::default();
+        let backend = self.backend()?;
+        if !matches!(
+            backend,
+            ArchiveBackends::Filesystem { .. } | ArchiveBackends::InMemory
+        ) {
+            let uri = self.uri.clone().ok_or_else(|| {
+                ArchiveError::invalid_input("No URI configured, set LASR_ARCHIVE_URI")
+            })?;
+            builder.uri(uri);
+        }
+        match (&self.username, &self.password) {
+            (Some(username), Some(password)) => {
+                builder.credentials(Credentials::user_password(username, password));
+            }
+            (None, Some(token)) => {
+                builder.credentials(Credentials::Token(token.clone()));
+            }
+            (Some(_), None) => {
+                return Err(ArchiveError::invalid_input(
+                    "A username is configured without a password, set LASR_ARCHIVE_PASSWORD",
+                ))
+            }
+            (None, None) => {}
+        }
+        builder.backend(backend);
+
+        let datastore = self.datastore.clone().ok_or_else(|| {
+            ArchiveError::invalid_input("No datastore configured, set LASR_ARCHIVE_DATASTORE")
+        })?;
+        builder.datastore(datastore);
+
+        if let Some(compression) = &self.compression {
+            builder.compression(match compression.as_str() {
+                "none" => Compression::None,
+                "zstd" => Compression::Zstd,
+                "gzip" => Compression::Gzip,
+                other => {
+                    return Err(ArchiveError::invalid_input(format!(
+                        "Unknown compression '{}', expected none, zstd or gzip",
+                        other
+                    )))
+                }
+            });
+        }
+
+        if let Some(codec) = &self.codec {
+            builder.codec(Codec::from_name(codec).ok_or_else(|| {
+                ArchiveError::invalid_input(format!(
+                    "Unknown codec '{}', expected bson, json, cbor or bincode",
+                    codec
+                ))
+            })?);
+        }
+
+        if let Some(retry) = &self.retry {
+            let mut policy = RetryPolicy::default();
+            if let Some(max_attempts) = retry.max_attempts {
+                policy.max_attempts = max_attempts;
+            }
+            if let Some(initial_backoff_ms) = retry.initial_backoff_ms {
+                policy.initial_backoff = Duration::from_millis(initial_backoff_ms);
+            }
+            if let Some(max_backoff_ms) = retry.max_backoff_ms {
+                policy.max_backoff = Duration::from_millis(max_backoff_ms);
+            }
+            if let Some(multiplier) = retry.multiplier {
+                policy.multiplier = multiplier;
+            }
+            if let Some(jitter) = retry.jitter {
+                policy.jitter = jitter;
+            }
+            builder.retry_policy(policy);
+        }
+        if let Some(limits) = &self.limits {
+            builder.rate_limits(limits.clone());
+        }
+        Ok(builder)
+    }
+
+    /// Builds a store with these settings.
+    fn build(&self) -> Result<ArchiveStore, ArchiveError> {
+        self.builder()?.build().map_err(ArchiveError::invalid_input)
+    }
+
+    /// The configured backend, which must have its feature enabled.
+    fn backend(&self) -> Result<ArchiveBackends, ArchiveError> {
+        let backend = match self.backend.as_deref().unwrap_or("mongodb") {
+            #[cfg(feature = "mongodb")]
+            "mongodb" => ArchiveBackends::MongoDB,
+            #[cfg(feature = "postgres")]
+            "postgres" => ArchiveBackends::Postgres,
+            #[cfg(feature = "sqlite")]
+            "sqlite" => ArchiveBackends::Sqlite,
+            #[cfg(feature = "s3")]
+            "s3" => ArchiveBackends::S3,
+            #[cfg(feature = "rocksdb")]
+            "rocksdb" => ArchiveBackends::RocksDb,
+            "filesystem" => ArchiveBackends::Filesystem {
+                root: self.root.clone().ok_or_else(|| {
+                    ArchiveError::invalid_input(
+                        "The filesystem backend needs a root directory, set LASR_ARCHIVE_ROOT",
+                    )
+                })?,
+            },
+            "memory" => ArchiveBackends::InMemory,
+            #[cfg(feature = "ipfs")]
+            "ipfs" => ArchiveBackends::Ipfs {
+                index: self.root.clone().ok_or_else(|| {
+                    ArchiveError::invalid_input(
+                        "The IPFS backend needs a directory for its CID index, set \
+                        LASR_ARCHIVE_ROOT",
+                    )
+                })?,
+            },
+            #[cfg(feature = "arweave")]
+            "arweave" => ArchiveBackends::Arweave {
+                index: self.root.clone().ok_or_else(|| {
+                    ArchiveError::invalid_input(
+                        "The Arweave backend needs a directory for its index, set \
+                        LASR_ARCHIVE_ROOT",
+                    )
+                })?,
+            },
+            other if custom_archive::is_registered(other) => ArchiveBackends::Custom {
+                name: other.to_string(),
+            },
+            other => {
+                return Err(ArchiveError::invalid_input(format!(
+                    "Unknown backend '{}', or its feature isn't enabled",
+                    other
+                )))
+            }
+        };
+        Ok(backend)
+    }
+}
+
+/// The value of an environment variable, or `None` if it's unset or empty.
+fn var(name: &str) -> Result<Option<String>, ArchiveError> {
+    match env::var(name) {
+        Ok(value) if value.is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value)),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(VarError::NotUnicode(_)) => Err(ArchiveError::invalid_input(format!(
+            "{} is not valid unicode",
+            name
+        ))),
+    }
+}
+
+/// Replaces a setting with the value of an environment variable, if it's set.
+fn override_with<T: FromStr>(setting: &mut Option<T>, name: &str) -> Result<(), ArchiveError> {
+    if let Some(value) = var(name)? {
+        let value = value.parse().map_err(|_| {
+            ArchiveError::invalid_input(format!("Invalid value '{}' for {}", value, name))
+        })?;
+        *setting = Some(value);
+    }
+    Ok(())
+}
+
+impl ArchiveStore {
+    /// Builds a store configured by environment variables, and the config file named by
+    /// `LASR_ARCHIVE_CONFIG` if set, see [ArchiveConfig].
+    pub fn from_env() -> Result<ArchiveStore, ArchiveError> {
+        ArchiveConfig::from_env()?.build()
+    }
+
+    /// Builds a store configured by a TOML or YAML config file, see [ArchiveConfig].
+    pub fn from_config_file(path: impl AsRef<Path>) -> Result<ArchiveStore, ArchiveError> {
+        ArchiveConfig::from_file(path)?.build()
+    }
+}
@@ -0,0 +1,255 @@
+/// Durability and consistency settings for archive operations: how many nodes must acknowledge
+/// a write before it is considered archived, and which nodes reads may be served from. They are
+/// set for every operation with [crate::ArchiveStoreBuilder::write_concern] and
+/// [crate::ArchiveStoreBuilder::read_preference], and for a single operation with
+/// [WriteOptions] and [ReadOptions], e.g. with [ArchiveStore::create_with_opts].
+///
+/// They map to MongoDB's write concern and read preference. The other backends have no replicas
+/// to acknowledge writes or serve reads, and ignore them.
+use crate::{ArchiveError, ArchiveRecordType, ArchiveStore, CreateOutcome, Filter};
+use core::fmt;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::borrow::Borrow;
+use std::time::Duration;
+
+/// How many nodes must acknowledge a write before it is reported as successful.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Acknowledgment {
+    /// Acknowledged by the given number of nodes.
+    Nodes(u32),
+    /// Acknowledged by a majority of the replica set.
+    Majority,
+}
+
+impl fmt::Display for Acknowledgment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Acknowledgment::Nodes(n) => write!(f, "{}", n),
+            Acknowledgment::Majority => write!(f, "majority"),
+        }
+    }
+}
+
+/// Write acknowledgement settings. Unset fields use the backend's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteConcern {
+    /// Nodes that must acknowledge each write
+    pub w: Option<Acknowledgment>,
+    /// Whether writes must be committed to the on-disk journal before being acknowledged
+    pub journal: Option<bool>,
+    /// How long to wait for the acknowledgements before failing the write
+    pub w_timeout: Option<Duration>,
+}
+
+impl WriteConcern {
+    /// Writes acknowledged by a majority of the replica set and committed to the journal.
+    pub fn majority() -> Self {
+        WriteConcern {
+            w: Some(Acknowledgment::Majority),
+            journal: Some(true),
+            w_timeout: None,
+        }
+    }
+
+    /// Checks that the settings can be used together, describing the problem if not.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if let Some(Acknowledgment::Nodes(0)) = self.w {
+            if self.journal == Some(true) {
+                return Err(
+                    "Invalid write concern: w=0 (unacknowledged) cannot be combined with \
+                    journal=true"
+                        .to_string(),
+                );
+            }
+            return Err(
+                "Invalid write concern: unacknowledged writes (w=0) are not supported".to_string(),
+            );
+        }
+        if self.w_timeout == Some(Duration::ZERO) {
+            return Err("Invalid write concern: wtimeout must be greater than zero".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for WriteConcern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.w {
+            Some(w) => write!(f, "w={}", w)?,
+            None => write!(f, "w=default")?,
+        }
+        if let Some(journal) = self.journal {
+            write!(f, ",j={}", journal)?;
+        }
+        if let Some(w_timeout) = self.w_timeout {
+            write!(f, ",wtimeout={}ms", w_timeout.as_millis())?;
+        }
+        Ok(())
+    }
+}
+
+/// Which nodes reads may be served from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ReadPreference {
+    /// Only read from the primary.
+    #[default]
+    Primary,
+    /// Read from a secondary when one is available, otherwise the primary. Suited to analytics
+    /// reads that can tolerate slightly stale data.
+    SecondaryPreferred,
+    /// Read from whichever node has the lowest latency.
+    Nearest,
+}
+
+impl fmt::Display for ReadPreference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadPreference::Primary => write!(f, "primary"),
+            ReadPreference::SecondaryPreferred => write!(f, "secondaryPreferred"),
+            ReadPreference::Nearest => write!(f, "nearest"),
+        }
+    }
+}
+
+/// Options for a single write, overriding the store's settings, see
+/// [ArchiveStore::create_with_opts].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Write concern of the write, in place of the store's
+    pub write_concern: Option<WriteConcern>,
+}
+
+impl WriteOptions {
+    /// Options for a write that is only acknowledged once a majority of the replica set has
+    /// committed it to the journal, see [WriteConcern::majority].
+    pub fn durable() -> Self {
+        WriteOptions {
+            write_concern: Some(WriteConcern::majority()),
+        }
+    }
+}
+
+/// Options for a single read, overriding the store's settings, see
+/// [ArchiveStore::find_by_id_with_opts].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReadOptions {
+    /// Which nodes the read may be served from, in place of the store's read preference
+    pub read_preference: Option<ReadPreference>,
+}
+
+impl ArchiveStore {
+    /// Persists a new record of [ArchiveRecordType] like [ArchiveStore::create], with the given
+    /// options, e.g. `store.create_with_opts(ArchiveRecordType::Block, block,
+    /// &WriteOptions::durable())` for a block that must survive the loss of the primary before
+    /// its finalization is acknowledged. The options apply to this store's own backend, not to
+    /// its mirrors. A record that is spilled because the write failed is later drained with the
+    /// store's write concern, so callers needing durability should check for
+    /// [CreateOutcome::Created].
+    pub async fn create_with_opts<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+        options: &WriteOptions,
+    ) -> Result<CreateOutcome, ArchiveError>
+    where
+        T: Serialize + Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let store = self
+            .with_consistency(options.write_concern.as_ref(), None)
+            .await?;
+        store.create(rec_type, rec).await
+    }
+
+    /// Persists a batch of records of [ArchiveRecordType] like [ArchiveStore::create_many], with
+    /// the given options.
+    pub async fn create_many_with_opts<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        recs: Vec<T>,
+        options: &WriteOptions,
+    ) -> Result<Vec<String>, ArchiveError>
+    where
+        T: Serialize + Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let store = self
+            .with_consistency(options.write_concern.as_ref(), None)
+            .await?;
+        store.create_many(rec_type, recs).await
+    }
+
+    /// Retrieves the record of [ArchiveRecordType] with the given id like
+    /// [ArchiveStore::find_by_id], with the given options, e.g. from a secondary with
+    /// [ReadPreference::SecondaryPreferred]. Records served by the read cache, see
+    /// [crate::ArchiveStoreBuilder::read_cache], aren't read from the backend at all.
+    pub async fn find_by_id_with_opts<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        options: &ReadOptions,
+    ) -> Result<Option<T>, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        let store = self
+            .with_consistency(None, options.read_preference.as_ref())
+            .await?;
+        store.find_by_id(rec_type, id).await
+    }
+
+    /// Retrieves the records of [ArchiveRecordType] matching a [Filter] like
+    /// [ArchiveStore::query], with the given options.
+    pub async fn query_with_opts<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+        options: &ReadOptions,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        let store = self
+            .with_consistency(None, options.read_preference.as_ref())
+            .await?;
+        store.query(rec_type, filter).await
+    }
+
+    /// Returns a handle on this store whose operations use the given write concern and read
+    /// preference, where set, in place of the store's.
+    #[cfg_attr(not(feature = "mongodb"), allow(unused_variables))]
+    async fn with_consistency(
+        &self,
+        write_concern: Option<&WriteConcern>,
+        read_preference: Option<&ReadPreference>,
+    ) -> Result<ArchiveStore, ArchiveError> {
+        if let Some(write_concern) = write_concern {
+            write_concern
+                .validate()
+                .map_err(ArchiveError::invalid_input)?;
+        }
+        // Only MongoDB has replicas to choose between, the other backends ignore the options.
+        #[cfg(feature = "mongodb")]
+        if (write_concern.is_some() || read_preference.is_some())
+            && matches!(self.inner.backend, crate::ArchiveBackends::MongoDB)
+        {
+            let mongodb = self
+                .mongodb()
+                .with_consistency(write_concern, read_preference)
+                .await?;
+            let mut store = self.clone();
+            store.mongodb_override = Some(std::sync::Arc::new(mongodb));
+            return Ok(store);
+        }
+        Ok(self.clone())
+    }
+}
@@ -0,0 +1,138 @@
+/// Credentials for the backend, kept apart from its URI so that secrets needn't be written into
+/// connection strings, where they end up in config files, process listings and logs. They are
+/// applied when the backend connects, in place of any given in the URI.
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use core::fmt;
+use std::sync::Arc;
+
+/// How the store authenticates with its backend, set with
+/// [crate::ArchiveStoreBuilder::credentials]. Used by the MongoDB, PostgreSQL and S3 backends.
+#[derive(Clone)]
+pub enum Credentials {
+    /// A user name and password. For S3, the access key id and secret access key.
+    UserPassword { username: String, password: String },
+    /// A token, e.g. an RDS IAM authentication token, used as the password of the user named in
+    /// the URI. For S3, a session token used with the access keys from the URI or environment.
+    Token(String),
+    /// Credentials fetched from a [SecretProvider] when the backend connects
+    Provider(Arc<dyn SecretProvider>),
+}
+
+impl Credentials {
+    /// A user name and password.
+    pub fn user_password(username: &str, password: &str) -> Self {
+        Credentials::UserPassword {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    /// Credentials fetched from `provider` when the backend connects.
+    pub fn provider<P: SecretProvider + 'static>(provider: P) -> Self {
+        Credentials::Provider(Arc::new(provider))
+    }
+
+    /// The user name, if any, and password or token to connect with, fetching them from the
+    /// provider if there is one.
+    #[cfg_attr(
+        not(any(feature = "mongodb", feature = "postgres", feature = "s3")),
+        allow(dead_code)
+    )]
+    pub(crate) async fn resolve(&self) -> Result<(Option<String>, String)> {
+        let fetched;
+        let credentials = match self {
+            Credentials::Provider(provider) => {
+                fetched = provider
+                    .credentials()
+                    .await
+                    .context("Fetching backend credentials")?;
+                &fetched
+            }
+            credentials => credentials,
+        };
+        match credentials {
+            Credentials::UserPassword { username, password } => {
+                Ok((Some(username.clone()), password.clone()))
+            }
+            Credentials::Token(token) => Ok((None, token.clone())),
+            Credentials::Provider(_) => bail!("A secret provider returned another provider"),
+        }
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Never print the secrets themselves.
+        match self {
+            Credentials::UserPassword { username, .. } => f
+                .debug_struct("UserPassword")
+                .field("username", username)
+                .field("password", &"***")
+                .finish(),
+            Credentials::Token(_) => f.debug_tuple("Token").field(&"***").finish(),
+            Credentials::Provider(provider) => {
+                f.debug_tuple("Provider").field(&provider.name()).finish()
+            }
+        }
+    }
+}
+
+impl fmt::Display for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Credentials::UserPassword { username, .. } => write!(f, "user {}", username),
+            Credentials::Token(_) => write!(f, "token"),
+            Credentials::Provider(provider) => write!(f, "{}", provider.name()),
+        }
+    }
+}
+
+/// Fetches credentials from wherever secrets are kept, e.g. the environment, HashiCorp Vault or
+/// AWS Secrets Manager. Called each time the backend creates its client or connection pool, so
+/// rotated secrets are picked up by stores built after the rotation.
+#[async_trait]
+pub trait SecretProvider: Send + Sync {
+    /// Fetches the current credentials, which must not themselves be a
+    /// [Credentials::Provider].
+    async fn credentials(&self) -> Result<Credentials>;
+
+    /// Name of the provider shown in logs in place of the credentials, e.g. `vault`.
+    fn name(&self) -> String {
+        "secret provider".to_string()
+    }
+}
+
+/// A [SecretProvider] reading a user name and password from environment variables.
+#[derive(Debug, Clone)]
+pub struct EnvSecrets {
+    username_var: String,
+    password_var: String,
+}
+
+impl EnvSecrets {
+    /// Reads the user name from `username_var` and the password from `password_var`.
+    pub fn new(username_var: &str, password_var: &str) -> Self {
+        EnvSecrets {
+            username_var: username_var.to_string(),
+            password_var: password_var.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl SecretProvider for EnvSecrets {
+    async fn credentials(&self) -> Result<Credentials> {
+        let var = |name: &str| {
+            std::env::var(name).with_context(|| format!("Reading credentials from ${}", name))
+        };
+        Ok(Credentials::UserPassword {
+            username: var(&self.username_var)?,
+            password: var(&self.password_var)?,
+        })
+    }
+
+    fn name(&self) -> String {
+        format!("${} and ${}", self.username_var, self.password_var)
+    }
+}
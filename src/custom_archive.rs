@@ -0,0 +1,449 @@
+/// Archive backends implemented outside this crate, e.g. over a proprietary datastore, so that
+/// adding one needn't mean forking the crate. A backend implements [ArchiveBackend] and is
+/// registered under a name with [ArchiveStore::register_backend], then selected with
+/// [ArchiveBackends::Custom] or by naming it as a config's backend. The store hands the backend
+/// records as BSON documents that are already compressed, encrypted and stamped with their
+/// provenance, as it does the built-in backends, so the backend only stores, filters and returns
+/// documents. Operations it doesn't implement fail with [crate::Unsupported].
+use crate::{
+    AggregationSpec, ArchiveBackend, ArchiveBackends, ArchiveCollectionStats, ArchiveError,
+    ArchiveEvent, ArchiveRecordType, ArchiveStore, Filter, GroupBy, IndexSpec, MergeMode, Page,
+    PageRequest,
+};
+use async_trait::async_trait;
+use bson::{Bson, Document};
+use futures::stream::BoxStream;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+
+/// Longest name a backend can be registered under
+const MAX_BACKEND_NAME_LEN: usize = 32;
+/// Names of the built-in backends, which custom backends can't be registered under
+const BUILT_IN_BACKENDS: [&str; 9] = [
+    "mongodb",
+    "postgres",
+    "sqlite",
+    "s3",
+    "rocksdb",
+    "filesystem",
+    "memory",
+    "ipfs",
+    "arweave",
+];
+
+/// Creates a registered backend from a store's URI and datastore.
+type Factory = dyn Fn(&str, &str) -> Result<Arc<dyn DynBackend>, ArchiveError> + Send + Sync;
+
+/// The factories of the registered backends, by name
+fn factories() -> &'static RwLock<HashMap<String, Arc<Factory>>> {
+    static FACTORIES: OnceLock<RwLock<HashMap<String, Arc<Factory>>>> = OnceLock::new();
+    FACTORIES.get_or_init(Default::default)
+}
+
+/// Whether a backend is registered under the name.
+pub(crate) fn is_registered(name: &str) -> bool {
+    factories()
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .contains_key(name)
+}
+
+/// Creates the backend registered under the name for a store's URI and datastore.
+pub(crate) fn create(
+    name: &str,
+    uri: &str,
+    datastore: &str,
+) -> Result<Arc<dyn DynBackend>, ArchiveError> {
+    let factory = factories()
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(name)
+        .cloned()
+        .ok_or_else(|| {
+            ArchiveError::invalid_input(format!("No backend is registered as '{}'", name))
+        })?;
+    factory(uri, datastore)
+}
+
+/// Checks that a backend can be registered under the name: 1 to 32 lowercase letters, digits,
+/// underscores and hyphens, starting with a letter, and not the name of a built-in backend.
+fn validate(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_BACKEND_NAME_LEN {
+        return Err(format!(
+            "Backend name '{}' must be between 1 and {} characters",
+            name, MAX_BACKEND_NAME_LEN
+        ));
+    }
+    if !name.starts_with(|c: char| c.is_ascii_lowercase())
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+    {
+        return Err(format!(
+            "Backend name '{}' must only contain lowercase letters, digits, underscores and \
+            hyphens, and start with a letter",
+            name
+        ));
+    }
+    if BUILT_IN_BACKENDS.contains(&name) {
+        return Err(format!("Backend name '{}' is reserved", name));
+    }
+    Ok(())
+}
+
+impl ArchiveStore {
+    /// Registers a backend implemented outside this crate under `name`, so that stores can be
+    /// built with `ArchiveBackends::Custom { name }`, or a config naming it as the backend, e.g.
+    /// `ArchiveStore::register_backend("vault", |uri, datastore| VaultBackend::connect(uri,
+    /// datastore))` at startup. Each store calls `factory` with its URI and datastore on its first
+    /// operation, then reuses the backend for every operation, as does each of its namespaces,
+    /// whose datastore names the namespace. Names are 1 to 32 lowercase letters, digits,
+    /// underscores and hyphens, starting with a letter, and can't be those of the built-in
+    /// backends. Registering a name again replaces its factory for stores that haven't created
+    /// their backend yet.
+    pub fn register_backend<B, F>(name: &str, factory: F) -> Result<(), ArchiveError>
+    where
+        B: ArchiveBackend + Send + Sync + 'static,
+        F: Fn(&str, &str) -> Result<B, ArchiveError> + Send + Sync + 'static,
+    {
+        validate(name).map_err(ArchiveError::invalid_input)?;
+        let factory: Arc<Factory> = Arc::new(move |uri: &str, datastore: &str| {
+            Ok(Arc::new(factory(uri, datastore)?) as Arc<dyn DynBackend>)
+        });
+        factories()
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(name.to_string(), factory);
+        Ok(())
+    }
+
+    /// Returns the backend registered as `name` for this store's datastore, creating it on first
+    /// use. The backend is shared by every clone of the store.
+    pub(crate) fn custom(&self, name: &str) -> Result<&dyn DynBackend, ArchiveError> {
+        if let Some(backend) = self.inner.custom.get() {
+            return Ok(backend.as_ref());
+        }
+        let backend = create(name, &self.inner.uri, &self.inner.datastore)?;
+        Ok(self.inner.custom.get_or_init(|| backend).as_ref())
+    }
+}
+
+impl ArchiveBackends {
+    /// Checks that a custom backend is registered under the name.
+    pub(crate) fn validate_custom(name: &str) -> Result<(), String> {
+        match is_registered(name) {
+            true => Ok(()),
+            false => Err(format!("No backend is registered as '{}'", name)),
+        }
+    }
+}
+
+/// An [ArchiveBackend] working on documents, which unlike [ArchiveBackend] can be used as a trait
+/// object, so that stores can hold backends of types they don't know.
+#[async_trait]
+pub(crate) trait DynBackend: Send + Sync {
+    async fn create(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: Document,
+    ) -> Result<String, ArchiveError>;
+    async fn create_many(
+        &self,
+        rec_type: ArchiveRecordType,
+        recs: Vec<Document>,
+    ) -> Result<Vec<String>, ArchiveError>;
+    async fn create_atomic(
+        &self,
+        records: Vec<(ArchiveRecordType, Document)>,
+    ) -> Result<Vec<String>, ArchiveError>;
+    async fn find_all(&self, rec_type: ArchiveRecordType) -> Result<Vec<Document>, ArchiveError>;
+    async fn find_all_stream<'a>(
+        &'a self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'a, Result<Document, ArchiveError>>, ArchiveError>;
+    async fn find_page(
+        &self,
+        rec_type: ArchiveRecordType,
+        request: &PageRequest,
+    ) -> Result<Page<Document>, ArchiveError>;
+    async fn query(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<Vec<Document>, ArchiveError>;
+    async fn find_projected(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        fields: &[String],
+    ) -> Result<Vec<Document>, ArchiveError>;
+    async fn find_by_id(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<Option<Document>, ArchiveError>;
+    async fn delete_by_id(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<bool, ArchiveError>;
+    async fn delete_where(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError>;
+    async fn update_by_id(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        rec: Document,
+    ) -> Result<bool, ArchiveError>;
+    async fn upsert(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        rec: Document,
+    ) -> Result<String, ArchiveError>;
+    async fn find_sampled(
+        &self,
+        rec_type: ArchiveRecordType,
+        rate: f64,
+    ) -> Result<Vec<Document>, ArchiveError>;
+    async fn find_orphaned_chunks(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Vec<String>, ArchiveError>;
+    async fn cleanup_orphans(&self, rec_type: ArchiveRecordType) -> Result<u64, ArchiveError>;
+    async fn reclaim_space(&self, rec_type: ArchiveRecordType) -> Result<(), ArchiveError>;
+    async fn merge_into(
+        &self,
+        source: ArchiveRecordType,
+        pipeline: Vec<Document>,
+        target: ArchiveRecordType,
+        mode: MergeMode,
+    ) -> Result<u64, ArchiveError>;
+    async fn watch(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'static, Result<ArchiveEvent, ArchiveError>>, ArchiveError>;
+    async fn count(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError>;
+    async fn exists(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<bool, ArchiveError>;
+    async fn stats(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<ArchiveCollectionStats, ArchiveError>;
+    async fn group_count(
+        &self,
+        rec_type: ArchiveRecordType,
+        group_by: GroupBy,
+    ) -> Result<Vec<(Bson, u64)>, ArchiveError>;
+    async fn aggregate(
+        &self,
+        rec_type: ArchiveRecordType,
+        spec: &AggregationSpec,
+    ) -> Result<Vec<Document>, ArchiveError>;
+    async fn ensure_indexes(
+        &self,
+        rec_type: ArchiveRecordType,
+        indexes: &[IndexSpec],
+    ) -> Result<(), ArchiveError>;
+}
+
+#[async_trait]
+impl<B: ArchiveBackend + Send + Sync> DynBackend for B {
+    async fn create(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: Document,
+    ) -> Result<String, ArchiveError> {
+        ArchiveBackend::create(self, rec_type, rec).await
+    }
+
+    async fn create_many(
+        &self,
+        rec_type: ArchiveRecordType,
+        recs: Vec<Document>,
+    ) -> Result<Vec<String>, ArchiveError> {
+        ArchiveBackend::create_many(self, rec_type, recs).await
+    }
+
+    async fn create_atomic(
+        &self,
+        records: Vec<(ArchiveRecordType, Document)>,
+    ) -> Result<Vec<String>, ArchiveError> {
+        ArchiveBackend::create_atomic(self, records).await
+    }
+
+    async fn find_all(&self, rec_type: ArchiveRecordType) -> Result<Vec<Document>, ArchiveError> {
+        ArchiveBackend::find_all(self, rec_type).await
+    }
+
+    async fn find_all_stream<'a>(
+        &'a self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'a, Result<Document, ArchiveError>>, ArchiveError> {
+        ArchiveBackend::find_all_stream(self, rec_type).await
+    }
+
+    async fn find_page(
+        &self,
+        rec_type: ArchiveRecordType,
+        request: &PageRequest,
+    ) -> Result<Page<Document>, ArchiveError> {
+        ArchiveBackend::find_page(self, rec_type, request).await
+    }
+
+    async fn query(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<Vec<Document>, ArchiveError> {
+        ArchiveBackend::query(self, rec_type, filter).await
+    }
+
+    async fn find_projected(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        fields: &[String],
+    ) -> Result<Vec<Document>, ArchiveError> {
+        ArchiveBackend::find_projected(self, rec_type, filter, fields).await
+    }
+
+    async fn find_by_id(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<Option<Document>, ArchiveError> {
+        ArchiveBackend::find_by_id(self, rec_type, id).await
+    }
+
+    async fn delete_by_id(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<bool, ArchiveError> {
+        ArchiveBackend::delete_by_id(self, rec_type, id).await
+    }
+
+    async fn delete_where(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        ArchiveBackend::delete_where(self, rec_type, filter).await
+    }
+
+    async fn update_by_id(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        rec: Document,
+    ) -> Result<bool, ArchiveError> {
+        ArchiveBackend::update_by_id(self, rec_type, id, rec).await
+    }
+
+    async fn upsert(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        rec: Document,
+    ) -> Result<String, ArchiveError> {
+        ArchiveBackend::upsert(self, rec_type, filter, rec).await
+    }
+
+    async fn find_sampled(
+        &self,
+        rec_type: ArchiveRecordType,
+        rate: f64,
+    ) -> Result<Vec<Document>, ArchiveError> {
+        ArchiveBackend::find_sampled(self, rec_type, rate).await
+    }
+
+    async fn find_orphaned_chunks(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Vec<String>, ArchiveError> {
+        ArchiveBackend::find_orphaned_chunks(self, rec_type).await
+    }
+
+    async fn cleanup_orphans(&self, rec_type: ArchiveRecordType) -> Result<u64, ArchiveError> {
+        ArchiveBackend::cleanup_orphans(self, rec_type).await
+    }
+
+    async fn reclaim_space(&self, rec_type: ArchiveRecordType) -> Result<(), ArchiveError> {
+        ArchiveBackend::reclaim_space(self, rec_type).await
+    }
+
+    async fn merge_into(
+        &self,
+        source: ArchiveRecordType,
+        pipeline: Vec<Document>,
+        target: ArchiveRecordType,
+        mode: MergeMode,
+    ) -> Result<u64, ArchiveError> {
+        ArchiveBackend::merge_into(self, source, pipeline, target, mode).await
+    }
+
+    async fn watch(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'static, Result<ArchiveEvent, ArchiveError>>, ArchiveError> {
+        ArchiveBackend::watch(self, rec_type).await
+    }
+
+    async fn count(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        ArchiveBackend::count(self, rec_type, filter).await
+    }
+
+    async fn exists(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<bool, ArchiveError> {
+        ArchiveBackend::exists(self, rec_type, filter).await
+    }
+
+    async fn stats(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<ArchiveCollectionStats, ArchiveError> {
+        ArchiveBackend::stats(self, rec_type).await
+    }
+
+    async fn group_count(
+        &self,
+        rec_type: ArchiveRecordType,
+        group_by: GroupBy,
+    ) -> Result<Vec<(Bson, u64)>, ArchiveError> {
+        ArchiveBackend::group_count(self, rec_type, group_by).await
+    }
+
+    async fn aggregate(
+        &self,
+        rec_type: ArchiveRecordType,
+        spec: &AggregationSpec,
+    ) -> Result<Vec<Document>, ArchiveError> {
+        ArchiveBackend::aggregate(self, rec_type, spec).await
+    }
+
+    async fn ensure_indexes(
+        &self,
+        rec_type: ArchiveRecordType,
+        indexes: &[IndexSpec],
+    ) -> Result<(), ArchiveError> {
+        ArchiveBackend::ensure_indexes(self, rec_type, indexes).await
+    }
+}
@@ -0,0 +1,301 @@
+/// A local dead-letter file for records that [ArchiveStore::create] couldn't archive because they
+/// couldn't be serialised, were too large or were rejected by the backend. Unlike spilled records,
+/// which are written once the backend recovers, dead letters fail the same way until something
+/// changes, e.g. the record type's serialisation or the store's chunking, so they're kept until
+/// retried or discarded by hand. Each line of the file is a JSON object holding a [DeadLetter],
+/// with its payload in canonical extended JSON.
+use crate::compression::RecordTooLarge;
+use crate::{spill, ArchiveError, ArchiveErrorKind, ArchiveRecordType, ArchiveStore};
+use anyhow::{Context, Result};
+use bson::{oid::ObjectId, spec::BinarySubtype, Binary, Bson, DateTime, Document};
+use log::warn;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Why a record was dead-lettered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeadLetterReason {
+    /// The record couldn't be serialised or encoded, e.g. it has a map with non-string keys.
+    Unserializable,
+    /// The record was over the backend's size limit, even after compression.
+    TooLarge,
+    /// The backend rejected the write as invalid.
+    Rejected,
+}
+
+/// The form a [DeadLetter]'s payload was captured in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadFormat {
+    /// The BSON document handed to the backend, compressed, encrypted and with its provenance.
+    Encoded,
+    /// The record serialised to a BSON document, before it was encoded.
+    Bson,
+    /// The record serialised to JSON, as it couldn't be serialised to BSON. Read back as a
+    /// document, numbers out of BSON's range become doubles.
+    Json,
+}
+
+/// A record captured in the dead-letter file, see [ArchiveStore::dead_letters].
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct DeadLetter {
+    /// The dead letter's id, used to retry or discard it
+    pub id: String,
+    pub record_type: ArchiveRecordType,
+    /// When the record was dead-lettered
+    #[serde(with = "rfc3339")]
+    pub failed_at: DateTime,
+    pub reason: DeadLetterReason,
+    /// The error the record failed with, with its context
+    pub error: String,
+    pub format: PayloadFormat,
+    /// The record's raw bytes, in [DeadLetter::format]
+    #[serde(with = "extjson_binary")]
+    pub payload: Vec<u8>,
+}
+
+impl DeadLetter {
+    /// The payload as a document, e.g. to inspect the record or fix it before archiving it
+    /// again. Encoded payloads are returned as they would have been stored, so may be compressed
+    /// or encrypted.
+    pub fn document(&self) -> Result<Document, ArchiveError> {
+        match self.format {
+            PayloadFormat::Encoded | PayloadFormat::Bson => {
+                Ok(bson::from_slice(&self.payload).context("Invalid dead letter payload")?)
+            }
+            PayloadFormat::Json => {
+                let value: serde_json::Value =
+                    serde_json::from_slice(&self.payload).context("Invalid dead letter payload")?;
+                match Bson::try_from(value).context("Invalid dead letter payload")? {
+                    Bson::Document(doc) => Ok(doc),
+                    other => Err(anyhow::anyhow!(
+                        "Dead letter payload is not a document: {}",
+                        other
+                    )
+                    .into()),
+                }
+            }
+        }
+    }
+}
+
+/// Path of the dead-letter file of a datastore within the dead-letter directory.
+pub(crate) fn path(dir: &Path, datastore: &str) -> PathBuf {
+    dir.join(format!("{}.dead.jsonl", datastore))
+}
+
+/// Whether a write failed because the backend rejected the record, so retrying it as it is
+/// won't help.
+pub(crate) fn rejected(error: &ArchiveError) -> bool {
+    matches!(
+        error.kind(),
+        ArchiveErrorKind::SerializationError | ArchiveErrorKind::InvalidInput
+    )
+}
+
+impl ArchiveStore {
+    /// Lists the records captured in the store's dead-letter file, oldest first. Empty when no
+    /// dead-letter directory is configured, see [crate::ArchiveStoreBuilder::dead_letter_dir].
+    pub async fn dead_letters(&self) -> Result<Vec<DeadLetter>, ArchiveError> {
+        let path = match self.dead_letter_path() {
+            Some(path) => path,
+            None => return Ok(Vec::new()),
+        };
+        let _guard = self.inner.dead_letter_lock.lock().await;
+        Ok(spill::read(&path).await?)
+    }
+
+    /// Retrieves the dead letter with the given id, if there is one.
+    pub async fn dead_letter(&self, id: &str) -> Result<Option<DeadLetter>, ArchiveError> {
+        Ok(self
+            .dead_letters()
+            .await?
+            .into_iter()
+            .find(|letter| letter.id == id))
+    }
+
+    /// Archives the record of the dead letter with the given id again, e.g. once the record type
+    /// or the store's configuration has been fixed, returning the record's id, or `None` if there
+    /// is no such dead letter. The dead letter is removed once the record is written. If the
+    /// write fails the dead letter is kept and the error returned. Encoded payloads are written as
+    /// they are, others are encoded by this store first. The write isn't retried or spilled.
+    pub async fn retry_dead_letter(&self, id: &str) -> Result<Option<String>, ArchiveError> {
+        let path = match self.dead_letter_path() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let _guard = self.inner.dead_letter_lock.lock().await;
+        let mut letters: Vec<DeadLetter> = spill::read(&path).await?;
+        let index = match letters.iter().position(|letter| letter.id == id) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        let letter = &letters[index];
+        let mut doc = letter.document()?;
+        if letter.format != PayloadFormat::Encoded {
+            doc = self.encode(&letter.record_type, &doc)?;
+        }
+        let record_id = self
+            .write_unique(letter.record_type.clone(), doc)
+            .await
+            .with_context(|| format!("Retrying dead letter {}", id))?;
+        letters.remove(index);
+        spill::rewrite(&path, &letters).await?;
+        Ok(Some(record_id))
+    }
+
+    /// Removes the dead letter with the given id without archiving its record, returning whether
+    /// there was one.
+    pub async fn discard_dead_letter(&self, id: &str) -> Result<bool, ArchiveError> {
+        let path = match self.dead_letter_path() {
+            Some(path) => path,
+            None => return Ok(false),
+        };
+        let _guard = self.inner.dead_letter_lock.lock().await;
+        let mut letters: Vec<DeadLetter> = spill::read(&path).await?;
+        let before = letters.len();
+        letters.retain(|letter| letter.id != id);
+        if letters.len() == before {
+            return Ok(false);
+        }
+        spill::rewrite(&path, &letters).await?;
+        Ok(true)
+    }
+
+    /// Captures a record that couldn't be encoded, returning the dead letter's id. Fails with the
+    /// record's own error if it can't even be serialised to JSON, as there's nothing to capture.
+    pub(crate) async fn dead_letter_record<T: Serialize>(
+        &self,
+        rec_type: &ArchiveRecordType,
+        rec: &T,
+        error: anyhow::Error,
+    ) -> Result<String> {
+        let (format, payload) = match bson::to_document(rec).map(|doc| bson::to_vec(&doc)) {
+            Ok(Ok(payload)) => (PayloadFormat::Bson, payload),
+            _ => match serde_json::to_vec(rec) {
+                Ok(payload) => (PayloadFormat::Json, payload),
+                Err(_) => return Err(error),
+            },
+        };
+        let reason = if error.chain().any(|cause| cause.is::<RecordTooLarge>()) {
+            DeadLetterReason::TooLarge
+        } else {
+            DeadLetterReason::Unserializable
+        };
+        self.capture(rec_type, reason, &error, format, payload)
+            .await
+    }
+
+    /// Captures an encoded record the backend rejected, returning the dead letter's id.
+    pub(crate) async fn dead_letter_encoded(
+        &self,
+        rec_type: &ArchiveRecordType,
+        doc: &Document,
+        error: anyhow::Error,
+    ) -> Result<String> {
+        let payload = bson::to_vec(doc).context("Failed to serialise record to BSON")?;
+        let reason = if error.chain().any(|cause| cause.is::<RecordTooLarge>()) {
+            DeadLetterReason::TooLarge
+        } else {
+            DeadLetterReason::Rejected
+        };
+        self.capture(rec_type, reason, &error, PayloadFormat::Encoded, payload)
+            .await
+    }
+
+    /// Appends a dead letter to the dead-letter file, returning its id.
+    async fn capture(
+        &self,
+        rec_type: &ArchiveRecordType,
+        reason: DeadLetterReason,
+        error: &anyhow::Error,
+        format: PayloadFormat,
+        payload: Vec<u8>,
+    ) -> Result<String> {
+        let path = self
+            .dead_letter_path()
+            .context("No dead-letter directory configured")?;
+        let letter = DeadLetter {
+            id: ObjectId::new().to_hex(),
+            record_type: rec_type.clone(),
+            failed_at: DateTime::now(),
+            reason,
+            error: format!("{:#}", error),
+            format,
+            payload,
+        };
+        warn!(
+            "Dead-lettering {:?} record as {} in {}: {:#}",
+            rec_type,
+            letter.id,
+            path.display(),
+            error
+        );
+
+        let _guard = self.inner.dead_letter_lock.lock().await;
+        spill::append(&path, &letter)
+            .await
+            .with_context(|| format!("Dead-lettering record after failed write: {:#}", error))?;
+        Ok(letter.id)
+    }
+
+    /// Path of the store's dead-letter file, if it has a dead-letter directory.
+    fn dead_letter_path(&self) -> Option<PathBuf> {
+        self.inner
+            .dead_letter_dir
+            .as_ref()
+            .map(|dir| path(dir, &self.inner.datastore))
+    }
+}
+
+/// Stores a time as an RFC 3339 string, like the times of spilled records.
+mod rfc3339 {
+    use bson::DateTime;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(
+        time: &DateTime,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let time = time
+            .try_to_rfc3339_string()
+            .map_err(serde::ser::Error::custom)?;
+        serializer.serialize_str(&time)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime, D::Error> {
+        let time = String::deserialize(deserializer)?;
+        DateTime::parse_rfc3339_str(time).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Stores bytes as canonical extended JSON binary, i.e. base64, rather than an array of numbers.
+mod extjson_binary {
+    use super::*;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let binary = Bson::Binary(Binary {
+            subtype: BinarySubtype::Generic,
+            bytes: bytes.to_vec(),
+        });
+        binary.into_canonical_extjson().serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        match Bson::try_from(value).map_err(serde::de::Error::custom)? {
+            Bson::Binary(binary) => Ok(binary.bytes),
+            other => Err(serde::de::Error::custom(format!(
+                "Expected binary payload, found {}",
+                other
+            ))),
+        }
+    }
+}
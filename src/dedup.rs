@@ -0,0 +1,143 @@
+/// Suppression of duplicate writes. [DedupCache] is a best-effort, per-process cache of recently
+/// written records used to suppress duplicate writes, such as those caused by a retry storm,
+/// without a round trip to the backend. Records are identified by an idempotency key derived from
+/// their record type and serialised contents, and remembered for a fixed window after they were
+/// written. The cache lives only in memory, so it does not survive restarts, is not shared between
+/// processes and does not guarantee that a record is written exactly once.
+///
+/// Content deduplication, enabled with [crate::ArchiveStoreBuilder::content_dedup], is durable
+/// instead: every record is stored with its checksum, which the backend indexes, and a record is
+/// only written if no archived record of its type with the same checksum is stored yet.
+use crate::checksum::CHECKSUM_FIELD;
+use crate::filter::id_to_string;
+use crate::{
+    chunking, encryption, envelope, ArchiveRecordType, ArchiveStore, EncryptionConfig, Filter,
+    LifecycleState,
+};
+use anyhow::{Context, Result};
+use bson::Document;
+use log::debug;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub(crate) struct DedupCache {
+    /// How long a written record is remembered for
+    window: Duration,
+    /// Idempotency key of each recently written record, mapped to when it was written and the
+    /// id the backend returned for it
+    recent: Arc<Mutex<HashMap<String, (Instant, String)>>>,
+}
+
+impl DedupCache {
+    pub(crate) fn new(window: Duration) -> Self {
+        DedupCache {
+            window,
+            recent: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// An empty cache with the same window, for a store derived from this cache's store.
+    pub(crate) fn emptied(&self) -> Self {
+        DedupCache::new(self.window)
+    }
+
+    /// Derives the idempotency key of an encoded record. The time it is archived at differs from
+    /// one write to the next, so isn't part of the key, and neither is the ciphertext of an
+    /// encrypted record, as every write encrypts it with a fresh nonce. Encrypted records are
+    /// keyed on their plaintext instead.
+    pub(crate) fn key(
+        rec_type: &ArchiveRecordType,
+        doc: &Document,
+        encryption: Option<&EncryptionConfig>,
+    ) -> Result<String> {
+        let mut doc = doc.clone();
+        doc.remove(envelope::ARCHIVED_AT_FIELD);
+        let mut plain = encryption::decrypt(doc.clone(), encryption)?;
+        // Provenance is stamped on the encrypted wrapper, outside its ciphertext.
+        for field in encryption::WRAPPER_FIELDS {
+            doc.remove(field);
+        }
+        plain.extend(doc);
+        let raw = bson::to_vec(&plain).context("Failed to serialise record to BSON")?;
+        Ok(format!("{:?}:{}", rec_type, chunking::checksum(&raw)))
+    }
+
+    /// Returns the id of the record written under `key`, if it was written within the window.
+    pub(crate) fn get(&self, key: &str) -> Option<String> {
+        let recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        recent
+            .get(key)
+            .filter(|(written, _)| written.elapsed() < self.window)
+            .map(|(_, id)| id.clone())
+    }
+
+    /// Remembers that the record with `key` was written with the given id, dropping any entries
+    /// that have fallen out of the window.
+    pub(crate) fn insert(&self, key: String, id: String) {
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        recent.retain(|_, (written, _)| written.elapsed() < self.window);
+        recent.insert(key, (Instant::now(), id));
+    }
+
+    /// Forgets the record written with the given id, so an identical record is written again.
+    pub(crate) fn forget(&self, id: &str) {
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        recent.retain(|_, (_, written_id)| written_id != id);
+    }
+
+    /// Forgets every record of the given type, so identical records are written again.
+    pub(crate) fn forget_type(&self, rec_type: &ArchiveRecordType) {
+        let prefix = format!("{:?}:", rec_type);
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        recent.retain(|key, _| !key.starts_with(&prefix));
+    }
+}
+
+impl ArchiveStore {
+    /// Writes an encoded record to the backend like [ArchiveStore::write], unless content
+    /// deduplication is enabled and a record of the same type with the same checksum is already
+    /// stored, in which case its id is returned instead.
+    pub(crate) async fn write_unique(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: Document,
+    ) -> Result<String> {
+        if let Some(id) = self.find_duplicate(&rec_type, &rec).await? {
+            debug!("Skipping write of record identical to {}", id);
+            return Ok(id);
+        }
+        self.write(rec_type, rec).await
+    }
+
+    /// The id of a stored record of [ArchiveRecordType] with the same checksum as the encoded
+    /// record, if content deduplication is enabled and there is one.
+    async fn find_duplicate(
+        &self,
+        rec_type: &ArchiveRecordType,
+        rec: &Document,
+    ) -> Result<Option<String>> {
+        if !self.inner.content_dedup {
+            return Ok(None);
+        }
+        let sum = match rec.get_str(CHECKSUM_FIELD) {
+            Ok(sum) => sum,
+            Err(_) => return Ok(None),
+        };
+        self.ensure_index_once(rec_type, CHECKSUM_FIELD).await?;
+
+        // Tombstoned records don't count, so a record can be written again once they are.
+        let filter =
+            Filter::eq(CHECKSUM_FIELD, sum).and(Filter::lifecycle(LifecycleState::Archived));
+        let found = self
+            .backend()?
+            .query(rec_type.clone(), &filter)
+            .await
+            .with_context(|| format!("Looking up identical record in {}", self.inner.backend))?;
+        Ok(found
+            .first()
+            .and_then(|doc| doc.get("_id"))
+            .map(id_to_string))
+    }
+}
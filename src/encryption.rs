@@ -0,0 +1,236 @@
+use crate::filter;
+/// Optional at-rest encryption of archived records. An encrypted record is serialised to BSON
+/// bytes (after compression, if enabled), encrypted with AES-256-GCM and stored as a wrapper
+/// document of the form `{ _encryption: "aes-256-gcm", _key_id, _nonce: Binary, _data: Binary }`,
+/// so the backend never sees its contents. The id of the key is stored alongside the ciphertext,
+/// so keys can be rotated: new records are encrypted with the current key while older records are
+/// still decrypted with the key they were written with. Documents without an `_encryption` field
+/// (e.g. those written before encryption was enabled) are read as they are.
+///
+/// A record whose id is known when it is encrypted, e.g. one archived with
+/// [crate::ArchiveStore::create_with_id] or replaced with [crate::ArchiveStore::update_by_id], has
+/// the id authenticated along with its ciphertext, so the ciphertext can't be moved to another
+/// record. Records whose ids the backend generates are authenticated with the key id alone.
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{Context, Result};
+use bson::{doc, spec::BinarySubtype, Binary, Bson, Document};
+use core::fmt;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Name of the field recording how an encrypted record's payload is encrypted
+const ENCRYPTION_FIELD: &str = "_encryption";
+/// Name of the field holding the id of the key a record was encrypted with
+const KEY_ID_FIELD: &str = "_key_id";
+/// Name of the field holding the nonce a record was encrypted with
+const NONCE_FIELD: &str = "_nonce";
+/// Name of the field holding an encrypted record's payload
+const DATA_FIELD: &str = "_data";
+/// The fields of an encrypted record's wrapper document, needed to decrypt it
+pub(crate) const WRAPPER_FIELDS: [&str; 4] =
+    [ENCRYPTION_FIELD, KEY_ID_FIELD, NONCE_FIELD, DATA_FIELD];
+/// The value stored in the `_encryption` field
+const AES_256_GCM: &str = "aes-256-gcm";
+/// Size in bytes of an AES-256-GCM nonce
+const NONCE_LEN: usize = 12;
+
+/// A 256-bit AES key
+pub type EncryptionKey = [u8; 32];
+
+/// Supplies the keys records are encrypted with, e.g. by unwrapping data keys with a key
+/// management service. Implementations are called for every record written or read, so should
+/// cache keys rather than fetching them each time.
+pub trait KeyProvider: Send + Sync {
+    /// Id of the key new records are encrypted with
+    fn current_key_id(&self) -> String;
+    /// The key with the given id, failing if it is unknown or can't be fetched
+    fn key(&self, key_id: &str) -> Result<EncryptionKey>;
+}
+
+/// A [KeyProvider] holding caller-supplied keys in memory.
+#[derive(Clone)]
+pub struct StaticKeys {
+    current: String,
+    keys: HashMap<String, EncryptionKey>,
+}
+
+impl StaticKeys {
+    /// Encrypts records with `key`, stored under the id `key_id`.
+    pub fn new(key_id: &str, key: EncryptionKey) -> Self {
+        StaticKeys {
+            current: key_id.to_string(),
+            keys: HashMap::from([(key_id.to_string(), key)]),
+        }
+    }
+
+    /// Adds an older key, so records encrypted with it before a rotation can still be read.
+    pub fn with_key(mut self, key_id: &str, key: EncryptionKey) -> Self {
+        self.keys.entry(key_id.to_string()).or_insert(key);
+        self
+    }
+}
+
+impl KeyProvider for StaticKeys {
+    fn current_key_id(&self) -> String {
+        self.current.clone()
+    }
+
+    fn key(&self, key_id: &str) -> Result<EncryptionKey> {
+        self.keys
+            .get(key_id)
+            .copied()
+            .with_context(|| format!("Unknown encryption key '{}'", key_id))
+    }
+}
+
+impl fmt::Debug for StaticKeys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Never print the keys themselves.
+        f.debug_struct("StaticKeys")
+            .field("current", &self.current)
+            .field("key_ids", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// How records are encrypted at rest, set with [crate::ArchiveStoreBuilder::encryption].
+/// Encrypted records can't be filtered, grouped or deduplicated by the backend, as it can't see
+/// their fields, but their ids are left in plaintext so they can still be found by id.
+#[derive(Clone)]
+pub struct EncryptionConfig {
+    provider: Arc<dyn KeyProvider>,
+}
+
+impl EncryptionConfig {
+    /// Encrypts records with a single caller-supplied key, stored under the id `key_id`.
+    pub fn new(key_id: &str, key: EncryptionKey) -> Self {
+        EncryptionConfig::with_provider(StaticKeys::new(key_id, key))
+    }
+
+    /// Encrypts records with the keys supplied by `provider`.
+    pub fn with_provider<P: KeyProvider + 'static>(provider: P) -> Self {
+        EncryptionConfig {
+            provider: Arc::new(provider),
+        }
+    }
+
+    /// The cipher for the key with the given id.
+    fn cipher(&self, key_id: &str) -> Result<Aes256Gcm> {
+        let key = self
+            .provider
+            .key(key_id)
+            .with_context(|| format!("Fetching encryption key '{}'", key_id))?;
+        Ok(Aes256Gcm::new(&key.into()))
+    }
+}
+
+impl fmt::Debug for EncryptionConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EncryptionConfig")
+            .field("current_key_id", &self.provider.current_key_id())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The associated data authenticated along with a record's ciphertext: the id of the key it is
+/// encrypted with, followed by its id, if it has one, prefixed with its length.
+fn associated_data(key_id: &str, id: Option<&Bson>) -> Vec<u8> {
+    let mut aad = key_id.as_bytes().to_vec();
+    if let Some(id) = id {
+        // Ids are compared as strings, as some backends store ObjectIds as their hex form.
+        let id = filter::id_to_string(id);
+        aad.extend_from_slice(&(id.len() as u64).to_be_bytes());
+        aad.extend_from_slice(id.as_bytes());
+    }
+    aad
+}
+
+/// Encrypts an encoded record into the wrapper document stored by the backend, keeping its `_id`
+/// in plaintext.
+pub(crate) fn encrypt(mut doc: Document, config: &EncryptionConfig) -> Result<Document> {
+    let id = doc.remove("_id");
+    let raw = bson::to_vec(&doc).context("Failed to serialise record to BSON")?;
+
+    let key_id = config.provider.current_key_id();
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    // The key id and record id are authenticated along with the ciphertext, so neither can be
+    // swapped.
+    let data = config
+        .cipher(&key_id)?
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &raw,
+                aad: &associated_data(&key_id, id.as_ref()),
+            },
+        )
+        .map_err(|_| anyhow::anyhow!("Failed to encrypt record"))?;
+
+    let mut wrapper = doc! {
+        ENCRYPTION_FIELD: AES_256_GCM,
+        KEY_ID_FIELD: key_id,
+        NONCE_FIELD: Binary { subtype: BinarySubtype::Generic, bytes: nonce.to_vec() },
+        DATA_FIELD: Binary { subtype: BinarySubtype::Generic, bytes: data },
+    };
+    if let Some(id) = id {
+        wrapper.insert("_id", id);
+    }
+    Ok(wrapper)
+}
+
+/// Decrypts a document read from the backend if it is an encrypted wrapper document, restoring
+/// its `_id`. Other documents are returned as they are.
+pub(crate) fn decrypt(mut doc: Document, config: Option<&EncryptionConfig>) -> Result<Document> {
+    match doc.get(ENCRYPTION_FIELD) {
+        Some(Bson::String(encryption)) if encryption == AES_256_GCM => {}
+        Some(Bson::String(other)) => {
+            anyhow::bail!("Unknown archived record encryption: '{}'", other)
+        }
+        // Not a wrapper document, so the record was stored in plaintext.
+        _ => return Ok(doc),
+    }
+    let config = config.context("Record is encrypted, but no encryption is configured")?;
+    let key_id = doc
+        .get_str(KEY_ID_FIELD)
+        .context("Encrypted record has no key id")?
+        .to_string();
+    let nonce = match doc.get(NONCE_FIELD) {
+        Some(Bson::Binary(nonce)) if nonce.bytes.len() == NONCE_LEN => nonce.bytes.clone(),
+        _ => anyhow::bail!("Encrypted record has no valid nonce"),
+    };
+    let data = match doc.get(DATA_FIELD) {
+        Some(Bson::Binary(data)) => &data.bytes,
+        _ => anyhow::bail!("Encrypted record has no data"),
+    };
+
+    let cipher = config.cipher(&key_id)?;
+    let decrypt = |id: Option<&Bson>| {
+        cipher.decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: data,
+                aad: &associated_data(&key_id, id),
+            },
+        )
+    };
+    // Records whose id was generated by the backend were encrypted before they had one. A
+    // ciphertext encrypted with its id fails both ways when moved to another record.
+    let raw = decrypt(doc.get("_id"))
+        .or_else(|e| match doc.get("_id") {
+            Some(_) => decrypt(None),
+            None => Err(e),
+        })
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "Failed to decrypt record with key '{}': wrong key or tampered record",
+                key_id
+            )
+        })?;
+    let mut rec: Document =
+        bson::from_slice(&raw).context("Failed to deserialise decrypted record")?;
+    if let Some(id) = doc.remove("_id") {
+        rec.insert("_id", id);
+    }
+    Ok(rec)
+}
@@ -0,0 +1,136 @@
+/// Provenance metadata archived with every record: when it was archived, the schema version it was
+/// written with, the node that archived it, any tags and its lifecycle state. The metadata is
+/// stored in reserved top-level fields next to the record (or its compressed or encrypted form), so
+/// backends can filter on it whatever the record's encoding, e.g. with
+/// [crate::Filter::archived_since].
+use crate::lifecycle::Lifecycle;
+use crate::ArchiveError;
+use anyhow::Result;
+use bson::{oid::ObjectId, Bson, DateTime, Document};
+
+/// Name of the field holding when a record was archived, in milliseconds since the epoch
+pub(crate) const ARCHIVED_AT_FIELD: &str = "_archived_at";
+/// Name of the field holding the id of the node that archived a record
+pub(crate) const NODE_ID_FIELD: &str = "_node_id";
+/// Name of the field holding a record's tags, as a document with a `true` field per tag
+pub(crate) const TAGS_FIELD: &str = "_tags";
+
+/// An archived record along with its provenance, as returned by
+/// [crate::ArchiveStore::find_envelope_by_id] and [crate::ArchiveStore::query_envelopes].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveEnvelope<T> {
+    /// The record, migrated to the current schema version
+    pub record: T,
+    /// When the record was archived. Records archived before envelopes were recorded fall back
+    /// to when their object id was generated, if they have one.
+    pub archived_at: Option<DateTime>,
+    /// The schema version the record was written with, before any migration on read
+    pub schema_version: u32,
+    /// The node that archived the record, see [crate::ArchiveStoreBuilder::node_id]
+    pub node_id: Option<String>,
+    /// Tags the record was archived with, see [crate::ArchiveStore::with_tags]
+    pub tags: Vec<String>,
+    /// The record's lifecycle state and when and why it last changed, see
+    /// [crate::ArchiveStore::set_lifecycle]
+    pub lifecycle: Lifecycle,
+}
+
+/// The provenance fields of a document, as stored.
+#[derive(Debug, Default)]
+pub(crate) struct Provenance {
+    pub(crate) archived_at: Option<DateTime>,
+    pub(crate) node_id: Option<String>,
+    pub(crate) tags: Vec<String>,
+    pub(crate) lifecycle: Lifecycle,
+}
+
+impl Provenance {
+    /// Removes the provenance fields from a document. Records passed in with them, e.g. when
+    /// moved between stores, keep their original provenance.
+    pub(crate) fn take(doc: &mut Document) -> Provenance {
+        let archived_at = match doc.remove(ARCHIVED_AT_FIELD) {
+            Some(Bson::Int64(millis)) => Some(DateTime::from_millis(millis)),
+            Some(Bson::Int32(millis)) => Some(DateTime::from_millis(millis.into())),
+            Some(Bson::DateTime(time)) => Some(time),
+            _ => None,
+        };
+        let node_id = match doc.remove(NODE_ID_FIELD) {
+            Some(Bson::String(node_id)) => Some(node_id),
+            _ => None,
+        };
+        let mut tags: Vec<String> = match doc.remove(TAGS_FIELD) {
+            Some(Bson::Document(tags)) => tags.into_iter().map(|(tag, _)| tag).collect(),
+            Some(Bson::Array(tags)) => tags
+                .into_iter()
+                .filter_map(|tag| match tag {
+                    Bson::String(tag) => Some(tag),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        // Some backends reorder fields, so return tags in a stable order.
+        tags.sort();
+        Provenance {
+            archived_at,
+            node_id,
+            tags,
+            lifecycle: Lifecycle::take(doc),
+        }
+    }
+
+    /// Stores the provenance fields in a document about to be written.
+    pub(crate) fn stamp(&self, doc: &mut Document) -> Result<()> {
+        let archived_at = self.archived_at.unwrap_or_else(DateTime::now);
+        doc.insert(ARCHIVED_AT_FIELD, archived_at.timestamp_millis());
+        if let Some(node_id) = &self.node_id {
+            doc.insert(NODE_ID_FIELD, node_id);
+        }
+        if !self.tags.is_empty() {
+            let mut tags = Document::new();
+            for tag in &self.tags {
+                validate_tag(tag).map_err(ArchiveError::invalid_input)?;
+                tags.insert(tag, true);
+            }
+            doc.insert(TAGS_FIELD, tags);
+        }
+        self.lifecycle.stamp(doc);
+        Ok(())
+    }
+
+    /// Puts the provenance fields back into a record, so that writing it to another store keeps
+    /// them.
+    pub(crate) fn restore(self, rec: &mut Document) {
+        if let Some(archived_at) = self.archived_at {
+            rec.insert(ARCHIVED_AT_FIELD, archived_at.timestamp_millis());
+        }
+        if let Some(node_id) = self.node_id {
+            rec.insert(NODE_ID_FIELD, node_id);
+        }
+        if !self.tags.is_empty() {
+            rec.insert(TAGS_FIELD, self.tags);
+        }
+        self.lifecycle.stamp(rec);
+    }
+}
+
+/// Checks that a tag can be stored as a field name: not empty, and neither containing `.` nor
+/// starting with `$`.
+pub(crate) fn validate_tag(tag: &str) -> Result<(), String> {
+    if tag.is_empty() || tag.contains('.') || tag.starts_with('$') {
+        return Err(format!(
+            "Invalid tag '{}': must not be empty, contain '.' or start with '$'",
+            tag
+        ));
+    }
+    Ok(())
+}
+
+/// The object id of a record, which it was archived under and whose timestamp is when that was.
+pub(crate) fn object_id(rec: &Document) -> Option<ObjectId> {
+    match rec.get("_id")? {
+        Bson::ObjectId(id) => Some(*id),
+        Bson::String(id) => ObjectId::parse_str(id).ok(),
+        _ => None,
+    }
+}
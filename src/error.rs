@@ -0,0 +1,394 @@
+/// The error type returned by [crate::ArchiveStore] and [crate::ArchiveBackend]. Each variant
+/// wraps the full error chain (with its context) and says what kind of failure it was, so callers
+/// can tell failures worth retrying, like a dropped connection, from permanent ones, like a record
+/// that can't be serialised, without inspecting backend specific error types.
+use crate::chunking::ChunkIntegrityError;
+use crate::compression::RecordTooLarge;
+use crate::migration::NewerSchemaVersion;
+use crate::unsupported::Unsupported;
+use core::fmt;
+use std::error::Error as StdError;
+use thiserror::Error;
+
+/// MongoDB server error code for a write violating a unique index
+#[cfg(feature = "mongodb")]
+const DUPLICATE_KEY: i32 = 11000;
+
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    /// The backend couldn't be reached, or the connection to it was lost. Retryable.
+    #[error(transparent)]
+    ConnectionFailed(anyhow::Error),
+    /// The backend was reached but is temporarily unable to serve the operation, e.g. it's busy,
+    /// electing a new primary or its pool timed out. Retryable.
+    #[error(transparent)]
+    BackendUnavailable(anyhow::Error),
+    /// A record couldn't be serialised for, or deserialised from, the backend, or its stored form
+    /// is corrupt. Retrying won't help.
+    #[error(transparent)]
+    SerializationError(anyhow::Error),
+    /// The datastore, collection or object the operation needed doesn't exist.
+    #[error(transparent)]
+    NotFound(anyhow::Error),
+    /// A record with the same id already exists.
+    #[error(transparent)]
+    DuplicateKey(anyhow::Error),
+    /// The backend can't perform the operation, see [crate::Unsupported].
+    #[error(transparent)]
+    Unsupported(anyhow::Error),
+    /// The operation was called with invalid arguments or configuration, e.g. a malformed record
+    /// type name or a record over the backend's size limit.
+    #[error(transparent)]
+    InvalidInput(anyhow::Error),
+    /// Any other failure.
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+/// The kinds of [ArchiveError], without the errors themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ArchiveErrorKind {
+    /// See [ArchiveError::ConnectionFailed]
+    ConnectionFailed,
+    /// See [ArchiveError::BackendUnavailable]
+    BackendUnavailable,
+    /// See [ArchiveError::SerializationError]
+    SerializationError,
+    /// See [ArchiveError::NotFound]
+    NotFound,
+    /// See [ArchiveError::DuplicateKey]
+    DuplicateKey,
+    /// See [ArchiveError::Unsupported]
+    Unsupported,
+    /// See [ArchiveError::InvalidInput]
+    InvalidInput,
+    /// See [ArchiveError::Other]
+    Other,
+}
+
+/// Constructor of an [ArchiveError] variant
+type Kind = fn(anyhow::Error) -> ArchiveError;
+
+impl ArchiveError {
+    /// Whether the operation may succeed if retried, i.e. the error is
+    /// [ArchiveError::ConnectionFailed] or [ArchiveError::BackendUnavailable].
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ArchiveError::ConnectionFailed(_) | ArchiveError::BackendUnavailable(_)
+        )
+    }
+
+    /// The underlying error chain, e.g. to downcast to a backend's own error type.
+    pub fn inner(&self) -> &anyhow::Error {
+        match self {
+            ArchiveError::ConnectionFailed(e)
+            | ArchiveError::BackendUnavailable(e)
+            | ArchiveError::SerializationError(e)
+            | ArchiveError::NotFound(e)
+            | ArchiveError::DuplicateKey(e)
+            | ArchiveError::Unsupported(e)
+            | ArchiveError::InvalidInput(e)
+            | ArchiveError::Other(e) => e,
+        }
+    }
+
+    /// Consumes the error, returning the underlying error chain.
+    pub fn into_inner(self) -> anyhow::Error {
+        match self {
+            ArchiveError::ConnectionFailed(e)
+            | ArchiveError::BackendUnavailable(e)
+            | ArchiveError::SerializationError(e)
+            | ArchiveError::NotFound(e)
+            | ArchiveError::DuplicateKey(e)
+            | ArchiveError::Unsupported(e)
+            | ArchiveError::InvalidInput(e)
+            | ArchiveError::Other(e) => e,
+        }
+    }
+
+    /// An [ArchiveError::InvalidInput] error with the given message.
+    pub(crate) fn invalid_input<M>(message: M) -> Self
+    where
+        M: fmt::Display + fmt::Debug + Send + Sync + 'static,
+    {
+        ArchiveError::InvalidInput(anyhow::Error::msg(message))
+    }
+
+    /// The kind of error this is, e.g. to match against a [crate::RetryPolicy]'s retryable kinds.
+    pub fn kind(&self) -> ArchiveErrorKind {
+        match self {
+            ArchiveError::ConnectionFailed(_) => ArchiveErrorKind::ConnectionFailed,
+            ArchiveError::BackendUnavailable(_) => ArchiveErrorKind::BackendUnavailable,
+            ArchiveError::SerializationError(_) => ArchiveErrorKind::SerializationError,
+            ArchiveError::NotFound(_) => ArchiveErrorKind::NotFound,
+            ArchiveError::DuplicateKey(_) => ArchiveErrorKind::DuplicateKey,
+            ArchiveError::Unsupported(_) => ArchiveErrorKind::Unsupported,
+            ArchiveError::InvalidInput(_) => ArchiveErrorKind::InvalidInput,
+            ArchiveError::Other(_) => ArchiveErrorKind::Other,
+        }
+    }
+
+    /// The constructor of the variant this error is, so it can be rewrapped with more context.
+    fn constructor(&self) -> Kind {
+        match self {
+            ArchiveError::ConnectionFailed(_) => ArchiveError::ConnectionFailed,
+            ArchiveError::BackendUnavailable(_) => ArchiveError::BackendUnavailable,
+            ArchiveError::SerializationError(_) => ArchiveError::SerializationError,
+            ArchiveError::NotFound(_) => ArchiveError::NotFound,
+            ArchiveError::DuplicateKey(_) => ArchiveError::DuplicateKey,
+            ArchiveError::Unsupported(_) => ArchiveError::Unsupported,
+            ArchiveError::InvalidInput(_) => ArchiveError::InvalidInput,
+            ArchiveError::Other(_) => ArchiveError::Other,
+        }
+    }
+}
+
+/// Classifies an error by the first cause in its chain, outermost first, whose type says what
+/// kind of failure it was. Errors nothing recognises are [ArchiveError::Other].
+impl From<anyhow::Error> for ArchiveError {
+    fn from(err: anyhow::Error) -> Self {
+        let kind = err
+            .chain()
+            .find_map(classify)
+            .unwrap_or(ArchiveError::Other);
+        kind(err)
+    }
+}
+
+impl From<Unsupported> for ArchiveError {
+    fn from(err: Unsupported) -> Self {
+        ArchiveError::Unsupported(err.into())
+    }
+}
+
+/// Implements `From` for errors of the libraries the backends use, so they classify the same way
+/// whether or not they were given context first.
+macro_rules! from_library_error {
+    ($($(#[$cfg:meta])* $ty:ty),* $(,)?) => {
+        $(
+            $(#[$cfg])*
+            impl From<$ty> for ArchiveError {
+                fn from(err: $ty) -> Self {
+                    anyhow::Error::new(err).into()
+                }
+            }
+        )*
+    };
+}
+
+from_library_error!(
+    #[cfg(feature = "mongodb")]
+    mongodb::error::Error,
+    bson::ser::Error,
+    bson::de::Error,
+    serde_json::Error,
+    std::io::Error,
+    #[cfg(feature = "postgres")]
+    tokio_postgres::Error,
+    #[cfg(feature = "postgres")]
+    deadpool_postgres::PoolError,
+    #[cfg(feature = "sqlite")]
+    rusqlite::Error,
+    #[cfg(feature = "s3")]
+    object_store::Error,
+    #[cfg(feature = "rocksdb")]
+    rocksdb::Error,
+    #[cfg(any(feature = "ipfs", feature = "arweave"))]
+    reqwest::Error,
+);
+
+/// The kind of failure a single cause represents, if its type says.
+fn classify(cause: &(dyn StdError + 'static)) -> Option<Kind> {
+    if let Some(e) = cause.downcast_ref::<ArchiveError>() {
+        return Some(e.constructor());
+    }
+    if cause.is::<Unsupported>() {
+        return Some(ArchiveError::Unsupported);
+    }
+    if cause.is::<RecordTooLarge>() {
+        return Some(ArchiveError::InvalidInput);
+    }
+    if cause.is::<NewerSchemaVersion>()
+        || cause.is::<ChunkIntegrityError>()
+        || cause.is::<bson::ser::Error>()
+        || cause.is::<bson::de::Error>()
+        || cause.is::<serde_json::Error>()
+    {
+        return Some(ArchiveError::SerializationError);
+    }
+    #[cfg(feature = "mongodb")]
+    if let Some(e) = cause.downcast_ref::<mongodb::error::Error>() {
+        return classify_mongodb(e);
+    }
+    if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+        return classify_io(e);
+    }
+    #[cfg(feature = "postgres")]
+    if let Some(e) = cause.downcast_ref::<tokio_postgres::Error>() {
+        return classify_postgres(e);
+    }
+    #[cfg(feature = "postgres")]
+    if let Some(e) = cause.downcast_ref::<deadpool_postgres::PoolError>() {
+        return match e {
+            deadpool_postgres::PoolError::Backend(e) => {
+                classify_postgres(e).or(Some(ArchiveError::ConnectionFailed))
+            }
+            _ => Some(ArchiveError::BackendUnavailable),
+        };
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(e) = cause.downcast_ref::<rusqlite::Error>() {
+        return classify_sqlite(e);
+    }
+    #[cfg(feature = "s3")]
+    if let Some(e) = cause.downcast_ref::<object_store::Error>() {
+        return match e {
+            object_store::Error::NotFound { .. } => Some(ArchiveError::NotFound),
+            object_store::Error::AlreadyExists { .. } => Some(ArchiveError::DuplicateKey),
+            object_store::Error::NotSupported { .. } => Some(ArchiveError::Unsupported),
+            object_store::Error::InvalidPath { .. } => Some(ArchiveError::InvalidInput),
+            // The client has already retried transient request failures by the time it
+            // gives up with a generic error.
+            object_store::Error::Generic { .. } => Some(ArchiveError::BackendUnavailable),
+            _ => None,
+        };
+    }
+    #[cfg(any(feature = "ipfs", feature = "arweave"))]
+    if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+        if e.is_connect() {
+            return Some(ArchiveError::ConnectionFailed);
+        }
+        if e.is_timeout() {
+            return Some(ArchiveError::BackendUnavailable);
+        }
+        if e.is_decode() {
+            return Some(ArchiveError::SerializationError);
+        }
+        return None;
+    }
+    #[cfg(feature = "rocksdb")]
+    if let Some(e) = cause.downcast_ref::<rocksdb::Error>() {
+        use rocksdb::ErrorKind;
+        return match e.kind() {
+            ErrorKind::Busy | ErrorKind::TryAgain | ErrorKind::TimedOut => {
+                Some(ArchiveError::BackendUnavailable)
+            }
+            ErrorKind::NotFound => Some(ArchiveError::NotFound),
+            ErrorKind::NotSupported => Some(ArchiveError::Unsupported),
+            ErrorKind::InvalidArgument => Some(ArchiveError::InvalidInput),
+            ErrorKind::Corruption => Some(ArchiveError::SerializationError),
+            _ => None,
+        };
+    }
+    None
+}
+
+#[cfg(feature = "mongodb")]
+fn classify_mongodb(e: &mongodb::error::Error) -> Option<Kind> {
+    use mongodb::error::{
+        ErrorKind, WriteFailure, RETRYABLE_WRITE_ERROR, TRANSIENT_TRANSACTION_ERROR,
+    };
+    match *e.kind {
+        ErrorKind::Io(_)
+        | ErrorKind::DnsResolve { .. }
+        | ErrorKind::ServerSelection { .. }
+        | ErrorKind::ConnectionPoolCleared { .. } => Some(ArchiveError::ConnectionFailed),
+        ErrorKind::BsonSerialization(_) | ErrorKind::BsonDeserialization(_) => {
+            Some(ArchiveError::SerializationError)
+        }
+        ErrorKind::InvalidArgument { .. } => Some(ArchiveError::InvalidInput),
+        ErrorKind::Write(WriteFailure::WriteError(ref err)) if err.code == DUPLICATE_KEY => {
+            Some(ArchiveError::DuplicateKey)
+        }
+        ErrorKind::BulkWrite(ref failure)
+            if failure
+                .write_errors
+                .iter()
+                .flatten()
+                .any(|err| err.code == DUPLICATE_KEY) =>
+        {
+            Some(ArchiveError::DuplicateKey)
+        }
+        ErrorKind::Command(ref err) if err.code == DUPLICATE_KEY => {
+            Some(ArchiveError::DuplicateKey)
+        }
+        _ if e.contains_label(RETRYABLE_WRITE_ERROR)
+            || e.contains_label(TRANSIENT_TRANSACTION_ERROR) =>
+        {
+            Some(ArchiveError::BackendUnavailable)
+        }
+        _ => None,
+    }
+}
+
+fn classify_io(e: &std::io::Error) -> Option<Kind> {
+    use std::io::ErrorKind;
+    match e.kind() {
+        ErrorKind::NotFound => Some(ArchiveError::NotFound),
+        ErrorKind::AlreadyExists => Some(ArchiveError::DuplicateKey),
+        ErrorKind::ConnectionRefused
+        | ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::NotConnected
+        | ErrorKind::BrokenPipe => Some(ArchiveError::ConnectionFailed),
+        ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted => {
+            Some(ArchiveError::BackendUnavailable)
+        }
+        ErrorKind::InvalidData => Some(ArchiveError::SerializationError),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "postgres")]
+fn classify_postgres(e: &tokio_postgres::Error) -> Option<Kind> {
+    use tokio_postgres::error::SqlState;
+    if e.is_closed() {
+        return Some(ArchiveError::ConnectionFailed);
+    }
+    let code = e.code()?;
+    if *code == SqlState::UNIQUE_VIOLATION {
+        Some(ArchiveError::DuplicateKey)
+    } else if *code == SqlState::UNDEFINED_TABLE {
+        Some(ArchiveError::NotFound)
+    } else if *code == SqlState::T_R_SERIALIZATION_FAILURE
+        || *code == SqlState::T_R_DEADLOCK_DETECTED
+        || *code == SqlState::TOO_MANY_CONNECTIONS
+        || *code == SqlState::ADMIN_SHUTDOWN
+        || *code == SqlState::CANNOT_CONNECT_NOW
+    {
+        Some(ArchiveError::BackendUnavailable)
+    } else if code.code().starts_with("08") {
+        // Class 08 is connection exceptions
+        Some(ArchiveError::ConnectionFailed)
+    } else {
+        None
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn classify_sqlite(e: &rusqlite::Error) -> Option<Kind> {
+    use rusqlite::ffi;
+    match e {
+        rusqlite::Error::SqliteFailure(err, _) => match err.extended_code {
+            ffi::SQLITE_CONSTRAINT_PRIMARYKEY | ffi::SQLITE_CONSTRAINT_UNIQUE => {
+                Some(ArchiveError::DuplicateKey)
+            }
+            _ => match err.code {
+                ffi::ErrorCode::DatabaseBusy | ffi::ErrorCode::DatabaseLocked => {
+                    Some(ArchiveError::BackendUnavailable)
+                }
+                ffi::ErrorCode::CannotOpen => Some(ArchiveError::ConnectionFailed),
+                ffi::ErrorCode::DatabaseCorrupt | ffi::ErrorCode::NotADatabase => {
+                    Some(ArchiveError::SerializationError)
+                }
+                _ => None,
+            },
+        },
+        rusqlite::Error::FromSqlConversionFailure(..)
+        | rusqlite::Error::InvalidColumnType(..)
+        | rusqlite::Error::ToSqlConversionFailure(_) => Some(ArchiveError::SerializationError),
+        rusqlite::Error::QueryReturnedNoRows => Some(ArchiveError::NotFound),
+        _ => None,
+    }
+}
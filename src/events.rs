@@ -0,0 +1,125 @@
+/// Notifications of changes to the archive, so that downstream indexers can follow new records as
+/// they are archived instead of polling [crate::ArchiveStore::find_all]. Backends that can watch
+/// for changes themselves, i.e. MongoDB change streams, report every change made by any process.
+/// Other backends report the changes made through the subscribing store and its clones.
+use crate::{ArchiveError, ArchiveErrorKind, ArchiveRecordType, ArchiveStore};
+use anyhow::anyhow;
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use std::sync::OnceLock;
+use tokio::sync::broadcast;
+
+/// Number of changes kept for subscribers of the in-process channel that haven't read them yet
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// What happened to a record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ArchiveEventKind {
+    /// The record was archived
+    Created,
+    /// The record was replaced. Records written by [ArchiveStore::upsert] are reported as
+    /// updated whether or not they existed, unless the backend watches for changes itself.
+    Updated,
+    /// The record was deleted
+    Deleted,
+}
+
+/// A change to an archived record, yielded by [ArchiveStore::subscribe]. Fetch the record itself
+/// with [ArchiveStore::find_by_id].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ArchiveEvent {
+    /// The type of the record
+    pub rec_type: ArchiveRecordType,
+    /// What happened to the record
+    pub kind: ArchiveEventKind,
+    /// The id of the record
+    pub id: String,
+}
+
+/// The in-process channel changes made through a store are published to
+pub(crate) type EventChannel = OnceLock<broadcast::Sender<ArchiveEvent>>;
+
+impl ArchiveStore {
+    /// Streams the changes to records of [ArchiveRecordType] made from now on, for as long as the
+    /// stream is kept. On MongoDB the changes are read from a change stream, which needs a replica
+    /// set or sharded cluster, and include those made by other processes. On other backends only
+    /// the changes made through this store and its clones are reported, and records deleted by
+    /// [ArchiveStore::delete_where] or pruning are not.
+    ///
+    /// A subscriber reading too slowly to keep up with the in-process channel is sent an error
+    /// saying how many changes it missed, after which it carries on with the latest changes.
+    pub async fn subscribe(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<impl Stream<Item = Result<ArchiveEvent, ArchiveError>>, ArchiveError> {
+        rec_type.validate().map_err(ArchiveError::invalid_input)?;
+        let watched = self.backend()?.watch(rec_type.clone()).await;
+        match watched {
+            Ok(events) => Ok(events),
+            // Backends that can't watch for changes fall back to the changes made through here.
+            Err(e) if e.kind() == ArchiveErrorKind::Unsupported => {
+                Ok(self.subscribe_locally(rec_type))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Streams the changes to records of [ArchiveRecordType] published by this store.
+    fn subscribe_locally(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> BoxStream<'static, Result<ArchiveEvent, ArchiveError>> {
+        let receiver = self.inner.events.get_or_init(channel).subscribe();
+        receive(receiver, rec_type)
+    }
+
+    /// Publishes changes to the records with the given ids to this store's subscribers, if it has
+    /// any.
+    pub(crate) fn publish<S: AsRef<str>>(
+        &self,
+        rec_type: &ArchiveRecordType,
+        kind: ArchiveEventKind,
+        ids: &[S],
+    ) {
+        let sender = match self.inner.events.get() {
+            Some(sender) if sender.receiver_count() > 0 => sender,
+            _ => return,
+        };
+        for id in ids {
+            // Sending only fails once every subscriber has gone.
+            let _ = sender.send(ArchiveEvent {
+                rec_type: rec_type.clone(),
+                kind,
+                id: id.as_ref().to_string(),
+            });
+        }
+    }
+}
+
+/// Creates an in-process channel for changes.
+pub(crate) fn channel() -> broadcast::Sender<ArchiveEvent> {
+    broadcast::channel(CHANNEL_CAPACITY).0
+}
+
+/// Streams the changes to records of [ArchiveRecordType] sent to an in-process channel.
+pub(crate) fn receive(
+    receiver: broadcast::Receiver<ArchiveEvent>,
+    rec_type: ArchiveRecordType,
+) -> BoxStream<'static, Result<ArchiveEvent, ArchiveError>> {
+    stream::unfold(receiver, move |mut receiver| {
+        let rec_type = rec_type.clone();
+        async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) if event.rec_type == rec_type => return Some((Ok(event), receiver)),
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        let err = anyhow!("Subscriber fell behind and missed {} changes", missed);
+                        return Some((Err(ArchiveError::Other(err)), receiver));
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        }
+    })
+    .boxed()
+}
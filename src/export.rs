@@ -0,0 +1,285 @@
+/// Exports of archived records to files that analytics tools can load directly, e.g. to pull
+/// transaction batches into a data warehouse without talking to the backend. Records are streamed
+/// from the backend and written as they are read, so an export never holds the whole archive in
+/// memory.
+use crate::envelope::Provenance;
+use crate::{ArchiveError, ArchiveRecordType, ArchiveStore, Filter};
+use anyhow::Context;
+use bson::{Bson, Document};
+use futures::{Stream, TryStreamExt};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// The file format records are exported in by [ArchiveStore::export].
+///
+/// CSV and Parquet are tabular, so their columns are the top-level fields of the first record
+/// exported, in order. Fields missing from a later record are left empty, and fields the first
+/// record didn't have are left out. Nested documents and arrays are written as JSON text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Newline-delimited JSON, one record per line in relaxed extended JSON
+    JsonLines,
+    /// Comma-separated values with a header row, quoted as described by RFC 4180
+    Csv,
+    /// Apache Parquet. Columns are typed from the first record's values: booleans, integers
+    /// and doubles keep their type, and other values are written as text. Requires the
+    /// `parquet` feature.
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl ArchiveStore {
+    /// Writes the archived records of [ArchiveRecordType] matching the [Filter] to `writer` in
+    /// the given [ExportFormat], returning the number of records exported. Records are read from
+    /// both tiers and decoded as by [ArchiveStore::find_all], but as with [ArchiveStore::query],
+    /// fields of compressed records can't be filtered on. Each record's provenance is exported in
+    /// its reserved fields, e.g. `_archived_at`, so [ArchiveStore::import] keeps it. The writer is
+    /// shut down once every record has been written.
+    pub async fn export<W>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+        format: ExportFormat,
+        writer: W,
+    ) -> Result<u64, ArchiveError>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
+        let records = self
+            .query_envelope_stream::<Document>(rec_type, filter)
+            .await?
+            .map_ok(|envelope| {
+                let mut rec = envelope.record;
+                Provenance {
+                    archived_at: envelope.archived_at,
+                    node_id: envelope.node_id,
+                    tags: envelope.tags,
+                    lifecycle: envelope.lifecycle,
+                }
+                .restore(&mut rec);
+                rec
+            });
+        futures::pin_mut!(records);
+        match format {
+            ExportFormat::JsonLines => export_json_lines(records, writer).await,
+            ExportFormat::Csv => export_csv(records, writer).await,
+            #[cfg(feature = "parquet")]
+            ExportFormat::Parquet => parquet_export::export_parquet(records, writer).await,
+        }
+    }
+}
+
+/// Writes each record as a line of relaxed extended JSON.
+async fn export_json_lines<W: AsyncWrite + Unpin>(
+    mut records: impl Stream<Item = Result<Document, ArchiveError>> + Unpin,
+    mut writer: W,
+) -> Result<u64, ArchiveError> {
+    let mut exported = 0;
+    while let Some(rec) = records.try_next().await? {
+        let mut line = Bson::Document(rec).into_relaxed_extjson().to_string();
+        line.push('\n');
+        writer
+            .write_all(line.as_bytes())
+            .await
+            .context("Failed to write exported record")?;
+        exported += 1;
+    }
+    writer.shutdown().await.context("Failed to finish export")?;
+    Ok(exported)
+}
+
+/// Writes a header row of the first record's fields, then a row per record.
+async fn export_csv<W: AsyncWrite + Unpin>(
+    mut records: impl Stream<Item = Result<Document, ArchiveError>> + Unpin,
+    mut writer: W,
+) -> Result<u64, ArchiveError> {
+    let mut columns: Option<Vec<String>> = None;
+    let mut exported = 0;
+    while let Some(rec) = records.try_next().await? {
+        let mut rows = String::new();
+        let columns = columns.get_or_insert_with(|| {
+            let columns: Vec<String> = rec.keys().cloned().collect();
+            rows.push_str(&csv_row(columns.iter().cloned()));
+            columns
+        });
+        rows.push_str(&csv_row(
+            columns
+                .iter()
+                .map(|column| rec.get(column).map(text).unwrap_or_default()),
+        ));
+        writer
+            .write_all(rows.as_bytes())
+            .await
+            .context("Failed to write exported record")?;
+        exported += 1;
+    }
+    writer.shutdown().await.context("Failed to finish export")?;
+    Ok(exported)
+}
+
+/// A CSV row of the given cells, ending in CRLF.
+fn csv_row(cells: impl Iterator<Item = String>) -> String {
+    let cells: Vec<String> = cells
+        .map(|cell| {
+            if cell.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell
+            }
+        })
+        .collect();
+    format!("{}\r\n", cells.join(","))
+}
+
+/// The text of a value in a tabular export: strings as they are, object ids in hex and other
+/// values as relaxed extended JSON. Nulls are empty.
+fn text(value: &Bson) -> String {
+    match value {
+        Bson::Null => String::new(),
+        Bson::String(value) => value.clone(),
+        Bson::ObjectId(id) => id.to_hex(),
+        value => value.clone().into_relaxed_extjson().to_string(),
+    }
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_export {
+    use super::text;
+    use crate::ArchiveError;
+    use anyhow::Context;
+    use arrow_array::builder::{BooleanBuilder, Float64Builder, Int64Builder, StringBuilder};
+    use arrow_array::{ArrayRef, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use bson::{Bson, Document};
+    use futures::{Stream, TryStreamExt};
+    use parquet::arrow::AsyncArrowWriter;
+    use std::sync::Arc;
+    use tokio::io::AsyncWrite;
+
+    /// Number of records buffered into each batch written to the Parquet file
+    const BATCH_SIZE: usize = 1024;
+
+    /// Builds a typed column from the values of a field. Values of other types than the column's
+    /// are written as nulls, except that integers are widened into double columns.
+    enum Column {
+        Boolean(BooleanBuilder),
+        Int64(Int64Builder),
+        Float64(Float64Builder),
+        Text(StringBuilder),
+    }
+
+    impl Column {
+        /// A column typed after the first record's value of the field.
+        fn new(value: &Bson) -> Self {
+            match value {
+                Bson::Boolean(_) => Column::Boolean(BooleanBuilder::new()),
+                Bson::Int32(_) | Bson::Int64(_) => Column::Int64(Int64Builder::new()),
+                Bson::Double(_) => Column::Float64(Float64Builder::new()),
+                _ => Column::Text(StringBuilder::new()),
+            }
+        }
+
+        fn data_type(&self) -> DataType {
+            match self {
+                Column::Boolean(_) => DataType::Boolean,
+                Column::Int64(_) => DataType::Int64,
+                Column::Float64(_) => DataType::Float64,
+                Column::Text(_) => DataType::Utf8,
+            }
+        }
+
+        fn append(&mut self, value: Option<&Bson>) {
+            match (self, value) {
+                (Column::Boolean(column), Some(Bson::Boolean(value))) => {
+                    column.append_value(*value)
+                }
+                (Column::Boolean(column), _) => column.append_null(),
+                (Column::Int64(column), Some(Bson::Int32(value))) => {
+                    column.append_value((*value).into())
+                }
+                (Column::Int64(column), Some(Bson::Int64(value))) => column.append_value(*value),
+                (Column::Int64(column), _) => column.append_null(),
+                (Column::Float64(column), Some(Bson::Double(value))) => column.append_value(*value),
+                (Column::Float64(column), Some(Bson::Int32(value))) => {
+                    column.append_value((*value).into())
+                }
+                (Column::Float64(column), Some(Bson::Int64(value))) => {
+                    column.append_value(*value as f64)
+                }
+                (Column::Float64(column), _) => column.append_null(),
+                (Column::Text(column), None | Some(Bson::Null)) => column.append_null(),
+                (Column::Text(column), Some(value)) => column.append_value(text(value)),
+            }
+        }
+
+        /// Takes the values appended so far, leaving the column empty.
+        fn finish(&mut self) -> ArrayRef {
+            match self {
+                Column::Boolean(column) => Arc::new(column.finish()),
+                Column::Int64(column) => Arc::new(column.finish()),
+                Column::Float64(column) => Arc::new(column.finish()),
+                Column::Text(column) => Arc::new(column.finish()),
+            }
+        }
+    }
+
+    /// Writes the records as Parquet, with a column per field of the first record.
+    pub(super) async fn export_parquet<W: AsyncWrite + Unpin + Send>(
+        mut records: impl Stream<Item = Result<Document, ArchiveError>> + Unpin,
+        writer: W,
+    ) -> Result<u64, ArchiveError> {
+        let first = match records.try_next().await? {
+            Some(rec) => rec,
+            // Without a record there are no columns, so write an empty file.
+            None => Document::new(),
+        };
+        let mut columns: Vec<(String, Column)> = first
+            .iter()
+            .map(|(field, value)| (field.clone(), Column::new(value)))
+            .collect();
+        let schema = Arc::new(Schema::new(
+            columns
+                .iter()
+                .map(|(field, column)| Field::new(field, column.data_type(), true))
+                .collect::<Vec<_>>(),
+        ));
+        let mut parquet = AsyncArrowWriter::try_new(writer, schema.clone(), None)
+            .context("Failed to start Parquet export")?;
+
+        let mut exported = 0;
+        let mut next = (!first.is_empty()).then_some(first);
+        while let Some(rec) = next {
+            for (field, column) in &mut columns {
+                column.append(rec.get(field));
+            }
+            exported += 1;
+            if exported % BATCH_SIZE as u64 == 0 {
+                write_batch(&mut parquet, &schema, &mut columns).await?;
+            }
+            next = records.try_next().await?;
+        }
+        if exported % BATCH_SIZE as u64 != 0 {
+            write_batch(&mut parquet, &schema, &mut columns).await?;
+        }
+        parquet.close().await.context("Failed to finish export")?;
+        Ok(exported)
+    }
+
+    /// Writes the values appended to the columns as a batch, leaving them empty.
+    async fn write_batch<W: AsyncWrite + Unpin + Send>(
+        parquet: &mut AsyncArrowWriter<W>,
+        schema: &Arc<Schema>,
+        columns: &mut [(String, Column)],
+    ) -> Result<(), ArchiveError> {
+        let arrays = columns
+            .iter_mut()
+            .map(|(_, column)| column.finish())
+            .collect();
+        let batch = RecordBatch::try_new(schema.clone(), arrays)
+            .context("Failed to build Parquet batch")?;
+        parquet
+            .write(&batch)
+            .await
+            .context("Failed to write exported records")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,806 @@
+/// An implementation of an archive datastore that stores records as files in a local directory, for
+/// air-gapped archival and archives that are backed up by simply copying a directory. Records of
+/// each [ArchiveRecordType] are kept in their own subdirectory of the root, named after the
+/// [ACCOUNT_DIR] and [TRANSACTION_DIR] constants or the custom record type's name, with each record
+/// in a file named after its id in the configured [FileFormat]. Ids are generated as ObjectIds, so
+/// listing a directory in name order returns its records in the order they were archived.
+///
+/// Every file is written to a temporary file in the same directory and then renamed into place,
+/// so a crash never leaves a partially written record behind. Queries, counts and statistics read
+/// the records they need and filter or tally them here. Atomic batches are staged first, see
+/// [crate::ArchiveStore::create_atomic]. Other operations that need transactions or take MongoDB
+/// specific arguments fail with [Unsupported]. Records are never chunked.
+use crate::filter::lookup;
+use crate::staging::{StagedBatch, STAGING_DIR};
+use crate::stats::{aggregate, tally};
+use crate::{
+    AggregationSpec, ArchiveBackend, ArchiveCollectionStats, ArchiveError, ArchiveEvent,
+    ArchiveRecordType, Filter, GroupBy, IndexSpec, MergeMode, Page, PageRequest, Unsupported,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bson::{oid::ObjectId, Bson, Document};
+use core::fmt;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use log::debug;
+use serde::{de::DeserializeOwned, Serialize};
+use std::borrow::Borrow;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::OnceCell;
+
+/// Directory storing account data
+const ACCOUNT_DIR: &str = "accounts";
+/// Directory storing transaction data
+const TRANSACTION_DIR: &str = "transaction_data";
+/// Directory storing blocks
+const BLOCK_DIR: &str = "blocks";
+/// Directory storing transaction receipts
+const RECEIPT_DIR: &str = "receipts";
+/// Age after which a temporary file is taken to have been left behind by an interrupted write,
+/// rather than belonging to a write in progress
+const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// The format records are written in by the filesystem backend.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FileFormat {
+    /// Pretty printed relaxed extended JSON, readable with any text editor. The default.
+    #[default]
+    Json,
+    /// BSON, as stored by MongoDB. Smaller and faster, and preserves every BSON type exactly.
+    Bson,
+}
+
+impl FileFormat {
+    /// Extension of the files records are written to.
+    fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::Json => "json",
+            FileFormat::Bson => "bson",
+        }
+    }
+
+    fn serialise(&self, doc: Document) -> Result<Vec<u8>> {
+        match self {
+            FileFormat::Json => {
+                serde_json::to_vec_pretty(&Bson::Document(doc).into_relaxed_extjson())
+                    .context("Failed to serialise record to JSON")
+            }
+            FileFormat::Bson => bson::to_vec(&doc).context("Failed to serialise record to BSON"),
+        }
+    }
+
+    fn deserialise(&self, bytes: &[u8]) -> Result<Document> {
+        match self {
+            FileFormat::Json => {
+                let value: serde_json::Value = serde_json::from_slice(bytes)?;
+                match Bson::try_from(value)? {
+                    Bson::Document(doc) => Ok(doc),
+                    other => anyhow::bail!("Record is not a document: {}", other),
+                }
+            }
+            FileFormat::Bson => Ok(bson::from_slice(bytes)?),
+        }
+    }
+}
+
+impl fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// A record file found when listing a record type's directory.
+struct Entry {
+    id: String,
+    path: PathBuf,
+    metadata: std::fs::Metadata,
+}
+
+/// Checks that an id can safely be used as a file name.
+fn validate_id(id: &str) -> Result<()> {
+    if id.is_empty()
+        || id.starts_with('.')
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    {
+        anyhow::bail!(
+            "Record id '{}' can't be used as a file name: ids must only contain letters, digits, \
+            '-', '_' and '.', and not start with '.'",
+            id
+        );
+    }
+    Ok(())
+}
+
+/// Writes a file through a temporary file in the same directory, which is then renamed into
+/// place, so a crash never leaves a partially written file behind.
+async fn replace(path: &Path, contents: &[u8]) -> Result<()> {
+    // Hidden, so that listing the directory skips it should the rename never happen.
+    let name = path.file_name().and_then(|name| name.to_str());
+    let tmp = path.with_file_name(format!(".{}.tmp", name.unwrap_or_default()));
+    let mut file = fs::File::create(&tmp)
+        .await
+        .with_context(|| format!("Creating {}", tmp.display()))?;
+    file.write_all(contents).await?;
+    file.sync_all().await?;
+    fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+/// Decodes the documents read from the files into records.
+fn decode<T: DeserializeOwned>(docs: Vec<Document>) -> Result<Vec<T>> {
+    docs.into_iter()
+        .map(|doc| bson::from_document(doc).context("Failed to deserialise record"))
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct FilesystemBackend {
+    /// Directory the record type directories are created in
+    pub root: PathBuf,
+    pub format: FileFormat,
+    /// Set once the batches left staged by a crash have been written, before the first atomic
+    /// batch
+    pub recovered: OnceCell<()>,
+}
+
+impl FilesystemBackend {
+    pub fn new(root: &Path, format: FileFormat) -> Self {
+        FilesystemBackend {
+            root: root.to_path_buf(),
+            format,
+            recovered: OnceCell::new(),
+        }
+    }
+
+    /// The filesystem backend takes its root directory from [crate::ArchiveBackends::Filesystem],
+    /// so doesn't use a URI.
+    pub fn validate_uri(uri: &str) -> std::result::Result<(), String> {
+        if !uri.is_empty() {
+            return Err(format!(
+                "The filesystem backend doesn't use a URI, but '{}' was given",
+                crate::uri::redact(uri)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that the datastore name is not empty. Records aren't stored under the datastore
+    /// name, which only identifies the archive in logs, metrics and spill files.
+    pub fn validate_datastore(datastore: &str) -> std::result::Result<(), String> {
+        if datastore.is_empty() {
+            return Err("Datastore name must not be empty".to_string());
+        }
+        Ok(())
+    }
+
+    /// Directory storing records of the given type.
+    fn dir(&self, rec_type: &ArchiveRecordType) -> PathBuf {
+        match rec_type {
+            ArchiveRecordType::Account => self.root.join(ACCOUNT_DIR),
+            ArchiveRecordType::TransactionBatch => self.root.join(TRANSACTION_DIR),
+            ArchiveRecordType::Block => self.root.join(BLOCK_DIR),
+            ArchiveRecordType::Receipt => self.root.join(RECEIPT_DIR),
+            ArchiveRecordType::Custom(name) => self.root.join(name),
+        }
+    }
+
+    /// File storing a record.
+    fn path(&self, rec_type: &ArchiveRecordType, id: &str) -> PathBuf {
+        self.dir(rec_type)
+            .join(format!("{}.{}", id, self.format.extension()))
+    }
+
+    /// Writes a record to its file, returning its id. The document's `_id` is used as the id if
+    /// it has one, otherwise a new ObjectId is generated. The id is kept in the file, so it
+    /// doesn't need to be recovered from the file name.
+    async fn write(&self, rec_type: &ArchiveRecordType, mut doc: Document) -> Result<String> {
+        let id = match doc.remove("_id") {
+            Some(Bson::ObjectId(oid)) => oid.to_hex(),
+            Some(Bson::String(id)) => id,
+            Some(other) => other.to_string(),
+            None => ObjectId::new().to_hex(),
+        };
+        validate_id(&id)?;
+        doc.insert("_id", id.clone());
+        let contents = self.format.serialise(doc)?;
+
+        let dir = self.dir(rec_type);
+        fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("Creating record directory {}", dir.display()))?;
+
+        let path = self.path(rec_type, &id);
+        replace(&path, &contents)
+            .await
+            .with_context(|| format!("Writing record {}", path.display()))?;
+        Ok(id)
+    }
+
+    /// Stages an atomic batch, returning the path of its staging file.
+    async fn stage(&self, batch: &StagedBatch) -> Result<PathBuf> {
+        let dir = self.root.join(STAGING_DIR);
+        fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("Creating staging directory {}", dir.display()))?;
+        let path = dir.join(StagedBatch::name());
+        replace(&path, &batch.to_bytes()?)
+            .await
+            .with_context(|| format!("Staging batch {}", path.display()))?;
+        Ok(path)
+    }
+
+    /// Writes the batches left staged by a crash in full, in the order they were staged,
+    /// returning how many were written. A batch that fails to be written stays staged.
+    pub(crate) async fn recover(&self) -> Result<u64> {
+        let dir = self.root.join(STAGING_DIR);
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e).with_context(|| format!("Listing {}", dir.display())),
+        };
+        let mut paths = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            if name.to_str().is_some_and(|name| !name.starts_with('.')) {
+                paths.push(entry.path());
+            }
+        }
+        paths.sort();
+
+        for path in &paths {
+            let bytes = fs::read(path)
+                .await
+                .with_context(|| format!("Reading staged batch {}", path.display()))?;
+            let batch = StagedBatch::from_bytes(&bytes)
+                .with_context(|| format!("Invalid staged batch {}", path.display()))?;
+            for (rec_type, doc) in batch.records {
+                self.write(&rec_type, doc).await?;
+            }
+            fs::remove_file(path)
+                .await
+                .with_context(|| format!("Removing staged batch {}", path.display()))?;
+            debug!("Recovered staged batch {}", path.display());
+        }
+        Ok(paths.len() as u64)
+    }
+
+    /// Reads and parses a record file.
+    async fn read(&self, path: &Path) -> Result<Document> {
+        let bytes = fs::read(path)
+            .await
+            .with_context(|| format!("Reading record {}", path.display()))?;
+        self.format
+            .deserialise(&bytes)
+            .with_context(|| format!("Invalid record in {}", path.display()))
+    }
+
+    /// Lists the record files of the given type in id order. A missing directory has no records.
+    async fn list(&self, rec_type: &ArchiveRecordType) -> Result<Vec<Entry>> {
+        let dir = self.dir(rec_type);
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("Listing {}", dir.display())),
+        };
+
+        let extension = format!(".{}", self.format.extension());
+        let mut files = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let id = match name.to_str().and_then(|name| name.strip_suffix(&extension)) {
+                Some(id) if !id.starts_with('.') => id.to_string(),
+                _ => continue,
+            };
+            files.push(Entry {
+                id,
+                path: entry.path(),
+                metadata: entry.metadata().await?,
+            });
+        }
+        files.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(files)
+    }
+
+    /// Reads the records stored in the listed files, keeping their order.
+    fn read_all(&self, files: Vec<Entry>) -> BoxStream<'_, Result<Document>> {
+        stream::iter(files)
+            .then(move |file| async move { self.read(&file.path).await })
+            .boxed()
+    }
+}
+
+#[async_trait]
+impl ArchiveBackend for FilesystemBackend {
+    /// Writes the record to a new file. A record given an `_id` that is already in use replaces
+    /// the record stored under it.
+    async fn create<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+        let id = self.write(&rec_type, doc).await?;
+        debug!("Wrote record {} to {}", id, self.root.display());
+        Ok(id)
+    }
+
+    /// Writes the records in order. Records written before a failure are kept, whatever the
+    /// ordering setting.
+    async fn create_many<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        recs: Vec<T>,
+    ) -> Result<Vec<String>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let mut ids = Vec::with_capacity(recs.len());
+        for rec in recs.iter() {
+            let doc = bson::to_document(rec).context("Failed to serialise record to BSON")?;
+            ids.push(self.write(&rec_type, doc).await?);
+        }
+        debug!("Wrote {} records to {}", ids.len(), self.root.display());
+        Ok(ids)
+    }
+
+    /// The filesystem has no transactions spanning several files, so the batch is staged in a
+    /// single file first, then its records written one by one.
+    async fn create_atomic(
+        &self,
+        records: Vec<(ArchiveRecordType, Document)>,
+    ) -> Result<Vec<String>, ArchiveError> {
+        self.recovered
+            .get_or_try_init(|| async { self.recover().await.map(|_| ()) })
+            .await?;
+        let batch = StagedBatch::new(records);
+        let staged = self.stage(&batch).await?;
+
+        let mut written = Vec::with_capacity(batch.records.len());
+        for (rec_type, doc) in &batch.records {
+            match self.write(rec_type, doc.clone()).await {
+                Ok(id) => written.push(self.path(rec_type, &id)),
+                Err(e) => {
+                    // Should deleting a record fail, the batch stays staged, to be written in full
+                    // by the next recovery rather than left half written.
+                    for path in &written {
+                        fs::remove_file(path)
+                            .await
+                            .with_context(|| format!("Rolling back record {}", path.display()))?;
+                    }
+                    fs::remove_file(&staged).await?;
+                    return Err(e.into());
+                }
+            }
+        }
+        fs::remove_file(&staged)
+            .await
+            .with_context(|| format!("Removing staged batch {}", staged.display()))?;
+        debug!(
+            "Wrote batch of {} records to {}",
+            written.len(),
+            self.root.display()
+        );
+        Ok(batch.ids())
+    }
+
+    async fn find_all<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let files = self.list(&rec_type).await?;
+        Ok(decode(self.read_all(files).try_collect().await?)?)
+    }
+
+    /// Streams the records, reading each file as it is needed.
+    async fn find_all_stream<'a, T: DeserializeOwned>(
+        &'a self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'a, Result<T, ArchiveError>>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin + 'a,
+    {
+        let files = self.list(&rec_type).await?;
+        Ok(self
+            .read_all(files)
+            .and_then(|doc| async move {
+                bson::from_document(doc).context("Failed to deserialise record")
+            })
+            .map_err(ArchiveError::from)
+            .boxed())
+    }
+
+    async fn find_page<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        request: &PageRequest,
+    ) -> Result<Page<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let mut files = self.list(&rec_type).await?;
+        if let Some(after) = &request.after_token {
+            files.retain(|file| file.id > *after);
+        }
+        let next_token = match files.len() > request.limit {
+            true => Some(files[request.limit - 1].id.clone()),
+            false => None,
+        };
+        files.truncate(request.limit);
+
+        Ok(Page {
+            items: decode(self.read_all(files).try_collect().await?)?,
+            next_token,
+        })
+    }
+
+    /// Query data store for the records of the given type matching a [Filter]. Every record of
+    /// the type is read and filtered here.
+    async fn query<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let files = self.list(&rec_type).await?;
+        let docs = self
+            .read_all(files)
+            .try_filter(|doc| futures::future::ready(filter.matches(doc)))
+            .try_collect()
+            .await?;
+        Ok(decode(docs)?)
+    }
+
+    /// Records are stored whole, so whole documents are returned.
+    async fn find_projected(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        _fields: &[String],
+    ) -> Result<Vec<Document>, ArchiveError> {
+        self.query::<Document>(rec_type, filter).await
+    }
+
+    async fn find_by_id<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<Option<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        // An id that can't be a file name can't have been written.
+        if validate_id(id).is_err() {
+            return Ok(None);
+        }
+        let path = self.path(&rec_type, id);
+        if !fs::try_exists(&path)
+            .await
+            .with_context(|| format!("Checking for record {}", path.display()))?
+        {
+            return Ok(None);
+        }
+        let doc = self.read(&path).await?;
+        Ok(Some(
+            bson::from_document(doc).context("Failed to deserialise record")?,
+        ))
+    }
+
+    async fn delete_by_id(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<bool, ArchiveError> {
+        // An id that can't be a file name can't have been written.
+        if validate_id(id).is_err() {
+            return Ok(false);
+        }
+        let path = self.path(&rec_type, id);
+        match fs::remove_file(&path).await {
+            Ok(()) => {
+                debug!("Deleted {}", path.display());
+                Ok(true)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(anyhow::Error::new(e)
+                .context(format!("Deleting record {}", path.display()))
+                .into()),
+        }
+    }
+
+    /// Every record of the type is read and filtered here, and the files of the matching records
+    /// removed.
+    async fn delete_where(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        let mut deleted = 0;
+        for file in self.list(&rec_type).await? {
+            if filter.matches(&self.read(&file.path).await?) {
+                fs::remove_file(&file.path)
+                    .await
+                    .with_context(|| format!("Deleting record {}", file.path.display()))?;
+                deleted += 1;
+            }
+        }
+
+        debug!("Deleted {} records from {}", deleted, self.root.display());
+        Ok(deleted)
+    }
+
+    /// Rewrites the record's file if it exists, keeping its id whatever `_id` the record holds.
+    async fn update_by_id<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        rec: T,
+    ) -> Result<bool, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        // An id that can't be a file name can't have been written.
+        if validate_id(id).is_err() {
+            return Ok(false);
+        }
+        let path = self.path(&rec_type, id);
+        if !fs::try_exists(&path)
+            .await
+            .with_context(|| format!("Checking for record {}", path.display()))?
+        {
+            return Ok(false);
+        }
+        let mut doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+        doc.insert("_id", id);
+        self.write(&rec_type, doc).await?;
+        debug!("Rewrote record {} in {}", id, self.root.display());
+        Ok(true)
+    }
+
+    /// Every record of the type is read and filtered here until the first match, in id order,
+    /// whose file is rewritten. Nothing stops concurrent upserts from both writing a new record.
+    async fn upsert<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let mut doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+        let files = self.list(&rec_type).await?;
+        let existing = self
+            .read_all(files)
+            .try_filter(|doc| futures::future::ready(filter.matches(doc)))
+            .try_next()
+            .await?;
+        if let Some(existing) = existing {
+            doc.insert("_id", existing.get_str("_id").unwrap_or_default());
+        }
+        let id = self.write(&rec_type, doc).await?;
+        debug!("Upserted record {} in {}", id, self.root.display());
+        Ok(id)
+    }
+
+    /// Returns a random sample of roughly `rate` (0.0 to 1.0) of the records of the given type,
+    /// selecting each record independently with probability `rate`. Only the selected records are
+    /// read.
+    async fn find_sampled<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rate: f64,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let mut files = self.list(&rec_type).await?;
+        files.retain(|_| rand::random::<f64>() < rate);
+        Ok(decode(self.read_all(files).try_collect().await?)?)
+    }
+
+    /// Records are never chunked, so there are never orphaned chunks.
+    async fn find_orphaned_chunks(
+        &self,
+        _rec_type: ArchiveRecordType,
+    ) -> Result<Vec<String>, ArchiveError> {
+        Ok(Vec::new())
+    }
+
+    /// Records are never chunked, so there are never orphaned chunks.
+    async fn cleanup_orphans(&self, _rec_type: ArchiveRecordType) -> Result<u64, ArchiveError> {
+        Ok(0)
+    }
+
+    /// Removes the temporary files that writes interrupted by a crash left in the record type's
+    /// directory, once they're [STALE_TEMP_AGE] old. Deleted records' files are removed when they
+    /// are deleted, so there is nothing else to reclaim.
+    async fn reclaim_space(&self, rec_type: ArchiveRecordType) -> Result<(), ArchiveError> {
+        let dir = self.dir(&rec_type);
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => {
+                return Err(anyhow::Error::new(e)
+                    .context(format!("Listing {}", dir.display()))
+                    .into())
+            }
+        };
+
+        let mut removed = 0;
+        while let Some(entry) = entries.next_entry().await.context("Listing records")? {
+            let name = entry.file_name();
+            let temporary = name
+                .to_str()
+                .is_some_and(|name| name.starts_with('.') && name.ends_with(".tmp"));
+            if !temporary {
+                continue;
+            }
+            let modified = entry.metadata().await?.modified()?;
+            if modified.elapsed().unwrap_or_default() < STALE_TEMP_AGE {
+                continue;
+            }
+            fs::remove_file(entry.path())
+                .await
+                .with_context(|| format!("Removing {}", entry.path().display()))?;
+            removed += 1;
+        }
+
+        debug!(
+            "Removed {} stale temporary files from {}",
+            removed,
+            dir.display()
+        );
+        Ok(())
+    }
+
+    /// Aggregation pipelines are MongoDB specific.
+    async fn merge_into(
+        &self,
+        _source: ArchiveRecordType,
+        _pipeline: Vec<Document>,
+        _target: ArchiveRecordType,
+        _mode: MergeMode,
+    ) -> Result<u64, ArchiveError> {
+        Err(Unsupported {
+            operation: "merge_into",
+            reason: "aggregation pipelines are only supported by the MongoDB backend".to_string(),
+        }
+        .into())
+    }
+
+    /// Changes are only reported by the store that made them.
+    async fn watch(
+        &self,
+        _rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'static, Result<ArchiveEvent, ArchiveError>>, ArchiveError> {
+        Err(Unsupported {
+            operation: "watch",
+            reason: "change streams are only supported by the MongoDB backend".to_string(),
+        }
+        .into())
+    }
+
+    /// [Filter::All] counts the record files. Otherwise every record of the type is read and
+    /// filtered here.
+    async fn count(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        let files = self.list(&rec_type).await?;
+        if let Filter::All = filter {
+            return Ok(files.len() as u64);
+        }
+
+        Ok(self
+            .read_all(files)
+            .try_filter(|doc| futures::future::ready(filter.matches(doc)))
+            .try_fold(0, |count, _| futures::future::ready(Ok(count + 1)))
+            .await?)
+    }
+
+    /// [Filter::All] only lists the record files. Otherwise records of the type are read and
+    /// filtered here until one matches.
+    async fn exists(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<bool, ArchiveError> {
+        let files = self.list(&rec_type).await?;
+        if let Filter::All = filter {
+            return Ok(!files.is_empty());
+        }
+
+        Ok(self
+            .read_all(files)
+            .try_filter(|doc| futures::future::ready(filter.matches(doc)))
+            .try_next()
+            .await?
+            .is_some())
+    }
+
+    /// Counts the record files and totals their sizes.
+    async fn stats(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<ArchiveCollectionStats, ArchiveError> {
+        let files = self.list(&rec_type).await?;
+        let document_count = files.len() as u64;
+        let storage_bytes = files.iter().map(|file| file.metadata.len()).sum::<u64>();
+
+        Ok(ArchiveCollectionStats {
+            document_count,
+            storage_bytes,
+            avg_doc_bytes: storage_bytes.checked_div(document_count).unwrap_or(0),
+        })
+    }
+
+    /// Groups by the time each file was last written when grouping by archive time, which only
+    /// needs the directory to be listed. Grouping by a field reads every record.
+    async fn group_count(
+        &self,
+        rec_type: ArchiveRecordType,
+        group_by: GroupBy,
+    ) -> Result<Vec<(Bson, u64)>, ArchiveError> {
+        let files = self.list(&rec_type).await?;
+        let keys: Vec<Bson> = match group_by {
+            GroupBy::Field(field) => {
+                self.read_all(files)
+                    .map_ok(|doc| lookup(&doc, &field).cloned().unwrap_or(Bson::Null))
+                    .try_collect()
+                    .await?
+            }
+            GroupBy::ArchivedAt(granularity) => files
+                .iter()
+                .map(|file| {
+                    let modified = file.metadata.modified()?;
+                    Ok(Bson::String(
+                        granularity.bucket(bson::DateTime::from_system_time(modified)),
+                    ))
+                })
+                .collect::<std::io::Result<_>>()
+                .context("Reading record modification times")?,
+        };
+        Ok(tally(keys))
+    }
+
+    /// Aggregates the records matching the filter in process.
+    async fn aggregate(
+        &self,
+        rec_type: ArchiveRecordType,
+        spec: &AggregationSpec,
+    ) -> Result<Vec<Document>, ArchiveError> {
+        let docs = self.query::<Document>(rec_type, &spec.filter).await?;
+        Ok(aggregate(spec, docs))
+    }
+
+    /// There are no secondary indexes: queries scan every record, so non-unique indexes are
+    /// ignored.
+    async fn ensure_indexes(
+        &self,
+        _rec_type: ArchiveRecordType,
+        indexes: &[IndexSpec],
+    ) -> Result<(), ArchiveError> {
+        if indexes.iter().any(|index| index.unique) {
+            return Err(Unsupported {
+                operation: "ensure_indexes",
+                reason: "the filesystem has no secondary indexes".to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+/// A small backend-neutral query language for selecting archived records, used by
+/// [crate::ArchiveStore::query]. Filters are built from comparisons on (possibly dotted) field
+/// names of the stored records and combined with [Filter::and], [Filter::or] and `!`, e.g.
+/// `Filter::eq("address", "0xabc").and(Filter::gt("block", 100i64))`. Each backend translates
+/// filters into its own query form. Fields of compressed or chunked records can't be filtered on,
+/// as they aren't stored in a form the backend can inspect.
+use crate::envelope::{ARCHIVED_AT_FIELD, NODE_ID_FIELD, TAGS_FIELD};
+use crate::lifecycle::{LifecycleState, LIFECYCLE_FIELD};
+use crate::migration::VERSION_FIELD;
+use bson::{Bson, DateTime, Document};
+use core::fmt;
+use std::cmp::Ordering;
+use std::ops::{Bound, Not, RangeBounds};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Filter {
+    /// Matches every record
+    #[default]
+    All,
+    /// The field equals the value
+    Eq(String, Bson),
+    /// The field does not equal the value, or is missing
+    Ne(String, Bson),
+    /// The field is greater than the value
+    Gt(String, Bson),
+    /// The field is greater than or equal to the value
+    Gte(String, Bson),
+    /// The field is less than the value
+    Lt(String, Bson),
+    /// The field is less than or equal to the value
+    Lte(String, Bson),
+    /// The field equals one of the values
+    In(String, Vec<Bson>),
+    /// The field is present (`true`) or missing (`false`)
+    Exists(String, bool),
+    /// Every one of the filters matches
+    And(Vec<Filter>),
+    /// At least one of the filters matches
+    Or(Vec<Filter>),
+    /// The filter does not match
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    /// Matches records whose `field` equals `value`.
+    pub fn eq(field: &str, value: impl Into<Bson>) -> Filter {
+        Filter::Eq(field.to_string(), value.into())
+    }
+
+    /// Matches records whose `field` does not equal `value`, including those without the field.
+    pub fn ne(field: &str, value: impl Into<Bson>) -> Filter {
+        Filter::Ne(field.to_string(), value.into())
+    }
+
+    /// Matches records whose `field` is greater than `value`.
+    pub fn gt(field: &str, value: impl Into<Bson>) -> Filter {
+        Filter::Gt(field.to_string(), value.into())
+    }
+
+    /// Matches records whose `field` is greater than or equal to `value`.
+    pub fn gte(field: &str, value: impl Into<Bson>) -> Filter {
+        Filter::Gte(field.to_string(), value.into())
+    }
+
+    /// Matches records whose `field` is less than `value`.
+    pub fn lt(field: &str, value: impl Into<Bson>) -> Filter {
+        Filter::Lt(field.to_string(), value.into())
+    }
+
+    /// Matches records whose `field` is less than or equal to `value`.
+    pub fn lte(field: &str, value: impl Into<Bson>) -> Filter {
+        Filter::Lte(field.to_string(), value.into())
+    }
+
+    /// Matches records whose `field` lies within `range`, e.g. `Filter::range("block_height",
+    /// 100i64..200)`. An unbounded range matches every record.
+    pub fn range<V, R>(field: &str, range: R) -> Filter
+    where
+        V: Into<Bson> + Clone,
+        R: RangeBounds<V>,
+    {
+        let start = match range.start_bound() {
+            Bound::Included(value) => Filter::gte(field, value.clone()),
+            Bound::Excluded(value) => Filter::gt(field, value.clone()),
+            Bound::Unbounded => Filter::All,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(value) => Filter::lte(field, value.clone()),
+            Bound::Excluded(value) => Filter::lt(field, value.clone()),
+            Bound::Unbounded => Filter::All,
+        };
+        start.and(end)
+    }
+
+    /// Matches records whose `field` equals any of `values`.
+    pub fn is_in<V: Into<Bson>>(field: &str, values: impl IntoIterator<Item = V>) -> Filter {
+        Filter::In(
+            field.to_string(),
+            values.into_iter().map(Into::into).collect(),
+        )
+    }
+
+    /// Matches records that have `field`.
+    pub fn exists(field: &str) -> Filter {
+        Filter::Exists(field.to_string(), true)
+    }
+
+    /// Matches records archived at or after `time`. Records archived before provenance was
+    /// recorded never match. Like the other provenance filters, this works on compressed and
+    /// encrypted records too, see [crate::ArchiveEnvelope].
+    pub fn archived_since(time: DateTime) -> Filter {
+        Filter::gte(ARCHIVED_AT_FIELD, time.timestamp_millis())
+    }
+
+    /// Matches records archived before `time`. Records archived before provenance was recorded
+    /// never match.
+    pub fn archived_before(time: DateTime) -> Filter {
+        Filter::lt(ARCHIVED_AT_FIELD, time.timestamp_millis())
+    }
+
+    /// Matches records archived by the node with the given id, see
+    /// [crate::ArchiveStoreBuilder::node_id].
+    pub fn from_node(node_id: &str) -> Filter {
+        Filter::eq(NODE_ID_FIELD, node_id)
+    }
+
+    /// Matches records archived with `tag`, see [crate::ArchiveStore::with_tags].
+    pub fn tagged(tag: &str) -> Filter {
+        Filter::eq(&format!("{}.{}", TAGS_FIELD, tag), true)
+    }
+
+    /// Matches records written with the given schema version, before any migration on read.
+    pub fn schema_version(version: u32) -> Filter {
+        Filter::eq(VERSION_FIELD, version)
+    }
+
+    /// Matches records in the given lifecycle state. Only reads through
+    /// [crate::ArchiveStore::with_tombstones] return records that aren't archived.
+    pub fn lifecycle(state: LifecycleState) -> Filter {
+        match state.name() {
+            Some(name) => Filter::eq(LIFECYCLE_FIELD, name),
+            None => Filter::Exists(LIFECYCLE_FIELD.to_string(), false),
+        }
+    }
+
+    /// Matches records that match both this filter and `other`.
+    pub fn and(self, other: Filter) -> Filter {
+        match (self, other) {
+            (Filter::All, other) | (other, Filter::All) => other,
+            (Filter::And(mut filters), Filter::And(others)) => {
+                filters.extend(others);
+                Filter::And(filters)
+            }
+            (Filter::And(mut filters), other) => {
+                filters.push(other);
+                Filter::And(filters)
+            }
+            (this, other) => Filter::And(vec![this, other]),
+        }
+    }
+
+    /// Matches records that match either this filter or `other`.
+    pub fn or(self, other: Filter) -> Filter {
+        match (self, other) {
+            (Filter::All, _) | (_, Filter::All) => Filter::All,
+            (Filter::Or(mut filters), Filter::Or(others)) => {
+                filters.extend(others);
+                Filter::Or(filters)
+            }
+            (Filter::Or(mut filters), other) => {
+                filters.push(other);
+                Filter::Or(filters)
+            }
+            (this, other) => Filter::Or(vec![this, other]),
+        }
+    }
+
+    /// Whether a stored record matches the filter, as used by backends that can't filter records
+    /// themselves. Follows the same rules as the database backends: values are only compared with
+    /// values of the same type (all numbers being one type), and null equals a missing field.
+    pub fn matches(&self, doc: &Document) -> bool {
+        let eq = |field: &str, value: &Bson| match (lookup(doc, field), value) {
+            (None, Bson::Null) => true,
+            (Some(found), value) => compare(found, value) == Some(Ordering::Equal),
+            (None, _) => false,
+        };
+        let ord = |field: &str, value: &Bson, accept: &[Ordering]| match lookup(doc, field) {
+            Some(found) => compare(found, value).is_some_and(|o| accept.contains(&o)),
+            None => *value == Bson::Null && accept.contains(&Ordering::Equal),
+        };
+        match self {
+            Filter::All => true,
+            Filter::Eq(field, value) => eq(field, value),
+            Filter::Ne(field, value) => !eq(field, value),
+            Filter::Gt(field, value) => ord(field, value, &[Ordering::Greater]),
+            Filter::Gte(field, value) => ord(field, value, &[Ordering::Greater, Ordering::Equal]),
+            Filter::Lt(field, value) => ord(field, value, &[Ordering::Less]),
+            Filter::Lte(field, value) => ord(field, value, &[Ordering::Less, Ordering::Equal]),
+            Filter::In(field, values) => values.iter().any(|value| eq(field, value)),
+            Filter::Exists(field, exists) => lookup(doc, field).is_some() == *exists,
+            Filter::And(filters) => filters.iter().all(|filter| filter.matches(doc)),
+            Filter::Or(filters) => filters.iter().any(|filter| filter.matches(doc)),
+            Filter::Not(filter) => !filter.matches(doc),
+        }
+    }
+}
+
+/// The value of a (possibly dotted) field of a document, if it has one.
+pub(crate) fn lookup<'a>(doc: &'a Document, field: &str) -> Option<&'a Bson> {
+    let mut keys = field.split('.');
+    let mut value = doc.get(keys.next()?)?;
+    for key in keys {
+        value = match value {
+            Bson::Document(doc) => doc.get(key)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+/// The value of an integer, or a double holding one, as backends may read either back.
+pub(crate) fn integer(value: &Bson) -> Option<i64> {
+    match value {
+        Bson::Int32(n) => Some(i64::from(*n)),
+        Bson::Int64(n) => Some(*n),
+        Bson::Double(n) if n.fract() == 0.0 => Some(*n as i64),
+        _ => None,
+    }
+}
+
+/// Converts the id of a stored record to the string form returned to callers: the hex form of
+/// an ObjectId, or the id itself if it is a string.
+pub(crate) fn id_to_string(id: &Bson) -> String {
+    match id {
+        Bson::ObjectId(oid) => oid.to_hex(),
+        Bson::String(id) => id.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Orders two values of the same type, `None` meaning they can't be compared. Values of other
+/// types are only ever equal, when they are identical.
+pub(crate) fn compare(a: &Bson, b: &Bson) -> Option<Ordering> {
+    let number = |value: &Bson| match value {
+        Bson::Int32(n) => Some(*n as f64),
+        Bson::Int64(n) => Some(*n as f64),
+        Bson::Double(n) => Some(*n),
+        _ => None,
+    };
+    match (a, b) {
+        (Bson::Int32(a), Bson::Int32(b)) => Some(a.cmp(b)),
+        (Bson::Int64(a), Bson::Int64(b)) => Some(a.cmp(b)),
+        (Bson::String(a), Bson::String(b)) => Some(a.cmp(b)),
+        (Bson::Boolean(a), Bson::Boolean(b)) => Some(a.cmp(b)),
+        (Bson::DateTime(a), Bson::DateTime(b)) => Some(a.cmp(b)),
+        (Bson::ObjectId(a), Bson::ObjectId(b)) => Some(a.cmp(b)),
+        (a, b) => match (number(a), number(b)) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => (a == b).then_some(Ordering::Equal),
+        },
+    }
+}
+
+impl Not for Filter {
+    type Output = Filter;
+
+    fn not(self) -> Filter {
+        match self {
+            Filter::Not(filter) => *filter,
+            filter => Filter::Not(Box::new(filter)),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |f: &mut fmt::Formatter, filters: &[Filter], op: &str| {
+            write!(f, "(")?;
+            for (i, filter) in filters.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " {} ", op)?;
+                }
+                write!(f, "{}", filter)?;
+            }
+            write!(f, ")")
+        };
+        match self {
+            Filter::All => write!(f, "all"),
+            Filter::Eq(field, value) => write!(f, "{} = {}", field, value),
+            Filter::Ne(field, value) => write!(f, "{} != {}", field, value),
+            Filter::Gt(field, value) => write!(f, "{} > {}", field, value),
+            Filter::Gte(field, value) => write!(f, "{} >= {}", field, value),
+            Filter::Lt(field, value) => write!(f, "{} < {}", field, value),
+            Filter::Lte(field, value) => write!(f, "{} <= {}", field, value),
+            Filter::In(field, values) => write!(f, "{} in {}", field, Bson::Array(values.clone())),
+            Filter::Exists(field, true) => write!(f, "{} exists", field),
+            Filter::Exists(field, false) => write!(f, "{} missing", field),
+            Filter::And(filters) => join(f, filters, "and"),
+            Filter::Or(filters) => join(f, filters, "or"),
+            Filter::Not(filter) => write!(f, "not {}", filter),
+        }
+    }
+}
@@ -0,0 +1,352 @@
+/// A GraphQL endpoint for flexible history queries from frontends. Every record type can be
+/// filtered and paged through, with typed roots for the built-in record types and `records` for
+/// custom ones, e.g. `{ blocks(first: 10) { records { id data } nextToken } }`, or with
+/// `where: [{field: "block_height", op: GTE, value: 100}]` for the blocks from height 100.
+/// Records are schemaless, so their fields are returned whole as the `data` JSON scalar, in
+/// relaxed extended JSON, along with their provenance. Serve it with
+/// [ArchiveStore::serve_graphql], or execute requests against [ArchiveStore::graphql_schema] from
+/// a server of your own. Requires the `graphql` feature.
+use crate::filter::id_to_string;
+use crate::{
+    http, ArchiveEnvelope, ArchiveError, ArchiveRecordType, ArchiveStore, Filter, PageRequest,
+    BLOCK_HEIGHT_FIELD, RECEIPT_TX_HASH_FIELD,
+};
+use async_graphql::{
+    EmptyMutation, EmptySubscription, Enum, InputObject, Json, Object, Schema, SimpleObject, ID,
+};
+use bson::{Bson, DateTime, Document};
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::{Method, Request, Response, StatusCode};
+use serde_json::Value;
+use std::future::Future;
+use std::net::SocketAddr;
+
+/// Number of records in a page when the query doesn't say
+const DEFAULT_PAGE_SIZE: usize = 100;
+/// Largest number of records in a page
+const MAX_PAGE_SIZE: usize = 1_000;
+/// Deepest nesting of fields a query may select
+const MAX_QUERY_DEPTH: usize = 8;
+
+/// The GraphQL schema of an archive, see [ArchiveStore::graphql_schema].
+pub type ArchiveSchema = Schema<ArchiveQuery, EmptyMutation, EmptySubscription>;
+
+/// The root of GraphQL queries of an archive.
+pub struct ArchiveQuery {
+    store: ArchiveStore,
+}
+
+/// An archived record.
+#[derive(SimpleObject)]
+struct ArchivedRecord {
+    /// The id of the record
+    id: ID,
+    /// The record, as relaxed extended JSON
+    data: Json<Value>,
+    /// When the record was archived, in milliseconds since the epoch
+    archived_at: Option<i64>,
+    /// The schema version the record was written with
+    schema_version: u32,
+    /// The node that archived the record
+    node_id: Option<String>,
+    /// Tags the record was archived with
+    tags: Vec<String>,
+}
+
+/// A page of records, in id order.
+#[derive(SimpleObject)]
+struct RecordPage {
+    records: Vec<ArchivedRecord>,
+    /// Token to pass as `after` to read the next page, or `null` if this is the last page
+    next_token: Option<String>,
+}
+
+/// How a condition compares a field with its value.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+/// A comparison of a (possibly dotted) field of the records with a value given as extended JSON.
+#[derive(InputObject)]
+struct Condition {
+    field: String,
+    op: Operator,
+    value: Json<Value>,
+}
+
+impl ArchiveStore {
+    /// The GraphQL schema of this store's records, to execute requests against.
+    pub fn graphql_schema(&self) -> ArchiveSchema {
+        let query = ArchiveQuery {
+            store: self.clone(),
+        };
+        Schema::build(query, EmptyMutation, EmptySubscription)
+            .limit_depth(MAX_QUERY_DEPTH)
+            .finish()
+    }
+
+    /// Serves this store's GraphQL endpoint over HTTP on `addr`, without TLS, until `shutdown`
+    /// completes, e.g. `tokio::signal::ctrl_c()`. Queries are POSTed as JSON, as
+    /// `{"query": ..., "variables": ...}`.
+    pub async fn serve_graphql(
+        &self,
+        addr: SocketAddr,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), ArchiveError> {
+        let schema = self.graphql_schema();
+        http::serve(addr, "GraphQL", shutdown, move |request| {
+            let schema = schema.clone();
+            async move { respond(&schema, request).await }
+        })
+        .await
+    }
+
+    /// Reads a page of the records of [ArchiveRecordType] matching every condition.
+    /// Unfiltered pages are read from the backend a page at a time, while filtered pages are
+    /// paged through every matching record.
+    async fn graphql_page(
+        &self,
+        rec_type: ArchiveRecordType,
+        conditions: Option<Vec<Condition>>,
+        first: Option<usize>,
+        after: Option<String>,
+    ) -> Result<RecordPage, ArchiveError> {
+        let limit = first.unwrap_or(DEFAULT_PAGE_SIZE);
+        if limit == 0 || limit > MAX_PAGE_SIZE {
+            return Err(ArchiveError::invalid_input(format!(
+                "first must be between 1 and {}",
+                MAX_PAGE_SIZE
+            )));
+        }
+        let filter = conditions
+            .unwrap_or_default()
+            .into_iter()
+            .try_fold(Filter::All, |filter, condition| {
+                Ok::<_, ArchiveError>(filter.and(condition_filter(condition)?))
+            })?;
+
+        if filter == Filter::All {
+            let request = PageRequest {
+                limit,
+                after_token: after,
+            };
+            let page = self
+                .find_envelope_page::<Document>(rec_type, request)
+                .await?;
+            return Ok(RecordPage {
+                records: page.items.into_iter().map(archived_record).collect(),
+                next_token: page.next_token,
+            });
+        }
+
+        // Filtered pages are ordered by id like unfiltered ones, the token being the last id.
+        let mut records: Vec<ArchivedRecord> = self
+            .query_envelopes::<Document>(rec_type, filter)
+            .await?
+            .into_iter()
+            .map(archived_record)
+            .filter(|rec| {
+                after
+                    .as_ref()
+                    .is_none_or(|after| rec.id.as_str() > after.as_str())
+            })
+            .collect();
+        records.sort_by(|a, b| a.id.cmp(&b.id));
+        let next_token = if records.len() > limit {
+            records.truncate(limit);
+            records.last().map(|rec| rec.id.to_string())
+        } else {
+            None
+        };
+        Ok(RecordPage {
+            records,
+            next_token,
+        })
+    }
+
+    /// The first record of [ArchiveRecordType] whose `field` equals `value`.
+    async fn graphql_find_by(
+        &self,
+        rec_type: ArchiveRecordType,
+        field: &str,
+        value: impl Into<Bson>,
+    ) -> Result<Option<ArchivedRecord>, ArchiveError> {
+        let envelopes = self
+            .query_envelopes::<Document>(rec_type, Filter::eq(field, value))
+            .await?;
+        Ok(envelopes.into_iter().next().map(archived_record))
+    }
+}
+
+#[Object(name = "Query")]
+impl ArchiveQuery {
+    /// The latest archived state of the account with the given owner address, or the latest
+    /// archived before `before`, in milliseconds since the epoch.
+    async fn account(
+        &self,
+        address: String,
+        before: Option<i64>,
+    ) -> async_graphql::Result<Option<ArchivedRecord>> {
+        let state = self
+            .store
+            .find_account_state(&address, before.map(DateTime::from_millis))
+            .await?;
+        Ok(state.map(archived_record))
+    }
+
+    /// Archived account states.
+    async fn accounts(
+        &self,
+        #[graphql(name = "where")] conditions: Option<Vec<Condition>>,
+        first: Option<usize>,
+        after: Option<String>,
+    ) -> async_graphql::Result<RecordPage> {
+        let page = self
+            .store
+            .graphql_page(ArchiveRecordType::Account, conditions, first, after);
+        Ok(page.await?)
+    }
+
+    /// The transaction batch with the given id.
+    async fn transaction_batch(&self, id: ID) -> async_graphql::Result<Option<ArchivedRecord>> {
+        let envelope = self
+            .store
+            .find_envelope_by_id::<Document>(ArchiveRecordType::TransactionBatch, &id)
+            .await?;
+        Ok(envelope.map(archived_record))
+    }
+
+    /// Archived transaction batches.
+    async fn transaction_batches(
+        &self,
+        #[graphql(name = "where")] conditions: Option<Vec<Condition>>,
+        first: Option<usize>,
+        after: Option<String>,
+    ) -> async_graphql::Result<RecordPage> {
+        let page = self.store.graphql_page(
+            ArchiveRecordType::TransactionBatch,
+            conditions,
+            first,
+            after,
+        );
+        Ok(page.await?)
+    }
+
+    /// The block at the given height.
+    async fn block(&self, height: i64) -> async_graphql::Result<Option<ArchivedRecord>> {
+        let block = self
+            .store
+            .graphql_find_by(ArchiveRecordType::Block, BLOCK_HEIGHT_FIELD, height)
+            .await?;
+        Ok(block)
+    }
+
+    /// Archived blocks.
+    async fn blocks(
+        &self,
+        #[graphql(name = "where")] conditions: Option<Vec<Condition>>,
+        first: Option<usize>,
+        after: Option<String>,
+    ) -> async_graphql::Result<RecordPage> {
+        let page = self
+            .store
+            .graphql_page(ArchiveRecordType::Block, conditions, first, after);
+        Ok(page.await?)
+    }
+
+    /// The receipt of the transaction with the given hash.
+    async fn receipt(&self, tx_hash: String) -> async_graphql::Result<Option<ArchivedRecord>> {
+        let receipt = self
+            .store
+            .graphql_find_by(ArchiveRecordType::Receipt, RECEIPT_TX_HASH_FIELD, tx_hash)
+            .await?;
+        Ok(receipt)
+    }
+
+    /// Archived receipts.
+    async fn receipts(
+        &self,
+        #[graphql(name = "where")] conditions: Option<Vec<Condition>>,
+        first: Option<usize>,
+        after: Option<String>,
+    ) -> async_graphql::Result<RecordPage> {
+        let page = self
+            .store
+            .graphql_page(ArchiveRecordType::Receipt, conditions, first, after);
+        Ok(page.await?)
+    }
+
+    /// Archived records of any record type, named `account`, `transaction_batch`, `block`,
+    /// `receipt` or the name of a custom record type.
+    async fn records(
+        &self,
+        record_type: String,
+        #[graphql(name = "where")] conditions: Option<Vec<Condition>>,
+        first: Option<usize>,
+        after: Option<String>,
+    ) -> async_graphql::Result<RecordPage> {
+        let rec_type = ArchiveRecordType::from_name(&record_type);
+        let page = self.store.graphql_page(rec_type, conditions, first, after);
+        Ok(page.await?)
+    }
+}
+
+/// Answers an HTTP request to the GraphQL endpoint.
+async fn respond(schema: &ArchiveSchema, request: Request<Incoming>) -> Response<Full<Bytes>> {
+    if request.method() != Method::POST {
+        return http::response(StatusCode::METHOD_NOT_ALLOWED, Bytes::new());
+    }
+    let body = match http::read_body(request).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let request: async_graphql::Request = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => return http::response(StatusCode::BAD_REQUEST, e.to_string().into()),
+    };
+    let response = schema.execute(request).await;
+    match serde_json::to_string(&response) {
+        Ok(body) => http::ok("application/json", body),
+        Err(e) => http::response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string().into()),
+    }
+}
+
+/// The filter of a condition.
+fn condition_filter(condition: Condition) -> Result<Filter, ArchiveError> {
+    let value = Bson::try_from(condition.value.0).map_err(|e| {
+        ArchiveError::invalid_input(format!("Invalid value of '{}': {}", condition.field, e))
+    })?;
+    let field = condition.field.as_str();
+    Ok(match condition.op {
+        Operator::Eq => Filter::eq(field, value),
+        Operator::Ne => Filter::ne(field, value),
+        Operator::Gt => Filter::gt(field, value),
+        Operator::Gte => Filter::gte(field, value),
+        Operator::Lt => Filter::lt(field, value),
+        Operator::Lte => Filter::lte(field, value),
+    })
+}
+
+/// A record as returned to clients.
+fn archived_record(envelope: ArchiveEnvelope<Document>) -> ArchivedRecord {
+    let id = envelope
+        .record
+        .get("_id")
+        .map(id_to_string)
+        .unwrap_or_default();
+    ArchivedRecord {
+        id: ID(id),
+        data: Json(Bson::Document(envelope.record).into_relaxed_extjson()),
+        archived_at: envelope.archived_at.map(|time| time.timestamp_millis()),
+        schema_version: envelope.schema_version,
+        node_id: envelope.node_id,
+        tags: envelope.tags,
+    }
+}
@@ -0,0 +1,89 @@
+/// A minimal HTTP/1 server for the JSON-RPC and GraphQL endpoints, which answer requests with a
+/// handler called on a task per connection.
+use crate::ArchiveError;
+use anyhow::Context;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use log::{debug, warn};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+/// Largest request body accepted, in bytes
+const MAX_REQUEST_BYTES: usize = 1024 * 1024;
+
+/// Serves HTTP requests on `addr` with `handler` until `shutdown` completes. Connections already
+/// open when it completes are served until they close. `name` names the endpoint in logs.
+pub(crate) async fn serve<F, Fut>(
+    addr: SocketAddr,
+    name: &'static str,
+    shutdown: impl Future<Output = ()>,
+    handler: F,
+) -> Result<(), ArchiveError>
+where
+    F: Fn(Request<Incoming>) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Response<Full<Bytes>>> + Send + 'static,
+{
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Listening for {} requests on {}", name, addr))?;
+    tokio::pin!(shutdown);
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("Failed to accept {} connection: {}", name, e);
+                    continue;
+                }
+            },
+            _ = &mut shutdown => return Ok(()),
+        };
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |request| {
+                let response = handler(request);
+                async move { Ok::<_, Infallible>(response.await) }
+            });
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("{} connection failed: {}", name, e);
+            }
+        });
+    }
+}
+
+/// Reads the body of a request, or the response to answer with if it's too large.
+pub(crate) async fn read_body(request: Request<Incoming>) -> Result<Bytes, Response<Full<Bytes>>> {
+    match Limited::new(request.into_body(), MAX_REQUEST_BYTES)
+        .collect()
+        .await
+    {
+        Ok(body) => Ok(body.to_bytes()),
+        Err(_) => Err(response(StatusCode::PAYLOAD_TOO_LARGE, Bytes::new())),
+    }
+}
+
+/// A response with the given status and body.
+pub(crate) fn response(status: StatusCode, body: Bytes) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(body));
+    *response.status_mut() = status;
+    response
+}
+
+/// A successful response with a body of the given content type.
+pub(crate) fn ok(content_type: &'static str, body: String) -> Response<Full<Bytes>> {
+    let mut response = response(StatusCode::OK, body.into());
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    response
+}
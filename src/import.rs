@@ -0,0 +1,400 @@
+/// Imports of records from dumps written by [ArchiveStore::export], e.g. to seed a new archive
+/// node from a snapshot of an existing one. Records are read from the dump as they are imported
+/// and written to the store in batches.
+use crate::{filter, ArchiveError, ArchiveRecordType, ArchiveStore, ExportFormat};
+use anyhow::Context;
+use bson::{oid::ObjectId, Bson, Document};
+use core::fmt;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+/// The file format records are imported from by [ArchiveStore::import], which reads the same
+/// formats records are exported in.
+pub type ImportFormat = ExportFormat;
+
+/// How far an import has got, reported after every batch and returned once it completes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportProgress {
+    /// Records read from the dump
+    pub read: u64,
+    /// Records written to the store
+    pub imported: u64,
+    /// Records skipped because a record with the same id was already archived
+    pub duplicates: u64,
+}
+
+/// How records are imported by [ArchiveStore::import_with_options].
+#[derive(Clone)]
+pub struct ImportOptions {
+    /// Number of records written to the store at once, as by [ArchiveStore::create_many].
+    /// Defaults to 1000.
+    pub batch_size: usize,
+    /// Whether records with the same id as one already archived, or earlier in the dump, are
+    /// skipped rather than failing the import, so an interrupted import can simply be run again.
+    /// Records without an id are always imported. Enabled by default.
+    pub skip_duplicates: bool,
+    /// Called with the progress of the import after every batch
+    pub progress: Option<Arc<ProgressFn>>,
+}
+
+/// A callback reporting the progress of an import
+type ProgressFn = dyn Fn(&ImportProgress) + Send + Sync;
+
+impl ImportOptions {
+    /// Calls `progress` with the progress of the import after every batch.
+    pub fn on_progress<F: Fn(&ImportProgress) + Send + Sync + 'static>(
+        mut self,
+        progress: F,
+    ) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            batch_size: 1000,
+            skip_duplicates: true,
+            progress: None,
+        }
+    }
+}
+
+impl fmt::Debug for ImportOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ImportOptions")
+            .field("batch_size", &self.batch_size)
+            .field("skip_duplicates", &self.skip_duplicates)
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
+}
+
+impl ArchiveStore {
+    /// Archives the records read from `reader`, a dump in the given [ImportFormat], as records of
+    /// [ArchiveRecordType] with the default [ImportOptions], returning how many were read,
+    /// imported and skipped as duplicates.
+    pub async fn import<R>(
+        &self,
+        rec_type: ArchiveRecordType,
+        format: ImportFormat,
+        reader: R,
+    ) -> Result<ImportProgress, ArchiveError>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        self.import_with_options(rec_type, format, reader, ImportOptions::default())
+            .await
+    }
+
+    /// Archives the records read from `reader`, a dump in the given [ImportFormat], as records of
+    /// [ArchiveRecordType], returning how many were read, imported and skipped as duplicates.
+    ///
+    /// Records are archived as new records, keeping their ids and the provenance exported with
+    /// them: ids exported as hex strings are read back as object ids. CSV doesn't record the types
+    /// of values, so cells holding valid JSON are read as that JSON value and other cells as
+    /// strings. Text values of JSON objects or arrays in Parquet string columns, as nested values
+    /// are exported, are read as documents or arrays. Empty cells and nulls are left out of the
+    /// record. Parquet files keep their metadata at the end, so they are read into memory before
+    /// their records are imported. An invalid dump fails the import at the first invalid record,
+    /// after the batches before it have been written.
+    pub async fn import_with_options<R>(
+        &self,
+        rec_type: ArchiveRecordType,
+        format: ImportFormat,
+        reader: R,
+        options: ImportOptions,
+    ) -> Result<ImportProgress, ArchiveError>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        if options.batch_size == 0 {
+            return Err(ArchiveError::invalid_input(
+                "Import batch size must be greater than zero",
+            ));
+        }
+        let mut importer = Importer {
+            store: self,
+            rec_type,
+            options,
+            batch: Vec::new(),
+            progress: ImportProgress::default(),
+        };
+        match format {
+            ExportFormat::JsonLines => import_json_lines(&mut importer, reader).await?,
+            ExportFormat::Csv => import_csv(&mut importer, reader).await?,
+            #[cfg(feature = "parquet")]
+            ExportFormat::Parquet => parquet_import::import_parquet(&mut importer, reader).await?,
+        }
+        importer.flush().await?;
+        Ok(importer.progress)
+    }
+}
+
+/// Collects the records read from a dump into batches and writes them to the store.
+struct Importer<'a> {
+    store: &'a ArchiveStore,
+    rec_type: ArchiveRecordType,
+    options: ImportOptions,
+    /// Records read but not yet written
+    batch: Vec<Document>,
+    progress: ImportProgress,
+}
+
+impl Importer<'_> {
+    /// Adds a record read from the dump, writing the batch once it is full.
+    async fn push(&mut self, mut rec: Document) -> Result<(), ArchiveError> {
+        // Object ids are exported as hex strings by the tabular formats and SQL backends.
+        if let Some(Bson::String(id)) = rec.get("_id") {
+            if let Ok(oid) = ObjectId::parse_str(id) {
+                rec.insert("_id", oid);
+            }
+        }
+        self.progress.read += 1;
+        self.batch.push(rec);
+        if self.batch.len() >= self.options.batch_size {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Writes the batch to the store, skipping duplicates if enabled, and reports progress.
+    async fn flush(&mut self) -> Result<(), ArchiveError> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let mut batch = std::mem::take(&mut self.batch);
+        if self.options.skip_duplicates {
+            let mut seen = HashSet::new();
+            let mut new = Vec::with_capacity(batch.len());
+            for rec in batch {
+                if let Some(id) = rec.get("_id").map(filter::id_to_string) {
+                    let duplicate = !seen.insert(id.clone())
+                        || self
+                            .store
+                            .with_tombstones()
+                            .find_by_id::<Document>(self.rec_type.clone(), &id)
+                            .await?
+                            .is_some();
+                    if duplicate {
+                        self.progress.duplicates += 1;
+                        continue;
+                    }
+                }
+                new.push(rec);
+            }
+            batch = new;
+        }
+        if !batch.is_empty() {
+            let ids = self.store.create_many(self.rec_type.clone(), batch).await?;
+            self.progress.imported += ids.len() as u64;
+        }
+        if let Some(progress) = &self.options.progress {
+            progress(&self.progress);
+        }
+        Ok(())
+    }
+}
+
+/// Reads a record from each non-empty line of relaxed or canonical extended JSON.
+async fn import_json_lines<R: AsyncRead + Unpin>(
+    importer: &mut Importer<'_>,
+    reader: R,
+) -> Result<(), ArchiveError> {
+    let mut lines = BufReader::new(reader).lines();
+    let mut number = 0;
+    while let Some(line) = lines.next_line().await.context("Failed to read dump")? {
+        number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let rec = serde_json::from_str::<serde_json::Value>(&line)
+            .ok()
+            .and_then(|json| Bson::try_from(json).ok());
+        match rec {
+            Some(Bson::Document(rec)) => importer.push(rec).await?,
+            _ => {
+                return Err(ArchiveError::invalid_input(format!(
+                    "Line {} of the dump is not a JSON object",
+                    number
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads a header row of field names, then a record from each row.
+async fn import_csv<R: AsyncRead + Unpin>(
+    importer: &mut Importer<'_>,
+    reader: R,
+) -> Result<(), ArchiveError> {
+    let mut reader = BufReader::new(reader);
+    let mut columns: Option<Vec<String>> = None;
+    let mut row = String::new();
+    let mut number = 0;
+    loop {
+        // A quoted cell may span lines, so read until the quotes are balanced.
+        row.clear();
+        loop {
+            let read = reader
+                .read_line(&mut row)
+                .await
+                .context("Failed to read dump")?;
+            number += 1;
+            if read == 0 || row.matches('"').count().is_multiple_of(2) {
+                break;
+            }
+        }
+        if row.is_empty() {
+            return Ok(());
+        }
+        let line = row.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            continue;
+        }
+        let cells = csv_cells(line).ok_or_else(|| {
+            ArchiveError::invalid_input(format!("Invalid CSV row ending on line {}", number))
+        })?;
+        let columns = match &columns {
+            Some(columns) => columns,
+            None => {
+                columns = Some(cells);
+                continue;
+            }
+        };
+
+        let mut rec = Document::new();
+        for (column, cell) in columns.iter().zip(cells) {
+            if cell.is_empty() {
+                continue;
+            }
+            let value = serde_json::from_str::<serde_json::Value>(&cell)
+                .ok()
+                .and_then(|json| Bson::try_from(json).ok())
+                .unwrap_or(Bson::String(cell));
+            rec.insert(column, value);
+        }
+        importer.push(rec).await?;
+    }
+}
+
+/// Splits a CSV row into its cells, unquoting quoted cells. Returns `None` if a quoted cell is
+/// malformed.
+fn csv_cells(row: &str) -> Option<Vec<String>> {
+    let mut cells = Vec::new();
+    let mut chars = row.chars().peekable();
+    loop {
+        let mut cell = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next()? {
+                    '"' if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        cell.push('"');
+                    }
+                    '"' => break,
+                    c => cell.push(c),
+                }
+            }
+            if !matches!(chars.peek(), None | Some(',')) {
+                return None;
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ',') {
+                cell.push(c);
+            }
+        }
+        cells.push(cell);
+        if chars.next().is_none() {
+            return Some(cells);
+        }
+    }
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_import {
+    use super::Importer;
+    use crate::ArchiveError;
+    use anyhow::Context;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float64Type, Int32Type, Int64Type};
+    use arrow_array::{Array, RecordBatch};
+    use arrow_schema::DataType;
+    use bson::{Bson, Document};
+    use futures::TryStreamExt;
+    use parquet::arrow::ParquetRecordBatchStreamBuilder;
+    use std::io::Cursor;
+    use tokio::io::{AsyncRead, AsyncReadExt};
+
+    /// Reads a record from each row of a Parquet file, with a field per non-null column.
+    pub(super) async fn import_parquet<R: AsyncRead + Unpin>(
+        importer: &mut Importer<'_>,
+        mut reader: R,
+    ) -> Result<(), ArchiveError> {
+        let mut file = Vec::new();
+        reader
+            .read_to_end(&mut file)
+            .await
+            .context("Failed to read dump")?;
+        let mut batches = ParquetRecordBatchStreamBuilder::new(Cursor::new(file))
+            .await
+            .map_err(|e| ArchiveError::invalid_input(format!("Invalid Parquet file: {}", e)))?
+            .build()
+            .context("Failed to read Parquet file")?;
+
+        while let Some(batch) = batches
+            .try_next()
+            .await
+            .context("Failed to read Parquet file")?
+        {
+            for row in 0..batch.num_rows() {
+                importer.push(record(&batch, row)?).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The record held in a row of a batch.
+    fn record(batch: &RecordBatch, row: usize) -> Result<Document, ArchiveError> {
+        let mut rec = Document::new();
+        for (field, column) in batch.schema().fields().iter().zip(batch.columns()) {
+            if column.is_null(row) {
+                continue;
+            }
+            let value = match column.data_type() {
+                DataType::Boolean => Bson::Boolean(column.as_boolean().value(row)),
+                DataType::Int32 => Bson::Int32(column.as_primitive::<Int32Type>().value(row)),
+                DataType::Int64 => Bson::Int64(column.as_primitive::<Int64Type>().value(row)),
+                DataType::Float64 => Bson::Double(column.as_primitive::<Float64Type>().value(row)),
+                DataType::Utf8 => text_value(column.as_string::<i32>().value(row)),
+                DataType::LargeUtf8 => text_value(column.as_string::<i64>().value(row)),
+                other => {
+                    return Err(ArchiveError::invalid_input(format!(
+                        "Parquet column '{}' has unsupported type {}",
+                        field.name(),
+                        other
+                    )))
+                }
+            };
+            rec.insert(field.name(), value);
+        }
+        Ok(rec)
+    }
+
+    /// A text value, read back as a document or array if it holds one as JSON.
+    fn text_value(text: &str) -> Bson {
+        if text.starts_with(['{', '[']) {
+            if let Some(value) = serde_json::from_str::<serde_json::Value>(text)
+                .ok()
+                .and_then(|json| Bson::try_from(json).ok())
+            {
+                return value;
+            }
+        }
+        Bson::String(text.to_string())
+    }
+}
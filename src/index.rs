@@ -0,0 +1,83 @@
+/// Secondary indexes over archived records, declared with [crate::ArchiveStore::ensure_indexes]
+/// so that queries filtering on other fields than a record type's built-in indexed field, e.g.
+/// `transaction_data.block_height`, don't scan every record.
+use core::fmt;
+
+/// A secondary index over one or more (possibly dotted) fields of the stored records. Fields of
+/// compressed or encrypted records are hidden from the backend, so can't be indexed, but the
+/// provenance fields, e.g. `_archived_at`, can.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexSpec {
+    /// The indexed fields, in order
+    pub fields: Vec<String>,
+    /// Whether records must have distinct values of the indexed fields
+    pub unique: bool,
+}
+
+impl IndexSpec {
+    /// An ascending, non-unique index over a single field.
+    pub fn new(field: &str) -> Self {
+        IndexSpec::compound(&[field])
+    }
+
+    /// An ascending, non-unique index over several fields, in order.
+    pub fn compound(fields: &[&str]) -> Self {
+        IndexSpec {
+            fields: fields.iter().map(|field| field.to_string()).collect(),
+            unique: false,
+        }
+    }
+
+    /// Rejects records sharing the same values of the indexed fields.
+    pub fn unique(mut self) -> Self {
+        self.unique = true;
+        self
+    }
+
+    /// Name of the index, derived from its fields so that declaring it again finds the existing
+    /// index, e.g. `accounts_address_idx`. Only SQL backends name their indexes.
+    #[cfg(any(feature = "postgres", feature = "sqlite"))]
+    pub(crate) fn name(&self, prefix: &str) -> String {
+        let fields: Vec<String> = self.fields.iter().map(|f| f.replace('.', "_")).collect();
+        format!(
+            "{}_{}_{}idx",
+            prefix,
+            fields.join("_"),
+            if self.unique { "unique_" } else { "" }
+        )
+    }
+
+    /// Checks that the index has fields and that they can be safely used in the backends' index
+    /// definitions, describing the problem if not.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.fields.is_empty() {
+            return Err("Invalid index: must have at least one field".to_string());
+        }
+        for field in &self.fields {
+            let valid = field.split('.').all(|key| {
+                !key.is_empty()
+                    && key
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            });
+            if !valid {
+                return Err(format!(
+                    "Invalid index field '{}': keys must be non-empty and contain only ASCII \
+                    letters, digits, '_' or '-'",
+                    field
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for IndexSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({})", self.fields.join(", "))?;
+        if self.unique {
+            write!(f, " unique")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,793 @@
+/// An implementation of an archive datastore that stores each record as a content-addressed
+/// object, enabled with the `ipfs` feature, so that archived history can be pinned, shared and
+/// verified by anyone holding its ids. Records are stored as BSON in a [ContentAddressedStore],
+/// by default the IPFS node whose HTTP RPC API is at the URI, e.g. `http://127.0.0.1:5001`, and
+/// each record's id is the CID (content identifier) of its content.
+///
+/// Content stores can't list what they hold, so the CIDs of each [ArchiveRecordType] are kept in
+/// a small local index: a JSON lines file per record type in the directory given by
+/// [crate::ArchiveBackends::Ipfs], named after the [ACCOUNT_INDEX] and [TRANSACTION_INDEX]
+/// constants or the custom record type's name. The index lists records in the order they were
+/// archived, along with their size and when they were archived, so counts of every record and
+/// statistics never touch the content store.
+///
+/// As ids are derived from content, archiving identical content again returns the id of the
+/// record already stored, any `_id` a record is given is replaced by its CID and records can't be
+/// updated in place. Deleting a record removes it from the index and unpins its content, even if
+/// another record type holds identical content. Queries and counts of matching records fetch
+/// every record of the type and filter them here. Operations that need transactions or take
+/// MongoDB specific arguments fail with [Unsupported]. Records are never chunked.
+use crate::filter::lookup;
+use crate::stats::{aggregate, tally};
+use crate::{
+    AggregationSpec, ArchiveBackend, ArchiveCollectionStats, ArchiveError, ArchiveEvent,
+    ArchiveRecordType, Filter, GroupBy, IndexSpec, MergeMode, Page, PageRequest, Unsupported,
+};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use bson::{doc, Bson, Document};
+use core::fmt;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use log::debug;
+use serde::{de::DeserializeOwned, Serialize};
+use std::borrow::Borrow;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+/// Index of account data
+const ACCOUNT_INDEX: &str = "accounts";
+/// Index of transaction data
+const TRANSACTION_INDEX: &str = "transaction_data";
+/// Index of blocks
+const BLOCK_INDEX: &str = "blocks";
+/// Index of transaction receipts
+const RECEIPT_INDEX: &str = "receipts";
+/// Number of records fetched at once
+const CONCURRENCY: usize = 16;
+
+/// A content-addressed store the IPFS backend keeps records in, addressing each by an identifier
+/// derived from its content. Implement it to archive to another store than an IPFS node, and set
+/// it with [crate::ArchiveStoreBuilder::content_store].
+#[async_trait]
+pub trait ContentAddressedStore: fmt::Debug + Send + Sync {
+    /// Stores `content` until it's removed, returning its content identifier. Storing identical
+    /// content again returns the same identifier.
+    async fn put(&self, content: Vec<u8>) -> Result<String, ArchiveError>;
+
+    /// The content stored under `cid`, or `None` if the store doesn't hold it.
+    async fn get(&self, cid: &str) -> Result<Option<Vec<u8>>, ArchiveError>;
+
+    /// Stops keeping the content stored under `cid`, so the store may discard it. Does nothing if
+    /// the store doesn't hold it.
+    async fn remove(&self, cid: &str) -> Result<(), ArchiveError>;
+}
+
+/// An IPFS node's HTTP RPC API, e.g. Kubo's. Content is added with CIDv1 and pinned, so the node
+/// keeps it until it's removed, and is only read from the node itself, never fetched from the
+/// network.
+#[derive(Debug)]
+pub(crate) struct IpfsHttpStore {
+    /// Base URL of the API's commands
+    api: String,
+    client: reqwest::Client,
+}
+
+/// The body of a failed RPC API call.
+#[derive(serde_derive::Deserialize)]
+struct ApiError {
+    #[serde(rename = "Message")]
+    message: String,
+}
+
+/// The body of a successful `add` call.
+#[derive(serde_derive::Deserialize)]
+struct Added {
+    #[serde(rename = "Hash")]
+    hash: String,
+}
+
+impl IpfsHttpStore {
+    pub(crate) fn new(uri: &str) -> Self {
+        IpfsHttpStore {
+            api: format!("{}/api/v0", uri.trim_end_matches('/')),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Calls an RPC API command, all of which are POSTed, returning the response if it succeeded
+    /// or the message it failed with otherwise.
+    async fn call(
+        &self,
+        command: &str,
+        query: &[(&str, &str)],
+        form: Option<reqwest::multipart::Form>,
+    ) -> Result<std::result::Result<reqwest::Response, String>> {
+        let mut request = self
+            .client
+            .post(format!("{}/{}", self.api, command))
+            .query(query);
+        if let Some(form) = form {
+            request = request.multipart(form);
+        }
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Calling IPFS {}", command))?;
+        if response.status().is_success() {
+            return Ok(Ok(response));
+        }
+        let status = response.status();
+        let message = match response.json::<ApiError>().await {
+            Ok(error) => error.message,
+            Err(_) => status.to_string(),
+        };
+        Ok(Err(message))
+    }
+}
+
+#[async_trait]
+impl ContentAddressedStore for IpfsHttpStore {
+    async fn put(&self, content: Vec<u8>) -> Result<String, ArchiveError> {
+        let form =
+            reqwest::multipart::Form::new().part("file", reqwest::multipart::Part::bytes(content));
+        let query = [("pin", "true"), ("cid-version", "1"), ("quieter", "true")];
+        match self.call("add", &query, Some(form)).await? {
+            Ok(response) => {
+                let added: Added = response
+                    .json()
+                    .await
+                    .context("Invalid response from IPFS add")?;
+                Ok(added.hash)
+            }
+            Err(message) => Err(anyhow!("Adding content to IPFS failed: {}", message).into()),
+        }
+    }
+
+    async fn get(&self, cid: &str) -> Result<Option<Vec<u8>>, ArchiveError> {
+        match self
+            .call("cat", &[("arg", cid), ("offline", "true")], None)
+            .await?
+        {
+            Ok(response) => {
+                let content = response
+                    .bytes()
+                    .await
+                    .with_context(|| format!("Reading {} from IPFS", cid))?;
+                Ok(Some(content.to_vec()))
+            }
+            Err(message) if message.contains("not found") => Ok(None),
+            Err(message) => Err(anyhow!("Reading {} from IPFS failed: {}", cid, message).into()),
+        }
+    }
+
+    async fn remove(&self, cid: &str) -> Result<(), ArchiveError> {
+        match self.call("pin/rm", &[("arg", cid)], None).await? {
+            Ok(_) => Ok(()),
+            Err(message) if message.contains("not pinned") => Ok(()),
+            Err(message) => Err(anyhow!("Unpinning {} from IPFS failed: {}", cid, message).into()),
+        }
+    }
+}
+
+/// A record listed in the index of its record type.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+struct IndexEntry {
+    cid: String,
+    /// Size of the record's content
+    bytes: u64,
+    /// When the record was archived, in milliseconds since the epoch
+    archived_at: i64,
+}
+
+/// Reads an index file. A missing file lists no records.
+async fn read_index(path: &Path) -> Result<Vec<IndexEntry>> {
+    let contents = match fs::read_to_string(path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Reading CID index {}", path.display())),
+    };
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("Invalid entry on line {} of {}", i + 1, path.display()))
+        })
+        .collect()
+}
+
+/// A line of an index file, ending in a newline.
+fn index_line(entry: &IndexEntry) -> Result<String> {
+    let mut line = serde_json::to_string(entry).context("Serialising CID index entry")?;
+    line.push('\n');
+    Ok(line)
+}
+
+/// Decodes the documents fetched from the content store into records.
+fn decode<T: DeserializeOwned>(docs: Vec<Document>) -> Result<Vec<T>> {
+    docs.into_iter()
+        .map(|doc| bson::from_document(doc).context("Failed to deserialise record"))
+        .collect()
+}
+
+/// Records are immutable, so can't be updated in place.
+fn immutable(operation: &'static str) -> ArchiveError {
+    Unsupported {
+        operation,
+        reason:
+            "records in the IPFS backend are immutable, their ids being the CIDs of their content"
+                .to_string(),
+    }
+    .into()
+}
+
+#[derive(Debug)]
+pub struct IpfsBackend {
+    /// Directory the CID index of each record type is kept in
+    pub index_dir: PathBuf,
+    store: Arc<dyn ContentAddressedStore>,
+    /// The index of each record type read so far. Writes to an index hold the lock until its
+    /// file is written, so they never interleave.
+    indexes: Mutex<HashMap<ArchiveRecordType, Vec<IndexEntry>>>,
+}
+
+impl IpfsBackend {
+    pub fn new(index_dir: &Path, store: Arc<dyn ContentAddressedStore>) -> Self {
+        IpfsBackend {
+            index_dir: index_dir.to_path_buf(),
+            store,
+            indexes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks that the URI, if given, is the HTTP URL of an IPFS node's API. It may be left empty
+    /// when records are stored in another [ContentAddressedStore].
+    pub fn validate_uri(uri: &str) -> std::result::Result<(), String> {
+        if uri.is_empty() || uri.starts_with("http://") || uri.starts_with("https://") {
+            return Ok(());
+        }
+        Err(format!(
+            "IPFS API URI '{}' must start with http:// or https://",
+            crate::uri::redact(uri)
+        ))
+    }
+
+    /// Checks that the datastore name is not empty. Records aren't stored under the datastore
+    /// name, which only identifies the archive in logs, metrics and spill files.
+    pub fn validate_datastore(datastore: &str) -> std::result::Result<(), String> {
+        if datastore.is_empty() {
+            return Err("Datastore name must not be empty".to_string());
+        }
+        Ok(())
+    }
+
+    /// File holding the index of the given record type.
+    fn index_path(&self, rec_type: &ArchiveRecordType) -> PathBuf {
+        let name = match rec_type {
+            ArchiveRecordType::Account => ACCOUNT_INDEX,
+            ArchiveRecordType::TransactionBatch => TRANSACTION_INDEX,
+            ArchiveRecordType::Block => BLOCK_INDEX,
+            ArchiveRecordType::Receipt => RECEIPT_INDEX,
+            ArchiveRecordType::Custom(name) => name,
+        };
+        self.index_dir.join(format!("{}.jsonl", name))
+    }
+
+    /// The index of the given record type, read from its file the first time it's needed.
+    async fn index<'a>(
+        &self,
+        indexes: &'a mut HashMap<ArchiveRecordType, Vec<IndexEntry>>,
+        rec_type: &ArchiveRecordType,
+    ) -> Result<&'a mut Vec<IndexEntry>> {
+        Ok(match indexes.entry(rec_type.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(read_index(&self.index_path(rec_type)).await?),
+        })
+    }
+
+    /// The records of the given type listed in its index, in the order they were archived.
+    async fn entries(&self, rec_type: &ArchiveRecordType) -> Result<Vec<IndexEntry>> {
+        let mut indexes = self.indexes.lock().await;
+        Ok(self.index(&mut indexes, rec_type).await?.clone())
+    }
+
+    /// Adds a record to the index of its type, unless it's already listed.
+    async fn add(&self, rec_type: &ArchiveRecordType, entry: IndexEntry) -> Result<()> {
+        let mut indexes = self.indexes.lock().await;
+        let index = self.index(&mut indexes, rec_type).await?;
+        if index.iter().any(|listed| listed.cid == entry.cid) {
+            return Ok(());
+        }
+
+        let path = self.index_path(rec_type);
+        fs::create_dir_all(&self.index_dir)
+            .await
+            .with_context(|| format!("Creating index directory {}", self.index_dir.display()))?;
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("Opening CID index {}", path.display()))?;
+        file.write_all(index_line(&entry)?.as_bytes()).await?;
+        file.sync_all()
+            .await
+            .with_context(|| format!("Writing CID index {}", path.display()))?;
+
+        index.push(entry);
+        Ok(())
+    }
+
+    /// Removes the given records from the index of their type, then unpins their content,
+    /// returning the number removed. The index is rewritten to a temporary file which is renamed
+    /// into place, so a crash never leaves it partially written.
+    async fn remove(&self, rec_type: &ArchiveRecordType, cids: &HashSet<String>) -> Result<u64> {
+        let mut indexes = self.indexes.lock().await;
+        let index = self.index(&mut indexes, rec_type).await?;
+        let mut kept = index.clone();
+        kept.retain(|entry| !cids.contains(&entry.cid));
+        let removed = index.len() - kept.len();
+        if removed == 0 {
+            return Ok(0);
+        }
+
+        let path = self.index_path(rec_type);
+        let mut contents = String::new();
+        for entry in &kept {
+            contents.push_str(&index_line(entry)?);
+        }
+        let tmp = path.with_extension("jsonl.tmp");
+        let mut file = fs::File::create(&tmp)
+            .await
+            .with_context(|| format!("Creating {}", tmp.display()))?;
+        file.write_all(contents.as_bytes()).await?;
+        file.sync_all().await?;
+        fs::rename(&tmp, &path)
+            .await
+            .with_context(|| format!("Writing CID index {}", path.display()))?;
+        *index = kept;
+        drop(indexes);
+
+        for cid in cids {
+            self.store.remove(cid).await?;
+        }
+        Ok(removed as u64)
+    }
+
+    /// Stores a record's content and lists it in the index, returning its CID. Any `_id` the
+    /// record holds is left out of its content.
+    async fn write(&self, rec_type: &ArchiveRecordType, mut doc: Document) -> Result<String> {
+        doc.remove("_id");
+        let content = bson::to_vec(&doc).context("Failed to serialise record to BSON")?;
+        let bytes = content.len() as u64;
+        let cid = self.store.put(content).await?;
+        let entry = IndexEntry {
+            cid: cid.clone(),
+            bytes,
+            archived_at: bson::DateTime::now().timestamp_millis(),
+        };
+        self.add(rec_type, entry).await?;
+        Ok(cid)
+    }
+
+    /// Fetches a record's content, with its CID as its `_id`.
+    async fn fetch(&self, cid: &str) -> Result<Document> {
+        let content = self.store.get(cid).await?.ok_or_else(|| {
+            ArchiveError::NotFound(anyhow!(
+                "The content of indexed record {} is missing from the content store",
+                cid
+            ))
+        })?;
+        let content: Document = bson::from_slice(&content)
+            .with_context(|| format!("Invalid record content {}", cid))?;
+        let mut doc = doc! { "_id": cid };
+        doc.extend(content);
+        Ok(doc)
+    }
+
+    /// Fetches the listed records, keeping their order.
+    fn fetch_all(&self, entries: Vec<IndexEntry>) -> BoxStream<'_, Result<Document>> {
+        stream::iter(entries)
+            .map(move |entry| async move { self.fetch(&entry.cid).await })
+            .buffered(CONCURRENCY)
+            .boxed()
+    }
+
+    /// Fetches the records of the given type matching a filter.
+    async fn matching(
+        &self,
+        rec_type: &ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<Vec<Document>> {
+        let entries = self.entries(rec_type).await?;
+        self.fetch_all(entries)
+            .try_filter(|doc| futures::future::ready(filter.matches(doc)))
+            .try_collect()
+            .await
+    }
+}
+
+#[async_trait]
+impl ArchiveBackend for IpfsBackend {
+    /// Stores the record's content, returning its CID. Identical content already archived as
+    /// this record type is stored once, under the same id.
+    async fn create<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+        let cid = self.write(&rec_type, doc).await?;
+        debug!("Stored record {} in IPFS", cid);
+        Ok(cid)
+    }
+
+    /// Stores the records in order. Records stored before a failure are kept, whatever the
+    /// ordering setting.
+    async fn create_many<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        recs: Vec<T>,
+    ) -> Result<Vec<String>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let mut cids = Vec::with_capacity(recs.len());
+        for rec in recs.iter() {
+            let doc = bson::to_document(rec).context("Failed to serialise record to BSON")?;
+            cids.push(self.write(&rec_type, doc).await?);
+        }
+        debug!("Stored {} records in IPFS", cids.len());
+        Ok(cids)
+    }
+
+    /// Content stores have no transactions.
+    async fn create_atomic(
+        &self,
+        _records: Vec<(ArchiveRecordType, Document)>,
+    ) -> Result<Vec<String>, ArchiveError> {
+        Err(Unsupported {
+            operation: "create_atomic",
+            reason: "the IPFS backend has no transactions".to_string(),
+        }
+        .into())
+    }
+
+    async fn find_all<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let entries = self.entries(&rec_type).await?;
+        Ok(decode(self.fetch_all(entries).try_collect().await?)?)
+    }
+
+    /// Streams the records, fetching them as they are needed.
+    async fn find_all_stream<'a, T: DeserializeOwned>(
+        &'a self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'a, Result<T, ArchiveError>>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin + 'a,
+    {
+        let entries = self.entries(&rec_type).await?;
+        Ok(self
+            .fetch_all(entries)
+            .and_then(|doc| async move {
+                bson::from_document(doc).context("Failed to deserialise record")
+            })
+            .map_err(ArchiveError::from)
+            .boxed())
+    }
+
+    /// Pages through the records in the order they were archived, the token being the CID of the
+    /// last record of the page, so paging fails if that record is deleted in between.
+    async fn find_page<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        request: &PageRequest,
+    ) -> Result<Page<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let mut entries = self.entries(&rec_type).await?;
+        if let Some(after) = &request.after_token {
+            let position = entries
+                .iter()
+                .position(|entry| entry.cid == *after)
+                .ok_or_else(|| {
+                    ArchiveError::invalid_input(format!(
+                        "Unknown page token '{}', the record it names may have been deleted",
+                        after
+                    ))
+                })?;
+            entries.drain(..=position);
+        }
+        let next_token = match entries.len() > request.limit {
+            true => Some(entries[request.limit - 1].cid.clone()),
+            false => None,
+        };
+        entries.truncate(request.limit);
+
+        Ok(Page {
+            items: decode(self.fetch_all(entries).try_collect().await?)?,
+            next_token,
+        })
+    }
+
+    /// Query data store for the records of the given type matching a [Filter]. Every record of
+    /// the type is fetched and filtered here.
+    async fn query<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        Ok(decode(self.matching(&rec_type, filter).await?)?)
+    }
+
+    /// Records are fetched whole, so whole documents are returned.
+    async fn find_projected(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        _fields: &[String],
+    ) -> Result<Vec<Document>, ArchiveError> {
+        self.query::<Document>(rec_type, filter).await
+    }
+
+    /// Only records listed in the index of the type are found, even if the content store holds
+    /// the CID.
+    async fn find_by_id<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<Option<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let entries = self.entries(&rec_type).await?;
+        if !entries.iter().any(|entry| entry.cid == id) {
+            return Ok(None);
+        }
+        let doc = self.fetch(id).await?;
+        Ok(Some(
+            bson::from_document(doc).context("Failed to deserialise record")?,
+        ))
+    }
+
+    /// Removes the record from the index and unpins its content.
+    async fn delete_by_id(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<bool, ArchiveError> {
+        let cids = HashSet::from([id.to_string()]);
+        let deleted = self.remove(&rec_type, &cids).await? > 0;
+        if deleted {
+            debug!("Deleted record {} from IPFS", id);
+        }
+        Ok(deleted)
+    }
+
+    /// Every record of the type is fetched and filtered here, and the matching records removed
+    /// from the index and unpinned.
+    async fn delete_where(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        let cids: HashSet<String> = self
+            .matching(&rec_type, filter)
+            .await?
+            .iter()
+            .filter_map(|doc| doc.get_str("_id").ok().map(str::to_string))
+            .collect();
+        let deleted = self.remove(&rec_type, &cids).await?;
+
+        debug!("Deleted {} records from IPFS", deleted);
+        Ok(deleted)
+    }
+
+    /// Changing a record would change its CID, and so its id.
+    async fn update_by_id<T: Serialize>(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _id: &str,
+        _rec: T,
+    ) -> Result<bool, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        Err(immutable("update_by_id"))
+    }
+
+    /// Changing a record would change its CID, and so its id.
+    async fn upsert<T: Serialize>(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _filter: &Filter,
+        _rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        Err(immutable("upsert"))
+    }
+
+    /// Returns a random sample of roughly `rate` (0.0 to 1.0) of the records of the given type,
+    /// selecting each record independently with probability `rate`. Only the selected records are
+    /// fetched.
+    async fn find_sampled<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rate: f64,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let mut entries = self.entries(&rec_type).await?;
+        entries.retain(|_| rand::random::<f64>() < rate);
+        Ok(decode(self.fetch_all(entries).try_collect().await?)?)
+    }
+
+    /// Records are never chunked, so there are never orphaned chunks.
+    async fn find_orphaned_chunks(
+        &self,
+        _rec_type: ArchiveRecordType,
+    ) -> Result<Vec<String>, ArchiveError> {
+        Ok(Vec::new())
+    }
+
+    /// Records are never chunked, so there are never orphaned chunks.
+    async fn cleanup_orphans(&self, _rec_type: ArchiveRecordType) -> Result<u64, ArchiveError> {
+        Ok(0)
+    }
+
+    /// Deleted records are unpinned, and their content reclaimed by the content store itself, e.g.
+    /// by the IPFS node's garbage collection.
+    async fn reclaim_space(&self, _rec_type: ArchiveRecordType) -> Result<(), ArchiveError> {
+        Ok(())
+    }
+
+    /// Aggregation pipelines are MongoDB specific.
+    async fn merge_into(
+        &self,
+        _source: ArchiveRecordType,
+        _pipeline: Vec<Document>,
+        _target: ArchiveRecordType,
+        _mode: MergeMode,
+    ) -> Result<u64, ArchiveError> {
+        Err(Unsupported {
+            operation: "merge_into",
+            reason: "aggregation pipelines are only supported by the MongoDB backend".to_string(),
+        }
+        .into())
+    }
+
+    /// Changes are only reported by the store that made them.
+    async fn watch(
+        &self,
+        _rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'static, Result<ArchiveEvent, ArchiveError>>, ArchiveError> {
+        Err(Unsupported {
+            operation: "watch",
+            reason: "change streams are only supported by the MongoDB backend".to_string(),
+        }
+        .into())
+    }
+
+    /// [Filter::All] counts the records in the index. Otherwise every record of the type is
+    /// fetched and filtered here.
+    async fn count(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        if let Filter::All = filter {
+            return Ok(self.entries(&rec_type).await?.len() as u64);
+        }
+        Ok(self.matching(&rec_type, filter).await?.len() as u64)
+    }
+
+    /// [Filter::All] only reads the index. Otherwise records of the type are fetched and filtered
+    /// here until one matches.
+    async fn exists(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<bool, ArchiveError> {
+        let entries = self.entries(&rec_type).await?;
+        if let Filter::All = filter {
+            return Ok(!entries.is_empty());
+        }
+
+        Ok(self
+            .fetch_all(entries)
+            .try_filter(|doc| futures::future::ready(filter.matches(doc)))
+            .try_next()
+            .await?
+            .is_some())
+    }
+
+    /// Counts the records in the index and totals the sizes of their content.
+    async fn stats(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<ArchiveCollectionStats, ArchiveError> {
+        let entries = self.entries(&rec_type).await?;
+        let document_count = entries.len() as u64;
+        let storage_bytes = entries.iter().map(|entry| entry.bytes).sum::<u64>();
+
+        Ok(ArchiveCollectionStats {
+            document_count,
+            storage_bytes,
+            avg_doc_bytes: storage_bytes.checked_div(document_count).unwrap_or(0),
+        })
+    }
+
+    /// Groups by the time each record was indexed when grouping by archive time, which only
+    /// needs the index. Grouping by a field fetches every record.
+    async fn group_count(
+        &self,
+        rec_type: ArchiveRecordType,
+        group_by: GroupBy,
+    ) -> Result<Vec<(Bson, u64)>, ArchiveError> {
+        let entries = self.entries(&rec_type).await?;
+        let keys: Vec<Bson> = match group_by {
+            GroupBy::Field(field) => {
+                self.fetch_all(entries)
+                    .map_ok(|doc| lookup(&doc, &field).cloned().unwrap_or(Bson::Null))
+                    .try_collect()
+                    .await?
+            }
+            GroupBy::ArchivedAt(granularity) => entries
+                .iter()
+                .map(|entry| {
+                    Bson::String(granularity.bucket(bson::DateTime::from_millis(entry.archived_at)))
+                })
+                .collect(),
+        };
+        Ok(tally(keys))
+    }
+
+    /// Aggregates the records matching the filter in process.
+    async fn aggregate(
+        &self,
+        rec_type: ArchiveRecordType,
+        spec: &AggregationSpec,
+    ) -> Result<Vec<Document>, ArchiveError> {
+        let docs = self.matching(&rec_type, &spec.filter).await?;
+        Ok(aggregate(spec, docs))
+    }
+
+    /// There are no secondary indexes: queries fetch every record, so non-unique indexes are
+    /// ignored.
+    async fn ensure_indexes(
+        &self,
+        _rec_type: ArchiveRecordType,
+        indexes: &[IndexSpec],
+    ) -> Result<(), ArchiveError> {
+        if indexes.iter().any(|index| index.unique) {
+            return Err(Unsupported {
+                operation: "ensure_indexes",
+                reason: "the IPFS backend has no secondary indexes".to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+/// A JSON-RPC 2.0 read endpoint over HTTP, so the archive can back the node's historical state
+/// RPC calls for wallets and explorers. Requests are POSTed as single calls or batches, and
+/// params are given by position. Records are returned as relaxed extended JSON, or `null` if
+/// there is no such record. The methods are:
+///
+/// - `archive_getAccount(address, before?)`: the latest archived state of the account whose
+///   [crate::ACCOUNT_ADDRESS_FIELD] is `address`, or the latest archived before `before`, in
+///   milliseconds since the epoch
+/// - `archive_getTransactionBatch(id)`: the transaction batch with the given id
+/// - `archive_getBlock(height)`: the block at the given [crate::BLOCK_HEIGHT_FIELD]
+/// - `archive_getReceipt(txHash)`: the receipt of the transaction with the given hash
+/// - `archive_queryRange(recordType, field, from, to)`: the records whose `field` lies between
+///   `from` and `to` inclusive, ordered by the field, either bound being `null` for none, as by
+///   [ArchiveStore::find_range]
+///
+/// Requires the `jsonrpc` feature.
+use crate::http;
+use crate::{
+    ArchiveError, ArchiveErrorKind, ArchiveRecordType, ArchiveStore, Filter, BLOCK_HEIGHT_FIELD,
+    RECEIPT_TX_HASH_FIELD,
+};
+use anyhow::anyhow;
+use bson::{Bson, DateTime, Document};
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::{Method, Request, Response, StatusCode};
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::ops::Bound;
+/// Most records `archive_queryRange` returns before asking for a narrower range
+const MAX_RANGE_RECORDS: usize = 10_000;
+
+/// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+/// Code of failures the call may succeed after if retried, e.g. the backend being unavailable
+const SERVER_ERROR: i64 = -32000;
+
+impl ArchiveStore {
+    /// Serves this store's JSON-RPC read endpoint over HTTP on `addr`, without TLS, until
+    /// `shutdown` completes, e.g. `tokio::signal::ctrl_c()`. Connections already open when it
+    /// completes are served until they close.
+    pub async fn serve_jsonrpc(
+        &self,
+        addr: SocketAddr,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), ArchiveError> {
+        let store = self.clone();
+        http::serve(addr, "JSON-RPC", shutdown, move |request| {
+            let store = store.clone();
+            async move { store.respond(request).await }
+        })
+        .await
+    }
+
+    /// Calls a method of the JSON-RPC read endpoint with its params, e.g. to register it with
+    /// the node's own RPC server instead of serving it with [ArchiveStore::serve_jsonrpc]. Fails
+    /// with [ArchiveError::Unsupported] for unknown methods and [ArchiveError::InvalidInput]
+    /// for invalid params.
+    pub async fn rpc_call(&self, method: &str, params: Value) -> Result<Value, ArchiveError> {
+        let params = match params {
+            Value::Array(params) => params,
+            Value::Null => Vec::new(),
+            _ => {
+                return Err(ArchiveError::invalid_input(
+                    "Params must be given by position",
+                ))
+            }
+        };
+        match method {
+            "archive_getAccount" => {
+                let address: String = param(&params, 0, "address")?;
+                let before = optional_param::<i64>(&params, 1, "before")?;
+                let state = self
+                    .find_account_state(&address, before.map(DateTime::from_millis))
+                    .await?;
+                Ok(record(state.map(|envelope| envelope.record)))
+            }
+            "archive_getTransactionBatch" => {
+                let id: String = param(&params, 0, "id")?;
+                let rec = self
+                    .find_by_id::<Document>(ArchiveRecordType::TransactionBatch, &id)
+                    .await?;
+                Ok(record(rec))
+            }
+            "archive_getBlock" => {
+                let height: i64 = param(&params, 0, "height")?;
+                let filter = Filter::eq(BLOCK_HEIGHT_FIELD, height);
+                let recs = self
+                    .query::<Document>(ArchiveRecordType::Block, filter)
+                    .await?;
+                Ok(record(recs.into_iter().next()))
+            }
+            "archive_getReceipt" => {
+                let tx_hash: String = param(&params, 0, "txHash")?;
+                let filter = Filter::eq(RECEIPT_TX_HASH_FIELD, tx_hash);
+                let recs = self
+                    .query::<Document>(ArchiveRecordType::Receipt, filter)
+                    .await?;
+                Ok(record(recs.into_iter().next()))
+            }
+            "archive_queryRange" => {
+                let name: String = param(&params, 0, "recordType")?;
+                let field: String = param(&params, 1, "field")?;
+                let bound = |i, name| -> Result<Bound<Bson>, ArchiveError> {
+                    match optional_param::<Value>(&params, i, name)? {
+                        Some(value) => Ok(Bound::Included(bson_value(value, name)?)),
+                        None => Ok(Bound::Unbounded),
+                    }
+                };
+                let range = (bound(2, "from")?, bound(3, "to")?);
+                let recs: Vec<Document> = self
+                    .find_range(ArchiveRecordType::from_name(&name), &field, range)
+                    .await?;
+                if recs.len() > MAX_RANGE_RECORDS {
+                    return Err(ArchiveError::invalid_input(format!(
+                        "The range holds more than {} records, query a narrower one",
+                        MAX_RANGE_RECORDS
+                    )));
+                }
+                Ok(Value::Array(
+                    recs.into_iter().map(|rec| record(Some(rec))).collect(),
+                ))
+            }
+            _ => Err(ArchiveError::Unsupported(anyhow!(
+                "Unknown method '{}'",
+                method
+            ))),
+        }
+    }
+
+    /// Answers an HTTP request to the JSON-RPC endpoint.
+    async fn respond(&self, request: Request<Incoming>) -> Response<Full<Bytes>> {
+        if request.method() != Method::POST {
+            return http::response(StatusCode::METHOD_NOT_ALLOWED, Bytes::new());
+        }
+        let body = match http::read_body(request).await {
+            Ok(body) => body,
+            Err(response) => return response,
+        };
+        match self.handle_jsonrpc(&body).await {
+            Some(reply) => http::ok("application/json", reply.to_string()),
+            // Nothing is returned for notifications.
+            None => http::response(StatusCode::NO_CONTENT, Bytes::new()),
+        }
+    }
+
+    /// Answers a JSON-RPC request or batch of requests, or `None` if they were all notifications.
+    async fn handle_jsonrpc(&self, body: &[u8]) -> Option<Value> {
+        let request: Value = match serde_json::from_slice(body) {
+            Ok(request) => request,
+            Err(e) => return Some(error_reply(Value::Null, PARSE_ERROR, e.to_string())),
+        };
+        match request {
+            Value::Array(batch) if batch.is_empty() => Some(error_reply(
+                Value::Null,
+                INVALID_REQUEST,
+                "Empty batch".to_string(),
+            )),
+            Value::Array(batch) => {
+                let mut replies = Vec::with_capacity(batch.len());
+                for request in batch {
+                    replies.extend(self.handle_call(request).await);
+                }
+                (!replies.is_empty()).then_some(Value::Array(replies))
+            }
+            request => self.handle_call(request).await,
+        }
+    }
+
+    /// Answers a single JSON-RPC request, or `None` if it was a notification.
+    async fn handle_call(&self, request: Value) -> Option<Value> {
+        let Value::Object(mut request) = request else {
+            return Some(error_reply(
+                Value::Null,
+                INVALID_REQUEST,
+                "Request must be an object".to_string(),
+            ));
+        };
+        let id = request.remove("id");
+        let method = match (request.remove("jsonrpc"), request.remove("method")) {
+            (Some(Value::String(version)), Some(Value::String(method))) if version == "2.0" => {
+                method
+            }
+            _ => {
+                return Some(error_reply(
+                    id.unwrap_or(Value::Null),
+                    INVALID_REQUEST,
+                    "Request must have jsonrpc \"2.0\" and a method".to_string(),
+                ))
+            }
+        };
+        let params = request.remove("params").unwrap_or(Value::Null);
+        let outcome = self.rpc_call(&method, params).await;
+        let id = id?;
+        Some(match outcome {
+            Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+            Err(e) => {
+                let code = match e.kind() {
+                    ArchiveErrorKind::Unsupported => METHOD_NOT_FOUND,
+                    ArchiveErrorKind::InvalidInput => INVALID_PARAMS,
+                    ArchiveErrorKind::ConnectionFailed | ArchiveErrorKind::BackendUnavailable => {
+                        SERVER_ERROR
+                    }
+                    _ => {
+                        warn!("JSON-RPC call {} failed: {:#}", method, e);
+                        INTERNAL_ERROR
+                    }
+                };
+                error_reply(id, code, format!("{:#}", e))
+            }
+        })
+    }
+}
+
+/// A required positional param.
+fn param<T: DeserializeOwned>(params: &[Value], i: usize, name: &str) -> Result<T, ArchiveError> {
+    optional_param(params, i, name)?
+        .ok_or_else(|| ArchiveError::invalid_input(format!("Missing param '{}'", name)))
+}
+
+/// An optional positional param, `None` if it's missing or `null`.
+fn optional_param<T: DeserializeOwned>(
+    params: &[Value],
+    i: usize,
+    name: &str,
+) -> Result<Option<T>, ArchiveError> {
+    match params.get(i) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => serde_json::from_value(value.clone())
+            .map(Some)
+            .map_err(|e| ArchiveError::invalid_input(format!("Invalid param '{}': {}", name, e))),
+    }
+}
+
+/// A param given as extended JSON, as a BSON value.
+fn bson_value(value: Value, name: &str) -> Result<Bson, ArchiveError> {
+    Bson::try_from(value)
+        .map_err(|e| ArchiveError::invalid_input(format!("Invalid param '{}': {}", name, e)))
+}
+
+/// A record as returned to callers, as relaxed extended JSON.
+fn record(rec: Option<Document>) -> Value {
+    match rec {
+        Some(rec) => Bson::Document(rec).into_relaxed_extjson(),
+        None => Value::Null,
+    }
+}
+
+/// A JSON-RPC error reply.
+fn error_reply(id: Value, code: i64, message: String) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "error": { "code": code, "message": message },
+        "id": id,
+    })
+}
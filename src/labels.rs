@@ -0,0 +1,55 @@
+/// Key-value labels attached to archive operations for observability, e.g. `tenant=acme` or
+/// `job=nightly_sync`. Labels are set on a store handle with [crate::ArchiveStore::with_labels]
+/// and are carried by every operation performed through that handle, so dashboards can be sliced
+/// by them.
+///
+/// Every distinct combination of label values becomes its own time series in most metrics
+/// systems, so labels should only take a small, bounded set of values. Avoid labelling with record
+/// ids, account addresses, block heights or anything else that grows without bound.
+use core::fmt;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Labels(Arc<Vec<(String, String)>>);
+
+impl Labels {
+    /// Returns these labels with the given labels added, replacing the values of any existing
+    /// labels with the same keys.
+    pub(crate) fn with(&self, labels: &[(&str, &str)]) -> Labels {
+        let mut merged = self.0.as_ref().clone();
+        for (key, value) in labels {
+            match merged.iter_mut().find(|(k, _)| k == key) {
+                Some((_, v)) => *v = value.to_string(),
+                None => merged.push((key.to_string(), value.to_string())),
+            }
+        }
+        Labels(Arc::new(merged))
+    }
+
+    /// Iterates over the labels as key-value pairs, in the order they were first added.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Returns the value of the label with the given key, if set.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.iter().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    /// Returns true if no labels are set.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Display for Labels {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (key, value)) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
@@ -1,108 +1,3116 @@
+#[cfg(feature = "arweave")]
+mod arweave_archive;
+mod as_of;
+mod audit;
+mod backfill;
+/// A synchronous facade over the store, see [blocking::BlockingArchiveStore]. Requires the
+/// `blocking` feature.
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod cache;
+mod checksum;
+// Records are only chunked by the MongoDB and in-memory backends.
+mod chunking;
+mod codec;
+mod compression;
+mod config;
+mod consistency;
+mod credentials;
+mod custom_archive;
+mod dead_letter;
+mod dedup;
+mod encryption;
+mod envelope;
+mod error;
+mod events;
+mod export;
+mod filesystem_archive;
+mod filter;
+#[cfg(feature = "graphql")]
+mod graphql;
+#[cfg(any(feature = "jsonrpc", feature = "graphql"))]
+mod http;
+mod import;
+mod index;
+#[cfg(feature = "ipfs")]
+mod ipfs_archive;
+#[cfg(feature = "jsonrpc")]
+mod jsonrpc;
+mod labels;
+mod lifecycle;
+mod memory_archive;
+mod migration;
+mod mirror;
+#[cfg(feature = "mongodb")]
 mod mongodb_archive;
+mod namespace;
+mod observability;
+mod page;
+mod parallel;
+#[cfg(feature = "postgres")]
+mod postgres_archive;
+mod projection;
+#[cfg(feature = "metrics")]
+mod prometheus_metrics;
+mod proof;
+mod rate_limit;
+#[cfg(feature = "mongodb")]
+mod registry;
+mod report;
+mod retention;
+mod retry;
+#[cfg(feature = "rocksdb")]
+mod rocksdb_archive;
+#[cfg(feature = "s3")]
+mod s3_archive;
+/// The gRPC archive service, see [server::ArchiveService]. Requires the `server` feature.
+#[cfg(feature = "server")]
+// The service returns tonic's `Status`, which is larger than clippy would like.
+#[allow(clippy::result_large_err)]
+pub mod server;
+mod snapshot;
+mod spill;
+#[cfg(feature = "sqlite")]
+mod sqlite_archive;
+mod staging;
+mod stats;
+mod tiering;
+mod transfer;
+mod typed;
+mod unsupported;
+mod uri;
+mod versioning;
+mod writer;
 
+#[cfg(feature = "arweave")]
+pub use crate::arweave_archive::PermanentStore;
+#[cfg(feature = "arweave")]
+use crate::arweave_archive::{ArweaveBackend, ArweaveGateway};
+pub use crate::as_of::AsOf;
+pub use crate::audit::MissingRange;
+pub use crate::backfill::{BackfillOptions, BackfillProgress, Backfiller, BACKFILL_JOBS};
+use crate::cache::RecordCache;
+pub use crate::checksum::{VerificationResult, VerificationSummary};
+pub use crate::chunking::ChunkIntegrityError;
+pub use crate::codec::Codec;
+pub use crate::compression::{Compression, RecordTooLarge};
+pub use crate::config::{ArchiveConfig, RetryConfig};
+pub use crate::consistency::{
+    Acknowledgment, ReadOptions, ReadPreference, WriteConcern, WriteOptions,
+};
+pub use crate::credentials::{Credentials, EnvSecrets, SecretProvider};
+use crate::custom_archive::DynBackend;
+pub use crate::dead_letter::{DeadLetter, DeadLetterReason, PayloadFormat};
+use crate::dedup::DedupCache;
+pub use crate::encryption::{EncryptionConfig, EncryptionKey, KeyProvider, StaticKeys};
+pub use crate::envelope::ArchiveEnvelope;
+use crate::envelope::Provenance;
+pub use crate::error::{ArchiveError, ArchiveErrorKind};
+use crate::events::EventChannel;
+pub use crate::events::{ArchiveEvent, ArchiveEventKind};
+pub use crate::export::ExportFormat;
+pub use crate::filesystem_archive::FileFormat;
+use crate::filesystem_archive::FilesystemBackend;
+pub use crate::filter::Filter;
+#[cfg(feature = "graphql")]
+pub use crate::graphql::{ArchiveQuery, ArchiveSchema};
+pub use crate::import::{ImportFormat, ImportOptions, ImportProgress};
+pub use crate::index::IndexSpec;
+#[cfg(feature = "ipfs")]
+pub use crate::ipfs_archive::ContentAddressedStore;
+#[cfg(feature = "ipfs")]
+use crate::ipfs_archive::{IpfsBackend, IpfsHttpStore};
+pub use crate::labels::Labels;
+pub use crate::lifecycle::{Lifecycle, LifecycleState};
+use crate::memory_archive::MemoryBackend;
+use crate::migration::Migrations;
+pub use crate::migration::{Migration, NewerSchemaVersion, DEFAULT_SCHEMA_VERSION};
+pub use crate::mirror::WriteStrategy;
+#[cfg(feature = "mongodb")]
 use crate::mongodb_archive::MongoDBBackend;
-use anyhow::{Context, Result};
+use crate::observability::{encoded_size, trace_ids};
+pub use crate::observability::{ArchiveMetrics, ArchiveOperation, Outcome};
+pub use crate::page::{Page, PageRequest};
+#[cfg(feature = "postgres")]
+use crate::postgres_archive::PostgresBackend;
+#[cfg(feature = "metrics")]
+pub use crate::prometheus_metrics::PrometheusMetrics;
+pub use crate::proof::{verify_proof, InclusionProof, MerkleRoot};
+use crate::rate_limit::RateLimiter;
+pub use crate::rate_limit::RateLimits;
+#[cfg(feature = "mongodb")]
+pub use crate::registry::ArchiveRegistry;
+pub use crate::report::{ArchiveStats, ArchiveStatsReport};
+pub use crate::retention::{ExpiryAction, RetentionPolicy};
+pub use crate::retry::RetryPolicy;
+#[cfg(feature = "rocksdb")]
+use crate::rocksdb_archive::RocksDbBackend;
+#[cfg(feature = "s3")]
+use crate::s3_archive::S3Backend;
+pub use crate::snapshot::{SnapshotFile, SnapshotManifest};
+pub use crate::spill::CreateOutcome;
+use crate::spill::SpillEntry;
+#[cfg(feature = "sqlite")]
+use crate::sqlite_archive::SqliteBackend;
+pub use crate::stats::{Aggregate, AggregationSpec, ArchiveCollectionStats, Granularity, GroupBy};
+pub use crate::tiering::TieringPolicy;
+pub use crate::typed::{ArchiveRecord, TypedArchive};
+pub use crate::unsupported::Unsupported;
+pub use crate::versioning::Versioned;
+pub use crate::writer::{ArchiveWriter, WriterOptions};
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
+use bson::{Bson, Document};
 use core::fmt;
 use derive_builder::Builder;
+use futures::stream::{BoxStream, Stream, StreamExt, TryStreamExt};
+#[cfg(feature = "derive")]
+pub use lasr_archive_derive::ArchiveRecord;
+use log::{debug, warn};
 use serde::{de::DeserializeOwned, Serialize};
 use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::Duration;
+#[cfg(feature = "mongodb")]
+use tokio::sync::OnceCell;
 
-/// A structure representing an archive datastore
-#[derive(Debug, Builder)]
+/// Longest custom record type name we allow
+const MAX_RECORD_TYPE_LEN: usize = 48;
+/// Names the built-in record types are stored under, which custom record types can't use
+const RESERVED_RECORD_TYPES: [&str; 4] = ["accounts", "transaction_data", "blocks", "receipts"];
+/// Field holding the height of an [ArchiveRecordType::Block] record. The MongoDB and PostgreSQL
+/// backends index it, other backends scan every block when filtering on it.
+pub const BLOCK_HEIGHT_FIELD: &str = "block_height";
+/// Field holding the transaction hash of an [ArchiveRecordType::Receipt] record. The MongoDB and
+/// PostgreSQL backends index it, other backends scan every receipt when filtering on it.
+pub const RECEIPT_TX_HASH_FIELD: &str = "tx_hash";
+/// Field holding the owner address of an [ArchiveRecordType::Account] record, which the
+/// `archive_getAccount` JSON-RPC method looks accounts up by. No backend indexes it.
+pub const ACCOUNT_ADDRESS_FIELD: &str = "owner_address";
+
+/// A structure representing an archive datastore. Stores are cheap to clone, with clones sharing
+/// the same configuration and backend connection, and can be used concurrently from many tasks.
+#[derive(Debug, Clone)]
 pub struct ArchiveStore {
-    /// The backend-specific URI to connect to the archive backend
+    inner: Arc<ArchiveStoreInner>,
+    /// Labels carried by operations performed through this handle
+    labels: Labels,
+    /// Tags archived with the records written through this handle
+    tags: Vec<String>,
+    /// Whether reads through this handle return records that aren't archived, see
+    /// [ArchiveStore::with_tombstones]
+    include_tombstones: bool,
+    /// The MongoDB backend used in place of the store's by a handle made for per-call
+    /// [WriteOptions] or [ReadOptions]
+    #[cfg(feature = "mongodb")]
+    mongodb_override: Option<Arc<MongoDBBackend>>,
+}
+
+/// The configuration and state shared by clones of an [ArchiveStore]
+#[derive(Builder)]
+#[builder(
+    name = "ArchiveStoreBuilder",
+    public,
+    build_fn(private, name = "build_inner", validate = "Self::validate")
+)]
+struct ArchiveStoreInner {
+    /// The backend-specific URI to connect to the archive backend. Required by every backend
+    /// except [ArchiveBackends::Filesystem] and [ArchiveBackends::InMemory], and the IPFS and
+    /// Arweave backends when given a content or permanent store.
+    #[builder(default)]
     uri: String,
+    /// Credentials to authenticate with in place of any in the URI, so that the URI needn't hold
+    /// secrets. Only used by the MongoDB, PostgreSQL and S3 backends.
+    #[builder(default, setter(strip_option))]
+    credentials: Option<Credentials>,
     /// Archive backend to use
     backend: ArchiveBackends,
     /// Name of archive datastore
     datastore: String,
+    /// Compression applied to records before they are stored. Defaults to no compression.
+    #[builder(default)]
+    compression: Compression,
+    /// Format records are serialised in before compression, see [Codec]. Defaults to plain BSON
+    /// documents, which are the only records backends can filter on.
+    #[builder(default)]
+    codec: Codec,
+    /// Encryption applied to records, after compression, before they leave the process. Records
+    /// are stored in plaintext by default.
+    #[builder(default, setter(strip_option))]
+    encryption: Option<EncryptionConfig>,
+    /// Whether every record written is stored with a checksum, as by
+    /// [ArchiveStore::create_with_checksum], so that [ArchiveStore::verify_all] can audit the
+    /// whole archive. Only records written with [ArchiveStore::create_with_checksum] are
+    /// checksummed by default.
+    #[builder(default)]
+    checksums: bool,
+    /// Id of the node archiving records, e.g. the validator's node id, stored in the
+    /// [ArchiveEnvelope] of every record it writes. Not recorded by default.
+    #[builder(default, setter(into, strip_option))]
+    node_id: Option<String>,
+    /// Records whose serialised size exceeds this many bytes are transparently split into chunks
+    /// stored in a sidecar collection, instead of failing against the backend's document size
+    /// limit, including records replacing stored ones by [ArchiveStore::update_by_id] and
+    /// [ArchiveStore::upsert]. Chunking is disabled by default.
+    #[builder(default, setter(strip_option))]
+    chunk_threshold: Option<usize>,
+    /// Whether the records passed to [ArchiveStore::create_many] are inserted in order, stopping
+    /// at the first failure, or unordered, letting the backend insert them in parallel and carry
+    /// on past failures. Inserts are ordered by default.
+    #[builder(default = "true")]
+    ordered_inserts: bool,
+    /// Whether [ArchiveStore::create_with_id] succeeds when a record is already stored under the
+    /// id, so that re-archiving a record is idempotent. When disabled it fails with
+    /// [ArchiveError::DuplicateKey]. Enabled by default.
+    #[builder(default = "true")]
+    ignore_duplicate_ids: bool,
+    /// Schema version written alongside records of each [ArchiveRecordType]. Record types not
+    /// listed use [DEFAULT_SCHEMA_VERSION].
+    #[builder(default, setter(custom))]
+    schema_versions: HashMap<ArchiveRecordType, u32>,
+    /// Migrations used to upgrade records written with older schema versions on read
+    #[builder(setter(skip))]
+    migrations: Migrations,
+    /// Cache of recently written records used to skip duplicate writes, set with
+    /// [ArchiveStoreBuilder::dedup_window]. Disabled by default.
+    #[builder(default, setter(custom))]
+    dedup: Option<DedupCache>,
+    /// Cache of records recently read by id, set with [ArchiveStoreBuilder::read_cache].
+    /// Disabled by default.
+    #[builder(default, setter(custom))]
+    cache: Option<RecordCache>,
+    /// Whether [ArchiveStore::create] skips records identical to one of the same type already
+    /// stored, returning its id instead, e.g. for batches replayed while handling a reorg. Every
+    /// record is then stored with its checksum, which the MongoDB, PostgreSQL and SQLite backends
+    /// index and other backends scan every record of the type for. Unlike
+    /// [ArchiveStoreBuilder::dedup_window] this holds across restarts and processes, though
+    /// identical records created at the same time may both be written. Disabled by default.
+    #[builder(default)]
+    content_dedup: bool,
+    /// Write concern applied to every write, e.g. [WriteConcern::majority] for writes that must
+    /// survive the loss of the primary. Defaults to the backend's (or the URI's) write concern.
+    #[builder(default, setter(strip_option))]
+    write_concern: Option<WriteConcern>,
+    /// Which nodes reads are served from. Defaults to the backend's (or the URI's) preference.
+    #[builder(default, setter(strip_option))]
+    read_preference: Option<ReadPreference>,
+    /// Retries operations that fail with transient errors, e.g. [RetryPolicy::default]. Failed
+    /// operations are not retried by default.
+    #[builder(default, setter(strip_option))]
+    retry_policy: Option<RetryPolicy>,
+    /// Limits on the operations performed against the backend, set with
+    /// [ArchiveStoreBuilder::rate_limits]. Unlimited by default.
+    #[builder(default, setter(custom))]
+    rate_limiter: Option<RateLimiter>,
+    /// Stores every write is mirrored to, added with [ArchiveStoreBuilder::mirror]. Reads fall
+    /// back to them in the order added when they fail on this store's own backend.
+    #[builder(default, setter(custom))]
+    mirrors: Vec<ArchiveStore>,
+    /// When a write to a store with mirrors succeeds. Defaults to [WriteStrategy::All].
+    #[builder(default)]
+    write_strategy: WriteStrategy,
+    /// Moves records to a cold store once they age, see [TieringPolicy]. Records stay in the
+    /// store's own backend by default.
+    #[builder(default, setter(strip_option))]
+    tiering: Option<TieringPolicy>,
+    /// Set once the background tier migration has been started
+    #[builder(setter(skip))]
+    tier_migration: OnceLock<()>,
+    /// Serialises migrations to the cold tier
+    #[builder(setter(skip))]
+    tier_migration_lock: tokio::sync::Mutex<()>,
+    /// How long records of each [ArchiveRecordType] are kept, set with
+    /// [ArchiveStoreBuilder::retention]. Record types not listed are kept forever.
+    #[builder(default, setter(custom))]
+    retention: HashMap<ArchiveRecordType, RetentionPolicy>,
+    /// The field identifying the versions of each versioned [ArchiveRecordType], set with
+    /// [ArchiveStoreBuilder::versioned]. Records of other types are replaced by updates.
+    #[builder(default, setter(custom))]
+    versioned: HashMap<ArchiveRecordType, String>,
+    /// How often records past their retention are pruned in the background, as by
+    /// [ArchiveStore::prune]. Pruning starts with the store's first operation and stops once the
+    /// store and all its clones are dropped. By default records are only pruned by calling
+    /// [ArchiveStore::prune].
+    #[builder(default, setter(strip_option))]
+    prune_interval: Option<Duration>,
+    /// Set once background pruning has been started
+    #[builder(setter(skip))]
+    pruning: OnceLock<()>,
+    /// Fields of each record type this store has created an index on as it needed them, e.g. for
+    /// [ArchiveStore::find_range]
+    #[builder(setter(skip))]
+    ensured_indexes: Mutex<HashSet<(ArchiveRecordType, String)>>,
+    /// Changes made through the store, published to its subscribers, see [ArchiveStore::subscribe]
+    #[builder(setter(skip))]
+    events: EventChannel,
+    /// Hook notified of every operation, set with [ArchiveStoreBuilder::metrics]
+    #[builder(default, setter(custom))]
+    metrics: Option<Arc<dyn ArchiveMetrics>>,
+    /// Directory of the dead-letter spill file that records are appended to when they can't be
+    /// written to the backend, see [ArchiveStore::create]. Spilling is disabled by default.
+    #[builder(default, setter(into, strip_option))]
+    spill_dir: Option<PathBuf>,
+    /// How often spilled records are written to the backend in the background, as by
+    /// [ArchiveStore::drain_spill], so they reach it once it recovers. Draining starts with the
+    /// store's first operation and stops once the store and all its clones are dropped. By default
+    /// spilled records are only written by calling [ArchiveStore::drain_spill].
+    #[builder(default, setter(strip_option))]
+    spill_drain_interval: Option<Duration>,
+    /// Set once the background drain has been started
+    #[builder(setter(skip))]
+    spill_drain: OnceLock<()>,
+    /// Serialises access to the spill file between spilling writes and draining
+    #[builder(setter(skip))]
+    spill_lock: tokio::sync::Mutex<()>,
+    /// Directory of the dead-letter file that records [ArchiveStore::create] can't archive, as
+    /// they can't be serialised, are too large or are rejected by the backend, are captured in,
+    /// see [ArchiveStore::dead_letters]. Such records fail with their error by default.
+    #[builder(default, setter(into, strip_option))]
+    dead_letter_dir: Option<PathBuf>,
+    /// Serialises access to the dead-letter file
+    #[builder(setter(skip))]
+    dead_letter_lock: tokio::sync::Mutex<()>,
+    /// A MongoDB client shared with other stores, set when the store is vended by an
+    /// [ArchiveRegistry]. Stores built directly connect on their own.
+    #[cfg(feature = "mongodb")]
+    #[builder(default, private, setter(strip_option))]
+    client: Option<mongodb::Client>,
+    /// The MongoDB backend, and so its connected client, reused by every operation
+    #[cfg(feature = "mongodb")]
+    #[builder(setter(skip))]
+    mongodb: OnceLock<MongoDBBackend>,
+    /// The PostgreSQL backend, and so its connection pool, reused by every operation
+    #[cfg(feature = "postgres")]
+    #[builder(setter(skip))]
+    postgres: OnceLock<PostgresBackend>,
+    /// The SQLite backend, and so its open database, reused by every operation
+    #[cfg(feature = "sqlite")]
+    #[builder(setter(skip))]
+    sqlite: OnceLock<SqliteBackend>,
+    /// The S3 backend, and so its object store client, reused by every operation
+    #[cfg(feature = "s3")]
+    #[builder(setter(skip))]
+    s3: OnceLock<S3Backend>,
+    /// The RocksDB backend, and so its open database, reused by every operation
+    #[cfg(feature = "rocksdb")]
+    #[builder(setter(skip))]
+    rocksdb: OnceLock<RocksDbBackend>,
+    /// Format records are written in by [ArchiveBackends::Filesystem]. Defaults to JSON.
+    #[builder(default)]
+    file_format: FileFormat,
+    /// The filesystem backend, reused by every operation
+    #[builder(setter(skip))]
+    filesystem: OnceLock<FilesystemBackend>,
+    /// The in-memory backend, holding the store's records
+    #[builder(setter(skip))]
+    memory: OnceLock<MemoryBackend>,
+    /// Content-addressed store [ArchiveBackends::Ipfs] keeps records in instead of an IPFS node,
+    /// set with [ArchiveStoreBuilder::content_store]
+    #[cfg(feature = "ipfs")]
+    #[builder(default, setter(custom))]
+    content_store: Option<Arc<dyn ContentAddressedStore>>,
+    /// The IPFS backend, and so its CID indexes, reused by every operation
+    #[cfg(feature = "ipfs")]
+    #[builder(setter(skip))]
+    ipfs: OnceLock<IpfsBackend>,
+    /// Permanent store [ArchiveBackends::Arweave] uploads records to instead of an Arweave
+    /// gateway, set with [ArchiveStoreBuilder::permanent_store]
+    #[cfg(feature = "arweave")]
+    #[builder(default, setter(custom))]
+    permanent_store: Option<Arc<dyn PermanentStore>>,
+    /// The Arweave backend, and so its indexes and wallet, reused by every operation
+    #[cfg(feature = "arweave")]
+    #[builder(setter(skip))]
+    arweave: OnceLock<ArweaveBackend>,
+    /// The backend registered for [ArchiveBackends::Custom], reused by every operation
+    #[builder(setter(skip))]
+    custom: OnceLock<Arc<dyn DynBackend>>,
+    /// The namespace this store holds the records of, for stores returned by
+    /// [ArchiveStore::namespace]
+    #[builder(setter(skip))]
+    namespace: Option<String>,
+    /// The store a namespace's store was derived from. Weak, as that store keeps its namespaces'
+    /// stores alive.
+    #[builder(setter(skip))]
+    parent: Weak<ArchiveStoreInner>,
+    /// The stores of the namespaces used so far, so they share their backends between calls to
+    /// [ArchiveStore::namespace]
+    #[builder(setter(skip))]
+    namespaces: Mutex<HashMap<String, Arc<ArchiveStoreInner>>>,
 }
 
 impl ArchiveStore {
     /// Persists a new archive record of [ArchiveRecordType] in the selected archive backend.
-    pub async fn create<T: Serialize>(
-        &mut self,
+    ///
+    /// When a spill directory is configured and the backend write fails, the record is appended
+    /// to a local spill file instead and [CreateOutcome::Spilled] is returned, so the record isn't
+    /// lost while the backend is unavailable. Spilled records are written to the backend by
+    /// [ArchiveStore::drain_spill], or in the background when
+    /// [ArchiveStoreBuilder::spill_drain_interval] is set. Records that can't be serialised are
+    /// never spilled. With a [RetryPolicy], the write is retried before the record is spilled.
+    ///
+    /// When a dead-letter directory is configured, records that can't be serialised, are too
+    /// large or are rejected by the backend are captured in a local dead-letter file instead and
+    /// [CreateOutcome::DeadLettered] is returned, see [ArchiveStore::dead_letters].
+    pub async fn create<T>(
+        &self,
         rec_type: ArchiveRecordType,
         rec: T,
-    ) -> Result<String>
+    ) -> Result<CreateOutcome, ArchiveError>
     where
-        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+        T: Serialize + Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        if self.inner.mirrors.is_empty() {
+            return self.create_unmirrored(rec_type, rec).await;
+        }
+        let doc = match mirror::with_id(&rec) {
+            Ok(doc) => doc,
+            Err(e) if self.inner.dead_letter_dir.is_some() => {
+                let id = self
+                    .dead_letter_record(&rec_type, &rec, e.into_inner())
+                    .await?;
+                return Ok(CreateOutcome::DeadLettered(id));
+            }
+            Err(e) => return Err(e),
+        };
+        self.mirrored_write("create", |store| {
+            store.create_unmirrored(rec_type.clone(), doc.clone())
+        })
+        .await
+    }
+
+    /// [ArchiveStore::create] on this store's own backend, without its mirrors.
+    async fn create_unmirrored<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+    ) -> Result<CreateOutcome, ArchiveError>
+    where
+        T: Serialize + Borrow<T> + std::marker::Send + std::marker::Sync,
     {
-        match self.backend {
-            ArchiveBackends::MongoDB => {
-                // Call the MongoDB backend
-                let mut backend = MongoDBBackend {
-                    uri: self.uri.clone(),
-                    datastore: self.datastore.clone(),
-                };
-                backend
-                    .create(rec_type, rec)
+        self.observe("create", &rec_type, || async {
+            let doc = match self.encode(&rec_type, &rec) {
+                Ok(doc) => doc,
+                Err(e) if self.inner.dead_letter_dir.is_some() => {
+                    let id = self.dead_letter_record(&rec_type, &rec, e).await?;
+                    return Ok((CreateOutcome::DeadLettered(id), 0));
+                }
+                Err(e) => return Err(e),
+            };
+            let bytes = encoded_size(&doc);
+            if self.inner.spill_dir.is_none() && self.inner.dead_letter_dir.is_none() {
+                let id = self.write_unique(rec_type.clone(), doc).await?;
+                trace_ids(&[&id]);
+                return Ok((CreateOutcome::Created(id), bytes));
+            }
+            let written = if self.inner.spill_dir.is_none() {
+                // Failures that aren't dead-lettered are retried with the whole operation.
+                self.write_unique(rec_type.clone(), doc.clone())
                     .await
-                    .context("Creating new MongoDB blob.")
+                    .map_err(ArchiveError::from)
+            } else {
+                // Retry the write here rather than the whole operation, so that the record is
+                // only spilled once the retries are used up.
+                self.retrying("create", || {
+                    self.write_unique(rec_type.clone(), doc.clone())
+                })
+                .await
+            };
+            match written {
+                Ok(id) => {
+                    trace_ids(&[&id]);
+                    Ok((CreateOutcome::Created(id), bytes))
+                }
+                Err(e) if self.inner.dead_letter_dir.is_some() && dead_letter::rejected(&e) => {
+                    let id = self
+                        .dead_letter_encoded(&rec_type, &doc, e.into_inner())
+                        .await?;
+                    Ok((CreateOutcome::DeadLettered(id), bytes))
+                }
+                Err(e) if self.inner.spill_dir.is_some() => {
+                    let path = self.spill(rec_type.clone(), doc, e.into_inner()).await?;
+                    Ok((CreateOutcome::Spilled(path), bytes))
+                }
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await
+    }
+
+    /// Replays the records spilled by [ArchiveStore::create] to the backend in the order they were
+    /// spilled, returning the number written. Records are removed from the spill file once
+    /// written. If a write fails, draining stops, the records not yet written are kept for a later
+    /// call and the error is returned, so this is safe to call repeatedly, e.g. on a timer.
+    ///
+    /// Delivery is at least once: the spill file is only updated once draining stops, so if the
+    /// process crashes part way through, records that were already written are written again by
+    /// the next drain. Does nothing when no spill directory is configured.
+    ///
+    /// When a dead-letter directory is configured, records the backend rejects are moved to the
+    /// dead-letter file rather than stopping every drain, see [ArchiveStore::dead_letters].
+    pub async fn drain_spill(&self) -> Result<u64, ArchiveError> {
+        let path = match &self.inner.spill_dir {
+            Some(dir) => spill::path(dir, &self.inner.datastore),
+            None => return Ok(0),
+        };
+        let _guard = self.inner.spill_lock.lock().await;
+
+        let mut entries: Vec<SpillEntry> = spill::read(&path).await?;
+        let mut drained = 0;
+        let mut done = 0;
+        let mut failure = None;
+        for entry in &entries {
+            let doc = match entry.record() {
+                Ok(doc) => doc,
+                Err(e) => {
+                    failure = Some(e);
+                    break;
+                }
+            };
+            match self
+                .write_unique(entry.record_type.clone(), doc.clone())
+                .await
+            {
+                Ok(_) => drained += 1,
+                Err(e) => {
+                    let e = ArchiveError::from(e);
+                    if self.inner.dead_letter_dir.is_none() || !dead_letter::rejected(&e) {
+                        failure = Some(e.into());
+                        break;
+                    }
+                    self.dead_letter_encoded(&entry.record_type, &doc, e.into_inner())
+                        .await?;
+                }
             }
+            done += 1;
+        }
+
+        entries.drain(..done);
+        spill::rewrite(&path, &entries).await?;
+        match failure {
+            Some(e) => Err(e
+                .context(format!(
+                    "Draining spill file {}, {} records remain",
+                    path.display(),
+                    entries.len()
+                ))
+                .into()),
+            None => Ok(drained as u64),
         }
     }
-    pub async fn find_all<T: DeserializeOwned>(
-        &mut self,
+
+    /// Appends a record whose write failed with `error` to the spill file, returning its path.
+    async fn spill(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: Document,
+        error: anyhow::Error,
+    ) -> Result<PathBuf> {
+        let dir = self
+            .inner
+            .spill_dir
+            .as_ref()
+            .context("No spill directory configured")?;
+        let path = spill::path(dir, &self.inner.datastore);
+        warn!(
+            "Spilling {:?} record to {} after failed write: {:#}",
+            rec_type,
+            path.display(),
+            error
+        );
+
+        let _guard = self.inner.spill_lock.lock().await;
+        spill::append(&path, &SpillEntry::new(rec_type, rec))
+            .await
+            .with_context(|| format!("Spilling record after failed write: {:#}", error))?;
+        Ok(path)
+    }
+
+    /// Persists a batch of archive records of [ArchiveRecordType] in bulk, which is much faster
+    /// than calling [ArchiveStore::create] for each. Returns the ids of the records in the order
+    /// given. Whether the batch is inserted in order is set with
+    /// [ArchiveStoreBuilder::ordered_inserts]. The batch is not atomic: if it fails part way,
+    /// some records may have been archived, see [ArchiveStore::create_atomic] if that matters.
+    /// Batches are neither deduplicated nor spilled.
+    pub async fn create_many<T>(
+        &self,
         rec_type: ArchiveRecordType,
-    ) -> Result<Vec<T>>
+        recs: Vec<T>,
+    ) -> Result<Vec<String>, ArchiveError>
     where
-        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+        T: Serialize + Borrow<T> + std::marker::Send + std::marker::Sync,
     {
-        match self.backend {
-            ArchiveBackends::MongoDB => {
-                // Call the MongoDB backend
-                let mut backend = MongoDBBackend {
-                    uri: self.uri.clone(),
-                    datastore: self.datastore.clone(),
-                };
-                backend
-                    .find_all(rec_type)
-                    .await
-                    .context("Retrieving blobs from MongoDB")
+        if self.inner.mirrors.is_empty() {
+            return self.create_many_unmirrored(rec_type, recs).await;
+        }
+        let docs = recs
+            .iter()
+            .map(mirror::with_id)
+            .collect::<Result<Vec<_>, _>>()?;
+        self.mirrored_write("create_many", |store| {
+            store.create_many_unmirrored(rec_type.clone(), docs.clone())
+        })
+        .await
+    }
+
+    /// [ArchiveStore::create_many] on this store's own backend, without its mirrors.
+    async fn create_many_unmirrored<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        recs: Vec<T>,
+    ) -> Result<Vec<String>, ArchiveError>
+    where
+        T: Serialize + Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        self.observe("create_many", &rec_type, || async {
+            let mut docs = Vec::with_capacity(recs.len());
+            let mut bytes = 0;
+            for rec in &recs {
+                let doc = self.encode(&rec_type, rec)?;
+                bytes += encoded_size(&doc);
+                docs.push(doc);
+            }
+            if docs.is_empty() {
+                return Ok((Vec::new(), 0));
+            }
+
+            self.backend()?
+                .create_many(rec_type.clone(), docs)
+                .await
+                .with_context(|| format!("Creating new {} blobs.", self.inner.backend))
+                .map(|ids| {
+                    trace_ids(&ids);
+                    if let Some(cache) = &self.inner.cache {
+                        for id in &ids {
+                            cache.invalidate(&rec_type, id);
+                        }
+                    }
+                    self.publish(&rec_type, ArchiveEventKind::Created, &ids);
+                    (ids, bytes)
+                })
+        })
+        .await
+    }
+
+    /// Persists several archive records, possibly of different record types, atomically: either
+    /// every record is archived or, if any write fails, none are. Returns the ids of the records
+    /// in the order given. Records are passed pre-serialised, e.g. with [bson::to_document], so
+    /// that records of different types can be archived together.
+    ///
+    /// On MongoDB the records are written within a multi-document transaction, which requires a
+    /// replica set or sharded cluster, so a standalone server fails with [Unsupported]. The
+    /// filesystem and S3 backends have no transactions, so they stage the whole batch in a single
+    /// file, or object, before writing its records one by one, and delete the records already
+    /// written if a write fails. A batch left staged by a crash is written in full before the
+    /// backend's next atomic write, or by [ArchiveStore::recover_atomic_batches]; until then
+    /// reads may see part of it. The IPFS and Arweave backends fail with [Unsupported]. Atomic
+    /// writes are neither deduplicated nor spilled.
+    pub async fn create_atomic(
+        &self,
+        records: Vec<(ArchiveRecordType, Document)>,
+    ) -> Result<Vec<String>, ArchiveError> {
+        let rec_type = match records.first() {
+            Some((rec_type, _)) => rec_type.clone(),
+            None => return Ok(Vec::new()),
+        };
+        self.observe("create_atomic", &rec_type, || async {
+            let mut encoded = Vec::with_capacity(records.len());
+            let mut bytes = 0;
+            for (rec_type, rec) in &records {
+                let doc = self.encode(rec_type, rec)?;
+                bytes += encoded_size(&doc);
+                encoded.push((rec_type.clone(), doc));
+            }
+
+            self.backend()?
+                .create_atomic(encoded)
+                .await
+                .with_context(|| format!("Atomically creating {} blobs", self.inner.backend))
+                .map(|ids| {
+                    trace_ids(&ids);
+                    for ((rec_type, _), id) in records.iter().zip(&ids) {
+                        if let Some(cache) = &self.inner.cache {
+                            cache.invalidate(rec_type, id);
+                        }
+                        self.publish(rec_type, ArchiveEventKind::Created, &[id]);
+                    }
+                    (ids, bytes)
+                })
+        })
+        .await
+    }
+
+    /// Persists several related records, possibly of different record types, atomically like
+    /// [ArchiveStore::create_atomic], e.g. an account snapshot along with the transaction batch
+    /// that produced it, so that a crash never leaves one archived without the other. Returns the
+    /// ids of the records in the order given. Records of different types can be given as
+    /// [bson::Document]s, or as the variants of an enum.
+    pub async fn create_batch_atomic<T>(
+        &self,
+        records: Vec<(ArchiveRecordType, T)>,
+    ) -> Result<Vec<String>, ArchiveError>
+    where
+        T: Serialize,
+    {
+        let records = records
+            .into_iter()
+            .map(|(rec_type, rec)| {
+                let doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+                Ok((rec_type, doc))
+            })
+            .collect::<Result<_>>()?;
+        self.create_atomic(records).await
+    }
+
+    /// Writes in full the atomic batches that the filesystem and S3 backends left staged when
+    /// the process crashed part way through [ArchiveStore::create_atomic], returning how many
+    /// were written. This happens anyway before the backend's next atomic write, so only needs
+    /// calling for reads to see whole batches before then, e.g. at startup before any atomic
+    /// writes. Other backends have nothing to recover.
+    pub async fn recover_atomic_batches(&self) -> Result<u64, ArchiveError> {
+        Ok(match self.inner.backend {
+            #[cfg(feature = "s3")]
+            ArchiveBackends::S3 => self
+                .s3()
+                .recover()
+                .await
+                .context("Recovering S3 atomic batches")?,
+            ArchiveBackends::Filesystem { ref root } => self
+                .filesystem(root)
+                .recover()
+                .await
+                .context("Recovering filesystem atomic batches")?,
+            _ => 0,
+        })
+    }
+
+    /// Persists a new archive record like [ArchiveStore::create], additionally storing a SHA-256
+    /// checksum of the record's canonical serialised form alongside it. Returns the id of the
+    /// new record and its checksum, which can be checked later with [ArchiveStore::verify].
+    pub async fn create_with_checksum<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+    ) -> Result<(String, String), ArchiveError>
+    where
+        T: Serialize + Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        self.observe("create_with_checksum", &rec_type, || async {
+            let mut rec_doc =
+                bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+            Provenance::take(&mut rec_doc);
+            let sum = checksum::compute(&rec_doc);
+            let mut doc = self.encode(&rec_type, &rec)?;
+            doc.insert(checksum::CHECKSUM_FIELD, &sum);
+            let bytes = encoded_size(&doc);
+            let id = self.write_unique(rec_type.clone(), doc).await?;
+            trace_ids(&[&id]);
+            Ok(((id, sum), bytes))
+        })
+        .await
+    }
+
+    /// Persists a new archive record of [ArchiveRecordType] under a caller-supplied id, e.g. a
+    /// transaction hash or an account address and block height, rather than one generated by the
+    /// backend, returning the id. Archiving the same record again, e.g. when replaying after a
+    /// crash, then finds it already stored rather than storing a second copy, and succeeds unless
+    /// [ArchiveStoreBuilder::ignore_duplicate_ids] is disabled. Ids of 24 hex digits are stored as
+    /// ObjectIds.
+    ///
+    /// The S3, RocksDB and filesystem backends replace a record already stored under the id
+    /// instead, so never fail with [ArchiveError::DuplicateKey]. Records are not spilled.
+    pub async fn create_with_id<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Serialize + Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        if id.is_empty() {
+            return Err(ArchiveError::invalid_input("Record ids must not be empty"));
+        }
+        let mut doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+        match bson::oid::ObjectId::parse_str(id) {
+            Ok(oid) => doc.insert("_id", oid),
+            Err(_) => doc.insert("_id", id),
+        };
+        // The hot tier's backend only sees its own ids, so records already moved to the cold
+        // tier are found there.
+        if let Some(cold) = self.cold_tier() {
+            let moved = cold
+                .find_envelope_by_id_untiered::<Document>(rec_type.clone(), id)
+                .await?;
+            if moved.is_some() {
+                if self.inner.ignore_duplicate_ids {
+                    debug!("Record {} is already archived in the cold tier", id);
+                    return Ok(id.to_string());
+                }
+                return Err(ArchiveError::DuplicateKey(anyhow!(
+                    "A {:?} record with id {} is already in the cold tier",
+                    rec_type,
+                    id
+                )));
+            }
+        }
+        self.mirrored_write("create_with_id", |store| {
+            store.create_with_id_unmirrored(rec_type.clone(), id, doc.clone())
+        })
+        .await
+    }
+
+    /// [ArchiveStore::create_with_id] on this store's own backend, without its mirrors.
+    async fn create_with_id_unmirrored(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        rec: Document,
+    ) -> Result<String, ArchiveError> {
+        self.observe("create_with_id", &rec_type, || async {
+            trace_ids(&[id]);
+            let doc = self.encode(&rec_type, &rec)?;
+            let bytes = encoded_size(&doc);
+            match self
+                .write(rec_type.clone(), doc)
+                .await
+                .map_err(ArchiveError::from)
+            {
+                Ok(id) => Ok((id, bytes)),
+                // Also covers a retry of a write that succeeded but whose response was lost.
+                Err(e)
+                    if e.kind() == ArchiveErrorKind::DuplicateKey
+                        && self.inner.ignore_duplicate_ids =>
+                {
+                    debug!("Record {} is already archived", id);
+                    Ok((id.to_string(), 0))
+                }
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await
+    }
+
+    /// Re-reads the record with the given id and checks that it still matches the checksum it
+    /// was archived with by [ArchiveStore::create_with_checksum].
+    pub async fn verify(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<VerificationResult, ArchiveError> {
+        self.observe("verify", &rec_type, || async {
+            trace_ids(&[id]);
+            let doc = self
+                .backend()?
+                .find_by_id(rec_type.clone(), id)
+                .await
+                .with_context(|| format!("Retrieving blob from {}", self.inner.backend))?;
+
+            let result = match doc {
+                Some(doc) => self.verify_document(doc)?,
+                None => VerificationResult::Missing,
+            };
+            Ok((result, 0))
+        })
+        .await
+    }
+
+    /// Verifies every record of [ArchiveRecordType] against its checksum, returning a summary
+    /// that lists the ids of any records that no longer match. Intended for periodic audit jobs.
+    pub async fn verify_all(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<VerificationSummary, ArchiveError> {
+        self.observe("verify_all", &rec_type, || async {
+            let mut docs = self
+                .backend()?
+                .find_all_stream(rec_type.clone())
+                .await
+                .with_context(|| format!("Retrieving blobs from {}", self.inner.backend))?;
+
+            // Stream the records, as audits run over entire, potentially very large, archives.
+            let mut summary = VerificationSummary::default();
+            while let Some(doc) = docs.try_next().await? {
+                let id = filter::id_to_string(doc.get("_id").unwrap_or(&Bson::Null));
+                summary.checked += 1;
+                match self.verify_document(doc)? {
+                    VerificationResult::Match => summary.matched += 1,
+                    VerificationResult::NoChecksum => summary.unchecksummed += 1,
+                    _ => summary.mismatched.push(id),
+                }
+            }
+            Ok((summary, 0))
+        })
+        .await
+    }
+
+    /// Checks a document read from the backend against the checksum stored with it.
+    fn verify_document(&self, mut doc: Document) -> Result<VerificationResult> {
+        Migrations::take_version(&mut doc);
+        Provenance::take(&mut doc);
+        let expected = match doc.remove(checksum::CHECKSUM_FIELD) {
+            Some(Bson::String(sum)) => Some(sum),
+            _ => None,
+        };
+        let doc = encryption::decrypt(doc, self.inner.encryption.as_ref())?;
+        let doc: Document = compression::decompress(doc)?;
+        Ok(checksum::verify(&doc, expected))
+    }
+
+    /// Returns a handle on this store whose operations carry the given labels (in addition to
+    /// any labels already on this handle, which are replaced if the keys match), e.g.
+    /// `store.with_labels(&[("tenant", "acme"), ("job", "nightly_sync")])`. Labels are included in
+    /// the log output, `tracing` spans and [ArchiveMetrics] reports of every operation performed
+    /// through the returned handle. Keep label values
+    /// to a small, bounded set, see [Labels].
+    pub fn with_labels(&self, labels: &[(&str, &str)]) -> ArchiveStore {
+        ArchiveStore {
+            inner: self.inner.clone(),
+            labels: self.labels.with(labels),
+            tags: self.tags.clone(),
+            include_tombstones: self.include_tombstones,
+            #[cfg(feature = "mongodb")]
+            mongodb_override: self.mongodb_override.clone(),
+        }
+    }
+
+    /// The labels carried by operations performed through this handle.
+    pub fn labels(&self) -> &Labels {
+        &self.labels
+    }
+
+    /// Returns a handle on this store that archives the given tags (in addition to any tags
+    /// already on this handle) with every record written through it, e.g.
+    /// `store.with_tags(&["backfill"])`. Tags are stored in each record's [ArchiveEnvelope] and
+    /// can be filtered on with [Filter::tagged]. They must not be empty, contain `.` or start with
+    /// `$`, otherwise writes fail with [ArchiveError::InvalidInput].
+    pub fn with_tags(&self, tags: &[&str]) -> ArchiveStore {
+        let mut store = self.clone();
+        for tag in tags {
+            if !store.tags.iter().any(|t| t == tag) {
+                store.tags.push(tag.to_string());
+            }
+        }
+        store
+    }
+
+    /// Returns this store's backend, creating it on first use, for operations every backend
+    /// performs. Backend-specific operations use the accessors for each backend below.
+    fn backend(&self) -> Result<&dyn DynBackend, ArchiveError> {
+        Ok(match self.inner.backend {
+            #[cfg(feature = "mongodb")]
+            ArchiveBackends::MongoDB => self.mongodb(),
+            #[cfg(feature = "postgres")]
+            ArchiveBackends::Postgres => self.postgres(),
+            #[cfg(feature = "sqlite")]
+            ArchiveBackends::Sqlite => self.sqlite(),
+            #[cfg(feature = "s3")]
+            ArchiveBackends::S3 => self.s3(),
+            #[cfg(feature = "rocksdb")]
+            ArchiveBackends::RocksDb => self.rocksdb(),
+            ArchiveBackends::Filesystem { ref root } => self.filesystem(root),
+            ArchiveBackends::InMemory => self.memory(),
+            #[cfg(feature = "ipfs")]
+            ArchiveBackends::Ipfs { ref index } => self.ipfs(index),
+            #[cfg(feature = "arweave")]
+            ArchiveBackends::Arweave { ref index } => self.arweave(index),
+            ArchiveBackends::Custom { ref name } => self.custom(name)?,
+        })
+    }
+
+    /// Returns the MongoDB backend for this store's datastore, creating it on first use. The
+    /// backend is shared by every clone of the store, so its client is only created once.
+    /// Handles made for per-call options use a backend of their own that shares the client.
+    #[cfg(feature = "mongodb")]
+    fn mongodb(&self) -> &MongoDBBackend {
+        if let Some(mongodb) = &self.mongodb_override {
+            return mongodb;
+        }
+        self.inner.mongodb.get_or_init(|| MongoDBBackend {
+            uri: self.inner.uri.clone(),
+            datastore: self.inner.datastore.clone(),
+            credentials: self.inner.credentials.clone(),
+            client: OnceCell::new_with(self.inner.client.clone()),
+            chunk_threshold: self.inner.chunk_threshold,
+            ordered_inserts: self.inner.ordered_inserts,
+            write_concern: self.inner.write_concern.clone(),
+            read_preference: self.inner.read_preference.clone(),
+            indexed: Arc::new(Mutex::new(HashSet::new())),
+        })
+    }
+
+    /// Returns the PostgreSQL backend for this store's datastore, creating it on first use. The
+    /// backend is shared by every clone of the store, so its connection pool is only created once.
+    #[cfg(feature = "postgres")]
+    fn postgres(&self) -> &PostgresBackend {
+        self.inner.postgres.get_or_init(|| {
+            PostgresBackend::new(
+                &self.inner.uri,
+                &self.inner.datastore,
+                self.inner.credentials.clone(),
+            )
+        })
+    }
+
+    /// Returns the SQLite backend for this store's datastore, creating it on first use. The
+    /// backend is shared by every clone of the store, so the database is only opened once.
+    #[cfg(feature = "sqlite")]
+    fn sqlite(&self) -> &SqliteBackend {
+        self.inner
+            .sqlite
+            .get_or_init(|| SqliteBackend::new(&self.inner.uri, &self.inner.datastore))
+    }
+
+    /// Returns the S3 backend for this store's bucket, creating it on first use. The backend is
+    /// shared by every clone of the store, so its client is only created once.
+    #[cfg(feature = "s3")]
+    fn s3(&self) -> &S3Backend {
+        self.inner.s3.get_or_init(|| S3Backend {
+            namespace: self.inner.namespace.clone(),
+            ..S3Backend::new(
+                &self.inner.uri,
+                &self.inner.datastore,
+                self.inner.credentials.clone(),
+            )
+        })
+    }
+
+    /// Returns the RocksDB backend for this store's datastore, creating it on first use. The
+    /// backend is shared by every clone of the store, so the database is only opened once.
+    #[cfg(feature = "rocksdb")]
+    fn rocksdb(&self) -> &RocksDbBackend {
+        self.inner
+            .rocksdb
+            .get_or_init(|| RocksDbBackend::new(&self.inner.uri, &self.inner.datastore))
+    }
+
+    /// Returns the filesystem backend for the given root directory, creating it on first use.
+    fn filesystem(&self, root: &Path) -> &FilesystemBackend {
+        self.inner
+            .filesystem
+            .get_or_init(|| FilesystemBackend::new(root, self.inner.file_format))
+    }
+
+    /// Returns the in-memory backend, creating it on first use.
+    fn memory(&self) -> &MemoryBackend {
+        self.inner
+            .memory
+            .get_or_init(|| MemoryBackend::new(self.inner.chunk_threshold))
+    }
+
+    /// Returns the IPFS backend keeping its CID indexes in the given directory, creating it on
+    /// first use.
+    #[cfg(feature = "ipfs")]
+    fn ipfs(&self, index: &Path) -> &IpfsBackend {
+        self.inner.ipfs.get_or_init(|| {
+            let store = self
+                .inner
+                .content_store
+                .clone()
+                .unwrap_or_else(|| Arc::new(IpfsHttpStore::new(&self.inner.uri)));
+            IpfsBackend::new(index, store)
+        })
+    }
+
+    /// Returns the Arweave backend keeping its indexes in the given directory, creating it on
+    /// first use.
+    #[cfg(feature = "arweave")]
+    fn arweave(&self, index: &Path) -> &ArweaveBackend {
+        self.inner.arweave.get_or_init(|| {
+            let store = self
+                .inner
+                .permanent_store
+                .clone()
+                .unwrap_or_else(|| Arc::new(ArweaveGateway::new(&self.inner.uri)));
+            ArweaveBackend::new(index, &self.inner.datastore, store)
+        })
+    }
+
+    /// Serialises a record into the document handed to the backend, compressing and encrypting
+    /// (if enabled) and size checking it and recording its provenance, schema version and, if
+    /// enabled, its checksum.
+    fn encode<T: Serialize>(&self, rec_type: &ArchiveRecordType, rec: &T) -> Result<Document> {
+        // Oversized records are fine when chunking is enabled, as the backend splits them.
+        let limit = match self.inner.chunk_threshold {
+            Some(_) => None,
+            None => self.inner.backend.max_document_size(),
+        };
+        // Atomic batches may hold several record types, only the first of which is checked
+        // when the operation starts.
+        rec_type.validate().map_err(ArchiveError::invalid_input)?;
+        let mut rec = bson::to_document(rec).context("Failed to serialise record to BSON")?;
+        let mut provenance = Provenance::take(&mut rec);
+        provenance.node_id = provenance.node_id.or_else(|| self.inner.node_id.clone());
+        for tag in &self.tags {
+            if !provenance.tags.contains(tag) {
+                provenance.tags.push(tag.clone());
+            }
+        }
+
+        let mut doc =
+            compression::compress(&rec, &self.inner.compression, self.inner.codec, limit)?;
+        if let Some(encryption) = &self.inner.encryption {
+            doc = encryption::encrypt(doc, encryption)?;
+        }
+        provenance.stamp(&mut doc)?;
+        doc.insert(migration::VERSION_FIELD, self.schema_version(rec_type));
+        if self.inner.checksums || self.inner.content_dedup {
+            doc.insert(checksum::CHECKSUM_FIELD, checksum::compute(&rec));
+        }
+        Ok(doc)
+    }
+
+    /// Writes an encoded record to the backend, returning its id.
+    async fn write(&self, rec_type: ArchiveRecordType, rec: Document) -> Result<String> {
+        // Skip the write entirely if an identical record was written within the dedup window.
+        let dedup_key = match &self.inner.dedup {
+            Some(dedup) => {
+                let key = DedupCache::key(&rec_type, &rec, self.inner.encryption.as_ref())?;
+                if let Some(id) = dedup.get(&key) {
+                    debug!("Skipping duplicate write of {}", id);
+                    return Ok(id);
+                }
+                Some(key)
+            }
+            None => None,
+        };
+
+        let id = self
+            .backend()?
+            .create(rec_type.clone(), rec)
+            .await
+            .with_context(|| format!("Creating new {} blob.", self.inner.backend))?;
+
+        if let (Some(dedup), Some(key)) = (&self.inner.dedup, dedup_key) {
+            dedup.insert(key, id.clone());
+        }
+        // Some backends replace a record already stored under the id.
+        if let Some(cache) = &self.inner.cache {
+            cache.invalidate(&rec_type, &id);
+        }
+        self.publish(&rec_type, ArchiveEventKind::Created, &[&id]);
+        Ok(id)
+    }
+    pub async fn find_all<T>(&self, rec_type: ArchiveRecordType) -> Result<Vec<T>, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        let include = self.include_tombstones;
+        let mut recs = self.find_all_untiered(rec_type.clone(), include).await?;
+        if let Some(cold) = self.cold_tier() {
+            recs.extend(cold.find_all_untiered(rec_type, include).await?);
+        }
+        Ok(recs)
+    }
+
+    /// [ArchiveStore::find_all] on this store's own tier, without its cold tier.
+    async fn find_all_untiered<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        include_tombstones: bool,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        self.mirrored_read("find_all", |store| {
+            store.find_all_unmirrored(rec_type.clone(), include_tombstones)
+        })
+        .await
+    }
+
+    /// [ArchiveStore::find_all] on this store's own backend, without its mirrors.
+    async fn find_all_unmirrored<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        include_tombstones: bool,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        self.observe("find_all", &rec_type, || async {
+            self.backend()?
+                .find_all(rec_type.clone())
+                .await
+                .with_context(|| format!("Retrieving blobs from {}", self.inner.backend))?
+                .into_iter()
+                .filter(|doc| lifecycle::visible(doc, include_tombstones))
+                .map(|doc| self.decode(&rec_type, doc))
+                .collect::<Result<Vec<T>>>()
+                .map(|v| (v, 0))
+        })
+        .await
+    }
+
+    /// Retrieves a page of archived records of [ArchiveRecordType], in id order. Pass the returned
+    /// page's [Page::next_token] in the next request to walk the whole archive a page at a time,
+    /// e.g. starting from `PageRequest::first(100)`. Records archived while walking are included
+    /// if they sort after the current page.
+    pub async fn find_page<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        request: PageRequest,
+    ) -> Result<Page<T>, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        let page = self.find_envelope_page(rec_type, request).await?;
+        Ok(Page {
+            items: page
+                .items
+                .into_iter()
+                .map(|envelope| envelope.record)
+                .collect(),
+            next_token: page.next_token,
+        })
+    }
+
+    /// Retrieves a page of archived records of [ArchiveRecordType] like [ArchiveStore::find_page],
+    /// along with their provenance.
+    pub async fn find_envelope_page<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        request: PageRequest,
+    ) -> Result<Page<ArchiveEnvelope<T>>, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        self.observe("find_page", &rec_type, || async {
+            if request.limit == 0 {
+                return Err(
+                    ArchiveError::invalid_input("Page limit must be greater than zero").into(),
+                );
+            }
+
+            self.backend()?
+                .find_page(rec_type.clone(), &request)
+                .await
+                .with_context(|| format!("Retrieving page of blobs from {}", self.inner.backend))?
+                .try_map(|doc| self.decode_envelope(&rec_type, doc))
+                .map(|page| (page, 0))
+        })
+        .await
+        .map(|mut page| {
+            // Pages may come back short, their tokens still follow on from the whole page.
+            if !self.include_tombstones {
+                page.items
+                    .retain(|envelope| envelope.lifecycle.state == LifecycleState::Archived);
+            }
+            page
+        })
+    }
+
+    /// Retrieves the records of [ArchiveRecordType] matching a [Filter], e.g. a single account's
+    /// archive history with `Filter::eq("address", address)`. Fields of compressed records can't
+    /// be filtered on.
+    pub async fn query<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        let envelopes = self.query_envelopes(rec_type, filter).await?;
+        Ok(envelopes
+            .into_iter()
+            .map(|envelope| envelope.record)
+            .collect())
+    }
+
+    /// Retrieves the records of [ArchiveRecordType] whose `field` lies within `range`, ordered by
+    /// the field, e.g. the blocks between two heights with
+    /// `find_range(ArchiveRecordType::Block, BLOCK_HEIGHT_FIELD, 100i64..200)`. The field is
+    /// indexed the first time it's queried, as by [ArchiveStore::ensure_indexes], unless the
+    /// backend already indexes it. As with [ArchiveStore::query], fields of compressed or chunked
+    /// records can't be queried, and only values of the bounds' type, all numbers being one type,
+    /// are in range.
+    pub async fn find_range<T, V, R>(
+        &self,
+        rec_type: ArchiveRecordType,
+        field: &str,
+        range: R,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: DeserializeOwned,
+        V: Into<Bson> + Clone,
+        R: RangeBounds<V>,
+    {
+        IndexSpec::new(field)
+            .validate()
+            .map_err(ArchiveError::invalid_input)?;
+        self.ensure_index_once(&rec_type, field).await?;
+
+        let mut recs: Vec<Document> = self.query(rec_type, Filter::range(field, range)).await?;
+        recs.sort_by(
+            |a, b| match (filter::lookup(a, field), filter::lookup(b, field)) {
+                (Some(a), Some(b)) => filter::compare(a, b).unwrap_or(Ordering::Equal),
+                _ => Ordering::Equal,
+            },
+        );
+        recs.into_iter()
+            .map(|rec| {
+                bson::from_document(rec)
+                    .context("Failed to deserialise record")
+                    .map_err(ArchiveError::from)
+            })
+            .collect()
+    }
+
+    /// Retrieves the latest archived state of the account whose [ACCOUNT_ADDRESS_FIELD] is
+    /// `address`, or the latest archived before `before`, for the network endpoints.
+    #[cfg(any(feature = "jsonrpc", feature = "graphql"))]
+    pub(crate) async fn find_account_state(
+        &self,
+        address: &str,
+        before: Option<bson::DateTime>,
+    ) -> Result<Option<ArchiveEnvelope<Document>>, ArchiveError> {
+        let mut filter = Filter::eq(ACCOUNT_ADDRESS_FIELD, address);
+        if let Some(before) = before {
+            filter = filter.and(Filter::archived_before(before));
+        }
+        let envelopes = self
+            .query_envelopes::<Document>(ArchiveRecordType::Account, filter)
+            .await?;
+        Ok(envelopes
+            .into_iter()
+            .max_by_key(|envelope| envelope.archived_at))
+    }
+
+    /// Retrieves the records of [ArchiveRecordType] matching a [Filter] like [ArchiveStore::query],
+    /// along with their provenance, e.g. the records archived by a node with
+    /// `Filter::from_node(node_id)`.
+    pub async fn query_envelopes<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<Vec<ArchiveEnvelope<T>>, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        let filter = self.visible(filter);
+        let mut recs = self
+            .query_envelopes_untiered(rec_type.clone(), filter.clone())
+            .await?;
+        if let Some(cold) = self.cold_tier() {
+            recs.extend(cold.query_envelopes_untiered(rec_type, filter).await?);
+        }
+        Ok(recs)
+    }
+
+    /// [ArchiveStore::query_envelopes] on this store's own tier, without its cold tier.
+    async fn query_envelopes_untiered<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<Vec<ArchiveEnvelope<T>>, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        self.mirrored_read("query", |store| {
+            store.query_envelopes_unmirrored(rec_type.clone(), filter.clone())
+        })
+        .await
+    }
+
+    /// [ArchiveStore::query_envelopes] on this store's own backend, without its mirrors.
+    async fn query_envelopes_unmirrored<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<Vec<ArchiveEnvelope<T>>, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        self.observe("query", &rec_type, || async {
+            self.backend()?
+                .query(rec_type.clone(), &filter)
+                .await
+                .with_context(|| {
+                    format!("Querying blobs in {} for {}", self.inner.backend, filter)
+                })?
+                .into_iter()
+                .map(|doc| self.decode_envelope(&rec_type, doc))
+                .collect::<Result<Vec<_>>>()
+                .map(|v| (v, 0))
+        })
+        .await
+    }
+
+    /// Retrieves the record of [ArchiveRecordType] with the given id, as returned by
+    /// [ArchiveStore::create], or `None` if there is no such record.
+    pub async fn find_by_id<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<Option<T>, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        let envelope = self.find_envelope_by_id(rec_type, id).await?;
+        Ok(envelope.map(|envelope| envelope.record))
+    }
+
+    /// Retrieves the record of [ArchiveRecordType] with the given id like
+    /// [ArchiveStore::find_by_id], along with its provenance.
+    pub async fn find_envelope_by_id<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<Option<ArchiveEnvelope<T>>, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        let found = self
+            .find_envelope_by_id_untiered(rec_type.clone(), id)
+            .await?;
+        let found = match (found, self.cold_tier()) {
+            (None, Some(cold)) => cold.find_envelope_by_id_untiered(rec_type, id).await?,
+            (found, _) => found,
+        };
+        Ok(found.filter(|envelope: &ArchiveEnvelope<T>| {
+            self.include_tombstones || envelope.lifecycle.state == LifecycleState::Archived
+        }))
+    }
+
+    /// [ArchiveStore::find_envelope_by_id] on this store's own tier, without its cold tier.
+    async fn find_envelope_by_id_untiered<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<Option<ArchiveEnvelope<T>>, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        self.mirrored_read("find_by_id", |store| {
+            store.find_envelope_by_id_unmirrored(rec_type.clone(), id)
+        })
+        .await
+    }
+
+    /// [ArchiveStore::find_envelope_by_id] on this store's own backend, without its mirrors.
+    async fn find_envelope_by_id_unmirrored<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<Option<ArchiveEnvelope<T>>, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        self.observe("find_by_id", &rec_type, || async {
+            trace_ids(&[id]);
+            let cache = self.inner.cache.as_ref();
+            if let Some(doc) = cache.and_then(|cache| cache.get(&rec_type, id)) {
+                return Ok((Some(self.decode_envelope(&rec_type, doc)?), 0));
+            }
+            let doc = self
+                .backend()?
+                .find_by_id(rec_type.clone(), id)
+                .await
+                .with_context(|| format!("Retrieving blob from {}", self.inner.backend))?;
+
+            if let (Some(cache), Some(doc)) = (cache, &doc) {
+                cache.insert(&rec_type, id, doc.clone());
+            }
+            let rec = match doc {
+                Some(doc) => Some(self.decode_envelope(&rec_type, doc)?),
+                None => None,
+            };
+            Ok((rec, 0))
+        })
+        .await
+    }
+
+    /// Deletes the record of [ArchiveRecordType] with the given id, as returned by
+    /// [ArchiveStore::create], returning whether there was such a record. Deleting a chunked
+    /// record deletes its chunks too.
+    pub async fn delete_by_id(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<bool, ArchiveError> {
+        let deleted = self.delete_by_id_untiered(rec_type.clone(), id).await?;
+        match self.cold_tier() {
+            Some(cold) => Ok(cold.delete_by_id_untiered(rec_type, id).await? || deleted),
+            None => Ok(deleted),
+        }
+    }
+
+    /// [ArchiveStore::delete_by_id] on this store's own tier, without its cold tier.
+    async fn delete_by_id_untiered(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<bool, ArchiveError> {
+        self.mirrored_write("delete_by_id", |store| {
+            store.delete_by_id_unmirrored(rec_type.clone(), id)
+        })
+        .await
+    }
+
+    /// [ArchiveStore::delete_by_id] on this store's own backend, without its mirrors.
+    async fn delete_by_id_unmirrored(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<bool, ArchiveError> {
+        self.observe("delete_by_id", &rec_type, || async {
+            trace_ids(&[id]);
+            let deleted = self
+                .backend()?
+                .delete_by_id(rec_type.clone(), id)
+                .await
+                .with_context(|| format!("Deleting blob from {}", self.inner.backend))?;
+
+            // An identical record written later must be stored again.
+            if let Some(dedup) = &self.inner.dedup {
+                dedup.forget(id);
+            }
+            if let Some(cache) = &self.inner.cache {
+                cache.invalidate(&rec_type, id);
+            }
+            if deleted {
+                self.publish(&rec_type, ArchiveEventKind::Deleted, &[id]);
+            }
+            Ok((deleted, 0))
+        })
+        .await
+    }
+
+    /// Deletes every record of [ArchiveRecordType] matching the [Filter], returning how many were
+    /// deleted. [Filter::All] purges every record of the type. As with [ArchiveStore::query],
+    /// fields of compressed or chunked records can't be filtered on.
+    pub async fn delete_where(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<u64, ArchiveError> {
+        let mut deleted = self
+            .delete_where_untiered(rec_type.clone(), filter.clone())
+            .await?;
+        if let Some(cold) = self.cold_tier() {
+            deleted += cold.delete_where_untiered(rec_type, filter).await?;
+        }
+        Ok(deleted)
+    }
+
+    /// [ArchiveStore::delete_where] on this store's own tier, without its cold tier.
+    async fn delete_where_untiered(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<u64, ArchiveError> {
+        self.mirrored_write("delete_where", |store| {
+            store.delete_where_unmirrored(rec_type.clone(), filter.clone())
+        })
+        .await
+    }
+
+    /// [ArchiveStore::delete_where] on this store's own backend, without its mirrors.
+    async fn delete_where_unmirrored(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<u64, ArchiveError> {
+        self.observe("delete_where", &rec_type, || async {
+            let deleted = self
+                .backend()?
+                .delete_where(rec_type.clone(), &filter)
+                .await
+                .with_context(|| format!("Deleting blobs from {}", self.inner.backend))?;
+
+            // Identical records written later must be stored again.
+            if let Some(dedup) = &self.inner.dedup {
+                dedup.forget_type(&rec_type);
+            }
+            if let Some(cache) = &self.inner.cache {
+                cache.invalidate_type(&rec_type);
+            }
+            Ok((deleted, 0))
+        })
+        .await
+    }
+
+    /// Replaces the record of [ArchiveRecordType] with the given id, e.g. to re-archive an
+    /// account snapshot after a correction, returning whether there was such a record. The record
+    /// keeps its id. Replacements are encoded like [ArchiveStore::create], but never spilled.
+    ///
+    /// Records of types made versioned with [ArchiveStoreBuilder::versioned] aren't replaced, the
+    /// record is archived as a new version under a new id instead, as by [ArchiveStore::create].
+    ///
+    /// Records that aren't [LifecycleState::Archived] aren't updated either, even through a
+    /// handle returned by [ArchiveStore::with_tombstones], so that a tombstone isn't silently
+    /// revived. Restore them with [ArchiveStore::set_lifecycle] first.
+    pub async fn update_by_id<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        rec: T,
+    ) -> Result<bool, ArchiveError>
+    where
+        T: Serialize + Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let live = self
+            .find_envelope_by_id::<Document>(rec_type.clone(), id)
+            .await?
+            .is_some_and(|envelope| envelope.lifecycle.state == LifecycleState::Archived);
+        if !live {
+            return Ok(false);
+        }
+        if self.version_key(&rec_type).is_some() {
+            self.append_version(rec_type, &rec).await?;
+            return Ok(true);
+        }
+        self.replace_by_id(rec_type, id, rec).await
+    }
+
+    /// Replaces the record with the given id in place, in whichever tier holds it, even if its
+    /// type is versioned, e.g. to move the record to another lifecycle state.
+    pub(crate) async fn replace_by_id<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        rec: T,
+    ) -> Result<bool, ArchiveError>
+    where
+        T: Serialize + Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let updated = self
+            .update_by_id_untiered(rec_type.clone(), id, &rec)
+            .await?;
+        match self.cold_tier() {
+            Some(cold) if !updated => cold.update_by_id_untiered(rec_type, id, &rec).await,
+            _ => Ok(updated),
+        }
+    }
+
+    /// [ArchiveStore::update_by_id] on this store's own tier, without its cold tier.
+    async fn update_by_id_untiered<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        rec: T,
+    ) -> Result<bool, ArchiveError>
+    where
+        T: Serialize + Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        self.mirrored_write("update_by_id", |store| {
+            store.update_by_id_unmirrored(rec_type.clone(), id, &rec)
+        })
+        .await
+    }
+
+    /// [ArchiveStore::update_by_id] on this store's own backend, without its mirrors.
+    async fn update_by_id_unmirrored<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        rec: T,
+    ) -> Result<bool, ArchiveError>
+    where
+        T: Serialize + Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        self.observe("update_by_id", &rec_type, || async {
+            trace_ids(&[id]);
+            let doc = match self.inner.encryption {
+                // Encrypted replacements are bound to the id they are stored under.
+                Some(_) => {
+                    let mut rec =
+                        bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+                    rec.insert("_id", id);
+                    self.encode(&rec_type, &rec)?
+                }
+                None => self.encode(&rec_type, &rec)?,
+            };
+            let bytes = encoded_size(&doc);
+            let updated = self
+                .backend()?
+                .update_by_id(rec_type.clone(), id, doc)
+                .await
+                .with_context(|| format!("Replacing blob in {}", self.inner.backend))?;
+
+            // The record written under this id has changed.
+            if let Some(dedup) = &self.inner.dedup {
+                dedup.forget(id);
             }
+            if let Some(cache) = &self.inner.cache {
+                cache.invalidate(&rec_type, id);
+            }
+            if updated {
+                self.publish(&rec_type, ArchiveEventKind::Updated, &[id]);
+            }
+            Ok((updated, bytes))
+        })
+        .await
+    }
+
+    /// Replaces the first record of [ArchiveRecordType] matching the [Filter], or archives the
+    /// record as a new one if none match, returning the id of the replaced or new record. As with
+    /// [ArchiveStore::query], fields of compressed or chunked records can't be filtered on.
+    ///
+    /// Mirrors are upserted by the same filter. A record without an `_id` that matches nothing
+    /// is archived under a different id by the store and each of its mirrors.
+    ///
+    /// Records of types made versioned with [ArchiveStoreBuilder::versioned] are always archived
+    /// as a new version, whether or not the filter matches, returning the new version's id.
+    pub async fn upsert<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Serialize + Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        if self.version_key(&rec_type).is_some() {
+            return self.append_version(rec_type, &rec).await;
+        }
+        self.mirrored_write("upsert", |store| {
+            store.upsert_unmirrored(rec_type.clone(), filter.clone(), &rec)
+        })
+        .await
+    }
+
+    /// [ArchiveStore::upsert] on this store's own backend, without its mirrors.
+    async fn upsert_unmirrored<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Serialize + Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        self.observe("upsert", &rec_type, || async {
+            let doc = self.encode(&rec_type, &rec)?;
+            let bytes = encoded_size(&doc);
+            let id = self
+                .backend()?
+                .upsert(rec_type.clone(), &filter, doc)
+                .await
+                .with_context(|| format!("Upserting blob in {}", self.inner.backend))?;
+
+            // The record written under this id may have changed.
+            if let Some(dedup) = &self.inner.dedup {
+                dedup.forget(&id);
+            }
+            if let Some(cache) = &self.inner.cache {
+                cache.invalidate(&rec_type, &id);
+            }
+            trace_ids(&[&id]);
+            self.publish(&rec_type, ArchiveEventKind::Updated, &[&id]);
+            Ok((id, bytes))
+        })
+        .await
+    }
+
+    /// Streams every archived record of [ArchiveRecordType], reading them from the backend
+    /// incrementally rather than collecting them into memory like [ArchiveStore::find_all]. Use
+    /// this to process archives too large to hold in memory at once, e.g. with
+    /// [futures::TryStreamExt::try_next]. Only opening the stream is reported as the operation,
+    /// failures reading records are yielded by the stream.
+    pub async fn find_all_stream<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<impl Stream<Item = Result<T, ArchiveError>> + '_, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        self.query_stream(rec_type, Filter::All).await
+    }
+
+    /// Streams the archived records of [ArchiveRecordType] matching the [Filter] from both tiers,
+    /// like [ArchiveStore::find_all_stream]. Records are matched as they are stored, so as with
+    /// [ArchiveStore::query], fields of compressed records can't be filtered on.
+    pub(crate) async fn query_stream<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<impl Stream<Item = Result<T, ArchiveError>> + '_, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        let envelopes = self.query_envelope_stream(rec_type, filter).await?;
+        Ok(envelopes.map_ok(|envelope| envelope.record))
+    }
+
+    /// Streams the archived records of [ArchiveRecordType] matching the [Filter] from both tiers
+    /// like [ArchiveStore::query_stream], along with their provenance.
+    pub(crate) async fn query_envelope_stream<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<impl Stream<Item = Result<ArchiveEnvelope<T>, ArchiveError>> + '_, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        let filter = self.visible(filter);
+        let hot = self
+            .find_all_stream_untiered(rec_type.clone(), filter.clone())
+            .await?;
+        let cold = match self.cold_tier() {
+            Some(cold) => Some(cold.find_all_stream_untiered(rec_type, filter).await?),
+            None => None,
+        };
+        Ok(hot.chain(futures::stream::iter(cold).flatten()))
+    }
+
+    /// [ArchiveStore::query_envelope_stream] on this store's own tier, without its cold tier.
+    async fn find_all_stream_untiered<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<impl Stream<Item = Result<ArchiveEnvelope<T>, ArchiveError>> + '_, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        self.mirrored_read("find_all_stream", |store| {
+            store.find_all_stream_unmirrored(rec_type.clone(), filter.clone())
+        })
+        .await
+    }
+
+    /// [ArchiveStore::query_envelope_stream] on this store's own backend, without its mirrors.
+    /// Backends can only stream every record, so records are filtered as they are read.
+    async fn find_all_stream_unmirrored<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<impl Stream<Item = Result<ArchiveEnvelope<T>, ArchiveError>> + '_, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        self.observe("find_all_stream", &rec_type, || async {
+            let docs = self
+                .backend()?
+                .find_all_stream(rec_type.clone())
+                .await
+                .with_context(|| format!("Streaming blobs from {}", self.inner.backend))?;
+            let rec_type = rec_type.clone();
+            let filter = filter.clone();
+            let records = docs
+                .try_filter(move |doc| futures::future::ready(filter.matches(doc)))
+                .map(move |doc| doc.and_then(|doc| Ok(self.decode_envelope(&rec_type, doc)?)));
+            Ok((records, 0))
+        })
+        .await
+    }
+
+    /// Retrieves a random sample of roughly `rate` (between 0.0 and 1.0) of the archived records
+    /// of [ArchiveRecordType]. Every record is selected independently with probability `rate`, so
+    /// the number of records returned is approximate and will vary between calls. Useful for
+    /// sampling based analytics over very large collections, where fetching everything with
+    /// [ArchiveStore::find_all] would be too expensive.
+    pub async fn find_sampled<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rate: f64,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        self.mirrored_read("find_sampled", |store| {
+            store.find_sampled_unmirrored(rec_type.clone(), rate, self.include_tombstones)
+        })
+        .await
+    }
+
+    /// [ArchiveStore::find_sampled] on this store's own backend, without its mirrors.
+    async fn find_sampled_unmirrored<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rate: f64,
+        include_tombstones: bool,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: DeserializeOwned
+            + Borrow<T>
+            + std::marker::Send
+            + std::marker::Sync
+            + std::clone::Clone
+            + Unpin,
+    {
+        self.observe("find_sampled", &rec_type, || async {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(ArchiveError::invalid_input(format!(
+                    "Sample rate must be between 0.0 and 1.0, got {}",
+                    rate
+                ))
+                .into());
+            }
+
+            self.backend()?
+                .find_sampled(rec_type.clone(), rate)
+                .await
+                .with_context(|| format!("Sampling blobs from {}", self.inner.backend))?
+                .into_iter()
+                .filter(|doc| lifecycle::visible(doc, include_tombstones))
+                .map(|doc| self.decode(&rec_type, doc))
+                .collect::<Result<Vec<T>>>()
+                .map(|v| (v, 0))
+        })
+        .await
+    }
+
+    /// Registers a migration upgrading records of [ArchiveRecordType] from schema version
+    /// `from_version` to `from_version + 1`. The migration receives and returns the JSON
+    /// representation of the record. When reading, records written with an older schema version
+    /// are passed through each migration in turn until they reach the record type's current
+    /// version, so a chain of single step migrations covers any older version. Upgraded records
+    /// can be stored in their new form with [ArchiveStore::rewrite_migrated].
+    pub fn register_migration<F>(&self, rec_type: ArchiveRecordType, from_version: u32, f: F)
+    where
+        F: Fn(serde_json::Value) -> Result<serde_json::Value> + Send + Sync + 'static,
+    {
+        self.inner
+            .migrations
+            .register(rec_type, from_version, Arc::new(f));
+    }
+
+    /// The current schema version of records of [ArchiveRecordType].
+    pub fn schema_version(&self, rec_type: &ArchiveRecordType) -> u32 {
+        self.inner
+            .schema_versions
+            .get(rec_type)
+            .copied()
+            .unwrap_or(DEFAULT_SCHEMA_VERSION)
+    }
+
+    /// Turns a document read from the backend back into a record, decrypting, decompressing and
+    /// migrating it to the current schema version as needed.
+    fn decode<T: DeserializeOwned>(
+        &self,
+        rec_type: &ArchiveRecordType,
+        doc: Document,
+    ) -> Result<T> {
+        self.decode_envelope(rec_type, doc)
+            .map(|envelope| envelope.record)
+    }
+
+    /// Decodes a document read from the backend like [ArchiveStore::decode], keeping the
+    /// provenance stored with it.
+    fn decode_envelope<T: DeserializeOwned>(
+        &self,
+        rec_type: &ArchiveRecordType,
+        mut doc: Document,
+    ) -> Result<ArchiveEnvelope<T>> {
+        let version = Migrations::take_version(&mut doc);
+        let provenance = Provenance::take(&mut doc);
+        let archived_at = provenance
+            .archived_at
+            .or_else(|| envelope::object_id(&doc).map(|id| id.timestamp()));
+        // Checksums are only of interest to verification.
+        doc.remove(checksum::CHECKSUM_FIELD);
+        let doc = encryption::decrypt(doc, self.inner.encryption.as_ref())?;
+        let doc: Document = compression::decompress(doc)?;
+        let doc =
+            self.inner
+                .migrations
+                .migrate(rec_type, doc, version, self.schema_version(rec_type))?;
+        Ok(ArchiveEnvelope {
+            record: bson::from_document(doc).context("Failed to deserialise record")?,
+            archived_at,
+            schema_version: version,
+            node_id: provenance.node_id,
+            tags: provenance.tags,
+            lifecycle: provenance.lifecycle,
+        })
+    }
+
+    /// Reports the ids of orphaned chunk groups of [ArchiveRecordType], i.e. chunks of oversized
+    /// records that no record manifest references. Orphans are left behind when a chunked write
+    /// is interrupted after some chunks were stored but before its manifest was, and otherwise
+    /// just consume space. This is a maintenance operation that scans the whole chunk collection.
+    pub async fn find_orphaned_chunks(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Vec<String>, ArchiveError> {
+        self.observe("find_orphaned_chunks", &rec_type, || async {
+            self.backend()?
+                .find_orphaned_chunks(rec_type.clone())
+                .await
+                .with_context(|| format!("Searching for orphaned chunks in {}", self.inner.backend))
+                .map(|v| (v, 0))
+        })
+        .await
+    }
+
+    /// Deletes orphaned chunk groups of [ArchiveRecordType], returning the number of chunks
+    /// removed. This is a maintenance operation. A chunked write that is still in progress has
+    /// not stored its manifest yet and so looks orphaned, so this should not be run while
+    /// oversized records are being archived.
+    pub async fn cleanup_orphans(&self, rec_type: ArchiveRecordType) -> Result<u64, ArchiveError> {
+        self.observe("cleanup_orphans", &rec_type, || async {
+            self.backend()?
+                .cleanup_orphans(rec_type.clone())
+                .await
+                .with_context(|| format!("Deleting orphaned chunks from {}", self.inner.backend))
+                .map(|v| (v, 0))
+        })
+        .await
+    }
+
+    /// Returns the space freed by deleted records of [ArchiveRecordType] to the operating system,
+    /// in both tiers and every mirror, see [ArchiveStore::compact].
+    async fn reclaim_space(&self, rec_type: ArchiveRecordType) -> Result<(), ArchiveError> {
+        self.reclaim_space_untiered(rec_type.clone()).await?;
+        if let Some(cold) = self.cold_tier() {
+            cold.reclaim_space_untiered(rec_type).await?;
+        }
+        Ok(())
+    }
+
+    /// [ArchiveStore::reclaim_space] on this store's own tier, without its cold tier.
+    async fn reclaim_space_untiered(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<(), ArchiveError> {
+        self.mirrored_write("reclaim_space", |store| {
+            store.reclaim_space_unmirrored(rec_type.clone())
+        })
+        .await
+    }
+
+    /// [ArchiveStore::reclaim_space] on this store's own backend, without its mirrors.
+    async fn reclaim_space_unmirrored(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<(), ArchiveError> {
+        self.observe("reclaim_space", &rec_type, || async {
+            self.backend()?
+                .reclaim_space(rec_type.clone())
+                .await
+                .with_context(|| format!("Reclaiming space in {}", self.inner.backend))
+                .map(|v| (v, 0))
+        })
+        .await
+    }
+
+    /// Runs an aggregation `pipeline` over the records of the `source` [ArchiveRecordType] and
+    /// writes its output directly into the collection of the `target` [ArchiveRecordType] on the
+    /// server, without round tripping the results through the client. This is useful for
+    /// maintaining precomputed report or materialised view collections. The pipeline must not
+    /// already end in a `$merge` or `$out` stage, as one is appended according to `mode`, and
+    /// operates on documents as they are stored, so is of little use on compressed records.
+    /// Returns the number of documents output by the pipeline.
+    pub async fn merge_into(
+        &self,
+        source: ArchiveRecordType,
+        pipeline: Vec<Document>,
+        target: ArchiveRecordType,
+        mode: MergeMode,
+    ) -> Result<u64, ArchiveError> {
+        self.observe("merge_into", &source, || async {
+            target.validate().map_err(ArchiveError::invalid_input)?;
+            let merged = self
+                .backend()?
+                .merge_into(
+                    source.clone(),
+                    pipeline.clone(),
+                    target.clone(),
+                    mode.clone(),
+                )
+                .await
+                .with_context(|| {
+                    format!("Merging aggregation results in {}", self.inner.backend)
+                })?;
+
+            // Any record of the target type may have been replaced.
+            if let Some(cache) = &self.inner.cache {
+                cache.invalidate_type(&target);
+            }
+            Ok((merged, 0))
+        })
+        .await
+    }
+
+    /// Counts the records of [ArchiveRecordType] matching the [Filter], without fetching them,
+    /// e.g. to check whether an epoch's transaction batches have all been archived. [Filter::All]
+    /// counts every record. As with [ArchiveStore::query], fields of compressed or chunked
+    /// records can't be filtered on.
+    pub async fn count(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<u64, ArchiveError> {
+        let filter = self.visible(filter);
+        let mut count = self
+            .count_untiered(rec_type.clone(), filter.clone())
+            .await?;
+        if let Some(cold) = self.cold_tier() {
+            count += cold.count_untiered(rec_type, filter).await?;
+        }
+        Ok(count)
+    }
+
+    /// [ArchiveStore::count] on this store's own tier, without its cold tier.
+    async fn count_untiered(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<u64, ArchiveError> {
+        self.mirrored_read("count", |store| {
+            store.count_unmirrored(rec_type.clone(), filter.clone())
+        })
+        .await
+    }
+
+    /// [ArchiveStore::count] on this store's own backend, without its mirrors.
+    async fn count_unmirrored(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<u64, ArchiveError> {
+        self.observe("count", &rec_type, || async {
+            self.backend()?
+                .count(rec_type.clone(), &filter)
+                .await
+                .with_context(|| format!("Counting blobs in {}", self.inner.backend))
+                .map(|v| (v, 0))
+        })
+        .await
+    }
+
+    /// Returns whether any record of [ArchiveRecordType] matches the [Filter], stopping at the
+    /// first match where the backend allows.
+    pub async fn exists(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<bool, ArchiveError> {
+        let filter = self.visible(filter);
+        if self
+            .exists_untiered(rec_type.clone(), filter.clone())
+            .await?
+        {
+            return Ok(true);
         }
+        match self.cold_tier() {
+            Some(cold) => cold.exists_untiered(rec_type, filter).await,
+            None => Ok(false),
+        }
+    }
+
+    /// [ArchiveStore::exists] on this store's own tier, without its cold tier.
+    async fn exists_untiered(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<bool, ArchiveError> {
+        self.mirrored_read("exists", |store| {
+            store.exists_unmirrored(rec_type.clone(), filter.clone())
+        })
+        .await
+    }
+
+    /// [ArchiveStore::exists] on this store's own backend, without its mirrors.
+    async fn exists_unmirrored(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+    ) -> Result<bool, ArchiveError> {
+        self.observe("exists", &rec_type, || async {
+            self.backend()?
+                .exists(rec_type.clone(), &filter)
+                .await
+                .with_context(|| format!("Searching for blobs in {}", self.inner.backend))
+                .map(|v| (v, 0))
+        })
+        .await
+    }
+
+    /// Returns storage statistics for the records of [ArchiveRecordType]: an estimated record
+    /// count, the storage they use and their average size. Record types with nothing archived
+    /// have zeroed statistics. See [ArchiveStore::archive_stats] for the time they span and their
+    /// breakdown by namespace.
+    pub async fn stats(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<ArchiveCollectionStats, ArchiveError> {
+        self.observe("stats", &rec_type, || async {
+            self.backend()?
+                .stats(rec_type.clone())
+                .await
+                .with_context(|| {
+                    format!(
+                        "Retrieving collection statistics from {}",
+                        self.inner.backend
+                    )
+                })
+                .map(|v| (v, 0))
+        })
+        .await
+    }
+
+    /// Counts the records of [ArchiveRecordType] in each group, e.g. the number of transaction
+    /// batches archived per day with `GroupBy::ArchivedAt(Granularity::Day)`. Returns the key of
+    /// each group and its count, ordered by key.
+    pub async fn group_count(
+        &self,
+        rec_type: ArchiveRecordType,
+        group_by: GroupBy,
+    ) -> Result<Vec<(Bson, u64)>, ArchiveError> {
+        self.observe("group_count", &rec_type, || async {
+            self.backend()?
+                .group_count(rec_type.clone(), group_by.clone())
+                .await
+                .with_context(|| format!("Grouping blobs in {}", self.inner.backend))
+                .map(|v| (v, 0))
+        })
+        .await
+    }
+
+    /// Runs an [AggregationSpec] over the records of [ArchiveRecordType], e.g. daily transaction
+    /// counts or per-account activity summaries, without reading every record into the caller.
+    /// Returns a document per group, ordered by key, holding the group's key as `key` and each
+    /// output under its name. Counts and integer sums are 64-bit integers. The MongoDB backend
+    /// runs the aggregation as a pipeline on the server, other backends on the records matching
+    /// the spec's filter as they are read.
+    pub async fn aggregate(
+        &self,
+        rec_type: ArchiveRecordType,
+        mut spec: AggregationSpec,
+    ) -> Result<Vec<Document>, ArchiveError> {
+        spec.validate().map_err(ArchiveError::invalid_input)?;
+        spec.filter = self.visible(spec.filter);
+        self.observe("aggregate", &rec_type, || async {
+            self.backend()?
+                .aggregate(rec_type.clone(), &spec)
+                .await
+                .with_context(|| format!("Aggregating blobs in {}", self.inner.backend))
+                .map(|v| (v, 0))
+        })
+        .await
+    }
+
+    /// Creates the secondary indexes declared for [ArchiveRecordType] that don't exist yet, e.g.
+    /// `IndexSpec::new("transaction_data.block_height")`, so that queries filtering on those
+    /// fields don't scan every record. Meant to be called on startup, as indexes that already
+    /// exist are left as they are. The indexes are created by the store's mirrors and cold tier
+    /// too. Backends without secondary indexes ignore non-unique indexes and fail with
+    /// [Unsupported] for unique ones.
+    pub async fn ensure_indexes(
+        &self,
+        rec_type: ArchiveRecordType,
+        indexes: Vec<IndexSpec>,
+    ) -> Result<(), ArchiveError> {
+        for index in &indexes {
+            index.validate().map_err(ArchiveError::invalid_input)?;
+        }
+        self.ensure_indexes_untiered(rec_type.clone(), &indexes)
+            .await?;
+        if let Some(cold) = self.cold_tier() {
+            cold.ensure_indexes_untiered(rec_type, &indexes).await?;
+        }
+        Ok(())
+    }
+
+    /// Creates an index on `field` of the records of [ArchiveRecordType] like
+    /// [ArchiveStore::ensure_indexes], unless the backends already index the field or this store
+    /// has created the index before.
+    pub(crate) async fn ensure_index_once(
+        &self,
+        rec_type: &ArchiveRecordType,
+        field: &str,
+    ) -> Result<(), ArchiveError> {
+        let key = (rec_type.clone(), field.to_string());
+        let ensured = self
+            .inner
+            .ensured_indexes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains(&key);
+        if ensured || rec_type.indexed_field() == Some(field) {
+            return Ok(());
+        }
+        self.ensure_indexes(rec_type.clone(), vec![IndexSpec::new(field)])
+            .await?;
+        self.inner
+            .ensured_indexes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key);
+        Ok(())
+    }
+
+    /// [ArchiveStore::ensure_indexes] on this store's own tier, without its cold tier.
+    async fn ensure_indexes_untiered(
+        &self,
+        rec_type: ArchiveRecordType,
+        indexes: &[IndexSpec],
+    ) -> Result<(), ArchiveError> {
+        self.mirrored_write("ensure_indexes", |store| {
+            store.ensure_indexes_unmirrored(rec_type.clone(), indexes)
+        })
+        .await
+    }
+
+    /// [ArchiveStore::ensure_indexes] on this store's own backend, without its mirrors.
+    async fn ensure_indexes_unmirrored(
+        &self,
+        rec_type: ArchiveRecordType,
+        indexes: &[IndexSpec],
+    ) -> Result<(), ArchiveError> {
+        self.observe("ensure_indexes", &rec_type, || async {
+            self.backend()?
+                .ensure_indexes(rec_type.clone(), indexes)
+                .await
+                .with_context(|| format!("Creating indexes in {}", self.inner.backend))
+                .map(|v| (v, 0))
+        })
+        .await
     }
 }
 
+impl ArchiveStoreBuilder {
+    /// Builds a new [ArchiveStore].
+    pub fn build(&self) -> Result<ArchiveStore, ArchiveStoreBuilderError> {
+        Ok(ArchiveStore {
+            inner: Arc::new(self.build_inner()?),
+            labels: Labels::default(),
+            tags: Vec::new(),
+            include_tombstones: false,
+            #[cfg(feature = "mongodb")]
+            mongodb_override: None,
+        })
+    }
+
+    /// Sets the schema version written alongside records of [ArchiveRecordType]. Records read
+    /// back with an older version are migrated using the migrations registered with
+    /// [ArchiveStore::register_migration].
+    pub fn schema_version(&mut self, rec_type: ArchiveRecordType, version: u32) -> &mut Self {
+        self.schema_versions
+            .get_or_insert_with(HashMap::new)
+            .insert(rec_type, version);
+        self
+    }
+
+    /// Sets how long records of [ArchiveRecordType] are kept before they are pruned, see
+    /// [ArchiveStore::prune].
+    pub fn retention(&mut self, rec_type: ArchiveRecordType, policy: RetentionPolicy) -> &mut Self {
+        self.retention
+            .get_or_insert_with(HashMap::new)
+            .insert(rec_type, policy);
+        self
+    }
+
+    /// Makes [ArchiveRecordType] append-only: [ArchiveStore::update_by_id] and
+    /// [ArchiveStore::upsert] archive the record as a new version instead of replacing the stored
+    /// one, e.g. `builder.versioned(ArchiveRecordType::Account, ACCOUNT_ADDRESS_FIELD)` to keep
+    /// every snapshot of an account. The versions of a record are those sharing `key_field`, read
+    /// back with [ArchiveStore::history] and [ArchiveStore::latest].
+    pub fn versioned(&mut self, rec_type: ArchiveRecordType, key_field: &str) -> &mut Self {
+        self.versioned
+            .get_or_insert_with(HashMap::new)
+            .insert(rec_type, key_field.to_string());
+        self
+    }
+
+    /// Checks the configuration is usable before the store is built.
+    fn validate(&self) -> Result<(), String> {
+        // Missing required fields are reported by the builder itself.
+        if let Some(backend) = &self.backend {
+            backend.validate_uri(self.uri.as_deref().unwrap_or_default())?;
+            if let Some(datastore) = &self.datastore {
+                backend.validate_datastore(datastore)?;
+            }
+            if let Some(Some(_)) = &self.credentials {
+                backend.validate_credentials()?;
+            }
+            #[cfg(feature = "ipfs")]
+            match (backend, &self.content_store) {
+                (ArchiveBackends::Ipfs { .. }, None | Some(None))
+                    if self.uri.as_deref().unwrap_or_default().is_empty() =>
+                {
+                    return Err(
+                        "The IPFS backend needs the URI of an IPFS node's API, or a content store"
+                            .to_string(),
+                    )
+                }
+                (ArchiveBackends::Ipfs { .. }, _) | (_, None | Some(None)) => {}
+                _ => {
+                    return Err(format!(
+                        "The {} backend doesn't use a content store",
+                        backend
+                    ))
+                }
+            }
+            #[cfg(feature = "arweave")]
+            match (backend, &self.permanent_store) {
+                (ArchiveBackends::Arweave { .. }, None | Some(None))
+                    if self.uri.as_deref().unwrap_or_default().is_empty() =>
+                {
+                    return Err(
+                        "The Arweave backend needs the URI of a gateway, or a permanent store"
+                            .to_string(),
+                    )
+                }
+                (ArchiveBackends::Arweave { .. }, _) | (_, None | Some(None)) => {}
+                _ => {
+                    return Err(format!(
+                        "The {} backend doesn't use a permanent store",
+                        backend
+                    ))
+                }
+            }
+        }
+        if let Some(Some(write_concern)) = &self.write_concern {
+            write_concern.validate()?;
+        }
+        if let Some(Some(retry_policy)) = &self.retry_policy {
+            retry_policy.validate()?;
+        }
+        if let Some(Some(rate_limiter)) = &self.rate_limiter {
+            rate_limiter.limits.validate()?;
+        }
+        if let Some(Some(tiering)) = &self.tiering {
+            tiering.validate()?;
+        }
+        for (rec_type, key_field) in self.versioned.iter().flatten() {
+            rec_type.validate()?;
+            if key_field.is_empty() {
+                return Err(format!("The version key field of {:?} is empty", rec_type));
+            }
+        }
+        for (rec_type, policy) in self.retention.iter().flatten() {
+            rec_type.validate()?;
+            policy.validate(matches!(self.tiering, Some(Some(_))))?;
+        }
+        if let Some(Some(interval)) = self.prune_interval {
+            if interval.is_zero() {
+                return Err("Prune interval must be greater than zero".to_string());
+            }
+        }
+        if let Some(Some(interval)) = self.spill_drain_interval {
+            if interval.is_zero() {
+                return Err("Spill drain interval must be greater than zero".to_string());
+            }
+            if !matches!(self.spill_dir, Some(Some(_))) {
+                return Err("A spill drain interval needs a spill directory".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Suppresses duplicate writes: [ArchiveStore::create] becomes a no-op, returning the id of
+    /// the earlier write, when an identical record of the same type was written by this store (or
+    /// its clones) within `window`. This is a best-effort, per-process optimisation for things like
+    /// retry storms. Written records are only remembered in memory, so it is not a durability or
+    /// exactly-once guarantee. Records without an `_id` are given a fresh one when the store has
+    /// mirrors, so they are never deduplicated.
+    pub fn dedup_window(&mut self, window: Duration) -> &mut Self {
+        self.dedup = Some(Some(DedupCache::new(window)));
+        self
+    }
+
+    /// Caches up to `capacity` records read by [ArchiveStore::find_by_id] in memory, serving
+    /// repeated lookups of the same records for up to `ttl` after they were read without a round
+    /// trip to the backend, e.g. `builder.read_cache(10_000, Duration::from_secs(30))`. The least
+    /// recently used records are evicted once the cache is full. Records updated or deleted through
+    /// the store (or its clones) are dropped from the cache, but changes made by other processes
+    /// are only seen once the cached record expires, so keep `ttl` short for record types that
+    /// aren't append-only. The cold tier and mirrors of a store have caches of their own, if any.
+    pub fn read_cache(&mut self, capacity: usize, ttl: Duration) -> &mut Self {
+        self.cache = Some(Some(RecordCache::new(capacity, ttl)));
+        self
+    }
+
+    /// Limits the operations the store (and its clones and namespaces) performs against its
+    /// backend, e.g. to stop a bulk job saturating a cluster shared with a live node, see
+    /// [RateLimits]. The cold tier and mirrors of a store have limits of their own, if any.
+    pub fn rate_limits(&mut self, limits: RateLimits) -> &mut Self {
+        self.rate_limiter = Some(Some(RateLimiter::new(limits)));
+        self
+    }
+
+    /// Reports every operation performed through the store (and its clones) to the given
+    /// [ArchiveMetrics] hook, along with its duration, outcome, bytes written and labels.
+    pub fn metrics<M: ArchiveMetrics + 'static>(&mut self, metrics: M) -> &mut Self {
+        self.metrics = Some(Some(Arc::new(metrics)));
+        self
+    }
+
+    /// Stores records of [ArchiveBackends::Ipfs] in the given content-addressed store instead of
+    /// the IPFS node at the URI, which may then be left empty.
+    #[cfg(feature = "ipfs")]
+    pub fn content_store<S: ContentAddressedStore + 'static>(&mut self, store: S) -> &mut Self {
+        self.content_store = Some(Some(Arc::new(store)));
+        self
+    }
+
+    /// Uploads records of [ArchiveBackends::Arweave] to the given permanent store instead of the
+    /// Arweave gateway at the URI, which may then be left empty.
+    #[cfg(feature = "arweave")]
+    pub fn permanent_store<S: PermanentStore + 'static>(&mut self, store: S) -> &mut Self {
+        self.permanent_store = Some(Some(Arc::new(store)));
+        self
+    }
+
+    /// Mirrors every write to `store` as well, e.g. to keep a copy of a MongoDB archive in S3.
+    /// Records are written to the mirrors under the same id, and succeed according to the
+    /// [ArchiveStoreBuilder::write_strategy]. Reads fall back to the mirrors when they fail on
+    /// this store's own backend. Can be called repeatedly to add several mirrors.
+    pub fn mirror(&mut self, store: ArchiveStore) -> &mut Self {
+        self.mirrors.get_or_insert_with(Vec::new).push(store);
+        self
+    }
+}
+
+// Stores are shared between tasks, so must remain thread safe as they grow.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<ArchiveStore>();
+};
+
 impl fmt::Display for ArchiveStore {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "URI: {}, Backend: {}, Datastore: {}",
-            self.uri, self.backend, self.datastore
+            "URI: {}, Backend: {}, Datastore: {}, Compression: {}, Write concern: {}, Read \
+            preference: {}",
+            uri::redact(&self.inner.uri),
+            self.inner.backend,
+            self.inner.datastore,
+            self.inner.compression,
+            self.inner
+                .write_concern
+                .as_ref()
+                .map_or("default".to_string(), |wc| wc.to_string()),
+            self.inner
+                .read_preference
+                .as_ref()
+                .map_or("default".to_string(), |rp| rp.to_string()),
         )
     }
 }
 
-/// A trait that defines an interface for an archive backend to support when implemented.
+impl fmt::Debug for ArchiveStoreInner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Never print the credentials in the URI.
+        f.debug_struct("ArchiveStoreInner")
+            .field("uri", &uri::redact(&self.uri))
+            .field("credentials", &self.credentials)
+            .field("backend", &self.backend)
+            .field("datastore", &self.datastore)
+            .field("compression", &self.compression)
+            .field("codec", &self.codec)
+            .field("encryption", &self.encryption)
+            .field("checksums", &self.checksums)
+            .field("node_id", &self.node_id)
+            .field("chunk_threshold", &self.chunk_threshold)
+            .field("ordered_inserts", &self.ordered_inserts)
+            .field("schema_versions", &self.schema_versions)
+            .field("versioned", &self.versioned)
+            .field("migrations", &self.migrations)
+            .field("dedup", &self.dedup)
+            .field("cache", &self.cache)
+            .field("content_dedup", &self.content_dedup)
+            .field("write_concern", &self.write_concern)
+            .field("read_preference", &self.read_preference)
+            .field("retry_policy", &self.retry_policy)
+            .field(
+                "rate_limits",
+                &self.rate_limiter.as_ref().map(|limiter| &limiter.limits),
+            )
+            .field("mirrors", &self.mirrors)
+            .field("write_strategy", &self.write_strategy)
+            .field("tiering", &self.tiering)
+            .field("metrics", &self.metrics.is_some())
+            .field("spill_dir", &self.spill_dir)
+            .field("spill_drain_interval", &self.spill_drain_interval)
+            .field("dead_letter_dir", &self.dead_letter_dir)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A trait that defines an interface for an archive backend to support when implemented. Only
+/// creating, finding, querying, updating and deleting records by id must be implemented; the other
+/// operations fail with [Unsupported] unless the backend overrides them.
 #[async_trait]
 pub trait ArchiveBackend {
     /// Adds a new document to the data store.
-    async fn create<T: Serialize>(&mut self, rec_type: ArchiveRecordType, rec: T) -> Result<String>
+    async fn create<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+    ) -> Result<String, ArchiveError>
     where
         T: Borrow<T> + std::marker::Send + std::marker::Sync;
+    /// Adds several documents of the same type to the data store in bulk, returning their ids in
+    /// the order given.
+    async fn create_many<T: Serialize>(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _recs: Vec<T>,
+    ) -> Result<Vec<String>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        Err(not_implemented("create_many"))
+    }
+    /// Adds several documents, possibly of different record types, to the data store atomically:
+    /// either every document is stored or none are. Backends that can't guarantee this must fail
+    /// with [Unsupported], or stage the documents first, rather than just writing them one by
+    /// one.
+    async fn create_atomic(
+        &self,
+        _records: Vec<(ArchiveRecordType, Document)>,
+    ) -> Result<Vec<String>, ArchiveError> {
+        Err(not_implemented("create_atomic"))
+    }
     /// Finds all documents in the data store matching a given attribute's value.
     async fn find_all<T: DeserializeOwned>(
-        &mut self,
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin;
+    /// Streams all documents in the data store, reading them incrementally rather than all at
+    /// once.
+    async fn find_all_stream<'a, T: DeserializeOwned>(
+        &'a self,
+        _rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'a, Result<T, ArchiveError>>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin + 'a,
+    {
+        Err(not_implemented("find_all_stream"))
+    }
+    /// Finds a page of documents in the data store in a stable order, continuing from the page the
+    /// request's token was returned with.
+    async fn find_page<T: DeserializeOwned>(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _request: &PageRequest,
+    ) -> Result<Page<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        Err(not_implemented("find_page"))
+    }
+    /// Finds all documents in the data store matching a [Filter].
+    async fn query<T: DeserializeOwned>(
+        &self,
         rec_type: ArchiveRecordType,
-    ) -> Result<Vec<T>>
+        filter: &Filter,
+    ) -> Result<Vec<T>, ArchiveError>
     where
         T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin;
+    /// Finds all documents in the data store matching a [Filter], holding only their `_id` and
+    /// the given (possibly dotted) fields. Backends that can't read part of a document may
+    /// return whole documents.
+    async fn find_projected(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _filter: &Filter,
+        _fields: &[String],
+    ) -> Result<Vec<Document>, ArchiveError> {
+        Err(not_implemented("find_projected"))
+    }
+    /// Finds the document with the given id, as returned by [ArchiveBackend::create].
+    async fn find_by_id<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<Option<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin;
+    /// Deletes the document with the given id, returning whether there was one to delete.
+    async fn delete_by_id(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<bool, ArchiveError>;
+    /// Deletes every document matching the [Filter], returning how many were deleted.
+    async fn delete_where(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        Err(not_implemented("delete_where"))
+    }
+    /// Replaces the document with the given id, returning whether there was one to replace.
+    async fn update_by_id<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        rec: T,
+    ) -> Result<bool, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync;
+    /// Replaces the first document matching the [Filter], or inserts the record if none match,
+    /// returning the id of the replaced or inserted document.
+    async fn upsert<T: Serialize>(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _filter: &Filter,
+        _rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        Err(not_implemented("upsert"))
+    }
+    /// Returns a random sample of approximately `rate` (between 0.0 and 1.0) of all documents in
+    /// the data store. The number of documents returned is approximate.
+    async fn find_sampled<T: DeserializeOwned>(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _rate: f64,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        Err(not_implemented("find_sampled"))
+    }
+    /// Finds groups of chunks (of records stored in chunks) that have no manifest referencing
+    /// them, returning the ids they were stored under.
+    async fn find_orphaned_chunks(
+        &self,
+        _rec_type: ArchiveRecordType,
+    ) -> Result<Vec<String>, ArchiveError> {
+        Err(not_implemented("find_orphaned_chunks"))
+    }
+    /// Deletes all orphaned chunks, returning the number of chunks deleted.
+    async fn cleanup_orphans(&self, _rec_type: ArchiveRecordType) -> Result<u64, ArchiveError> {
+        Err(not_implemented("cleanup_orphans"))
+    }
+    /// Returns the space freed by deleted records of a type to the operating system, where the
+    /// backend keeps it for reuse, e.g. by rewriting the type's storage.
+    async fn reclaim_space(&self, _rec_type: ArchiveRecordType) -> Result<(), ArchiveError> {
+        Err(not_implemented("reclaim_space"))
+    }
+    /// Runs an aggregation pipeline over one record type's documents, writing the output into
+    /// another record type's documents on the server. Returns the number of documents output.
+    async fn merge_into(
+        &self,
+        _source: ArchiveRecordType,
+        _pipeline: Vec<Document>,
+        _target: ArchiveRecordType,
+        _mode: MergeMode,
+    ) -> Result<u64, ArchiveError> {
+        Err(not_implemented("merge_into"))
+    }
+    /// Streams the changes made to the documents in the data store by any process, from now on.
+    async fn watch(
+        &self,
+        _rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'static, Result<ArchiveEvent, ArchiveError>>, ArchiveError> {
+        Err(not_implemented("watch"))
+    }
+    /// Counts the documents matching the [Filter].
+    async fn count(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        Err(not_implemented("count"))
+    }
+    /// Returns whether any document matches the [Filter].
+    async fn exists(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _filter: &Filter,
+    ) -> Result<bool, ArchiveError> {
+        Err(not_implemented("exists"))
+    }
+    /// Returns storage statistics for the documents in the data store, zeroed if it is empty.
+    async fn stats(
+        &self,
+        _rec_type: ArchiveRecordType,
+    ) -> Result<ArchiveCollectionStats, ArchiveError> {
+        Err(not_implemented("stats"))
+    }
+    /// Counts documents per group, returning each group's key and count ordered by key.
+    /// Documents missing a grouped field are counted under a null key.
+    async fn group_count(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _group_by: GroupBy,
+    ) -> Result<Vec<(Bson, u64)>, ArchiveError> {
+        Err(not_implemented("group_count"))
+    }
+    /// Runs an aggregation over the documents matching its filter, returning a document per
+    /// group, ordered by key.
+    async fn aggregate(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _spec: &AggregationSpec,
+    ) -> Result<Vec<Document>, ArchiveError> {
+        Err(not_implemented("aggregate"))
+    }
+    /// Creates the secondary indexes on the documents in the data store that don't exist yet.
+    /// Backends without secondary indexes ignore non-unique indexes, as they only speed queries
+    /// up, and fail with [Unsupported] for unique ones.
+    async fn ensure_indexes(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _indexes: &[IndexSpec],
+    ) -> Result<(), ArchiveError> {
+        Err(not_implemented("ensure_indexes"))
+    }
+}
+
+/// The error of an [ArchiveBackend] operation the backend doesn't implement.
+fn not_implemented(operation: &'static str) -> ArchiveError {
+    Unsupported {
+        operation,
+        reason: "the backend doesn't implement it".to_string(),
+    }
+    .into()
 }
 
 /// List of possible backends
 #[derive(Debug, Clone)]
 pub enum ArchiveBackends {
     /// Uses MongoDB as a backend, with a different collection used for each [ArchiveRecordType].
+    /// Requires the `mongodb` feature, which is enabled by default.
+    #[cfg(feature = "mongodb")]
     MongoDB,
+    /// Uses PostgreSQL as a backend, with a different table used for each [ArchiveRecordType].
+    /// Requires the `postgres` feature.
+    #[cfg(feature = "postgres")]
+    Postgres,
+    /// Uses an embedded SQLite database as a backend, with a different table used for each
+    /// [ArchiveRecordType]. Requires the `sqlite` feature.
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+    /// Uses S3-compatible object storage as a backend, with the datastore as the bucket and a
+    /// different key prefix used for each [ArchiveRecordType]. Requires the `s3` feature.
+    #[cfg(feature = "s3")]
+    S3,
+    /// Uses an embedded RocksDB database as a backend, with a different column family used for
+    /// each [ArchiveRecordType]. Requires the `rocksdb` feature.
+    #[cfg(feature = "rocksdb")]
+    RocksDb,
+    /// Stores records as files under `root`, with a different directory used for each
+    /// [ArchiveRecordType]. Takes no URI.
+    Filesystem { root: PathBuf },
+    /// Keeps records in memory, for testing code that uses an [ArchiveStore] without running a
+    /// database, see [ArchiveStore::in_memory]. Records are lost when the store is dropped. Takes
+    /// no URI.
+    InMemory,
+    /// Stores each record as a content-addressed object in the IPFS node whose HTTP RPC API is at
+    /// the URI, or in the [ArchiveStoreBuilder::content_store], with the record's CID as its id
+    /// and a local index of the CIDs of each [ArchiveRecordType] kept in the `index` directory.
+    /// Requires the `ipfs` feature.
+    #[cfg(feature = "ipfs")]
+    Ipfs { index: PathBuf },
+    /// Stores records permanently on Arweave, through the gateway at the URI, or in the
+    /// [ArchiveStoreBuilder::permanent_store], uploading the records of each write in bundles
+    /// and keeping a local index of the records of each [ArchiveRecordType] in the `index`
+    /// directory. Records can't be updated or deleted. Requires the `arweave` feature.
+    #[cfg(feature = "arweave")]
+    Arweave { index: PathBuf },
+    /// Uses the backend registered under `name` with [ArchiveStore::register_backend], which is
+    /// given the URI and datastore.
+    Custom { name: String },
+}
+
+impl ArchiveBackends {
+    /// Checks that the URI is well formed for the backend.
+    pub fn validate_uri(&self, uri: &str) -> Result<(), String> {
+        match *self {
+            #[cfg(feature = "mongodb")]
+            ArchiveBackends::MongoDB => MongoDBBackend::validate_uri(uri),
+            #[cfg(feature = "postgres")]
+            ArchiveBackends::Postgres => PostgresBackend::validate_uri(uri),
+            #[cfg(feature = "sqlite")]
+            ArchiveBackends::Sqlite => SqliteBackend::validate_uri(uri),
+            #[cfg(feature = "s3")]
+            ArchiveBackends::S3 => S3Backend::validate_uri(uri),
+            #[cfg(feature = "rocksdb")]
+            ArchiveBackends::RocksDb => RocksDbBackend::validate_uri(uri),
+            ArchiveBackends::Filesystem { .. } => FilesystemBackend::validate_uri(uri),
+            ArchiveBackends::InMemory => MemoryBackend::validate_uri(uri),
+            #[cfg(feature = "ipfs")]
+            ArchiveBackends::Ipfs { .. } => IpfsBackend::validate_uri(uri),
+            #[cfg(feature = "arweave")]
+            ArchiveBackends::Arweave { .. } => ArweaveBackend::validate_uri(uri),
+            ArchiveBackends::Custom { ref name } => ArchiveBackends::validate_custom(name),
+        }
+    }
+
+    /// Checks that the name is usable as a datastore name by the backend.
+    pub fn validate_datastore(&self, datastore: &str) -> Result<(), String> {
+        match *self {
+            #[cfg(feature = "mongodb")]
+            ArchiveBackends::MongoDB => MongoDBBackend::validate_datastore(datastore),
+            #[cfg(feature = "postgres")]
+            ArchiveBackends::Postgres => PostgresBackend::validate_datastore(datastore),
+            #[cfg(feature = "sqlite")]
+            ArchiveBackends::Sqlite => SqliteBackend::validate_datastore(datastore),
+            #[cfg(feature = "s3")]
+            ArchiveBackends::S3 => S3Backend::validate_datastore(datastore),
+            #[cfg(feature = "rocksdb")]
+            ArchiveBackends::RocksDb => RocksDbBackend::validate_datastore(datastore),
+            ArchiveBackends::Filesystem { .. } => FilesystemBackend::validate_datastore(datastore),
+            ArchiveBackends::InMemory => MemoryBackend::validate_datastore(datastore),
+            #[cfg(feature = "ipfs")]
+            ArchiveBackends::Ipfs { .. } => IpfsBackend::validate_datastore(datastore),
+            #[cfg(feature = "arweave")]
+            ArchiveBackends::Arweave { .. } => ArweaveBackend::validate_datastore(datastore),
+            ArchiveBackends::Custom { ref name } => ArchiveBackends::validate_custom(name),
+        }
+    }
+
+    /// Checks that the backend authenticates, so can use [Credentials].
+    fn validate_credentials(&self) -> Result<(), String> {
+        match *self {
+            #[cfg(feature = "mongodb")]
+            ArchiveBackends::MongoDB => Ok(()),
+            #[cfg(feature = "postgres")]
+            ArchiveBackends::Postgres => Ok(()),
+            #[cfg(feature = "s3")]
+            ArchiveBackends::S3 => Ok(()),
+            _ => Err(format!("The {} backend doesn't use credentials", self)),
+        }
+    }
+
+    /// The largest document, in bytes, the backend is able to store, if it has a limit.
+    pub fn max_document_size(&self) -> Option<usize> {
+        match *self {
+            #[cfg(feature = "mongodb")]
+            ArchiveBackends::MongoDB => Some(mongodb_archive::MAX_DOCUMENT_SIZE),
+            #[cfg(feature = "postgres")]
+            ArchiveBackends::Postgres => Some(postgres_archive::MAX_DOCUMENT_SIZE),
+            #[cfg(feature = "sqlite")]
+            ArchiveBackends::Sqlite => Some(sqlite_archive::MAX_DOCUMENT_SIZE),
+            #[cfg(feature = "s3")]
+            ArchiveBackends::S3 => None,
+            #[cfg(feature = "rocksdb")]
+            ArchiveBackends::RocksDb => None,
+            ArchiveBackends::Filesystem { .. } => None,
+            ArchiveBackends::InMemory => None,
+            #[cfg(feature = "ipfs")]
+            ArchiveBackends::Ipfs { .. } => None,
+            #[cfg(feature = "arweave")]
+            ArchiveBackends::Arweave { .. } => Some(arweave_archive::MAX_BUNDLE_BYTES),
+            ArchiveBackends::Custom { .. } => None,
+        }
+    }
 }
 
 impl fmt::Display for ArchiveBackends {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            #[cfg(feature = "mongodb")]
             ArchiveBackends::MongoDB => write!(f, "MongoDB"),
+            #[cfg(feature = "postgres")]
+            ArchiveBackends::Postgres => write!(f, "PostgreSQL"),
+            #[cfg(feature = "sqlite")]
+            ArchiveBackends::Sqlite => write!(f, "SQLite"),
+            #[cfg(feature = "s3")]
+            ArchiveBackends::S3 => write!(f, "S3"),
+            #[cfg(feature = "rocksdb")]
+            ArchiveBackends::RocksDb => write!(f, "RocksDB"),
+            ArchiveBackends::Filesystem { ref root } => {
+                write!(f, "Filesystem ({})", root.display())
+            }
+            ArchiveBackends::InMemory => write!(f, "In-memory"),
+            #[cfg(feature = "ipfs")]
+            ArchiveBackends::Ipfs { ref index } => write!(f, "IPFS ({})", index.display()),
+            #[cfg(feature = "arweave")]
+            ArchiveBackends::Arweave { ref index } => write!(f, "Arweave ({})", index.display()),
+            ArchiveBackends::Custom { ref name } => write!(f, "{}", name),
         }
     }
 }
@@ -110,8 +3118,86 @@ impl fmt::Display for ArchiveBackends {
 /// An enum representing different types of blobs/records we support archiving. We treat these as
 /// being totally opaque within this crate, but may store them separately or slightly differently
 /// for performance, indexing, retention and other record-specific criteria.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde_derive::Serialize, serde_derive::Deserialize)]
 pub enum ArchiveRecordType {
     Account,
     TransactionBatch,
+    /// A full block, looked up by the [BLOCK_HEIGHT_FIELD] field
+    Block,
+    /// A transaction receipt, looked up by the [RECEIPT_TX_HASH_FIELD] field
+    Receipt,
+    /// A record type defined by the caller, e.g. receipts or bridge events. Each backend stores
+    /// these under the given name, which must pass [ArchiveRecordType::validate].
+    Custom(String),
+}
+
+impl ArchiveRecordType {
+    /// Checks that a custom record type's name can name the collection, table, key prefix,
+    /// directory or column family its records are stored in: 1 to 48 lowercase letters, digits and
+    /// underscores, starting with a letter. Names that could be confused with the built-in record
+    /// types or MongoDB's chunk collections are rejected.
+    pub fn validate(&self) -> Result<(), String> {
+        let name = match self {
+            ArchiveRecordType::Custom(name) => name,
+            _ => return Ok(()),
+        };
+        if name.is_empty() || name.len() > MAX_RECORD_TYPE_LEN {
+            return Err(format!(
+                "Record type name '{}' must be between 1 and {} characters",
+                name, MAX_RECORD_TYPE_LEN
+            ));
+        }
+        if !name.starts_with(|c: char| c.is_ascii_lowercase())
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        {
+            return Err(format!(
+                "Record type name '{}' must only contain lowercase letters, digits and \
+                underscores, and start with a letter",
+                name
+            ));
+        }
+        if RESERVED_RECORD_TYPES.contains(&name.as_str()) || name.ends_with("_chunks") {
+            return Err(format!("Record type name '{}' is reserved", name));
+        }
+        Ok(())
+    }
+
+    /// Parses the name a record type is given by over the network, e.g. `transaction_batch`.
+    /// Other names are custom record types.
+    #[cfg(any(feature = "server", feature = "jsonrpc", feature = "graphql"))]
+    pub(crate) fn from_name(name: &str) -> ArchiveRecordType {
+        match name {
+            "account" => ArchiveRecordType::Account,
+            "transaction_batch" => ArchiveRecordType::TransactionBatch,
+            "block" => ArchiveRecordType::Block,
+            "receipt" => ArchiveRecordType::Receipt,
+            other => ArchiveRecordType::Custom(other.to_string()),
+        }
+    }
+
+    /// The field backends index records of this type by, if any.
+    pub(crate) fn indexed_field(&self) -> Option<&'static str> {
+        match self {
+            ArchiveRecordType::Block => Some(BLOCK_HEIGHT_FIELD),
+            ArchiveRecordType::Receipt => Some(RECEIPT_TX_HASH_FIELD),
+            _ => None,
+        }
+    }
+}
+
+/// How the output of a server side aggregation is written into its target by
+/// [ArchiveStore::merge_into]. The first three are modes of MongoDB's `$merge` stage, matching
+/// output documents to existing ones by `_id` and inserting those without a match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeMode {
+    /// Merge the fields of matching output documents into the existing documents.
+    Merge,
+    /// Replace matching existing documents with the output documents.
+    Replace,
+    /// Keep matching existing documents, discarding the matching output documents.
+    KeepExisting,
+    /// Replace the entire target with the output, using an `$out` stage.
+    ReplaceAll,
 }
@@ -0,0 +1,243 @@
+/// Record lifecycle states, for logically removing records while keeping them for audit, e.g. the
+/// blocks and transaction batches orphaned by a chain reorg. Records are
+/// [LifecycleState::Archived] when written, and are marked [LifecycleState::Tombstoned] or
+/// [LifecycleState::PendingPurge] instead of being deleted with [ArchiveStore::set_lifecycle] or
+/// [ArchiveStore::tombstone_where]. The state, when and why it last changed are stored in
+/// reserved top-level fields next to the record, like its provenance, so backends can filter on
+/// them whatever the record's encoding, e.g. with [Filter::lifecycle].
+///
+/// Reads only return archived records, unless made through a handle returned by
+/// [ArchiveStore::with_tombstones]. Storage statistics, [ArchiveStore::stats] and
+/// [ArchiveStore::group_count], count every stored record. Records pending purge, and tombstones
+/// older than the [crate::RetentionPolicy::purge_tombstones_after] of their record type, are
+/// deleted by pruning, for the record types with a retention policy, and by
+/// [ArchiveStore::compact], which also returns the space they held to the operating system.
+use crate::envelope::Provenance;
+use crate::{filter, ArchiveError, ArchiveRecordType, ArchiveStore, Filter};
+use bson::{Bson, DateTime, Document};
+use std::time::SystemTime;
+
+/// Name of the field holding the lifecycle state of a record that isn't archived
+pub(crate) const LIFECYCLE_FIELD: &str = "_lifecycle";
+/// Name of the field holding when a record's lifecycle state last changed, in milliseconds since
+/// the epoch
+pub(crate) const LIFECYCLE_CHANGED_AT_FIELD: &str = "_lifecycle_changed_at";
+/// Name of the field holding why a record's lifecycle state last changed
+pub(crate) const LIFECYCLE_REASON_FIELD: &str = "_lifecycle_reason";
+
+/// The lifecycle state of an archived record.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum LifecycleState {
+    /// The record is live, and returned by reads.
+    #[default]
+    Archived,
+    /// The record was logically removed, e.g. by a reorg, and is kept for audit until its
+    /// record type's [crate::RetentionPolicy::purge_tombstones_after] has passed.
+    Tombstoned,
+    /// The record was logically removed and is deleted by the next [ArchiveStore::compact].
+    PendingPurge,
+}
+
+impl LifecycleState {
+    /// The value stored in the [LIFECYCLE_FIELD], which archived records don't have.
+    pub(crate) fn name(&self) -> Option<&'static str> {
+        match self {
+            LifecycleState::Archived => None,
+            LifecycleState::Tombstoned => Some("tombstoned"),
+            LifecycleState::PendingPurge => Some("pending_purge"),
+        }
+    }
+}
+
+/// A record's lifecycle state, along with when and why it last changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lifecycle {
+    /// The record's state
+    pub state: LifecycleState,
+    /// When the state last changed, `None` if it never has
+    pub changed_at: Option<DateTime>,
+    /// Why the state last changed, e.g. `reorg at height 1024`
+    pub reason: Option<String>,
+}
+
+impl Lifecycle {
+    /// Removes the lifecycle fields from a document. Unknown states are read as tombstoned, so
+    /// that records written by newer versions are hidden rather than revived.
+    pub(crate) fn take(doc: &mut Document) -> Lifecycle {
+        let state = match doc.remove(LIFECYCLE_FIELD) {
+            None | Some(Bson::Null) => LifecycleState::Archived,
+            Some(Bson::String(state)) if state == "pending_purge" => LifecycleState::PendingPurge,
+            Some(_) => LifecycleState::Tombstoned,
+        };
+        let changed_at = match doc.remove(LIFECYCLE_CHANGED_AT_FIELD) {
+            Some(Bson::Int64(millis)) => Some(DateTime::from_millis(millis)),
+            Some(Bson::Int32(millis)) => Some(DateTime::from_millis(millis.into())),
+            Some(Bson::DateTime(time)) => Some(time),
+            _ => None,
+        };
+        let reason = match doc.remove(LIFECYCLE_REASON_FIELD) {
+            Some(Bson::String(reason)) => Some(reason),
+            _ => None,
+        };
+        Lifecycle {
+            state,
+            changed_at,
+            reason,
+        }
+    }
+
+    /// Stores the lifecycle fields in a document, leaving out those that are unset.
+    pub(crate) fn stamp(&self, doc: &mut Document) {
+        if let Some(state) = self.state.name() {
+            doc.insert(LIFECYCLE_FIELD, state);
+        }
+        if let Some(changed_at) = self.changed_at {
+            doc.insert(LIFECYCLE_CHANGED_AT_FIELD, changed_at.timestamp_millis());
+        }
+        if let Some(reason) = &self.reason {
+            doc.insert(LIFECYCLE_REASON_FIELD, reason);
+        }
+    }
+}
+
+/// Whether a document read from the backend is returned by a read, i.e. it's archived or the
+/// read includes tombstones.
+pub(crate) fn visible(doc: &Document, include_tombstones: bool) -> bool {
+    include_tombstones || !doc.contains_key(LIFECYCLE_FIELD)
+}
+
+impl ArchiveStore {
+    /// Returns a handle on this store whose reads include tombstoned records and records pending
+    /// purge, e.g. to audit the records removed by a reorg with
+    /// `store.with_tombstones().query(rec_type, Filter::lifecycle(LifecycleState::Tombstoned))`.
+    /// Their state is in the [crate::ArchiveEnvelope] of each record.
+    pub fn with_tombstones(&self) -> ArchiveStore {
+        let mut store = self.clone();
+        store.include_tombstones = true;
+        store
+    }
+
+    /// Restricts a filter to the records this handle reads.
+    pub(crate) fn visible(&self, filter: Filter) -> Filter {
+        if self.include_tombstones {
+            filter
+        } else {
+            filter.and(Filter::lifecycle(LifecycleState::Archived))
+        }
+    }
+
+    /// Moves the record of [ArchiveRecordType] with the given id to a lifecycle state, recording
+    /// when and why, e.g. `store.set_lifecycle(rec_type, id, LifecycleState::Tombstoned,
+    /// Some("reorg at height 1024"))`, returning whether there was such a record. Setting
+    /// [LifecycleState::Archived] restores a logically removed record. The record keeps its id,
+    /// provenance and contents. Fails with [crate::Unsupported] on backends that can't update
+    /// records.
+    pub async fn set_lifecycle(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        state: LifecycleState,
+        reason: Option<&str>,
+    ) -> Result<bool, ArchiveError> {
+        let store = self.with_tombstones();
+        let envelope = match store
+            .find_envelope_by_id::<Document>(rec_type.clone(), id)
+            .await?
+        {
+            Some(envelope) => envelope,
+            None => return Ok(false),
+        };
+        let mut rec = envelope.record;
+        rec.remove("_id");
+        Provenance {
+            archived_at: envelope.archived_at,
+            node_id: envelope.node_id,
+            tags: envelope.tags,
+            lifecycle: Lifecycle {
+                state,
+                changed_at: Some(DateTime::now()),
+                reason: reason.map(str::to_string),
+            },
+        }
+        .restore(&mut rec);
+        // Versioned types too are rewritten in place, rather than gaining a tombstoned version.
+        store.replace_by_id(rec_type, id, rec).await
+    }
+
+    /// Tombstones every archived record of [ArchiveRecordType] matching the [Filter], e.g. the
+    /// blocks above a reorg's fork point with `Filter::gt(BLOCK_HEIGHT_FIELD, 1024)`, returning
+    /// how many were tombstoned. Each record is tombstoned on its own, so if this fails part way
+    /// it can simply be run again.
+    pub async fn tombstone_where(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+        reason: &str,
+    ) -> Result<u64, ArchiveError> {
+        let filter = filter.and(Filter::lifecycle(LifecycleState::Archived));
+        let ids: Vec<String> = self
+            .with_tombstones()
+            .query_envelopes::<Document>(rec_type.clone(), filter)
+            .await?
+            .into_iter()
+            .filter_map(|envelope| envelope.record.get("_id").cloned())
+            .map(|id| filter::id_to_string(&id))
+            .collect();
+        let mut tombstoned = 0;
+        for id in ids {
+            if self
+                .set_lifecycle(
+                    rec_type.clone(),
+                    &id,
+                    LifecycleState::Tombstoned,
+                    Some(reason),
+                )
+                .await?
+            {
+                tombstoned += 1;
+            }
+        }
+        Ok(tombstoned)
+    }
+
+    /// Deletes the records of [ArchiveRecordType] pending purge, and the tombstones older than
+    /// the record type's [crate::RetentionPolicy::purge_tombstones_after], from both tiers,
+    /// returning how many were deleted. Without such a policy, tombstones are kept until they are
+    /// marked [LifecycleState::PendingPurge].
+    ///
+    /// The space held by every record of the type deleted so far, including by
+    /// [ArchiveStore::prune] and [ArchiveStore::delete_where], is then returned to the operating
+    /// system, in both tiers and every mirror, as backends otherwise keep it for reuse: MongoDB
+    /// compacts the type's collections, PostgreSQL rewrites its table, SQLite its whole database
+    /// and RocksDB compacts its column family, and the filesystem backend removes the temporary
+    /// files of interrupted writes. These rewrites can take long and block other operations on
+    /// the type, so run this off-peak after large deletions. Other backends free space as
+    /// records are deleted.
+    pub async fn compact(&self, rec_type: ArchiveRecordType) -> Result<u64, ArchiveError> {
+        let purged = self.purge(rec_type.clone()).await?;
+        self.reclaim_space(rec_type).await?;
+        Ok(purged)
+    }
+
+    /// Deletes the records of [ArchiveRecordType] due to be purged, like [ArchiveStore::compact]
+    /// but leaving the space they held to the backend.
+    pub(crate) async fn purge(&self, rec_type: ArchiveRecordType) -> Result<u64, ArchiveError> {
+        let mut purged = Filter::lifecycle(LifecycleState::PendingPurge);
+        let window = self
+            .inner
+            .retention
+            .get(&rec_type)
+            .and_then(|policy| policy.purge_tombstones_after);
+        if let Some(window) = window {
+            let cutoff = SystemTime::now()
+                .checked_sub(window)
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            let expired = Filter::lifecycle(LifecycleState::Tombstoned).and(Filter::lt(
+                LIFECYCLE_CHANGED_AT_FIELD,
+                DateTime::from_system_time(cutoff).timestamp_millis(),
+            ));
+            purged = purged.or(expired);
+        }
+        self.delete_where(rec_type, purged).await
+    }
+}
@@ -0,0 +1,962 @@
+/// An implementation of an archive datastore that keeps records in memory, for testing code that
+/// archives records without running a database. Records of each [ArchiveRecordType] are kept in a
+/// map ordered by id, so they are listed and paged in the order they were archived, as with
+/// MongoDB's ObjectIds. Every store built with [crate::ArchiveBackends::InMemory] has records of
+/// its own, shared by its clones and lost when the last of them is dropped, and each of its
+/// namespaces has its own too.
+///
+/// Queries, counts and aggregations filter the records here. Writes behave as MongoDB's do: ids
+/// already in use are rejected, unique indexes are enforced, and atomic batches are written in
+/// full or not at all. Every change, including those made by [crate::ArchiveStore::delete_where]
+/// and pruning, is reported to subscribers. Only aggregation pipelines, which are MongoDB
+/// specific, fail with [Unsupported]. Records over the [ArchiveStoreBuilder::chunk_threshold] are
+/// chunked as MongoDB chunks them, with their chunks kept apart from the records, whose fields
+/// can't then be filtered on.
+use crate::chunking::{self, FILES_ID_FIELD};
+use crate::events::{self, ArchiveEventKind};
+use crate::filter::{compare, id_to_string, lookup};
+use crate::projection;
+use crate::stats::{aggregate, tally};
+use crate::{
+    AggregationSpec, ArchiveBackend, ArchiveBackends, ArchiveCollectionStats, ArchiveError,
+    ArchiveEvent, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder, Filter, GroupBy, IndexSpec,
+    MergeMode, Page, PageRequest, Unsupported,
+};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use bson::{oid::ObjectId, Bson, DateTime, Document};
+use futures::stream::{self, BoxStream, StreamExt};
+use log::debug;
+use serde::{de::DeserializeOwned, Serialize};
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use tokio::sync::broadcast;
+
+/// Datastore name of the stores built by [ArchiveStore::in_memory]
+const DEFAULT_DATASTORE: &str = "memory";
+
+/// A stored record.
+#[derive(Debug)]
+struct Stored {
+    doc: Document,
+    /// When the record was last written
+    written_at: DateTime,
+}
+
+/// The records and indexes of a [MemoryBackend].
+#[derive(Debug, Default)]
+struct State {
+    /// Records of each type, keyed by id
+    records: HashMap<ArchiveRecordType, BTreeMap<String, Stored>>,
+    /// Chunks of the chunked records of each type, see [chunking]
+    chunks: HashMap<ArchiveRecordType, Vec<Document>>,
+    /// Unique indexes declared on each type
+    unique: HashMap<ArchiveRecordType, Vec<IndexSpec>>,
+}
+
+impl State {
+    /// Records of the given type, if any were written.
+    fn records(&self, rec_type: &ArchiveRecordType) -> impl Iterator<Item = (&String, &Stored)> {
+        self.records.get(rec_type).into_iter().flatten()
+    }
+
+    /// Checks that writing a record under `id` wouldn't give it the same values of the fields of
+    /// a unique index as another record. Missing fields count as null, as with MongoDB.
+    fn check_unique(
+        &self,
+        rec_type: &ArchiveRecordType,
+        id: &str,
+        doc: &Document,
+    ) -> Result<(), ArchiveError> {
+        for index in self.unique.get(rec_type).into_iter().flatten() {
+            let key = index_key(index, doc);
+            let duplicate = self.records(rec_type).find(|(other, stored)| {
+                *other != id && same_key(&key, &index_key(index, &stored.doc))
+            });
+            if let Some((other, _)) = duplicate {
+                return Err(ArchiveError::DuplicateKey(anyhow!(
+                    "Record {} has the same {} as record {}",
+                    id,
+                    index.fields.join(", "),
+                    other
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores a new record, returning its id. The document's `_id` is used as the id if it has
+    /// one, otherwise a new ObjectId is generated and stored as its `_id`. Records over the chunk
+    /// threshold, if any, are chunked, and their chunks dropped again if the record isn't stored.
+    fn insert(
+        &mut self,
+        rec_type: &ArchiveRecordType,
+        doc: Document,
+        chunk_threshold: Option<usize>,
+    ) -> Result<String, ArchiveError> {
+        let doc = self.chunk(rec_type, doc, chunk_threshold)?;
+        let inserted = self.insert_stored(rec_type, doc.clone());
+        if inserted.is_err() {
+            self.drop_chunks(rec_type, &doc);
+        }
+        inserted
+    }
+
+    /// Stores a new document as it is, returning its id, see [State::insert].
+    fn insert_stored(
+        &mut self,
+        rec_type: &ArchiveRecordType,
+        mut doc: Document,
+    ) -> Result<String, ArchiveError> {
+        let id = match doc.get("_id") {
+            Some(id) => id_to_string(id),
+            None => {
+                let id = ObjectId::new();
+                doc.insert("_id", id);
+                id.to_hex()
+            }
+        };
+        let exists = self
+            .records
+            .get(rec_type)
+            .is_some_and(|records| records.contains_key(&id));
+        if exists {
+            return Err(ArchiveError::DuplicateKey(anyhow!(
+                "A {:?} record with id {} already exists",
+                rec_type,
+                id
+            )));
+        }
+        self.check_unique(rec_type, &id, &doc)?;
+        self.records.entry(rec_type.clone()).or_default().insert(
+            id.clone(),
+            Stored {
+                doc,
+                written_at: DateTime::now(),
+            },
+        );
+        Ok(id)
+    }
+
+    /// Replaces the record stored under `id`, keeping its `_id`.
+    fn replace(
+        &mut self,
+        rec_type: &ArchiveRecordType,
+        id: &str,
+        doc: Document,
+        chunk_threshold: Option<usize>,
+    ) -> Result<(), ArchiveError> {
+        let stored_id = self
+            .records
+            .get(rec_type)
+            .and_then(|records| records.get(id))
+            .with_context(|| format!("No {:?} record with id {}", rec_type, id))?
+            .doc
+            .get("_id")
+            .cloned();
+        let mut doc = self.chunk(rec_type, doc, chunk_threshold)?;
+        match stored_id {
+            Some(stored_id) => doc.insert("_id", stored_id),
+            None => doc.remove("_id"),
+        };
+        if let Err(e) = self.check_unique(rec_type, id, &doc) {
+            self.drop_chunks(rec_type, &doc);
+            return Err(e);
+        }
+        let replaced = self.records.entry(rec_type.clone()).or_default().insert(
+            id.to_string(),
+            Stored {
+                doc,
+                written_at: DateTime::now(),
+            },
+        );
+        if let Some(replaced) = replaced {
+            self.drop_chunks(rec_type, &replaced.doc);
+        }
+        Ok(())
+    }
+
+    /// Removes the record with the given id, and its chunks if it's chunked, returning whether
+    /// there was one.
+    fn remove(&mut self, rec_type: &ArchiveRecordType, id: &str) -> bool {
+        let removed = self
+            .records
+            .get_mut(rec_type)
+            .and_then(|records| records.remove(id));
+        match removed {
+            Some(removed) => {
+                self.drop_chunks(rec_type, &removed.doc);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the document to store for a record, which is the manifest of its chunks, stored
+    /// here, if it's over the chunk threshold. The manifest keeps the record's `_id`, if it has
+    /// one, as the record's chunks share an id of their own.
+    fn chunk(
+        &mut self,
+        rec_type: &ArchiveRecordType,
+        doc: Document,
+        chunk_threshold: Option<usize>,
+    ) -> Result<Document> {
+        let Some(threshold) = chunk_threshold else {
+            return Ok(doc);
+        };
+        let raw = bson::to_vec(&doc).context("Failed to serialise record to BSON")?;
+        if raw.len() <= threshold {
+            return Ok(doc);
+        }
+        let (mut manifest, chunks) = chunking::split_replacement(&raw, threshold.max(1));
+        if let Some(id) = doc.get("_id") {
+            manifest.insert("_id", id.clone());
+        }
+        self.chunks
+            .entry(rec_type.clone())
+            .or_default()
+            .extend(chunks);
+        Ok(manifest)
+    }
+
+    /// Removes the chunks of a record, if `doc` is the manifest of a chunked record.
+    fn drop_chunks(&mut self, rec_type: &ArchiveRecordType, doc: &Document) {
+        let (Some(files_id), Some(chunks)) =
+            (chunking::manifest_id(doc), self.chunks.get_mut(rec_type))
+        else {
+            return;
+        };
+        chunks.retain(|chunk| chunk.get_object_id(FILES_ID_FIELD).ok() != Some(files_id));
+    }
+
+    /// Returns a stored document as the record it holds, reassembling it from its chunks if it's
+    /// the manifest of a chunked record.
+    fn reassemble(&self, rec_type: &ArchiveRecordType, doc: Document) -> Result<Document> {
+        let Some(files_id) = chunking::manifest_id(&doc) else {
+            return Ok(doc);
+        };
+        let chunks = self
+            .chunks
+            .get(rec_type)
+            .into_iter()
+            .flatten()
+            .filter(|chunk| chunk.get_object_id(FILES_ID_FIELD).ok() == Some(files_id))
+            .cloned()
+            .collect();
+        let raw = chunking::reassemble(&doc, chunks)?;
+        let mut rec: Document =
+            bson::from_slice(&raw).context("Failed to deserialise reassembled record")?;
+        // Chunked records are identified by their manifest's id, as plain records are by theirs.
+        if !rec.contains_key("_id") {
+            rec.insert(
+                "_id",
+                doc.get("_id").cloned().unwrap_or(Bson::ObjectId(files_id)),
+            );
+        }
+        Ok(rec)
+    }
+
+    /// The ids shared by the chunks of each record of the given type that isn't stored.
+    fn orphaned_chunk_ids(&self, rec_type: &ArchiveRecordType) -> Vec<ObjectId> {
+        let referenced: Vec<ObjectId> = self
+            .records(rec_type)
+            .filter_map(|(_, stored)| chunking::manifest_id(&stored.doc))
+            .collect();
+        let mut orphans: Vec<ObjectId> = self
+            .chunks
+            .get(rec_type)
+            .into_iter()
+            .flatten()
+            .filter_map(|chunk| chunk.get_object_id(FILES_ID_FIELD).ok())
+            .filter(|files_id| !referenced.contains(files_id))
+            .collect();
+        orphans.sort();
+        orphans.dedup();
+        orphans
+    }
+}
+
+/// The values of the fields of an index in a document.
+fn index_key(index: &IndexSpec, doc: &Document) -> Vec<Bson> {
+    index
+        .fields
+        .iter()
+        .map(|field| lookup(doc, field).cloned().unwrap_or(Bson::Null))
+        .collect()
+}
+
+/// Whether two index keys are equal, numbers being equal whatever their type.
+fn same_key(a: &[Bson], b: &[Bson]) -> bool {
+    a.iter()
+        .zip(b)
+        .all(|(a, b)| a == b || compare(a, b) == Some(Ordering::Equal))
+}
+
+/// Decodes the stored documents into records.
+fn decode<T: DeserializeOwned>(docs: Vec<Document>) -> Result<Vec<T>> {
+    docs.into_iter()
+        .map(|doc| bson::from_document(doc).context("Failed to deserialise record"))
+        .collect()
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    state: Mutex<State>,
+    /// Channel changes are sent to, created once something watches for them
+    events: OnceLock<broadcast::Sender<ArchiveEvent>>,
+    /// Records whose serialised size exceeds this many bytes are chunked
+    chunk_threshold: Option<usize>,
+}
+
+impl MemoryBackend {
+    pub fn new(chunk_threshold: Option<usize>) -> Self {
+        MemoryBackend {
+            chunk_threshold,
+            ..MemoryBackend::default()
+        }
+    }
+
+    /// The in-memory backend has nothing to connect to, so doesn't use a URI.
+    pub fn validate_uri(uri: &str) -> std::result::Result<(), String> {
+        if !uri.is_empty() {
+            return Err(format!(
+                "The in-memory backend doesn't use a URI, but '{}' was given",
+                crate::uri::redact(uri)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that the datastore name is not empty. Records aren't stored under the datastore
+    /// name, which only identifies the archive in logs, metrics and spill files.
+    pub fn validate_datastore(datastore: &str) -> std::result::Result<(), String> {
+        if datastore.is_empty() {
+            return Err("Datastore name must not be empty".to_string());
+        }
+        Ok(())
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Sends changes to the records with the given ids to the backend's watchers, if it has any.
+    fn publish<S: AsRef<str>>(
+        &self,
+        rec_type: &ArchiveRecordType,
+        kind: ArchiveEventKind,
+        ids: &[S],
+    ) {
+        let sender = match self.events.get() {
+            Some(sender) if sender.receiver_count() > 0 => sender,
+            _ => return,
+        };
+        for id in ids {
+            // Sending only fails once every watcher has gone.
+            let _ = sender.send(ArchiveEvent {
+                rec_type: rec_type.clone(),
+                kind,
+                id: id.as_ref().to_string(),
+            });
+        }
+    }
+
+    /// Copies of the stored documents of the given type matching a [Filter], in id order.
+    /// Chunked records are left as their manifests.
+    fn matching(&self, rec_type: &ArchiveRecordType, filter: &Filter) -> Vec<Document> {
+        self.state()
+            .records(rec_type)
+            .filter(|(_, stored)| filter.matches(&stored.doc))
+            .map(|(_, stored)| stored.doc.clone())
+            .collect()
+    }
+
+    /// The records of the given type matching a [Filter], in id order, reassembling chunked
+    /// records.
+    fn matching_records(
+        &self,
+        rec_type: &ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<Vec<Document>> {
+        let state = self.state();
+        state
+            .records(rec_type)
+            .filter(|(_, stored)| filter.matches(&stored.doc))
+            .map(|(_, stored)| state.reassemble(rec_type, stored.doc.clone()))
+            .collect()
+    }
+
+    /// Stores documents in order, returning their ids, chunking them over `chunk_threshold`.
+    /// Documents stored before a failure are kept.
+    fn insert_many(
+        &self,
+        rec_type: &ArchiveRecordType,
+        docs: Vec<Document>,
+        chunk_threshold: Option<usize>,
+    ) -> Result<Vec<String>, ArchiveError> {
+        let mut ids = Vec::with_capacity(docs.len());
+        let mut failure = None;
+        {
+            let mut state = self.state();
+            for doc in docs {
+                match state.insert(rec_type, doc, chunk_threshold) {
+                    Ok(id) => ids.push(id),
+                    Err(e) => {
+                        failure = Some(e);
+                        break;
+                    }
+                }
+            }
+        }
+        self.publish(rec_type, ArchiveEventKind::Created, &ids);
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(ids),
+        }
+    }
+
+    /// Removes every record and index.
+    fn clear(&self) {
+        *self.state() = State::default();
+    }
+
+    /// Whether no records are stored.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.state()
+            .records
+            .values()
+            .all(|records| records.is_empty())
+    }
+}
+
+#[async_trait]
+impl ArchiveBackend for MemoryBackend {
+    /// Fails with [ArchiveError::DuplicateKey] if the record's `_id` is already in use.
+    async fn create<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+        let id = self.state().insert(&rec_type, doc, self.chunk_threshold)?;
+        self.publish(&rec_type, ArchiveEventKind::Created, &[&id]);
+        Ok(id)
+    }
+
+    /// Stores the records in order. Records stored before a failure are kept, whatever the
+    /// ordering setting.
+    async fn create_many<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        recs: Vec<T>,
+    ) -> Result<Vec<String>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let docs = recs
+            .iter()
+            .map(|rec| bson::to_document(rec).context("Failed to serialise record to BSON"))
+            .collect::<Result<Vec<_>>>()?;
+        let ids = self.insert_many(&rec_type, docs, self.chunk_threshold)?;
+        debug!("Stored {} records in memory", ids.len());
+        Ok(ids)
+    }
+
+    /// Stores the records while holding the lock on every record, removing those already stored
+    /// should one fail, so readers see the whole batch or none of it.
+    async fn create_atomic(
+        &self,
+        records: Vec<(ArchiveRecordType, Document)>,
+    ) -> Result<Vec<String>, ArchiveError> {
+        let mut written: Vec<(ArchiveRecordType, String)> = Vec::with_capacity(records.len());
+        {
+            let mut state = self.state();
+            for (rec_type, doc) in records {
+                match state.insert(&rec_type, doc, self.chunk_threshold) {
+                    Ok(id) => written.push((rec_type, id)),
+                    Err(e) => {
+                        for (rec_type, id) in &written {
+                            state.remove(rec_type, id);
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        for (rec_type, id) in &written {
+            self.publish(rec_type, ArchiveEventKind::Created, &[&id]);
+        }
+        debug!("Stored batch of {} records in memory", written.len());
+        Ok(written.into_iter().map(|(_, id)| id).collect())
+    }
+
+    async fn find_all<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        Ok(decode(self.matching_records(&rec_type, &Filter::All)?)?)
+    }
+
+    /// Streams the records stored when the stream was created.
+    async fn find_all_stream<'a, T: DeserializeOwned>(
+        &'a self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'a, Result<T, ArchiveError>>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin + 'a,
+    {
+        let docs = self.matching_records(&rec_type, &Filter::All)?;
+        Ok(stream::iter(docs)
+            .map(|doc| Ok(bson::from_document(doc).context("Failed to deserialise record")?))
+            .boxed())
+    }
+
+    async fn find_page<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        request: &PageRequest,
+    ) -> Result<Page<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let after = match &request.after_token {
+            Some(after) => Bound::Excluded(after.clone()),
+            None => Bound::Unbounded,
+        };
+        let mut page: Vec<(String, Document)> = {
+            let state = self.state();
+            match state.records.get(&rec_type) {
+                Some(records) => records
+                    .range((after, Bound::Unbounded))
+                    .take(request.limit.saturating_add(1))
+                    .map(|(id, stored)| {
+                        Ok((id.clone(), state.reassemble(&rec_type, stored.doc.clone())?))
+                    })
+                    .collect::<Result<_>>()?,
+                None => Vec::new(),
+            }
+        };
+        let next_token = match page.len() > request.limit {
+            true => Some(page[request.limit - 1].0.clone()),
+            false => None,
+        };
+        page.truncate(request.limit);
+
+        Ok(Page {
+            items: decode(page.into_iter().map(|(_, doc)| doc).collect())?,
+            next_token,
+        })
+    }
+
+    /// Every record of the type is filtered here.
+    async fn query<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        Ok(decode(self.matching_records(&rec_type, filter)?)?)
+    }
+
+    /// Only the fields are copied out of the stored records, other than chunked records, which
+    /// are reassembled first.
+    async fn find_projected(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        fields: &[String],
+    ) -> Result<Vec<Document>, ArchiveError> {
+        let state = self.state();
+        Ok(state
+            .records(&rec_type)
+            .filter(|(_, stored)| filter.matches(&stored.doc))
+            .map(|(_, stored)| match chunking::manifest_id(&stored.doc) {
+                Some(_) => Ok(projection::project(
+                    &state.reassemble(&rec_type, stored.doc.clone())?,
+                    fields,
+                )),
+                None => Ok(projection::project(&stored.doc, fields)),
+            })
+            .collect::<Result<_>>()?)
+    }
+
+    async fn find_by_id<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<Option<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let doc = {
+            let state = self.state();
+            state
+                .records
+                .get(&rec_type)
+                .and_then(|records| records.get(id))
+                .map(|stored| state.reassemble(&rec_type, stored.doc.clone()))
+                .transpose()?
+        };
+        match doc {
+            Some(doc) => Ok(Some(
+                bson::from_document(doc).context("Failed to deserialise record")?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    async fn delete_by_id(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<bool, ArchiveError> {
+        let deleted = self.state().remove(&rec_type, id);
+        if deleted {
+            self.publish(&rec_type, ArchiveEventKind::Deleted, &[id]);
+        }
+        Ok(deleted)
+    }
+
+    async fn delete_where(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        let deleted: Vec<String> = {
+            let mut state = self.state();
+            let deleted: Vec<String> = state
+                .records(&rec_type)
+                .filter(|(_, stored)| filter.matches(&stored.doc))
+                .map(|(id, _)| id.clone())
+                .collect();
+            for id in &deleted {
+                state.remove(&rec_type, id);
+            }
+            deleted
+        };
+        self.publish(&rec_type, ArchiveEventKind::Deleted, &deleted);
+        debug!("Deleted {} records from memory", deleted.len());
+        Ok(deleted.len() as u64)
+    }
+
+    /// Replaces the record if it exists, keeping its `_id` whatever `_id` the record holds.
+    async fn update_by_id<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        rec: T,
+    ) -> Result<bool, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+        {
+            let mut state = self.state();
+            let exists = state
+                .records
+                .get(&rec_type)
+                .is_some_and(|records| records.contains_key(id));
+            if !exists {
+                return Ok(false);
+            }
+            state.replace(&rec_type, id, doc, self.chunk_threshold)?;
+        }
+        self.publish(&rec_type, ArchiveEventKind::Updated, &[id]);
+        Ok(true)
+    }
+
+    /// Replaces the first record matching the filter, in id order, or stores a new one if none
+    /// does.
+    async fn upsert<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+        let (id, kind) = {
+            let mut state = self.state();
+            let existing = state
+                .records(&rec_type)
+                .find(|(_, stored)| filter.matches(&stored.doc))
+                .map(|(id, _)| id.clone());
+            match existing {
+                Some(id) => {
+                    state.replace(&rec_type, &id, doc, self.chunk_threshold)?;
+                    (id, ArchiveEventKind::Updated)
+                }
+                None => (
+                    state.insert(&rec_type, doc, self.chunk_threshold)?,
+                    ArchiveEventKind::Created,
+                ),
+            }
+        };
+        self.publish(&rec_type, kind, &[&id]);
+        Ok(id)
+    }
+
+    /// Selects each record independently with probability `rate` (0.0 to 1.0).
+    async fn find_sampled<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rate: f64,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let mut docs = self.matching_records(&rec_type, &Filter::All)?;
+        docs.retain(|_| rand::random::<f64>() < rate);
+        Ok(decode(docs)?)
+    }
+
+    /// Chunks are stored along with their record, so are only orphaned if storing the record
+    /// fails.
+    async fn find_orphaned_chunks(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Vec<String>, ArchiveError> {
+        Ok(self
+            .state()
+            .orphaned_chunk_ids(&rec_type)
+            .into_iter()
+            .map(ObjectId::to_hex)
+            .collect())
+    }
+
+    async fn cleanup_orphans(&self, rec_type: ArchiveRecordType) -> Result<u64, ArchiveError> {
+        let mut state = self.state();
+        let orphans = state.orphaned_chunk_ids(&rec_type);
+        let Some(chunks) = state.chunks.get_mut(&rec_type) else {
+            return Ok(0);
+        };
+        let before = chunks.len();
+        chunks.retain(|chunk| {
+            !chunk
+                .get_object_id(FILES_ID_FIELD)
+                .is_ok_and(|files_id| orphans.contains(&files_id))
+        });
+        Ok((before - chunks.len()) as u64)
+    }
+
+    /// Deleted records are freed as they are deleted, so there is nothing to reclaim.
+    async fn reclaim_space(&self, _rec_type: ArchiveRecordType) -> Result<(), ArchiveError> {
+        Ok(())
+    }
+
+    /// Aggregation pipelines are MongoDB specific.
+    async fn merge_into(
+        &self,
+        _source: ArchiveRecordType,
+        _pipeline: Vec<Document>,
+        _target: ArchiveRecordType,
+        _mode: MergeMode,
+    ) -> Result<u64, ArchiveError> {
+        Err(Unsupported {
+            operation: "merge_into",
+            reason: "aggregation pipelines are only supported by the MongoDB backend".to_string(),
+        }
+        .into())
+    }
+
+    /// Streams every change made to the backend's records from now on, like a MongoDB change
+    /// stream.
+    async fn watch(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'static, Result<ArchiveEvent, ArchiveError>>, ArchiveError> {
+        let receiver = self.events.get_or_init(events::channel).subscribe();
+        Ok(events::receive(receiver, rec_type))
+    }
+
+    async fn count(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        Ok(self
+            .state()
+            .records(&rec_type)
+            .filter(|(_, stored)| filter.matches(&stored.doc))
+            .count() as u64)
+    }
+
+    async fn exists(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<bool, ArchiveError> {
+        Ok(self
+            .state()
+            .records(&rec_type)
+            .any(|(_, stored)| filter.matches(&stored.doc)))
+    }
+
+    /// Storage is the size of the records serialised to BSON.
+    async fn stats(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<ArchiveCollectionStats, ArchiveError> {
+        let state = self.state();
+        let mut document_count = 0;
+        let mut storage_bytes = 0;
+        for (_, stored) in state.records(&rec_type) {
+            document_count += 1;
+            storage_bytes += bson::to_vec(&stored.doc)
+                .context("Failed to serialise record to BSON")?
+                .len() as u64;
+        }
+
+        Ok(ArchiveCollectionStats {
+            document_count,
+            storage_bytes,
+            avg_doc_bytes: storage_bytes.checked_div(document_count).unwrap_or(0),
+        })
+    }
+
+    /// Groups by the time each record was last written when grouping by archive time.
+    async fn group_count(
+        &self,
+        rec_type: ArchiveRecordType,
+        group_by: GroupBy,
+    ) -> Result<Vec<(Bson, u64)>, ArchiveError> {
+        let state = self.state();
+        let keys: Vec<Bson> = match group_by {
+            GroupBy::Field(field) => state
+                .records(&rec_type)
+                .map(|(_, stored)| lookup(&stored.doc, &field).cloned().unwrap_or(Bson::Null))
+                .collect(),
+            GroupBy::ArchivedAt(granularity) => state
+                .records(&rec_type)
+                .map(|(_, stored)| Bson::String(granularity.bucket(stored.written_at)))
+                .collect(),
+        };
+        Ok(tally(keys))
+    }
+
+    /// Aggregates the records matching the filter in process.
+    async fn aggregate(
+        &self,
+        rec_type: ArchiveRecordType,
+        spec: &AggregationSpec,
+    ) -> Result<Vec<Document>, ArchiveError> {
+        Ok(aggregate(
+            spec,
+            self.matching_records(&rec_type, &spec.filter)?,
+        ))
+    }
+
+    /// Unique indexes are enforced on every later write, and fail with
+    /// [ArchiveError::DuplicateKey] if stored records already break them. Queries scan every
+    /// record, so non-unique indexes are ignored.
+    async fn ensure_indexes(
+        &self,
+        rec_type: ArchiveRecordType,
+        indexes: &[IndexSpec],
+    ) -> Result<(), ArchiveError> {
+        let mut state = self.state();
+        for index in indexes.iter().filter(|index| index.unique) {
+            if state
+                .unique
+                .get(&rec_type)
+                .is_some_and(|unique| unique.contains(index))
+            {
+                continue;
+            }
+            let keys: Vec<(&String, Vec<Bson>)> = state
+                .records(&rec_type)
+                .map(|(id, stored)| (id, index_key(index, &stored.doc)))
+                .collect();
+            for (i, (id, key)) in keys.iter().enumerate() {
+                if let Some((other, _)) = keys[..i].iter().find(|(_, other)| same_key(key, other)) {
+                    return Err(ArchiveError::DuplicateKey(anyhow!(
+                        "Can't create unique index over {}: records {} and {} have the same values",
+                        index.fields.join(", "),
+                        other,
+                        id
+                    )));
+                }
+            }
+            state
+                .unique
+                .entry(rec_type.clone())
+                .or_default()
+                .push(index.clone());
+        }
+        Ok(())
+    }
+}
+
+impl ArchiveStore {
+    /// Builds a store keeping its records in memory, see [ArchiveBackends::InMemory], e.g. to
+    /// test code that archives records without running a database. Use an
+    /// [ArchiveStoreBuilder] with [ArchiveBackends::InMemory] to configure anything else.
+    pub fn in_memory() -> ArchiveStore {
+        ArchiveStoreBuilder::default()
+            .datastore(DEFAULT_DATASTORE.to_string())
+            .backend(ArchiveBackends::InMemory)
+            .build()
+            .expect("An in-memory store needs no configuration")
+    }
+
+    /// Stores documents in the store's in-memory backend exactly as they are, returning their
+    /// ids, e.g. to set up records as an older version of the crate would have written them.
+    /// Unlike [ArchiveStore::create_many] the documents aren't encoded and get no provenance, so
+    /// aren't stamped with when they were archived. Fails with [Unsupported] if the store doesn't
+    /// use [ArchiveBackends::InMemory], or [ArchiveError::DuplicateKey] if an `_id` is already
+    /// in use, keeping the documents before it.
+    pub fn seed_memory(
+        &self,
+        rec_type: ArchiveRecordType,
+        docs: Vec<Document>,
+    ) -> Result<Vec<String>, ArchiveError> {
+        self.memory_backend("seed_memory")?
+            .insert_many(&rec_type, docs, None)
+    }
+
+    /// The documents of [ArchiveRecordType] held by the store's in-memory backend, in id order,
+    /// as they are stored, e.g. to check that records are compressed or tagged. Tombstoned
+    /// records are included, and chunked records are left as their manifests. Fails with [Unsupported] if the store doesn't use
+    /// [ArchiveBackends::InMemory].
+    pub fn memory_records(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Vec<Document>, ArchiveError> {
+        Ok(self
+            .memory_backend("memory_records")?
+            .matching(&rec_type, &Filter::All))
+    }
+
+    /// Removes every record and index from the store's in-memory backend, e.g. between tests
+    /// sharing a store. Fails with [Unsupported] if the store doesn't use
+    /// [ArchiveBackends::InMemory].
+    pub fn clear_memory(&self) -> Result<(), ArchiveError> {
+        self.memory_backend("clear_memory")?.clear();
+        Ok(())
+    }
+
+    /// The store's in-memory backend, for the named operation, which only it supports.
+    fn memory_backend(&self, operation: &'static str) -> Result<&MemoryBackend, ArchiveError> {
+        match self.inner.backend {
+            ArchiveBackends::InMemory => Ok(self.memory()),
+            ref backend => Err(Unsupported {
+                operation,
+                reason: format!("the {} backend doesn't keep records in memory", backend),
+            }
+            .into()),
+        }
+    }
+}
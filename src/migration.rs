@@ -0,0 +1,181 @@
+/// Schema versioning of archived records. Every record is stored with the schema version of its
+/// [ArchiveRecordType] at the time it was written. When a record written with an older version is
+/// read, it is upgraded one version at a time through the migrations registered for its record
+/// type before being deserialised, so old archives remain readable as record types evolve.
+/// Upgraded records can also be written back with [ArchiveStore::rewrite_migrated], so they are
+/// no longer migrated on every read.
+use crate::envelope::Provenance;
+use crate::{filter, ArchiveError, ArchiveRecordType, ArchiveStore, Filter};
+use anyhow::{Context, Result};
+use bson::{Bson, Document};
+use core::fmt;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Name of the field recording the schema version a record was written with
+pub(crate) const VERSION_FIELD: &str = "_schema_version";
+/// Schema version of record types that have not been given one explicitly
+pub const DEFAULT_SCHEMA_VERSION: u32 = 1;
+
+/// A migration upgrading the JSON representation of a record by one schema version.
+pub type Migration =
+    Arc<dyn Fn(serde_json::Value) -> Result<serde_json::Value> + Send + Sync + 'static>;
+
+/// Returned when a record was written with a newer schema version than the reader knows about.
+#[derive(Debug, Clone)]
+pub struct NewerSchemaVersion {
+    /// The type of the record
+    pub rec_type: ArchiveRecordType,
+    /// The schema version the record was written with
+    pub found: u32,
+    /// The newest schema version understood by the reader
+    pub supported: u32,
+}
+
+impl fmt::Display for NewerSchemaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Archive written by a newer version: {:?} record has schema version {} but only \
+            versions up to {} are supported",
+            self.rec_type, self.found, self.supported
+        )
+    }
+}
+
+impl std::error::Error for NewerSchemaVersion {}
+
+/// The migrations registered on a store, keyed by record type and the version they upgrade from.
+/// Shared between clones of the store, so a migration registered on one applies to all of them.
+#[derive(Clone, Default)]
+pub(crate) struct Migrations {
+    migrations: Arc<RwLock<HashMap<(ArchiveRecordType, u32), Migration>>>,
+}
+
+impl Migrations {
+    /// Registers a migration from `from_version` to `from_version + 1` for the record type,
+    /// replacing any migration previously registered for that step.
+    pub(crate) fn register(
+        &self,
+        rec_type: ArchiveRecordType,
+        from_version: u32,
+        migration: Migration,
+    ) {
+        self.migrations
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert((rec_type, from_version), migration);
+    }
+
+    /// Strips the version field from a stored document, returning the version it was written
+    /// with. Records written before versioning was introduced are treated as the default version.
+    pub(crate) fn take_version(doc: &mut Document) -> u32 {
+        let version = Self::version(doc);
+        doc.remove(VERSION_FIELD);
+        version
+    }
+
+    /// The version a stored document was written with, see [Migrations::take_version].
+    pub(crate) fn version(doc: &Document) -> u32 {
+        match doc.get(VERSION_FIELD) {
+            Some(Bson::Int32(v)) => *v as u32,
+            Some(Bson::Int64(v)) => *v as u32,
+            _ => DEFAULT_SCHEMA_VERSION,
+        }
+    }
+
+    /// Upgrades a record document written with schema version `version` to `current`, running it
+    /// through each registered migration in turn. Documents already at `current` are returned
+    /// untouched.
+    pub(crate) fn migrate(
+        &self,
+        rec_type: &ArchiveRecordType,
+        doc: Document,
+        version: u32,
+        current: u32,
+    ) -> Result<Document> {
+        if version > current {
+            return Err(NewerSchemaVersion {
+                rec_type: rec_type.clone(),
+                found: version,
+                supported: current,
+            }
+            .into());
+        }
+        if version == current {
+            return Ok(doc);
+        }
+
+        let migrations = self.migrations.read().unwrap_or_else(|e| e.into_inner());
+        let mut value = Bson::Document(doc).into_relaxed_extjson();
+        for from in version..current {
+            let migration = migrations.get(&(rec_type.clone(), from)).with_context(|| {
+                format!(
+                    "No migration registered for {:?} records from schema version {}",
+                    rec_type, from
+                )
+            })?;
+            value = migration(value).with_context(|| {
+                format!(
+                    "Failed to migrate {:?} record from schema version {} to {}",
+                    rec_type,
+                    from,
+                    from + 1
+                )
+            })?;
+        }
+
+        match Bson::try_from(value).context("Migrated record is not valid BSON")? {
+            Bson::Document(doc) => Ok(doc),
+            _ => anyhow::bail!("Migrated {:?} record is not a document", rec_type),
+        }
+    }
+}
+
+impl fmt::Debug for Migrations {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let migrations = self.migrations.read().unwrap_or_else(|e| e.into_inner());
+        f.debug_set().entries(migrations.keys()).finish()
+    }
+}
+
+impl ArchiveStore {
+    /// Rewrites the records of [ArchiveRecordType] stored with an older schema version in their
+    /// migrated form, e.g. once after deploying a new schema version, so they are no longer
+    /// migrated on every read. Returns the number of records rewritten. Records keep their ids and
+    /// provenance. Each record is rewritten on its own, so if this fails part way it can simply be
+    /// run again.
+    pub async fn rewrite_migrated(&self, rec_type: ArchiveRecordType) -> Result<u64, ArchiveError> {
+        let current = self.schema_version(&rec_type);
+        let mut outdated = Filter::lt(VERSION_FIELD, current);
+        // Records without a version are read as the default version.
+        if current > DEFAULT_SCHEMA_VERSION {
+            outdated = outdated.or(!Filter::exists(VERSION_FIELD));
+        }
+
+        let mut rewritten = 0;
+        for envelope in self
+            .with_tombstones()
+            .query_envelopes::<Document>(rec_type.clone(), outdated)
+            .await?
+        {
+            let mut rec = envelope.record;
+            let id = match rec.remove("_id") {
+                Some(id) => filter::id_to_string(&id),
+                None => continue,
+            };
+            Provenance {
+                archived_at: envelope.archived_at,
+                node_id: envelope.node_id,
+                tags: envelope.tags,
+                lifecycle: envelope.lifecycle,
+            }
+            .restore(&mut rec);
+            // Tombstones are rewritten too, which update_by_id would leave alone.
+            if self.replace_by_id(rec_type.clone(), &id, rec).await? {
+                rewritten += 1;
+            }
+        }
+        Ok(rewritten)
+    }
+}
@@ -0,0 +1,143 @@
+/// Mirrored archives. A store configured with mirror stores, e.g. a MongoDB store mirrored to S3,
+/// writes every record to its mirrors as well as its own backend, succeeding according to its
+/// [WriteStrategy], and falls back to the mirrors when a read from its own backend fails.
+use crate::{ArchiveError, ArchiveErrorKind, ArchiveStore};
+use anyhow::Context;
+use bson::{oid::ObjectId, Document};
+use core::fmt;
+use futures::future::join_all;
+use log::warn;
+use serde::Serialize;
+use std::future::Future;
+
+/// When a write to a store with mirrors succeeds. Writes are sent to every store at once and are
+/// not atomic across them, so a write that fails may still have been applied to some stores.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WriteStrategy {
+    /// Once the store's own backend and every mirror have written the record.
+    #[default]
+    All,
+    /// Once a majority of the store's own backend and its mirrors have written the record.
+    Quorum,
+    /// Once the store's own backend has written the record. Failed mirror writes are logged and
+    /// otherwise ignored.
+    PrimaryBestEffort,
+}
+
+impl fmt::Display for WriteStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WriteStrategy::All => write!(f, "all"),
+            WriteStrategy::Quorum => write!(f, "quorum"),
+            WriteStrategy::PrimaryBestEffort => write!(f, "primary+best-effort"),
+        }
+    }
+}
+
+/// Serialises a record to a document with an `_id`, generating one if it has none, so that the
+/// record is stored under the same id by the store and its mirrors.
+pub(crate) fn with_id<T: Serialize>(rec: &T) -> Result<Document, ArchiveError> {
+    let mut doc = bson::to_document(rec).context("Failed to serialise record to BSON")?;
+    if !doc.contains_key("_id") {
+        doc.insert("_id", ObjectId::new());
+    }
+    Ok(doc)
+}
+
+/// Adds the mirror a write or read failed on to its error.
+fn mirror_error(op: &str, mirror: &ArchiveStore, err: ArchiveError) -> ArchiveError {
+    err.into_inner()
+        .context(format!("Mirroring {} to {}", op, mirror))
+        .into()
+}
+
+impl ArchiveStore {
+    /// Runs a write with `write` on this store's own backend and on each of its mirrors at once,
+    /// succeeding according to the store's [WriteStrategy]. Returns this store's result when its
+    /// own write succeeded, otherwise the result of the first mirror that succeeded.
+    pub(crate) async fn mirrored_write<'a, R, W, F>(
+        &'a self,
+        op: &str,
+        write: W,
+    ) -> Result<R, ArchiveError>
+    where
+        W: Fn(&'a ArchiveStore) -> F,
+        F: Future<Output = Result<R, ArchiveError>>,
+    {
+        let mirrors = &self.inner.mirrors;
+        if mirrors.is_empty() {
+            return write(self).await;
+        }
+
+        let (primary, written) = futures::join!(write(self), join_all(mirrors.iter().map(&write)));
+        let mut succeeded = Vec::new();
+        let mut failed = Vec::new();
+        for (mirror, result) in mirrors.iter().zip(written) {
+            match result {
+                Ok(value) => succeeded.push(value),
+                Err(e) => failed.push(mirror_error(op, mirror, e)),
+            }
+        }
+
+        let stores = mirrors.len() + 1;
+        let acknowledged = succeeded.len() + usize::from(primary.is_ok());
+        let strategy = self.inner.write_strategy;
+        let failure = match strategy {
+            WriteStrategy::All => primary.is_err() || !failed.is_empty(),
+            WriteStrategy::Quorum => acknowledged * 2 <= stores,
+            WriteStrategy::PrimaryBestEffort => primary.is_err(),
+        };
+        if failure {
+            // Report this store's own failure first, as its backend is the one reads prefer.
+            return match primary {
+                Err(e) => Err(e),
+                Ok(_) => Err(failed.remove(0)),
+            };
+        }
+
+        for e in &failed {
+            warn!(
+                "{} succeeded with {} of {} stores ({}): {:#}",
+                op, acknowledged, stores, strategy, e
+            );
+        }
+        match primary {
+            Ok(value) => Ok(value),
+            Err(e) => {
+                warn!("{} succeeded on mirrors only ({}): {:#}", op, strategy, e);
+                Ok(succeeded.remove(0))
+            }
+        }
+    }
+
+    /// Runs a read with `read` on this store's own backend, falling back to each of its mirrors in
+    /// turn if it fails. Invalid reads fail on every store alike, so are never retried on mirrors.
+    /// If every store fails, this store's own error is returned.
+    pub(crate) async fn mirrored_read<'a, R, Q, F>(
+        &'a self,
+        op: &str,
+        read: Q,
+    ) -> Result<R, ArchiveError>
+    where
+        Q: Fn(&'a ArchiveStore) -> F,
+        F: Future<Output = Result<R, ArchiveError>>,
+    {
+        let err = match read(self).await {
+            Err(e)
+                if !self.inner.mirrors.is_empty() && e.kind() != ArchiveErrorKind::InvalidInput =>
+            {
+                e
+            }
+            result => return result,
+        };
+
+        for mirror in &self.inner.mirrors {
+            warn!("{} failed, falling back to {}: {:#}", op, mirror, err);
+            match read(mirror).await {
+                Ok(value) => return Ok(value),
+                Err(e) => warn!("{:#}", mirror_error(op, mirror, e)),
+            }
+        }
+        Err(err)
+    }
+}
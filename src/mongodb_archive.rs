@@ -1,60 +1,627 @@
 /// An implementation of an archive datastore that uses MongoDB as its backend. Within the
 /// database, this backend stores account data and transaction data as separate document
-/// collections as defined by the [ACCOUNT_COLLECTION] and [TRANSACTION_COLLECTION] constants. It
-/// uses the datastore name passed in as the name of the MongoDB database to archive to/from.
-use crate::{ArchiveBackend, ArchiveRecordType};
+/// collections as defined by the [ACCOUNT_COLLECTION] and [TRANSACTION_COLLECTION] constants,
+/// while custom record types are stored in collections of their own name. Blocks and receipts are
+/// indexed on [crate::BLOCK_HEIGHT_FIELD] and [crate::RECEIPT_TX_HASH_FIELD]. It uses the
+/// datastore name passed in as the name of the MongoDB database to archive to/from.
+use crate::filter::id_to_string;
+use crate::stats::KEY_FIELD;
+use crate::{
+    chunking, uri, Acknowledgment, Aggregate, AggregationSpec, ArchiveBackend,
+    ArchiveCollectionStats, ArchiveError, ArchiveEvent, ArchiveEventKind, ArchiveRecordType,
+    Credentials, Filter, GroupBy, IndexSpec, MergeMode, Page, PageRequest, ReadPreference,
+    Unsupported, WriteConcern,
+};
 use anyhow::{Context, Result};
 use async_trait::async_trait;
-use futures::stream::TryStreamExt;
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
 use log::debug;
-use mongodb::{bson::doc, options::ClientOptions, Client, Collection};
+use mongodb::{
+    bson::{doc, oid::ObjectId, Bson, Document},
+    change_stream::event::OperationType,
+    error::ErrorKind,
+    options::{
+        self, ClientOptions, CollectionOptions, ConnectionString, FindOneAndReplaceOptions,
+        FindOneOptions, FindOptions, IndexOptions, InsertManyOptions, ReadPreferenceOptions,
+        ReturnDocument, SelectionCriteria, TransactionOptions,
+    },
+    Client, ClientSession, Collection, IndexModel,
+};
 use serde::{de::DeserializeOwned, Serialize};
 use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+/// Parses an id returned by [id_to_string] back into the `_id` value of the stored document. Ids
+/// in the `ObjectId("...")` form returned by earlier versions are accepted too.
+fn parse_id(id: &str) -> Bson {
+    let hex = id
+        .strip_prefix("ObjectId(\"")
+        .and_then(|id| id.strip_suffix("\")"))
+        .unwrap_or(id);
+    match ObjectId::parse_str(hex) {
+        Ok(oid) => Bson::ObjectId(oid),
+        Err(_) => Bson::String(id.trim_matches('"').to_string()),
+    }
+}
+
+/// The BSON types of `_id` values, grouped and ordered as MongoDB sorts them.
+const ID_TYPE_ORDER: &[&[&str]] = &[
+    &["minKey"],
+    &["null"],
+    &["int", "long", "double", "decimal"],
+    &["string", "symbol"],
+    &["object"],
+    &["binData"],
+    &["objectId"],
+    &["bool"],
+    &["date"],
+    &["timestamp"],
+    &["regex"],
+    &["maxKey"],
+];
+
+/// Builds the query for the documents sorting after the given `_id`. `$gt` only matches values of
+/// the same type, so ids of the types sorting later are matched by type, letting pages run on
+/// across collections mixing string ids and ObjectIds.
+fn after_id(id: Bson) -> Document {
+    let type_name = match id {
+        Bson::ObjectId(_) => "objectId",
+        _ => "string",
+    };
+    let later: Vec<&str> = ID_TYPE_ORDER
+        .iter()
+        .skip_while(|group| !group.contains(&type_name))
+        .skip(1)
+        .flat_map(|group| group.iter().copied())
+        .collect();
+    doc! { "$or": [{ "_id": { "$gt": id } }, { "_id": { "$type": later } }] }
+}
+
+/// Characters MongoDB does not allow in database names
+const FORBIDDEN_DATASTORE_CHARS: &[char] = &[
+    '/', '\\', '.', ' ', '"', '$', '*', '<', '>', ':', '|', '?', '\0',
+];
+/// Longest database name MongoDB allows, in bytes
+const MAX_DATASTORE_LEN: usize = 63;
+
+/// Error code returned by the server for operations on a collection that doesn't exist
+const NAMESPACE_NOT_FOUND: i32 = 26;
+
+/// Error code returned by a standalone server for operations that need a replica set, such as
+/// transactions
+const ILLEGAL_OPERATION: i32 = 20;
+
+/// Translates a [Filter] into a MongoDB query document.
+fn filter_document(filter: &Filter) -> Document {
+    let all =
+        |filters: &[Filter]| -> Vec<Document> { filters.iter().map(filter_document).collect() };
+    match filter {
+        Filter::All => doc! {},
+        Filter::Eq(field, value) => doc! { field: { "$eq": value } },
+        Filter::Ne(field, value) => doc! { field: { "$ne": value } },
+        Filter::Gt(field, value) => doc! { field: { "$gt": value } },
+        Filter::Gte(field, value) => doc! { field: { "$gte": value } },
+        Filter::Lt(field, value) => doc! { field: { "$lt": value } },
+        Filter::Lte(field, value) => doc! { field: { "$lte": value } },
+        Filter::In(field, values) => doc! { field: { "$in": values } },
+        Filter::Exists(field, exists) => doc! { field: { "$exists": exists } },
+        Filter::And(filters) => doc! { "$and": all(filters) },
+        Filter::Or(filters) => doc! { "$or": all(filters) },
+        // $not only applies to a single field's operator, so negate whole filters with $nor.
+        Filter::Not(filter) => doc! { "$nor": [filter_document(filter)] },
+    }
+}
+
+/// Reads a numeric statistic returned by the server, which may be of any numeric BSON type.
+fn stat_u64(value: Option<&Bson>) -> u64 {
+    match value {
+        Some(Bson::Int32(n)) => *n as u64,
+        Some(Bson::Int64(n)) => *n as u64,
+        Some(Bson::Double(n)) => *n as u64,
+        _ => 0,
+    }
+}
 
 /// MongoDB collection name for storing account data
 const ACCOUNT_COLLECTION: &str = "accounts";
 /// MongoDB collection name for storing trasnaction data
 const TRANSACTION_COLLECTION: &str = "transaction_data";
+/// MongoDB collection name for storing blocks
+const BLOCK_COLLECTION: &str = "blocks";
+/// MongoDB collection name for storing transaction receipts
+const RECEIPT_COLLECTION: &str = "receipts";
+/// Maximum size of a BSON document accepted by MongoDB (16MB)
+pub const MAX_DOCUMENT_SIZE: usize = 16 * 1024 * 1024;
+/// Largest chunk payload we store in a single chunk document, leaving headroom below
+/// [MAX_DOCUMENT_SIZE] for the chunk's other fields
+const MAX_CHUNK_SIZE: usize = MAX_DOCUMENT_SIZE - 16 * 1024;
 
-#[derive(Debug)]
 pub struct MongoDBBackend {
     pub uri: String,
     pub datastore: String,
+    /// Credentials used in place of any in the URI
+    pub credentials: Option<Credentials>,
+    /// The client used for every operation. It may be handed an already connected client, e.g.
+    /// one shared by an [crate::ArchiveRegistry], otherwise one is created from `uri` on first
+    /// use and reused from then on.
+    pub client: OnceCell<Client>,
+    /// Records whose serialised size exceeds this many bytes are split into chunks stored in a
+    /// sidecar collection. Chunking is disabled when unset.
+    pub chunk_threshold: Option<usize>,
+    /// Whether batches of records are inserted in order, stopping at the first failure
+    pub ordered_inserts: bool,
+    /// Write concern applied to writes, overriding any given in the URI
+    pub write_concern: Option<WriteConcern>,
+    /// Read preference applied to reads, overriding any given in the URI
+    pub read_preference: Option<ReadPreference>,
+    /// Record types whose collections are known to have their index, shared with the backends
+    /// derived from this one by [MongoDBBackend::with_consistency]
+    pub indexed: Arc<Mutex<HashSet<ArchiveRecordType>>>,
+}
+
+impl std::fmt::Debug for MongoDBBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("MongoDBBackend")
+            .field("uri", &uri::redact(&self.uri))
+            .field("datastore", &self.datastore)
+            .field("credentials", &self.credentials)
+            .field("chunk_threshold", &self.chunk_threshold)
+            .field("ordered_inserts", &self.ordered_inserts)
+            .field("write_concern", &self.write_concern)
+            .field("read_preference", &self.read_preference)
+            .finish_non_exhaustive()
+    }
+}
+
+impl From<&WriteConcern> for options::WriteConcern {
+    fn from(write_concern: &WriteConcern) -> Self {
+        options::WriteConcern::builder()
+            .w(write_concern.w.as_ref().map(|w| match w {
+                Acknowledgment::Nodes(n) => options::Acknowledgment::Nodes(*n),
+                Acknowledgment::Majority => options::Acknowledgment::Majority,
+            }))
+            .journal(write_concern.journal)
+            .w_timeout(write_concern.w_timeout)
+            .build()
+    }
+}
+
+impl From<&ReadPreference> for SelectionCriteria {
+    fn from(read_preference: &ReadPreference) -> Self {
+        SelectionCriteria::ReadPreference(match read_preference {
+            ReadPreference::Primary => options::ReadPreference::Primary,
+            ReadPreference::SecondaryPreferred => options::ReadPreference::SecondaryPreferred {
+                options: ReadPreferenceOptions::default(),
+            },
+            ReadPreference::Nearest => options::ReadPreference::Nearest {
+                options: ReadPreferenceOptions::default(),
+            },
+        })
+    }
+}
+
+impl MongoDBBackend {
+    /// Checks that the URI is a well formed MongoDB connection string, without connecting or
+    /// resolving `mongodb+srv://` hosts.
+    pub fn validate_uri(uri: &str) -> std::result::Result<(), String> {
+        if !uri.starts_with("mongodb://") && !uri.starts_with("mongodb+srv://") {
+            return Err(format!(
+                "MongoDB URI must start with 'mongodb://' or 'mongodb+srv://', got '{}'",
+                uri::redact(uri)
+            ));
+        }
+        ConnectionString::parse(uri)
+            .map(|_| ())
+            .map_err(|e| format!("Invalid MongoDB URI '{}': {}", uri::redact(uri), e))
+    }
+
+    /// Checks that the name can be used as a MongoDB database name.
+    pub fn validate_datastore(datastore: &str) -> std::result::Result<(), String> {
+        if datastore.is_empty() {
+            return Err("Datastore name must not be empty".to_string());
+        }
+        if datastore.len() > MAX_DATASTORE_LEN {
+            return Err(format!(
+                "Datastore name '{}' is longer than MongoDB's limit of {} bytes",
+                datastore, MAX_DATASTORE_LEN
+            ));
+        }
+        if let Some(c) = datastore
+            .chars()
+            .find(|c| FORBIDDEN_DATASTORE_CHARS.contains(c))
+        {
+            return Err(format!(
+                "Datastore name '{}' contains {:?}, which MongoDB does not allow in database names",
+                datastore, c
+            ));
+        }
+        Ok(())
+    }
+
+    /// Parse the given URI and create a client handle for it. The returned client owns a
+    /// connection pool and is cheap to clone, so clones should be shared rather than calling this
+    /// repeatedly.
+    pub async fn connect(uri: &str, credentials: Option<&Credentials>) -> Result<Client> {
+        Self::connect_with(uri, credentials, None, None).await
+    }
+
+    /// Parses the given URI into client options, overriding the credentials, write concern and
+    /// read preference it specifies with those given, if any.
+    pub async fn client_options(
+        uri: &str,
+        credentials: Option<&Credentials>,
+        write_concern: Option<&WriteConcern>,
+        read_preference: Option<&ReadPreference>,
+    ) -> Result<ClientOptions> {
+        // Set DB client options, including URI
+        let mut options = ClientOptions::parse(uri).await.context(format!(
+            "Failed to parse MongoDB URI: '{}'",
+            uri::redact(uri)
+        ))?;
+
+        if let Some(credentials) = credentials {
+            let (username, password) = credentials.resolve().await?;
+            // Keep the auth source and mechanism the URI gives, if any.
+            let credential = options.credential.get_or_insert_with(Default::default);
+            if username.is_some() {
+                credential.username = username;
+            }
+            credential.password = Some(password);
+        }
+        if let Some(write_concern) = write_concern {
+            options.write_concern = Some(write_concern.into());
+        }
+        if let Some(read_preference) = read_preference {
+            options.selection_criteria = Some(read_preference.into());
+        }
+        Ok(options)
+    }
+
+    /// Creates a client handle for the given URI with the given credentials, write concern and
+    /// read preference.
+    async fn connect_with(
+        uri: &str,
+        credentials: Option<&Credentials>,
+        write_concern: Option<&WriteConcern>,
+        read_preference: Option<&ReadPreference>,
+    ) -> Result<Client> {
+        let options =
+            Self::client_options(uri, credentials, write_concern, read_preference).await?;
+
+        Client::with_options(options).context("Failed to set MongoDB client options")
+    }
+
+    /// Returns the backend's client, creating it from `uri` the first time it is needed.
+    async fn client(&self) -> Result<Client> {
+        // The client is only created once, as parsing the URI (which may involve SRV and TXT
+        // lookups) and starting the connection pool is expensive. The driver reconnects and
+        // retries on its own when connections drop, so the client stays usable for the lifetime
+        // of the backend. If creating it fails nothing is cached and the next call tries again.
+        let client = self
+            .client
+            .get_or_try_init(|| {
+                Self::connect_with(
+                    &self.uri,
+                    self.credentials.as_ref(),
+                    self.write_concern.as_ref(),
+                    self.read_preference.as_ref(),
+                )
+            })
+            .await?;
+        Ok(client.clone())
+    }
+
+    /// A backend sharing this backend's client, with the given write concern and read preference
+    /// in place of its own, for operations made with per-call options. Connects first, so that
+    /// the client isn't created again for every call.
+    pub(crate) async fn with_consistency(
+        &self,
+        write_concern: Option<&WriteConcern>,
+        read_preference: Option<&ReadPreference>,
+    ) -> Result<MongoDBBackend> {
+        let client = self.client().await?;
+        Ok(MongoDBBackend {
+            uri: self.uri.clone(),
+            datastore: self.datastore.clone(),
+            credentials: self.credentials.clone(),
+            client: OnceCell::new_with(Some(client)),
+            chunk_threshold: self.chunk_threshold,
+            ordered_inserts: self.ordered_inserts,
+            write_concern: write_concern.or(self.write_concern.as_ref()).cloned(),
+            read_preference: read_preference.or(self.read_preference.as_ref()).cloned(),
+            indexed: self.indexed.clone(),
+        })
+    }
+
+    /// Names of the databases on the deployment the user may access, for listing namespaces.
+    pub(crate) async fn database_names(&self) -> Result<Vec<String>> {
+        let client = self.client().await?;
+        client
+            .list_database_names(None, None)
+            .await
+            .context("Failed to list databases")
+    }
+
+    /// Name of the collection used to store records of the given type.
+    fn collection_name(rec_type: &ArchiveRecordType) -> &str {
+        match rec_type {
+            ArchiveRecordType::Account => ACCOUNT_COLLECTION,
+            ArchiveRecordType::TransactionBatch => TRANSACTION_COLLECTION,
+            ArchiveRecordType::Block => BLOCK_COLLECTION,
+            ArchiveRecordType::Receipt => RECEIPT_COLLECTION,
+            ArchiveRecordType::Custom(name) => name,
+        }
+    }
+
+    /// Returns a handle on the collection used to store records of the given type within the
+    /// datastore's database, first making sure it has the index on the type's indexed field, if
+    /// any, the first time the type is used.
+    async fn collection<T>(&self, rec_type: ArchiveRecordType) -> Result<Collection<T>> {
+        // Reuse the shared client if we were handed one, otherwise connect.
+        let client = self.client().await?;
+
+        // Associate with a specific database
+        let db = client.database(&self.datastore);
+
+        // Apply our settings to the collection too, as a shared client may have been created
+        // with different ones.
+        let options = CollectionOptions::builder()
+            .write_concern(self.write_concern.as_ref().map(Into::into))
+            .selection_criteria(self.read_preference.as_ref().map(Into::into))
+            .build();
+        let collection = db.collection_with_options(Self::collection_name(&rec_type), options);
+
+        if let Some(field) = rec_type.indexed_field() {
+            if !self
+                .indexed
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .contains(&rec_type)
+            {
+                let index = IndexModel::builder().keys(doc! { field: 1 }).build();
+                collection
+                    .create_index(index, None)
+                    .await
+                    .with_context(|| format!("Failed to create {} index", field))?;
+                self.indexed
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(rec_type);
+            }
+        }
+        Ok(collection)
+    }
+
+    /// Returns a handle on the sidecar collection holding the chunks of chunked records of the
+    /// given type.
+    async fn chunk_collection(&self, rec_type: ArchiveRecordType) -> Result<Collection<Document>> {
+        let client = self.client().await?;
+        let db = client.database(&self.datastore);
+
+        Ok(db.collection(&format!("{}_chunks", Self::collection_name(&rec_type))))
+    }
+
+    /// Returns a handle on the sidecar chunk collection, first making sure it has the unique index
+    /// on each chunk's record and position.
+    async fn indexed_chunk_collection(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Collection<Document>> {
+        let chunk_collection = self.chunk_collection(rec_type).await?;
+        let index = IndexModel::builder()
+            .keys(doc! { chunking::FILES_ID_FIELD: 1, chunking::INDEX_FIELD: 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        chunk_collection
+            .create_index(index, None)
+            .await
+            .context("Failed to create chunk index")?;
+        Ok(chunk_collection)
+    }
+
+    /// Inserts every record inside the session's transaction, returning their ids. Oversized
+    /// records have already been split into their manifest and chunks.
+    async fn insert_in_transaction(
+        &self,
+        session: &mut ClientSession,
+        records: Vec<(ArchiveRecordType, Document, Option<Vec<Document>>)>,
+    ) -> Result<Vec<String>> {
+        let mut ids = Vec::with_capacity(records.len());
+        for (rec_type, rec, chunks) in records {
+            if let Some(chunks) = chunks {
+                self.chunk_collection(rec_type.clone())
+                    .await?
+                    .insert_many_with_session(chunks, None, session)
+                    .await
+                    .context("Failed to insert record chunks")?;
+            }
+            let collection: Collection<Document> = self.collection(rec_type).await?;
+            let res = collection
+                .insert_one_with_session(rec, None, session)
+                .await
+                .map_err(|e| match *e.kind {
+                    ErrorKind::Command(ref err) if err.code == ILLEGAL_OPERATION => {
+                        anyhow::Error::new(Unsupported {
+                            operation: "create_atomic",
+                            reason: format!(
+                                "transactions need a replica set or sharded cluster ({})",
+                                err.message
+                            ),
+                        })
+                    }
+                    _ => anyhow::Error::new(e).context("Failed to insert document"),
+                })?;
+            ids.push(id_to_string(&res.inserted_id));
+        }
+        Ok(ids)
+    }
+
+    /// Splits an oversized serialised record into chunks, storing the chunks in the sidecar
+    /// collection and a manifest in the main collection. Chunks are written before the manifest,
+    /// so an interrupted write can leave unreferenced chunks but never a manifest without them.
+    async fn create_chunked(
+        &self,
+        rec_type: ArchiveRecordType,
+        raw: &[u8],
+        threshold: usize,
+    ) -> Result<String> {
+        let (manifest, chunks) = chunking::split(raw, threshold.clamp(1, MAX_CHUNK_SIZE));
+
+        let chunk_collection = self.indexed_chunk_collection(rec_type.clone()).await?;
+        chunk_collection
+            .insert_many(chunks, None)
+            .await
+            .context("Failed to insert record chunks")?;
+
+        let collection: Collection<Document> = self.collection(rec_type).await?;
+        let res = collection
+            .insert_one(manifest, None)
+            .await
+            .context("Failed to insert chunk manifest")?;
+
+        debug!("Inserted chunked record {}", res.inserted_id.to_string());
+
+        Ok(id_to_string(&res.inserted_id))
+    }
+
+    /// Serialises a record replacing a stored one, returning the document to store in its place.
+    /// Records over the chunk threshold are split, with their chunks written first under a fresh
+    /// id recorded in the manifest returned, as the manifest keeps the replaced record's id.
+    async fn replacement<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+    ) -> Result<Document> {
+        let doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+        let Some(threshold) = self.chunk_threshold else {
+            return Ok(doc);
+        };
+        let raw = bson::to_vec(&doc).context("Failed to serialise record to BSON")?;
+        if raw.len() <= threshold {
+            return Ok(doc);
+        }
+        let (mut manifest, chunks) =
+            chunking::split_replacement(&raw, threshold.clamp(1, MAX_CHUNK_SIZE));
+        self.indexed_chunk_collection(rec_type)
+            .await?
+            .insert_many(chunks, None)
+            .await
+            .context("Failed to insert record chunks")?;
+        // Upserts that insert the record keep its id, as plain records do.
+        if let Some(id) = doc.get("_id") {
+            manifest.insert("_id", id.clone());
+        }
+        Ok(manifest)
+    }
+
+    /// Deletes the chunks of a record, if `doc` is the manifest of a chunked record.
+    async fn delete_chunks(&self, rec_type: ArchiveRecordType, doc: &Document) -> Result<()> {
+        if let Some(files_id) = chunking::manifest_id(doc) {
+            self.chunk_collection(rec_type)
+                .await?
+                .delete_many(doc! { chunking::FILES_ID_FIELD: files_id }, None)
+                .await
+                .context("Failed to delete record chunks")?;
+        }
+        Ok(())
+    }
+
+    /// Returns the `files_id` of every chunk group in the sidecar collection that has no manifest
+    /// in the main collection.
+    async fn orphaned_chunk_ids(&self, rec_type: ArchiveRecordType) -> Result<Vec<Bson>> {
+        let chunk_collection = self.chunk_collection(rec_type.clone()).await?;
+        // Manifests of replacement records hold the id their chunks share, others share theirs.
+        let pipeline = vec![
+            doc! { "$group": { "_id": format!("${}", chunking::FILES_ID_FIELD) } },
+            doc! { "$lookup": {
+                "from": Self::collection_name(&rec_type),
+                "localField": "_id",
+                "foreignField": "_id",
+                "as": "manifest",
+            } },
+            doc! { "$lookup": {
+                "from": Self::collection_name(&rec_type),
+                "localField": "_id",
+                "foreignField": format!(
+                    "{}.{}",
+                    chunking::MANIFEST_FIELD,
+                    chunking::FILES_ID_FIELD
+                ),
+                "as": "replacement",
+            } },
+            doc! { "$match": { "manifest": { "$size": 0 }, "replacement": { "$size": 0 } } },
+        ];
+        let groups: Vec<Document> = chunk_collection
+            .aggregate(pipeline, None)
+            .await
+            .context("Failed to search for orphaned chunks")?
+            .try_collect()
+            .await?;
+
+        Ok(groups
+            .into_iter()
+            .filter_map(|mut group| group.remove("_id"))
+            .collect())
+    }
+
+    /// Deserialises a document read from the main collection, first fetching and reassembling
+    /// its chunks if it is the manifest of a chunked record.
+    async fn decode<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        doc: Document,
+    ) -> Result<T> {
+        let id = match chunking::manifest_id(&doc) {
+            Some(id) => id,
+            None => return bson::from_document(doc).context("Failed to deserialise document"),
+        };
+
+        let chunk_collection = self.chunk_collection(rec_type).await?;
+        let options = FindOptions::builder()
+            .sort(doc! { chunking::INDEX_FIELD: 1 })
+            .build();
+        let chunks: Vec<Document> = chunk_collection
+            .find(doc! { chunking::FILES_ID_FIELD: id }, options)
+            .await
+            .context("Failed to find record chunks")?
+            .try_collect()
+            .await?;
+
+        let raw = chunking::reassemble(&doc, chunks)?;
+        let mut reassembled: Document =
+            bson::from_slice(&raw).context("Failed to deserialise reassembled record")?;
+        // Chunked records are identified by their manifest's id, as plain records are by theirs.
+        if !reassembled.contains_key("_id") {
+            reassembled.insert("_id", doc.get("_id").cloned().unwrap_or(Bson::ObjectId(id)));
+        }
+        bson::from_document(reassembled).context("Failed to deserialise reassembled record")
+    }
 }
 
 #[async_trait]
 impl ArchiveBackend for MongoDBBackend {
     /// Take any blob, as long as it can be serialised to BSON, and insert it into the relevant
     /// collection.
-    async fn create<T: Serialize>(&mut self, rec_type: ArchiveRecordType, rec: T) -> Result<String>
+    async fn create<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+    ) -> Result<String, ArchiveError>
     where
         T: Borrow<T> + std::marker::Send + std::marker::Sync,
     {
-        // We call connect each time rather than taking a handle and holding onto it. The Rust
-        // driver for MongoDB handles connection pooling and is likely to do a better job at us of
-        // managing connections and retries than us. The connect call below will generally be a
-        // no-op unless the connection was dropped.
-
-        // Set DB client options, including URI and then create client handle
-        let options = ClientOptions::parse(&self.uri)
-            .await
-            .context(format!("Failed to parse MongoDB URI: '{}'", self.uri))?;
-
-        let client =
-            Client::with_options(options).context("Failed to set MongoDB client options")?;
-
-        // Associate with a specific database
-        let db = client.database(&self.datastore);
+        // Records over the chunk threshold are split across the sidecar chunk collection.
+        if let Some(threshold) = self.chunk_threshold {
+            let raw = bson::to_vec(&rec).context("Failed to serialise record to BSON")?;
+            if raw.len() > threshold {
+                return Ok(self.create_chunked(rec_type, &raw, threshold).await?);
+            }
+        }
 
         // Retrieve the relevant collection handle.
-        let collection: Collection<T>;
-        if let ArchiveRecordType::Account = rec_type {
-            collection = db.collection(ACCOUNT_COLLECTION);
-        } else if let ArchiveRecordType::TransactionBatch = rec_type {
-            collection = db.collection(TRANSACTION_COLLECTION);
-        } else {
-            panic!("Invalid archive record type");
-        }
+        let collection: Collection<T> = self.collection(rec_type).await?;
 
         // Now insert the record that was passed in....
         let res = collection
@@ -65,60 +632,888 @@ impl ArchiveBackend for MongoDBBackend {
         // Here we should log the doc ID
         debug!("Inserted {}", res.inserted_id.to_string());
 
-        Ok(res.inserted_id.to_owned().to_string())
+        Ok(id_to_string(&res.inserted_id))
+    }
+
+    /// Inserts the records with a single `insert_many`, which the driver splits into as few
+    /// round trips as the server allows. Oversized records are chunked and inserted individually.
+    async fn create_many<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        recs: Vec<T>,
+    ) -> Result<Vec<String>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let mut ids = vec![String::new(); recs.len()];
+        // Records inserted in bulk, along with their position in `recs`
+        let mut batch = Vec::with_capacity(recs.len());
+        for (i, rec) in recs.iter().enumerate() {
+            let doc = bson::to_document(rec).context("Failed to serialise record to BSON")?;
+            if let Some(threshold) = self.chunk_threshold {
+                let raw = bson::to_vec(&doc).context("Failed to serialise record to BSON")?;
+                if raw.len() > threshold {
+                    ids[i] = self
+                        .create_chunked(rec_type.clone(), &raw, threshold)
+                        .await?;
+                    continue;
+                }
+            }
+            batch.push((i, doc));
+        }
+        if batch.is_empty() {
+            return Ok(ids);
+        }
+
+        let collection: Collection<Document> = self.collection(rec_type).await?;
+        let options = InsertManyOptions::builder()
+            .ordered(self.ordered_inserts)
+            .build();
+        let (positions, docs): (Vec<usize>, Vec<Document>) = batch.into_iter().unzip();
+        let res = collection
+            .insert_many(docs, options)
+            .await
+            .context("Failed to insert documents")?;
+
+        // Inserted ids are keyed by position within the batch.
+        for (n, id) in res.inserted_ids {
+            ids[positions[n]] = id_to_string(&id);
+        }
+        debug!("Inserted {} documents", ids.len());
+
+        Ok(ids)
+    }
+
+    /// Inserts the records within a multi-document transaction, which is only committed once
+    /// every insert has succeeded and is aborted otherwise. Transactions need a replica set or
+    /// sharded cluster, a standalone server fails with [Unsupported].
+    async fn create_atomic(
+        &self,
+        records: Vec<(ArchiveRecordType, Document)>,
+    ) -> Result<Vec<String>, ArchiveError> {
+        // Split oversized records up front, as the chunk index can't be created inside the
+        // transaction.
+        let mut prepared = Vec::with_capacity(records.len());
+        for (rec_type, rec) in records {
+            let raw = match self.chunk_threshold {
+                Some(threshold) => Some((
+                    bson::to_vec(&rec).context("Failed to serialise record to BSON")?,
+                    threshold,
+                )),
+                None => None,
+            };
+            match raw {
+                Some((raw, threshold)) if raw.len() > threshold => {
+                    let (manifest, chunks) =
+                        chunking::split(&raw, threshold.clamp(1, MAX_CHUNK_SIZE));
+                    self.indexed_chunk_collection(rec_type.clone()).await?;
+                    prepared.push((rec_type, manifest, Some(chunks)));
+                }
+                _ => prepared.push((rec_type, rec, None)),
+            }
+        }
+
+        let client = self.client().await?;
+        let mut session = client
+            .start_session(None)
+            .await
+            .context("Failed to start session")?;
+        let options = TransactionOptions::builder()
+            .write_concern(self.write_concern.as_ref().map(Into::into))
+            .build();
+        session
+            .start_transaction(options)
+            .await
+            .context("Failed to start transaction")?;
+
+        match self.insert_in_transaction(&mut session, prepared).await {
+            Ok(ids) => {
+                session
+                    .commit_transaction()
+                    .await
+                    .context("Failed to commit transaction")?;
+                debug!("Atomically inserted {} records", ids.len());
+                Ok(ids)
+            }
+            Err(e) => {
+                // The server aborts the transaction itself if we can't reach it.
+                if let Err(abort) = session.abort_transaction().await {
+                    debug!("Failed to abort transaction: {}", abort);
+                }
+                Err(e.into())
+            }
+        }
     }
 
     /// Query data store for all records matching a specific attribute. For example all accounts
     /// in the [ACCOUNT_COLLECTION] table with a specific account ID.
-    async fn find_all<T: DeserializeOwned>(&mut self, rec_type: ArchiveRecordType) -> Result<Vec<T>>
+    async fn find_all<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Vec<T>, ArchiveError>
     where
         T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
     {
-        // We call connect each time rather than taking a handle and holding onto it. The Rust
-        // driver for MongoDB handles connection pooling and is likely to do a better job at us of
-        // managing connections and retries than us. The connect call below will generally be a
-        // no-op unless the connection was dropped.
+        self.query(rec_type, &Filter::All).await
+    }
 
-        // Set DB client options, including URI and then create client handle
-        let options = ClientOptions::parse(&self.uri)
+    /// Streams the records of the given type as they are read from the cursor, rather than
+    /// collecting them first, so that arbitrarily large collections can be processed with
+    /// bounded memory.
+    async fn find_all_stream<'a, T: DeserializeOwned>(
+        &'a self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'a, Result<T, ArchiveError>>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin + 'a,
+    {
+        let collection: Collection<Document> = self.collection(rec_type.clone()).await?;
+        let cursor = collection
+            .find(doc! {}, None)
             .await
-            .context(format!("Failed to parse MongoDB URI: '{}'", self.uri))?;
+            .context("Failed to find documents")?;
 
-        let client =
-            Client::with_options(options).context("Failed to set MongoDB client options")?;
+        Ok(cursor
+            .map_err(|e| anyhow::Error::new(e).context("Failed to read document"))
+            .and_then(move |doc| self.decode(rec_type.clone(), doc))
+            .map_err(ArchiveError::from)
+            .boxed())
+    }
 
-        // Associate with a specific database
-        let db = client.database(&self.datastore);
+    /// Reads a page of records in `_id` order, starting after the id encoded in the request's
+    /// token. One more record than requested is read to tell whether there is a next page.
+    async fn find_page<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        request: &PageRequest,
+    ) -> Result<Page<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let collection: Collection<Document> = self.collection(rec_type.clone()).await?;
+        let filter = match &request.after_token {
+            Some(token) => after_id(parse_id(token)),
+            None => doc! {},
+        };
+        let options = FindOptions::builder()
+            .sort(doc! { "_id": 1 })
+            .limit(request.limit as i64 + 1)
+            .build();
+        let mut docs: Vec<Document> = collection
+            .find(filter, options)
+            .await
+            .context("Failed to find documents")?
+            .try_collect()
+            .await
+            .context("Failed to read documents")?;
 
-        // Retrieve the relevant collection handle.
-        let collection: Collection<T>;
-        if let ArchiveRecordType::Account = rec_type {
-            collection = db.collection(ACCOUNT_COLLECTION);
-        } else if let ArchiveRecordType::TransactionBatch = rec_type {
-            collection = db.collection(TRANSACTION_COLLECTION);
+        let next_token = if docs.len() > request.limit {
+            docs.truncate(request.limit);
+            docs.last().and_then(|doc| doc.get("_id")).map(id_to_string)
         } else {
-            panic!("Invalid archive record type");
+            None
+        };
+
+        let mut items: Vec<T> = Vec::with_capacity(docs.len());
+        for doc in docs {
+            items.push(self.decode(rec_type.clone(), doc).await?);
         }
+        Ok(Page { items, next_token })
+    }
 
-        let filter = doc! { "_id": "$exists" };
+    /// Query data store for the records of the given type matching a [Filter], translated into a
+    /// MongoDB query document.
+    async fn query<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        // Retrieve the relevant collection handle. Documents are read untyped so that chunk
+        // manifests can be recognised and reassembled.
+        let collection: Collection<Document> = self.collection(rec_type.clone()).await?;
 
-        // Now insert the record that was passed in....
         let cursor = collection
-            .find(filter, None)
+            .find(filter_document(filter), None)
             .await
             .context("Failed to find documents")?;
 
-        // TODO: now do stuff with the returned Cursor...
-        let ret: Vec<T> = cursor.try_collect().await?;
-        //while cursor.advance().await? {
-        //println!("Doc: {:?}", cursor.deserialize_current()?);
-        //let val: T = cursor.deserialize_current()?;
-        //ret.push(val.clone());
-        //}
-        //while let Some(doc) = cursor.try_next().await? {
-        //    dbg!(doc);
-        //}
-        // TODO: just returns the filter string, not the results
+        let docs: Vec<Document> = cursor
+            .try_collect()
+            .await
+            .context("Failed to read documents")?;
+        let mut ret: Vec<T> = Vec::with_capacity(docs.len());
+        for doc in docs {
+            ret.push(self.decode(rec_type.clone(), doc).await?);
+        }
         Ok(ret)
     }
+
+    /// Projects the fields on the server. The manifests of chunked records are always read, and
+    /// the records reassembled whole.
+    async fn find_projected(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        fields: &[String],
+    ) -> Result<Vec<Document>, ArchiveError> {
+        let collection: Collection<Document> = self.collection(rec_type.clone()).await?;
+        let mut projection: Document = fields
+            .iter()
+            .map(|field| (field.clone(), Bson::Int32(1)))
+            .collect();
+        projection.insert(chunking::MANIFEST_FIELD, 1);
+        let options = FindOptions::builder().projection(projection).build();
+
+        let docs: Vec<Document> = collection
+            .find(filter_document(filter), options)
+            .await
+            .context("Failed to find documents")?
+            .try_collect()
+            .await
+            .context("Failed to read documents")?;
+        let mut ret = Vec::with_capacity(docs.len());
+        for doc in docs {
+            ret.push(self.decode(rec_type.clone(), doc).await?);
+        }
+        Ok(ret)
+    }
+
+    /// Returns a random sample of roughly `rate` (0.0 to 1.0) of the records of the given type,
+    /// using a `$match` stage with `$sampleRate`. Each document is selected independently with
+    /// probability `rate`, so the number returned is approximate and varies between calls.
+    async fn find_sampled<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rate: f64,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        // Retrieve the relevant collection handle.
+        let collection: Collection<Document> = self.collection(rec_type.clone()).await?;
+
+        let pipeline = vec![doc! { "$match": { "$sampleRate": rate } }];
+        let cursor = collection
+            .aggregate(pipeline, None)
+            .await
+            .context("Failed to sample documents")?;
+
+        let docs: Vec<Document> = cursor
+            .try_collect()
+            .await
+            .context("Failed to read documents")?;
+        let mut ret: Vec<T> = Vec::with_capacity(docs.len());
+        for doc in docs {
+            ret.push(self.decode(rec_type.clone(), doc).await?);
+        }
+        Ok(ret)
+    }
+
+    /// Looks up a single record by the id returned when it was created.
+    async fn find_by_id<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<Option<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let collection: Collection<Document> = self.collection(rec_type.clone()).await?;
+        let doc = collection
+            .find_one(doc! { "_id": parse_id(id) }, None)
+            .await
+            .context("Failed to find document")?;
+
+        match doc {
+            Some(doc) => Ok(Some(self.decode(rec_type, doc).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Deletes the document with `find_one_and_delete`, then the chunks of a chunked record.
+    async fn delete_by_id(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<bool, ArchiveError> {
+        let collection: Collection<Document> = self.collection(rec_type.clone()).await?;
+        let doc = collection
+            .find_one_and_delete(doc! { "_id": parse_id(id) }, None)
+            .await
+            .context("Failed to delete document")?;
+
+        let doc = match doc {
+            Some(doc) => doc,
+            None => return Ok(false),
+        };
+        self.delete_chunks(rec_type, &doc).await?;
+
+        debug!("Deleted document {}", id);
+
+        Ok(true)
+    }
+
+    /// Deletes the matching documents with `delete_many`. Any chunk manifests among them are
+    /// found first, so that their chunks can be deleted afterwards.
+    async fn delete_where(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        let collection: Collection<Document> = self.collection(rec_type.clone()).await?;
+        let filter = filter_document(filter);
+
+        let manifests = doc! {
+            "$and": [filter.clone(), { chunking::MANIFEST_FIELD: { "$exists": true } }],
+        };
+        let options = FindOptions::builder()
+            .projection(doc! { "_id": 1, chunking::MANIFEST_FIELD: 1 })
+            .build();
+        let files_ids: Vec<Bson> = collection
+            .find(manifests, options)
+            .await
+            .context("Failed to find chunk manifests")?
+            .try_collect::<Vec<Document>>()
+            .await
+            .context("Failed to read documents")?
+            .iter()
+            .filter_map(chunking::manifest_id)
+            .map(Bson::ObjectId)
+            .collect();
+
+        let res = collection
+            .delete_many(filter, None)
+            .await
+            .context("Failed to delete documents")?;
+        if !files_ids.is_empty() {
+            self.chunk_collection(rec_type)
+                .await?
+                .delete_many(
+                    doc! { chunking::FILES_ID_FIELD: { "$in": files_ids } },
+                    None,
+                )
+                .await
+                .context("Failed to delete record chunks")?;
+        }
+
+        debug!("Deleted {} documents", res.deleted_count);
+
+        Ok(res.deleted_count)
+    }
+
+    /// Replaces the document with `find_one_and_replace`, which returns the replaced document so
+    /// that its chunks can be deleted if it was chunked. The `_id` of the document is kept,
+    /// whatever the record holds.
+    async fn update_by_id<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        rec: T,
+    ) -> Result<bool, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let mut doc = self.replacement(rec_type.clone(), rec).await?;
+        doc.remove("_id");
+        let collection: Collection<Document> = self.collection(rec_type.clone()).await?;
+        let options = FindOneAndReplaceOptions::builder()
+            .projection(doc! { "_id": 1, chunking::MANIFEST_FIELD: 1 })
+            .build();
+        let replaced = collection
+            .find_one_and_replace(doc! { "_id": parse_id(id) }, &doc, options)
+            .await
+            .context("Failed to replace document")?;
+        match replaced {
+            Some(replaced) => self.delete_chunks(rec_type, &replaced).await?,
+            None => {
+                // Nothing was replaced, so the chunks of the replacement aren't needed.
+                self.delete_chunks(rec_type, &doc).await?;
+                return Ok(false);
+            }
+        }
+
+        debug!("Replaced document {}", id);
+
+        Ok(true)
+    }
+
+    /// Replaces the first matching document, or inserts the record, with `find_one_and_replace`
+    /// and `upsert(true)`. Unlike `replace_one`, this reports the id of a replaced document. The
+    /// document to be replaced is read first, so that its chunks can be deleted if it was chunked
+    /// and is still the one replaced.
+    async fn upsert<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let doc = self.replacement(rec_type.clone(), rec).await?;
+        let collection: Collection<Document> = self.collection(rec_type.clone()).await?;
+        let options = FindOneOptions::builder()
+            .projection(doc! { "_id": 1, chunking::MANIFEST_FIELD: 1 })
+            .build();
+        let previous = collection
+            .find_one(filter_document(filter), options)
+            .await
+            .context("Failed to find document to upsert")?;
+        let options = FindOneAndReplaceOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .projection(doc! { "_id": 1 })
+            .build();
+        let id = collection
+            .find_one_and_replace(filter_document(filter), doc, options)
+            .await
+            .context("Failed to upsert document")?
+            .and_then(|mut doc| doc.remove("_id"))
+            .context("Upserted document has no _id")?;
+        if let Some(previous) = previous.filter(|previous| previous.get("_id") == Some(&id)) {
+            self.delete_chunks(rec_type, &previous).await?;
+        }
+
+        debug!("Upserted document {}", id);
+
+        Ok(id_to_string(&id))
+    }
+
+    /// Groups the chunk collection by `files_id` and looks each group up in the main collection,
+    /// returning the ids of groups with no manifest.
+    async fn find_orphaned_chunks(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Vec<String>, ArchiveError> {
+        Ok(self
+            .orphaned_chunk_ids(rec_type)
+            .await?
+            .iter()
+            .map(id_to_string)
+            .collect())
+    }
+
+    /// Deletes every chunk belonging to an orphaned chunk group, returning the number of chunk
+    /// documents deleted.
+    async fn cleanup_orphans(&self, rec_type: ArchiveRecordType) -> Result<u64, ArchiveError> {
+        let orphans = self.orphaned_chunk_ids(rec_type.clone()).await?;
+        if orphans.is_empty() {
+            return Ok(0);
+        }
+
+        let chunk_collection = self.chunk_collection(rec_type).await?;
+        let res = chunk_collection
+            .delete_many(doc! { chunking::FILES_ID_FIELD: { "$in": orphans } }, None)
+            .await
+            .context("Failed to delete orphaned chunks")?;
+
+        debug!("Deleted {} orphaned chunks", res.deleted_count);
+
+        Ok(res.deleted_count)
+    }
+
+    /// Runs the `compact` command on the record type's collection and its chunk collection,
+    /// which only compacts them on the node the client sends commands to, so must be run against
+    /// each member of a replica set. Collections that don't exist yet are skipped.
+    async fn reclaim_space(&self, rec_type: ArchiveRecordType) -> Result<(), ArchiveError> {
+        let client = self.client().await?;
+        let db = client.database(&self.datastore);
+        let name = Self::collection_name(&rec_type);
+        for collection in [name.to_string(), format!("{}_chunks", name)] {
+            match db.run_command(doc! { "compact": &collection }, None).await {
+                Ok(_) => debug!("Compacted {}", collection),
+                Err(e) => match *e.kind {
+                    ErrorKind::Command(ref err) if err.code == NAMESPACE_NOT_FOUND => {}
+                    _ => {
+                        return Err(anyhow::Error::new(e)
+                            .context(format!("Failed to compact {}", collection))
+                            .into())
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends a `$merge` (or `$out`) stage targeting the target record type's collection to the
+    /// pipeline and runs it against the source collection. As those stages produce no output, the
+    /// documents written are counted by first running the pipeline with a `$count` stage.
+    async fn merge_into(
+        &self,
+        source: ArchiveRecordType,
+        pipeline: Vec<Document>,
+        target: ArchiveRecordType,
+        mode: MergeMode,
+    ) -> Result<u64, ArchiveError> {
+        let collection: Collection<Document> = self.collection(source).await?;
+        let into = Self::collection_name(&target);
+
+        let mut counting = pipeline.clone();
+        counting.push(doc! { "$count": "written" });
+        let counted: Vec<Document> = collection
+            .aggregate(counting, None)
+            .await
+            .context("Failed to count aggregation output")?
+            .try_collect()
+            .await
+            .context("Failed to read documents")?;
+        let written = counted
+            .first()
+            .and_then(|doc| doc.get("written"))
+            .and_then(|written| match written {
+                Bson::Int32(n) => Some(*n as u64),
+                Bson::Int64(n) => Some(*n as u64),
+                _ => None,
+            })
+            .unwrap_or(0);
+
+        let when_matched = match mode {
+            MergeMode::Merge => Some("merge"),
+            MergeMode::Replace => Some("replace"),
+            MergeMode::KeepExisting => Some("keepExisting"),
+            MergeMode::ReplaceAll => None,
+        };
+        let mut merging = pipeline;
+        merging.push(match when_matched {
+            Some(when_matched) => doc! { "$merge": {
+                "into": into,
+                "on": "_id",
+                "whenMatched": when_matched,
+                "whenNotMatched": "insert",
+            } },
+            None => doc! { "$out": into },
+        });
+        // Drive the (empty) cursor so the pipeline runs to completion.
+        let _: Vec<Document> = collection
+            .aggregate(merging, None)
+            .await
+            .context("Failed to merge aggregation output")?
+            .try_collect()
+            .await
+            .context("Failed to read documents")?;
+
+        debug!("Merged {} documents into {}", written, into);
+
+        Ok(written)
+    }
+
+    /// Opens a change stream on the collection, which needs a replica set or sharded cluster.
+    async fn watch(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'static, Result<ArchiveEvent, ArchiveError>>, ArchiveError> {
+        let collection: Collection<Document> = self.collection(rec_type.clone()).await?;
+        let changes = collection
+            .watch(None, None)
+            .await
+            .context("Failed to open change stream")?;
+        Ok(changes
+            .filter_map(move |change| {
+                let event = match change {
+                    Ok(change) => {
+                        let kind = match change.operation_type {
+                            OperationType::Insert => Some(ArchiveEventKind::Created),
+                            OperationType::Update | OperationType::Replace => {
+                                Some(ArchiveEventKind::Updated)
+                            }
+                            OperationType::Delete => Some(ArchiveEventKind::Deleted),
+                            _ => None,
+                        };
+                        let id = change.document_key.as_ref().and_then(|key| key.get("_id"));
+                        kind.zip(id).map(|(kind, id)| {
+                            Ok(ArchiveEvent {
+                                rec_type: rec_type.clone(),
+                                kind,
+                                id: id_to_string(id),
+                            })
+                        })
+                    }
+                    Err(e) => Some(Err(anyhow::Error::new(e)
+                        .context("Failed to read change stream")
+                        .into())),
+                };
+                async move { event }
+            })
+            .boxed())
+    }
+
+    /// Counts the documents matching the filter using `count_documents`.
+    async fn count(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        let collection: Collection<Document> = self.collection(rec_type).await?;
+        Ok(collection
+            .count_documents(filter_document(filter), None)
+            .await
+            .context("Failed to count documents")?)
+    }
+
+    /// Looks for a matching document with `find_one`, only fetching its `_id`.
+    async fn exists(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<bool, ArchiveError> {
+        let collection: Collection<Document> = self.collection(rec_type).await?;
+        let options = FindOneOptions::builder()
+            .projection(doc! { "_id": 1 })
+            .build();
+        Ok(collection
+            .find_one(filter_document(filter), options)
+            .await
+            .context("Failed to find document")?
+            .is_some())
+    }
+
+    /// Reads the collection's storage statistics with a `$collStats` stage. A collection that
+    /// doesn't exist yet has zeroed statistics.
+    async fn stats(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<ArchiveCollectionStats, ArchiveError> {
+        let collection: Collection<Document> = self.collection(rec_type).await?;
+        let pipeline = vec![doc! { "$collStats": { "storageStats": {} } }];
+        let docs: Vec<Document> = match collection.aggregate(pipeline, None).await {
+            Ok(cursor) => cursor
+                .try_collect()
+                .await
+                .context("Failed to read collection statistics")?,
+            Err(e) => match *e.kind {
+                ErrorKind::Command(ref err) if err.code == NAMESPACE_NOT_FOUND => {
+                    return Ok(ArchiveCollectionStats::default())
+                }
+                _ => {
+                    return Err(anyhow::Error::new(e)
+                        .context("Failed to read collection statistics")
+                        .into())
+                }
+            },
+        };
+
+        let storage = docs
+            .first()
+            .and_then(|doc| doc.get_document("storageStats").ok());
+        Ok(match storage {
+            Some(storage) => ArchiveCollectionStats {
+                document_count: stat_u64(storage.get("count")),
+                storage_bytes: stat_u64(storage.get("storageSize")),
+                avg_doc_bytes: stat_u64(storage.get("avgObjSize")),
+            },
+            None => ArchiveCollectionStats::default(),
+        })
+    }
+
+    /// Counts documents per distinct value of a field, or per date bucket of the time they were
+    /// inserted (taken from their ObjectId), with a `$group` stage.
+    async fn group_count(
+        &self,
+        rec_type: ArchiveRecordType,
+        group_by: GroupBy,
+    ) -> Result<Vec<(Bson, u64)>, ArchiveError> {
+        let collection: Collection<Document> = self.collection(rec_type).await?;
+        let key = match &group_by {
+            GroupBy::Field(field) => Bson::String(format!("${}", field)),
+            GroupBy::ArchivedAt(granularity) => Bson::Document(doc! {
+                "$dateToString": {
+                    "format": granularity.date_format(),
+                    "date": { "$convert": { "input": "$_id", "to": "date", "onError": null } },
+                }
+            }),
+        };
+        let pipeline = vec![
+            doc! { "$group": { "_id": key, "count": { "$sum": 1 } } },
+            doc! { "$sort": { "_id": 1 } },
+        ];
+        let groups: Vec<Document> = collection
+            .aggregate(pipeline, None)
+            .await
+            .context(format!("Failed to group documents by {}", group_by))?
+            .try_collect()
+            .await
+            .context("Failed to read documents")?;
+
+        Ok(groups
+            .into_iter()
+            .map(|mut group| {
+                let key = group.remove("_id").unwrap_or(Bson::Null);
+                (key, stat_u64(group.get("count")))
+            })
+            .collect())
+    }
+
+    /// Translates the aggregation into a `$match`, `$group` and `$sort` pipeline.
+    async fn aggregate(
+        &self,
+        rec_type: ArchiveRecordType,
+        spec: &AggregationSpec,
+    ) -> Result<Vec<Document>, ArchiveError> {
+        let collection: Collection<Document> = self.collection(rec_type).await?;
+        let key = match &spec.group_by {
+            None => Bson::Null,
+            Some(GroupBy::Field(field)) => Bson::String(format!("${}", field)),
+            // Records archived before provenance was stored fall back to their id's timestamp.
+            Some(GroupBy::ArchivedAt(granularity)) => Bson::Document(doc! {
+                "$dateToString": {
+                    "format": granularity.date_format(),
+                    "date": { "$ifNull": [
+                        { "$convert": { "input": "$_archived_at", "to": "date", "onError": null } },
+                        { "$convert": { "input": "$_id", "to": "date", "onError": null } },
+                    ] },
+                }
+            }),
+        };
+        let mut group = doc! { "_id": key };
+        for (name, aggregate) in &spec.outputs {
+            let accumulator = match aggregate {
+                Aggregate::Count => doc! { "$sum": 1 },
+                Aggregate::Sum(field) => doc! { "$sum": format!("${}", field) },
+                Aggregate::Min(field) => doc! { "$min": format!("${}", field) },
+                Aggregate::Max(field) => doc! { "$max": format!("${}", field) },
+            };
+            group.insert(name, accumulator);
+        }
+        let pipeline = vec![
+            doc! { "$match": filter_document(&spec.filter) },
+            doc! { "$group": group },
+            doc! { "$sort": { "_id": 1 } },
+        ];
+        let groups: Vec<Document> = collection
+            .aggregate(pipeline, None)
+            .await
+            .context("Failed to aggregate documents")?
+            .try_collect()
+            .await
+            .context("Failed to read documents")?;
+
+        // Return the same types as the other backends: the key as `key`, and 64-bit counts and
+        // integer sums.
+        Ok(groups
+            .into_iter()
+            .map(|mut group| {
+                let mut output = doc! { KEY_FIELD: group.remove("_id").unwrap_or(Bson::Null) };
+                for (name, aggregate) in &spec.outputs {
+                    let value = match (aggregate, group.remove(name)) {
+                        (Aggregate::Count | Aggregate::Sum(_), Some(Bson::Int32(n))) => {
+                            Bson::Int64(n.into())
+                        }
+                        (_, value) => value.unwrap_or(Bson::Null),
+                    };
+                    output.insert(name, value);
+                }
+                output
+            })
+            .collect())
+    }
+
+    /// Creates the indexes with `createIndexes`, which leaves existing identical indexes as they
+    /// are.
+    async fn ensure_indexes(
+        &self,
+        rec_type: ArchiveRecordType,
+        indexes: &[IndexSpec],
+    ) -> Result<(), ArchiveError> {
+        if indexes.is_empty() {
+            return Ok(());
+        }
+        let collection: Collection<Document> = self.collection(rec_type).await?;
+        let models = indexes.iter().map(|index| {
+            let keys: Document = index
+                .fields
+                .iter()
+                .map(|field| (field.clone(), Bson::Int32(1)))
+                .collect();
+            IndexModel::builder()
+                .keys(keys)
+                .options(IndexOptions::builder().unique(index.unique).build())
+                .build()
+        });
+        collection
+            .create_indexes(models, None)
+            .await
+            .context("Failed to create indexes")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Acknowledgment, ArchiveBackends, ArchiveStoreBuilder};
+    use std::time::Duration;
+
+    #[test]
+    fn pages_on_to_ids_of_later_types() {
+        let oid = ObjectId::new();
+        assert_eq!(
+            after_id(parse_id("a")),
+            doc! { "$or": [
+                { "_id": { "$gt": "a" } },
+                { "_id": { "$type": [
+                    "object", "binData", "objectId", "bool", "date", "timestamp", "regex", "maxKey",
+                ] } },
+            ] }
+        );
+        assert_eq!(
+            after_id(parse_id(&oid.to_hex())),
+            doc! { "$or": [
+                { "_id": { "$gt": oid } },
+                { "_id": { "$type": ["bool", "date", "timestamp", "regex", "maxKey"] } },
+            ] }
+        );
+    }
+
+    #[tokio::test]
+    async fn builder_options_reach_the_client() {
+        let write_concern = WriteConcern {
+            w: Some(Acknowledgment::Nodes(2)),
+            journal: Some(true),
+            w_timeout: Some(Duration::from_secs(5)),
+        };
+        let store = ArchiveStoreBuilder::default()
+            .uri("mongodb://localhost:27017/?w=1&readPreference=primary".to_string())
+            .backend(ArchiveBackends::MongoDB)
+            .datastore("options".to_string())
+            .write_concern(write_concern.clone())
+            .read_preference(ReadPreference::SecondaryPreferred)
+            .build()
+            .unwrap();
+
+        // Creating the client doesn't connect.
+        let client = store.mongodb().client().await.unwrap();
+        assert_eq!(
+            client.write_concern(),
+            Some(&options::WriteConcern::from(&write_concern))
+        );
+        assert_eq!(
+            client.selection_criteria(),
+            Some(&SelectionCriteria::from(
+                &ReadPreference::SecondaryPreferred
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn uri_options_apply_without_builder_options() {
+        let store = ArchiveStoreBuilder::default()
+            .uri("mongodb://localhost:27017/?w=majority&readPreference=nearest".to_string())
+            .backend(ArchiveBackends::MongoDB)
+            .datastore("options".to_string())
+            .build()
+            .unwrap();
+
+        let client = store.mongodb().client().await.unwrap();
+        assert_eq!(
+            client.write_concern().and_then(|w| w.w.clone()),
+            Some(options::Acknowledgment::Majority)
+        );
+        assert_eq!(
+            client.selection_criteria(),
+            Some(&SelectionCriteria::from(&ReadPreference::Nearest))
+        );
+    }
 }
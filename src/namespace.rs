@@ -0,0 +1,371 @@
+/// Namespaces, for keeping several independent archives, e.g. one per network (`mainnet`,
+/// `testnet`, `devnet`), in one backend behind one store. [ArchiveStore::namespace] returns a
+/// handle on the store whose records are kept apart from those of the store itself and of every
+/// other namespace, with the store's configuration. Backends map a namespace to their own
+/// datastore or prefix:
+/// - MongoDB: the `{datastore}__{namespace}` database
+/// - PostgreSQL: the `{datastore}__{namespace}` schema
+/// - SQLite: tables prefixed `{datastore}__{namespace}_` rather than `{datastore}_`
+/// - RocksDB: the `{datastore}__{namespace}` database next to the store's own
+/// - S3: keys prefixed `_namespaces/{namespace}/` in the store's bucket
+/// - Filesystem, IPFS and Arweave: the `_namespaces/{namespace}` directory within the store's
+///   root or index directory
+/// - In-memory: records of the namespace's own, kept as long as the store
+///
+/// The handle of a namespace has backends, caches, events and background tasks of its own, like a
+/// separately built store, which are shared by every handle on the namespace. Its mirrors and cold
+/// tier are the same namespace of the store's mirrors and cold tier, and its spill and dead-letter
+/// files are kept in the `_namespaces/{namespace}` directory within the store's spill and
+/// dead-letter directories.
+use crate::transfer::{copy_page, portable};
+use crate::{
+    ArchiveBackends, ArchiveError, ArchiveRecordType, ArchiveStore, ArchiveStoreInner, PageRequest,
+    TieringPolicy, Unsupported,
+};
+use anyhow::Context;
+use bson::Document;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Directory, or key prefix, holding the namespaces of backends that nest them within the store's
+pub(crate) const NAMESPACE_DIR: &str = "_namespaces";
+/// Separates a store's datastore from the namespace in the datastore of the namespace
+const SEPARATOR: &str = "__";
+/// Longest namespace name allowed, keeping derived datastore names within backend limits
+const MAX_NAMESPACE_LEN: usize = 32;
+/// Number of records copied at once
+const PAGE_SIZE: usize = 500;
+
+impl ArchiveStore {
+    /// Returns a handle on the named namespace of this store, e.g. `store.namespace("testnet")`,
+    /// whose records are kept apart from those of the store and of its other namespaces, see the
+    /// module docs. Names are 1 to 32 lowercase ASCII letters and digits, starting with a letter.
+    /// Called on a namespace's handle, returns the named namespace of the store it belongs to.
+    /// The handle keeps this handle's labels, adding a `namespace` label, and tags.
+    pub fn namespace(&self, name: &str) -> Result<ArchiveStore, ArchiveError> {
+        validate(name).map_err(ArchiveError::invalid_input)?;
+        let root = self.root()?;
+        let inner = {
+            let mut namespaces = root.namespaces.lock().unwrap_or_else(|e| e.into_inner());
+            match namespaces.get(name) {
+                Some(inner) => inner.clone(),
+                None => {
+                    let inner = Arc::new(derive(&root, name)?);
+                    namespaces.insert(name.to_string(), inner.clone());
+                    inner
+                }
+            }
+        };
+        Ok(ArchiveStore {
+            inner,
+            labels: self.labels.with(&[("namespace", name)]),
+            tags: self.tags.clone(),
+            include_tombstones: self.include_tombstones,
+            #[cfg(feature = "mongodb")]
+            mongodb_override: None,
+        })
+    }
+
+    /// The namespace this handle reads and writes, `None` for the store's own records.
+    pub fn namespace_name(&self) -> Option<&str> {
+        self.inner.namespace.as_deref()
+    }
+
+    /// Lists the namespaces of the store that hold records in its backend, in name order.
+    /// Namespaces that were opened with [ArchiveStore::namespace] but never written to may be
+    /// left out. On MongoDB the user needs permission to list the databases.
+    pub async fn namespaces(&self) -> Result<Vec<String>, ArchiveError> {
+        let root = ArchiveStore {
+            inner: self.root()?,
+            labels: self.labels.clone(),
+            tags: Vec::new(),
+            include_tombstones: false,
+            #[cfg(feature = "mongodb")]
+            mongodb_override: None,
+        };
+        let prefix = format!("{}{}", root.inner.datastore, SEPARATOR);
+        #[cfg_attr(
+            not(any(
+                feature = "mongodb",
+                feature = "postgres",
+                feature = "sqlite",
+                feature = "rocksdb"
+            )),
+            allow(unused_variables)
+        )]
+        let in_datastore = |names: Vec<String>| -> Vec<String> {
+            names
+                .iter()
+                .filter_map(|name| name.strip_prefix(&prefix))
+                .map(str::to_string)
+                .collect()
+        };
+        let names = match root.inner.backend {
+            #[cfg(feature = "mongodb")]
+            ArchiveBackends::MongoDB => in_datastore(root.mongodb().database_names().await?),
+            #[cfg(feature = "postgres")]
+            ArchiveBackends::Postgres => in_datastore(root.postgres().schema_names().await?),
+            #[cfg(feature = "sqlite")]
+            ArchiveBackends::Sqlite => {
+                // Tables are named `{datastore}__{namespace}_{record type}`.
+                in_datastore(root.sqlite().table_names().await?)
+                    .into_iter()
+                    .filter_map(|table| table.split_once('_').map(|(name, _)| name.to_string()))
+                    .collect()
+            }
+            #[cfg(feature = "s3")]
+            ArchiveBackends::S3 => root.s3().namespaces().await?,
+            #[cfg(feature = "rocksdb")]
+            ArchiveBackends::RocksDb => {
+                let dir = root.inner.uri.strip_prefix("rocksdb://");
+                in_datastore(dir_names(Path::new(dir.unwrap_or(&root.inner.uri))).await?)
+            }
+            ArchiveBackends::Filesystem { ref root } => {
+                dir_names(&root.join(NAMESPACE_DIR)).await?
+            }
+            ArchiveBackends::InMemory => {
+                let namespaces = root
+                    .inner
+                    .namespaces
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner());
+                namespaces
+                    .iter()
+                    .filter(|(_, inner)| {
+                        inner.memory.get().is_some_and(|memory| !memory.is_empty())
+                    })
+                    .map(|(name, _)| name.clone())
+                    .collect()
+            }
+            #[cfg(feature = "ipfs")]
+            ArchiveBackends::Ipfs { ref index } => dir_names(&index.join(NAMESPACE_DIR)).await?,
+            #[cfg(feature = "arweave")]
+            ArchiveBackends::Arweave { ref index } => dir_names(&index.join(NAMESPACE_DIR)).await?,
+            ArchiveBackends::Custom { ref name } => {
+                return Err(Unsupported {
+                    operation: "namespaces",
+                    reason: format!("the {} backend can't list namespaces", name),
+                }
+                .into())
+            }
+        };
+        let names: BTreeSet<String> = names
+            .into_iter()
+            .filter(|name| validate(name).is_ok())
+            .collect();
+        Ok(names.into_iter().collect())
+    }
+
+    /// Copies every record of the given record types from this handle's namespace, or the store's
+    /// own records, to the named namespace, e.g. to seed `devnet` from `testnet` with
+    /// `store.namespace("testnet")?.copy_namespace("devnet", &record_types)`, returning the
+    /// number of records copied. Records keep their ids and provenance, including their lifecycle
+    /// state. Records already in the target namespace are skipped, or written again by the S3,
+    /// RocksDB and filesystem backends, so a copy that fails can simply be run again. Only this
+    /// handle's own tier is copied, not its cold tier.
+    pub async fn copy_namespace(
+        &self,
+        to: &str,
+        rec_types: &[ArchiveRecordType],
+    ) -> Result<u64, ArchiveError> {
+        if self.namespace_name() == Some(to) {
+            return Err(ArchiveError::invalid_input(format!(
+                "Can't copy namespace '{}' to itself",
+                to
+            )));
+        }
+        let target = self.namespace(to)?;
+        let source = self.with_tombstones();
+        let mut copied = 0;
+        for rec_type in rec_types {
+            let mut request = PageRequest::first(PAGE_SIZE);
+            loop {
+                let page = source
+                    .find_envelope_page::<Document>(rec_type.clone(), request.clone())
+                    .await?;
+                let recs = page.items.into_iter().map(portable).collect();
+                copied += copy_page(&target, rec_type, recs).await?;
+                match page.next_token {
+                    Some(token) => request.after_token = Some(token),
+                    None => break,
+                }
+            }
+        }
+        Ok(copied)
+    }
+
+    /// The shared state of the store namespaces are derived from, i.e. of this store unless this
+    /// is a namespace's handle.
+    fn root(&self) -> Result<Arc<ArchiveStoreInner>, ArchiveError> {
+        if self.inner.namespace.is_none() {
+            return Ok(self.inner.clone());
+        }
+        self.inner.parent.upgrade().ok_or_else(|| {
+            ArchiveError::invalid_input("The store this namespace belongs to has been dropped")
+        })
+    }
+}
+
+/// Checks that a namespace name is 1 to [MAX_NAMESPACE_LEN] lowercase ASCII letters and digits,
+/// starting with a letter, so it can't be confused with a record type or datastore name.
+fn validate(name: &str) -> Result<(), String> {
+    if name.is_empty()
+        || name.len() > MAX_NAMESPACE_LEN
+        || !name.starts_with(|c: char| c.is_ascii_lowercase())
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+    {
+        return Err(format!(
+            "Namespace '{}' must be 1 to {} lowercase letters and digits, starting with a letter",
+            name, MAX_NAMESPACE_LEN
+        ));
+    }
+    Ok(())
+}
+
+/// Names of the directories within `dir`, none if it doesn't exist.
+async fn dir_names(dir: &Path) -> Result<Vec<String>, ArchiveError> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(anyhow::Error::new(e)
+                .context(format!("Listing {}", dir.display()))
+                .into())
+        }
+    };
+    let mut names = Vec::new();
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .with_context(|| format!("Listing {}", dir.display()))?
+    {
+        if entry.path().is_dir() {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    Ok(names)
+}
+
+/// The state of the named namespace of the `root` store: its configuration, with the datastore,
+/// directories, mirrors and cold tier of the namespace, and fresh backends, caches and tasks.
+fn derive(root: &Arc<ArchiveStoreInner>, name: &str) -> Result<ArchiveStoreInner, ArchiveError> {
+    let datastore = match root.backend {
+        #[cfg(feature = "s3")]
+        ArchiveBackends::S3 => root.datastore.clone(),
+        _ => format!("{}{}{}", root.datastore, SEPARATOR, name),
+    };
+    root.backend
+        .validate_datastore(&datastore)
+        .map_err(ArchiveError::invalid_input)?;
+    let backend = match &root.backend {
+        ArchiveBackends::Filesystem { root } => ArchiveBackends::Filesystem {
+            root: root.join(NAMESPACE_DIR).join(name),
+        },
+        #[cfg(feature = "ipfs")]
+        ArchiveBackends::Ipfs { index } => ArchiveBackends::Ipfs {
+            index: index.join(NAMESPACE_DIR).join(name),
+        },
+        #[cfg(feature = "arweave")]
+        ArchiveBackends::Arweave { index } => ArchiveBackends::Arweave {
+            index: index.join(NAMESPACE_DIR).join(name),
+        },
+        backend => backend.clone(),
+    };
+    let mirrors = root
+        .mirrors
+        .iter()
+        .map(|mirror| mirror.namespace(name))
+        .collect::<Result<_, _>>()?;
+    let tiering = match &root.tiering {
+        Some(tiering) => Some(TieringPolicy {
+            cold: tiering.cold.namespace(name)?,
+            ..tiering.clone()
+        }),
+        None => None,
+    };
+    // Share the store's MongoDB client, if it has connected.
+    #[cfg(feature = "mongodb")]
+    let client = root.client.clone().or_else(|| {
+        root.mongodb
+            .get()
+            .and_then(|mongodb| mongodb.client.get().cloned())
+    });
+
+    Ok(ArchiveStoreInner {
+        uri: root.uri.clone(),
+        credentials: root.credentials.clone(),
+        backend,
+        datastore,
+        compression: root.compression.clone(),
+        codec: root.codec,
+        encryption: root.encryption.clone(),
+        checksums: root.checksums,
+        node_id: root.node_id.clone(),
+        chunk_threshold: root.chunk_threshold,
+        ordered_inserts: root.ordered_inserts,
+        ignore_duplicate_ids: root.ignore_duplicate_ids,
+        schema_versions: root.schema_versions.clone(),
+        migrations: root.migrations.clone(),
+        dedup: root.dedup.as_ref().map(|dedup| dedup.emptied()),
+        cache: root.cache.as_ref().map(|cache| cache.emptied()),
+        content_dedup: root.content_dedup,
+        write_concern: root.write_concern.clone(),
+        read_preference: root.read_preference.clone(),
+        retry_policy: root.retry_policy.clone(),
+        rate_limiter: root.rate_limiter.clone(),
+        mirrors,
+        write_strategy: root.write_strategy,
+        tiering,
+        tier_migration: OnceLock::new(),
+        tier_migration_lock: tokio::sync::Mutex::new(()),
+        retention: root.retention.clone(),
+        versioned: root.versioned.clone(),
+        prune_interval: root.prune_interval,
+        pruning: OnceLock::new(),
+        ensured_indexes: Mutex::new(HashSet::new()),
+        events: OnceLock::new(),
+        metrics: root.metrics.clone(),
+        spill_dir: root
+            .spill_dir
+            .as_ref()
+            .map(|dir| dir.join(NAMESPACE_DIR).join(name)),
+        spill_drain_interval: root.spill_drain_interval,
+        spill_drain: OnceLock::new(),
+        spill_lock: tokio::sync::Mutex::new(()),
+        dead_letter_dir: root
+            .dead_letter_dir
+            .as_ref()
+            .map(|dir| dir.join(NAMESPACE_DIR).join(name)),
+        dead_letter_lock: tokio::sync::Mutex::new(()),
+        #[cfg(feature = "mongodb")]
+        client,
+        #[cfg(feature = "mongodb")]
+        mongodb: OnceLock::new(),
+        #[cfg(feature = "postgres")]
+        postgres: OnceLock::new(),
+        #[cfg(feature = "sqlite")]
+        sqlite: OnceLock::new(),
+        #[cfg(feature = "s3")]
+        s3: OnceLock::new(),
+        #[cfg(feature = "rocksdb")]
+        rocksdb: OnceLock::new(),
+        file_format: root.file_format,
+        filesystem: OnceLock::new(),
+        memory: OnceLock::new(),
+        #[cfg(feature = "ipfs")]
+        content_store: root.content_store.clone(),
+        #[cfg(feature = "ipfs")]
+        ipfs: OnceLock::new(),
+        #[cfg(feature = "arweave")]
+        permanent_store: root.permanent_store.clone(),
+        #[cfg(feature = "arweave")]
+        arweave: OnceLock::new(),
+        custom: OnceLock::new(),
+        namespace: Some(name.to_string()),
+        parent: Arc::downgrade(root),
+        namespaces: Mutex::new(HashMap::new()),
+    })
+}
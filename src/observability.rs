@@ -0,0 +1,185 @@
+/// Instrumentation of archive operations. Every operation performed through an [ArchiveStore] is
+/// timed and reported to the optional [ArchiveMetrics] hook set on the builder, so it can be wired
+/// up to any metrics backend, or to Prometheus with `PrometheusMetrics` and the `metrics` feature,
+/// without this crate depending on one. With the
+/// `tracing` feature enabled, each operation also runs within a `tracing` span carrying the
+/// operation name, record type, backend, datastore, labels, duration, outcome, the ids of the
+/// records it wrote or acted on by id and, on failure, the error chain.
+use crate::{ArchiveBackends, ArchiveError, ArchiveRecordType, ArchiveStore, Labels};
+use anyhow::Result;
+use bson::Document;
+use core::fmt;
+use log::debug;
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+#[cfg(feature = "tracing")]
+use tracing::Instrument;
+
+/// Most ids recorded in an operation's span by [trace_ids]
+#[cfg(feature = "tracing")]
+const MAX_TRACED_IDS: usize = 16;
+
+/// Whether an archive operation succeeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    Failure,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Outcome::Success => write!(f, "success"),
+            Outcome::Failure => write!(f, "failure"),
+        }
+    }
+}
+
+/// A completed archive operation, as reported to [ArchiveMetrics::on_operation].
+#[derive(Debug, Clone)]
+pub struct ArchiveOperation<'a> {
+    /// Name of the operation, e.g. `create` or `find_all`
+    pub op: &'static str,
+    /// The record type operated on
+    pub rec_type: &'a ArchiveRecordType,
+    /// The backend the operation ran against
+    pub backend: &'a ArchiveBackends,
+    /// The datastore the operation ran against
+    pub datastore: &'a str,
+    /// How long the operation took, including serialisation
+    pub duration: Duration,
+    /// Whether the operation succeeded
+    pub outcome: Outcome,
+    /// Size in bytes of the encoded record written, or zero for operations that don't write one
+    pub bytes: usize,
+    /// How many times the operation was retried under the store's [crate::RetryPolicy]
+    pub retries: u32,
+    /// Labels of the store handle the operation was performed through
+    pub labels: &'a Labels,
+}
+
+/// A hook notified of every archive operation, e.g. to maintain latency histograms and error
+/// counters. Implementations are called inline on the operation's task, so should be cheap.
+pub trait ArchiveMetrics: Send + Sync {
+    /// Called once each operation completes, whether it succeeded or not.
+    fn on_operation(&self, op: &ArchiveOperation);
+}
+
+impl ArchiveStore {
+    /// Runs an operation, timing it and reporting it to the metrics hook and, when enabled, a
+    /// `tracing` span. `attempt` creates the operation's future, which resolves to its result
+    /// along with the number of bytes it wrote, and is called again for each retry allowed by the
+    /// store's [crate::RetryPolicy]. Errors are classified into an [ArchiveError] once the
+    /// operation fails.
+    pub(crate) async fn observe<R, A, F>(
+        &self,
+        op: &'static str,
+        rec_type: &ArchiveRecordType,
+        attempt: A,
+    ) -> Result<R, ArchiveError>
+    where
+        A: Fn() -> F,
+        F: Future<Output = Result<(R, usize)>>,
+    {
+        self.start_spill_drain();
+        self.start_tier_migration();
+        self.start_pruning();
+        let started = Instant::now();
+        let attempts = AtomicU32::new(0);
+        let fut = async {
+            // Every operation runs through here, so custom record type names are checked before
+            // they can reach a backend.
+            rec_type.validate().map_err(ArchiveError::invalid_input)?;
+            self.retrying(op, || {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                self.limited(op, attempt())
+            })
+            .await
+        };
+
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(
+            "archive_operation",
+            op,
+            rec_type = ?rec_type,
+            backend = %self.inner.backend,
+            datastore = %self.inner.datastore,
+            labels = %self.labels,
+            duration_ms = tracing::field::Empty,
+            outcome = tracing::field::Empty,
+            bytes = tracing::field::Empty,
+            ids = tracing::field::Empty,
+            error = tracing::field::Empty,
+        );
+        // Boxed, as otherwise every store operation nested within another, e.g. on the cold tier
+        // or a mirror, adds another layer to the type of the outer operation's future.
+        #[cfg(feature = "tracing")]
+        let result = Box::pin(fut.instrument(span.clone())).await;
+        #[cfg(not(feature = "tracing"))]
+        let result = fut.await;
+
+        let duration = started.elapsed();
+        let (outcome, bytes) = match &result {
+            Ok((_, bytes)) => (Outcome::Success, *bytes),
+            Err(_) => (Outcome::Failure, 0),
+        };
+
+        #[cfg(feature = "tracing")]
+        {
+            span.record("duration_ms", duration.as_millis() as u64);
+            span.record("outcome", tracing::field::display(outcome));
+            span.record("bytes", bytes as u64);
+            if let Err(e) = &result {
+                span.record("error", tracing::field::display(format!("{:#}", e)));
+            }
+        }
+
+        debug!(
+            "{} {:?} in {} took {:?}: {} [{}]",
+            op, rec_type, self.inner.datastore, duration, outcome, self.labels
+        );
+
+        if let Some(metrics) = &self.inner.metrics {
+            metrics.on_operation(&ArchiveOperation {
+                op,
+                rec_type,
+                backend: &self.inner.backend,
+                datastore: &self.inner.datastore,
+                duration,
+                outcome,
+                bytes,
+                retries: attempts.into_inner().saturating_sub(1),
+                labels: &self.labels,
+            });
+        }
+
+        result.map(|(value, _)| value)
+    }
+}
+
+/// Records the ids of the records an operation acts on in its `tracing` span, when enabled. Only
+/// the first 16 ids of a batch are recorded, so large batches don't bloat their spans.
+/// Must be called from within the operation passed to [ArchiveStore::observe].
+pub(crate) fn trace_ids<S: AsRef<str>>(ids: &[S]) {
+    #[cfg(feature = "tracing")]
+    {
+        let mut traced = ids
+            .iter()
+            .take(MAX_TRACED_IDS)
+            .map(|id| id.as_ref())
+            .collect::<Vec<_>>()
+            .join(",");
+        if ids.len() > MAX_TRACED_IDS {
+            traced.push_str(&format!(" and {} more", ids.len() - MAX_TRACED_IDS));
+        }
+        tracing::Span::current().record("ids", traced);
+    }
+    #[cfg(not(feature = "tracing"))]
+    let _ = ids;
+}
+
+/// Size in bytes of a document as written to the backend.
+pub(crate) fn encoded_size(doc: &Document) -> usize {
+    bson::to_vec(doc).map_or(0, |bytes| bytes.len())
+}
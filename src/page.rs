@@ -0,0 +1,49 @@
+/// Which page of records to read with [crate::ArchiveStore::find_page]. Pages are ordered by
+/// record id and continue from the last record of the previous page rather than skipping a number
+/// of records, so walking an archive is stable while records are being added and each page costs
+/// the same no matter how deep into the archive it is.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PageRequest {
+    /// Largest number of records to return
+    pub limit: usize,
+    /// The [Page::next_token] of the previous page, or `None` for the first page
+    pub after_token: Option<String>,
+}
+
+impl PageRequest {
+    /// Requests the first page of up to `limit` records.
+    pub fn first(limit: usize) -> Self {
+        PageRequest {
+            limit,
+            after_token: None,
+        }
+    }
+
+    /// Requests the page of up to `limit` records following the page `token` was returned with.
+    pub fn after(limit: usize, token: String) -> Self {
+        PageRequest {
+            limit,
+            after_token: Some(token),
+        }
+    }
+}
+
+/// A page of records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page<T> {
+    /// The records, in id order
+    pub items: Vec<T>,
+    /// Opaque token to pass as [PageRequest::after_token] to read the next page, or `None` if
+    /// this is the last page
+    pub next_token: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Converts the records of the page, keeping its continuation token.
+    pub(crate) fn try_map<U, E>(self, f: impl FnMut(T) -> Result<U, E>) -> Result<Page<U>, E> {
+        Ok(Page {
+            items: self.items.into_iter().map(f).collect::<Result<_, _>>()?,
+            next_token: self.next_token,
+        })
+    }
+}
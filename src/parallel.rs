@@ -0,0 +1,167 @@
+/// Parallel full scans of a record type, for jobs such as reindexing that read entire, very large
+/// archives. The id keyspace is split into ranges that are each walked a page at a time by their
+/// own task, see [ArchiveStore::find_all_parallel].
+use crate::{filter, ArchiveError, ArchiveRecordType, ArchiveStore, Filter, PageRequest};
+use anyhow::Context;
+use bson::{oid::ObjectId, Bson, DateTime, Document};
+use futures::channel::mpsc;
+use futures::{SinkExt, Stream};
+use log::debug;
+use serde::de::DeserializeOwned;
+
+/// Number of records each partition reads at a time
+const PAGE_SIZE: usize = 500;
+
+impl ArchiveStore {
+    /// Streams every archived record of [ArchiveRecordType] like
+    /// [ArchiveStore::find_all_stream], reading up to `partitions` ranges of record ids
+    /// concurrently, each with its own cursor and task. Records are yielded as they are read, in
+    /// no particular order.
+    ///
+    /// The ranges split the time between the first record's id and now evenly, as ids generated
+    /// by the archive start with when they were generated, so records archived in bursts make for
+    /// uneven partitions. The ids are read first to check that every one was generated by the
+    /// archive; otherwise, as with backends whose ids aren't ordered that way, such as IPFS and
+    /// Arweave, the records are read with a single cursor. The cold tier of a tiered store is read
+    /// in as many partitions again. If a partition fails, its error is yielded and the other
+    /// partitions carry on.
+    pub async fn find_all_parallel<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        partitions: usize,
+    ) -> Result<impl Stream<Item = Result<T, ArchiveError>> + 'static, ArchiveError>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        if partitions == 0 {
+            return Err(ArchiveError::invalid_input(
+                "Number of partitions must be greater than zero",
+            ));
+        }
+        let (tx, rx) = mpsc::channel(PAGE_SIZE);
+        self.spawn_partitions(rec_type.clone(), partitions, tx.clone())
+            .await?;
+        if let Some(cold) = self.cold_tier() {
+            let cold = match self.include_tombstones {
+                true => cold.with_tombstones(),
+                false => cold.clone(),
+            };
+            cold.spawn_partitions(rec_type, partitions, tx).await?;
+        }
+        Ok(rx)
+    }
+
+    /// Spawns a task per partition of this store's own tier, sending the records they read.
+    async fn spawn_partitions<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        partitions: usize,
+        tx: mpsc::Sender<Result<T, ArchiveError>>,
+    ) -> Result<(), ArchiveError>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        // Ranges of object ids only hold every record if every id is one: backends needn't order
+        // other ids, e.g. of records created with an id, among object ids by their hex form.
+        let ids = self
+            .find_projected_untiered(rec_type.clone(), &self.visible(Filter::All), &[])
+            .await?;
+        let ids: Option<Vec<ObjectId>> = ids
+            .iter()
+            .map(|doc| match doc.get("_id") {
+                Some(Bson::ObjectId(id)) => Some(*id),
+                Some(Bson::String(id)) => ObjectId::parse_str(id).ok(),
+                _ => None,
+            })
+            .collect();
+        let bounds = match ids.and_then(|ids| ids.into_iter().min()) {
+            Some(first) => boundaries(first, partitions),
+            None => Vec::new(),
+        };
+        debug!(
+            "Reading {:?} records in {} partitions",
+            rec_type,
+            bounds.len() + 1
+        );
+
+        // Partition i reads the ids after bound i - 1, up to and including bound i.
+        let mut after = None;
+        for until in bounds.into_iter().map(Some).chain([None]) {
+            let store = self.clone();
+            let rec_type = rec_type.clone();
+            let tx = tx.clone();
+            let from = after.clone();
+            after.clone_from(&until);
+            tokio::spawn(async move {
+                store.scan_partition(rec_type, from, until, tx).await;
+            });
+        }
+        Ok(())
+    }
+
+    /// Sends the records whose ids are after `after` and up to `until` to `tx`, until they are
+    /// exhausted, a page fails to be read or the receiver is dropped.
+    async fn scan_partition<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        after: Option<String>,
+        until: Option<String>,
+        mut tx: mpsc::Sender<Result<T, ArchiveError>>,
+    ) where
+        T: DeserializeOwned,
+    {
+        let past = |id: &str| until.as_deref().is_some_and(|until| id > until);
+        let mut request = PageRequest {
+            limit: PAGE_SIZE,
+            after_token: after,
+        };
+        loop {
+            let page = match self
+                .find_envelope_page::<Document>(rec_type.clone(), request.clone())
+                .await
+            {
+                Ok(page) => page,
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                    return;
+                }
+            };
+            for envelope in page.items {
+                let id = envelope.record.get("_id").map(filter::id_to_string);
+                if id.as_deref().is_some_and(past) {
+                    return;
+                }
+                let rec = bson::from_document(envelope.record)
+                    .context("Failed to deserialise record")
+                    .map_err(ArchiveError::from);
+                if tx.send(rec).await.is_err() {
+                    return;
+                }
+            }
+            match page.next_token {
+                Some(token) if !past(&token) => request.after_token = Some(token),
+                _ => return,
+            }
+        }
+    }
+}
+
+/// The ids splitting the time from the first id until now into `partitions` ranges, as the hex
+/// form of object ids with the range's starting timestamp and no other bytes.
+fn boundaries(first: ObjectId, partitions: usize) -> Vec<String> {
+    let start = first.timestamp().timestamp_millis() / 1000;
+    let end = DateTime::now().timestamp_millis() / 1000 + 1;
+    let span = (end - start).max(0);
+    let mut bounds: Vec<String> = (1..partitions as i64)
+        .map(|i| start + span * i / partitions as i64)
+        .filter_map(|secs| u32::try_from(secs).ok())
+        .map(|secs| {
+            let mut bytes = [0; 12];
+            bytes[..4].copy_from_slice(&secs.to_be_bytes());
+            ObjectId::from_bytes(bytes).to_hex()
+        })
+        .collect();
+    // Short spans give several partitions the same starting second.
+    bounds.dedup();
+    bounds
+}
@@ -0,0 +1,1015 @@
+/// An implementation of an archive datastore that uses PostgreSQL as its backend, enabled with the
+/// `postgres` feature. The datastore name is used as the name of a schema, within which each
+/// [ArchiveRecordType] gets its own table, as defined by the [ACCOUNT_TABLE] and
+/// [TRANSACTION_TABLE] constants or the custom record type's name. Tables are created on first use
+/// and hold each record as JSONB alongside its id and the time it was archived. Blocks and receipts
+/// are indexed on [crate::BLOCK_HEIGHT_FIELD] and [crate::RECEIPT_TX_HASH_FIELD]. Ids are generated
+/// as ObjectIds, so records sort by the time they were archived just like they do with the MongoDB
+/// backend.
+///
+/// Operations that take MongoDB specific arguments, such as aggregation pipelines and query
+/// documents, fail with [Unsupported]. Records are never chunked, as a JSONB value can hold up to
+/// [MAX_DOCUMENT_SIZE] bytes.
+use crate::projection;
+use crate::stats::aggregate;
+use crate::{
+    AggregationSpec, ArchiveBackend, ArchiveCollectionStats, ArchiveError, ArchiveEvent,
+    ArchiveRecordType, Credentials, Filter, Granularity, GroupBy, IndexSpec, MergeMode, Page,
+    PageRequest, Unsupported,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bson::{oid::ObjectId, Bson, Document};
+use deadpool_postgres::{Manager, ManagerConfig, Object, Pool, RecyclingMethod};
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use log::debug;
+use serde::{de::DeserializeOwned, Serialize};
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use tokio::sync::OnceCell;
+use tokio_postgres::{types::ToSql, NoTls, Row};
+
+/// PostgreSQL table name for storing account data
+const ACCOUNT_TABLE: &str = "accounts";
+/// PostgreSQL table name for storing transaction data
+const TRANSACTION_TABLE: &str = "transaction_data";
+/// PostgreSQL table name for storing blocks
+const BLOCK_TABLE: &str = "blocks";
+/// PostgreSQL table name for storing transaction receipts
+const RECEIPT_TABLE: &str = "receipts";
+/// Largest JSONB value PostgreSQL can store (255MB)
+pub const MAX_DOCUMENT_SIZE: usize = 255 * 1024 * 1024;
+/// Longest identifier PostgreSQL allows, in bytes
+const MAX_DATASTORE_LEN: usize = 63;
+/// Most connections opened to the server at once
+const MAX_CONNECTIONS: usize = 16;
+
+/// A query parameter
+type Param = Box<dyn ToSql + Sync + Send>;
+
+/// Adds a parameter to a query, returning its placeholder.
+fn param(params: &mut Vec<Param>, value: Param) -> String {
+    params.push(value);
+    format!("${}", params.len())
+}
+
+/// The JSON path of a (possibly dotted) field name, as a parameter.
+fn path_param(params: &mut Vec<Param>, field: &str) -> String {
+    let path: Vec<String> = field.split('.').map(str::to_string).collect();
+    format!("{}::text[]", param(params, Box::new(path)))
+}
+
+/// A BSON value as a JSONB parameter.
+fn value_param(params: &mut Vec<Param>, value: &Bson) -> String {
+    format!(
+        "{}::jsonb",
+        param(params, Box::new(value.clone().into_relaxed_extjson()))
+    )
+}
+
+/// Translates a [Filter] into a SQL condition over the `record` column, adding the values it
+/// compares against to `params`. Conditions are never NULL, so they can be safely negated.
+fn filter_sql(filter: &Filter, params: &mut Vec<Param>) -> String {
+    let compare = |params: &mut Vec<Param>, field: &str, op: &str, value: &Bson| {
+        let path = path_param(params, field);
+        let value = value_param(params, value);
+        // Like MongoDB, only compare values of the same type. Written so that it is never null,
+        // rather than with COALESCE, so that indexes on the field can be used.
+        format!(
+            "(record #> {path} IS NOT NULL AND jsonb_typeof(record #> {path}) = \
+            jsonb_typeof({value}) AND record #> {path} {op} {value})"
+        )
+    };
+    let join = |params: &mut Vec<Param>, filters: &[Filter], op: &str, empty: &str| {
+        if filters.is_empty() {
+            return empty.to_string();
+        }
+        let conditions: Vec<String> = filters.iter().map(|f| filter_sql(f, params)).collect();
+        format!("({})", conditions.join(op))
+    };
+    match filter {
+        Filter::All => "true".to_string(),
+        Filter::Eq(field, value) => {
+            let path = path_param(params, field);
+            let value = value_param(params, value);
+            format!(
+                "(record #> {} IS NOT NULL AND record #> {} = {})",
+                path, path, value
+            )
+        }
+        Filter::Ne(field, value) => {
+            let path = path_param(params, field);
+            let value = value_param(params, value);
+            // Records missing the field match, as with MongoDB.
+            format!("COALESCE(record #> {} <> {}, true)", path, value)
+        }
+        Filter::Gt(field, value) => compare(params, field, ">", value),
+        Filter::Gte(field, value) => compare(params, field, ">=", value),
+        Filter::Lt(field, value) => compare(params, field, "<", value),
+        Filter::Lte(field, value) => compare(params, field, "<=", value),
+        Filter::In(field, values) => {
+            if values.is_empty() {
+                return "false".to_string();
+            }
+            let path = path_param(params, field);
+            let values: Vec<serde_json::Value> = values
+                .iter()
+                .map(|value| value.clone().into_relaxed_extjson())
+                .collect();
+            let values = param(params, Box::new(values));
+            format!(
+                "(record #> {} IS NOT NULL AND record #> {} = ANY({}::jsonb[]))",
+                path, path, values
+            )
+        }
+        Filter::Exists(field, exists) => {
+            let path = path_param(params, field);
+            match exists {
+                true => format!("record #> {} IS NOT NULL", path),
+                false => format!("record #> {} IS NULL", path),
+            }
+        }
+        Filter::And(filters) => join(params, filters, " AND ", "true"),
+        Filter::Or(filters) => join(params, filters, " OR ", "false"),
+        Filter::Not(filter) => format!("NOT {}", filter_sql(filter, params)),
+    }
+}
+
+/// Borrows query parameters in the form the driver takes them.
+fn borrow_params(params: &[Param]) -> Vec<&(dyn ToSql + Sync)> {
+    params
+        .iter()
+        .map(|p| p.as_ref() as &(dyn ToSql + Sync))
+        .collect()
+}
+
+/// Splits a document into the id and JSONB value it is stored as. The document's `_id` is used
+/// as the id if it has one, otherwise a new ObjectId is generated.
+fn to_row(mut doc: Document) -> (String, serde_json::Value) {
+    let id = match doc.remove("_id") {
+        Some(Bson::ObjectId(oid)) => oid.to_hex(),
+        Some(Bson::String(id)) => id,
+        Some(other) => other.to_string(),
+        None => ObjectId::new().to_hex(),
+    };
+    (id, Bson::Document(doc).into_relaxed_extjson())
+}
+
+/// Reassembles the document stored in a row, including its id as `_id`.
+fn from_row(row: &Row) -> Result<Document> {
+    let id: String = row.try_get("id")?;
+    let record: serde_json::Value = row.try_get("record")?;
+    let mut doc = match Bson::try_from(record).context("Invalid stored record")? {
+        Bson::Document(doc) => doc,
+        other => anyhow::bail!("Stored record {} is not a document: {}", id, other),
+    };
+    doc.insert("_id", id);
+    Ok(doc)
+}
+
+/// `to_char` format producing the bucket key of a time.
+fn date_format(granularity: &Granularity) -> &'static str {
+    match granularity {
+        Granularity::Hour => "YYYY-MM-DD\"T\"HH24",
+        Granularity::Day => "YYYY-MM-DD",
+        Granularity::Month => "YYYY-MM",
+    }
+}
+
+pub struct PostgresBackend {
+    pub uri: String,
+    pub datastore: String,
+    /// Credentials used in place of any in the URI
+    pub credentials: Option<Credentials>,
+    /// Pool of connections to the server, created on first use
+    pub pool: OnceCell<Pool>,
+    /// Record types whose tables are known to exist
+    pub tables: Mutex<HashSet<ArchiveRecordType>>,
+}
+
+impl std::fmt::Debug for PostgresBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PostgresBackend")
+            .field("uri", &crate::uri::redact(&self.uri))
+            .field("datastore", &self.datastore)
+            .field("credentials", &self.credentials)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PostgresBackend {
+    /// Creates a backend for the datastore at `uri`, authenticating with `credentials` if given.
+    /// No connections are made until it is used.
+    pub fn new(uri: &str, datastore: &str, credentials: Option<Credentials>) -> Self {
+        PostgresBackend {
+            uri: uri.to_string(),
+            datastore: datastore.to_string(),
+            credentials,
+            pool: OnceCell::new(),
+            tables: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Checks that the URI is a PostgreSQL connection string that can be parsed.
+    pub fn validate_uri(uri: &str) -> std::result::Result<(), String> {
+        if !uri.starts_with("postgres://") && !uri.starts_with("postgresql://") {
+            return Err(format!(
+                "Invalid PostgreSQL URI '{}': must start with postgres:// or postgresql://",
+                crate::uri::redact(uri)
+            ));
+        }
+        uri.parse::<tokio_postgres::Config>()
+            .map(|_| ())
+            .map_err(|e| {
+                format!(
+                    "Invalid PostgreSQL URI '{}': {}",
+                    crate::uri::redact(uri),
+                    e
+                )
+            })
+    }
+
+    /// Checks that the datastore name can be used, unquoted, as a schema name: lowercase ASCII
+    /// letters, digits and underscores, not starting with a digit.
+    pub fn validate_datastore(datastore: &str) -> std::result::Result<(), String> {
+        if datastore.is_empty() {
+            return Err("Datastore name must not be empty".to_string());
+        }
+        if datastore.len() > MAX_DATASTORE_LEN {
+            return Err(format!(
+                "Datastore name '{}' is longer than the {} bytes PostgreSQL allows in schema names",
+                datastore, MAX_DATASTORE_LEN
+            ));
+        }
+        if datastore.starts_with(|c: char| c.is_ascii_digit())
+            || !datastore
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        {
+            return Err(format!(
+                "Datastore name '{}' must only contain lowercase letters, digits and underscores, \
+                and not start with a digit",
+                datastore
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns a connection from the pool, creating the pool the first time it is needed.
+    /// Connections that have been closed, e.g. by a server restart, are replaced by the pool.
+    async fn connection(&self) -> Result<Object> {
+        let pool = self
+            .pool
+            .get_or_try_init(|| async {
+                let mut config: tokio_postgres::Config = self.uri.parse().context(format!(
+                    "Failed to parse PostgreSQL URI: '{}'",
+                    crate::uri::redact(&self.uri)
+                ))?;
+                if let Some(credentials) = &self.credentials {
+                    let (username, password) = credentials.resolve().await?;
+                    if let Some(username) = username {
+                        config.user(&username);
+                    }
+                    config.password(password);
+                }
+                let manager = Manager::from_config(
+                    config,
+                    NoTls,
+                    ManagerConfig {
+                        recycling_method: RecyclingMethod::Fast,
+                    },
+                );
+                Pool::builder(manager)
+                    .max_size(MAX_CONNECTIONS)
+                    .build()
+                    .context("Failed to create PostgreSQL connection pool")
+            })
+            .await?;
+        pool.get().await.context("Failed to connect to PostgreSQL")
+    }
+
+    /// Names of the schemas in the database, for listing namespaces.
+    pub(crate) async fn schema_names(&self) -> Result<Vec<String>> {
+        let client = self.connection().await?;
+        let rows = client
+            .query(
+                "SELECT schema_name::text FROM information_schema.schemata",
+                &[],
+            )
+            .await
+            .context("Failed to list schemas")?;
+        rows.iter()
+            .map(|row| row.try_get(0).context("Failed to list schemas"))
+            .collect()
+    }
+
+    /// Name of the table used to store records of the given type.
+    fn table_name(rec_type: &ArchiveRecordType) -> &str {
+        match rec_type {
+            ArchiveRecordType::Account => ACCOUNT_TABLE,
+            ArchiveRecordType::TransactionBatch => TRANSACTION_TABLE,
+            ArchiveRecordType::Block => BLOCK_TABLE,
+            ArchiveRecordType::Receipt => RECEIPT_TABLE,
+            ArchiveRecordType::Custom(name) => name,
+        }
+    }
+
+    /// Returns the qualified name of the table used to store records of the given type, creating
+    /// the schema and table, along with the index on the type's indexed field if it has one, if
+    /// this backend hasn't used them yet.
+    async fn table(&self, client: &Object, rec_type: &ArchiveRecordType) -> Result<String> {
+        let name = Self::table_name(rec_type);
+        let table = format!("\"{}\".\"{}\"", self.datastore, name);
+        if self
+            .tables
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains(rec_type)
+        {
+            return Ok(table);
+        }
+
+        let mut sql = format!(
+            "CREATE SCHEMA IF NOT EXISTS \"{}\";
+             CREATE TABLE IF NOT EXISTS {} (
+                 id TEXT PRIMARY KEY,
+                 record JSONB NOT NULL,
+                 archived_at TIMESTAMPTZ NOT NULL DEFAULT now()
+             );",
+            self.datastore, table
+        );
+        if let Some(field) = rec_type.indexed_field() {
+            // The same expression filters use, so that they can use the index.
+            sql.push_str(&format!(
+                "CREATE INDEX IF NOT EXISTS \"{}_{}_idx\" ON {} ((record #> '{{{}}}'));",
+                name, field, table, field
+            ));
+        }
+        client
+            .batch_execute(&sql)
+            .await
+            .context("Failed to create table")?;
+        self.tables
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(rec_type.clone());
+        Ok(table)
+    }
+
+    /// Runs a query over the records of the given type, decoding every row returned.
+    async fn select<T: DeserializeOwned>(
+        &self,
+        rec_type: &ArchiveRecordType,
+        condition: &str,
+        params: &[Param],
+    ) -> Result<Vec<T>> {
+        let client = self.connection().await?;
+        let table = self.table(&client, rec_type).await?;
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT id, record FROM {} WHERE {} ORDER BY id",
+                    table, condition
+                ),
+                &borrow_params(params),
+            )
+            .await
+            .context("Failed to find records")?;
+
+        rows.iter()
+            .map(|row| bson::from_document(from_row(row)?).context("Failed to deserialise record"))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl ArchiveBackend for PostgresBackend {
+    async fn create<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let (id, record) =
+            to_row(bson::to_document(&rec).context("Failed to serialise record to BSON")?);
+        let client = self.connection().await?;
+        let table = self.table(&client, &rec_type).await?;
+        client
+            .execute(
+                &format!("INSERT INTO {} (id, record) VALUES ($1, $2)", table),
+                &[&id, &record],
+            )
+            .await
+            .context("Failed to insert record")?;
+
+        debug!("Inserted {}", id);
+        Ok(id)
+    }
+
+    /// Inserts every record with a single statement, so the batch is always atomic and the
+    /// ordering setting has no effect.
+    async fn create_many<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        recs: Vec<T>,
+    ) -> Result<Vec<String>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let mut ids = Vec::with_capacity(recs.len());
+        let mut records = Vec::with_capacity(recs.len());
+        for rec in &recs {
+            let (id, record) =
+                to_row(bson::to_document(rec).context("Failed to serialise record to BSON")?);
+            ids.push(id);
+            records.push(record);
+        }
+
+        let client = self.connection().await?;
+        let table = self.table(&client, &rec_type).await?;
+        client
+            .execute(
+                &format!(
+                    "INSERT INTO {} (id, record) SELECT * FROM UNNEST($1::text[], $2::jsonb[])",
+                    table
+                ),
+                &[&ids, &records],
+            )
+            .await
+            .context("Failed to insert records")?;
+
+        debug!("Inserted {} records", ids.len());
+        Ok(ids)
+    }
+
+    /// Inserts the records within a transaction, which is only committed once every insert has
+    /// succeeded and is rolled back otherwise.
+    async fn create_atomic(
+        &self,
+        records: Vec<(ArchiveRecordType, Document)>,
+    ) -> Result<Vec<String>, ArchiveError> {
+        let mut client = self.connection().await?;
+        let mut tables = Vec::with_capacity(records.len());
+        for (rec_type, _) in &records {
+            tables.push(self.table(&client, rec_type).await?);
+        }
+
+        let transaction = client
+            .transaction()
+            .await
+            .context("Failed to start transaction")?;
+        let mut ids = Vec::with_capacity(records.len());
+        for ((_, rec), table) in records.into_iter().zip(tables) {
+            let (id, record) = to_row(rec);
+            // Dropping the transaction on error rolls it back.
+            transaction
+                .execute(
+                    &format!("INSERT INTO {} (id, record) VALUES ($1, $2)", table),
+                    &[&id, &record],
+                )
+                .await
+                .context("Failed to insert record")?;
+            ids.push(id);
+        }
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")?;
+
+        debug!("Atomically inserted {} records", ids.len());
+        Ok(ids)
+    }
+
+    async fn find_all<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        Ok(self.select(&rec_type, "true", &[]).await?)
+    }
+
+    /// Streams the records as rows arrive from the server. The connection is held by the stream
+    /// until it is dropped.
+    async fn find_all_stream<'a, T: DeserializeOwned>(
+        &'a self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'a, Result<T, ArchiveError>>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin + 'a,
+    {
+        let client = self.connection().await?;
+        let table = self.table(&client, &rec_type).await?;
+        let rows = client
+            .query_raw(
+                &format!("SELECT id, record FROM {} ORDER BY id", table),
+                Vec::<&(dyn ToSql + Sync)>::new(),
+            )
+            .await
+            .context("Failed to find records")?;
+
+        Ok(rows
+            .map_err(|e| anyhow::Error::new(e).context("Failed to read record"))
+            .and_then(move |row| {
+                // Keep the connection out of the pool while the stream is in use.
+                let _ = &client;
+                async move {
+                    bson::from_document(from_row(&row)?).context("Failed to deserialise record")
+                }
+            })
+            .map_err(ArchiveError::from)
+            .boxed())
+    }
+
+    async fn find_page<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        request: &PageRequest,
+    ) -> Result<Page<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let client = self.connection().await?;
+        let table = self.table(&client, &rec_type).await?;
+        let after = request.after_token.clone().unwrap_or_default();
+        // Read one more record than requested to tell whether there is a next page.
+        let mut rows = client
+            .query(
+                &format!(
+                    "SELECT id, record FROM {} WHERE id > $1 ORDER BY id LIMIT $2",
+                    table
+                ),
+                &[&after, &(request.limit as i64 + 1)],
+            )
+            .await
+            .context("Failed to find records")?;
+
+        let next_token = if rows.len() > request.limit {
+            rows.truncate(request.limit);
+            rows.last().map(|row| row.try_get("id")).transpose()?
+        } else {
+            None
+        };
+
+        let items = rows
+            .iter()
+            .map(|row| bson::from_document(from_row(row)?).context("Failed to deserialise record"))
+            .collect::<Result<_>>()?;
+        Ok(Page { items, next_token })
+    }
+
+    /// Query data store for the records of the given type matching a [Filter], translated into a
+    /// SQL condition over the JSONB records.
+    async fn query<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let mut params = Vec::new();
+        let condition = filter_sql(filter, &mut params);
+        Ok(self.select(&rec_type, &condition, &params).await?)
+    }
+
+    /// Reads each field with the `#>` operator, so only those fields are sent back.
+    async fn find_projected(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        fields: &[String],
+    ) -> Result<Vec<Document>, ArchiveError> {
+        let mut params = Vec::new();
+        let columns: Vec<String> = fields
+            .iter()
+            .map(|field| format!("record #> {}", path_param(&mut params, field)))
+            .collect();
+        let condition = filter_sql(filter, &mut params);
+        let client = self.connection().await?;
+        let table = self.table(&client, &rec_type).await?;
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT id, {} FROM {} WHERE {} ORDER BY id",
+                    columns.join(", "),
+                    table,
+                    condition
+                ),
+                &borrow_params(&params),
+            )
+            .await
+            .context("Failed to find records")?;
+
+        let mut docs = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Document::new();
+            doc.insert(
+                "_id",
+                row.try_get::<_, String>(0).context("Invalid stored id")?,
+            );
+            for (i, field) in fields.iter().enumerate() {
+                let value: Option<serde_json::Value> =
+                    row.try_get(i + 1).context("Invalid stored record")?;
+                if let Some(value) = value {
+                    let value = Bson::try_from(value).context("Invalid stored record")?;
+                    projection::insert(&mut doc, field, value);
+                }
+            }
+            docs.push(doc);
+        }
+        Ok(docs)
+    }
+
+    async fn find_by_id<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<Option<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let params: Vec<Param> = vec![Box::new(id.to_string())];
+        Ok(self
+            .select(&rec_type, "id = $1", &params)
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    async fn delete_by_id(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<bool, ArchiveError> {
+        let client = self.connection().await?;
+        let table = self.table(&client, &rec_type).await?;
+        let deleted = client
+            .execute(&format!("DELETE FROM {} WHERE id = $1", table), &[&id])
+            .await
+            .context("Failed to delete record")?;
+
+        debug!("Deleted {}", id);
+        Ok(deleted > 0)
+    }
+
+    async fn delete_where(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        let mut params = Vec::new();
+        let condition = filter_sql(filter, &mut params);
+        let client = self.connection().await?;
+        let table = self.table(&client, &rec_type).await?;
+        let deleted = client
+            .execute(
+                &format!("DELETE FROM {} WHERE {}", table, condition),
+                &borrow_params(&params),
+            )
+            .await
+            .context("Failed to delete records")?;
+
+        debug!("Deleted {} records", deleted);
+        Ok(deleted)
+    }
+
+    /// Replaces the stored record, keeping its id whatever `_id` the record holds.
+    async fn update_by_id<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        rec: T,
+    ) -> Result<bool, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let (_, record) =
+            to_row(bson::to_document(&rec).context("Failed to serialise record to BSON")?);
+        let client = self.connection().await?;
+        let table = self.table(&client, &rec_type).await?;
+        let updated = client
+            .execute(
+                &format!("UPDATE {} SET record = $2 WHERE id = $1", table),
+                &[&id, &record],
+            )
+            .await
+            .context("Failed to update record")?;
+
+        debug!("Updated {}", id);
+        Ok(updated > 0)
+    }
+
+    /// Looks up the first matching record, in id order, and replaces or inserts within a
+    /// transaction. The matching row is locked until the transaction commits, but a concurrent
+    /// upsert may still insert a second record when nothing matches.
+    async fn upsert<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let (new_id, record) =
+            to_row(bson::to_document(&rec).context("Failed to serialise record to BSON")?);
+        let mut params = Vec::new();
+        let condition = filter_sql(filter, &mut params);
+        let mut client = self.connection().await?;
+        let table = self.table(&client, &rec_type).await?;
+
+        let transaction = client
+            .transaction()
+            .await
+            .context("Failed to start transaction")?;
+        let existing = transaction
+            .query_opt(
+                &format!(
+                    "SELECT id FROM {} WHERE {} ORDER BY id LIMIT 1 FOR UPDATE",
+                    table, condition
+                ),
+                &borrow_params(&params),
+            )
+            .await
+            .context("Failed to find record")?;
+        // Dropping the transaction on error rolls it back.
+        let id = match existing {
+            Some(row) => {
+                let id: String = row.try_get("id")?;
+                transaction
+                    .execute(
+                        &format!("UPDATE {} SET record = $2 WHERE id = $1", table),
+                        &[&id, &record],
+                    )
+                    .await
+                    .context("Failed to update record")?;
+                id
+            }
+            None => {
+                transaction
+                    .execute(
+                        &format!("INSERT INTO {} (id, record) VALUES ($1, $2)", table),
+                        &[&new_id, &record],
+                    )
+                    .await
+                    .context("Failed to insert record")?;
+                new_id
+            }
+        };
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")?;
+
+        debug!("Upserted {}", id);
+        Ok(id)
+    }
+
+    /// Returns a random sample of roughly `rate` (0.0 to 1.0) of the records of the given type,
+    /// selecting each record independently with probability `rate`.
+    async fn find_sampled<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rate: f64,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let params: Vec<Param> = vec![Box::new(rate)];
+        Ok(self
+            .select(&rec_type, "random() < $1::float8", &params)
+            .await?)
+    }
+
+    /// Records are never chunked, so there are never orphaned chunks.
+    async fn find_orphaned_chunks(
+        &self,
+        _rec_type: ArchiveRecordType,
+    ) -> Result<Vec<String>, ArchiveError> {
+        Ok(Vec::new())
+    }
+
+    /// Records are never chunked, so there are never orphaned chunks.
+    async fn cleanup_orphans(&self, _rec_type: ArchiveRecordType) -> Result<u64, ArchiveError> {
+        Ok(0)
+    }
+
+    /// Rewrites the record type's table with `VACUUM FULL`, which locks it against reads and
+    /// writes while it runs and needs as much free disk space as the table's size.
+    async fn reclaim_space(&self, rec_type: ArchiveRecordType) -> Result<(), ArchiveError> {
+        let client = self.connection().await?;
+        let table = self.table(&client, &rec_type).await?;
+        client
+            .batch_execute(&format!("VACUUM FULL {}", table))
+            .await
+            .context("Failed to vacuum table")?;
+        debug!("Vacuumed {}", table);
+        Ok(())
+    }
+
+    /// Aggregation pipelines are MongoDB specific.
+    async fn merge_into(
+        &self,
+        _source: ArchiveRecordType,
+        _pipeline: Vec<Document>,
+        _target: ArchiveRecordType,
+        _mode: MergeMode,
+    ) -> Result<u64, ArchiveError> {
+        Err(Unsupported {
+            operation: "merge_into",
+            reason: "aggregation pipelines are only supported by the MongoDB backend".to_string(),
+        }
+        .into())
+    }
+
+    /// Changes are only reported by the store that made them.
+    async fn watch(
+        &self,
+        _rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'static, Result<ArchiveEvent, ArchiveError>>, ArchiveError> {
+        Err(Unsupported {
+            operation: "watch",
+            reason: "change streams are only supported by the MongoDB backend".to_string(),
+        }
+        .into())
+    }
+
+    async fn count(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        let mut params = Vec::new();
+        let condition = filter_sql(filter, &mut params);
+        let client = self.connection().await?;
+        let table = self.table(&client, &rec_type).await?;
+        let row = client
+            .query_one(
+                &format!("SELECT count(*) FROM {} WHERE {}", table, condition),
+                &borrow_params(&params),
+            )
+            .await
+            .context("Failed to count records")?;
+        Ok(row.try_get::<_, i64>(0)? as u64)
+    }
+
+    async fn exists(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<bool, ArchiveError> {
+        let mut params = Vec::new();
+        let condition = filter_sql(filter, &mut params);
+        let client = self.connection().await?;
+        let table = self.table(&client, &rec_type).await?;
+        let row = client
+            .query_one(
+                &format!(
+                    "SELECT EXISTS (SELECT 1 FROM {} WHERE {})",
+                    table, condition
+                ),
+                &borrow_params(&params),
+            )
+            .await
+            .context("Failed to look for records")?;
+        Ok(row.try_get(0)?)
+    }
+
+    /// Reads the planner's estimated row count and the table's size, including its indexes and
+    /// TOAST storage. Tables that haven't been analysed yet have no estimate, so are counted.
+    async fn stats(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<ArchiveCollectionStats, ArchiveError> {
+        let client = self.connection().await?;
+        let row = client
+            .query_opt(
+                "SELECT c.reltuples::bigint, pg_total_relation_size(c.oid)
+                 FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace
+                 WHERE n.nspname = $1 AND c.relname = $2",
+                &[&self.datastore, &Self::table_name(&rec_type)],
+            )
+            .await
+            .context("Failed to read table statistics")?;
+
+        let (estimate, storage_bytes) = match row {
+            Some(row) => (row.try_get::<_, i64>(0)?, row.try_get::<_, i64>(1)? as u64),
+            None => return Ok(ArchiveCollectionStats::default()),
+        };
+        let document_count = match u64::try_from(estimate) {
+            Ok(estimate) => estimate,
+            Err(_) => self.count(rec_type, &Filter::All).await?,
+        };
+        Ok(ArchiveCollectionStats {
+            document_count,
+            storage_bytes,
+            avg_doc_bytes: storage_bytes.checked_div(document_count).unwrap_or(0),
+        })
+    }
+
+    async fn group_count(
+        &self,
+        rec_type: ArchiveRecordType,
+        group_by: GroupBy,
+    ) -> Result<Vec<(Bson, u64)>, ArchiveError> {
+        let client = self.connection().await?;
+        let table = self.table(&client, &rec_type).await?;
+
+        match group_by {
+            GroupBy::Field(field) => {
+                let path: Vec<String> = field.split('.').map(str::to_string).collect();
+                let rows = client
+                    .query(
+                        &format!(
+                            "SELECT record #> $1::text[] AS key, count(*) FROM {} GROUP BY key \
+                            ORDER BY key",
+                            table
+                        ),
+                        &[&path],
+                    )
+                    .await
+                    .context("Failed to group records")?;
+                rows.iter()
+                    .map(|row| {
+                        let key = match row.try_get::<_, Option<serde_json::Value>>(0)? {
+                            Some(key) => Bson::try_from(key).context("Invalid group key")?,
+                            None => Bson::Null,
+                        };
+                        Ok((key, row.try_get::<_, i64>(1)? as u64))
+                    })
+                    .collect()
+            }
+            GroupBy::ArchivedAt(granularity) => {
+                let rows = client
+                    .query(
+                        &format!(
+                            "SELECT to_char(archived_at AT TIME ZONE 'UTC', $1) AS key, count(*) \
+                            FROM {} GROUP BY key ORDER BY key",
+                            table
+                        ),
+                        &[&date_format(&granularity)],
+                    )
+                    .await
+                    .context("Failed to group records")?;
+                rows.iter()
+                    .map(|row| {
+                        Ok((
+                            Bson::String(row.try_get(0)?),
+                            row.try_get::<_, i64>(1)? as u64,
+                        ))
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Aggregates the records matching the filter in process.
+    async fn aggregate(
+        &self,
+        rec_type: ArchiveRecordType,
+        spec: &AggregationSpec,
+    ) -> Result<Vec<Document>, ArchiveError> {
+        let docs = self.query::<Document>(rec_type, &spec.filter).await?;
+        Ok(aggregate(spec, docs))
+    }
+
+    /// Creates an expression index over the same paths filters compare, so that they can use it.
+    async fn ensure_indexes(
+        &self,
+        rec_type: ArchiveRecordType,
+        indexes: &[IndexSpec],
+    ) -> Result<(), ArchiveError> {
+        if indexes.is_empty() {
+            return Ok(());
+        }
+        let client = self.connection().await?;
+        let table = self.table(&client, &rec_type).await?;
+        let name = Self::table_name(&rec_type);
+
+        let mut sql = String::new();
+        for index in indexes {
+            let paths: Vec<String> = index
+                .fields
+                .iter()
+                .map(|field| format!("(record #> '{{{}}}')", field.replace('.', ",")))
+                .collect();
+            sql.push_str(&format!(
+                "CREATE {}INDEX IF NOT EXISTS \"{}\" ON {} ({});",
+                if index.unique { "UNIQUE " } else { "" },
+                index.name(name),
+                table,
+                paths.join(", ")
+            ));
+        }
+        client
+            .batch_execute(&sql)
+            .await
+            .context("Failed to create indexes")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+/// Projected reads, which fetch only some fields of each record, e.g. the address and nonce of
+/// every account for a reconciliation job, instead of reading and deserialising whole records.
+/// Backends that can read part of a stored document do so, others read whole documents, which
+/// the store then trims to the requested fields.
+use crate::filter::{id_to_string, lookup};
+use crate::migration::{Migrations, VERSION_FIELD};
+use crate::{
+    chunking, compression, encryption, ArchiveError, ArchiveRecordType, ArchiveStore, Filter,
+};
+use anyhow::Context;
+use bson::{Bson, Document};
+
+impl ArchiveStore {
+    /// Finds the records of [ArchiveRecordType] matching a [Filter], keeping only the given
+    /// fields, e.g. `find_projected(ArchiveRecordType::Account, Filter::All, &["address",
+    /// "nonce"])`. Each record is returned as a document holding its `_id` and those of the
+    /// fields it has. Fields may be dotted paths into embedded documents, but not into arrays.
+    /// The MongoDB, PostgreSQL and SQLite backends read only the requested fields, other
+    /// backends read whole records. Compressed, encrypted and chunked records, and records
+    /// written with an older schema version, are always read whole, as they have to be decoded
+    /// or migrated before their fields can be picked out.
+    pub async fn find_projected(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: Filter,
+        fields: &[&str],
+    ) -> Result<Vec<Document>, ArchiveError> {
+        let fields = normalise(fields).map_err(ArchiveError::invalid_input)?;
+        let filter = self.visible(filter);
+        let mut recs = self
+            .find_projected_untiered(rec_type.clone(), &filter, &fields)
+            .await?;
+        if let Some(cold) = self.cold_tier() {
+            recs.extend(
+                cold.find_projected_untiered(rec_type, &filter, &fields)
+                    .await?,
+            );
+        }
+        Ok(recs)
+    }
+
+    /// [ArchiveStore::find_projected] on this store's own tier, without its cold tier.
+    pub(crate) async fn find_projected_untiered(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        fields: &[String],
+    ) -> Result<Vec<Document>, ArchiveError> {
+        self.mirrored_read("find_projected", |store| {
+            store.find_projected_unmirrored(rec_type.clone(), filter, fields)
+        })
+        .await
+    }
+
+    /// [ArchiveStore::find_projected] on this store's own backend, without its mirrors.
+    async fn find_projected_unmirrored(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        fields: &[String],
+    ) -> Result<Vec<Document>, ArchiveError> {
+        // Wrapped records are read whole, along with the schema version they were written with.
+        let mut stored = fields.to_vec();
+        stored.extend(
+            compression::WRAPPER_FIELDS
+                .iter()
+                .chain(&encryption::WRAPPER_FIELDS)
+                .chain(&[VERSION_FIELD, chunking::MANIFEST_FIELD])
+                .map(|field| field.to_string()),
+        );
+        let stored = normalise(&stored.iter().map(String::as_str).collect::<Vec<_>>())
+            .map_err(ArchiveError::invalid_input)?;
+
+        let docs = self
+            .observe("find_projected", &rec_type, || async {
+                self.backend()?
+                    .find_projected(rec_type.clone(), filter, &stored)
+                    .await
+                    .with_context(|| {
+                        format!("Projecting blobs in {} for {}", self.inner.backend, filter)
+                    })
+                    .map(|v| (v, 0))
+            })
+            .await?;
+
+        let current = self.schema_version(&rec_type);
+        let mut recs = Vec::with_capacity(docs.len());
+        for doc in docs {
+            let rec = if Migrations::version(&doc) < current {
+                // Migrations may need fields that weren't read, so the record is read again whole.
+                let id = doc.get("_id").map(id_to_string).unwrap_or_default();
+                match self
+                    .find_envelope_by_id_unmirrored::<Document>(rec_type.clone(), &id)
+                    .await?
+                {
+                    Some(envelope) => envelope.record,
+                    // Deleted since it was found
+                    None => continue,
+                }
+            } else {
+                self.decode_envelope::<Document>(&rec_type, doc)?.record
+            };
+            recs.push(project(&rec, fields));
+        }
+        Ok(recs)
+    }
+}
+
+/// Checks the fields to project, returning them without duplicates, `_id`, which is always
+/// included, or fields within another of the fields.
+fn normalise(fields: &[&str]) -> Result<Vec<String>, String> {
+    if fields.is_empty() {
+        return Err("No fields to project".to_string());
+    }
+    if let Some(field) = fields
+        .iter()
+        .find(|field| field.split('.').any(str::is_empty) || field.starts_with('$'))
+    {
+        return Err(format!("Invalid field to project: {:?}", field));
+    }
+
+    let within = |field: &str, other: &str| {
+        field
+            .strip_prefix(other)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    };
+    let mut normalised: Vec<String> = Vec::with_capacity(fields.len());
+    for (i, field) in fields.iter().enumerate() {
+        let covered = within(field, "_id")
+            || fields[..i].contains(field)
+            || fields
+                .iter()
+                .any(|other| other != field && within(field, other));
+        if !covered {
+            normalised.push(field.to_string());
+        }
+    }
+    Ok(normalised)
+}
+
+/// A document holding only the `_id` and the given fields of `doc`, those it has.
+pub(crate) fn project(doc: &Document, fields: &[String]) -> Document {
+    let mut projected = Document::new();
+    if let Some(id) = doc.get("_id") {
+        projected.insert("_id", id.clone());
+    }
+    for field in fields {
+        if let Some(value) = lookup(doc, field) {
+            insert(&mut projected, field, value.clone());
+        }
+    }
+    projected
+}
+
+/// Sets a (possibly dotted) field of a document, creating the embedded documents on its path.
+pub(crate) fn insert(doc: &mut Document, field: &str, value: Bson) {
+    let (key, rest) = match field.split_once('.') {
+        Some(split) => split,
+        None => {
+            doc.insert(field, value);
+            return;
+        }
+    };
+    if !matches!(doc.get(key), Some(Bson::Document(_))) {
+        doc.insert(key, Document::new());
+    }
+    if let Some(Bson::Document(inner)) = doc.get_mut(key) {
+        insert(inner, rest, value);
+    }
+}
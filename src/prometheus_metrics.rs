@@ -0,0 +1,210 @@
+/// An [ArchiveMetrics] hook maintaining Prometheus metrics, for dashboards of archive throughput,
+/// latency and error rates. Requires the `metrics` feature.
+use crate::{ArchiveBackends, ArchiveError, ArchiveMetrics, ArchiveOperation, ArchiveRecordType};
+use anyhow::{Context, Result};
+use prometheus::{
+    exponential_buckets, Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry,
+    TextEncoder,
+};
+
+/// Labels of every metric, identifying the operation
+const LABELS: [&str; 4] = ["op", "rec_type", "backend", "datastore"];
+
+/// Records every archive operation in Prometheus metrics, set with
+/// [crate::ArchiveStoreBuilder::metrics]:
+///
+/// - `lasr_archive_operations_total`: operations by `op`, `rec_type`, `backend`, `datastore` and
+///   `outcome`, i.e. `success` or `failure`
+/// - `lasr_archive_operation_duration_seconds`: a histogram of how long operations took,
+///   including retries
+/// - `lasr_archive_written_bytes`: a histogram of the size of the encoded records written
+/// - `lasr_archive_retries_total`: retries of failed operations
+///
+/// The metrics are registered in a registry of their own, read with [PrometheusMetrics::gather],
+/// or in the service's registry with [PrometheusMetrics::with_registry]. Labels of store handles
+/// (see [crate::ArchiveStore::with_labels]) are only added to the metrics for the keys given to
+/// [PrometheusMetrics::with_label_keys], as Prometheus needs every label of a metric up front.
+/// Clones share the same metrics, so one can be given to several stores and kept to be gathered.
+#[derive(Debug, Clone)]
+pub struct PrometheusMetrics {
+    registry: Registry,
+    /// Keys of the handle labels added to every metric, after the [LABELS]
+    label_keys: Vec<String>,
+    operations: IntCounterVec,
+    duration: HistogramVec,
+    written_bytes: HistogramVec,
+    retries: IntCounterVec,
+}
+
+impl PrometheusMetrics {
+    /// Registers the metrics in a registry of their own.
+    pub fn new() -> Self {
+        PrometheusMetrics::with_registry(&Registry::new())
+            .expect("Metrics are only registered once in a new registry")
+    }
+
+    /// Registers the metrics in `registry`, e.g. the one the service exposes its own metrics
+    /// from. Fails if they are already registered in it.
+    pub fn with_registry(registry: &Registry) -> Result<Self, ArchiveError> {
+        PrometheusMetrics::with_label_keys(registry, &[])
+    }
+
+    /// Registers the metrics in `registry`, labelled with the values of the given keys of the
+    /// handle labels operations carry, e.g. `&["tenant"]`, or an empty value for operations
+    /// without one. Keys must be valid Prometheus label names, other than the built-in labels,
+    /// and take a small, bounded set of values. Fails if the metrics are already registered in
+    /// the registry.
+    pub fn with_label_keys(registry: &Registry, keys: &[&str]) -> Result<Self, ArchiveError> {
+        if let Some(key) = keys
+            .iter()
+            .find(|key| LABELS.contains(key) || **key == "outcome")
+        {
+            return Err(ArchiveError::invalid_input(format!(
+                "Label key '{}' is a built-in metric label",
+                key
+            )));
+        }
+        Ok(PrometheusMetrics::register(registry, keys)?)
+    }
+
+    /// Creates the metrics, labelled with the given handle label keys, and registers them in
+    /// `registry`.
+    fn register(registry: &Registry, keys: &[&str]) -> Result<Self> {
+        let mut labels = LABELS.to_vec();
+        labels.extend(keys);
+        let mut outcome_labels = labels.clone();
+        outcome_labels.push("outcome");
+        let operations = IntCounterVec::new(
+            Opts::new("lasr_archive_operations_total", "Archive operations"),
+            &outcome_labels,
+        )?;
+        let duration = HistogramVec::new(
+            HistogramOpts::new(
+                "lasr_archive_operation_duration_seconds",
+                "How long archive operations took",
+            )
+            .buckets(exponential_buckets(0.001, 2.0, 15)?),
+            &labels,
+        )?;
+        let written_bytes = HistogramVec::new(
+            HistogramOpts::new(
+                "lasr_archive_written_bytes",
+                "Size of the encoded records written",
+            )
+            .buckets(exponential_buckets(64.0, 4.0, 10)?),
+            &labels,
+        )?;
+        let retries = IntCounterVec::new(
+            Opts::new(
+                "lasr_archive_retries_total",
+                "Retries of failed archive operations",
+            ),
+            &labels,
+        )?;
+
+        registry
+            .register(Box::new(operations.clone()))
+            .context("Registering archive metrics")?;
+        registry
+            .register(Box::new(duration.clone()))
+            .context("Registering archive metrics")?;
+        registry
+            .register(Box::new(written_bytes.clone()))
+            .context("Registering archive metrics")?;
+        registry
+            .register(Box::new(retries.clone()))
+            .context("Registering archive metrics")?;
+        Ok(PrometheusMetrics {
+            registry: registry.clone(),
+            label_keys: keys.iter().map(|key| key.to_string()).collect(),
+            operations,
+            duration,
+            written_bytes,
+            retries,
+        })
+    }
+
+    /// The registry the metrics are registered in.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Every metric in the registry in the Prometheus text exposition format, to be served to
+    /// the Prometheus server, e.g. from a `/metrics` endpoint.
+    pub fn gather(&self) -> String {
+        let mut text = Vec::new();
+        // Encoding into a Vec can only fail on malformed metrics, which the registry rejects.
+        let _ = TextEncoder::new().encode(&self.registry.gather(), &mut text);
+        String::from_utf8(text).unwrap_or_default()
+    }
+}
+
+impl Default for PrometheusMetrics {
+    fn default() -> Self {
+        PrometheusMetrics::new()
+    }
+}
+
+impl ArchiveMetrics for PrometheusMetrics {
+    fn on_operation(&self, op: &ArchiveOperation) {
+        let rec_type = rec_type_label(op.rec_type);
+        let mut labels = vec![op.op, rec_type, backend_label(op.backend), op.datastore];
+        labels.extend(
+            self.label_keys
+                .iter()
+                .map(|key| op.labels.get(key).unwrap_or_default()),
+        );
+        let outcome = op.outcome.to_string();
+        let mut outcome_labels = labels.to_vec();
+        outcome_labels.push(&outcome);
+
+        self.operations.with_label_values(&outcome_labels).inc();
+        self.duration
+            .with_label_values(&labels)
+            .observe(op.duration.as_secs_f64());
+        if op.bytes > 0 {
+            self.written_bytes
+                .with_label_values(&labels)
+                .observe(op.bytes as f64);
+        }
+        if op.retries > 0 {
+            self.retries
+                .with_label_values(&labels)
+                .inc_by(op.retries.into());
+        }
+    }
+}
+
+/// The `rec_type` label of a record type, e.g. `transaction_batch`.
+fn rec_type_label(rec_type: &ArchiveRecordType) -> &str {
+    match rec_type {
+        ArchiveRecordType::Account => "account",
+        ArchiveRecordType::TransactionBatch => "transaction_batch",
+        ArchiveRecordType::Block => "block",
+        ArchiveRecordType::Receipt => "receipt",
+        ArchiveRecordType::Custom(name) => name,
+    }
+}
+
+/// The `backend` label of a backend, leaving out the filesystem backend's root.
+fn backend_label(backend: &ArchiveBackends) -> &str {
+    match backend {
+        #[cfg(feature = "mongodb")]
+        ArchiveBackends::MongoDB => "mongodb",
+        #[cfg(feature = "postgres")]
+        ArchiveBackends::Postgres => "postgres",
+        #[cfg(feature = "sqlite")]
+        ArchiveBackends::Sqlite => "sqlite",
+        #[cfg(feature = "s3")]
+        ArchiveBackends::S3 => "s3",
+        #[cfg(feature = "rocksdb")]
+        ArchiveBackends::RocksDb => "rocksdb",
+        ArchiveBackends::Filesystem { .. } => "filesystem",
+        ArchiveBackends::InMemory => "memory",
+        #[cfg(feature = "ipfs")]
+        ArchiveBackends::Ipfs { .. } => "ipfs",
+        #[cfg(feature = "arweave")]
+        ArchiveBackends::Arweave { .. } => "arweave",
+        ArchiveBackends::Custom { name } => name,
+    }
+}
@@ -0,0 +1,224 @@
+/// Merkle inclusion proofs over archived records, so that light clients can verify a historical
+/// record served by an archive node was genuinely part of the archive without trusting the node's
+/// database. The tree of an [ArchiveRecordType] has a leaf per record, ordered by record id, so
+/// every node serving the same archive computes the same root whatever its backend. A leaf is the
+/// SHA-256 hash of the record's id and its canonical form, the same form its checksum is computed
+/// over, so backend specific encodings such as compression or encryption don't affect it. Leaves
+/// and inner nodes are hashed with distinct prefixes, and the last node of a level with an odd
+/// number of nodes is promoted to the next level unchanged.
+///
+/// The root to verify against must come from a trusted source, e.g. a root published or anchored
+/// on chain by the operator with [ArchiveStore::merkle_root], never from the node serving the
+/// record. Proofs are only valid against the root of the same archive state, so roots and proofs
+/// of record types that are still being written to go stale as records are added.
+use crate::{checksum, filter, ArchiveError, ArchiveRecordType, ArchiveStore};
+use anyhow::anyhow;
+use bson::{Bson, Document};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Prefix of the hashed data of a leaf
+const LEAF_PREFIX: u8 = 0;
+
+/// Prefix of the hashed data of an inner node
+const NODE_PREFIX: u8 = 1;
+
+type Hash = [u8; 32];
+
+/// The root of the Merkle tree over the records of an [ArchiveRecordType].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleRoot {
+    /// Hex encoded root hash, the hash of no data for an empty record type
+    pub hash: String,
+    /// Number of records in the tree
+    pub leaf_count: usize,
+}
+
+/// Proof that a record is included in the Merkle tree of its [ArchiveRecordType], see
+/// [verify_proof]. Proofs are plain data, so they can be handed to light clients as JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InclusionProof {
+    /// Id of the proven record
+    pub id: String,
+    /// Hex encoded leaf hash of the proven record
+    pub leaf: String,
+    /// Position of the record's leaf in the tree
+    pub index: usize,
+    /// Number of records in the tree the proof was generated from
+    pub leaf_count: usize,
+    /// Hex encoded hashes of the siblings on the path from the leaf to the root, starting with
+    /// the leaf's sibling. Levels on which the path's node is promoted have no sibling.
+    pub siblings: Vec<String>,
+}
+
+impl InclusionProof {
+    /// Whether the proof is of the given record, as served e.g. by [ArchiveStore::find_by_id]
+    /// as a [Document]. Its `_id`, if any, is ignored in favour of the proof's id.
+    pub fn matches(&self, record: &Document) -> bool {
+        hex(&leaf_hash(&self.id, record)) == self.leaf
+    }
+
+    /// Verifies the proof against a trusted root, see [verify_proof].
+    pub fn verify(&self, root: &MerkleRoot) -> bool {
+        verify_proof(root, self)
+    }
+}
+
+/// Verifies that the proof's leaf is included in the tree with the given root, at the proof's
+/// position. Combined with [InclusionProof::matches] this proves the record was archived as
+/// served.
+pub fn verify_proof(root: &MerkleRoot, proof: &InclusionProof) -> bool {
+    if proof.leaf_count != root.leaf_count || proof.index >= proof.leaf_count {
+        return false;
+    }
+    let Some(mut hash) = unhex(&proof.leaf) else {
+        return false;
+    };
+    let mut siblings = proof.siblings.iter();
+    let (mut index, mut width) = (proof.index, proof.leaf_count);
+    while width > 1 {
+        // The last node of an odd level is promoted without a sibling.
+        if index != width - 1 || width % 2 == 0 {
+            let Some(sibling) = siblings.next().and_then(|sibling| unhex(sibling)) else {
+                return false;
+            };
+            hash = if index % 2 == 0 {
+                node_hash(&hash, &sibling)
+            } else {
+                node_hash(&sibling, &hash)
+            };
+        }
+        index /= 2;
+        width = width.div_ceil(2);
+    }
+    siblings.next().is_none() && hex(&hash) == root.hash
+}
+
+impl ArchiveStore {
+    /// Computes the root of the Merkle tree over every archived record of [ArchiveRecordType],
+    /// in both tiers. Publish it, e.g. on chain, for light clients to verify the proofs of
+    /// [ArchiveStore::prove] against. This reads the whole record type.
+    pub async fn merkle_root(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<MerkleRoot, ArchiveError> {
+        let leaves = self.merkle_leaves(rec_type).await?;
+        let leaf_count = leaves.len();
+        let levels = levels(leaves.into_iter().map(|(_, leaf)| leaf).collect());
+        let hash = match levels.last() {
+            Some(level) => level[0],
+            None => Sha256::digest([]).into(),
+        };
+        Ok(MerkleRoot {
+            hash: hex(&hash),
+            leaf_count,
+        })
+    }
+
+    /// Generates the proof that the record of [ArchiveRecordType] with the given id is included
+    /// in the tree with the root [ArchiveStore::merkle_root] computes for the current state of
+    /// the archive. Fails with [ArchiveError::NotFound] if there is no such record. This reads
+    /// the whole record type.
+    pub async fn prove(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<InclusionProof, ArchiveError> {
+        let leaves = self.merkle_leaves(rec_type.clone()).await?;
+        let leaf_count = leaves.len();
+        let Some(index) = leaves.iter().position(|(leaf_id, _)| leaf_id == id) else {
+            return Err(ArchiveError::NotFound(anyhow!(
+                "No {:?} record with id {}",
+                rec_type,
+                id
+            )));
+        };
+        let leaf = leaves[index].1;
+        let levels = levels(leaves.into_iter().map(|(_, leaf)| leaf).collect());
+        let mut siblings = Vec::new();
+        let mut position = index;
+        for level in &levels[..levels.len() - 1] {
+            if let Some(sibling) = level.get(position ^ 1) {
+                siblings.push(hex(sibling));
+            }
+            position /= 2;
+        }
+        Ok(InclusionProof {
+            id: id.to_string(),
+            leaf: hex(&leaf),
+            index,
+            leaf_count,
+            siblings,
+        })
+    }
+
+    /// The ids and leaf hashes of every record of [ArchiveRecordType], ordered by id.
+    async fn merkle_leaves(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Vec<(String, Hash)>, ArchiveError> {
+        let mut records = self.find_all_stream::<Document>(rec_type).await?;
+        let mut leaves = Vec::new();
+        while let Some(record) = records.try_next().await? {
+            let id = filter::id_to_string(record.get("_id").unwrap_or(&Bson::Null));
+            let leaf = leaf_hash(&id, &record);
+            leaves.push((id, leaf));
+        }
+        leaves.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(leaves)
+    }
+}
+
+/// Every level of the tree over the leaves, from the leaves up to the root. Empty for no leaves.
+fn levels(leaves: Vec<Hash>) -> Vec<Vec<Hash>> {
+    if leaves.is_empty() {
+        return Vec::new();
+    }
+    let mut levels = vec![leaves];
+    while levels[levels.len() - 1].len() > 1 {
+        let next = levels[levels.len() - 1]
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node_hash(left, right),
+                [promoted] => *promoted,
+                _ => unreachable!(),
+            })
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+/// Hashes a record's id, length prefixed, and canonical form into its leaf.
+fn leaf_hash(id: &str, record: &Document) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update((id.len() as u64).to_be_bytes());
+    hasher.update(id.as_bytes());
+    hasher.update(checksum::canonical_bytes(record));
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+fn hex(hash: &Hash) -> String {
+    hash.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(hex: &str) -> Option<Hash> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut hash = [0; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(hash)
+}
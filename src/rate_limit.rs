@@ -0,0 +1,167 @@
+/// Caps on the load a store puts on its backend, so that bulk jobs such as reindexing or
+/// backfills can share a cluster with a live node without starving it. Every attempt of an
+/// operation, including each retry, waits for the store's [RateLimits] before it reaches the
+/// backend: for a free slot when too many operations are in flight, and for a token bucket to
+/// refill when operations or bytes have been used faster than allowed.
+use crate::ArchiveStore;
+use anyhow::Result;
+use log::debug;
+use serde_derive::Deserialize;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+tokio::task_local! {
+    /// Set while an operation is running under the limits, so that operations it performs in
+    /// turn aren't limited again, which could deadlock on the in-flight slots it holds.
+    static LIMITED: ();
+}
+
+/// Limits on the operations a store (and its clones and namespaces) performs against its
+/// backend, set with [crate::ArchiveStoreBuilder::rate_limits]. Every limit is optional, and
+/// none are set by default. Rates allow bursts of up to a second's worth.
+///
+/// Time spent waiting counts towards an operation's duration as reported to
+/// [crate::ArchiveMetrics]. Streams returned by operations such as
+/// [crate::ArchiveStore::find_all_stream] are limited when opened, not as they are read.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimits {
+    /// Most operations running against the backend at once. `LASR_ARCHIVE_MAX_IN_FLIGHT`
+    pub max_in_flight: Option<usize>,
+    /// Most operations started per second. `LASR_ARCHIVE_OPS_PER_SEC`
+    pub ops_per_sec: Option<u32>,
+    /// Most bytes of encoded records written per second. Writes are charged once they complete,
+    /// so a large write may go over the limit, and the operations after it wait until it's paid
+    /// off. `LASR_ARCHIVE_BYTES_PER_SEC`
+    pub bytes_per_sec: Option<u64>,
+}
+
+impl RateLimits {
+    /// Checks that the limits can be enforced, describing the problem if not.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.max_in_flight == Some(0) {
+            return Err("Invalid rate limits: max_in_flight must be at least 1".to_string());
+        }
+        if self.ops_per_sec == Some(0) {
+            return Err("Invalid rate limits: ops_per_sec must be at least 1".to_string());
+        }
+        if self.bytes_per_sec == Some(0) {
+            return Err("Invalid rate limits: bytes_per_sec must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Enforces a store's [RateLimits]. Clones share the same slots and buckets.
+#[derive(Debug, Clone)]
+pub(crate) struct RateLimiter {
+    pub(crate) limits: RateLimits,
+    in_flight: Option<Arc<Semaphore>>,
+    ops: Option<Arc<TokenBucket>>,
+    bytes: Option<Arc<TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limits: RateLimits) -> Self {
+        RateLimiter {
+            in_flight: limits
+                .max_in_flight
+                .map(|max| Arc::new(Semaphore::new(max))),
+            ops: limits
+                .ops_per_sec
+                .map(|rate| Arc::new(TokenBucket::new(rate.into()))),
+            bytes: limits
+                .bytes_per_sec
+                .map(|rate| Arc::new(TokenBucket::new(rate as f64))),
+            limits,
+        }
+    }
+
+    /// Runs an attempt of an operation once the limits allow it, charging the bytes it wrote.
+    pub(crate) async fn run<R, F>(&self, op: &str, attempt: F) -> Result<(R, usize)>
+    where
+        F: Future<Output = Result<(R, usize)>>,
+    {
+        if LIMITED.try_with(|_| ()).is_ok() {
+            return attempt.await;
+        }
+        let started = Instant::now();
+        let mut wait = Duration::ZERO;
+        if let Some(ops) = &self.ops {
+            wait = wait.max(ops.take(1.0));
+        }
+        if let Some(bytes) = &self.bytes {
+            wait = wait.max(bytes.take(0.0));
+        }
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        // Waiting for a slot last leaves the slots free while operations wait for the rates.
+        let _slot = match &self.in_flight {
+            Some(in_flight) => Some(in_flight.acquire().await?),
+            None => None,
+        };
+        let waited = started.elapsed();
+        if waited >= Duration::from_millis(1) {
+            debug!("{} waited {:?} for the store's rate limits", op, waited);
+        }
+
+        let result = LIMITED.scope((), attempt).await;
+        if let (Some(bytes), Ok((_, written))) = (&self.bytes, &result) {
+            bytes.take(*written as f64);
+        }
+        result
+    }
+}
+
+/// A token bucket refilled at a steady rate, holding at most a second's worth of tokens.
+#[derive(Debug)]
+struct TokenBucket {
+    /// Tokens added per second
+    rate: f64,
+    /// The tokens in the bucket, negative when more were taken than it held, and when it was
+    /// last refilled
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    fn new(rate: f64) -> Self {
+        TokenBucket {
+            rate,
+            state: Mutex::new((rate, Instant::now())),
+        }
+    }
+
+    /// Takes `tokens` from the bucket, returning how long to wait until they've been refilled.
+    /// Callers are queued in the order they take tokens, as each one's wait covers the tokens
+    /// taken before it.
+    fn take(&self, tokens: f64) -> Duration {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let (available, refilled) = &mut *state;
+        let now = Instant::now();
+        *available =
+            (*available + now.duration_since(*refilled).as_secs_f64() * self.rate).min(self.rate);
+        *refilled = now;
+        *available -= tokens;
+        if *available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-*available / self.rate)
+        }
+    }
+}
+
+impl ArchiveStore {
+    /// Runs an attempt of an operation under the store's [RateLimits], if it has any.
+    pub(crate) async fn limited<R, F>(&self, op: &str, attempt: F) -> Result<(R, usize)>
+    where
+        F: Future<Output = Result<(R, usize)>>,
+    {
+        match &self.inner.rate_limiter {
+            Some(limiter) => limiter.run(op, attempt).await,
+            None => attempt.await,
+        }
+    }
+}
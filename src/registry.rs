@@ -0,0 +1,72 @@
+/// A registry of archive datastores that share a single MongoDB client. A service archiving to
+/// several logical datastores (e.g. one per chain or per environment) would otherwise build a
+/// separate [crate::ArchiveStore] per datastore, each of which creates its own client and so its
+/// own connection pool, handshakes and monitoring threads. Stores vended by the registry instead
+/// all clone one client, so they share one connection pool to the cluster. Requires the `mongodb`
+/// feature.
+use crate::mongodb_archive::MongoDBBackend;
+use crate::{uri, ArchiveBackends, ArchiveError, ArchiveStore, ArchiveStoreBuilder, Credentials};
+use core::fmt;
+use mongodb::Client;
+
+#[derive(Clone)]
+pub struct ArchiveRegistry {
+    /// The backend-specific URI the shared client was created from
+    uri: String,
+    /// The client shared by every store vended from this registry
+    client: Client,
+}
+
+impl ArchiveRegistry {
+    /// Creates a registry connected to the MongoDB deployment at `uri`.
+    pub async fn connect(uri: String) -> Result<Self, ArchiveError> {
+        let client = MongoDBBackend::connect(&uri, None).await?;
+        Ok(ArchiveRegistry { uri, client })
+    }
+
+    /// Creates a registry connected to the MongoDB deployment at `uri`, authenticating with
+    /// `credentials` in place of any in the URI.
+    pub async fn connect_with_credentials(
+        uri: String,
+        credentials: Credentials,
+    ) -> Result<Self, ArchiveError> {
+        let client = MongoDBBackend::connect(&uri, Some(&credentials)).await?;
+        Ok(ArchiveRegistry { uri, client })
+    }
+
+    /// Returns a ready to use [ArchiveStore] for the named datastore. The store shares this
+    /// registry's client, so calling this for many datastores does not open new connections.
+    pub fn store(&self, datastore: &str) -> Result<ArchiveStore, ArchiveError> {
+        ArchiveStoreBuilder::default()
+            .uri(self.uri.clone())
+            .backend(ArchiveBackends::MongoDB)
+            .datastore(datastore.to_string())
+            .client(self.client.clone())
+            .build()
+            .map_err(ArchiveError::invalid_input)
+    }
+
+    /// Returns the client shared by the stores vended from this registry.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+}
+
+impl fmt::Debug for ArchiveRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ArchiveRegistry")
+            .field("uri", &uri::redact(&self.uri))
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for ArchiveRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "URI: {}, Backend: {}",
+            uri::redact(&self.uri),
+            ArchiveBackends::MongoDB
+        )
+    }
+}
@@ -0,0 +1,139 @@
+/// Statistics of an archive for capacity planning: how many records of each type are stored, the
+/// storage they use and the time they span, across the store and each of its namespaces, see
+/// [ArchiveStore::archive_stats] and [ArchiveStore::stats_all].
+use crate::envelope::ARCHIVED_AT_FIELD;
+use crate::{AggregationSpec, ArchiveError, ArchiveRecordType, ArchiveStore};
+use bson::{Bson, DateTime, Document};
+use std::collections::BTreeMap;
+
+/// Statistics of the records of a record type, or of several, as returned by
+/// [ArchiveStore::archive_stats]. All values are zero or `None` when nothing is archived.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveStats {
+    /// Estimated number of records, including tombstones
+    pub document_count: u64,
+    /// Bytes of storage allocated to the records
+    pub storage_bytes: u64,
+    /// When the first record was archived. Records archived before their provenance was stored
+    /// alongside them aren't taken into account.
+    pub oldest_archived_at: Option<DateTime>,
+    /// When the last record was archived
+    pub newest_archived_at: Option<DateTime>,
+    /// The statistics of each of the store's namespaces, by name, which the values above include
+    pub namespaces: BTreeMap<String, ArchiveStats>,
+}
+
+impl ArchiveStats {
+    /// Average size of a record in bytes.
+    pub fn avg_doc_bytes(&self) -> u64 {
+        self.storage_bytes
+            .checked_div(self.document_count)
+            .unwrap_or_default()
+    }
+
+    /// Adds the statistics of other records, along with their namespaces'.
+    fn add(&mut self, other: &ArchiveStats) {
+        self.document_count += other.document_count;
+        self.storage_bytes += other.storage_bytes;
+        self.oldest_archived_at = match (self.oldest_archived_at, other.oldest_archived_at) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.newest_archived_at = match (self.newest_archived_at, other.newest_archived_at) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+        for (name, stats) in &other.namespaces {
+            self.namespaces.entry(name.clone()).or_default().add(stats);
+        }
+    }
+}
+
+/// Statistics of every record type of an archive, as returned by [ArchiveStore::stats_all].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveStatsReport {
+    /// The statistics of each record type
+    pub record_types: Vec<(ArchiveRecordType, ArchiveStats)>,
+    /// The statistics of every record type together
+    pub total: ArchiveStats,
+}
+
+impl ArchiveStore {
+    /// Returns statistics for the records of [ArchiveRecordType], like [ArchiveStore::stats]
+    /// along with when the oldest and newest were archived, totalled over the store's own
+    /// records and those of each of its namespaces, with a breakdown per namespace. Called on a
+    /// namespace's handle, only covers that namespace. Finding the oldest and newest records
+    /// reads every record on backends other than MongoDB, as by [ArchiveStore::aggregate].
+    pub async fn archive_stats(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<ArchiveStats, ArchiveError> {
+        let mut stats = self.own_stats(&rec_type).await?;
+        if self.namespace_name().is_some() {
+            return Ok(stats);
+        }
+        for name in self.namespaces().await? {
+            let namespace = self.namespace(&name)?.own_stats(&rec_type).await?;
+            stats.add(&namespace);
+            stats.namespaces.insert(name, namespace);
+        }
+        Ok(stats)
+    }
+
+    /// Returns the [ArchiveStore::archive_stats] of every record type, and their total, e.g. to
+    /// track an archive's growth. Covers the built-in record types, and the custom record types
+    /// given a schema version or retention policy when the store was built.
+    pub async fn stats_all(&self) -> Result<ArchiveStatsReport, ArchiveError> {
+        let mut rec_types = vec![
+            ArchiveRecordType::Account,
+            ArchiveRecordType::TransactionBatch,
+            ArchiveRecordType::Block,
+            ArchiveRecordType::Receipt,
+        ];
+        let configured = self.inner.schema_versions.keys();
+        let mut custom: Vec<&ArchiveRecordType> = configured
+            .chain(self.inner.retention.keys())
+            .filter(|rec_type| matches!(rec_type, ArchiveRecordType::Custom(_)))
+            .collect();
+        custom.sort_by_key(|rec_type| format!("{:?}", rec_type));
+        custom.dedup();
+        rec_types.extend(custom.into_iter().cloned());
+
+        let mut report = ArchiveStatsReport::default();
+        for rec_type in rec_types {
+            let stats = self.archive_stats(rec_type.clone()).await?;
+            report.total.add(&stats);
+            report.record_types.push((rec_type, stats));
+        }
+        Ok(report)
+    }
+
+    /// The statistics of this handle's own records of [ArchiveRecordType].
+    async fn own_stats(&self, rec_type: &ArchiveRecordType) -> Result<ArchiveStats, ArchiveError> {
+        let stats = self.stats(rec_type.clone()).await?;
+        let spec = AggregationSpec::new()
+            .min("oldest", ARCHIVED_AT_FIELD)
+            .max("newest", ARCHIVED_AT_FIELD);
+        let times = self
+            .with_tombstones()
+            .aggregate(rec_type.clone(), spec)
+            .await?;
+        let time = |name: &str| {
+            times
+                .first()
+                .and_then(|doc: &Document| match doc.get(name) {
+                    Some(Bson::Int64(millis)) => Some(DateTime::from_millis(*millis)),
+                    Some(Bson::Int32(millis)) => Some(DateTime::from_millis((*millis).into())),
+                    Some(Bson::DateTime(time)) => Some(*time),
+                    _ => None,
+                })
+        };
+        Ok(ArchiveStats {
+            document_count: stats.document_count,
+            storage_bytes: stats.storage_bytes,
+            oldest_archived_at: time("oldest"),
+            newest_archived_at: time("newest"),
+            namespaces: BTreeMap::new(),
+        })
+    }
+}
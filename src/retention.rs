@@ -0,0 +1,222 @@
+/// Retention policies. A store can be configured to keep records of each record type for a
+/// limited time, or only the most recent ones, with [crate::ArchiveStoreBuilder::retention].
+/// Records past their retention are deleted, or moved to the store's cold tier, by
+/// [ArchiveStore::prune] or in the background every [crate::ArchiveStoreBuilder::prune_interval].
+/// Pruning also purges the record types' logically removed records, see [ArchiveStore::compact],
+/// which reclaims the space expired records held.
+use crate::{filter, ArchiveError, ArchiveRecordType, ArchiveStore, Filter, Labels};
+use bson::{DateTime, Document};
+use log::{debug, warn};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::time::MissedTickBehavior;
+
+/// What happens to records once they are past their retention.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExpiryAction {
+    /// They are deleted.
+    #[default]
+    Delete,
+    /// They are moved to the store's cold tier, see [crate::TieringPolicy]. The store must be
+    /// tiered.
+    MoveToColdTier,
+}
+
+/// How long records of a record type are kept, e.g. a [RetentionPolicy::max_age] of 90 days to
+/// keep 90 days of transaction batches. When both limits are set, records exceeding either are
+/// expired. A record's age is taken from when it was archived, and records whose archive time is
+/// unknown, such as those written before it was recorded, are always kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// How long records are kept after they are archived
+    pub max_age: Option<Duration>,
+    /// How many of the most recently archived records are kept. Only records in the store's own
+    /// tier are counted.
+    pub max_count: Option<u64>,
+    /// What happens to expired records. Defaults to [ExpiryAction::Delete].
+    pub action: ExpiryAction,
+    /// How long tombstoned records are kept for audit after they were tombstoned, see
+    /// [crate::LifecycleState::Tombstoned]. They are kept until marked pending purge when unset.
+    pub purge_tombstones_after: Option<Duration>,
+}
+
+impl RetentionPolicy {
+    /// Deletes records once older than `max_age`.
+    pub fn max_age(max_age: Duration) -> Self {
+        RetentionPolicy {
+            max_age: Some(max_age),
+            ..Default::default()
+        }
+    }
+
+    /// Deletes all but the `max_count` most recently archived records.
+    pub fn max_count(max_count: u64) -> Self {
+        RetentionPolicy {
+            max_count: Some(max_count),
+            ..Default::default()
+        }
+    }
+
+    /// Purges tombstoned records once `window` has passed since they were tombstoned, keeping
+    /// live records indefinitely.
+    pub fn purge_tombstones_after(window: Duration) -> Self {
+        RetentionPolicy {
+            purge_tombstones_after: Some(window),
+            ..Default::default()
+        }
+    }
+
+    /// Checks that the settings can be used together, describing the problem if not.
+    pub(crate) fn validate(&self, tiered: bool) -> Result<(), String> {
+        if self.max_age.is_none()
+            && self.max_count.is_none()
+            && self.purge_tombstones_after.is_none()
+        {
+            return Err(
+                "Invalid retention policy: must set max_age, max_count or purge_tombstones_after"
+                    .to_string(),
+            );
+        }
+        if self.max_age.is_some_and(|age| age.is_zero()) {
+            return Err("Invalid retention policy: max_age must be greater than zero".to_string());
+        }
+        if self.action == ExpiryAction::MoveToColdTier && !tiered {
+            return Err(
+                "Invalid retention policy: moving expired records needs a tiering policy"
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl ArchiveStore {
+    /// Deletes, or moves to the cold tier, the records past the retention configured for their
+    /// record type, returning the number expired. Records expired by age are deleted from both
+    /// tiers. Limiting the number of records reads every record in the store's own tier, so is
+    /// best run periodically rather than after every write. Each record is expired on its own, so
+    /// if this fails part way it can simply be run again. The records of these record types that
+    /// are due to be purged are deleted too, as by [ArchiveStore::compact], though the space they
+    /// held isn't reclaimed.
+    pub async fn prune(&self) -> Result<u64, ArchiveError> {
+        let mut expired = 0;
+        // Each record type's work is boxed, as it nests store operations several deep.
+        for (rec_type, policy) in &self.inner.retention {
+            expired += Box::pin(self.prune_type(rec_type, policy)).await?;
+        }
+        Ok(expired)
+    }
+
+    /// Expires the records of a single record type according to its policy.
+    async fn prune_type(
+        &self,
+        rec_type: &ArchiveRecordType,
+        policy: &RetentionPolicy,
+    ) -> Result<u64, ArchiveError> {
+        let mut expired = 0;
+        if let Some(max_age) = policy.max_age {
+            let cutoff = SystemTime::now()
+                .checked_sub(max_age)
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            let aged = Filter::archived_before(DateTime::from_system_time(cutoff));
+            expired += match policy.action {
+                ExpiryAction::Delete => Box::pin(self.delete_where(rec_type.clone(), aged)).await?,
+                ExpiryAction::MoveToColdTier => {
+                    let ids = self
+                        .query_envelopes_untiered::<Document>(rec_type.clone(), aged)
+                        .await?
+                        .into_iter()
+                        .filter_map(|envelope| envelope.record.get("_id").cloned())
+                        .map(|id| filter::id_to_string(&id))
+                        .collect();
+                    Box::pin(self.expire(rec_type, policy.action, ids)).await?
+                }
+            };
+        }
+
+        if let Some(max_count) = policy.max_count {
+            let mut archived: Vec<(DateTime, String)> = self
+                .query_envelopes_untiered::<Document>(rec_type.clone(), Filter::All)
+                .await?
+                .into_iter()
+                .filter_map(|envelope| {
+                    let id = envelope.record.get("_id")?;
+                    Some((envelope.archived_at?, filter::id_to_string(id)))
+                })
+                .collect();
+            // Newest first, keeping the first `max_count`.
+            archived.sort_unstable_by(|a, b| b.cmp(a));
+            let ids = archived
+                .into_iter()
+                .skip(usize::try_from(max_count).unwrap_or(usize::MAX))
+                .map(|(_, id)| id)
+                .collect();
+            expired += Box::pin(self.expire(rec_type, policy.action, ids)).await?;
+        }
+
+        expired += Box::pin(self.purge(rec_type.clone())).await?;
+        Ok(expired)
+    }
+
+    /// Deletes, or moves to the cold tier, the records of [ArchiveRecordType] with the given ids
+    /// from this store's own tier, returning the number expired.
+    async fn expire(
+        &self,
+        rec_type: &ArchiveRecordType,
+        action: ExpiryAction,
+        ids: Vec<String>,
+    ) -> Result<u64, ArchiveError> {
+        let mut expired = 0;
+        for id in ids {
+            let done = match (action, self.cold_tier()) {
+                (ExpiryAction::MoveToColdTier, Some(cold)) => {
+                    self.move_to_cold_tier(cold, rec_type, &id).await?
+                }
+                _ => self.delete_by_id_untiered(rec_type.clone(), &id).await?,
+            };
+            if done {
+                expired += 1;
+            }
+        }
+        Ok(expired)
+    }
+
+    /// Starts pruning records in the background every
+    /// [crate::ArchiveStoreBuilder::prune_interval], if one is configured and pruning isn't
+    /// already running. Like the spill drain, it ends once the store and all its clones are
+    /// dropped.
+    pub(crate) fn start_pruning(&self) {
+        let interval = match self.inner.prune_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        if self.inner.pruning.set(()).is_err() {
+            return;
+        }
+
+        let inner = Arc::downgrade(&self.inner);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let store = match inner.upgrade() {
+                    Some(inner) => ArchiveStore {
+                        inner,
+                        labels: Labels::default(),
+                        tags: Vec::new(),
+                        include_tombstones: false,
+                        #[cfg(feature = "mongodb")]
+                        mongodb_override: None,
+                    },
+                    None => break,
+                };
+                match store.prune().await {
+                    Ok(0) => {}
+                    Ok(expired) => debug!("Pruned {} expired records", expired),
+                    Err(e) => warn!("Failed to prune expired records, retrying later: {:#}", e),
+                }
+            }
+        });
+    }
+}
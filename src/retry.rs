@@ -0,0 +1,126 @@
+/// Retrying of archive operations that fail with transient errors, such as a dropped connection
+/// or a replica set electing a new primary, with exponential backoff between attempts.
+use crate::{ArchiveError, ArchiveErrorKind, ArchiveStore};
+use futures::TryFutureExt;
+use log::warn;
+use std::future::Future;
+use std::time::Duration;
+
+/// When and how often failed operations are retried, set with
+/// [crate::ArchiveStoreBuilder::retry_policy]. An operation is retried while it fails with one of
+/// the [RetryPolicy::retry_on] kinds of error and attempts remain. The first retry waits
+/// [RetryPolicy::initial_backoff], with the wait multiplied by [RetryPolicy::multiplier] for each
+/// retry after it, up to [RetryPolicy::max_backoff].
+///
+/// A write whose response was lost may have been applied even though it failed, so retried writes
+/// can be applied twice. Records that carry their own `_id` fail the retry with
+/// [ArchiveError::DuplicateKey] instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Most times an operation is attempted, including the first. 1 disables retries.
+    pub max_attempts: u32,
+    /// How long to wait before the first retry
+    pub initial_backoff: Duration,
+    /// Longest wait between attempts
+    pub max_backoff: Duration,
+    /// Factor the wait grows by with each retry
+    pub multiplier: f64,
+    /// Whether each wait is picked at random between zero and the backoff ("full jitter"), so
+    /// that many clients failing together don't all retry at the same moment
+    pub jitter: bool,
+    /// The kinds of error that are retried. Others fail the operation straight away.
+    pub retry_on: Vec<ArchiveErrorKind>,
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts, backing off from 100ms to at most 5s with jitter, retrying connection
+    /// failures and unavailable backends, i.e. the errors [ArchiveError::is_retryable] reports.
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2.0,
+            jitter: true,
+            retry_on: vec![
+                ArchiveErrorKind::ConnectionFailed,
+                ArchiveErrorKind::BackendUnavailable,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Checks that the settings can be used together, describing the problem if not.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.max_attempts == 0 {
+            return Err("Invalid retry policy: max_attempts must be at least 1".to_string());
+        }
+        if !self.multiplier.is_finite() || self.multiplier < 1.0 {
+            return Err(format!(
+                "Invalid retry policy: multiplier must be at least 1.0, got {}",
+                self.multiplier
+            ));
+        }
+        if self.initial_backoff > self.max_backoff {
+            return Err(
+                "Invalid retry policy: initial_backoff must not exceed max_backoff".to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    /// How long to wait before the given retry, counting from zero.
+    fn backoff(&self, retry: u32) -> Duration {
+        let backoff = (self.initial_backoff.as_secs_f64() * self.multiplier.powi(retry as i32))
+            .min(self.max_backoff.as_secs_f64());
+        if self.jitter {
+            Duration::from_secs_f64(rand::random::<f64>() * backoff)
+        } else {
+            Duration::from_secs_f64(backoff)
+        }
+    }
+
+    /// Runs the attempts `attempt` creates until one succeeds, fails with an error that isn't
+    /// retried, or the attempts run out. `op` names the operation in logs.
+    pub(crate) async fn run<T, F, Fut>(&self, op: &str, attempt: F) -> Result<T, ArchiveError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let mut retries = 0;
+        loop {
+            match attempt().map_err(ArchiveError::from).await {
+                Err(e) if retries + 1 < self.max_attempts && self.retry_on.contains(&e.kind()) => {
+                    let wait = self.backoff(retries);
+                    retries += 1;
+                    warn!(
+                        "{} failed, retrying in {:?} (attempt {} of {}): {:#}",
+                        op,
+                        wait,
+                        retries + 1,
+                        self.max_attempts,
+                        e
+                    );
+                    tokio::time::sleep(wait).await;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl ArchiveStore {
+    /// Runs the attempts `attempt` creates under the store's [RetryPolicy], or only the first
+    /// when it has none.
+    pub(crate) async fn retrying<T, F, Fut>(&self, op: &str, attempt: F) -> Result<T, ArchiveError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        match &self.inner.retry_policy {
+            Some(policy) => policy.run(op, attempt).await,
+            None => attempt().await.map_err(ArchiveError::from),
+        }
+    }
+}
@@ -0,0 +1,820 @@
+/// An implementation of an archive datastore that uses an embedded RocksDB database as its
+/// backend, enabled with the `rocksdb` feature. It suits archive nodes that need more write
+/// throughput than a round trip to a remote database allows. The URI names the directory
+/// databases are kept in, e.g. `rocksdb:///var/lib/lasr/archive`, and each datastore is a separate
+/// database within it. Records of each [ArchiveRecordType] are kept in their own column family,
+/// named after the [ACCOUNT_CF] and [TRANSACTION_CF] constants or the custom record type's name,
+/// keyed by their id.
+///
+/// Records are given ids from a sequence that increases with every record written, persisted
+/// alongside the records, so ids sort in the order records were archived. Each record is stored
+/// as BSON, after the time it was archived. Writes of several records, including
+/// [ArchiveBackend::create_atomic], are applied as a single atomic batch.
+///
+/// RocksDB is synchronous, so operations run on Tokio's blocking thread pool. A database can only
+/// be opened by one process, and by one store within it, at a time. Queries, counts and
+/// statistics scan the records they need and filter or tally them here. Operations that take
+/// MongoDB specific arguments fail with [Unsupported]. Records are never chunked.
+use crate::filter::lookup;
+use crate::stats::{aggregate, tally};
+use crate::{
+    AggregationSpec, ArchiveBackend, ArchiveCollectionStats, ArchiveError, ArchiveEvent,
+    ArchiveRecordType, Filter, GroupBy, IndexSpec, MergeMode, Page, PageRequest, Unsupported,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bson::{Bson, Document};
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use log::debug;
+use rocksdb::{
+    BoundColumnFamily, DBWithThreadMode, Direction, IteratorMode, MultiThreaded, Options,
+    WriteBatch,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::borrow::Borrow;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+/// Column family storing account data
+const ACCOUNT_CF: &str = "accounts";
+/// Column family storing transaction data
+const TRANSACTION_CF: &str = "transaction_data";
+/// Column family storing blocks
+const BLOCK_CF: &str = "blocks";
+/// Column family storing transaction receipts
+const RECEIPT_CF: &str = "receipts";
+/// Key of the next sequence number, in the default column family
+const SEQUENCE_KEY: &[u8] = b"sequence";
+/// Number of records read at a time when streaming
+const STREAM_BATCH_SIZE: usize = 1000;
+/// Longest datastore name we allow
+const MAX_DATASTORE_LEN: usize = 255;
+
+/// An open database.
+struct Database {
+    /// Multi-threaded, so column families for custom record types can be created while the
+    /// database is shared.
+    db: DBWithThreadMode<MultiThreaded>,
+    /// The next sequence number to give a record. Held while writing, so sequence numbers are
+    /// persisted in the order they are given out.
+    sequence: Mutex<u64>,
+}
+
+impl Database {
+    /// Column family storing records of the given type. Column families for custom record types
+    /// are created the first time they are used.
+    fn column_family(&self, rec_type: &ArchiveRecordType) -> Result<Arc<BoundColumnFamily<'_>>> {
+        let name = match rec_type {
+            ArchiveRecordType::Account => ACCOUNT_CF,
+            ArchiveRecordType::TransactionBatch => TRANSACTION_CF,
+            ArchiveRecordType::Block => BLOCK_CF,
+            ArchiveRecordType::Receipt => RECEIPT_CF,
+            ArchiveRecordType::Custom(name) => name,
+        };
+        if let Some(cf) = self.db.cf_handle(name) {
+            return Ok(cf);
+        }
+        if let ArchiveRecordType::Custom(_) = rec_type {
+            // Another thread may have created it in the meantime.
+            if let Err(e) = self.db.create_cf(name, &Options::default()) {
+                if self.db.cf_handle(name).is_none() {
+                    return Err(e)
+                        .with_context(|| format!("Failed to create column family {}", name));
+                }
+            }
+        }
+        self.db
+            .cf_handle(name)
+            .with_context(|| format!("Missing column family {}", name))
+    }
+
+    /// Reads the records of the given type, in id order, starting after the given id and
+    /// stopping after `limit` records. Only records that `select` accepts are returned.
+    fn scan<F>(
+        &self,
+        rec_type: &ArchiveRecordType,
+        after: Option<&str>,
+        limit: Option<usize>,
+        mut select: F,
+    ) -> Result<Vec<(bson::DateTime, Document)>>
+    where
+        F: FnMut(&Document) -> bool,
+    {
+        let mode = match after {
+            Some(after) => IteratorMode::From(after.as_bytes(), Direction::Forward),
+            None => IteratorMode::Start,
+        };
+        let mut records = Vec::new();
+        for entry in self.db.iterator_cf(&self.column_family(rec_type)?, mode) {
+            if limit.is_some_and(|limit| records.len() >= limit) {
+                break;
+            }
+            let (key, value) = entry?;
+            if after.is_some_and(|after| *key == *after.as_bytes()) {
+                continue;
+            }
+            let (archived_at, doc) = from_value(&value)
+                .with_context(|| format!("Invalid record {}", String::from_utf8_lossy(&key)))?;
+            if select(&doc) {
+                records.push((archived_at, doc));
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// Serialises a record into the value stored for it: the time it was archived, as big endian
+/// milliseconds since the epoch, followed by the record as BSON.
+fn to_value(archived_at: bson::DateTime, doc: &Document) -> Result<Vec<u8>> {
+    let mut value = archived_at.timestamp_millis().to_be_bytes().to_vec();
+    doc.to_writer(&mut value)
+        .context("Failed to serialise record to BSON")?;
+    Ok(value)
+}
+
+/// Splits a stored value back into the time the record was archived and the record.
+fn from_value(value: &[u8]) -> Result<(bson::DateTime, Document)> {
+    let (millis, record) = value
+        .split_first_chunk::<8>()
+        .context("Stored value is truncated")?;
+    let archived_at = bson::DateTime::from_millis(i64::from_be_bytes(*millis));
+    Ok((archived_at, bson::from_slice(record)?))
+}
+
+/// Decodes the documents read from the database into records.
+fn decode<T: DeserializeOwned>(docs: Vec<(bson::DateTime, Document)>) -> Result<Vec<T>> {
+    docs.into_iter()
+        .map(|(_, doc)| bson::from_document(doc).context("Failed to deserialise record"))
+        .collect()
+}
+
+pub struct RocksDbBackend {
+    pub uri: String,
+    pub datastore: String,
+    /// The database, opened on first use
+    database: OnceCell<Arc<Database>>,
+}
+
+impl std::fmt::Debug for RocksDbBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RocksDbBackend")
+            .field("uri", &self.uri)
+            .field("datastore", &self.datastore)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RocksDbBackend {
+    /// Creates a backend for the datastore's database in the directory given by `uri`. The
+    /// database isn't opened until it is used.
+    pub fn new(uri: &str, datastore: &str) -> Self {
+        RocksDbBackend {
+            uri: uri.to_string(),
+            datastore: datastore.to_string(),
+            database: OnceCell::new(),
+        }
+    }
+
+    /// Checks that the URI is a `rocksdb://` URI naming a directory.
+    pub fn validate_uri(uri: &str) -> std::result::Result<(), String> {
+        match uri.strip_prefix("rocksdb://") {
+            Some("") => Err(format!("Invalid RocksDB URI '{}': no directory given", uri)),
+            Some(_) => Ok(()),
+            None => Err(format!(
+                "Invalid RocksDB URI '{}': must start with rocksdb://",
+                uri
+            )),
+        }
+    }
+
+    /// Checks that the datastore name can be used as the name of the database's directory:
+    /// ASCII letters, digits, underscores and hyphens.
+    pub fn validate_datastore(datastore: &str) -> std::result::Result<(), String> {
+        if datastore.is_empty() {
+            return Err("Datastore name must not be empty".to_string());
+        }
+        if datastore.len() > MAX_DATASTORE_LEN {
+            return Err(format!(
+                "Datastore name '{}' is longer than {} bytes",
+                datastore, MAX_DATASTORE_LEN
+            ));
+        }
+        if !datastore
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(format!(
+                "Datastore name '{}' must only contain letters, digits, underscores and hyphens",
+                datastore
+            ));
+        }
+        Ok(())
+    }
+
+    /// Directory of the datastore's database.
+    fn path(&self) -> PathBuf {
+        let dir = self.uri.strip_prefix("rocksdb://").unwrap_or(&self.uri);
+        PathBuf::from(dir).join(&self.datastore)
+    }
+
+    /// Opens the database the first time it is needed, creating it and its column families if
+    /// they don't exist.
+    async fn database(&self) -> Result<Arc<Database>> {
+        let database = self
+            .database
+            .get_or_try_init(|| async {
+                let path = self.path();
+                tokio::task::spawn_blocking(move || -> Result<Arc<Database>> {
+                    let mut options = Options::default();
+                    options.create_if_missing(true);
+                    options.create_missing_column_families(true);
+                    // Every existing column family must be opened, including those of custom
+                    // record types. A new database has none.
+                    let mut column_families =
+                        DBWithThreadMode::<MultiThreaded>::list_cf(&options, &path)
+                            .unwrap_or_default();
+                    for name in [ACCOUNT_CF, TRANSACTION_CF, BLOCK_CF, RECEIPT_CF] {
+                        if !column_families.iter().any(|cf| cf == name) {
+                            column_families.push(name.to_string());
+                        }
+                    }
+                    let db = DBWithThreadMode::open_cf(&options, &path, column_families)
+                        .with_context(|| {
+                            format!("Failed to open RocksDB database {}", path.display())
+                        })?;
+
+                    let sequence = match db.get(SEQUENCE_KEY)? {
+                        Some(bytes) => u64::from_be_bytes(
+                            bytes
+                                .as_slice()
+                                .try_into()
+                                .context("Invalid stored sequence number")?,
+                        ),
+                        None => 0,
+                    };
+                    Ok(Arc::new(Database {
+                        db,
+                        sequence: Mutex::new(sequence),
+                    }))
+                })
+                .await
+                .context("RocksDB task failed")?
+            })
+            .await?;
+        Ok(database.clone())
+    }
+
+    /// Runs `f` with the database on the blocking thread pool.
+    async fn run<R, F>(&self, f: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&Database) -> Result<R> + Send + 'static,
+    {
+        let database = self.database().await?;
+        tokio::task::spawn_blocking(move || f(&database))
+            .await
+            .context("RocksDB task failed")?
+    }
+
+    /// Writes records in a single atomic batch, returning their ids. A document's `_id` is used
+    /// as its id if it has one, otherwise it is given the next sequence number. The id is kept in
+    /// the record, so it doesn't need to be recovered from the key.
+    async fn write(&self, records: Vec<(ArchiveRecordType, Document)>) -> Result<Vec<String>> {
+        let ids = self
+            .run(move |database| {
+                let mut sequence = database
+                    .sequence
+                    .lock()
+                    .map_err(|_| anyhow::anyhow!("RocksDB sequence poisoned"))?;
+                let mut next = *sequence;
+                let archived_at = bson::DateTime::now();
+
+                let mut batch = WriteBatch::default();
+                let mut ids = Vec::with_capacity(records.len());
+                for (rec_type, mut doc) in records {
+                    let id = match doc.remove("_id") {
+                        Some(Bson::ObjectId(oid)) => oid.to_hex(),
+                        Some(Bson::String(id)) => id,
+                        Some(other) => other.to_string(),
+                        // Zero padded, so ids sort in sequence order.
+                        None => {
+                            next += 1;
+                            format!("{:020}", next)
+                        }
+                    };
+                    doc.insert("_id", id.clone());
+                    batch.put_cf(
+                        &database.column_family(&rec_type)?,
+                        id.as_bytes(),
+                        to_value(archived_at, &doc)?,
+                    );
+                    ids.push(id);
+                }
+                batch.put(SEQUENCE_KEY, next.to_be_bytes());
+
+                database.db.write(batch)?;
+                *sequence = next;
+                Ok(ids)
+            })
+            .await
+            .context("Failed to write records")?;
+
+        debug!("Wrote {} records to {}", ids.len(), self.path().display());
+        Ok(ids)
+    }
+
+    /// Reads the records of the given type, see [Database::scan].
+    async fn scan<F>(
+        &self,
+        rec_type: &ArchiveRecordType,
+        after: Option<String>,
+        limit: Option<usize>,
+        select: F,
+    ) -> Result<Vec<(bson::DateTime, Document)>>
+    where
+        F: FnMut(&Document) -> bool + Send + 'static,
+    {
+        let rec_type = rec_type.clone();
+        self.run(move |database| database.scan(&rec_type, after.as_deref(), limit, select))
+            .await
+            .context("Failed to read records")
+    }
+}
+
+#[async_trait]
+impl ArchiveBackend for RocksDbBackend {
+    /// Writes the record. A record given an `_id` that is already in use replaces the record
+    /// stored under it.
+    async fn create<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+        let mut ids = self.write(vec![(rec_type, doc)]).await?;
+        Ok(ids.remove(0))
+    }
+
+    /// Writes every record in one atomic batch, so the ordering setting has no effect.
+    async fn create_many<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        recs: Vec<T>,
+    ) -> Result<Vec<String>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let records = recs
+            .iter()
+            .map(|rec| {
+                let doc = bson::to_document(rec).context("Failed to serialise record to BSON")?;
+                Ok((rec_type.clone(), doc))
+            })
+            .collect::<Result<_>>()?;
+        Ok(self.write(records).await?)
+    }
+
+    /// Writes the records in one atomic batch, which is either applied in full or not at all.
+    async fn create_atomic(
+        &self,
+        records: Vec<(ArchiveRecordType, Document)>,
+    ) -> Result<Vec<String>, ArchiveError> {
+        Ok(self.write(records).await?)
+    }
+
+    async fn find_all<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        Ok(decode(self.scan(&rec_type, None, None, |_| true).await?)?)
+    }
+
+    /// Streams the records in batches of [STREAM_BATCH_SIZE], each read after the last id of the
+    /// previous batch, so only one batch is held in memory at a time.
+    async fn find_all_stream<'a, T: DeserializeOwned>(
+        &'a self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'a, Result<T, ArchiveError>>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin + 'a,
+    {
+        let batches = stream::try_unfold(Some(None), move |after: Option<Option<String>>| {
+            let rec_type = rec_type.clone();
+            async move {
+                let after = match after {
+                    Some(after) => after,
+                    None => return Ok::<_, anyhow::Error>(None),
+                };
+                let batch = self
+                    .scan(&rec_type, after, Some(STREAM_BATCH_SIZE), |_| true)
+                    .await?;
+                let next = match batch.len() {
+                    STREAM_BATCH_SIZE => batch
+                        .last()
+                        .and_then(|(_, doc)| doc.get_str("_id").ok())
+                        .map(|id| Some(id.to_string())),
+                    _ => None,
+                };
+                Ok(Some((batch, next)))
+            }
+        });
+
+        Ok(batches
+            .map_ok(|batch| stream::iter(batch.into_iter().map(Ok)))
+            .try_flatten()
+            .and_then(|(_, doc)| async move {
+                bson::from_document(doc).context("Failed to deserialise record")
+            })
+            .map_err(ArchiveError::from)
+            .boxed())
+    }
+
+    async fn find_page<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        request: &PageRequest,
+    ) -> Result<Page<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        // Read one more record than requested to tell whether there is a next page.
+        let mut records = self
+            .scan(
+                &rec_type,
+                request.after_token.clone(),
+                Some(request.limit + 1),
+                |_| true,
+            )
+            .await?;
+
+        let next_token = if records.len() > request.limit {
+            records.truncate(request.limit);
+            records
+                .last()
+                .and_then(|(_, doc)| doc.get_str("_id").ok())
+                .map(str::to_string)
+        } else {
+            None
+        };
+        Ok(Page {
+            items: decode(records)?,
+            next_token,
+        })
+    }
+
+    /// Query data store for the records of the given type matching a [Filter]. Every record of
+    /// the type is read and filtered here.
+    async fn query<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let filter = filter.clone();
+        Ok(decode(
+            self.scan(&rec_type, None, None, move |doc| filter.matches(doc))
+                .await?,
+        )?)
+    }
+
+    /// Records are stored as single values, so whole documents are returned.
+    async fn find_projected(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        _fields: &[String],
+    ) -> Result<Vec<Document>, ArchiveError> {
+        self.query::<Document>(rec_type, filter).await
+    }
+
+    async fn find_by_id<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<Option<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let key = id.to_string();
+        let value = self
+            .run(move |database| {
+                Ok(database
+                    .db
+                    .get_cf(&database.column_family(&rec_type)?, key.as_bytes())?)
+            })
+            .await
+            .context("Failed to read record")?;
+
+        match value {
+            Some(value) => {
+                let (_, doc) = from_value(&value)?;
+                Ok(Some(
+                    bson::from_document(doc).context("Failed to deserialise record")?,
+                ))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn delete_by_id(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<bool, ArchiveError> {
+        let key = id.to_string();
+        let deleted = self
+            .run(move |database| {
+                let cf = database.column_family(&rec_type)?;
+                if database.db.get_cf(&cf, key.as_bytes())?.is_none() {
+                    return Ok(false);
+                }
+                database.db.delete_cf(&cf, key.as_bytes())?;
+                Ok(true)
+            })
+            .await
+            .context("Failed to delete record")?;
+
+        debug!("Deleted {}", id);
+        Ok(deleted)
+    }
+
+    /// Every record of the type is read and filtered here, and the matching records deleted in a
+    /// single atomic batch.
+    async fn delete_where(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        let filter = filter.clone();
+        let deleted = self
+            .run(move |database| {
+                let records = database.scan(&rec_type, None, None, |doc| filter.matches(doc))?;
+                let cf = database.column_family(&rec_type)?;
+                let mut batch = WriteBatch::default();
+                for (_, doc) in &records {
+                    batch.delete_cf(&cf, doc.get_str("_id")?.as_bytes());
+                }
+                database.db.write(batch)?;
+                Ok(records.len() as u64)
+            })
+            .await
+            .context("Failed to delete records")?;
+
+        debug!("Deleted {} records from {}", deleted, self.path().display());
+        Ok(deleted)
+    }
+
+    /// Overwrites the record if it exists, keeping its id whatever `_id` the record holds. The
+    /// record's archived-at time is reset.
+    async fn update_by_id<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        rec: T,
+    ) -> Result<bool, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let mut doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+        doc.insert("_id", id);
+        let key = id.to_string();
+        let cf_type = rec_type.clone();
+        let exists = self
+            .run(move |database| {
+                Ok(database
+                    .db
+                    .get_cf(&database.column_family(&cf_type)?, key.as_bytes())?
+                    .is_some())
+            })
+            .await
+            .context("Failed to read record")?;
+        if !exists {
+            return Ok(false);
+        }
+        self.write(vec![(rec_type, doc)]).await?;
+        Ok(true)
+    }
+
+    /// Every record of the type is read and filtered here until the first match, in id order,
+    /// which is overwritten. Concurrent upserts may both insert a record.
+    async fn upsert<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let mut doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+        let filter = filter.clone();
+        let existing = self
+            .scan(&rec_type, None, Some(1), move |doc| filter.matches(doc))
+            .await?;
+        if let Some((_, existing)) = existing.first() {
+            doc.insert(
+                "_id",
+                existing
+                    .get_str("_id")
+                    .context("Stored record has no _id")?,
+            );
+        }
+        let mut ids = self.write(vec![(rec_type, doc)]).await?;
+        Ok(ids.remove(0))
+    }
+
+    /// Returns a random sample of roughly `rate` (0.0 to 1.0) of the records of the given type,
+    /// selecting each record independently with probability `rate`.
+    async fn find_sampled<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rate: f64,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        Ok(decode(
+            self.scan(&rec_type, None, None, move |_| rand::random::<f64>() < rate)
+                .await?,
+        )?)
+    }
+
+    /// Records are never chunked, so there are never orphaned chunks.
+    async fn find_orphaned_chunks(
+        &self,
+        _rec_type: ArchiveRecordType,
+    ) -> Result<Vec<String>, ArchiveError> {
+        Ok(Vec::new())
+    }
+
+    /// Records are never chunked, so there are never orphaned chunks.
+    async fn cleanup_orphans(&self, _rec_type: ArchiveRecordType) -> Result<u64, ArchiveError> {
+        Ok(0)
+    }
+
+    /// Compacts the record type's whole column family, rewriting its files without the deleted
+    /// records' tombstones and the values they shadow.
+    async fn reclaim_space(&self, rec_type: ArchiveRecordType) -> Result<(), ArchiveError> {
+        self.run(move |database| {
+            let cf = database.column_family(&rec_type)?;
+            database
+                .db
+                .compact_range_cf(&cf, None::<&[u8]>, None::<&[u8]>);
+            Ok(())
+        })
+        .await
+        .context("Failed to compact column family")?;
+        debug!("Compacted {}", self.path().display());
+        Ok(())
+    }
+
+    /// Aggregation pipelines are MongoDB specific.
+    async fn merge_into(
+        &self,
+        _source: ArchiveRecordType,
+        _pipeline: Vec<Document>,
+        _target: ArchiveRecordType,
+        _mode: MergeMode,
+    ) -> Result<u64, ArchiveError> {
+        Err(Unsupported {
+            operation: "merge_into",
+            reason: "aggregation pipelines are only supported by the MongoDB backend".to_string(),
+        }
+        .into())
+    }
+
+    /// Changes are only reported by the store that made them.
+    async fn watch(
+        &self,
+        _rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'static, Result<ArchiveEvent, ArchiveError>>, ArchiveError> {
+        Err(Unsupported {
+            operation: "watch",
+            reason: "change streams are only supported by the MongoDB backend".to_string(),
+        }
+        .into())
+    }
+
+    /// [Filter::All] counts every record by scanning their keys. Otherwise every record of the
+    /// type is read and filtered here.
+    async fn count(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        if !matches!(filter, Filter::All) {
+            let filter = filter.clone();
+            let records = self
+                .scan(&rec_type, None, None, move |doc| filter.matches(doc))
+                .await?;
+            return Ok(records.len() as u64);
+        }
+
+        Ok(self
+            .run(move |database| {
+                let mut count = 0;
+                for entry in database
+                    .db
+                    .iterator_cf(&database.column_family(&rec_type)?, IteratorMode::Start)
+                {
+                    entry?;
+                    count += 1;
+                }
+                Ok(count)
+            })
+            .await
+            .context("Failed to count records")?)
+    }
+
+    /// Records of the type are read and filtered here until one matches.
+    async fn exists(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<bool, ArchiveError> {
+        let filter = filter.clone();
+        let records = self
+            .scan(&rec_type, None, Some(1), move |doc| filter.matches(doc))
+            .await?;
+        Ok(!records.is_empty())
+    }
+
+    /// Reads RocksDB's estimates of the number of records and the space used by their column
+    /// family, both on disk and in memory.
+    async fn stats(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<ArchiveCollectionStats, ArchiveError> {
+        let (document_count, storage_bytes) = self
+            .run(move |database| {
+                let cf = database.column_family(&rec_type)?;
+                let property = |name: &str| -> Result<u64> {
+                    Ok(database.db.property_int_value_cf(&cf, name)?.unwrap_or(0))
+                };
+                Ok((
+                    property("rocksdb.estimate-num-keys")?,
+                    property("rocksdb.total-sst-files-size")?
+                        + property("rocksdb.size-all-mem-tables")?,
+                ))
+            })
+            .await
+            .context("Failed to read column family statistics")?;
+
+        Ok(ArchiveCollectionStats {
+            document_count,
+            storage_bytes,
+            avg_doc_bytes: storage_bytes.checked_div(document_count).unwrap_or(0),
+        })
+    }
+
+    async fn group_count(
+        &self,
+        rec_type: ArchiveRecordType,
+        group_by: GroupBy,
+    ) -> Result<Vec<(Bson, u64)>, ArchiveError> {
+        let records = self.scan(&rec_type, None, None, |_| true).await?;
+        let keys = records
+            .into_iter()
+            .map(|(archived_at, doc)| match &group_by {
+                GroupBy::Field(field) => lookup(&doc, field).cloned().unwrap_or(Bson::Null),
+                GroupBy::ArchivedAt(granularity) => Bson::String(granularity.bucket(archived_at)),
+            });
+        Ok(tally(keys))
+    }
+
+    /// Aggregates the records matching the filter in process.
+    async fn aggregate(
+        &self,
+        rec_type: ArchiveRecordType,
+        spec: &AggregationSpec,
+    ) -> Result<Vec<Document>, ArchiveError> {
+        let docs = self.query::<Document>(rec_type, &spec.filter).await?;
+        Ok(aggregate(spec, docs))
+    }
+
+    /// There are no secondary indexes: queries scan every record, so non-unique indexes are
+    /// ignored.
+    async fn ensure_indexes(
+        &self,
+        _rec_type: ArchiveRecordType,
+        indexes: &[IndexSpec],
+    ) -> Result<(), ArchiveError> {
+        if indexes.iter().any(|index| index.unique) {
+            return Err(Unsupported {
+                operation: "ensure_indexes",
+                reason: "RocksDB has no secondary indexes".to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
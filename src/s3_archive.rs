@@ -0,0 +1,877 @@
+/// An implementation of an archive datastore that uses S3-compatible object storage, such as AWS
+/// S3, MinIO or Cloudflare R2, as its backend, enabled with the `s3` feature. Object storage is
+/// far cheaper than a database for cold archives that are written once and rarely read. The
+/// datastore is the bucket, and each record is a BSON object keyed `{record_type}/{id}`, where the
+/// record type is one of the [ACCOUNT_PREFIX] and [TRANSACTION_PREFIX] constants or the custom
+/// record type's name, or `_namespaces/{namespace}/{record_type}/{id}` for the records of a
+/// namespace, see [crate::ArchiveStore::namespace]. Ids are
+/// generated as ObjectIds, so listing a record type returns its records in the order they were
+/// archived.
+///
+/// The URI selects the endpoint: `s3://` for AWS itself, `s3://host:port` for another
+/// S3-compatible service over HTTPS, or `s3+http://host:port` over plain HTTP, e.g. for a local
+/// MinIO. Credentials can be given as `s3://access_key:secret_key@host`, and the region as a
+/// `region` query parameter, e.g. `s3://account.r2.cloudflarestorage.com?region=auto`. Anything
+/// not given is read from the usual `AWS_*` environment variables.
+///
+/// Object storage can only list and fetch objects, so queries, counts and statistics fetch the
+/// records they need and filter or tally them here, which is slow for large archives. Atomic
+/// batches are staged first, see [crate::ArchiveStore::create_atomic]. Other operations that need
+/// transactions or take MongoDB specific arguments fail with [Unsupported]. Records are never
+/// chunked.
+use crate::filter::lookup;
+use crate::namespace::NAMESPACE_DIR;
+use crate::staging::{StagedBatch, STAGING_DIR};
+use crate::stats::{aggregate, tally};
+use crate::{
+    AggregationSpec, ArchiveBackend, ArchiveCollectionStats, ArchiveError, ArchiveEvent,
+    ArchiveRecordType, Credentials, Filter, GroupBy, IndexSpec, MergeMode, Page, PageRequest,
+    Unsupported,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bson::{oid::ObjectId, Bson, Document};
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use log::debug;
+use object_store::aws::AmazonS3Builder;
+use object_store::{path::Path, ObjectMeta, ObjectStore};
+use serde::{de::DeserializeOwned, Serialize};
+use std::borrow::Borrow;
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+
+/// Key prefix of objects storing account data
+const ACCOUNT_PREFIX: &str = "accounts";
+/// Key prefix of objects storing transaction data
+const TRANSACTION_PREFIX: &str = "transaction_data";
+/// Key prefix of objects storing blocks
+const BLOCK_PREFIX: &str = "blocks";
+/// Key prefix of objects storing transaction receipts
+const RECEIPT_PREFIX: &str = "receipts";
+/// Number of objects written or fetched at once
+const CONCURRENCY: usize = 16;
+
+/// The parts of an S3 URI.
+#[derive(Debug, Default, PartialEq)]
+struct S3Uri {
+    /// Endpoint of an S3-compatible service, or `None` for AWS
+    endpoint: Option<String>,
+    access_key: Option<String>,
+    secret_key: Option<String>,
+    region: Option<String>,
+}
+
+impl S3Uri {
+    fn parse(uri: &str) -> std::result::Result<S3Uri, String> {
+        let invalid = |reason: &str| {
+            Err(format!(
+                "Invalid S3 URI '{}': {}",
+                crate::uri::redact(uri),
+                reason
+            ))
+        };
+        let (scheme, rest) = if let Some(rest) = uri.strip_prefix("s3://") {
+            ("https", rest)
+        } else if let Some(rest) = uri.strip_prefix("s3+http://") {
+            ("http", rest)
+        } else {
+            return invalid("must start with s3:// or s3+http://");
+        };
+
+        let (authority, query) = match rest.split_once('?') {
+            Some((authority, query)) => (authority, Some(query)),
+            None => (rest, None),
+        };
+        let authority = authority.trim_end_matches('/');
+        if authority.contains('/') {
+            return invalid("must not have a path, the bucket is the datastore");
+        }
+
+        let mut parsed = S3Uri::default();
+        let host = match authority.rsplit_once('@') {
+            Some((userinfo, host)) => match userinfo.split_once(':') {
+                Some((access_key, secret_key)) => {
+                    parsed.access_key = Some(access_key.to_string());
+                    parsed.secret_key = Some(secret_key.to_string());
+                    host
+                }
+                None => return invalid("credentials must be given as access_key:secret_key"),
+            },
+            None => authority,
+        };
+        match host {
+            "" if scheme == "http" => return invalid("s3+http:// needs a host"),
+            "" => {}
+            host => parsed.endpoint = Some(format!("{}://{}", scheme, host)),
+        }
+
+        for option in query.unwrap_or_default().split('&') {
+            match option.split_once('=') {
+                Some(("region", region)) => parsed.region = Some(region.to_string()),
+                None if option.is_empty() => {}
+                _ => return invalid(&format!("unknown option '{}'", option)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// Serialises a document into the object stored for it, returning its id and the object. The
+/// document's `_id` is used as the id if it has one, otherwise a new ObjectId is generated. The
+/// id is kept in the object, so it doesn't need to be recovered from the key.
+fn to_object(mut doc: Document) -> Result<(String, Vec<u8>)> {
+    let id = match doc.remove("_id") {
+        Some(Bson::ObjectId(oid)) => oid.to_hex(),
+        Some(Bson::String(id)) => id,
+        Some(other) => other.to_string(),
+        None => ObjectId::new().to_hex(),
+    };
+    doc.insert("_id", id.clone());
+    let object = bson::to_vec(&doc).context("Failed to serialise record to BSON")?;
+    Ok((id, object))
+}
+
+/// Decodes the documents fetched from the store into records.
+fn decode<T: DeserializeOwned>(docs: Vec<Document>) -> Result<Vec<T>> {
+    docs.into_iter()
+        .map(|doc| bson::from_document(doc).context("Failed to deserialise record"))
+        .collect()
+}
+
+pub struct S3Backend {
+    pub uri: String,
+    /// The bucket records are stored in
+    pub datastore: String,
+    /// Credentials used in place of any in the URI
+    pub credentials: Option<Credentials>,
+    /// The object store client, created on first use
+    pub store: OnceCell<Arc<dyn ObjectStore>>,
+    /// The namespace whose records are stored, if any
+    pub namespace: Option<String>,
+    /// Set once the batches left staged by a crash have been written, before the first atomic
+    /// batch
+    pub recovered: OnceCell<()>,
+}
+
+impl std::fmt::Debug for S3Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("S3Backend")
+            .field("uri", &crate::uri::redact(&self.uri))
+            .field("datastore", &self.datastore)
+            .field("credentials", &self.credentials)
+            .field("namespace", &self.namespace)
+            .finish_non_exhaustive()
+    }
+}
+
+impl S3Backend {
+    /// Creates a backend for the bucket named by the datastore at the service given by `uri`,
+    /// authenticating with `credentials` if given. Nothing is contacted until the backend is used.
+    pub fn new(uri: &str, datastore: &str, credentials: Option<Credentials>) -> Self {
+        S3Backend {
+            uri: uri.to_string(),
+            datastore: datastore.to_string(),
+            credentials,
+            store: OnceCell::new(),
+            namespace: None,
+            recovered: OnceCell::new(),
+        }
+    }
+
+    /// Checks that the URI is an `s3://` or `s3+http://` URI this backend understands.
+    pub fn validate_uri(uri: &str) -> std::result::Result<(), String> {
+        S3Uri::parse(uri).map(|_| ())
+    }
+
+    /// Checks that the datastore name is a valid bucket name: 3 to 63 lowercase ASCII letters,
+    /// digits, dots and hyphens, starting and ending with a letter or digit.
+    pub fn validate_datastore(datastore: &str) -> std::result::Result<(), String> {
+        if !(3..=63).contains(&datastore.len()) {
+            return Err(format!(
+                "Datastore name '{}' must be between 3 and 63 bytes long to be a bucket name",
+                datastore
+            ));
+        }
+        let alphanumeric = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+        if !datastore.starts_with(alphanumeric)
+            || !datastore.ends_with(alphanumeric)
+            || !datastore
+                .chars()
+                .all(|c| alphanumeric(c) || c == '.' || c == '-')
+        {
+            return Err(format!(
+                "Datastore name '{}' must only contain lowercase letters, digits, dots and \
+                hyphens, and start and end with a letter or digit",
+                datastore
+            ));
+        }
+        Ok(())
+    }
+
+    /// Creates the object store client the first time it is needed.
+    async fn store(&self) -> Result<&Arc<dyn ObjectStore>> {
+        self.store
+            .get_or_try_init(|| async {
+                let uri = S3Uri::parse(&self.uri).map_err(anyhow::Error::msg)?;
+                let mut builder = AmazonS3Builder::from_env().with_bucket_name(&self.datastore);
+                if let Some(endpoint) = uri.endpoint {
+                    // S3-compatible services rarely support bucket subdomains.
+                    builder = builder
+                        .with_allow_http(endpoint.starts_with("http://"))
+                        .with_endpoint(endpoint)
+                        .with_virtual_hosted_style_request(false);
+                }
+                if let (Some(access_key), Some(secret_key)) = (uri.access_key, uri.secret_key) {
+                    builder = builder
+                        .with_access_key_id(access_key)
+                        .with_secret_access_key(secret_key);
+                }
+                if let Some(credentials) = &self.credentials {
+                    builder = match credentials.resolve().await? {
+                        (Some(access_key), secret_key) => builder
+                            .with_access_key_id(access_key)
+                            .with_secret_access_key(secret_key),
+                        (None, token) => builder.with_token(token),
+                    };
+                }
+                if let Some(region) = uri.region {
+                    builder = builder.with_region(region);
+                }
+                let store = builder.build().context("Failed to create S3 client")?;
+                Ok::<_, anyhow::Error>(Arc::new(store) as Arc<dyn ObjectStore>)
+            })
+            .await
+    }
+
+    /// Key prefix of the objects storing records of the given type.
+    fn prefix(&self, rec_type: &ArchiveRecordType) -> Path {
+        self.within(match rec_type {
+            ArchiveRecordType::Account => ACCOUNT_PREFIX,
+            ArchiveRecordType::TransactionBatch => TRANSACTION_PREFIX,
+            ArchiveRecordType::Block => BLOCK_PREFIX,
+            ArchiveRecordType::Receipt => RECEIPT_PREFIX,
+            ArchiveRecordType::Custom(name) => name,
+        })
+    }
+
+    /// The key prefix `name` within the namespace's objects, if any.
+    fn within(&self, name: &str) -> Path {
+        match &self.namespace {
+            Some(namespace) => Path::from_iter([NAMESPACE_DIR, namespace, name]),
+            None => Path::from(name),
+        }
+    }
+
+    /// Key of the object storing a record.
+    fn key(&self, rec_type: &ArchiveRecordType, id: &str) -> Path {
+        self.prefix(rec_type).child(id)
+    }
+
+    /// Names of the namespaces with records in the bucket.
+    pub(crate) async fn namespaces(&self) -> Result<Vec<String>> {
+        let store = self.store().await?;
+        let listing = store
+            .list_with_delimiter(Some(&Path::from(NAMESPACE_DIR)))
+            .await
+            .context("Failed to list namespaces")?;
+        Ok(listing
+            .common_prefixes
+            .iter()
+            .filter_map(|prefix| prefix.filename().map(str::to_string))
+            .collect())
+    }
+
+    /// Writes records of the given type, returning their ids in the same order.
+    async fn put(&self, rec_type: &ArchiveRecordType, docs: Vec<Document>) -> Result<Vec<String>> {
+        let store = self.store().await?;
+        let ids: Vec<String> = stream::iter(docs)
+            .map(|doc| async move {
+                let (id, object) = to_object(doc)?;
+                store
+                    .put(&self.key(rec_type, &id), object.into())
+                    .await
+                    .with_context(|| format!("Failed to write record {}", id))?;
+                Ok::<_, anyhow::Error>(id)
+            })
+            .buffered(CONCURRENCY)
+            .try_collect()
+            .await?;
+
+        debug!("Wrote {} records to bucket {}", ids.len(), self.datastore);
+        Ok(ids)
+    }
+
+    /// Writes the batches left staged by a crash in full, in the order they were staged,
+    /// returning how many were written. A batch that fails to be written stays staged.
+    pub(crate) async fn recover(&self) -> Result<u64> {
+        let store = self.store().await?;
+        let mut staged: Vec<ObjectMeta> = store
+            .list(Some(&self.within(STAGING_DIR)))
+            .try_collect()
+            .await
+            .context("Failed to list staged batches")?;
+        staged.sort_by(|a, b| a.location.cmp(&b.location));
+
+        for meta in &staged {
+            let location = &meta.location;
+            let bytes = store
+                .get(location)
+                .await
+                .with_context(|| format!("Failed to fetch {}", location))?
+                .bytes()
+                .await
+                .with_context(|| format!("Failed to fetch {}", location))?;
+            let batch = StagedBatch::from_bytes(&bytes)
+                .with_context(|| format!("Invalid staged batch {}", location))?;
+            for (rec_type, doc) in batch.records {
+                self.put(&rec_type, vec![doc]).await?;
+            }
+            store
+                .delete(location)
+                .await
+                .with_context(|| format!("Failed to delete {}", location))?;
+            debug!("Recovered staged batch {}", location);
+        }
+        Ok(staged.len() as u64)
+    }
+
+    /// Fetches and parses the object at the given key.
+    async fn get(&self, location: &Path) -> Result<Document> {
+        let bytes = self
+            .store()
+            .await?
+            .get(location)
+            .await
+            .with_context(|| format!("Failed to fetch {}", location))?
+            .bytes()
+            .await
+            .with_context(|| format!("Failed to fetch {}", location))?;
+        bson::from_slice(&bytes).with_context(|| format!("Invalid record in {}", location))
+    }
+
+    /// Lists the objects storing records of the given type, in key order, optionally only those
+    /// after the given id.
+    async fn list(
+        &self,
+        rec_type: &ArchiveRecordType,
+        after: Option<&str>,
+    ) -> Result<BoxStream<'_, Result<ObjectMeta>>> {
+        let store = self.store().await?;
+        let prefix = self.prefix(rec_type);
+        let objects = match after {
+            Some(id) => store.list_with_offset(Some(&prefix), &self.key(rec_type, id)),
+            None => store.list(Some(&prefix)),
+        };
+        Ok(objects
+            .map(|meta| meta.context("Failed to list records"))
+            .boxed())
+    }
+
+    /// Fetches the records stored in the listed objects, keeping their order.
+    fn fetch<'a>(
+        &'a self,
+        objects: BoxStream<'a, Result<ObjectMeta>>,
+    ) -> BoxStream<'a, Result<Document>> {
+        objects
+            .map_ok(move |meta| async move { self.get(&meta.location).await })
+            .try_buffered(CONCURRENCY)
+            .boxed()
+    }
+}
+
+#[async_trait]
+impl ArchiveBackend for S3Backend {
+    /// Writes the record as a new object. A record given an `_id` that is already in use replaces
+    /// the record stored under it.
+    async fn create<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+        let mut ids = self.put(&rec_type, vec![doc]).await?;
+        Ok(ids.remove(0))
+    }
+
+    /// Writes the records as objects, several at a time. Object storage has no transactions, so
+    /// records written before a failure are kept, whatever the ordering setting.
+    async fn create_many<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        recs: Vec<T>,
+    ) -> Result<Vec<String>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let docs = recs
+            .iter()
+            .map(|rec| bson::to_document(rec).context("Failed to serialise record to BSON"))
+            .collect::<Result<_>>()?;
+        Ok(self.put(&rec_type, docs).await?)
+    }
+
+    /// Object storage has no transactions, so the batch is staged in a single object first, then
+    /// its records written one by one.
+    async fn create_atomic(
+        &self,
+        records: Vec<(ArchiveRecordType, Document)>,
+    ) -> Result<Vec<String>, ArchiveError> {
+        self.recovered
+            .get_or_try_init(|| async { self.recover().await.map(|_| ()) })
+            .await?;
+        let store = self.store().await?;
+        let batch = StagedBatch::new(records);
+        let staged = self.within(STAGING_DIR).child(StagedBatch::name());
+        store
+            .put(&staged, batch.to_bytes()?.into())
+            .await
+            .with_context(|| format!("Failed to stage batch {}", staged))?;
+
+        let mut written = Vec::with_capacity(batch.records.len());
+        for (rec_type, doc) in &batch.records {
+            match self.put(rec_type, vec![doc.clone()]).await {
+                Ok(ids) => written.extend(ids.iter().map(|id| self.key(rec_type, id))),
+                Err(e) => {
+                    // Should deleting a record fail, the batch stays staged, to be written in full
+                    // by the next recovery rather than left half written.
+                    for location in &written {
+                        store
+                            .delete(location)
+                            .await
+                            .with_context(|| format!("Failed to roll back {}", location))?;
+                    }
+                    store.delete(&staged).await?;
+                    return Err(e.into());
+                }
+            }
+        }
+        store
+            .delete(&staged)
+            .await
+            .with_context(|| format!("Failed to delete {}", staged))?;
+        debug!(
+            "Wrote batch of {} records to bucket {}",
+            written.len(),
+            self.datastore
+        );
+        Ok(batch.ids())
+    }
+
+    async fn find_all<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let objects = self.list(&rec_type, None).await?;
+        Ok(decode(self.fetch(objects).try_collect().await?)?)
+    }
+
+    /// Streams the records as they are listed and fetched, so only a few are held in memory at a
+    /// time.
+    async fn find_all_stream<'a, T: DeserializeOwned>(
+        &'a self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'a, Result<T, ArchiveError>>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin + 'a,
+    {
+        let objects = self.list(&rec_type, None).await?;
+        Ok(self
+            .fetch(objects)
+            .and_then(|doc| async move {
+                bson::from_document(doc).context("Failed to deserialise record")
+            })
+            .map_err(ArchiveError::from)
+            .boxed())
+    }
+
+    async fn find_page<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        request: &PageRequest,
+    ) -> Result<Page<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        // List one more object than requested to tell whether there is a next page.
+        let mut objects: Vec<ObjectMeta> = self
+            .list(&rec_type, request.after_token.as_deref())
+            .await?
+            .take(request.limit + 1)
+            .try_collect()
+            .await?;
+        let more = objects.len() > request.limit;
+        objects.truncate(request.limit);
+
+        let docs: Vec<Document> = self
+            .fetch(stream::iter(objects.into_iter().map(Ok)).boxed())
+            .try_collect()
+            .await?;
+        let next_token = match more {
+            true => docs
+                .last()
+                .and_then(|doc| doc.get_str("_id").ok())
+                .map(str::to_string),
+            false => None,
+        };
+        Ok(Page {
+            items: decode(docs)?,
+            next_token,
+        })
+    }
+
+    /// Query data store for the records of the given type matching a [Filter]. Every record of
+    /// the type is fetched and filtered here.
+    async fn query<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let objects = self.list(&rec_type, None).await?;
+        let docs = self
+            .fetch(objects)
+            .try_filter(|doc| futures::future::ready(filter.matches(doc)))
+            .try_collect()
+            .await?;
+        Ok(decode(docs)?)
+    }
+
+    /// Objects are read whole, so whole documents are returned.
+    async fn find_projected(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        _fields: &[String],
+    ) -> Result<Vec<Document>, ArchiveError> {
+        self.query::<Document>(rec_type, filter).await
+    }
+
+    async fn find_by_id<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<Option<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let location = self.key(&rec_type, id);
+        let result = self.store().await?.get(&location).await;
+        let bytes = match result {
+            Ok(result) => result.bytes().await,
+            Err(object_store::Error::NotFound { .. }) => return Ok(None),
+            Err(e) => Err(e),
+        }
+        .with_context(|| format!("Failed to fetch {}", location))?;
+
+        let doc: Document =
+            bson::from_slice(&bytes).with_context(|| format!("Invalid record in {}", location))?;
+        Ok(Some(
+            bson::from_document(doc).context("Failed to deserialise record")?,
+        ))
+    }
+
+    /// Object storage does not report whether a deleted object existed, so the object is looked up
+    /// first.
+    async fn delete_by_id(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<bool, ArchiveError> {
+        let store = self.store().await?;
+        let location = self.key(&rec_type, id);
+        match store.head(&location).await {
+            Ok(_) => {}
+            Err(object_store::Error::NotFound { .. }) => return Ok(false),
+            Err(e) => {
+                return Err(anyhow::Error::new(e)
+                    .context(format!("Failed to fetch {}", location))
+                    .into())
+            }
+        }
+        store
+            .delete(&location)
+            .await
+            .with_context(|| format!("Failed to delete {}", location))?;
+
+        debug!("Deleted {}", id);
+        Ok(true)
+    }
+
+    /// Every record of the type is fetched and filtered here, then the matching objects are
+    /// deleted several at a time.
+    async fn delete_where(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        let store = self.store().await?;
+        let objects = self.list(&rec_type, None).await?;
+        let ids: Vec<String> = self
+            .fetch(objects)
+            .try_filter(|doc| futures::future::ready(filter.matches(doc)))
+            .map_ok(|doc| doc.get_str("_id").unwrap_or_default().to_string())
+            .try_collect()
+            .await?;
+
+        let locations: Vec<Path> = ids.iter().map(|id| self.key(&rec_type, id)).collect();
+        stream::iter(locations)
+            .map(|location| async move {
+                store
+                    .delete(&location)
+                    .await
+                    .with_context(|| format!("Failed to delete {}", location))
+            })
+            .buffered(CONCURRENCY)
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        debug!(
+            "Deleted {} records from bucket {}",
+            ids.len(),
+            self.datastore
+        );
+        Ok(ids.len() as u64)
+    }
+
+    /// Overwrites the object if it exists, keeping the record's id whatever `_id` it holds. The
+    /// object is looked up first, so it could be deleted before being overwritten.
+    async fn update_by_id<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        rec: T,
+    ) -> Result<bool, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let location = self.key(&rec_type, id);
+        match self.store().await?.head(&location).await {
+            Ok(_) => {}
+            Err(object_store::Error::NotFound { .. }) => return Ok(false),
+            Err(e) => {
+                return Err(anyhow::Error::new(e)
+                    .context(format!("Failed to fetch {}", location))
+                    .into())
+            }
+        }
+        let mut doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+        doc.insert("_id", id);
+        self.put(&rec_type, vec![doc]).await?;
+        Ok(true)
+    }
+
+    /// Every record of the type is fetched and filtered here until the first match, in key
+    /// order, which is overwritten. Object storage has no transactions, so concurrent upserts may
+    /// both insert a record.
+    async fn upsert<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let mut doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+        let objects = self.list(&rec_type, None).await?;
+        let existing = self
+            .fetch(objects)
+            .try_filter(|doc| futures::future::ready(filter.matches(doc)))
+            .try_next()
+            .await?;
+        if let Some(existing) = existing {
+            doc.insert("_id", existing.get_str("_id").unwrap_or_default());
+        }
+        let mut ids = self.put(&rec_type, vec![doc]).await?;
+        Ok(ids.remove(0))
+    }
+
+    /// Returns a random sample of roughly `rate` (0.0 to 1.0) of the records of the given type,
+    /// selecting each record independently with probability `rate`. Only the selected records are
+    /// fetched.
+    async fn find_sampled<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rate: f64,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let objects = self
+            .list(&rec_type, None)
+            .await?
+            .try_filter(|_| futures::future::ready(rand::random::<f64>() < rate))
+            .boxed();
+        Ok(decode(self.fetch(objects).try_collect().await?)?)
+    }
+
+    /// Records are never chunked, so there are never orphaned chunks.
+    async fn find_orphaned_chunks(
+        &self,
+        _rec_type: ArchiveRecordType,
+    ) -> Result<Vec<String>, ArchiveError> {
+        Ok(Vec::new())
+    }
+
+    /// Records are never chunked, so there are never orphaned chunks.
+    async fn cleanup_orphans(&self, _rec_type: ArchiveRecordType) -> Result<u64, ArchiveError> {
+        Ok(0)
+    }
+
+    /// Deleted objects free their space in the bucket, so there is nothing to reclaim.
+    async fn reclaim_space(&self, _rec_type: ArchiveRecordType) -> Result<(), ArchiveError> {
+        Ok(())
+    }
+
+    /// Aggregation pipelines are MongoDB specific.
+    async fn merge_into(
+        &self,
+        _source: ArchiveRecordType,
+        _pipeline: Vec<Document>,
+        _target: ArchiveRecordType,
+        _mode: MergeMode,
+    ) -> Result<u64, ArchiveError> {
+        Err(Unsupported {
+            operation: "merge_into",
+            reason: "aggregation pipelines are only supported by the MongoDB backend".to_string(),
+        }
+        .into())
+    }
+
+    /// Changes are only reported by the store that made them.
+    async fn watch(
+        &self,
+        _rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'static, Result<ArchiveEvent, ArchiveError>>, ArchiveError> {
+        Err(Unsupported {
+            operation: "watch",
+            reason: "change streams are only supported by the MongoDB backend".to_string(),
+        }
+        .into())
+    }
+
+    /// [Filter::All] counts every record by listing them. Otherwise every record of the type is
+    /// fetched and filtered here.
+    async fn count(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        let objects = self.list(&rec_type, None).await?;
+        if let Filter::All = filter {
+            return Ok(objects
+                .try_fold(0, |count, _| futures::future::ready(Ok(count + 1)))
+                .await?);
+        }
+
+        Ok(self
+            .fetch(objects)
+            .try_filter(|doc| futures::future::ready(filter.matches(doc)))
+            .try_fold(0, |count, _| futures::future::ready(Ok(count + 1)))
+            .await?)
+    }
+
+    /// [Filter::All] only lists the first object. Otherwise records of the type are fetched and
+    /// filtered here until one matches.
+    async fn exists(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<bool, ArchiveError> {
+        let mut objects = self.list(&rec_type, None).await?;
+        if let Filter::All = filter {
+            return Ok(objects.try_next().await?.is_some());
+        }
+
+        Ok(self
+            .fetch(objects)
+            .try_filter(|doc| futures::future::ready(filter.matches(doc)))
+            .try_next()
+            .await?
+            .is_some())
+    }
+
+    /// Counts the records and totals the size of their objects by listing them.
+    async fn stats(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<ArchiveCollectionStats, ArchiveError> {
+        let (document_count, storage_bytes) = self
+            .list(&rec_type, None)
+            .await?
+            .try_fold((0u64, 0u64), |(count, bytes), meta| {
+                futures::future::ready(Ok((count + 1, bytes + meta.size as u64)))
+            })
+            .await?;
+
+        Ok(ArchiveCollectionStats {
+            document_count,
+            storage_bytes,
+            avg_doc_bytes: storage_bytes.checked_div(document_count).unwrap_or(0),
+        })
+    }
+
+    /// Groups by the time each object was last written when grouping by archive time, which only
+    /// needs the records to be listed. Grouping by a field fetches every record.
+    async fn group_count(
+        &self,
+        rec_type: ArchiveRecordType,
+        group_by: GroupBy,
+    ) -> Result<Vec<(Bson, u64)>, ArchiveError> {
+        let objects = self.list(&rec_type, None).await?;
+        let keys: Vec<Bson> = match group_by {
+            GroupBy::Field(field) => {
+                self.fetch(objects)
+                    .map_ok(|doc| lookup(&doc, &field).cloned().unwrap_or(Bson::Null))
+                    .try_collect()
+                    .await?
+            }
+            GroupBy::ArchivedAt(granularity) => {
+                objects
+                    .map_ok(|meta| {
+                        Bson::String(granularity.bucket(bson::DateTime::from_millis(
+                            meta.last_modified.timestamp_millis(),
+                        )))
+                    })
+                    .try_collect()
+                    .await?
+            }
+        };
+        Ok(tally(keys))
+    }
+
+    /// Aggregates the records matching the filter in process.
+    async fn aggregate(
+        &self,
+        rec_type: ArchiveRecordType,
+        spec: &AggregationSpec,
+    ) -> Result<Vec<Document>, ArchiveError> {
+        let docs = self.query::<Document>(rec_type, &spec.filter).await?;
+        Ok(aggregate(spec, docs))
+    }
+
+    /// There are no secondary indexes: queries scan every record, so non-unique indexes are
+    /// ignored.
+    async fn ensure_indexes(
+        &self,
+        _rec_type: ArchiveRecordType,
+        indexes: &[IndexSpec],
+    ) -> Result<(), ArchiveError> {
+        if indexes.iter().any(|index| index.unique) {
+            return Err(Unsupported {
+                operation: "ensure_indexes",
+                reason: "object storage has no secondary indexes".to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+/// A gRPC service serving an archive, so that non-Rust services and other nodes can read and write
+/// it without linking the crate or holding the backend's credentials. The service is defined in
+/// `proto/archive.proto`, and [proto] holds the types and a client generated from it. Serve a
+/// store with [ArchiveStore::serve], or add [ArchiveService::into_server] to a [tonic] server of
+/// your own, e.g. to serve it over TLS or behind authentication, which the service itself leaves
+/// to the deployment. Requires the `server` feature.
+use crate::filter::id_to_string;
+use crate::{
+    ArchiveError, ArchiveErrorKind, ArchiveEventKind, ArchiveRecordType, ArchiveStore,
+    CreateOutcome, Filter, PageRequest,
+};
+use anyhow::Context;
+use bson::{Bson, Document};
+use futures::stream::{self, BoxStream, StreamExt};
+use log::warn;
+use std::future::Future;
+use std::net::SocketAddr;
+use tonic::{Request, Response, Status};
+
+/// Types and service traits generated from `proto/archive.proto`, including a client for the
+/// service.
+#[allow(clippy::all)]
+pub mod proto {
+    tonic::include_proto!("lasr.archive.v1");
+}
+
+use proto::archive_server::{Archive, ArchiveServer};
+use proto::condition::Operator;
+
+/// Number of records in a page of the `Stream` RPC when the request doesn't say
+const DEFAULT_PAGE_SIZE: u32 = 500;
+/// Largest number of records in a page of the `Stream` RPC
+const MAX_PAGE_SIZE: u32 = 10_000;
+
+/// The gRPC archive service, serving the records of an [ArchiveStore].
+#[derive(Debug, Clone)]
+pub struct ArchiveService {
+    store: ArchiveStore,
+}
+
+impl ArchiveService {
+    /// Serves the records of `store`.
+    pub fn new(store: ArchiveStore) -> Self {
+        ArchiveService { store }
+    }
+
+    /// The service, to be added to a [tonic::transport::Server].
+    pub fn into_server(self) -> ArchiveServer<ArchiveService> {
+        ArchiveServer::new(self)
+    }
+}
+
+impl ArchiveStore {
+    /// Serves this store over gRPC on `addr`, without TLS, until `shutdown` completes, e.g.
+    /// `tokio::signal::ctrl_c()`. Pass `std::future::pending()` to serve until the process exits.
+    pub async fn serve(
+        &self,
+        addr: SocketAddr,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), ArchiveError> {
+        tonic::transport::Server::builder()
+            .add_service(ArchiveService::new(self.clone()).into_server())
+            .serve_with_shutdown(addr, shutdown)
+            .await
+            .with_context(|| format!("Serving the archive on {}", addr))?;
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl Archive for ArchiveService {
+    type QueryStream = BoxStream<'static, Result<proto::Record, Status>>;
+    type StreamStream = BoxStream<'static, Result<proto::Page, Status>>;
+    type SubscribeStream = BoxStream<'static, Result<proto::Event, Status>>;
+
+    async fn put(
+        &self,
+        request: Request<proto::PutRequest>,
+    ) -> Result<Response<proto::PutResponse>, Status> {
+        let request = request.into_inner();
+        let rec = parse_record(&request.json)?;
+        let outcome = self
+            .store
+            .create(ArchiveRecordType::from_name(&request.record_type), rec)
+            .await
+            .map_err(status)?;
+        Ok(Response::new(match outcome {
+            CreateOutcome::Created(id) => proto::PutResponse {
+                id,
+                spilled: false,
+                dead_letter_id: String::new(),
+            },
+            CreateOutcome::Spilled(_) => proto::PutResponse {
+                id: String::new(),
+                spilled: true,
+                dead_letter_id: String::new(),
+            },
+            CreateOutcome::DeadLettered(dead_letter_id) => proto::PutResponse {
+                id: String::new(),
+                spilled: false,
+                dead_letter_id,
+            },
+        }))
+    }
+
+    async fn get(
+        &self,
+        request: Request<proto::GetRequest>,
+    ) -> Result<Response<proto::Record>, Status> {
+        let request = request.into_inner();
+        match self
+            .store
+            .find_by_id::<Document>(
+                ArchiveRecordType::from_name(&request.record_type),
+                &request.id,
+            )
+            .await
+            .map_err(status)?
+        {
+            Some(rec) => Ok(Response::new(record(rec))),
+            None => Err(Status::not_found(format!(
+                "No {} record with id {}",
+                request.record_type, request.id
+            ))),
+        }
+    }
+
+    async fn query(
+        &self,
+        request: Request<proto::QueryRequest>,
+    ) -> Result<Response<Self::QueryStream>, Status> {
+        let request = request.into_inner();
+        let filter = request
+            .conditions
+            .iter()
+            .try_fold(Filter::All, |filter, condition| {
+                Ok::<_, Status>(filter.and(condition_filter(condition)?))
+            })?;
+        let recs: Vec<Document> = self
+            .store
+            .query(ArchiveRecordType::from_name(&request.record_type), filter)
+            .await
+            .map_err(status)?;
+        Ok(Response::new(
+            stream::iter(recs.into_iter().map(|rec| Ok(record(rec)))).boxed(),
+        ))
+    }
+
+    async fn stream(
+        &self,
+        request: Request<proto::StreamRequest>,
+    ) -> Result<Response<Self::StreamStream>, Status> {
+        let request = request.into_inner();
+        let limit = match request.page_size {
+            0 => DEFAULT_PAGE_SIZE,
+            size if size > MAX_PAGE_SIZE => {
+                return Err(Status::invalid_argument(format!(
+                    "Page size must be at most {}",
+                    MAX_PAGE_SIZE
+                )))
+            }
+            size => size,
+        } as usize;
+        let store = self.store.clone();
+        let rec_type = ArchiveRecordType::from_name(&request.record_type);
+        // The state is the token of the next page to read, or `None` once the last was read.
+        let pages = stream::try_unfold(Some(request.after_token), move |after_token| {
+            let store = store.clone();
+            let rec_type = rec_type.clone();
+            async move {
+                let Some(after_token) = after_token else {
+                    return Ok(None);
+                };
+                let page = store
+                    .find_page::<Document>(rec_type, PageRequest { limit, after_token })
+                    .await
+                    .map_err(status)?;
+                let next = page.next_token.clone().map(Some);
+                let page = proto::Page {
+                    records: page.items.into_iter().map(record).collect(),
+                    next_token: page.next_token,
+                };
+                Ok(Some((page, next)))
+            }
+        });
+        Ok(Response::new(pages.boxed()))
+    }
+
+    async fn subscribe(
+        &self,
+        request: Request<proto::SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let request = request.into_inner();
+        let events = self
+            .store
+            .subscribe(ArchiveRecordType::from_name(&request.record_type))
+            .await
+            .map_err(status)?;
+        let name = request.record_type;
+        let events = events.map(move |event| {
+            let event = event.map_err(status)?;
+            let kind = match event.kind {
+                ArchiveEventKind::Created => proto::event::Kind::Created,
+                ArchiveEventKind::Updated => proto::event::Kind::Updated,
+                ArchiveEventKind::Deleted => proto::event::Kind::Deleted,
+            };
+            Ok(proto::Event {
+                record_type: name.clone(),
+                kind: kind.into(),
+                id: event.id,
+            })
+        });
+        Ok(Response::new(events.boxed()))
+    }
+}
+
+/// Parses a value given as extended JSON.
+fn parse_value(json: &str) -> Result<Bson, Status> {
+    serde_json::from_str::<serde_json::Value>(json)
+        .ok()
+        .and_then(|json| Bson::try_from(json).ok())
+        .ok_or_else(|| Status::invalid_argument(format!("Invalid extended JSON: {}", json)))
+}
+
+/// Parses a record given as an extended JSON object.
+fn parse_record(json: &str) -> Result<Document, Status> {
+    match parse_value(json)? {
+        Bson::Document(rec) => Ok(rec),
+        _ => Err(Status::invalid_argument("The record must be a JSON object")),
+    }
+}
+
+/// The filter of a condition of a `Query` request.
+fn condition_filter(condition: &proto::Condition) -> Result<Filter, Status> {
+    let value = parse_value(&condition.value)?;
+    let field = condition.field.as_str();
+    let operator = Operator::try_from(condition.operator).map_err(|_| {
+        Status::invalid_argument(format!("Unknown operator {}", condition.operator))
+    })?;
+    Ok(match operator {
+        Operator::Eq => Filter::eq(field, value),
+        Operator::Ne => Filter::ne(field, value),
+        Operator::Gt => Filter::gt(field, value),
+        Operator::Gte => Filter::gte(field, value),
+        Operator::Lt => Filter::lt(field, value),
+        Operator::Lte => Filter::lte(field, value),
+    })
+}
+
+/// A record as sent to clients, as relaxed extended JSON.
+fn record(rec: Document) -> proto::Record {
+    let id = rec.get("_id").map(id_to_string).unwrap_or_default();
+    proto::Record {
+        id,
+        json: Bson::Document(rec).into_relaxed_extjson().to_string(),
+    }
+}
+
+/// The gRPC status of a failed archive operation.
+fn status(e: ArchiveError) -> Status {
+    let message = format!("{:#}", e);
+    match e.kind() {
+        ArchiveErrorKind::ConnectionFailed | ArchiveErrorKind::BackendUnavailable => {
+            Status::unavailable(message)
+        }
+        ArchiveErrorKind::NotFound => Status::not_found(message),
+        ArchiveErrorKind::DuplicateKey => Status::already_exists(message),
+        ArchiveErrorKind::Unsupported => Status::unimplemented(message),
+        ArchiveErrorKind::InvalidInput => Status::invalid_argument(message),
+        ArchiveErrorKind::SerializationError | ArchiveErrorKind::Other => {
+            warn!("Archive service request failed: {}", message);
+            Status::internal(message)
+        }
+    }
+}
@@ -0,0 +1,295 @@
+/// Snapshots of whole archives to a portable bundle, and restoring them into any store, e.g. for
+/// disaster recovery or to seed an archive on another backend. A bundle is a directory holding a
+/// `manifest.json` and a file per record type of the records as newline-delimited canonical
+/// extended JSON, decoded from whatever compression and encryption the store applies, with their
+/// ids and provenance.
+use crate::transfer::{copy_page, portable};
+use crate::{ArchiveError, ArchiveRecordType, ArchiveStore, PageRequest};
+use anyhow::{bail, Context};
+use bson::{Bson, DateTime, Document};
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tokio::fs;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+
+/// Name of the bundle's manifest file
+const MANIFEST_FILE: &str = "manifest.json";
+/// Version of the bundle layout written by [ArchiveStore::snapshot]
+const FORMAT_VERSION: u32 = 1;
+/// Number of records read or restored at once
+const PAGE_SIZE: usize = 500;
+
+/// Describes a snapshot bundle, as written to its `manifest.json` by [ArchiveStore::snapshot].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    /// Version of the bundle layout
+    pub format_version: u32,
+    /// The datastore the snapshot was taken of
+    pub datastore: String,
+    /// The point in time captured: records archived at or after it are left out
+    pub cutoff: DateTime,
+    /// The file of each record type captured
+    pub record_types: Vec<SnapshotFile>,
+}
+
+/// The records of one record type in a snapshot bundle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotFile {
+    /// The type of the records
+    pub rec_type: ArchiveRecordType,
+    /// Name of the file within the bundle
+    pub file: String,
+    /// Number of records in the file
+    pub records: u64,
+    /// SHA-256 hash of the file, checked before it's restored
+    pub sha256: String,
+}
+
+impl ArchiveStore {
+    /// Captures the records of the given record types archived before now into a bundle in the
+    /// `destination` directory, which is created if need be, returning the bundle's manifest.
+    /// Records archived while the snapshot is taken are left out, so the bundle holds the archive
+    /// as it was when the snapshot started, except for records updated or deleted since. Only
+    /// this store's own backend is captured, not its cold tier or mirrors.
+    ///
+    /// Records are written decoded, so the bundle can be restored into a store with other
+    /// compression or encryption settings, but holds encrypted records in plaintext. The manifest
+    /// is written last, so a bundle without one is incomplete. Fails if `destination` already
+    /// holds a bundle.
+    pub async fn snapshot(
+        &self,
+        rec_types: &[ArchiveRecordType],
+        destination: impl AsRef<Path>,
+    ) -> Result<SnapshotManifest, ArchiveError> {
+        let dir = destination.as_ref();
+        let manifest_path = dir.join(MANIFEST_FILE);
+        if fs::try_exists(&manifest_path).await.unwrap_or(false) {
+            return Err(ArchiveError::invalid_input(format!(
+                "{} already holds a snapshot",
+                dir.display()
+            )));
+        }
+        for rec_type in rec_types {
+            rec_type.validate().map_err(ArchiveError::invalid_input)?;
+        }
+        fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Creating snapshot directory {}", dir.display()))?;
+
+        let cutoff = DateTime::now();
+        let mut manifest = SnapshotManifest {
+            format_version: FORMAT_VERSION,
+            datastore: self.inner.datastore.clone(),
+            cutoff,
+            record_types: Vec::with_capacity(rec_types.len()),
+        };
+        for rec_type in rec_types {
+            let file = self.snapshot_type(rec_type, cutoff, dir).await?;
+            info!(
+                "Captured {} {:?} records in {}",
+                file.records,
+                rec_type,
+                dir.display()
+            );
+            manifest.record_types.push(file);
+        }
+
+        let contents =
+            serde_json::to_vec_pretty(&manifest).context("Serialising snapshot manifest")?;
+        fs::write(&manifest_path, contents)
+            .await
+            .with_context(|| format!("Writing {}", manifest_path.display()))?;
+        Ok(manifest)
+    }
+
+    /// Writes the records of [ArchiveRecordType] archived before `cutoff` to their file in the
+    /// bundle.
+    async fn snapshot_type(
+        &self,
+        rec_type: &ArchiveRecordType,
+        cutoff: DateTime,
+        dir: &Path,
+    ) -> Result<SnapshotFile, ArchiveError> {
+        let name = format!("{}.jsonl", file_stem(rec_type));
+        let path = dir.join(&name);
+        let mut writer = BufWriter::new(
+            fs::File::create(&path)
+                .await
+                .with_context(|| format!("Creating {}", path.display()))?,
+        );
+        let mut hash = Sha256::new();
+        let mut records = 0;
+        let mut after_token = None;
+        loop {
+            let request = PageRequest {
+                limit: PAGE_SIZE,
+                after_token,
+            };
+            let page = self
+                .with_tombstones()
+                .find_envelope_page::<Document>(rec_type.clone(), request)
+                .await?;
+            for envelope in page.items {
+                // Records without a time they were archived at predate the cutoff.
+                if envelope.archived_at.is_some_and(|time| time >= cutoff) {
+                    continue;
+                }
+                let mut line = Bson::Document(portable(envelope))
+                    .into_canonical_extjson()
+                    .to_string();
+                line.push('\n');
+                hash.update(line.as_bytes());
+                writer
+                    .write_all(line.as_bytes())
+                    .await
+                    .with_context(|| format!("Writing {}", path.display()))?;
+                records += 1;
+            }
+            match page.next_token {
+                Some(token) => after_token = Some(token),
+                None => break,
+            }
+        }
+        writer
+            .flush()
+            .await
+            .with_context(|| format!("Writing {}", path.display()))?;
+        writer
+            .into_inner()
+            .sync_all()
+            .await
+            .with_context(|| format!("Writing {}", path.display()))?;
+
+        Ok(SnapshotFile {
+            rec_type: rec_type.clone(),
+            file: name,
+            records,
+            sha256: format!("{:x}", hash.finalize()),
+        })
+    }
+
+    /// Restores the snapshot bundle in the `source` directory, written by
+    /// [ArchiveStore::snapshot], into this store, returning the number of records restored.
+    /// Records keep their ids and provenance, and are encoded as configured for this store.
+    /// Every file is checked against its hash in the manifest before anything is restored.
+    /// Records already archived under the same id are skipped, so an interrupted restore can
+    /// simply be run again.
+    pub async fn restore(&self, source: impl AsRef<Path>) -> Result<u64, ArchiveError> {
+        let dir = source.as_ref();
+        let manifest_path = dir.join(MANIFEST_FILE);
+        let contents = fs::read(&manifest_path)
+            .await
+            .with_context(|| format!("Reading snapshot manifest {}", manifest_path.display()))?;
+        let manifest: SnapshotManifest = serde_json::from_slice(&contents)
+            .with_context(|| format!("Invalid snapshot manifest {}", manifest_path.display()))?;
+        if manifest.format_version != FORMAT_VERSION {
+            return Err(ArchiveError::invalid_input(format!(
+                "Unsupported snapshot format version {}",
+                manifest.format_version
+            )));
+        }
+        for file in &manifest.record_types {
+            file.rec_type
+                .validate()
+                .map_err(ArchiveError::invalid_input)?;
+            verify_file(dir, file).await?;
+        }
+
+        let mut restored = 0;
+        for file in &manifest.record_types {
+            let written = self.restore_file(dir, file).await?;
+            info!(
+                "Restored {} of {} {:?} records from {}",
+                written,
+                file.records,
+                file.rec_type,
+                dir.display()
+            );
+            restored += written;
+        }
+        Ok(restored)
+    }
+
+    /// Writes the records in a file of the bundle to this store, returning the number written.
+    async fn restore_file(&self, dir: &Path, file: &SnapshotFile) -> Result<u64, ArchiveError> {
+        let path = bundle_path(dir, file)?;
+        let reader = fs::File::open(&path)
+            .await
+            .with_context(|| format!("Opening {}", path.display()))?;
+        let mut lines = BufReader::new(reader).lines();
+        let mut batch = Vec::with_capacity(PAGE_SIZE);
+        let mut written = 0;
+        let mut line_number = 0;
+        while let Some(line) = lines
+            .next_line()
+            .await
+            .with_context(|| format!("Reading {}", path.display()))?
+        {
+            line_number += 1;
+            batch.push(parse_record(&line).with_context(|| {
+                format!(
+                    "Invalid record on line {} of {}",
+                    line_number,
+                    path.display()
+                )
+            })?);
+            if batch.len() == PAGE_SIZE {
+                written += copy_page(self, &file.rec_type, std::mem::take(&mut batch)).await?;
+            }
+        }
+        written += copy_page(self, &file.rec_type, batch).await?;
+        Ok(written)
+    }
+}
+
+/// Name of the file holding the records of a record type in a bundle, without its extension.
+fn file_stem(rec_type: &ArchiveRecordType) -> &str {
+    match rec_type {
+        ArchiveRecordType::Account => "accounts",
+        ArchiveRecordType::TransactionBatch => "transaction_data",
+        ArchiveRecordType::Block => "blocks",
+        ArchiveRecordType::Receipt => "receipts",
+        ArchiveRecordType::Custom(name) => name,
+    }
+}
+
+/// The path of a file of the bundle, which must be directly within it.
+fn bundle_path(dir: &Path, file: &SnapshotFile) -> Result<std::path::PathBuf, ArchiveError> {
+    let name = Path::new(&file.file);
+    if name.components().count() != 1 || name.file_name().is_none() {
+        return Err(ArchiveError::invalid_input(format!(
+            "Invalid snapshot file name '{}'",
+            file.file
+        )));
+    }
+    Ok(dir.join(name))
+}
+
+/// Checks that a file of the bundle hashes to the hash in the manifest.
+async fn verify_file(dir: &Path, file: &SnapshotFile) -> Result<(), ArchiveError> {
+    let path = bundle_path(dir, file)?;
+    let contents = fs::read(&path)
+        .await
+        .with_context(|| format!("Reading {}", path.display()))?;
+    let actual = format!("{:x}", Sha256::digest(&contents));
+    if actual != file.sha256 {
+        return Err(ArchiveError::invalid_input(format!(
+            "Snapshot file {} is corrupt: its SHA-256 hash is {} but the manifest has {}",
+            path.display(),
+            actual,
+            file.sha256
+        )));
+    }
+    Ok(())
+}
+
+/// Parses a line of a bundle's file back into a record.
+fn parse_record(line: &str) -> anyhow::Result<Document> {
+    let json: serde_json::Value = serde_json::from_str(line)?;
+    match Bson::try_from(json)? {
+        Bson::Document(rec) => Ok(rec),
+        other => bail!("Expected a document, found {}", other),
+    }
+}
@@ -0,0 +1,207 @@
+/// A local dead-letter spill file for records that could not be written to the backend. Each line
+/// of the file is a JSON object holding the record type, the time the record was spilled and the
+/// record exactly as it would have been handed to the backend (compressed and versioned), in
+/// canonical extended JSON so that binary payloads round trip losslessly.
+use crate::{ArchiveRecordType, ArchiveStore, Labels};
+use anyhow::{Context, Result};
+use bson::{Bson, Document};
+use core::fmt;
+use log::{debug, warn};
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::time::MissedTickBehavior;
+
+/// The result of [crate::ArchiveStore::create].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CreateOutcome {
+    /// The record was written to the backend under the given id.
+    Created(String),
+    /// The backend write failed and the record was appended to the spill file at the given path,
+    /// to be written later by [crate::ArchiveStore::drain_spill]. Only returned when a spill
+    /// directory is configured.
+    Spilled(PathBuf),
+    /// The record couldn't be serialised, was too large or was rejected by the backend, and was
+    /// captured as the dead letter with the given id, see [crate::ArchiveStore::dead_letters].
+    /// Only returned when a dead-letter directory is configured.
+    DeadLettered(String),
+}
+
+impl CreateOutcome {
+    /// The id of the record, if it was written to the backend.
+    pub fn id(&self) -> Option<&str> {
+        match self {
+            CreateOutcome::Created(id) => Some(id),
+            CreateOutcome::Spilled(_) | CreateOutcome::DeadLettered(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for CreateOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CreateOutcome::Created(id) => write!(f, "created {}", id),
+            CreateOutcome::Spilled(path) => write!(f, "spilled to {}", path.display()),
+            CreateOutcome::DeadLettered(id) => write!(f, "dead-lettered as {}", id),
+        }
+    }
+}
+
+/// A single spilled record.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SpillEntry {
+    pub(crate) record_type: ArchiveRecordType,
+    /// RFC 3339 time the record was spilled
+    pub(crate) spilled_at: String,
+    /// The encoded record, in canonical extended JSON
+    pub(crate) record: serde_json::Value,
+}
+
+impl SpillEntry {
+    pub(crate) fn new(record_type: ArchiveRecordType, record: Document) -> SpillEntry {
+        SpillEntry {
+            record_type,
+            spilled_at: bson::DateTime::now()
+                .try_to_rfc3339_string()
+                .unwrap_or_default(),
+            record: Bson::Document(record).into_canonical_extjson(),
+        }
+    }
+
+    /// The encoded record, ready to be handed to the backend.
+    pub(crate) fn record(&self) -> Result<Document> {
+        match Bson::try_from(self.record.clone()).context("Invalid spilled record")? {
+            Bson::Document(doc) => Ok(doc),
+            other => anyhow::bail!("Spilled record is not a document: {}", other),
+        }
+    }
+}
+
+/// Path of the spill file of a datastore within the spill directory.
+pub(crate) fn path(dir: &Path, datastore: &str) -> PathBuf {
+    dir.join(format!("{}.spill.jsonl", datastore))
+}
+
+/// Appends an entry to the spill file, or another file of JSON lines such as the dead-letter
+/// file, creating it (and its directory) if needed. The entry is synced to disk before returning.
+pub(crate) async fn append<E: serde::Serialize>(path: &Path, entry: &E) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Creating directory {}", dir.display()))?;
+    }
+    let mut line = serde_json::to_vec(entry).context("Serialising entry")?;
+    line.push(b'\n');
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("Opening {}", path.display()))?;
+    file.write_all(&line).await?;
+    file.sync_data().await?;
+    Ok(())
+}
+
+/// Reads every entry of the spill file in the order they were spilled. A missing file has no
+/// entries. Lines that can't be parsed, such as one torn by a crash part way through an append,
+/// are logged and skipped.
+pub(crate) async fn read<E: DeserializeOwned>(path: &Path) -> Result<Vec<E>> {
+    let contents = match fs::read_to_string(path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Reading {}", path.display())),
+    };
+
+    let mut entries = Vec::new();
+    for (n, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn!(
+                "Skipping corrupt entry on line {} of {}: {}",
+                n + 1,
+                path.display(),
+                e
+            ),
+        }
+    }
+    Ok(entries)
+}
+
+/// Replaces the contents of the spill file with the given entries, removing it if there are
+/// none. The new contents are written to a temporary file which is then renamed over the spill
+/// file, so a crash leaves either the old or the new contents in place.
+pub(crate) async fn rewrite<E: serde::Serialize>(path: &Path, entries: &[E]) -> Result<()> {
+    if entries.is_empty() {
+        return match fs::remove_file(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Removing {}", path.display()))
+            }
+            _ => Ok(()),
+        };
+    }
+
+    let mut contents = Vec::new();
+    for entry in entries {
+        contents.extend(serde_json::to_vec(entry).context("Serialising entry")?);
+        contents.push(b'\n');
+    }
+
+    let tmp = path.with_extension("jsonl.tmp");
+    let mut file = fs::File::create(&tmp)
+        .await
+        .with_context(|| format!("Creating {}", tmp.display()))?;
+    file.write_all(&contents).await?;
+    file.sync_all().await?;
+    fs::rename(&tmp, path)
+        .await
+        .with_context(|| format!("Replacing {}", path.display()))
+}
+
+impl ArchiveStore {
+    /// Starts draining the spill file in the background every
+    /// [crate::ArchiveStoreBuilder::spill_drain_interval], if one is configured and the drain isn't
+    /// already running. The drain only holds a weak reference to the store, so it ends once the
+    /// store and all its clones are dropped.
+    pub(crate) fn start_spill_drain(&self) {
+        let interval = match self.inner.spill_drain_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        if self.inner.spill_drain.set(()).is_err() {
+            return;
+        }
+
+        let inner = Arc::downgrade(&self.inner);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let store = match inner.upgrade() {
+                    Some(inner) => ArchiveStore {
+                        inner,
+                        labels: Labels::default(),
+                        tags: Vec::new(),
+                        include_tombstones: false,
+                        #[cfg(feature = "mongodb")]
+                        mongodb_override: None,
+                    },
+                    None => break,
+                };
+                match store.drain_spill().await {
+                    Ok(0) => {}
+                    Ok(drained) => debug!("Drained {} spilled records", drained),
+                    Err(e) => warn!("Failed to drain spill file, retrying later: {:#}", e),
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,1023 @@
+/// An implementation of an archive datastore that uses an embedded SQLite database as its backend,
+/// enabled with the `sqlite` feature. It needs no database server, so suits running a node with
+/// archival on a laptop and integration tests. The URI names the database file, e.g.
+/// `sqlite://archive.db` or `sqlite:///var/lib/lasr/archive.db`, or `sqlite::memory:` for a private
+/// in-memory database. Each [ArchiveRecordType] gets its own table, named after the datastore and
+/// the [ACCOUNT_TABLE] and [TRANSACTION_TABLE] constants or the custom record type's name, created
+/// on first use and holding each record as JSON text alongside its id and the time it was archived.
+/// Ids are generated as ObjectIds, so records sort by the time they were archived just like they do
+/// with the MongoDB backend.
+///
+/// SQLite is synchronous, so operations run on Tokio's blocking thread pool over a single
+/// connection, and are serialised. Operations that take MongoDB specific arguments, such as
+/// aggregation pipelines and query documents, fail with [Unsupported]. Records are never chunked.
+use crate::projection;
+use crate::stats::aggregate;
+use crate::{
+    AggregationSpec, ArchiveBackend, ArchiveCollectionStats, ArchiveError, ArchiveEvent,
+    ArchiveRecordType, Filter, GroupBy, IndexSpec, MergeMode, Page, PageRequest, Unsupported,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bson::{oid::ObjectId, Bson, Document};
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use log::debug;
+use rusqlite::{params_from_iter, types::Value, Connection, OptionalExtension};
+use serde::{de::DeserializeOwned, Serialize};
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+/// SQLite table name suffix for storing account data
+const ACCOUNT_TABLE: &str = "accounts";
+/// SQLite table name suffix for storing transaction data
+const TRANSACTION_TABLE: &str = "transaction_data";
+/// SQLite table name suffix for storing blocks
+const BLOCK_TABLE: &str = "blocks";
+/// SQLite table name suffix for storing transaction receipts
+const RECEIPT_TABLE: &str = "receipts";
+/// Largest string SQLite stores by default (1GB)
+pub const MAX_DOCUMENT_SIZE: usize = 1_000_000_000;
+/// Longest datastore name we allow, leaving room for the table name suffixes
+const MAX_DATASTORE_LEN: usize = 63;
+/// Number of records read at a time when streaming
+const STREAM_BATCH_SIZE: usize = 1000;
+
+/// Adds a parameter to a query, returning its placeholder.
+fn param(params: &mut Vec<Value>, value: Value) -> String {
+    params.push(value);
+    format!("?{}", params.len())
+}
+
+/// The SQLite JSON path of a (possibly dotted) field name, as a parameter.
+fn path_param(params: &mut Vec<Value>, field: &str) -> String {
+    let path: String = field
+        .split('.')
+        .map(|key| format!(".\"{}\"", key))
+        .collect();
+    param(params, Value::Text(format!("${}", path)))
+}
+
+/// How a BSON value is compared with the JSON stored at a path: the JSON types it can be equal
+/// or ordered against, how the stored value is read and the placeholder of the value itself.
+fn comparison(params: &mut Vec<Value>, path: &str, value: &Bson) -> (&'static str, String, String) {
+    let extract = format!("json_extract(record, {})", path);
+    match value {
+        Bson::String(s) => ("'text'", extract, param(params, Value::Text(s.clone()))),
+        Bson::Int32(n) => (
+            "'integer','real'",
+            extract,
+            param(params, Value::Integer(*n as i64)),
+        ),
+        Bson::Int64(n) => (
+            "'integer','real'",
+            extract,
+            param(params, Value::Integer(*n)),
+        ),
+        Bson::Double(n) => ("'integer','real'", extract, param(params, Value::Real(*n))),
+        // json_extract reads JSON booleans as 1 and 0.
+        Bson::Boolean(b) => (
+            "'true','false'",
+            extract,
+            param(params, Value::Integer(*b as i64)),
+        ),
+        // Anything else is stored as a JSON object or array, so compare normalised JSON text.
+        other => {
+            let json = other.clone().into_relaxed_extjson().to_string();
+            (
+                "'object','array'",
+                format!("json({})", extract),
+                format!("json({})", param(params, Value::Text(json))),
+            )
+        }
+    }
+}
+
+/// Translates a [Filter] into a SQL condition over the `record` column, adding the values it
+/// compares against to `params`. Conditions are never NULL, so they can be safely negated.
+fn filter_sql(filter: &Filter, params: &mut Vec<Value>) -> String {
+    let compare = |params: &mut Vec<Value>, field: &str, op: &str, value: &Bson| {
+        let path = path_param(params, field);
+        if let Bson::Null = value {
+            // Like MongoDB, null equals missing fields and is neither greater nor less than them.
+            return match op {
+                "=" | ">=" | "<=" => format!("COALESCE(json_type(record, {}) = 'null', 1)", path),
+                _ => "0".to_string(),
+            };
+        }
+        // Like MongoDB, only compare values of the same type.
+        let (types, stored, value) = comparison(params, &path, value);
+        format!(
+            "COALESCE(json_type(record, {}) IN ({}) AND {} {} {}, 0)",
+            path, types, stored, op, value
+        )
+    };
+    let join = |params: &mut Vec<Value>, filters: &[Filter], op: &str, empty: &str| {
+        if filters.is_empty() {
+            return empty.to_string();
+        }
+        let conditions: Vec<String> = filters.iter().map(|f| filter_sql(f, params)).collect();
+        format!("({})", conditions.join(op))
+    };
+    match filter {
+        Filter::All => "1".to_string(),
+        Filter::Eq(field, value) => compare(params, field, "=", value),
+        // Records missing the field match, as with MongoDB.
+        Filter::Ne(field, value) => format!("NOT {}", compare(params, field, "=", value)),
+        Filter::Gt(field, value) => compare(params, field, ">", value),
+        Filter::Gte(field, value) => compare(params, field, ">=", value),
+        Filter::Lt(field, value) => compare(params, field, "<", value),
+        Filter::Lte(field, value) => compare(params, field, "<=", value),
+        Filter::In(field, values) => {
+            let conditions: Vec<Filter> = values
+                .iter()
+                .map(|value| Filter::Eq(field.clone(), value.clone()))
+                .collect();
+            join(params, &conditions, " OR ", "0")
+        }
+        Filter::Exists(field, exists) => {
+            let path = path_param(params, field);
+            match exists {
+                true => format!("json_type(record, {}) IS NOT NULL", path),
+                false => format!("json_type(record, {}) IS NULL", path),
+            }
+        }
+        Filter::And(filters) => join(params, filters, " AND ", "1"),
+        Filter::Or(filters) => join(params, filters, " OR ", "0"),
+        Filter::Not(filter) => format!("NOT {}", filter_sql(filter, params)),
+    }
+}
+
+/// Splits a document into the id and JSON text it is stored as. The document's `_id` is used as
+/// the id if it has one, otherwise a new ObjectId is generated.
+fn to_row(mut doc: Document) -> (String, String) {
+    let id = match doc.remove("_id") {
+        Some(Bson::ObjectId(oid)) => oid.to_hex(),
+        Some(Bson::String(id)) => id,
+        Some(other) => other.to_string(),
+        None => ObjectId::new().to_hex(),
+    };
+    (id, Bson::Document(doc).into_relaxed_extjson().to_string())
+}
+
+/// Reassembles the document stored in a row, including its id as `_id`.
+fn from_row(id: String, record: &str) -> Result<Document> {
+    let record: serde_json::Value =
+        serde_json::from_str(record).context("Invalid stored record")?;
+    let mut doc = match Bson::try_from(record).context("Invalid stored record")? {
+        Bson::Document(doc) => doc,
+        other => anyhow::bail!("Stored record {} is not a document: {}", id, other),
+    };
+    doc.insert("_id", id);
+    Ok(doc)
+}
+
+/// Converts JSON text read by SQLite back to BSON, a missing value being null.
+fn from_json_text(json: Option<String>) -> Result<Bson> {
+    let json = match json {
+        Some(json) => json,
+        None => return Ok(Bson::Null),
+    };
+    let value: serde_json::Value = serde_json::from_str(&json).context("Invalid stored JSON")?;
+    Bson::try_from(value).context("Invalid stored JSON")
+}
+
+/// Parses the database location out of a `sqlite:` URI, `None` meaning an in-memory database.
+fn database_path(uri: &str) -> Option<&str> {
+    let path = uri
+        .strip_prefix("sqlite://")
+        .or_else(|| uri.strip_prefix("sqlite:"))
+        .unwrap_or(uri);
+    match path {
+        ":memory:" | "" => None,
+        path => Some(path),
+    }
+}
+
+pub struct SqliteBackend {
+    pub uri: String,
+    pub datastore: String,
+    /// The connection to the database, opened on first use
+    pub connection: OnceCell<Arc<Mutex<Connection>>>,
+    /// Record types whose tables are known to exist
+    pub tables: Mutex<HashSet<ArchiveRecordType>>,
+}
+
+impl std::fmt::Debug for SqliteBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SqliteBackend")
+            .field("uri", &self.uri)
+            .field("datastore", &self.datastore)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SqliteBackend {
+    /// Creates a backend for the datastore in the database at `uri`. The database isn't opened
+    /// until it is used.
+    pub fn new(uri: &str, datastore: &str) -> Self {
+        SqliteBackend {
+            uri: uri.to_string(),
+            datastore: datastore.to_string(),
+            connection: OnceCell::new(),
+            tables: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Checks that the URI is a `sqlite:` URI naming a database file or `:memory:`.
+    pub fn validate_uri(uri: &str) -> std::result::Result<(), String> {
+        if !uri.starts_with("sqlite:") {
+            return Err(format!(
+                "Invalid SQLite URI '{}': must start with sqlite://, or be sqlite::memory:",
+                uri
+            ));
+        }
+        if uri == "sqlite://" || uri == "sqlite:" {
+            return Err(format!("Invalid SQLite URI '{}': no database given", uri));
+        }
+        Ok(())
+    }
+
+    /// Checks that the datastore name can be used, unquoted, as a table name prefix: ASCII
+    /// letters, digits and underscores, not starting with a digit.
+    pub fn validate_datastore(datastore: &str) -> std::result::Result<(), String> {
+        if datastore.is_empty() {
+            return Err("Datastore name must not be empty".to_string());
+        }
+        if datastore.len() > MAX_DATASTORE_LEN {
+            return Err(format!(
+                "Datastore name '{}' is longer than {} bytes",
+                datastore, MAX_DATASTORE_LEN
+            ));
+        }
+        if datastore.starts_with(|c: char| c.is_ascii_digit())
+            || !datastore
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(format!(
+                "Datastore name '{}' must only contain letters, digits and underscores, and not \
+                start with a digit",
+                datastore
+            ));
+        }
+        Ok(())
+    }
+
+    /// Names of the tables in the database, for listing namespaces.
+    pub(crate) async fn table_names(&self) -> Result<Vec<String>> {
+        self.run(|connection| {
+            let mut statement =
+                connection.prepare("SELECT name FROM sqlite_master WHERE type = 'table'")?;
+            let names = statement
+                .query_map([], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            Ok(names)
+        })
+        .await
+    }
+
+    /// Returns the name of the table used to store records of the given type, creating the table
+    /// if this backend hasn't used it yet.
+    async fn table(&self, rec_type: &ArchiveRecordType) -> Result<String> {
+        let name = match rec_type {
+            ArchiveRecordType::Account => ACCOUNT_TABLE,
+            ArchiveRecordType::TransactionBatch => TRANSACTION_TABLE,
+            ArchiveRecordType::Block => BLOCK_TABLE,
+            ArchiveRecordType::Receipt => RECEIPT_TABLE,
+            ArchiveRecordType::Custom(name) => name,
+        };
+        let table = format!("\"{}_{}\"", self.datastore, name);
+        if self
+            .tables
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains(rec_type)
+        {
+            return Ok(table);
+        }
+
+        let sql = format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                 id TEXT PRIMARY KEY,
+                 record TEXT NOT NULL,
+                 archived_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+             );",
+            table
+        );
+        self.run(move |connection| Ok(connection.execute_batch(&sql)?))
+            .await
+            .context("Failed to create table")?;
+        self.tables
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(rec_type.clone());
+        Ok(table)
+    }
+
+    /// Opens the database the first time it is needed.
+    async fn connection(&self) -> Result<Arc<Mutex<Connection>>> {
+        let connection = self
+            .connection
+            .get_or_try_init(|| async {
+                let path = database_path(&self.uri).map(str::to_string);
+                let connection = tokio::task::spawn_blocking(move || -> Result<Connection> {
+                    let connection = match &path {
+                        Some(path) => {
+                            let connection = Connection::open(path).with_context(|| {
+                                format!("Failed to open SQLite database {}", path)
+                            })?;
+                            // Let readers carry on while records are being written.
+                            connection.pragma_update(None, "journal_mode", "WAL")?;
+                            connection
+                        }
+                        None => Connection::open_in_memory()
+                            .context("Failed to open in-memory SQLite database")?,
+                    };
+                    Ok(connection)
+                })
+                .await
+                .context("SQLite task failed")??;
+                Ok::<_, anyhow::Error>(Arc::new(Mutex::new(connection)))
+            })
+            .await?;
+        Ok(connection.clone())
+    }
+
+    /// Runs `f` with the connection on the blocking thread pool.
+    async fn run<R, F>(&self, f: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<R> + Send + 'static,
+    {
+        let connection = self.connection().await?;
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection
+                .lock()
+                .map_err(|_| anyhow::anyhow!("SQLite connection poisoned"))?;
+            f(&mut connection)
+        })
+        .await
+        .context("SQLite task failed")?
+    }
+
+    /// Reads the records of the given type matching a SQL condition, in id order, optionally
+    /// stopping after `limit` records.
+    async fn select(
+        &self,
+        rec_type: &ArchiveRecordType,
+        condition: String,
+        params: Vec<Value>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Document>> {
+        let mut sql = format!(
+            "SELECT id, record FROM {} WHERE {} ORDER BY id",
+            self.table(rec_type).await?,
+            condition
+        );
+        if let Some(limit) = limit {
+            sql.push_str(&format!(" LIMIT {}", limit));
+        }
+        self.run(move |connection| {
+            let mut statement = connection.prepare(&sql)?;
+            let rows = statement.query_map(params_from_iter(params), |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?;
+            rows.map(|row| {
+                let (id, record) = row?;
+                from_row(id, &record)
+            })
+            .collect()
+        })
+        .await
+        .context("Failed to find records")
+    }
+
+    /// Reads up to `limit` records of the given type with ids after `after`, in id order.
+    async fn select_after(
+        &self,
+        rec_type: &ArchiveRecordType,
+        after: String,
+        limit: usize,
+    ) -> Result<Vec<Document>> {
+        self.select(
+            rec_type,
+            "id > ?1".to_string(),
+            vec![Value::Text(after)],
+            Some(limit),
+        )
+        .await
+    }
+
+    /// Deletes the records of the given type matching a SQL condition, returning how many were
+    /// deleted.
+    async fn delete(
+        &self,
+        rec_type: &ArchiveRecordType,
+        condition: String,
+        params: Vec<Value>,
+    ) -> Result<u64> {
+        let sql = format!(
+            "DELETE FROM {} WHERE {}",
+            self.table(rec_type).await?,
+            condition
+        );
+        let deleted = self
+            .run(move |connection| Ok(connection.execute(&sql, params_from_iter(params))?))
+            .await
+            .context("Failed to delete records")?;
+
+        debug!("Deleted {} records", deleted);
+        Ok(deleted as u64)
+    }
+
+    /// Inserts records into their tables within a single transaction, returning their ids.
+    async fn insert(&self, records: Vec<(ArchiveRecordType, Document)>) -> Result<Vec<String>> {
+        let mut rows: Vec<(String, String, String)> = Vec::with_capacity(records.len());
+        for (rec_type, rec) in records {
+            let (id, record) = to_row(rec);
+            rows.push((self.table(&rec_type).await?, id, record));
+        }
+
+        let ids = self
+            .run(move |connection| {
+                // Dropping the transaction on error rolls it back.
+                let transaction = connection.transaction()?;
+                let mut ids = Vec::with_capacity(rows.len());
+                for (table, id, record) in rows {
+                    transaction.execute(
+                        &format!("INSERT INTO {} (id, record) VALUES (?1, ?2)", table),
+                        (&id, &record),
+                    )?;
+                    ids.push(id);
+                }
+                transaction.commit()?;
+                Ok(ids)
+            })
+            .await
+            .context("Failed to insert records")?;
+
+        debug!("Inserted {} records", ids.len());
+        Ok(ids)
+    }
+}
+
+/// Decodes the documents read from the database into records.
+fn decode<T: DeserializeOwned>(docs: Vec<Document>) -> Result<Vec<T>> {
+    docs.into_iter()
+        .map(|doc| bson::from_document(doc).context("Failed to deserialise record"))
+        .collect()
+}
+
+#[async_trait]
+impl ArchiveBackend for SqliteBackend {
+    async fn create<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+        let mut ids = self.insert(vec![(rec_type, doc)]).await?;
+        Ok(ids.remove(0))
+    }
+
+    /// Inserts every record within one transaction, so the batch is always atomic and the
+    /// ordering setting has no effect.
+    async fn create_many<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        recs: Vec<T>,
+    ) -> Result<Vec<String>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let records = recs
+            .iter()
+            .map(|rec| {
+                let doc = bson::to_document(rec).context("Failed to serialise record to BSON")?;
+                Ok((rec_type.clone(), doc))
+            })
+            .collect::<Result<_>>()?;
+        Ok(self.insert(records).await?)
+    }
+
+    /// Inserts the records within a transaction, which is only committed once every insert has
+    /// succeeded and is rolled back otherwise.
+    async fn create_atomic(
+        &self,
+        records: Vec<(ArchiveRecordType, Document)>,
+    ) -> Result<Vec<String>, ArchiveError> {
+        Ok(self.insert(records).await?)
+    }
+
+    async fn find_all<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        Ok(decode(
+            self.select(&rec_type, "1".to_string(), Vec::new(), None)
+                .await?,
+        )?)
+    }
+
+    /// Streams the records in batches of [STREAM_BATCH_SIZE], each read after the last id of the
+    /// previous batch, so only one batch is held in memory at a time.
+    async fn find_all_stream<'a, T: DeserializeOwned>(
+        &'a self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'a, Result<T, ArchiveError>>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin + 'a,
+    {
+        let batches = stream::try_unfold(Some(String::new()), move |after| {
+            let rec_type = rec_type.clone();
+            async move {
+                let after = match after {
+                    Some(after) => after,
+                    None => return Ok::<_, anyhow::Error>(None),
+                };
+                let batch = self
+                    .select_after(&rec_type, after, STREAM_BATCH_SIZE)
+                    .await?;
+                let next = match batch.len() {
+                    STREAM_BATCH_SIZE => batch
+                        .last()
+                        .and_then(|doc| doc.get_str("_id").ok())
+                        .map(str::to_string),
+                    _ => None,
+                };
+                Ok(Some((batch, next)))
+            }
+        });
+
+        Ok(batches
+            .map_ok(|batch| stream::iter(batch.into_iter().map(Ok)))
+            .try_flatten()
+            .and_then(|doc| async move {
+                bson::from_document(doc).context("Failed to deserialise record")
+            })
+            .map_err(ArchiveError::from)
+            .boxed())
+    }
+
+    async fn find_page<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        request: &PageRequest,
+    ) -> Result<Page<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let after = request.after_token.clone().unwrap_or_default();
+        // Read one more record than requested to tell whether there is a next page.
+        let mut docs = self
+            .select_after(&rec_type, after, request.limit + 1)
+            .await?;
+
+        let next_token = if docs.len() > request.limit {
+            docs.truncate(request.limit);
+            docs.last()
+                .and_then(|doc| doc.get_str("_id").ok())
+                .map(str::to_string)
+        } else {
+            None
+        };
+        Ok(Page {
+            items: decode(docs)?,
+            next_token,
+        })
+    }
+
+    /// Query data store for the records of the given type matching a [Filter], translated into a
+    /// SQL condition using SQLite's JSON functions.
+    async fn query<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let mut params = Vec::new();
+        let condition = filter_sql(filter, &mut params);
+        Ok(decode(
+            self.select(&rec_type, condition, params, None).await?,
+        )?)
+    }
+
+    /// Reads each field's JSON with the `->` operator, so only those fields are converted.
+    async fn find_projected(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        fields: &[String],
+    ) -> Result<Vec<Document>, ArchiveError> {
+        let mut params = Vec::new();
+        let columns: Vec<String> = fields
+            .iter()
+            .map(|field| format!("record -> {}", path_param(&mut params, field)))
+            .collect();
+        let condition = filter_sql(filter, &mut params);
+        let sql = format!(
+            "SELECT id, {} FROM {} WHERE {} ORDER BY id",
+            columns.join(", "),
+            self.table(&rec_type).await?,
+            condition
+        );
+        let fields = fields.to_vec();
+        let docs = self
+            .run(move |connection| {
+                let mut statement = connection.prepare(&sql)?;
+                let rows = statement.query_map(params_from_iter(params), |row| {
+                    let values = (1..=fields.len())
+                        .map(|i| row.get::<_, Option<String>>(i))
+                        .collect::<rusqlite::Result<Vec<_>>>()?;
+                    Ok((row.get::<_, String>(0)?, values))
+                })?;
+                rows.map(|row| {
+                    let (id, values) = row?;
+                    let mut doc = Document::new();
+                    doc.insert("_id", id);
+                    // Missing fields are read as NULL, JSON nulls as the text `null`.
+                    for (field, json) in fields.iter().zip(values) {
+                        if let Some(json) = json {
+                            projection::insert(&mut doc, field, from_json_text(Some(json))?);
+                        }
+                    }
+                    Ok(doc)
+                })
+                .collect()
+            })
+            .await
+            .context("Failed to find records")?;
+        Ok(docs)
+    }
+
+    async fn find_by_id<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<Option<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let docs = self
+            .select(
+                &rec_type,
+                "id = ?1".to_string(),
+                vec![Value::Text(id.to_string())],
+                Some(1),
+            )
+            .await?;
+        Ok(decode(docs)?.into_iter().next())
+    }
+
+    async fn delete_by_id(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<bool, ArchiveError> {
+        let deleted = self
+            .delete(
+                &rec_type,
+                "id = ?1".to_string(),
+                vec![Value::Text(id.to_string())],
+            )
+            .await?;
+        Ok(deleted > 0)
+    }
+
+    async fn delete_where(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        let mut params = Vec::new();
+        let condition = filter_sql(filter, &mut params);
+        Ok(self.delete(&rec_type, condition, params).await?)
+    }
+
+    /// Replaces the stored record, keeping its id whatever `_id` the record holds.
+    async fn update_by_id<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        rec: T,
+    ) -> Result<bool, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let (_, record) =
+            to_row(bson::to_document(&rec).context("Failed to serialise record to BSON")?);
+        let sql = format!(
+            "UPDATE {} SET record = ?2 WHERE id = ?1",
+            self.table(&rec_type).await?
+        );
+        let key = id.to_string();
+        let updated = self
+            .run(move |connection| Ok(connection.execute(&sql, (&key, &record))?))
+            .await
+            .context("Failed to update record")?;
+
+        debug!("Updated {}", id);
+        Ok(updated > 0)
+    }
+
+    /// Looks up the first matching record, in id order, and replaces or inserts within a single
+    /// transaction.
+    async fn upsert<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let (new_id, record) =
+            to_row(bson::to_document(&rec).context("Failed to serialise record to BSON")?);
+        let mut params = Vec::new();
+        let condition = filter_sql(filter, &mut params);
+        let table = self.table(&rec_type).await?;
+
+        let id = self
+            .run(move |connection| {
+                // Dropping the transaction on error rolls it back.
+                let transaction = connection.transaction()?;
+                let existing = transaction
+                    .query_row(
+                        &format!(
+                            "SELECT id FROM {} WHERE {} ORDER BY id LIMIT 1",
+                            table, condition
+                        ),
+                        params_from_iter(params),
+                        |row| row.get::<_, String>(0),
+                    )
+                    .optional()?;
+                let id = match existing {
+                    Some(id) => {
+                        transaction.execute(
+                            &format!("UPDATE {} SET record = ?2 WHERE id = ?1", table),
+                            (&id, &record),
+                        )?;
+                        id
+                    }
+                    None => {
+                        transaction.execute(
+                            &format!("INSERT INTO {} (id, record) VALUES (?1, ?2)", table),
+                            (&new_id, &record),
+                        )?;
+                        new_id
+                    }
+                };
+                transaction.commit()?;
+                Ok(id)
+            })
+            .await
+            .context("Failed to upsert record")?;
+
+        debug!("Upserted {}", id);
+        Ok(id)
+    }
+
+    /// Returns a random sample of roughly `rate` (0.0 to 1.0) of the records of the given type,
+    /// selecting each record independently with probability `rate`.
+    async fn find_sampled<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rate: f64,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        // random() is uniform over the 64 bit integers, so scale it to [0, 1).
+        let condition = "random() / 18446744073709551616.0 + 0.5 < ?1".to_string();
+        Ok(decode(
+            self.select(&rec_type, condition, vec![Value::Real(rate)], None)
+                .await?,
+        )?)
+    }
+
+    /// Records are never chunked, so there are never orphaned chunks.
+    async fn find_orphaned_chunks(
+        &self,
+        _rec_type: ArchiveRecordType,
+    ) -> Result<Vec<String>, ArchiveError> {
+        Ok(Vec::new())
+    }
+
+    /// Records are never chunked, so there are never orphaned chunks.
+    async fn cleanup_orphans(&self, _rec_type: ArchiveRecordType) -> Result<u64, ArchiveError> {
+        Ok(0)
+    }
+
+    /// Rebuilds the whole database file with `VACUUM`, as SQLite can't reclaim the space of a
+    /// single table, then checkpoints the write-ahead log so the file shrinks straight away.
+    /// Operations wait for it to finish, and it needs as much free disk space as the database's
+    /// size while it runs.
+    async fn reclaim_space(&self, _rec_type: ArchiveRecordType) -> Result<(), ArchiveError> {
+        self.run(|connection| {
+            connection.execute_batch("VACUUM")?;
+            // Returns the checkpoint's progress, and is a no-op for in-memory databases.
+            connection.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+            Ok(())
+        })
+        .await
+        .context("Failed to vacuum database")?;
+        debug!("Vacuumed database");
+        Ok(())
+    }
+
+    /// Aggregation pipelines are MongoDB specific.
+    async fn merge_into(
+        &self,
+        _source: ArchiveRecordType,
+        _pipeline: Vec<Document>,
+        _target: ArchiveRecordType,
+        _mode: MergeMode,
+    ) -> Result<u64, ArchiveError> {
+        Err(Unsupported {
+            operation: "merge_into",
+            reason: "aggregation pipelines are only supported by the MongoDB backend".to_string(),
+        }
+        .into())
+    }
+
+    /// Changes are only reported by the store that made them.
+    async fn watch(
+        &self,
+        _rec_type: ArchiveRecordType,
+    ) -> Result<BoxStream<'static, Result<ArchiveEvent, ArchiveError>>, ArchiveError> {
+        Err(Unsupported {
+            operation: "watch",
+            reason: "change streams are only supported by the MongoDB backend".to_string(),
+        }
+        .into())
+    }
+
+    async fn count(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<u64, ArchiveError> {
+        let mut params = Vec::new();
+        let condition = filter_sql(filter, &mut params);
+        let sql = format!(
+            "SELECT count(*) FROM {} WHERE {}",
+            self.table(&rec_type).await?,
+            condition
+        );
+        Ok(self
+            .run(move |connection| {
+                Ok(connection
+                    .query_row(&sql, params_from_iter(params), |row| row.get::<_, i64>(0))?
+                    as u64)
+            })
+            .await
+            .context("Failed to count records")?)
+    }
+
+    async fn exists(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<bool, ArchiveError> {
+        let mut params = Vec::new();
+        let condition = filter_sql(filter, &mut params);
+        let sql = format!(
+            "SELECT EXISTS (SELECT 1 FROM {} WHERE {})",
+            self.table(&rec_type).await?,
+            condition
+        );
+        Ok(self
+            .run(move |connection| {
+                Ok(connection.query_row(&sql, params_from_iter(params), |row| row.get(0))?)
+            })
+            .await
+            .context("Failed to look for records")?)
+    }
+
+    /// Counts the records and totals the size of their stored JSON. SQLite doesn't report the
+    /// space used by each table, so the storage excludes page and index overheads.
+    async fn stats(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<ArchiveCollectionStats, ArchiveError> {
+        let sql = format!(
+            "SELECT count(*), COALESCE(sum(length(CAST(record AS BLOB))), 0) FROM {}",
+            self.table(&rec_type).await?
+        );
+        let (document_count, storage_bytes) = self
+            .run(move |connection| {
+                Ok(connection.query_row(&sql, [], |row| {
+                    Ok((row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)? as u64))
+                })?)
+            })
+            .await
+            .context("Failed to read table statistics")?;
+
+        Ok(ArchiveCollectionStats {
+            document_count,
+            storage_bytes,
+            avg_doc_bytes: storage_bytes.checked_div(document_count).unwrap_or(0),
+        })
+    }
+
+    async fn group_count(
+        &self,
+        rec_type: ArchiveRecordType,
+        group_by: GroupBy,
+    ) -> Result<Vec<(Bson, u64)>, ArchiveError> {
+        let mut params = Vec::new();
+        // Keys are read as JSON text, so values of any type can be grouped and converted back.
+        let key = match &group_by {
+            GroupBy::Field(field) => {
+                format!("record -> {}", path_param(&mut params, field))
+            }
+            GroupBy::ArchivedAt(granularity) => format!(
+                "json_quote(strftime({}, archived_at))",
+                param(
+                    &mut params,
+                    Value::Text(granularity.date_format().to_string())
+                )
+            ),
+        };
+        let sql = format!(
+            "SELECT {} AS key, count(*) FROM {} GROUP BY key ORDER BY key",
+            key,
+            self.table(&rec_type).await?
+        );
+
+        let groups = self
+            .run(move |connection| {
+                let mut statement = connection.prepare(&sql)?;
+                let rows = statement.query_map(params_from_iter(params), |row| {
+                    Ok((row.get::<_, Option<String>>(0)?, row.get::<_, i64>(1)?))
+                })?;
+                Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+            })
+            .await
+            .context("Failed to group records")?;
+
+        groups
+            .into_iter()
+            .map(|(key, count)| Ok((from_json_text(key)?, count as u64)))
+            .collect()
+    }
+
+    /// Aggregates the records matching the filter in process.
+    async fn aggregate(
+        &self,
+        rec_type: ArchiveRecordType,
+        spec: &AggregationSpec,
+    ) -> Result<Vec<Document>, ArchiveError> {
+        let docs = self.query::<Document>(rec_type, &spec.filter).await?;
+        Ok(aggregate(spec, docs))
+    }
+
+    /// Creates an index over the `json_extract` of each field's path.
+    async fn ensure_indexes(
+        &self,
+        rec_type: ArchiveRecordType,
+        indexes: &[IndexSpec],
+    ) -> Result<(), ArchiveError> {
+        if indexes.is_empty() {
+            return Ok(());
+        }
+        let table = self.table(&rec_type).await?;
+        let name = table.trim_matches('"').to_string();
+
+        let mut sql = String::new();
+        for index in indexes {
+            let paths: Vec<String> = index
+                .fields
+                .iter()
+                .map(|field| {
+                    let path: String = field
+                        .split('.')
+                        .map(|key| format!(".\"{}\"", key))
+                        .collect();
+                    format!("json_extract(record, '${}')", path)
+                })
+                .collect();
+            sql.push_str(&format!(
+                "CREATE {}INDEX IF NOT EXISTS \"{}\" ON {} ({});",
+                if index.unique { "UNIQUE " } else { "" },
+                index.name(&name),
+                table,
+                paths.join(", ")
+            ));
+        }
+        self.run(move |connection| Ok(connection.execute_batch(&sql)?))
+            .await
+            .context("Failed to create indexes")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+/// Atomic batches on backends without transactions, the filesystem and S3 backends. A batch is
+/// staged before any of its records are written: every record is given its id, and the whole
+/// batch is written to a single staging file, or object, under [STAGING_DIR], which is the
+/// batch's commit marker. The records are then written to their own files, and the staged batch
+/// deleted.
+///
+/// If a write fails, the records already written are deleted again before the staged batch is.
+/// If the process crashes part way, the batch stays staged, and is written again in full by the
+/// backend's next atomic write, or by [crate::ArchiveStore::recover_atomic_batches], so readers
+/// may see part of a batch until then. As records are written under the ids they were given when
+/// staged, writing a batch again only replaces the records already written.
+use crate::{filter, ArchiveRecordType};
+use anyhow::{Context, Result};
+use bson::{oid::ObjectId, Document};
+use serde_derive::{Deserialize, Serialize};
+
+/// Directory, or key prefix, holding the staged batches
+pub(crate) const STAGING_DIR: &str = "_staging";
+
+/// The records of an atomic batch, as staged.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct StagedBatch {
+    pub(crate) records: Vec<(ArchiveRecordType, Document)>,
+}
+
+impl StagedBatch {
+    /// Stages records, giving those without an `_id` a new ObjectId, so that the batch writes the
+    /// same records however many times it's written.
+    pub(crate) fn new(mut records: Vec<(ArchiveRecordType, Document)>) -> StagedBatch {
+        for (_, doc) in &mut records {
+            if !doc.contains_key("_id") {
+                doc.insert("_id", ObjectId::new());
+            }
+        }
+        StagedBatch { records }
+    }
+
+    /// The ids of the records, in the order given.
+    pub(crate) fn ids(&self) -> Vec<String> {
+        self.records
+            .iter()
+            .filter_map(|(_, doc)| doc.get("_id"))
+            .map(filter::id_to_string)
+            .collect()
+    }
+
+    /// Name of the staging file, or object, of a new batch, ordering batches by when they were
+    /// staged.
+    pub(crate) fn name() -> String {
+        format!("{}.bson", ObjectId::new().to_hex())
+    }
+
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>> {
+        bson::to_vec(self).context("Failed to serialise staged batch")
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<StagedBatch> {
+        bson::from_slice(bytes).context("Invalid staged batch")
+    }
+}
@@ -0,0 +1,352 @@
+/// Types describing basic statistics over archived records, as returned by
+/// [crate::ArchiveStore::stats], [crate::ArchiveStore::group_count] and
+/// [crate::ArchiveStore::aggregate].
+use crate::envelope::{object_id, ARCHIVED_AT_FIELD};
+use crate::filter::{compare, lookup};
+use crate::Filter;
+use bson::{Bson, Document};
+use core::fmt;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// Storage statistics for the records of a single record type. All values are zero for a record
+/// type that has nothing archived yet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveCollectionStats {
+    /// Estimated number of records
+    pub document_count: u64,
+    /// Bytes of storage allocated to the records
+    pub storage_bytes: u64,
+    /// Average size of a record in bytes
+    pub avg_doc_bytes: u64,
+}
+
+/// Size of the date buckets records are grouped into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    Hour,
+    Day,
+    Month,
+}
+
+impl Granularity {
+    /// `$dateToString` format producing the bucket key of a date.
+    #[cfg(any(feature = "mongodb", feature = "sqlite"))]
+    pub(crate) fn date_format(&self) -> &'static str {
+        match self {
+            Granularity::Hour => "%Y-%m-%dT%H",
+            Granularity::Day => "%Y-%m-%d",
+            Granularity::Month => "%Y-%m",
+        }
+    }
+
+    /// The bucket key of a time, formatted as by [Granularity::date_format], for backends that
+    /// bucket records themselves.
+    pub(crate) fn bucket(&self, time: bson::DateTime) -> String {
+        let len = match self {
+            Granularity::Hour => "YYYY-MM-DDTHH".len(),
+            Granularity::Day => "YYYY-MM-DD".len(),
+            Granularity::Month => "YYYY-MM".len(),
+        };
+        let mut key = time.try_to_rfc3339_string().unwrap_or_default();
+        key.truncate(len);
+        key
+    }
+}
+
+/// What to group records by when counting them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupBy {
+    /// The value of a (possibly dotted) field of the stored records. Records missing the field
+    /// are counted under a `null` key. Fields of compressed records can't be grouped on.
+    Field(String),
+    /// The time each record was archived, bucketed by the given granularity. Keys are formatted
+    /// dates, e.g. `2024-05-02` for [Granularity::Day].
+    ArchivedAt(Granularity),
+}
+
+impl fmt::Display for GroupBy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GroupBy::Field(field) => write!(f, "{}", field),
+            GroupBy::ArchivedAt(granularity) => write!(f, "archived at ({:?})", granularity),
+        }
+    }
+}
+
+/// Counts how many times each key occurs, for backends that group records themselves. Groups are
+/// ordered by the JSON of their keys, as BSON values can't be ordered or hashed.
+pub(crate) fn tally(keys: impl IntoIterator<Item = Bson>) -> Vec<(Bson, u64)> {
+    let mut groups: BTreeMap<String, (Bson, u64)> = BTreeMap::new();
+    for key in keys {
+        groups
+            .entry(key.clone().into_relaxed_extjson().to_string())
+            .or_insert((key, 0))
+            .1 += 1;
+    }
+    groups.into_values().collect()
+}
+
+/// How an output field of an [AggregationSpec] is computed from the records in a group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Aggregate {
+    /// The number of records
+    Count,
+    /// The sum of a numeric field, ignoring records where it's missing or not a number
+    Sum(String),
+    /// The least value of a field, ignoring records where it's missing or null
+    Min(String),
+    /// The greatest value of a field, ignoring records where it's missing or null
+    Max(String),
+}
+
+/// A backend-neutral aggregation run by [crate::ArchiveStore::aggregate]: the records matching
+/// a filter are grouped, and output fields are computed over each group, e.g. the number of
+/// transaction batches and their total fees per day with
+/// `AggregationSpec::grouped_by(GroupBy::ArchivedAt(Granularity::Day))` followed by
+/// `.count("batches").sum("fees", "fee")`. As with [crate::ArchiveStore::query], fields of
+/// compressed or chunked records can't be aggregated.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AggregationSpec {
+    /// The records aggregated. Defaults to every record.
+    pub filter: Filter,
+    /// What records are grouped by. Every record aggregated is in one group when unset.
+    pub group_by: Option<GroupBy>,
+    /// Name of each output field and how it's computed, in order
+    pub outputs: Vec<(String, Aggregate)>,
+}
+
+impl AggregationSpec {
+    /// Aggregates every record into one group.
+    pub fn new() -> Self {
+        AggregationSpec::default()
+    }
+
+    /// Aggregates the records in each group.
+    pub fn grouped_by(group_by: GroupBy) -> Self {
+        AggregationSpec {
+            group_by: Some(group_by),
+            ..AggregationSpec::default()
+        }
+    }
+
+    /// Only aggregates the records matching `filter`.
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Outputs the number of records in the group as `name`.
+    pub fn count(self, name: &str) -> Self {
+        self.output(name, Aggregate::Count)
+    }
+
+    /// Outputs the sum of `field` over the group as `name`.
+    pub fn sum(self, name: &str, field: &str) -> Self {
+        self.output(name, Aggregate::Sum(field.to_string()))
+    }
+
+    /// Outputs the least value of `field` in the group as `name`.
+    pub fn min(self, name: &str, field: &str) -> Self {
+        self.output(name, Aggregate::Min(field.to_string()))
+    }
+
+    /// Outputs the greatest value of `field` in the group as `name`.
+    pub fn max(self, name: &str, field: &str) -> Self {
+        self.output(name, Aggregate::Max(field.to_string()))
+    }
+
+    /// Outputs `aggregate` over the group as `name`.
+    pub fn output(mut self, name: &str, aggregate: Aggregate) -> Self {
+        self.outputs.push((name.to_string(), aggregate));
+        self
+    }
+
+    /// Checks that the output names are distinct, plain field names other than `key`, and that
+    /// the fields aggregated and grouped by are (possibly dotted) field names, describing the
+    /// problem if not.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let valid = |field: &str| {
+            field.split('.').all(|key| {
+                !key.is_empty()
+                    && key
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            })
+        };
+        if self.outputs.is_empty() {
+            return Err("An aggregation must have at least one output".to_string());
+        }
+        if let Some(GroupBy::Field(field)) = &self.group_by {
+            if !valid(field) {
+                return Err(format!("Invalid group by field '{}'", field));
+            }
+        }
+        for (i, (name, aggregate)) in self.outputs.iter().enumerate() {
+            if !valid(name) || name.contains('.') || name == KEY_FIELD {
+                return Err(format!(
+                    "Invalid aggregation output '{}': must be a field name other than '{}' of \
+                    ASCII letters, digits, '_' or '-'",
+                    name, KEY_FIELD
+                ));
+            }
+            if self.outputs[..i].iter().any(|(other, _)| other == name) {
+                return Err(format!("Duplicate aggregation output '{}'", name));
+            }
+            match aggregate {
+                Aggregate::Count => {}
+                Aggregate::Sum(field) | Aggregate::Min(field) | Aggregate::Max(field) => {
+                    if !valid(field) {
+                        return Err(format!("Invalid aggregated field '{}'", field));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Field of each aggregation result holding its group's key
+pub(crate) const KEY_FIELD: &str = "key";
+
+/// The running value of an [Aggregate] over the records of a group seen so far
+enum Accumulator {
+    Count(i64),
+    Sum {
+        int: i64,
+        float: f64,
+        is_float: bool,
+    },
+    Min(Option<Bson>),
+    Max(Option<Bson>),
+}
+
+impl Accumulator {
+    fn new(aggregate: &Aggregate) -> Self {
+        match aggregate {
+            Aggregate::Count => Accumulator::Count(0),
+            Aggregate::Sum(_) => Accumulator::Sum {
+                int: 0,
+                float: 0.0,
+                is_float: false,
+            },
+            Aggregate::Min(_) => Accumulator::Min(None),
+            Aggregate::Max(_) => Accumulator::Max(None),
+        }
+    }
+
+    /// Adds the value of the aggregated field of a record, if it has one.
+    fn add(&mut self, value: Option<&Bson>) {
+        match (self, value) {
+            (Accumulator::Count(count), _) => *count += 1,
+            (
+                Accumulator::Sum {
+                    int,
+                    float,
+                    is_float,
+                },
+                Some(value),
+            ) => match value {
+                Bson::Int32(n) => add_int(int, float, is_float, (*n).into()),
+                Bson::Int64(n) => add_int(int, float, is_float, *n),
+                Bson::Double(n) => {
+                    *float += n;
+                    *is_float = true;
+                }
+                _ => {}
+            },
+            (Accumulator::Min(best), Some(value)) => keep(best, value, Ordering::Less),
+            (Accumulator::Max(best), Some(value)) => keep(best, value, Ordering::Greater),
+            _ => {}
+        }
+    }
+
+    fn finish(self) -> Bson {
+        match self {
+            Accumulator::Count(count) => Bson::Int64(count),
+            Accumulator::Sum {
+                int,
+                float,
+                is_float,
+            } => match is_float {
+                true => Bson::Double(int as f64 + float),
+                false => Bson::Int64(int),
+            },
+            Accumulator::Min(best) | Accumulator::Max(best) => best.unwrap_or(Bson::Null),
+        }
+    }
+}
+
+/// Adds an integer to a sum, switching to floating point if it overflows.
+fn add_int(int: &mut i64, float: &mut f64, is_float: &mut bool, n: i64) {
+    match int.checked_add(n) {
+        Some(sum) => *int = sum,
+        None => {
+            *float += n as f64;
+            *is_float = true;
+        }
+    }
+}
+
+/// Replaces the best value so far with `value` if it's ordered `better` than it.
+fn keep(best: &mut Option<Bson>, value: &Bson, better: Ordering) {
+    if *value == Bson::Null {
+        return;
+    }
+    match best {
+        Some(current) if compare(value, current) != Some(better) => {}
+        _ => *best = Some(value.clone()),
+    }
+}
+
+/// Runs an aggregation over stored records, for backends that can't aggregate records
+/// themselves. The records must already match the spec's filter. Returns a document per group,
+/// ordered by key.
+pub(crate) fn aggregate(spec: &AggregationSpec, docs: Vec<Document>) -> Vec<Document> {
+    let mut groups: BTreeMap<String, (Bson, Vec<Accumulator>)> = BTreeMap::new();
+    for doc in docs {
+        let key = match &spec.group_by {
+            None => Bson::Null,
+            Some(GroupBy::Field(field)) => lookup(&doc, field).cloned().unwrap_or(Bson::Null),
+            Some(GroupBy::ArchivedAt(granularity)) => {
+                let archived_at = match doc.get(ARCHIVED_AT_FIELD) {
+                    Some(Bson::Int64(millis)) => Some(bson::DateTime::from_millis(*millis)),
+                    _ => object_id(&doc).map(|oid| oid.timestamp()),
+                };
+                archived_at
+                    .map(|time| Bson::String(granularity.bucket(time)))
+                    .unwrap_or(Bson::Null)
+            }
+        };
+        let (_, accumulators) = groups
+            .entry(key.clone().into_relaxed_extjson().to_string())
+            .or_insert_with(|| {
+                let accumulators = spec.outputs.iter().map(|(_, a)| Accumulator::new(a));
+                (key, accumulators.collect())
+            });
+        for ((_, aggregate), accumulator) in spec.outputs.iter().zip(accumulators) {
+            let value = match aggregate {
+                Aggregate::Count => None,
+                Aggregate::Sum(field) | Aggregate::Min(field) | Aggregate::Max(field) => {
+                    lookup(&doc, field)
+                }
+            };
+            accumulator.add(value);
+        }
+    }
+
+    let mut groups: Vec<(Bson, Vec<Accumulator>)> = groups.into_values().collect();
+    // Keys of different types keep the order of their JSON.
+    groups.sort_by(|(a, _), (b, _)| compare(a, b).unwrap_or(Ordering::Equal));
+    groups
+        .into_iter()
+        .map(|(key, accumulators)| {
+            let mut doc = Document::new();
+            doc.insert(KEY_FIELD, key);
+            for ((name, _), accumulator) in spec.outputs.iter().zip(accumulators) {
+                doc.insert(name, accumulator.finish());
+            }
+            doc
+        })
+        .collect()
+}
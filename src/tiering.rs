@@ -0,0 +1,215 @@
+/// Tiered storage. Records are archived to a store's own ("hot") backend, e.g. MongoDB, and moved
+/// to a cheaper "cold" store, e.g. S3, once older than [TieringPolicy::hot_age], so that
+/// long-running chains don't keep their whole history in the hot database.
+use crate::envelope::{object_id, Provenance};
+use crate::{
+    ArchiveError, ArchiveErrorKind, ArchiveRecordType, ArchiveStore, CreateOutcome, Filter, Labels,
+};
+use bson::{oid::ObjectId, Document};
+use futures::TryStreamExt;
+use log::{debug, warn};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::time::MissedTickBehavior;
+
+/// Where and when records are moved out of a store's own backend, set with
+/// [crate::ArchiveStoreBuilder::tiering]. Records are moved by
+/// [ArchiveStore::migrate_to_cold_tier], or in the background every
+/// [TieringPolicy::migration_interval].
+///
+/// A record's age is taken from the time its `_id` was generated, so records archived with ids
+/// that aren't object ids stay in the hot tier. Reads by id, queries, counts, streams, updates and
+/// deletes cover both tiers, with records from the hot tier first. Other operations only see the
+/// hot tier. A record being moved may briefly be read from both tiers.
+#[derive(Debug, Clone)]
+pub struct TieringPolicy {
+    /// The store records are moved to. It can't be tiered itself.
+    pub cold: ArchiveStore,
+    /// How long records stay in the hot tier after they are archived
+    pub hot_age: Duration,
+    /// How often records are moved in the background. By default they are only moved by calling
+    /// [ArchiveStore::migrate_to_cold_tier].
+    pub migration_interval: Option<Duration>,
+    /// The record types that are moved. Defaults to the built-in record types, custom record types
+    /// must be added to be moved.
+    pub record_types: Vec<ArchiveRecordType>,
+}
+
+impl TieringPolicy {
+    /// Moves records of the built-in record types to `cold` once older than `hot_age`, when
+    /// [ArchiveStore::migrate_to_cold_tier] is called.
+    pub fn new(cold: ArchiveStore, hot_age: Duration) -> Self {
+        TieringPolicy {
+            cold,
+            hot_age,
+            migration_interval: None,
+            record_types: vec![
+                ArchiveRecordType::Account,
+                ArchiveRecordType::TransactionBatch,
+                ArchiveRecordType::Block,
+                ArchiveRecordType::Receipt,
+            ],
+        }
+    }
+
+    /// Checks that the settings can be used together, describing the problem if not.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.cold.inner.tiering.is_some() {
+            return Err(
+                "Invalid tiering policy: the cold store can't be tiered itself".to_string(),
+            );
+        }
+        if self
+            .migration_interval
+            .is_some_and(|interval| interval.is_zero())
+        {
+            return Err(
+                "Invalid tiering policy: migration_interval must be greater than zero".to_string(),
+            );
+        }
+        for rec_type in &self.record_types {
+            rec_type.validate()?;
+        }
+        Ok(())
+    }
+}
+
+impl ArchiveStore {
+    /// The store records are moved to by the store's [TieringPolicy], if it has one.
+    pub(crate) fn cold_tier(&self) -> Option<&ArchiveStore> {
+        self.inner.tiering.as_ref().map(|tiering| &tiering.cold)
+    }
+
+    /// Moves the records older than [TieringPolicy::hot_age] to the cold tier, returning the
+    /// number moved. Each record is written to the cold tier before it is deleted from the hot
+    /// tier, so a failure part way leaves it in one tier or both, never neither, and it is moved
+    /// by the next call. Records the cold tier spills are left in the hot tier until then too.
+    /// Does nothing when the store isn't tiered.
+    pub async fn migrate_to_cold_tier(&self) -> Result<u64, ArchiveError> {
+        let tiering = match &self.inner.tiering {
+            Some(tiering) => tiering,
+            None => return Ok(0),
+        };
+        let _guard = self.inner.tier_migration_lock.lock().await;
+
+        let cutoff = SystemTime::now()
+            .checked_sub(tiering.hot_age)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let mut moved = 0;
+        for rec_type in &tiering.record_types {
+            // Collect the aged records' ids first rather than deleting from under the stream.
+            let aged: Vec<ObjectId> = self
+                .find_all_stream_untiered::<Document>(rec_type.clone(), Filter::All)
+                .await?
+                .try_filter_map(|envelope| async move {
+                    let id = object_id(&envelope.record);
+                    Ok(id.filter(|id| id.timestamp().to_system_time() < cutoff))
+                })
+                .try_collect()
+                .await?;
+
+            for id in aged {
+                if self
+                    .move_to_cold_tier(&tiering.cold, rec_type, &id.to_hex())
+                    .await?
+                {
+                    moved += 1;
+                }
+            }
+        }
+        Ok(moved)
+    }
+
+    /// Moves the record of [ArchiveRecordType] with the given id from this store's own tier to
+    /// `cold`, keeping its id and provenance, returning whether it was moved. The record is
+    /// written to `cold` before it is deleted here, so a failure never loses it.
+    pub(crate) async fn move_to_cold_tier(
+        &self,
+        cold: &ArchiveStore,
+        rec_type: &ArchiveRecordType,
+        id: &str,
+    ) -> Result<bool, ArchiveError> {
+        let envelope = match self
+            .find_envelope_by_id_untiered::<Document>(rec_type.clone(), id)
+            .await?
+        {
+            Some(envelope) => envelope,
+            // Deleted since it was listed
+            None => return Ok(false),
+        };
+        let mut rec = envelope.record;
+        // Keep the id an object id, whatever form the hot backend returned it in.
+        if let Some(oid) = object_id(&rec) {
+            rec.insert("_id", oid);
+        }
+        // Keep the record's provenance rather than stamping it as archived now.
+        Provenance {
+            archived_at: envelope.archived_at,
+            node_id: envelope.node_id,
+            tags: envelope.tags,
+            lifecycle: envelope.lifecycle,
+        }
+        .restore(&mut rec);
+
+        match cold.create(rec_type.clone(), rec).await {
+            Ok(CreateOutcome::Created(_)) => {}
+            Ok(outcome) => {
+                warn!(
+                    "Not moving {:?} record {} to the cold tier: {:?}",
+                    rec_type, id, outcome
+                );
+                return Ok(false);
+            }
+            // Written by an earlier move that failed before deleting it
+            Err(e) if e.kind() == ArchiveErrorKind::DuplicateKey => {}
+            Err(e) => return Err(e),
+        }
+        self.delete_by_id_untiered(rec_type.clone(), id).await
+    }
+
+    /// Starts moving records to the cold tier in the background every
+    /// [TieringPolicy::migration_interval], if one is configured and the migration isn't already
+    /// running. Like the spill drain, it ends once the store and all its clones are dropped.
+    pub(crate) fn start_tier_migration(&self) {
+        let interval = match self
+            .inner
+            .tiering
+            .as_ref()
+            .and_then(|tiering| tiering.migration_interval)
+        {
+            Some(interval) => interval,
+            None => return,
+        };
+        if self.inner.tier_migration.set(()).is_err() {
+            return;
+        }
+
+        let inner = Arc::downgrade(&self.inner);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let store = match inner.upgrade() {
+                    Some(inner) => ArchiveStore {
+                        inner,
+                        labels: Labels::default(),
+                        tags: Vec::new(),
+                        include_tombstones: false,
+                        #[cfg(feature = "mongodb")]
+                        mongodb_override: None,
+                    },
+                    None => break,
+                };
+                match store.migrate_to_cold_tier().await {
+                    Ok(0) => {}
+                    Ok(moved) => debug!("Moved {} records to the cold tier", moved),
+                    Err(e) => warn!(
+                        "Failed to move records to the cold tier, retrying later: {:#}",
+                        e
+                    ),
+                }
+            }
+        });
+    }
+}
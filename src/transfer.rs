@@ -0,0 +1,197 @@
+/// Copying an archive from one store to another, e.g. to move from MongoDB to PostgreSQL. Records
+/// are read a page at a time and the position reached is saved to a checkpoint file after every
+/// page, so a copy that fails, or is stopped, resumes from where it got to when run again.
+use crate::envelope::{object_id, Provenance};
+use crate::{
+    ArchiveEnvelope, ArchiveError, ArchiveErrorKind, ArchiveRecordType, ArchiveStore, PageRequest,
+};
+use anyhow::Context;
+use bson::Document;
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+use std::path::Path;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+/// Number of records copied at once
+const PAGE_SIZE: usize = 500;
+
+/// How far a copy has got, as saved in its checkpoint file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Checkpoint {
+    record_types: Vec<TypeCheckpoint>,
+}
+
+/// How far the records of one record type have been copied.
+#[derive(Debug, Serialize, Deserialize)]
+struct TypeCheckpoint {
+    rec_type: ArchiveRecordType,
+    /// Token of the last page copied, if any
+    after_token: Option<String>,
+    /// Whether every record has been copied
+    complete: bool,
+    /// Number of records copied so far
+    copied: u64,
+}
+
+impl Checkpoint {
+    /// Reads the checkpoint file, or starts afresh if there isn't one yet.
+    async fn load(path: &Path) -> Result<Checkpoint, ArchiveError> {
+        match fs::read(path).await {
+            Ok(contents) => Ok(serde_json::from_slice(&contents)
+                .with_context(|| format!("Reading migration checkpoint {}", path.display()))?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Checkpoint::default()),
+            Err(e) => Err(anyhow::Error::new(e)
+                .context(format!("Reading migration checkpoint {}", path.display()))
+                .into()),
+        }
+    }
+
+    /// Replaces the checkpoint file, writing to a temporary file which is then renamed over it
+    /// so a crash leaves either the old or the new checkpoint in place.
+    async fn save(&self, path: &Path) -> Result<(), ArchiveError> {
+        let contents = serde_json::to_vec(self).context("Serialising migration checkpoint")?;
+        let tmp = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp)
+            .await
+            .with_context(|| format!("Creating {}", tmp.display()))?;
+        file.write_all(&contents)
+            .await
+            .with_context(|| format!("Writing {}", tmp.display()))?;
+        file.sync_all()
+            .await
+            .with_context(|| format!("Writing {}", tmp.display()))?;
+        fs::rename(&tmp, path)
+            .await
+            .with_context(|| format!("Replacing migration checkpoint {}", path.display()))?;
+        Ok(())
+    }
+
+    /// The progress of a record type, added if it hasn't been started yet.
+    fn record_type(&mut self, rec_type: &ArchiveRecordType) -> &mut TypeCheckpoint {
+        let i = match self
+            .record_types
+            .iter()
+            .position(|checkpoint| checkpoint.rec_type == *rec_type)
+        {
+            Some(i) => i,
+            None => {
+                self.record_types.push(TypeCheckpoint {
+                    rec_type: rec_type.clone(),
+                    after_token: None,
+                    complete: false,
+                    copied: 0,
+                });
+                self.record_types.len() - 1
+            }
+        };
+        &mut self.record_types[i]
+    }
+}
+
+impl ArchiveStore {
+    /// Copies every record of the given record types from this store to `to`, e.g. to move an
+    /// archive from MongoDB to a self-hosted PostgreSQL, returning the number of records copied
+    /// by this call. Records keep their ids and provenance, and are encoded as configured for
+    /// `to`. Only this store's own backend is copied, not its cold tier or mirrors.
+    ///
+    /// The position reached is saved to the `checkpoint` file after every page of records, so
+    /// if the copy fails it resumes from the last page saved when called again with the same
+    /// file. Records already copied are skipped, so a page that was only partly copied is simply
+    /// copied again. Delete the checkpoint file to start over.
+    ///
+    /// Records are read in id order like [ArchiveStore::find_page], so records archived while
+    /// copying are copied too if their ids sort after the current page, but records updated or
+    /// deleted after they were copied are not. To move without downtime, add `to` as a mirror of
+    /// the stores writing the archive first, see [crate::ArchiveStoreBuilder::mirror], then copy
+    /// the records archived before it was added.
+    pub async fn migrate_to(
+        &self,
+        to: &ArchiveStore,
+        rec_types: &[ArchiveRecordType],
+        checkpoint: impl AsRef<Path>,
+    ) -> Result<u64, ArchiveError> {
+        let path = checkpoint.as_ref();
+        let mut checkpoint = Checkpoint::load(path).await?;
+        let mut copied = 0;
+        for rec_type in rec_types {
+            loop {
+                let progress = checkpoint.record_type(rec_type);
+                if progress.complete {
+                    break;
+                }
+                let request = PageRequest {
+                    limit: PAGE_SIZE,
+                    after_token: progress.after_token.clone(),
+                };
+                let page = self
+                    .with_tombstones()
+                    .find_envelope_page::<Document>(rec_type.clone(), request)
+                    .await?;
+
+                let recs = page.items.into_iter().map(portable).collect();
+                let written = copy_page(to, rec_type, recs).await?;
+
+                let progress = checkpoint.record_type(rec_type);
+                progress.copied += written;
+                match page.next_token {
+                    Some(token) => progress.after_token = Some(token),
+                    None => progress.complete = true,
+                }
+                info!(
+                    "Copied {} {:?} records to {}",
+                    progress.copied, rec_type, to
+                );
+                copied += written;
+                checkpoint.save(path).await?;
+            }
+        }
+        Ok(copied)
+    }
+}
+
+/// A record read from one store as it's written to another, keeping its id and provenance.
+pub(crate) fn portable(envelope: ArchiveEnvelope<Document>) -> Document {
+    let mut rec = envelope.record;
+    // Keep the id an object id, whatever form this backend returned it in.
+    if let Some(oid) = object_id(&rec) {
+        rec.insert("_id", oid);
+    }
+    Provenance {
+        archived_at: envelope.archived_at,
+        node_id: envelope.node_id,
+        tags: envelope.tags,
+        lifecycle: envelope.lifecycle,
+    }
+    .restore(&mut rec);
+    rec
+}
+
+/// Writes a page of records to `to` in one batch, falling back to writing them one at a time,
+/// skipping those already there, if any were copied before. Returns the number written.
+pub(crate) async fn copy_page(
+    to: &ArchiveStore,
+    rec_type: &ArchiveRecordType,
+    recs: Vec<Document>,
+) -> Result<u64, ArchiveError> {
+    if recs.is_empty() {
+        return Ok(0);
+    }
+    let count = recs.len() as u64;
+    match to.create_many(rec_type.clone(), recs.clone()).await {
+        Ok(_) => return Ok(count),
+        Err(e) if e.kind() == ArchiveErrorKind::DuplicateKey => {}
+        Err(e) => return Err(e),
+    }
+
+    let mut written = 0;
+    for rec in recs {
+        match to.create_many(rec_type.clone(), vec![rec]).await {
+            Ok(_) => written += 1,
+            // Copied by an earlier run
+            Err(e) if e.kind() == ArchiveErrorKind::DuplicateKey => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(written)
+}
@@ -0,0 +1,182 @@
+/// A typed facade over an [ArchiveStore], binding a Rust record type to the [ArchiveRecordType]
+/// it is archived as, e.g. `store.typed::<Account>().create(account)`. The untyped API takes the
+/// record type and the record separately, so nothing stops a transaction batch from being written
+/// into the accounts collection; through a [TypedArchive] every record of a type goes to the same
+/// collection, is read back as the same type, and is looked up by the same key.
+use crate::filter::lookup;
+use crate::{
+    ArchiveError, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder, CreateOutcome, Filter,
+    IndexSpec, Page, PageRequest, DEFAULT_SCHEMA_VERSION,
+};
+use bson::Bson;
+use futures::Stream;
+use serde::{de::DeserializeOwned, Serialize};
+use std::marker::PhantomData;
+
+/// A Rust type archived as a single [ArchiveRecordType]. The record type names the collection,
+/// table, key prefix or directory the records are stored in, e.g.
+/// `ArchiveRecordType::Custom("bridge_events".to_string())`. With the `derive` feature, it can be
+/// implemented with `#[derive(ArchiveRecord)]` and attributes such as
+/// `#[archive(collection = "receipts", key = "tx_hash")]`, see the `lasr-archive-derive` crate.
+pub trait ArchiveRecord:
+    Serialize + DeserializeOwned + Send + Sync + Clone + Unpin + 'static
+{
+    /// The (possibly dotted) field records are looked up by with [TypedArchive::find_by_key] and
+    /// matched on by [TypedArchive::upsert], e.g. [crate::ACCOUNT_ADDRESS_FIELD], if any.
+    const KEY_FIELD: Option<&'static str> = None;
+
+    /// The schema version records are written with, registered by [ArchiveStoreBuilder::record].
+    const SCHEMA_VERSION: u32 = DEFAULT_SCHEMA_VERSION;
+
+    /// The record type records of this type are archived as.
+    fn record_type() -> ArchiveRecordType;
+
+    /// The secondary indexes [TypedArchive::ensure_indexes] creates for the record type.
+    fn indexes() -> Vec<IndexSpec> {
+        Vec::new()
+    }
+
+    /// The record's key, by default the value of its [ArchiveRecord::KEY_FIELD]. `None` if the
+    /// type has no key field or the record doesn't have it.
+    fn key(&self) -> Option<Bson> {
+        let doc = bson::to_document(self).ok()?;
+        lookup(&doc, Self::KEY_FIELD?).cloned()
+    }
+}
+
+/// An [ArchiveStore] handle that archives and retrieves records of a single [ArchiveRecord] type,
+/// returned by [ArchiveStore::typed]. Handles are cheap to clone and share the store.
+#[derive(Debug)]
+pub struct TypedArchive<T> {
+    store: ArchiveStore,
+    record: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for TypedArchive<T> {
+    fn clone(&self) -> Self {
+        TypedArchive {
+            store: self.store.clone(),
+            record: PhantomData,
+        }
+    }
+}
+
+impl ArchiveStore {
+    /// Returns a handle on this store for records of type `T`, which are always archived as
+    /// `T`'s [ArchiveRecordType], e.g. `store.typed::<Account>()`. The handle keeps this handle's
+    /// labels and tags.
+    pub fn typed<T: ArchiveRecord>(&self) -> TypedArchive<T> {
+        TypedArchive {
+            store: self.clone(),
+            record: PhantomData,
+        }
+    }
+}
+
+impl ArchiveStoreBuilder {
+    /// Declares the [ArchiveRecord] type `T`, setting the schema version of its record type to
+    /// its [ArchiveRecord::SCHEMA_VERSION], e.g. `builder.record::<Receipt>()`.
+    pub fn record<T: ArchiveRecord>(&mut self) -> &mut Self {
+        self.schema_version(T::record_type(), T::SCHEMA_VERSION)
+    }
+}
+
+impl<T: ArchiveRecord> TypedArchive<T> {
+    /// The underlying store, for operations without a typed equivalent.
+    pub fn store(&self) -> &ArchiveStore {
+        &self.store
+    }
+
+    /// Archives a record, see [ArchiveStore::create].
+    pub async fn create(&self, rec: T) -> Result<CreateOutcome, ArchiveError> {
+        self.store.create(T::record_type(), rec).await
+    }
+
+    /// Archives several records in one write, see [ArchiveStore::create_many].
+    pub async fn create_many(&self, recs: Vec<T>) -> Result<Vec<String>, ArchiveError> {
+        self.store.create_many(T::record_type(), recs).await
+    }
+
+    /// Retrieves the record with the given id, see [ArchiveStore::find_by_id].
+    pub async fn find_by_id(&self, id: &str) -> Result<Option<T>, ArchiveError> {
+        self.store.find_by_id(T::record_type(), id).await
+    }
+
+    /// Retrieves the first record whose [ArchiveRecord::KEY_FIELD] equals the key. Fails with
+    /// [ArchiveError::InvalidInput] if `T` has no key field.
+    pub async fn find_by_key(&self, key: impl Into<Bson>) -> Result<Option<T>, ArchiveError> {
+        let filter = Filter::eq(Self::key_field()?, key);
+        Ok(self
+            .store
+            .query(T::record_type(), filter)
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    /// Retrieves every record, see [ArchiveStore::find_all].
+    pub async fn find_all(&self) -> Result<Vec<T>, ArchiveError> {
+        self.store.find_all(T::record_type()).await
+    }
+
+    /// Streams every record, see [ArchiveStore::find_all_stream].
+    pub async fn find_all_stream(
+        &self,
+    ) -> Result<impl Stream<Item = Result<T, ArchiveError>> + '_, ArchiveError> {
+        self.store.find_all_stream(T::record_type()).await
+    }
+
+    /// Retrieves a page of records, see [ArchiveStore::find_page].
+    pub async fn find_page(&self, request: PageRequest) -> Result<Page<T>, ArchiveError> {
+        self.store.find_page(T::record_type(), request).await
+    }
+
+    /// Retrieves the records matching the [Filter], see [ArchiveStore::query].
+    pub async fn query(&self, filter: Filter) -> Result<Vec<T>, ArchiveError> {
+        self.store.query(T::record_type(), filter).await
+    }
+
+    /// Counts the records matching the [Filter], see [ArchiveStore::count].
+    pub async fn count(&self, filter: Filter) -> Result<u64, ArchiveError> {
+        self.store.count(T::record_type(), filter).await
+    }
+
+    /// Replaces the record with the given id, see [ArchiveStore::update_by_id].
+    pub async fn update_by_id(&self, id: &str, rec: T) -> Result<bool, ArchiveError> {
+        self.store.update_by_id(T::record_type(), id, rec).await
+    }
+
+    /// Replaces the record with the same key, or archives the record if there is none, returning
+    /// its id, see [ArchiveStore::upsert]. Fails with [ArchiveError::InvalidInput] if `T` has no
+    /// key field or the record has no key.
+    pub async fn upsert(&self, rec: T) -> Result<String, ArchiveError> {
+        let field = Self::key_field()?;
+        let key = rec.key().ok_or_else(|| {
+            ArchiveError::invalid_input(format!("Record has no key field '{}'", field))
+        })?;
+        self.store
+            .upsert(T::record_type(), Filter::eq(field, key), rec)
+            .await
+    }
+
+    /// Deletes the record with the given id, see [ArchiveStore::delete_by_id].
+    pub async fn delete_by_id(&self, id: &str) -> Result<bool, ArchiveError> {
+        self.store.delete_by_id(T::record_type(), id).await
+    }
+
+    /// Creates the [ArchiveRecord::indexes] of `T`, see [ArchiveStore::ensure_indexes].
+    pub async fn ensure_indexes(&self) -> Result<(), ArchiveError> {
+        self.store
+            .ensure_indexes(T::record_type(), T::indexes())
+            .await
+    }
+
+    fn key_field() -> Result<&'static str, ArchiveError> {
+        T::KEY_FIELD.ok_or_else(|| {
+            ArchiveError::invalid_input(format!(
+                "Record type {:?} has no key field",
+                T::record_type()
+            ))
+        })
+    }
+}
@@ -0,0 +1,19 @@
+/// Error returned when a backend (or the deployment it is connected to) can't perform an
+/// operation, rather than silently performing it with weaker guarantees.
+use core::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unsupported {
+    /// The operation that was attempted
+    pub operation: &'static str,
+    /// Why the operation isn't supported
+    pub reason: String,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is not supported: {}", self.operation, self.reason)
+    }
+}
+
+impl std::error::Error for Unsupported {}
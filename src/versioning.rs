@@ -0,0 +1,112 @@
+/// Append-only record types. Updates to records of a type made versioned with
+/// [crate::ArchiveStoreBuilder::versioned] are archived as new versions instead of replacing the
+/// stored record, e.g. to keep every snapshot of an account rather than only the newest. The
+/// versions of a record are the records sharing the type's key field, numbered in the order they
+/// were archived.
+use crate::filter::id_to_string;
+use crate::{ArchiveError, ArchiveRecordType, ArchiveStore, CreateOutcome, Filter, LifecycleState};
+use anyhow::Context;
+use bson::{oid::ObjectId, Bson, DateTime, Document};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// A version of a record of a versioned record type, see [ArchiveStore::history].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Versioned<T> {
+    /// The record as archived in this version
+    pub record: T,
+    /// The id the version is stored under
+    pub id: String,
+    /// The version's number, counting from 1 for the first version archived
+    pub version: u64,
+    /// When the version was archived, if known
+    pub archived_at: Option<DateTime>,
+}
+
+impl ArchiveStore {
+    /// Retrieves every version of the record of a versioned [ArchiveRecordType] whose key field
+    /// is `key`, oldest first, e.g. `history(ArchiveRecordType::Account, address)` for every
+    /// snapshot of an account. Versions are ordered by when they were archived, then by id, so
+    /// versions archived by different processes in the same millisecond may be numbered in
+    /// either order. Tombstoned versions are skipped, but still counted, unless read through
+    /// [ArchiveStore::with_tombstones]. As with [ArchiveStore::query], key fields of compressed
+    /// or chunked records can't be filtered on.
+    pub async fn history<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        key: impl Into<Bson>,
+    ) -> Result<Vec<Versioned<T>>, ArchiveError>
+    where
+        T: DeserializeOwned,
+    {
+        let key_field = self.version_key(&rec_type).ok_or_else(|| {
+            ArchiveError::invalid_input(format!("{:?} records aren't versioned", rec_type))
+        })?;
+        let filter = Filter::eq(key_field, key);
+        // Tombstoned versions are only left out once numbered.
+        let mut envelopes = self
+            .with_tombstones()
+            .query_envelopes::<Document>(rec_type, filter)
+            .await?;
+        envelopes.sort_by_cached_key(|envelope| {
+            let id = envelope.record.get("_id").map(id_to_string);
+            (envelope.archived_at, id)
+        });
+
+        let mut versions = Vec::with_capacity(envelopes.len());
+        for (version, envelope) in (1..).zip(envelopes) {
+            if !self.include_tombstones && envelope.lifecycle.state != LifecycleState::Archived {
+                continue;
+            }
+            versions.push(Versioned {
+                id: envelope
+                    .record
+                    .get("_id")
+                    .map(id_to_string)
+                    .unwrap_or_default(),
+                record: bson::from_document(envelope.record)
+                    .context("Failed to deserialise record")?,
+                version,
+                archived_at: envelope.archived_at,
+            });
+        }
+        Ok(versions)
+    }
+
+    /// Retrieves the newest version of the record of a versioned [ArchiveRecordType] whose key
+    /// field is `key`, if it has one, see [ArchiveStore::history]. Every version of the record is
+    /// read to find it.
+    pub async fn latest<T>(
+        &self,
+        rec_type: ArchiveRecordType,
+        key: impl Into<Bson>,
+    ) -> Result<Option<Versioned<T>>, ArchiveError>
+    where
+        T: DeserializeOwned,
+    {
+        Ok(self.history(rec_type, key).await?.pop())
+    }
+
+    /// The field identifying the versions of a record of [ArchiveRecordType], if it's versioned.
+    pub(crate) fn version_key(&self, rec_type: &ArchiveRecordType) -> Option<&str> {
+        self.inner.versioned.get(rec_type).map(String::as_str)
+    }
+
+    /// Archives a record of a versioned [ArchiveRecordType] as a new version under a fresh id,
+    /// returning the id. Identical records may be deduplicated as by [ArchiveStore::create],
+    /// returning the id of the earlier version.
+    pub(crate) async fn append_version<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: &T,
+    ) -> Result<String, ArchiveError> {
+        let mut doc = bson::to_document(rec).context("Failed to serialise record to BSON")?;
+        let id = ObjectId::new();
+        doc.insert("_id", id);
+        match self.create(rec_type, doc).await? {
+            CreateOutcome::Created(id) => Ok(id),
+            // Written under the same id once drained or retried.
+            CreateOutcome::Spilled(_) | CreateOutcome::DeadLettered(_) => Ok(id.to_hex()),
+        }
+    }
+}
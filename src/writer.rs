@@ -0,0 +1,260 @@
+/// Buffered writes. An [ArchiveWriter] queues records and writes them to the store in batches
+/// from a background task, so that callers producing records on a hot path, e.g. block
+/// production, only wait for the backend when the queue is full rather than on every record.
+use crate::{ArchiveError, ArchiveRecordType, ArchiveStore};
+use anyhow::Context;
+use bson::Document;
+use log::{debug, warn};
+use serde::Serialize;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::MissedTickBehavior;
+
+/// How an [ArchiveWriter] buffers records, see [ArchiveStore::writer].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriterOptions {
+    /// Most records queued before [ArchiveWriter::write] waits for room. Defaults to 10000.
+    pub capacity: usize,
+    /// Number of queued records of a record type that are written at once, as by
+    /// [ArchiveStore::create_many]. Defaults to 500.
+    pub batch_size: usize,
+    /// Longest a record waits in the queue before it's written, however few are queued.
+    /// Defaults to 100ms.
+    pub flush_interval: Duration,
+}
+
+impl Default for WriterOptions {
+    fn default() -> Self {
+        WriterOptions {
+            capacity: 10_000,
+            batch_size: 500,
+            flush_interval: Duration::from_millis(100),
+        }
+    }
+}
+
+/// What is sent to the background task
+enum Message {
+    /// A record to write
+    Record(ArchiveRecordType, Document),
+    /// Write every queued record, then report the failures since the last report
+    Flush(oneshot::Sender<Result<(), ArchiveError>>),
+    /// As [Message::Flush], then stop
+    Shutdown(oneshot::Sender<Result<(), ArchiveError>>),
+}
+
+/// A handle queueing records to be written to an [ArchiveStore] in batches by a background task,
+/// created with [ArchiveStore::writer]. Clones share the same queue and task.
+///
+/// Records are written in the order queued within each record type. A batch that fails, after
+/// any retries the store's [crate::RetryPolicy] allows, is dropped and its failure returned by
+/// the next [ArchiveWriter::flush] or [ArchiveWriter::shutdown], so call one of them
+/// periodically to find out whether records were lost. Queued records are lost if the process
+/// exits without calling [ArchiveWriter::shutdown]. Once every handle is dropped the task writes
+/// the records still queued and stops.
+#[derive(Debug, Clone)]
+pub struct ArchiveWriter {
+    sender: mpsc::Sender<Message>,
+}
+
+impl ArchiveStore {
+    /// Starts a background task writing records to this store in batches, returning an
+    /// [ArchiveWriter] to queue them with. Must be called within a tokio runtime.
+    pub fn writer(&self, options: WriterOptions) -> Result<ArchiveWriter, ArchiveError> {
+        if options.capacity == 0 || options.batch_size == 0 {
+            return Err(ArchiveError::invalid_input(
+                "Writer capacity and batch size must be greater than zero",
+            ));
+        }
+        if options.flush_interval.is_zero() {
+            return Err(ArchiveError::invalid_input(
+                "Writer flush interval must be greater than zero",
+            ));
+        }
+        let (sender, receiver) = mpsc::channel(options.capacity);
+        tokio::spawn(run(self.clone(), options, receiver));
+        Ok(ArchiveWriter { sender })
+    }
+}
+
+impl ArchiveWriter {
+    /// Queues a record of [ArchiveRecordType] to be written, waiting for room if the queue is
+    /// full. Only fails if the record can't be serialised or the writer has been shut down.
+    pub async fn write<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+    ) -> Result<(), ArchiveError> {
+        let doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+        self.sender
+            .send(Message::Record(rec_type, doc))
+            .await
+            .map_err(|_| stopped())
+    }
+
+    /// Queues a record of [ArchiveRecordType] to be written like [ArchiveWriter::write], but
+    /// fails with [ArchiveError::BackendUnavailable] rather than waiting if the queue is full.
+    pub fn try_write<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+    ) -> Result<(), ArchiveError> {
+        let doc = bson::to_document(&rec).context("Failed to serialise record to BSON")?;
+        self.sender
+            .try_send(Message::Record(rec_type, doc))
+            .map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => ArchiveError::BackendUnavailable(
+                    anyhow::anyhow!("The archive writer's queue is full"),
+                ),
+                mpsc::error::TrySendError::Closed(_) => stopped(),
+            })
+    }
+
+    /// Writes every record queued so far, returning the failure of any batch written since the
+    /// last flush.
+    pub async fn flush(&self) -> Result<(), ArchiveError> {
+        let (ack, done) = oneshot::channel();
+        self.sender
+            .send(Message::Flush(ack))
+            .await
+            .map_err(|_| stopped())?;
+        done.await.map_err(|_| stopped())?
+    }
+
+    /// Writes every record queued so far and stops the background task, returning the failure of
+    /// any batch written since the last flush. Records queued by clones after this fail to be
+    /// written.
+    pub async fn shutdown(self) -> Result<(), ArchiveError> {
+        let (ack, done) = oneshot::channel();
+        self.sender
+            .send(Message::Shutdown(ack))
+            .await
+            .map_err(|_| stopped())?;
+        done.await.map_err(|_| stopped())?
+    }
+}
+
+/// The error returned once the background task has stopped.
+fn stopped() -> ArchiveError {
+    ArchiveError::invalid_input("The archive writer has been shut down")
+}
+
+/// Records queued by the background task, and the failures writing them
+struct Queue {
+    /// Queued records, by record type
+    records: Vec<(ArchiveRecordType, Vec<Document>)>,
+    /// Number of records whose batches failed since the last report
+    failed: usize,
+    /// The latest failure since the last report
+    error: Option<ArchiveError>,
+}
+
+impl Queue {
+    /// Queues a record, writing its record type's records if there's a full batch of them.
+    async fn push(
+        &mut self,
+        store: &ArchiveStore,
+        batch_size: usize,
+        rec_type: ArchiveRecordType,
+        doc: Document,
+    ) {
+        let i = match self.records.iter().position(|(t, _)| *t == rec_type) {
+            Some(i) => i,
+            None => {
+                self.records.push((rec_type, Vec::new()));
+                self.records.len() - 1
+            }
+        };
+        self.records[i].1.push(doc);
+        if self.records[i].1.len() >= batch_size {
+            self.write(store, i).await;
+        }
+    }
+
+    /// Writes the queued records of every record type.
+    async fn flush(&mut self, store: &ArchiveStore) {
+        for i in 0..self.records.len() {
+            self.write(store, i).await;
+        }
+    }
+
+    /// Writes the queued records of the `i`th record type, recording the failure if they can't
+    /// be written.
+    async fn write(&mut self, store: &ArchiveStore, i: usize) {
+        let (rec_type, docs) = &mut self.records[i];
+        if docs.is_empty() {
+            return;
+        }
+        let rec_type = rec_type.clone();
+        let batch = std::mem::take(docs);
+        let count = batch.len();
+        match store.create_many(rec_type.clone(), batch).await {
+            Ok(_) => debug!("Wrote {} buffered {:?} records", count, rec_type),
+            Err(e) => {
+                warn!(
+                    "Failed to write {} buffered {:?} records: {:#}",
+                    count, rec_type, e
+                );
+                self.failed += count;
+                self.error = Some(e);
+            }
+        }
+    }
+
+    /// The failure since the last report, if any, clearing it.
+    fn report(&mut self) -> Result<(), ArchiveError> {
+        let failed = std::mem::take(&mut self.failed);
+        match self.error.take() {
+            // Wrapping the error itself keeps its kind.
+            Some(e) => Err(anyhow::Error::new(e)
+                .context(format!("{} buffered records failed to be written", failed))
+                .into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The background task, writing the records received until it's shut down or every
+/// [ArchiveWriter] is dropped.
+async fn run(store: ArchiveStore, options: WriterOptions, mut receiver: mpsc::Receiver<Message>) {
+    let mut queue = Queue {
+        records: Vec::new(),
+        failed: 0,
+        error: None,
+    };
+    let mut ticker = tokio::time::interval(options.flush_interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            message = receiver.recv() => match message {
+                Some(Message::Record(rec_type, doc)) => {
+                    queue.push(&store, options.batch_size, rec_type, doc).await;
+                }
+                Some(Message::Flush(ack)) => {
+                    queue.flush(&store).await;
+                    let _ = ack.send(queue.report());
+                }
+                Some(Message::Shutdown(ack)) => {
+                    receiver.close();
+                    // Write the records queued before the shutdown too.
+                    while let Ok(message) = receiver.try_recv() {
+                        if let Message::Record(rec_type, doc) = message {
+                            queue.push(&store, options.batch_size, rec_type, doc).await;
+                        }
+                    }
+                    queue.flush(&store).await;
+                    let _ = ack.send(queue.report());
+                    break;
+                }
+                None => {
+                    queue.flush(&store).await;
+                    if let Err(e) = queue.report() {
+                        warn!("Archive writer dropped with unreported failures: {:#}", e);
+                    }
+                    break;
+                }
+            },
+            _ = ticker.tick() => queue.flush(&store).await,
+        }
+    }
+}
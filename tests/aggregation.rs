@@ -0,0 +1,79 @@
+use bson::{doc, Bson, DateTime, Document};
+use lasr_archive::{
+    AggregationSpec, ArchiveRecordType, ArchiveStore, Filter, Granularity, GroupBy,
+};
+
+const BATCH: ArchiveRecordType = ArchiveRecordType::TransactionBatch;
+
+/// Archives batches of each account's transactions.
+async fn archive_batches(store: &ArchiveStore) {
+    let batches = [("a", 1, 10), ("b", 2, 5), ("a", 3, 30), ("c", 4, 7)];
+    for (account, height, fee) in batches {
+        store
+            .create(
+                BATCH,
+                doc! { "account": account, "block_height": height, "fee": fee },
+            )
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn aggregates_each_group() {
+    let store = ArchiveStore::in_memory();
+    archive_batches(&store).await;
+    store
+        .create(
+            BATCH,
+            doc! { "account": "a", "block_height": 0, "fee": 100, "_lifecycle": "tombstoned" },
+        )
+        .await
+        .unwrap();
+
+    let spec = AggregationSpec::grouped_by(GroupBy::Field("account".to_string()))
+        .filter(Filter::lt("block_height", 4))
+        .count("batches")
+        .sum("fees", "fee")
+        .min("first", "block_height")
+        .max("last", "block_height");
+    let groups = store.aggregate(BATCH, spec).await.unwrap();
+    // Tombstoned records aren't aggregated, as they can't be read.
+    assert_eq!(
+        groups,
+        [
+            doc! { "key": "a", "batches": 2_i64, "fees": 40_i64, "first": 1, "last": 3 },
+            doc! { "key": "b", "batches": 1_i64, "fees": 5_i64, "first": 2, "last": 2 },
+        ]
+    );
+
+    // Every record is in one group when they aren't grouped.
+    let spec = AggregationSpec::new().count("batches").sum("fees", "fee");
+    let totals: Vec<Document> = store.aggregate(BATCH, spec).await.unwrap();
+    assert_eq!(totals.len(), 1);
+    assert_eq!(totals[0].get_i64("batches"), Ok(4));
+    assert_eq!(totals[0].get_i64("fees"), Ok(52));
+}
+
+#[tokio::test]
+async fn counts_the_records_in_each_group() {
+    let store = ArchiveStore::in_memory();
+    archive_batches(&store).await;
+
+    let counts = store
+        .group_count(BATCH, GroupBy::Field("account".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(
+        counts,
+        [("a".into(), 2), ("b".into(), 1), ("c".into(), 1)] as [(Bson, u64); 3]
+    );
+
+    // Every record was archived just now.
+    let today = DateTime::now().try_to_rfc3339_string().unwrap()[..10].to_string();
+    let counts = store
+        .group_count(BATCH, GroupBy::ArchivedAt(Granularity::Day))
+        .await
+        .unwrap();
+    assert_eq!(counts, [(Bson::String(today), 4)]);
+}
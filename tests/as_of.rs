@@ -0,0 +1,61 @@
+use bson::{doc, Document};
+use lasr_archive::{
+    ArchiveBackends, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder, AsOf, Filter,
+};
+
+fn field(docs: &[Document], name: &str) -> Vec<i64> {
+    docs.iter()
+        .map(|doc| doc.get(name).and_then(|value| value.as_i64()).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn reads_accounts_as_of_a_height() {
+    let store = ArchiveStore::in_memory();
+    for (address, height, balance) in [
+        ("a", 1_i64, 10_i64),
+        ("a", 3, 30),
+        ("b", 2, 20),
+        ("a", 5, 50),
+    ] {
+        store
+            .create(
+                ArchiveRecordType::Account,
+                doc! { "owner_address": address, "block_height": height, "balance": balance },
+            )
+            .await
+            .unwrap();
+    }
+
+    let accounts: Vec<Document> = store
+        .find_as_of(ArchiveRecordType::Account, Filter::All, AsOf::Height(4))
+        .await
+        .unwrap();
+    assert_eq!(field(&accounts, "balance"), vec![30, 20]);
+}
+
+#[tokio::test]
+async fn keys_versions_by_the_field_a_type_is_versioned_by() {
+    let rec_type = ArchiveRecordType::Custom("balances".to_string());
+    let store = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::InMemory)
+        .datastore("as_of".to_string())
+        .versioned(rec_type.clone(), "wallet")
+        .build()
+        .unwrap();
+    for (wallet, height, balance) in [(1_i64, 1_i64, 10_i64), (2, 1, 20), (1, 2, 11), (1, 4, 12)] {
+        store
+            .create(
+                rec_type.clone(),
+                doc! { "wallet": wallet, "block_height": height, "balance": balance },
+            )
+            .await
+            .unwrap();
+    }
+
+    let balances: Vec<Document> = store
+        .find_as_of(rec_type, Filter::All, AsOf::Height(3))
+        .await
+        .unwrap();
+    assert_eq!(field(&balances, "balance"), vec![11, 20]);
+}
@@ -0,0 +1,91 @@
+use bson::doc;
+use lasr_archive::{
+    ArchiveErrorKind, ArchiveRecordType, ArchiveStore, Filter, MissingRange, BLOCK_HEIGHT_FIELD,
+};
+
+const BLOCK: ArchiveRecordType = ArchiveRecordType::Block;
+
+/// A store holding a block at each of the heights.
+async fn store(heights: impl IntoIterator<Item = i64>) -> ArchiveStore {
+    let store = ArchiveStore::in_memory();
+    let blocks = heights
+        .into_iter()
+        .map(|height| doc! { BLOCK_HEIGHT_FIELD: height })
+        .collect();
+    store.create_many(BLOCK, blocks).await.unwrap();
+    store
+}
+
+fn range(start: i64, end: i64) -> MissingRange {
+    MissingRange { start, end }
+}
+
+#[tokio::test]
+async fn finds_the_heights_never_archived() {
+    let store = store((0..30).filter(|height| ![5, 10, 11, 12, 29].contains(height))).await;
+    let missing = store
+        .audit_sequence(BLOCK, BLOCK_HEIGHT_FIELD, 0..=29)
+        .await
+        .unwrap();
+    assert_eq!(missing, vec![range(5, 5), range(10, 12), range(29, 29)]);
+    assert_eq!(missing[1].count(), 3);
+    assert_eq!(missing[1].to_string(), "10-12");
+
+    // Exclusive ends, and ranges beyond the archived heights.
+    let missing = store
+        .audit_sequence(BLOCK, BLOCK_HEIGHT_FIELD, 20..29)
+        .await
+        .unwrap();
+    assert!(missing.is_empty());
+    let missing = store
+        .audit_sequence(BLOCK, BLOCK_HEIGHT_FIELD, 25..=40)
+        .await
+        .unwrap();
+    assert_eq!(missing, vec![range(29, 40)]);
+}
+
+#[tokio::test]
+async fn finds_gaps_spanning_windows() {
+    // Heights are looked for 10,000 at a time.
+    let store = store((9_990..10_010).filter(|height| !(9_998..10_002).contains(height))).await;
+    let missing = store
+        .audit_sequence(BLOCK, BLOCK_HEIGHT_FIELD, 0..10_010)
+        .await
+        .unwrap();
+    assert_eq!(missing, vec![range(0, 9_989), range(9_998, 10_001)]);
+}
+
+#[tokio::test]
+async fn counts_tombstoned_heights_as_missing() {
+    let store = store(0..5).await;
+    store
+        .tombstone_where(BLOCK, Filter::eq(BLOCK_HEIGHT_FIELD, 2i64), "reorg")
+        .await
+        .unwrap();
+    let missing = store
+        .audit_sequence(BLOCK, BLOCK_HEIGHT_FIELD, 0..5)
+        .await
+        .unwrap();
+    assert_eq!(missing, vec![range(2, 2)]);
+    let missing = store
+        .with_tombstones()
+        .audit_sequence(BLOCK, BLOCK_HEIGHT_FIELD, 0..5)
+        .await
+        .unwrap();
+    assert!(missing.is_empty());
+}
+
+#[tokio::test]
+async fn needs_a_bounded_range() {
+    let store = store(0..5).await;
+    let error = store
+        .audit_sequence(BLOCK, BLOCK_HEIGHT_FIELD, 0..)
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), ArchiveErrorKind::InvalidInput);
+    let error = store
+        .audit_sequence(BLOCK, BLOCK_HEIGHT_FIELD, ..5)
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), ArchiveErrorKind::InvalidInput);
+}
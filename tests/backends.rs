@@ -0,0 +1,198 @@
+use bson::{doc, Document};
+use lasr_archive::{
+    ArchiveBackends, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder, Filter, PageRequest,
+};
+use std::path::PathBuf;
+
+const ACCOUNT: ArchiveRecordType = ArchiveRecordType::Account;
+
+fn nonces(docs: &[Document]) -> Vec<i32> {
+    docs.iter()
+        .map(|doc| doc.get_i32("nonce").unwrap())
+        .collect()
+}
+
+/// A store on the backend under test, removing the directory it keeps its records in, if any,
+/// when dropped.
+struct Backend {
+    store: ArchiveStore,
+    dir: Option<PathBuf>,
+}
+
+impl Drop for Backend {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.dir {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+fn memory(_test: &str) -> Backend {
+    Backend {
+        store: ArchiveStore::in_memory(),
+        dir: None,
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite(_test: &str) -> Backend {
+    let store = ArchiveStoreBuilder::default()
+        .uri("sqlite::memory:".to_string())
+        .backend(ArchiveBackends::Sqlite)
+        .datastore("backends".to_string())
+        .build()
+        .unwrap();
+    Backend { store, dir: None }
+}
+
+fn filesystem(test: &str) -> Backend {
+    let dir = std::env::temp_dir().join(format!(
+        "lasr-archive-backends-{}-{}",
+        test,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    let store = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::Filesystem { root: dir.clone() })
+        .datastore("backends".to_string())
+        .build()
+        .unwrap();
+    Backend {
+        store,
+        dir: Some(dir),
+    }
+}
+
+async fn creates_finds_and_queries_records(store: &ArchiveStore) {
+    let mut ids = Vec::new();
+    for nonce in 0..5 {
+        let outcome = store
+            .create(ACCOUNT, doc! { "nonce": nonce })
+            .await
+            .unwrap();
+        ids.push(outcome.id().unwrap().to_string());
+    }
+
+    let found: Option<Document> = store.find_by_id(ACCOUNT, &ids[2]).await.unwrap();
+    assert_eq!(found.unwrap().get_i32("nonce").unwrap(), 2);
+    let missing: Option<Document> = store.find_by_id(ACCOUNT, "missing").await.unwrap();
+    assert!(missing.is_none());
+
+    let all: Vec<Document> = store.find_all(ACCOUNT).await.unwrap();
+    assert_eq!(nonces(&all), vec![0, 1, 2, 3, 4]);
+    let queried: Vec<Document> = store.query(ACCOUNT, Filter::gte("nonce", 3)).await.unwrap();
+    assert_eq!(nonces(&queried), vec![3, 4]);
+    assert_eq!(
+        store.count(ACCOUNT, Filter::lt("nonce", 3)).await.unwrap(),
+        3
+    );
+    // Other record types are stored apart.
+    assert_eq!(
+        store
+            .count(ArchiveRecordType::Block, Filter::All)
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+async fn updates_and_deletes_records(store: &ArchiveStore) {
+    let id = store
+        .create_with_id(ACCOUNT, "a", doc! { "nonce": 1 })
+        .await
+        .unwrap();
+    assert_eq!(id, "a");
+    store
+        .create_with_id(ACCOUNT, "b", doc! { "nonce": 2 })
+        .await
+        .unwrap();
+
+    assert!(store
+        .update_by_id(ACCOUNT, "a", doc! { "nonce": 10 })
+        .await
+        .unwrap());
+    assert!(!store
+        .update_by_id(ACCOUNT, "missing", doc! { "nonce": 0 })
+        .await
+        .unwrap());
+    let upserted = store
+        .upsert(ACCOUNT, Filter::eq("nonce", 3), doc! { "nonce": 3 })
+        .await
+        .unwrap();
+    let found: Option<Document> = store.find_by_id(ACCOUNT, &upserted).await.unwrap();
+    assert_eq!(found.unwrap().get_i32("nonce").unwrap(), 3);
+    let all: Vec<Document> = store.find_all(ACCOUNT).await.unwrap();
+    assert_eq!(nonces(&all).len(), 3);
+
+    assert!(store.delete_by_id(ACCOUNT, "a").await.unwrap());
+    assert!(!store.delete_by_id(ACCOUNT, "a").await.unwrap());
+    assert_eq!(
+        store
+            .delete_where(ACCOUNT, Filter::gte("nonce", 2))
+            .await
+            .unwrap(),
+        2
+    );
+    assert_eq!(store.count(ACCOUNT, Filter::All).await.unwrap(), 0);
+}
+
+async fn pages_through_records_in_id_order(store: &ArchiveStore) {
+    for nonce in 0..7 {
+        store
+            .create_with_id(ACCOUNT, &format!("{:02}", nonce), doc! { "nonce": nonce })
+            .await
+            .unwrap();
+    }
+
+    let mut request = PageRequest::first(3);
+    let mut pages = Vec::new();
+    loop {
+        let page = store.find_page::<Document>(ACCOUNT, request).await.unwrap();
+        pages.push(nonces(&page.items));
+        match page.next_token {
+            Some(token) => request = PageRequest::after(3, token),
+            None => break,
+        }
+    }
+    assert_eq!(pages, vec![vec![0, 1, 2], vec![3, 4, 5], vec![6]]);
+
+    let page = store
+        .find_page::<Document>(ArchiveRecordType::Block, PageRequest::first(3))
+        .await
+        .unwrap();
+    assert!(page.items.is_empty());
+    assert!(page.next_token.is_none());
+}
+
+/// Runs every test against the stores a backend's function returns, given the test's name.
+macro_rules! backend_tests {
+    ($(#[$attr:meta])* $backend:ident) => {
+        $(#[$attr])*
+        mod $backend {
+            #[tokio::test]
+            async fn creates_finds_and_queries_records() {
+                let backend = super::$backend("creates_finds_and_queries_records");
+                super::creates_finds_and_queries_records(&backend.store).await;
+            }
+
+            #[tokio::test]
+            async fn updates_and_deletes_records() {
+                let backend = super::$backend("updates_and_deletes_records");
+                super::updates_and_deletes_records(&backend.store).await;
+            }
+
+            #[tokio::test]
+            async fn pages_through_records_in_id_order() {
+                let backend = super::$backend("pages_through_records_in_id_order");
+                super::pages_through_records_in_id_order(&backend.store).await;
+            }
+        }
+    };
+}
+
+backend_tests!(memory);
+backend_tests!(filesystem);
+backend_tests!(
+    #[cfg(feature = "sqlite")]
+    sqlite
+);
@@ -0,0 +1,107 @@
+use bson::{doc, Document};
+use lasr_archive::{
+    ArchiveError, ArchiveErrorKind, ArchiveRecordType, ArchiveStore, BackfillOptions, Backfiller,
+    Filter,
+};
+use std::ops::RangeInclusive;
+
+const BLOCK: ArchiveRecordType = ArchiveRecordType::Block;
+
+fn backfiller(store: &ArchiveStore, end: i64) -> Backfiller {
+    let options = BackfillOptions {
+        checkpoint_every: 2,
+        ..BackfillOptions::default()
+    };
+    store
+        .backfiller("blocks", BLOCK, "height", 0..=end, options)
+        .unwrap()
+}
+
+/// A block per height, read in height order.
+fn blocks(heights: impl IntoIterator<Item = i64>) -> Vec<Result<Document, ArchiveError>> {
+    heights
+        .into_iter()
+        .map(|height| Ok(doc! { "height": height }))
+        .collect()
+}
+
+/// Checks that a single block of each height was archived.
+async fn assert_archived(store: &ArchiveStore, heights: RangeInclusive<i64>) {
+    let count = store.count(BLOCK, Filter::All).await.unwrap();
+    assert_eq!(count, heights.clone().count() as u64);
+    for height in heights {
+        let id = format!("height-{}-0", height);
+        let rec: Option<Document> = store.find_by_id(BLOCK, &id).await.unwrap();
+        assert_eq!(rec.unwrap().get_i64("height"), Ok(height), "{}", id);
+    }
+}
+
+#[tokio::test]
+async fn resumes_after_the_checkpoint_of_a_failed_run() {
+    let store = ArchiveStore::in_memory();
+    let backfiller = backfiller(&store, 9);
+
+    // The source fails after the first record of height 5, which may have had more records.
+    let mut failing = blocks(0..=5);
+    failing.push(Err(ArchiveError::BackendUnavailable(anyhow::anyhow!(
+        "Node unavailable"
+    ))));
+    let error = backfiller
+        .run(futures::stream::iter(failing))
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), ArchiveErrorKind::BackendUnavailable);
+    assert_eq!(backfiller.checkpoint().await.unwrap(), Some(4));
+    assert_eq!(backfiller.remaining().await.unwrap(), Some(5..=9));
+
+    // Running again skips the checkpointed heights, and finds height 5 already archived.
+    let progress = backfiller
+        .run(futures::stream::iter(blocks(0..=9)))
+        .await
+        .unwrap();
+    assert_eq!(progress.read, 10);
+    assert_eq!(progress.skipped, 5);
+    assert_eq!(progress.written, 5);
+    assert_eq!(progress.checkpoint, Some(9));
+    assert_archived(&store, 0..=9).await;
+    assert_eq!(backfiller.remaining().await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn fails_without_a_checkpoint_on_keys_out_of_order() {
+    let store = ArchiveStore::in_memory();
+    let backfiller = backfiller(&store, 9);
+
+    let error = backfiller
+        .run(futures::stream::iter(blocks([0, 1, 2, 3, 1])))
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), ArchiveErrorKind::InvalidInput);
+    // Only the periodic checkpoint, taken before the keys were found out of order, is kept.
+    assert_eq!(backfiller.checkpoint().await.unwrap(), Some(1));
+}
+
+#[tokio::test]
+async fn fails_on_a_checkpoint_of_another_range_until_reset() {
+    let store = ArchiveStore::in_memory();
+    let narrower = backfiller(&store, 4);
+    narrower
+        .run(futures::stream::iter(blocks(0..=4)))
+        .await
+        .unwrap();
+
+    let wider = backfiller(&store, 9);
+    let error = wider
+        .run(futures::stream::iter(blocks(0..=9)))
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), ArchiveErrorKind::InvalidInput);
+
+    assert!(wider.reset().await.unwrap());
+    let progress = wider
+        .run(futures::stream::iter(blocks(0..=9)))
+        .await
+        .unwrap();
+    assert_eq!(progress.written, 10);
+    assert_archived(&store, 0..=9).await;
+}
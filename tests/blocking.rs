@@ -0,0 +1,51 @@
+#![cfg(feature = "blocking")]
+
+use bson::{doc, Document};
+use lasr_archive::blocking::BlockingArchiveStore;
+use lasr_archive::{ArchiveRecordType, ArchiveStore, Filter, LifecycleState};
+
+const ACCOUNT: ArchiveRecordType = ArchiveRecordType::Account;
+
+#[test]
+fn runs_operations_without_a_runtime_of_its_own() {
+    let store = BlockingArchiveStore::new(ArchiveStore::in_memory()).unwrap();
+    let id = store
+        .create(ACCOUNT, doc! { "nonce": 1 })
+        .unwrap()
+        .id()
+        .unwrap()
+        .to_string();
+    store
+        .create_many(
+            ACCOUNT,
+            (2..=5).map(|nonce| doc! { "nonce": nonce }).collect(),
+        )
+        .unwrap();
+
+    let found: Option<Document> = store.find_by_id(ACCOUNT, &id).unwrap();
+    assert_eq!(found.unwrap().get_i32("nonce"), Ok(1));
+    assert!(store
+        .update_by_id(ACCOUNT, &id, doc! { "nonce": 10 })
+        .unwrap());
+    assert_eq!(store.count(ACCOUNT, Filter::gte("nonce", 5)).unwrap(), 2);
+
+    // Every record is read, a page at a time.
+    let nonces: Vec<i32> = store
+        .find_all_iter::<Document>(ACCOUNT, 2)
+        .map(|rec| rec.unwrap().get_i32("nonce").unwrap())
+        .collect();
+    assert_eq!(nonces.len(), 5);
+    assert!(nonces.contains(&10));
+
+    // Derived handles share the store.
+    assert!(store
+        .set_lifecycle(ACCOUNT, &id, LifecycleState::Tombstoned, None)
+        .unwrap());
+    assert_eq!(store.count(ACCOUNT, Filter::All).unwrap(), 4);
+    let found: Option<Document> = store.with_tombstones().find_by_id(ACCOUNT, &id).unwrap();
+    assert!(found.is_some());
+
+    // Operations without a blocking equivalent run on the store's runtime.
+    let exists = store.block_on(store.store().exists(ACCOUNT, Filter::eq("nonce", 2)));
+    assert!(exists.unwrap());
+}
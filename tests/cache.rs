@@ -0,0 +1,83 @@
+use bson::{doc, Document};
+use lasr_archive::{
+    ArchiveBackends, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder, LifecycleState,
+};
+use std::time::Duration;
+
+const ACCOUNT: ArchiveRecordType = ArchiveRecordType::Account;
+
+/// A store caching up to `capacity` records for `ttl`, holding records "a", "b" and "c".
+async fn store(capacity: usize, ttl: Duration) -> ArchiveStore {
+    let store = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::InMemory)
+        .datastore("cache".to_string())
+        .read_cache(capacity, ttl)
+        .build()
+        .unwrap();
+    for (nonce, id) in ["a", "b", "c"].into_iter().enumerate() {
+        store
+            .create_with_id(ACCOUNT, id, doc! { "nonce": nonce as i32 })
+            .await
+            .unwrap();
+    }
+    store
+}
+
+async fn nonce(store: &ArchiveStore, id: &str) -> Option<i32> {
+    let found: Option<Document> = store.find_by_id(ACCOUNT, id).await.unwrap();
+    found.map(|doc| doc.get_i32("nonce").unwrap())
+}
+
+#[tokio::test]
+async fn evicts_the_least_recently_used_records() {
+    let store = store(2, Duration::from_secs(60)).await;
+    nonce(&store, "a").await;
+    nonce(&store, "b").await;
+    nonce(&store, "a").await;
+    // "b" is now the least recently used, so is evicted to make room for "c".
+    nonce(&store, "c").await;
+
+    // Clearing the backend directly leaves the cache as it was.
+    store.clear_memory().unwrap();
+    assert_eq!(nonce(&store, "a").await, Some(0));
+    assert_eq!(nonce(&store, "c").await, Some(2));
+    assert_eq!(nonce(&store, "b").await, None);
+}
+
+#[tokio::test]
+async fn expires_records_after_their_time_to_live() {
+    let store = store(10, Duration::from_millis(50)).await;
+    nonce(&store, "a").await;
+    store.clear_memory().unwrap();
+    assert_eq!(nonce(&store, "a").await, Some(0));
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(nonce(&store, "a").await, None);
+}
+
+#[tokio::test]
+async fn drops_records_changed_through_the_store() {
+    let store = store(10, Duration::from_secs(60)).await;
+    for id in ["a", "b", "c"] {
+        nonce(&store, id).await;
+    }
+
+    assert!(store
+        .update_by_id(ACCOUNT, "a", doc! { "nonce": 10 })
+        .await
+        .unwrap());
+    assert_eq!(nonce(&store, "a").await, Some(10));
+
+    assert!(store.delete_by_id(ACCOUNT, "b").await.unwrap());
+    assert_eq!(nonce(&store, "b").await, None);
+
+    // Clones of the store share its cache.
+    assert!(store
+        .clone()
+        .set_lifecycle(ACCOUNT, "c", LifecycleState::Tombstoned, Some("reorg"))
+        .await
+        .unwrap());
+    assert_eq!(nonce(&store, "c").await, None);
+    let tombstoned = store.with_tombstones();
+    assert_eq!(nonce(&tombstoned, "c").await, Some(2));
+}
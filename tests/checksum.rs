@@ -0,0 +1,57 @@
+use bson::doc;
+use lasr_archive::{ArchiveRecordType, ArchiveStore, VerificationResult};
+
+const ACCOUNT: ArchiveRecordType = ArchiveRecordType::Account;
+
+#[tokio::test]
+async fn verifies_records_against_their_checksums() {
+    let store = ArchiveStore::in_memory();
+    let (intact, _) = store
+        .create_with_checksum(ACCOUNT, doc! { "owner": "a", "balance": 10 })
+        .await
+        .unwrap();
+    let (tampered, sum) = store
+        .create_with_checksum(ACCOUNT, doc! { "owner": "b", "balance": 20 })
+        .await
+        .unwrap();
+    let unchecksummed = store
+        .create_with_id(ACCOUNT, "plain", doc! { "owner": "c" })
+        .await
+        .unwrap();
+
+    // Change a balance behind the store's back.
+    let mut stored = store.memory_records(ACCOUNT).unwrap();
+    for rec in &mut stored {
+        if rec.get_object_id("_id").map(|id| id.to_hex()) == Ok(tampered.clone()) {
+            rec.insert("balance", 2_000);
+        }
+    }
+    store.clear_memory().unwrap();
+    store.seed_memory(ACCOUNT, stored).unwrap();
+
+    assert_eq!(
+        store.verify(ACCOUNT, &intact).await.unwrap(),
+        VerificationResult::Match
+    );
+    match store.verify(ACCOUNT, &tampered).await.unwrap() {
+        VerificationResult::Mismatch { expected, actual } => {
+            assert_eq!(expected, sum);
+            assert_ne!(actual, sum);
+        }
+        other => panic!("Tampered record verified as {:?}", other),
+    }
+    assert_eq!(
+        store.verify(ACCOUNT, &unchecksummed).await.unwrap(),
+        VerificationResult::NoChecksum
+    );
+    assert_eq!(
+        store.verify(ACCOUNT, "missing").await.unwrap(),
+        VerificationResult::Missing
+    );
+
+    let summary = store.verify_all(ACCOUNT).await.unwrap();
+    assert_eq!(summary.checked, 3);
+    assert_eq!(summary.matched, 1);
+    assert_eq!(summary.unchecksummed, 1);
+    assert_eq!(summary.mismatched, vec![tampered]);
+}
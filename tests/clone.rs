@@ -0,0 +1,55 @@
+use bson::doc;
+use lasr_archive::{ArchiveBackends, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder, Filter};
+use std::collections::HashSet;
+
+const TASKS: i32 = 8;
+const CREATES: i32 = 25;
+
+/// Creates records concurrently through clones of the store, checking every record is written
+/// once and visible through the original.
+async fn create_through_clones(store: ArchiveStore) {
+    let tasks: Vec<_> = (0..TASKS)
+        .map(|task| {
+            let store = store.clone();
+            tokio::spawn(async move {
+                let mut ids = Vec::new();
+                for i in 0..CREATES {
+                    let outcome = store
+                        .create(ArchiveRecordType::Account, doc! { "task": task, "i": i })
+                        .await
+                        .unwrap();
+                    ids.push(outcome.id().unwrap().to_string());
+                }
+                ids
+            })
+        })
+        .collect();
+
+    let mut ids = HashSet::new();
+    for task in tasks {
+        ids.extend(task.await.unwrap());
+    }
+    assert_eq!(ids.len(), (TASKS * CREATES) as usize);
+    let count = store
+        .count(ArchiveRecordType::Account, Filter::All)
+        .await
+        .unwrap();
+    assert_eq!(count, (TASKS * CREATES) as u64);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn creates_concurrently_through_clones_in_memory() {
+    create_through_clones(ArchiveStore::in_memory()).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn creates_concurrently_through_clones_on_the_filesystem() {
+    let root = std::env::temp_dir().join(format!("lasr-archive-clone-{}", std::process::id()));
+    let store = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::Filesystem { root: root.clone() })
+        .datastore("clone".to_string())
+        .build()
+        .unwrap();
+    create_through_clones(store).await;
+    std::fs::remove_dir_all(root).unwrap();
+}
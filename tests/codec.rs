@@ -0,0 +1,128 @@
+use bson::{doc, oid::ObjectId, spec::BinarySubtype, Binary, Document};
+use lasr_archive::{
+    ArchiveBackends, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder, Codec, Compression,
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+const CODECS: [Codec; 4] = [Codec::Bson, Codec::Json, Codec::Cbor, Codec::Bincode];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Receipt {
+    tx_hash: String,
+    block: ObjectId,
+    gas_used: i64,
+    success: bool,
+    logs: Vec<String>,
+    memo: Option<String>,
+}
+
+fn receipt(n: i64) -> Receipt {
+    Receipt {
+        tx_hash: format!("0x{:064x}", n),
+        block: ObjectId::new(),
+        gas_used: 21_000 * n,
+        success: n % 2 == 0,
+        logs: vec![format!("log {}", n), "transfer".to_string()],
+        memo: None,
+    }
+}
+
+fn store(backend: ArchiveBackends, codec: Codec, compression: Compression) -> ArchiveStore {
+    ArchiveStoreBuilder::default()
+        .backend(backend)
+        .datastore("codec".to_string())
+        .codec(codec)
+        .compression(compression)
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn records_round_trip_with_every_codec() {
+    for codec in CODECS {
+        for compression in [Compression::None, Compression::Zstd] {
+            let store = store(ArchiveBackends::InMemory, codec, compression.clone());
+            let rec = receipt(3);
+            let outcome = store
+                .create(ArchiveRecordType::Receipt, &rec)
+                .await
+                .unwrap();
+            let found: Option<Receipt> = store
+                .find_by_id(ArchiveRecordType::Receipt, outcome.id().unwrap())
+                .await
+                .unwrap();
+            assert_eq!(found, Some(rec), "{:?} with {}", codec, compression);
+
+            // Only records encoded with another codec than BSON are wrapped.
+            let stored = store.memory_records(ArchiveRecordType::Receipt).unwrap();
+            let name = stored[0].get_str("_codec").ok();
+            let expected = match codec {
+                Codec::Bson => None,
+                Codec::Json => Some("json"),
+                Codec::Cbor => Some("cbor"),
+                Codec::Bincode => Some("bincode"),
+            };
+            assert_eq!(name, expected);
+        }
+    }
+}
+
+#[tokio::test]
+async fn reads_records_written_with_mixed_codecs() {
+    let root = std::env::temp_dir().join(format!("lasr-archive-codec-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let backend = |root: &Path| ArchiveBackends::Filesystem {
+        root: root.to_path_buf(),
+    };
+    let mut written = Vec::new();
+    for (n, codec) in (0..).zip(CODECS) {
+        let store = store(backend(&root), codec, Compression::None);
+        let rec = receipt(n);
+        let outcome = store
+            .create(ArchiveRecordType::Receipt, &rec)
+            .await
+            .unwrap();
+        written.push((outcome.id().unwrap().to_string(), rec));
+    }
+
+    // Every record is decoded with the codec it was written with, whatever the reader's.
+    let reader = store(backend(&root), Codec::Cbor, Compression::Zstd);
+    for (id, rec) in &written {
+        let found: Option<Receipt> = reader
+            .find_by_id(ArchiveRecordType::Receipt, id)
+            .await
+            .unwrap();
+        assert_eq!(found.as_ref(), Some(rec));
+    }
+    let mut all: Vec<Receipt> = reader.find_all(ArchiveRecordType::Receipt).await.unwrap();
+    all.sort_by_key(|rec| rec.gas_used);
+    let mut expected: Vec<Receipt> = written.into_iter().map(|(_, rec)| rec).collect();
+    expected.sort_by_key(|rec| rec.gas_used);
+    assert_eq!(all, expected);
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn rejects_records_of_unknown_codecs() {
+    let store = ArchiveStore::in_memory();
+    let data = Binary {
+        subtype: BinarySubtype::Generic,
+        bytes: b"payload".to_vec(),
+    };
+    store
+        .seed_memory(
+            ArchiveRecordType::Receipt,
+            vec![doc! { "_id": "a", "_codec": "avro", "_data": data }],
+        )
+        .unwrap();
+    let error = store
+        .find_by_id::<Document>(ArchiveRecordType::Receipt, "a")
+        .await
+        .unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("Unknown archived record codec: 'avro'"),
+        "{:#}",
+        error
+    );
+}
@@ -0,0 +1,106 @@
+use bson::{doc, spec::BinarySubtype, Binary, Document};
+use lasr_archive::{
+    ArchiveBackends, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder, Compression,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Transfer {
+    from: String,
+    to: String,
+    amount: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Batch {
+    transfers: Vec<Transfer>,
+}
+
+/// A batch of a few MB, of the repetitive kind records mostly are.
+fn batch() -> Batch {
+    let transfers = (0..40_000)
+        .map(|i| Transfer {
+            from: format!("0x{:040x}", i % 97),
+            to: format!("0x{:040x}", i % 89),
+            amount: i * 1_000,
+        })
+        .collect();
+    Batch { transfers }
+}
+
+#[tokio::test]
+async fn large_records_round_trip_compressed() {
+    let batch = batch();
+    let plain = bson::to_vec(&bson::to_document(&batch).unwrap())
+        .unwrap()
+        .len();
+    assert!(plain > 4 * 1024 * 1024, "{} bytes", plain);
+
+    for compression in [Compression::Zstd, Compression::Gzip] {
+        let store = ArchiveStoreBuilder::default()
+            .backend(ArchiveBackends::InMemory)
+            .datastore("compression".to_string())
+            .compression(compression.clone())
+            .build()
+            .unwrap();
+        let outcome = store
+            .create(ArchiveRecordType::TransactionBatch, &batch)
+            .await
+            .unwrap();
+        let id = outcome.id().unwrap();
+
+        let stored = store
+            .memory_records(ArchiveRecordType::TransactionBatch)
+            .unwrap();
+        assert_eq!(stored.len(), 1);
+        let encoding = stored[0].get_str("_encoding").unwrap();
+        assert_eq!(encoding, compression.to_string());
+        let size = bson::to_vec(&stored[0]).unwrap().len();
+        assert!(
+            size * 10 < plain,
+            "{} stored {} of {} bytes",
+            compression,
+            size,
+            plain
+        );
+
+        let found: Option<Batch> = store
+            .find_by_id(ArchiveRecordType::TransactionBatch, id)
+            .await
+            .unwrap();
+        assert_eq!(found.as_ref(), Some(&batch), "{}", compression);
+    }
+}
+
+#[tokio::test]
+async fn records_with_wrapper_like_fields_round_trip() {
+    // Plain fields named like those of a compressed record's wrapper are the record's own.
+    let rec = doc! {
+        "encoding": "utf8",
+        "codec": "json",
+        "data": Binary { subtype: BinarySubtype::Generic, bytes: b"payload".to_vec() },
+    };
+    for compression in [Compression::None, Compression::Zstd] {
+        let store = match compression {
+            Compression::None => ArchiveStore::in_memory(),
+            _ => ArchiveStoreBuilder::default()
+                .backend(ArchiveBackends::InMemory)
+                .datastore("compression".to_string())
+                .compression(compression.clone())
+                .build()
+                .unwrap(),
+        };
+        let outcome = store
+            .create(ArchiveRecordType::Account, &rec)
+            .await
+            .unwrap();
+        let found: Document = store
+            .find_by_id(ArchiveRecordType::Account, outcome.id().unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        for field in ["encoding", "codec", "data"] {
+            assert_eq!(found.get(field), rec.get(field), "{}", compression);
+        }
+    }
+}
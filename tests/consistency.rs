@@ -0,0 +1,79 @@
+use bson::{doc, Document};
+use lasr_archive::{
+    Acknowledgment, ArchiveBackends, ArchiveErrorKind, ArchiveRecordType, ArchiveStore,
+    ArchiveStoreBuilder, Filter, ReadOptions, ReadPreference, WriteConcern, WriteOptions,
+};
+use std::time::Duration;
+
+const BLOCK: ArchiveRecordType = ArchiveRecordType::Block;
+
+#[tokio::test]
+async fn backends_without_replicas_ignore_the_options() {
+    let store = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::InMemory)
+        .datastore("consistency".to_string())
+        .write_concern(WriteConcern::majority())
+        .read_preference(ReadPreference::SecondaryPreferred)
+        .build()
+        .unwrap();
+
+    let outcome = store
+        .create_with_opts(BLOCK, doc! { "block_height": 1 }, &WriteOptions::durable())
+        .await
+        .unwrap();
+    let id = outcome.id().unwrap().to_string();
+    store
+        .create_many_with_opts(
+            BLOCK,
+            vec![doc! { "block_height": 2 }, doc! { "block_height": 3 }],
+            &WriteOptions::default(),
+        )
+        .await
+        .unwrap();
+
+    let nearest = ReadOptions {
+        read_preference: Some(ReadPreference::Nearest),
+    };
+    let found: Option<Document> = store
+        .find_by_id_with_opts(BLOCK, &id, &nearest)
+        .await
+        .unwrap();
+    assert_eq!(found.unwrap().get_i32("block_height"), Ok(1));
+    let found: Vec<Document> = store
+        .query_with_opts(BLOCK, Filter::gte("block_height", 2), &nearest)
+        .await
+        .unwrap();
+    assert_eq!(found.len(), 2);
+}
+
+#[tokio::test]
+async fn rejects_unacknowledged_writes() {
+    let unacknowledged = WriteConcern {
+        w: Some(Acknowledgment::Nodes(0)),
+        ..WriteConcern::default()
+    };
+    let options = WriteOptions {
+        write_concern: Some(unacknowledged.clone()),
+    };
+    let store = ArchiveStore::in_memory();
+    let error = store
+        .create_with_opts(BLOCK, doc! { "block_height": 1 }, &options)
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), ArchiveErrorKind::InvalidInput);
+    assert_eq!(store.count(BLOCK, Filter::All).await.unwrap(), 0);
+
+    // Nor can a store be built with such a write concern, or one that never waits.
+    let never_waits = WriteConcern {
+        w_timeout: Some(Duration::ZERO),
+        ..WriteConcern::majority()
+    };
+    for write_concern in [unacknowledged, never_waits] {
+        let built = ArchiveStoreBuilder::default()
+            .backend(ArchiveBackends::InMemory)
+            .datastore("consistency".to_string())
+            .write_concern(write_concern.clone())
+            .build();
+        assert!(built.is_err(), "{}", write_concern);
+    }
+}
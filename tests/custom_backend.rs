@@ -0,0 +1,175 @@
+use async_trait::async_trait;
+use bson::{doc, oid::ObjectId, Bson, Document};
+use lasr_archive::{
+    ArchiveBackend, ArchiveBackends, ArchiveError, ArchiveErrorKind, ArchiveRecordType,
+    ArchiveStore, ArchiveStoreBuilder, Filter, PageRequest,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+const ACCOUNT: ArchiveRecordType = ArchiveRecordType::Account;
+
+/// A backend implementing only the operations every backend must, keeping records in a map.
+#[derive(Default)]
+struct MapBackend {
+    records: Mutex<HashMap<ArchiveRecordType, BTreeMap<String, Document>>>,
+}
+
+impl MapBackend {
+    fn matching<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<Vec<T>, ArchiveError> {
+        let records = self.records.lock().unwrap();
+        let docs = records
+            .get(&rec_type)
+            .into_iter()
+            .flat_map(|docs| docs.values());
+        docs.filter(|doc| filter.matches(doc))
+            .map(|doc| Ok(bson::from_document(doc.clone()).map_err(anyhow::Error::from)?))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl ArchiveBackend for MapBackend {
+    async fn create<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let mut doc = bson::to_document(&rec).map_err(anyhow::Error::from)?;
+        let id = match doc.get("_id") {
+            Some(Bson::String(id)) => id.clone(),
+            _ => ObjectId::new().to_hex(),
+        };
+        doc.insert("_id", id.clone());
+        let mut records = self.records.lock().unwrap();
+        records.entry(rec_type).or_default().insert(id.clone(), doc);
+        Ok(id)
+    }
+
+    async fn find_all<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        self.matching(rec_type, &Filter::All)
+    }
+
+    async fn query<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        filter: &Filter,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        self.matching(rec_type, filter)
+    }
+
+    async fn find_by_id<T: DeserializeOwned>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<Option<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        let records = self.records.lock().unwrap();
+        let doc = records
+            .get(&rec_type)
+            .and_then(|docs| docs.get(id))
+            .cloned();
+        Ok(doc
+            .map(bson::from_document)
+            .transpose()
+            .map_err(anyhow::Error::from)?)
+    }
+
+    async fn delete_by_id(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+    ) -> Result<bool, ArchiveError> {
+        let mut records = self.records.lock().unwrap();
+        Ok(records
+            .get_mut(&rec_type)
+            .is_some_and(|docs| docs.remove(id).is_some()))
+    }
+
+    async fn update_by_id<T: Serialize>(
+        &self,
+        rec_type: ArchiveRecordType,
+        id: &str,
+        rec: T,
+    ) -> Result<bool, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        let mut doc = bson::to_document(&rec).map_err(anyhow::Error::from)?;
+        doc.insert("_id", id);
+        let mut records = self.records.lock().unwrap();
+        match records.get_mut(&rec_type).and_then(|docs| docs.get_mut(id)) {
+            Some(stored) => {
+                *stored = doc;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+fn store(name: &str) -> ArchiveStore {
+    ArchiveStore::register_backend(name, |_uri, _datastore| Ok(MapBackend::default())).unwrap();
+    ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::Custom {
+            name: name.to_string(),
+        })
+        .datastore("custom".to_string())
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn creates_and_finds_records_in_a_registered_backend() {
+    let store = store("map");
+    let a = store
+        .create(ACCOUNT, doc! { "owner_address": "a", "nonce": 1 })
+        .await
+        .unwrap();
+    let a = a.id().unwrap();
+    store
+        .create(ACCOUNT, doc! { "owner_address": "b", "nonce": 2 })
+        .await
+        .unwrap();
+
+    let found: Document = store.find_by_id(ACCOUNT, a).await.unwrap().unwrap();
+    assert_eq!(found.get_str("owner_address").unwrap(), "a");
+    let all: Vec<Document> = store.find_all(ACCOUNT).await.unwrap();
+    assert_eq!(all.len(), 2);
+    let queried: Vec<Document> = store
+        .query(ACCOUNT, Filter::eq("owner_address", "b"))
+        .await
+        .unwrap();
+    assert_eq!(queried.len(), 1);
+    assert_eq!(queried[0].get_i32("nonce").unwrap(), 2);
+}
+
+#[tokio::test]
+async fn operations_a_registered_backend_doesnt_implement_are_unsupported() {
+    let store = store("map-minimal");
+    let error = store
+        .find_page::<Document>(ACCOUNT, PageRequest::first(10))
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), ArchiveErrorKind::Unsupported);
+}
@@ -0,0 +1,109 @@
+use bson::doc;
+use lasr_archive::{
+    ArchiveBackends, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder, EncryptionConfig,
+    LifecycleState,
+};
+use std::time::Duration;
+
+fn store(window: Duration, encryption: Option<EncryptionConfig>) -> ArchiveStore {
+    let mut builder = ArchiveStoreBuilder::default();
+    builder
+        .backend(ArchiveBackends::InMemory)
+        .datastore("dedup".to_string())
+        .dedup_window(window);
+    if let Some(encryption) = encryption {
+        builder.encryption(encryption);
+    }
+    builder.build().unwrap()
+}
+
+/// Creates the record several times, returning the ids of the writes.
+async fn create_repeatedly(store: &ArchiveStore, nonce: i32) -> Vec<String> {
+    let mut ids = Vec::new();
+    for _ in 0..3 {
+        let outcome = store
+            .create(ArchiveRecordType::Account, doc! { "nonce": nonce })
+            .await
+            .unwrap();
+        ids.push(outcome.id().unwrap().to_string());
+    }
+    ids
+}
+
+fn stored(store: &ArchiveStore) -> usize {
+    store
+        .memory_records(ArchiveRecordType::Account)
+        .unwrap()
+        .len()
+}
+
+#[tokio::test]
+async fn writes_duplicates_within_the_window_once() {
+    for encryption in [None, Some(EncryptionConfig::new("key", [7; 32]))] {
+        let encrypted = encryption.is_some();
+        let store = store(Duration::from_secs(60), encryption);
+
+        let ids = create_repeatedly(&store, 1).await;
+        assert!(
+            ids.iter().all(|id| *id == ids[0]),
+            "encrypted: {}",
+            encrypted
+        );
+        assert_eq!(stored(&store), 1, "encrypted: {}", encrypted);
+
+        // Other records are still written.
+        let other = create_repeatedly(&store, 2).await;
+        assert_ne!(other[0], ids[0]);
+        assert_eq!(stored(&store), 2, "encrypted: {}", encrypted);
+    }
+}
+
+#[tokio::test]
+async fn writes_duplicates_again_once_the_window_passes() {
+    let store = store(Duration::from_millis(50), None);
+    let first = create_repeatedly(&store, 1).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let second = create_repeatedly(&store, 1).await;
+    assert_ne!(first[0], second[0]);
+    assert_eq!(stored(&store), 2);
+}
+
+#[tokio::test]
+async fn writes_duplicates_again_once_deleted() {
+    let store = store(Duration::from_secs(60), None);
+    let first = create_repeatedly(&store, 1).await;
+    assert!(store
+        .delete_by_id(ArchiveRecordType::Account, &first[0])
+        .await
+        .unwrap());
+    let second = create_repeatedly(&store, 1).await;
+    assert_ne!(first[0], second[0]);
+    assert_eq!(stored(&store), 1);
+}
+
+#[tokio::test]
+async fn writes_records_identical_to_tombstoned_ones_again() {
+    let store = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::InMemory)
+        .datastore("dedup".to_string())
+        .content_dedup(true)
+        .build()
+        .unwrap();
+    let first = create_repeatedly(&store, 1).await;
+    assert!(first.iter().all(|id| *id == first[0]));
+    assert_eq!(stored(&store), 1);
+
+    assert!(store
+        .set_lifecycle(
+            ArchiveRecordType::Account,
+            &first[0],
+            LifecycleState::Tombstoned,
+            Some("reorg"),
+        )
+        .await
+        .unwrap());
+    let second = create_repeatedly(&store, 1).await;
+    assert_ne!(second[0], first[0]);
+    assert!(second.iter().all(|id| *id == second[0]));
+    assert_eq!(stored(&store), 2);
+}
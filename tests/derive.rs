@@ -0,0 +1,91 @@
+#![cfg(feature = "derive")]
+
+use bson::{Bson, Document};
+use lasr_archive::{
+    ArchiveRecord, ArchiveRecordType, ArchiveStore, Filter, IndexSpec, DEFAULT_SCHEMA_VERSION,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ArchiveRecord)]
+#[archive(collection = "receipts", key = "tx_hash", version = 3)]
+#[archive(unique_index = "block_height,index")]
+struct Receipt {
+    tx_hash: String,
+    block_height: i64,
+    index: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ArchiveRecord)]
+#[archive(collection = "bridge_events")]
+struct BridgeEvent {
+    #[archive(key)]
+    #[serde(rename = "eventId")]
+    event_id: String,
+    #[archive(index)]
+    chain: String,
+    #[archive(unique)]
+    r#ref: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ArchiveRecord)]
+#[archive(collection = "accounts")]
+struct Account {
+    owner_address: String,
+}
+
+#[test]
+fn maps_attributes_on_the_type() {
+    assert_eq!(Receipt::record_type(), ArchiveRecordType::Receipt);
+    assert_eq!(Receipt::KEY_FIELD, Some("tx_hash"));
+    assert_eq!(Receipt::SCHEMA_VERSION, 3);
+    assert_eq!(
+        Receipt::indexes(),
+        vec![IndexSpec::compound(&["block_height", "index"]).unique()]
+    );
+}
+
+#[test]
+fn maps_attributes_on_fields_by_their_serde_names() {
+    assert_eq!(
+        BridgeEvent::record_type(),
+        ArchiveRecordType::Custom("bridge_events".to_string())
+    );
+    assert_eq!(BridgeEvent::KEY_FIELD, Some("eventId"));
+    assert_eq!(
+        BridgeEvent::indexes(),
+        vec![IndexSpec::new("chain"), IndexSpec::new("ref").unique()]
+    );
+    let event = BridgeEvent {
+        event_id: "e".to_string(),
+        chain: "lasr".to_string(),
+        r#ref: "r".to_string(),
+    };
+    assert_eq!(event.key(), Some(Bson::String("e".to_string())));
+}
+
+#[test]
+fn defaults_what_isnt_declared() {
+    assert_eq!(Account::record_type(), ArchiveRecordType::Account);
+    assert_eq!(Account::KEY_FIELD, None);
+    assert_eq!(Account::SCHEMA_VERSION, DEFAULT_SCHEMA_VERSION);
+    assert!(Account::indexes().is_empty());
+}
+
+#[tokio::test]
+async fn archives_derived_records_in_their_collection() {
+    let store = ArchiveStore::in_memory();
+    let receipts = store.typed::<Receipt>();
+    let receipt = Receipt {
+        tx_hash: "0xabc".to_string(),
+        block_height: 7,
+        index: 0,
+    };
+    receipts.create(receipt.clone()).await.unwrap();
+    assert_eq!(receipts.find_by_key("0xabc").await.unwrap(), Some(receipt));
+    let stored: Vec<Document> = store
+        .query(ArchiveRecordType::Receipt, Filter::All)
+        .await
+        .unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].get_str("tx_hash"), Ok("0xabc"));
+}
@@ -0,0 +1,200 @@
+use bson::{doc, Document};
+use lasr_archive::{
+    ArchiveBackends, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder, EncryptionConfig,
+    StaticKeys,
+};
+use std::path::PathBuf;
+
+const ACCOUNT: ArchiveRecordType = ArchiveRecordType::Account;
+
+fn store(encryption: Option<EncryptionConfig>) -> ArchiveStore {
+    let mut builder = ArchiveStoreBuilder::default();
+    builder
+        .backend(ArchiveBackends::InMemory)
+        .datastore("encryption".to_string());
+    if let Some(encryption) = encryption {
+        builder.encryption(encryption);
+    }
+    builder.build().unwrap()
+}
+
+/// A filesystem archive in a scratch directory, so stores with different keys can share it.
+struct Scratch {
+    root: PathBuf,
+}
+
+impl Scratch {
+    fn new(name: &str) -> Scratch {
+        let root = std::env::temp_dir().join(format!(
+            "lasr-archive-encryption-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        Scratch { root }
+    }
+
+    fn store(&self, keys: StaticKeys) -> ArchiveStore {
+        ArchiveStoreBuilder::default()
+            .backend(ArchiveBackends::Filesystem {
+                root: self.root.clone(),
+            })
+            .datastore("encryption".to_string())
+            .encryption(EncryptionConfig::with_provider(keys))
+            .build()
+            .unwrap()
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+#[tokio::test]
+async fn records_with_wrapper_like_fields_round_trip() {
+    for encryption in [None, Some(EncryptionConfig::new("key", [7; 32]))] {
+        let store = store(encryption);
+        let rec = doc! {
+            "encryption": "none",
+            "key_id": "key",
+            "nonce": 1,
+            "data": "plain",
+        };
+        let outcome = store.create(ACCOUNT, rec.clone()).await.unwrap();
+        let found: Document = store
+            .find_by_id(ACCOUNT, outcome.id().unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        for (field, value) in &rec {
+            assert_eq!(found.get(field), Some(value), "{}", field);
+        }
+    }
+}
+
+#[tokio::test]
+async fn encrypts_records_at_rest() {
+    let store = store(Some(EncryptionConfig::new("key", [7; 32])));
+    let rec = doc! { "owner_address": "secret-owner", "nonce": 5 };
+    let outcome = store.create(ACCOUNT, rec.clone()).await.unwrap();
+    let id = outcome.id().unwrap();
+
+    let found: Document = store.find_by_id(ACCOUNT, id).await.unwrap().unwrap();
+    assert_eq!(found.get_str("owner_address").unwrap(), "secret-owner");
+    assert_eq!(found.get_i32("nonce").unwrap(), 5);
+    let all: Vec<Document> = store.find_all(ACCOUNT).await.unwrap();
+    assert_eq!(all.len(), 1);
+    assert_eq!(all[0].get_str("owner_address").unwrap(), "secret-owner");
+
+    // The backend only sees the ciphertext, the key id and the record's id.
+    let stored = store.memory_records(ACCOUNT).unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].get_str("_encryption").unwrap(), "aes-256-gcm");
+    assert_eq!(stored[0].get_str("_key_id").unwrap(), "key");
+    assert!(!stored[0].contains_key("owner_address"));
+    assert!(!stored[0].contains_key("nonce"));
+    let raw = bson::to_vec(&stored[0]).unwrap();
+    assert!(!raw
+        .windows(b"secret-owner".len())
+        .any(|window| window == b"secret-owner"));
+}
+
+#[tokio::test]
+async fn reads_records_encrypted_with_rotated_keys() {
+    let scratch = Scratch::new("rotated");
+    let old = scratch.store(StaticKeys::new("old", [1; 32]));
+    let old_id = old
+        .create(ACCOUNT, doc! { "nonce": 1 })
+        .await
+        .unwrap()
+        .id()
+        .unwrap()
+        .to_string();
+
+    // After rotating, new records are encrypted with the new key and old ones still read.
+    let rotated = scratch.store(StaticKeys::new("new", [2; 32]).with_key("old", [1; 32]));
+    let new_id = rotated
+        .create(ACCOUNT, doc! { "nonce": 2 })
+        .await
+        .unwrap()
+        .id()
+        .unwrap()
+        .to_string();
+    for (id, nonce) in [(&old_id, 1), (&new_id, 2)] {
+        let found: Document = rotated.find_by_id(ACCOUNT, id).await.unwrap().unwrap();
+        assert_eq!(found.get_i32("nonce").unwrap(), nonce);
+    }
+
+    // Each record is decrypted with the key it was written with, whatever the current key.
+    let only_new = scratch.store(StaticKeys::new("new", [2; 32]));
+    let found: Document = only_new
+        .find_by_id(ACCOUNT, &new_id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.get_i32("nonce").unwrap(), 2);
+}
+
+#[tokio::test]
+async fn fails_to_read_records_encrypted_with_unknown_keys() {
+    let scratch = Scratch::new("unknown");
+    let id = scratch
+        .store(StaticKeys::new("old", [1; 32]))
+        .create(ACCOUNT, doc! { "nonce": 1 })
+        .await
+        .unwrap()
+        .id()
+        .unwrap()
+        .to_string();
+
+    let store = scratch.store(StaticKeys::new("new", [2; 32]));
+    let error = store
+        .find_by_id::<Document>(ACCOUNT, &id)
+        .await
+        .unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("Unknown encryption key 'old'"),
+        "{:#}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn fails_to_read_ciphertexts_moved_to_another_record() {
+    let store = store(Some(EncryptionConfig::new("key", [7; 32])));
+    for (id, balance) in [("alice", 1), ("bob", 1_000_000)] {
+        store
+            .create_with_id(ACCOUNT, id, doc! { "balance": balance })
+            .await
+            .unwrap();
+    }
+    // Replacements are bound to their record's id too.
+    assert!(store
+        .update_by_id(ACCOUNT, "bob", doc! { "balance": 2_000_000 })
+        .await
+        .unwrap());
+    let bob: Document = store.find_by_id(ACCOUNT, "bob").await.unwrap().unwrap();
+    assert_eq!(bob.get_i32("balance"), Ok(2_000_000));
+
+    // Swap the two accounts' ciphertexts, keeping their ids.
+    let mut stored = store.memory_records(ACCOUNT).unwrap();
+    let (alice, bob) = stored.split_at_mut(1);
+    for field in ["_data", "_nonce"] {
+        let value = alice[0].get(field).unwrap().clone();
+        alice[0].insert(field, bob[0].get(field).unwrap().clone());
+        bob[0].insert(field, value);
+    }
+    store.clear_memory().unwrap();
+    store.seed_memory(ACCOUNT, stored).unwrap();
+
+    for id in ["alice", "bob"] {
+        let error = store.find_by_id::<Document>(ACCOUNT, id).await.unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("tampered record"),
+            "{:#}",
+            error
+        );
+    }
+}
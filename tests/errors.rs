@@ -0,0 +1,125 @@
+use anyhow::Context;
+use bson::doc;
+use lasr_archive::{ArchiveError, ArchiveErrorKind};
+use std::io;
+
+fn io_error(kind: io::ErrorKind) -> io::Error {
+    io::Error::new(kind, "failed")
+}
+
+#[test]
+fn classifies_io_errors() {
+    for (kind, expected) in [
+        (io::ErrorKind::NotFound, ArchiveErrorKind::NotFound),
+        (io::ErrorKind::AlreadyExists, ArchiveErrorKind::DuplicateKey),
+        (
+            io::ErrorKind::ConnectionRefused,
+            ArchiveErrorKind::ConnectionFailed,
+        ),
+        (
+            io::ErrorKind::BrokenPipe,
+            ArchiveErrorKind::ConnectionFailed,
+        ),
+        (
+            io::ErrorKind::TimedOut,
+            ArchiveErrorKind::BackendUnavailable,
+        ),
+        (
+            io::ErrorKind::InvalidData,
+            ArchiveErrorKind::SerializationError,
+        ),
+        (io::ErrorKind::PermissionDenied, ArchiveErrorKind::Other),
+    ] {
+        assert_eq!(
+            ArchiveError::from(io_error(kind)).kind(),
+            expected,
+            "{:?}",
+            kind
+        );
+    }
+}
+
+#[test]
+fn classifies_errors_by_their_cause_whatever_the_context() {
+    let error: ArchiveError = Err::<(), _>(io_error(io::ErrorKind::ConnectionReset))
+        .context("Reading the record")
+        .context("Finding the record")
+        .unwrap_err()
+        .into();
+    assert_eq!(error.kind(), ArchiveErrorKind::ConnectionFailed);
+    assert!(format!("{:#}", error).starts_with("Finding the record: Reading the record"));
+
+    // An error already classified keeps its kind when given more context.
+    let inner = ArchiveError::InvalidInput(anyhow::anyhow!("Bad record type"));
+    let error: ArchiveError = anyhow::Error::new(inner).context("Creating").into();
+    assert_eq!(error.kind(), ArchiveErrorKind::InvalidInput);
+
+    let error: ArchiveError = anyhow::anyhow!("Something else").into();
+    assert_eq!(error.kind(), ArchiveErrorKind::Other);
+}
+
+#[test]
+fn classifies_serialisation_errors() {
+    let error = bson::from_document::<String>(doc! { "a": 1 }).unwrap_err();
+    assert_eq!(
+        ArchiveError::from(error).kind(),
+        ArchiveErrorKind::SerializationError
+    );
+    let error = serde_json::from_str::<u32>("{").unwrap_err();
+    assert_eq!(
+        ArchiveError::from(error).kind(),
+        ArchiveErrorKind::SerializationError
+    );
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::*;
+    use lasr_archive::{ArchiveBackends, ArchiveRecordType, ArchiveStoreBuilder};
+    use rusqlite::ffi;
+
+    fn failure(code: std::os::raw::c_int) -> ArchiveError {
+        rusqlite::Error::SqliteFailure(ffi::Error::new(code), None).into()
+    }
+
+    #[test]
+    fn classifies_sqlite_errors() {
+        for (code, expected) in [
+            (
+                ffi::SQLITE_CONSTRAINT_PRIMARYKEY,
+                ArchiveErrorKind::DuplicateKey,
+            ),
+            (ffi::SQLITE_BUSY, ArchiveErrorKind::BackendUnavailable),
+            (ffi::SQLITE_LOCKED, ArchiveErrorKind::BackendUnavailable),
+            (ffi::SQLITE_CANTOPEN, ArchiveErrorKind::ConnectionFailed),
+            (ffi::SQLITE_CORRUPT, ArchiveErrorKind::SerializationError),
+            (ffi::SQLITE_FULL, ArchiveErrorKind::Other),
+        ] {
+            assert_eq!(failure(code).kind(), expected, "{}", code);
+        }
+        assert_eq!(
+            ArchiveError::from(rusqlite::Error::QueryReturnedNoRows).kind(),
+            ArchiveErrorKind::NotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_to_store_records_under_an_id_twice() {
+        let store = ArchiveStoreBuilder::default()
+            .uri("sqlite::memory:".to_string())
+            .backend(ArchiveBackends::Sqlite)
+            .datastore("errors".to_string())
+            .ignore_duplicate_ids(false)
+            .build()
+            .unwrap();
+        store
+            .create_with_id(ArchiveRecordType::Account, "a", doc! { "nonce": 0 })
+            .await
+            .unwrap();
+        let error = store
+            .create_with_id(ArchiveRecordType::Account, "a", doc! { "nonce": 1 })
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), ArchiveErrorKind::DuplicateKey);
+    }
+}
@@ -0,0 +1,117 @@
+use bson::doc;
+use lasr_archive::{
+    ArchiveBackends, ArchiveMetrics, ArchiveOperation, ArchiveRecordType, ArchiveStoreBuilder,
+    Outcome,
+};
+use std::sync::{Arc, Mutex};
+
+/// The name, outcome and labels of an operation reported.
+type Reported = (&'static str, Outcome, String);
+
+/// Every operation reported.
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<Reported>>>);
+
+impl ArchiveMetrics for Recorder {
+    fn on_operation(&self, op: &ArchiveOperation) {
+        let labels = op.labels.to_string();
+        self.0.lock().unwrap().push((op.op, op.outcome, labels));
+    }
+}
+
+impl Recorder {
+    fn take(&self) -> Vec<Reported> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+#[tokio::test]
+async fn labels_reach_the_metrics_hook() {
+    let recorder = Recorder::default();
+    let store = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::InMemory)
+        .datastore("labels".to_string())
+        .metrics(recorder.clone())
+        .build()
+        .unwrap();
+
+    store
+        .create(ArchiveRecordType::Account, doc! { "nonce": 1 })
+        .await
+        .unwrap();
+    assert_eq!(
+        recorder.take(),
+        vec![("create", Outcome::Success, String::new())]
+    );
+
+    let tenant = store.with_labels(&[("tenant", "acme")]);
+    let job = tenant.with_labels(&[("job", "nightly_sync"), ("tenant", "other")]);
+    tenant
+        .create(ArchiveRecordType::Account, doc! { "nonce": 2 })
+        .await
+        .unwrap();
+    job.find_sampled::<bson::Document>(ArchiveRecordType::Account, 2.0)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        recorder.take(),
+        vec![
+            ("create", Outcome::Success, "tenant=acme".to_string()),
+            (
+                "find_sampled",
+                Outcome::Failure,
+                "tenant=other,job=nightly_sync".to_string()
+            ),
+        ]
+    );
+    // Labelled handles leave the store's own operations unlabelled.
+    assert!(store.labels().is_empty());
+}
+
+#[cfg(feature = "metrics")]
+#[tokio::test]
+async fn labels_reach_prometheus_metrics() {
+    use lasr_archive::PrometheusMetrics;
+    use prometheus::Registry;
+
+    let metrics = PrometheusMetrics::with_label_keys(&Registry::new(), &["tenant"]).unwrap();
+    let store = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::InMemory)
+        .datastore("labels".to_string())
+        .metrics(metrics.clone())
+        .build()
+        .unwrap();
+    store
+        .with_labels(&[("tenant", "acme"), ("job", "nightly_sync")])
+        .create(ArchiveRecordType::Account, doc! { "nonce": 1 })
+        .await
+        .unwrap();
+    store
+        .create(ArchiveRecordType::Account, doc! { "nonce": 2 })
+        .await
+        .unwrap();
+
+    let gathered = metrics.gather();
+    // Labels are exposed in alphabetical order.
+    let counted = |tenant: &str| {
+        format!(
+            "lasr_archive_operations_total{{backend=\"memory\",datastore=\"labels\",op=\"create\",\
+             outcome=\"success\",rec_type=\"account\",tenant=\"{}\"}} 1",
+            tenant
+        )
+    };
+    assert!(gathered.contains(&counted("acme")), "{}", gathered);
+    assert!(gathered.contains(&counted("")), "{}", gathered);
+    // Only the given keys are added.
+    assert!(!gathered.contains("nightly_sync"), "{}", gathered);
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn rejects_built_in_label_keys() {
+    use lasr_archive::{ArchiveErrorKind, PrometheusMetrics};
+    use prometheus::Registry;
+
+    let error = PrometheusMetrics::with_label_keys(&Registry::new(), &["op"]).unwrap_err();
+    assert_eq!(error.kind(), ArchiveErrorKind::InvalidInput);
+}
@@ -0,0 +1,135 @@
+use bson::{doc, Document};
+use lasr_archive::{
+    ArchiveBackends, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder, Filter, LifecycleState,
+    Versioned, ACCOUNT_ADDRESS_FIELD,
+};
+
+const ACCOUNT: ArchiveRecordType = ArchiveRecordType::Account;
+
+fn versioned_store() -> ArchiveStore {
+    ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::InMemory)
+        .datastore("lifecycle".to_string())
+        .versioned(ACCOUNT, ACCOUNT_ADDRESS_FIELD)
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn tombstones_records_in_place() {
+    let store = ArchiveStore::in_memory();
+    let id = store
+        .create_with_id(ACCOUNT, "a", doc! { "owner_address": "a" })
+        .await
+        .unwrap();
+    assert!(store
+        .set_lifecycle(ACCOUNT, &id, LifecycleState::Tombstoned, Some("reorg"))
+        .await
+        .unwrap());
+
+    let found: Option<Document> = store.find_by_id(ACCOUNT, &id).await.unwrap();
+    assert!(found.is_none());
+    let found: Option<Document> = store
+        .with_tombstones()
+        .find_by_id(ACCOUNT, &id)
+        .await
+        .unwrap();
+    assert!(found.is_some());
+
+    // Restoring the record makes it visible again.
+    assert!(store
+        .set_lifecycle(ACCOUNT, &id, LifecycleState::Archived, None)
+        .await
+        .unwrap());
+    let found: Option<Document> = store.find_by_id(ACCOUNT, &id).await.unwrap();
+    assert!(found.is_some());
+}
+
+#[tokio::test]
+async fn tombstones_versions_of_versioned_types_in_place() {
+    let store = versioned_store();
+    let first = store
+        .create(ACCOUNT, doc! { "owner_address": "a", "nonce": 1 })
+        .await
+        .unwrap();
+    let first = first.id().unwrap().to_string();
+    store
+        .update_by_id(ACCOUNT, &first, doc! { "owner_address": "a", "nonce": 2 })
+        .await
+        .unwrap();
+    let second = store
+        .latest::<Document>(ACCOUNT, "a")
+        .await
+        .unwrap()
+        .unwrap()
+        .id;
+
+    assert!(store
+        .set_lifecycle(ACCOUNT, &second, LifecycleState::Tombstoned, Some("reorg"))
+        .await
+        .unwrap());
+    // No tombstoned copy is archived as a further version.
+    let stored: Vec<Document> = store.with_tombstones().find_all(ACCOUNT).await.unwrap();
+    assert_eq!(stored.len(), 2);
+
+    let found: Option<Document> = store.find_by_id(ACCOUNT, &second).await.unwrap();
+    assert!(found.is_none());
+    let latest: Versioned<Document> = store.latest(ACCOUNT, "a").await.unwrap().unwrap();
+    assert_eq!(latest.id, first);
+    let history: Vec<Versioned<Document>> = store.history(ACCOUNT, "a").await.unwrap();
+    assert_eq!(
+        history.iter().map(|v| v.id.as_str()).collect::<Vec<_>>(),
+        vec![first.as_str()]
+    );
+
+    // Tombstoning the rest leaves no live version.
+    assert_eq!(
+        store
+            .tombstone_where(ACCOUNT, Filter::All, "reorg")
+            .await
+            .unwrap(),
+        1
+    );
+    let all: Vec<Document> = store.find_all(ACCOUNT).await.unwrap();
+    assert!(all.is_empty());
+    assert!(store
+        .latest::<Document>(ACCOUNT, "a")
+        .await
+        .unwrap()
+        .is_none());
+    assert!(store
+        .history::<Document>(ACCOUNT, "a")
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn doesnt_revive_tombstoned_records_by_updating_them() {
+    let store = ArchiveStore::in_memory();
+    let id = store
+        .create_with_id(ACCOUNT, "a", doc! { "owner_address": "a", "nonce": 1 })
+        .await
+        .unwrap();
+    store
+        .set_lifecycle(ACCOUNT, &id, LifecycleState::Tombstoned, Some("reorg"))
+        .await
+        .unwrap();
+
+    for handle in [store.clone(), store.with_tombstones()] {
+        assert!(!handle
+            .update_by_id(ACCOUNT, &id, doc! { "owner_address": "a", "nonce": 2 })
+            .await
+            .unwrap());
+    }
+    let found: Option<Document> = store.find_by_id(ACCOUNT, &id).await.unwrap();
+    assert!(found.is_none());
+    let tombstone = store
+        .with_tombstones()
+        .find_envelope_by_id::<Document>(ACCOUNT, &id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(tombstone.lifecycle.state, LifecycleState::Tombstoned);
+    assert_eq!(tombstone.record.get_i32("nonce"), Ok(1));
+}
@@ -0,0 +1,198 @@
+use bson::{doc, Document};
+use futures::StreamExt;
+use lasr_archive::{
+    ArchiveBackends, ArchiveErrorKind, ArchiveEventKind, ArchiveRecordType, ArchiveStore,
+    ArchiveStoreBuilder, Filter, IndexSpec,
+};
+
+const ACCOUNT: ArchiveRecordType = ArchiveRecordType::Account;
+
+#[tokio::test]
+async fn enforces_duplicate_ids_and_unique_indexes() {
+    let store = ArchiveStore::in_memory();
+    store
+        .ensure_indexes(ACCOUNT, vec![IndexSpec::new("owner_address").unique()])
+        .await
+        .unwrap();
+    store
+        .create(ACCOUNT, doc! { "owner_address": "a" })
+        .await
+        .unwrap();
+    let error = store
+        .create(ACCOUNT, doc! { "owner_address": "a" })
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), ArchiveErrorKind::DuplicateKey);
+
+    let error = store
+        .seed_memory(ACCOUNT, vec![doc! { "_id": "x" }, doc! { "_id": "x" }])
+        .unwrap_err();
+    assert_eq!(error.kind(), ArchiveErrorKind::DuplicateKey);
+
+    // A failing atomic batch writes none of its records.
+    let error = store
+        .create_atomic(vec![
+            (ArchiveRecordType::Block, doc! { "block_height": 1 }),
+            (ACCOUNT, doc! { "owner_address": "a" }),
+        ])
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), ArchiveErrorKind::DuplicateKey);
+    assert_eq!(
+        store
+            .count(ArchiveRecordType::Block, Filter::All)
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn seeds_inspects_and_clears_records() {
+    let store = ArchiveStore::in_memory();
+    let ids = store
+        .seed_memory(
+            ACCOUNT,
+            vec![doc! { "_id": "seeded", "nonce": 1 }, doc! { "nonce": 2 }],
+        )
+        .unwrap();
+    assert_eq!(ids[0], "seeded");
+    let found: Option<Document> = store.find_by_id(ACCOUNT, "seeded").await.unwrap();
+    assert_eq!(found.unwrap().get_i32("nonce").unwrap(), 1);
+
+    // Records are inspected as they are stored, with their provenance.
+    store.create(ACCOUNT, doc! { "nonce": 3 }).await.unwrap();
+    let stored = store.memory_records(ACCOUNT).unwrap();
+    assert_eq!(stored.len(), 3);
+    assert!(stored.iter().any(|doc| doc.contains_key("_archived_at")));
+
+    store.clear_memory().unwrap();
+    assert!(store.memory_records(ACCOUNT).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn publishes_changes_to_subscribers() {
+    let store = ArchiveStore::in_memory();
+    let events = store.subscribe(ACCOUNT).await.unwrap();
+    futures::pin_mut!(events);
+
+    let id = store
+        .create(ACCOUNT, doc! { "nonce": 1 })
+        .await
+        .unwrap()
+        .id()
+        .unwrap()
+        .to_string();
+    store
+        .update_by_id(ACCOUNT, &id, doc! { "nonce": 2 })
+        .await
+        .unwrap();
+    store.delete_by_id(ACCOUNT, &id).await.unwrap();
+
+    let mut kinds = Vec::new();
+    for _ in 0..3 {
+        let event = events.next().await.unwrap().unwrap();
+        assert_eq!(event.id, id);
+        kinds.push(event.kind);
+    }
+    assert_eq!(
+        kinds,
+        vec![
+            ArchiveEventKind::Created,
+            ArchiveEventKind::Updated,
+            ArchiveEventKind::Deleted
+        ]
+    );
+}
+
+#[tokio::test]
+async fn keeps_stores_and_namespaces_apart() {
+    let store = ArchiveStore::in_memory();
+    let other = ArchiveStore::in_memory();
+    let testnet = store.namespace("testnet").unwrap();
+    store.create(ACCOUNT, doc! { "nonce": 1 }).await.unwrap();
+    testnet.create(ACCOUNT, doc! { "nonce": 2 }).await.unwrap();
+
+    assert_eq!(store.count(ACCOUNT, Filter::All).await.unwrap(), 1);
+    assert_eq!(testnet.count(ACCOUNT, Filter::All).await.unwrap(), 1);
+    assert_eq!(other.count(ACCOUNT, Filter::All).await.unwrap(), 0);
+    assert_eq!(store.namespaces().await.unwrap(), vec!["testnet"]);
+}
+
+#[tokio::test]
+async fn chunks_records_over_the_threshold() {
+    let store = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::InMemory)
+        .datastore("memory".to_string())
+        .chunk_threshold(64)
+        .build()
+        .unwrap();
+    let code = "ab".repeat(100);
+    let id = store
+        .create(ACCOUNT, doc! { "nonce": 1, "code": &code })
+        .await
+        .unwrap()
+        .id()
+        .unwrap()
+        .to_string();
+    store.create(ACCOUNT, doc! { "nonce": 2 }).await.unwrap();
+
+    // The large record is stored as the manifest of its chunks.
+    let stored = store.memory_records(ACCOUNT).unwrap();
+    let manifests: Vec<&Document> = stored
+        .iter()
+        .filter(|doc| doc.contains_key("archive_chunks"))
+        .collect();
+    assert_eq!(manifests.len(), 1);
+    assert!(!manifests[0].contains_key("code"));
+
+    // And reassembled whenever it's read.
+    let found: Document = store.find_by_id(ACCOUNT, &id).await.unwrap().unwrap();
+    assert_eq!(found.get_str("code"), Ok(code.as_str()));
+    let all: Vec<Document> = store.find_all(ACCOUNT).await.unwrap();
+    assert_eq!(all.len(), 2);
+    assert!(all
+        .iter()
+        .any(|rec| rec.get_str("code") == Ok(code.as_str())));
+
+    // Replacing the record chunks its replacement, keeping its id.
+    let longer = "cd".repeat(200);
+    assert!(store
+        .update_by_id(ACCOUNT, &id, doc! { "nonce": 1, "code": &longer })
+        .await
+        .unwrap());
+    let found: Document = store.find_by_id(ACCOUNT, &id).await.unwrap().unwrap();
+    assert_eq!(found.get_str("code"), Ok(longer.as_str()));
+
+    // Neither replacing nor deleting the record leaves its chunks behind.
+    assert!(store.delete_by_id(ACCOUNT, &id).await.unwrap());
+    assert!(store
+        .find_orphaned_chunks(ACCOUNT)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(store.cleanup_orphans(ACCOUNT).await.unwrap(), 0);
+}
+
+#[test]
+fn helpers_need_the_in_memory_backend() {
+    let store = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::Filesystem {
+            root: std::env::temp_dir(),
+        })
+        .datastore("memory".to_string())
+        .build()
+        .unwrap();
+    assert_eq!(
+        store.clear_memory().unwrap_err().kind(),
+        ArchiveErrorKind::Unsupported
+    );
+    assert_eq!(
+        store.memory_records(ACCOUNT).unwrap_err().kind(),
+        ArchiveErrorKind::Unsupported
+    );
+    assert_eq!(
+        store.seed_memory(ACCOUNT, Vec::new()).unwrap_err().kind(),
+        ArchiveErrorKind::Unsupported
+    );
+}
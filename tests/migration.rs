@@ -0,0 +1,138 @@
+use bson::{doc, Document};
+use lasr_archive::{ArchiveBackends, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Accounts as of schema version 3. Version 1 called the balance `bal`, and version 2 had no
+/// currency.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Account {
+    owner_address: String,
+    balance: i64,
+    currency: String,
+}
+
+fn account(owner_address: &str, balance: i64) -> Account {
+    Account {
+        owner_address: owner_address.to_string(),
+        balance,
+        currency: "VRS".to_string(),
+    }
+}
+
+/// An in-memory store at schema version 3 holding an account written with each version, and
+/// one written before versions were recorded.
+async fn store() -> ArchiveStore {
+    let store = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::InMemory)
+        .datastore("migration".to_string())
+        .schema_version(ArchiveRecordType::Account, 3)
+        .build()
+        .unwrap();
+    store.register_migration(ArchiveRecordType::Account, 1, |mut value| {
+        let record = value.as_object_mut().unwrap();
+        let balance = record.remove("bal").unwrap_or(json!(0));
+        record.insert("balance".to_string(), balance);
+        Ok(value)
+    });
+    store.register_migration(ArchiveRecordType::Account, 2, |mut value| {
+        value["currency"] = json!("VRS");
+        Ok(value)
+    });
+
+    store
+        .seed_memory(
+            ArchiveRecordType::Account,
+            vec![
+                doc! { "_id": "a", "owner_address": "a", "bal": 1_i64 },
+                doc! { "_id": "b", "owner_address": "b", "bal": 2_i64, "_schema_version": 1 },
+                doc! { "_id": "c", "owner_address": "c", "balance": 3_i64, "_schema_version": 2 },
+            ],
+        )
+        .unwrap();
+    store
+        .create_with_id(ArchiveRecordType::Account, "d", account("d", 4))
+        .await
+        .unwrap();
+    store
+}
+
+#[tokio::test]
+async fn migrates_older_records_through_the_chain() {
+    let store = store().await;
+    let accounts: Vec<Account> = store.find_all(ArchiveRecordType::Account).await.unwrap();
+    assert_eq!(
+        accounts,
+        vec![
+            account("a", 1),
+            account("b", 2),
+            account("c", 3),
+            account("d", 4)
+        ]
+    );
+
+    let found: Option<Account> = store
+        .find_by_id(ArchiveRecordType::Account, "b")
+        .await
+        .unwrap();
+    assert_eq!(found, Some(account("b", 2)));
+}
+
+#[tokio::test]
+async fn rewrites_only_older_records() {
+    let store = store().await;
+    let rewritten = store
+        .rewrite_migrated(ArchiveRecordType::Account)
+        .await
+        .unwrap();
+    assert_eq!(rewritten, 3);
+
+    let stored = store.memory_records(ArchiveRecordType::Account).unwrap();
+    for doc in &stored {
+        assert_eq!(doc.get_i32("_schema_version").unwrap(), 3, "{}", doc);
+        assert!(!doc.contains_key("bal"), "{}", doc);
+    }
+    let accounts: Vec<Account> = store.find_all(ArchiveRecordType::Account).await.unwrap();
+    assert_eq!(accounts.len(), 4);
+    assert_eq!(
+        store
+            .rewrite_migrated(ArchiveRecordType::Account)
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn fails_on_a_missing_step_or_a_newer_version() {
+    let store = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::InMemory)
+        .datastore("migration".to_string())
+        .schema_version(ArchiveRecordType::Account, 3)
+        .build()
+        .unwrap();
+    // Only the step from version 2 is registered.
+    store.register_migration(ArchiveRecordType::Account, 2, Ok);
+    store
+        .seed_memory(
+            ArchiveRecordType::Account,
+            vec![
+                doc! { "_id": "old", "_schema_version": 1 },
+                doc! { "_id": "new", "_schema_version": 4 },
+            ],
+        )
+        .unwrap();
+
+    for id in ["old", "new"] {
+        let error = store
+            .find_by_id::<Document>(ArchiveRecordType::Account, id)
+            .await
+            .unwrap_err();
+        let message = format!("{:#}", error);
+        let expected = match id {
+            "old" => "No migration registered",
+            _ => "newer version",
+        };
+        assert!(message.contains(expected), "{}", message);
+    }
+}
@@ -0,0 +1,173 @@
+use bson::{doc, Document};
+use lasr_archive::{
+    ArchiveBackends, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder, RetryPolicy,
+    WriteStrategy,
+};
+use std::path::PathBuf;
+
+const ACCOUNT: ArchiveRecordType = ArchiveRecordType::Account;
+
+/// A filesystem archive whose root is a plain file, so every operation on it fails.
+struct Broken {
+    root: PathBuf,
+}
+
+impl Broken {
+    fn new(test: &str) -> Broken {
+        let root = std::env::temp_dir().join(format!(
+            "lasr-archive-mirror-{}-{}",
+            test,
+            std::process::id()
+        ));
+        std::fs::write(&root, b"").unwrap();
+        Broken { root }
+    }
+
+    fn backend(&self) -> ArchiveBackends {
+        ArchiveBackends::Filesystem {
+            root: self.root.clone(),
+        }
+    }
+
+    fn store(&self) -> ArchiveStore {
+        mirrored(self.backend(), &[], WriteStrategy::All)
+    }
+}
+
+impl Drop for Broken {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.root);
+    }
+}
+
+/// A store on `backend` mirrored to each of `mirrors`, succeeding according to `strategy`.
+fn mirrored(
+    backend: ArchiveBackends,
+    mirrors: &[&ArchiveStore],
+    strategy: WriteStrategy,
+) -> ArchiveStore {
+    let mut builder = ArchiveStoreBuilder::default();
+    builder
+        .backend(backend)
+        .datastore("mirror".to_string())
+        .write_strategy(strategy)
+        .retry_policy(RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        });
+    for mirror in mirrors {
+        builder.mirror((*mirror).clone());
+    }
+    builder.build().unwrap()
+}
+
+async fn find(store: &ArchiveStore, id: &str) -> Option<Document> {
+    store.find_by_id(ACCOUNT, id).await.unwrap()
+}
+
+#[tokio::test]
+async fn writes_records_to_every_mirror_under_the_same_id() {
+    let mirrors = [ArchiveStore::in_memory(), ArchiveStore::in_memory()];
+    let store = mirrored(
+        ArchiveBackends::InMemory,
+        &[&mirrors[0], &mirrors[1]],
+        WriteStrategy::All,
+    );
+    let outcome = store.create(ACCOUNT, doc! { "nonce": 0 }).await.unwrap();
+    let id = outcome.id().unwrap();
+    let record = find(&store, id).await.unwrap();
+    for mirror in &mirrors {
+        assert_eq!(find(mirror, id).await, Some(record.clone()));
+    }
+}
+
+#[tokio::test]
+async fn writing_to_all_fails_when_any_mirror_fails() {
+    let broken = Broken::new("all");
+    let (mirror, failing) = (ArchiveStore::in_memory(), broken.store());
+    let store = mirrored(
+        ArchiveBackends::InMemory,
+        &[&mirror, &failing],
+        WriteStrategy::All,
+    );
+    store
+        .create_with_id(ACCOUNT, "a", doc! { "nonce": 0 })
+        .await
+        .unwrap_err();
+    // Writes aren't atomic across stores, so the others still hold the record.
+    assert!(find(&mirror, "a").await.is_some());
+}
+
+#[tokio::test]
+async fn writing_to_a_quorum_needs_a_majority_of_stores() {
+    let (first, second) = (Broken::new("quorum-1"), Broken::new("quorum-2"));
+    let (mirror, failing) = (ArchiveStore::in_memory(), first.store());
+    let store = mirrored(
+        ArchiveBackends::InMemory,
+        &[&mirror, &failing],
+        WriteStrategy::Quorum,
+    );
+    store
+        .create_with_id(ACCOUNT, "a", doc! { "nonce": 0 })
+        .await
+        .unwrap();
+    assert!(find(&mirror, "a").await.is_some());
+
+    let failing_too = second.store();
+    let store = mirrored(
+        ArchiveBackends::InMemory,
+        &[&failing, &failing_too],
+        WriteStrategy::Quorum,
+    );
+    store
+        .create_with_id(ACCOUNT, "b", doc! { "nonce": 1 })
+        .await
+        .unwrap_err();
+}
+
+#[tokio::test]
+async fn writing_to_the_primary_ignores_failed_mirrors() {
+    let broken = Broken::new("primary");
+    let failing = broken.store();
+    let store = mirrored(
+        ArchiveBackends::InMemory,
+        &[&failing],
+        WriteStrategy::PrimaryBestEffort,
+    );
+    store
+        .create_with_id(ACCOUNT, "a", doc! { "nonce": 0 })
+        .await
+        .unwrap();
+    assert!(find(&store, "a").await.is_some());
+
+    // The primary failing still fails the write, however the mirrors fare.
+    let mirror = ArchiveStore::in_memory();
+    let store = mirrored(
+        broken.backend(),
+        &[&mirror],
+        WriteStrategy::PrimaryBestEffort,
+    );
+    store
+        .create_with_id(ACCOUNT, "b", doc! { "nonce": 1 })
+        .await
+        .unwrap_err();
+}
+
+#[tokio::test]
+async fn reads_fall_back_to_the_mirrors() {
+    let broken = Broken::new("read");
+    let (failing, mirror) = (broken.store(), ArchiveStore::in_memory());
+    mirror
+        .create_with_id(ACCOUNT, "a", doc! { "nonce": 0 })
+        .await
+        .unwrap();
+    let store = mirrored(broken.backend(), &[&failing, &mirror], WriteStrategy::All);
+    assert_eq!(find(&store, "a").await.unwrap().get_i32("nonce"), Ok(0));
+
+    // Reads only fail once they have failed on every store.
+    let store = mirrored(broken.backend(), &[&failing], WriteStrategy::All);
+    store
+        .find_by_id::<Document>(ACCOUNT, "a")
+        .await
+        .unwrap_err();
+}
@@ -0,0 +1,246 @@
+//! Tests against a MongoDB deployment, run when `LASR_ARCHIVE_TEST_MONGODB_URI` is set, e.g. to
+//! `mongodb://localhost:27017`, and skipped otherwise. Tests needing transactions run against
+//! the replica set at `LASR_ARCHIVE_TEST_REPLICA_SET_URI` instead. Each test archives into a
+//! database of its own, dropped when the test passes.
+#![cfg(feature = "mongodb")]
+
+use bson::{doc, oid::ObjectId, Document};
+use futures::TryStreamExt;
+use lasr_archive::{
+    ArchiveBackends, ArchiveErrorKind, ArchiveRecordType, ArchiveStoreBuilder, Filter, IndexSpec,
+    MergeMode, PageRequest,
+};
+use mongodb::{Client, Database};
+
+/// A fresh database on the test deployment.
+struct TestDatabase {
+    uri: String,
+    database: Database,
+}
+
+impl TestDatabase {
+    /// Connects to the deployment named by the environment variable, if it's set.
+    async fn connect(var: &str) -> Option<TestDatabase> {
+        let uri = match std::env::var(var) {
+            Ok(uri) => uri,
+            Err(_) => {
+                eprintln!("Skipped, as {} isn't set", var);
+                return None;
+            }
+        };
+        let client = Client::with_uri_str(&uri).await.unwrap();
+        let database = client.database(&format!("lasr_archive_test_{}", ObjectId::new()));
+        Some(TestDatabase { uri, database })
+    }
+
+    /// A builder for stores archiving into the database.
+    fn builder(&self) -> ArchiveStoreBuilder {
+        let mut builder = ArchiveStoreBuilder::default();
+        builder
+            .uri(self.uri.clone())
+            .backend(ArchiveBackends::MongoDB)
+            .datastore(self.database.name().to_string());
+        builder
+    }
+
+    async fn drop(self) {
+        self.database.drop(None).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn finds_and_cleans_up_orphaned_chunks() {
+    let Some(db) = TestDatabase::connect("LASR_ARCHIVE_TEST_MONGODB_URI").await else {
+        return;
+    };
+    let store = db.builder().chunk_threshold(16 * 1024).build().unwrap();
+    let rec_type = ArchiveRecordType::Custom("orphans".to_string());
+    let payload: String = (0..100_000)
+        .map(|i| char::from(b'a' + (i % 26) as u8))
+        .collect();
+
+    let kept = store
+        .create(rec_type.clone(), doc! { "payload": &payload })
+        .await
+        .unwrap();
+    let kept = kept.id().unwrap().to_string();
+    let orphaned = store
+        .create(rec_type.clone(), doc! { "payload": &payload })
+        .await
+        .unwrap();
+    let orphaned = orphaned.id().unwrap().to_string();
+    assert!(store
+        .find_orphaned_chunks(rec_type.clone())
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Lose the manifest, as when a chunked write is interrupted before storing it.
+    let id = ObjectId::parse_str(&orphaned).unwrap();
+    db.database
+        .collection::<Document>("orphans")
+        .delete_one(doc! { "_id": id }, None)
+        .await
+        .unwrap();
+
+    let orphans = store.find_orphaned_chunks(rec_type.clone()).await.unwrap();
+    assert_eq!(orphans, vec![orphaned]);
+    let removed = store.cleanup_orphans(rec_type.clone()).await.unwrap();
+    assert!(removed >= 2, "removed {} chunks", removed);
+    assert!(store
+        .find_orphaned_chunks(rec_type.clone())
+        .await
+        .unwrap()
+        .is_empty());
+
+    let found: Option<Document> = store.find_by_id(rec_type, &kept).await.unwrap();
+    assert_eq!(found.unwrap().get_str("payload").unwrap(), payload);
+    db.drop().await;
+}
+
+#[tokio::test]
+async fn materialises_aggregation_results_into_the_target() {
+    let Some(db) = TestDatabase::connect("LASR_ARCHIVE_TEST_MONGODB_URI").await else {
+        return;
+    };
+    let store = db.builder().build().unwrap();
+    let transfers = ArchiveRecordType::Custom("transfers".to_string());
+    let totals = ArchiveRecordType::Custom("totals".to_string());
+    for (from, amount) in [("a", 1), ("b", 2), ("a", 3)] {
+        store
+            .create(transfers.clone(), doc! { "from": from, "amount": amount })
+            .await
+            .unwrap();
+    }
+    let totals_of = |min: i32| {
+        vec![
+            doc! { "$group": { "_id": "$from", "total": { "$sum": "$amount" } } },
+            doc! { "$match": { "total": { "$gte": min } } },
+        ]
+    };
+    let target = db.database.collection::<Document>("totals");
+    let read = || async {
+        let mut docs: Vec<Document> = target
+            .find(None, None)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        docs.sort_by_key(|doc| doc.get_str("_id").unwrap().to_string());
+        docs
+    };
+
+    let merged = store
+        .merge_into(
+            transfers.clone(),
+            totals_of(0),
+            totals.clone(),
+            MergeMode::Merge,
+        )
+        .await
+        .unwrap();
+    assert_eq!(merged, 2);
+    assert_eq!(
+        read().await,
+        vec![
+            doc! { "_id": "a", "total": 4 },
+            doc! { "_id": "b", "total": 2 }
+        ]
+    );
+
+    // $out replaces the whole target.
+    let replaced = store
+        .merge_into(transfers, totals_of(3), totals, MergeMode::ReplaceAll)
+        .await
+        .unwrap();
+    assert_eq!(replaced, 1);
+    assert_eq!(read().await, vec![doc! { "_id": "a", "total": 4 }]);
+    db.drop().await;
+}
+
+#[tokio::test]
+async fn writes_atomic_batches_all_or_nothing() {
+    // Transactions need a replica set or sharded cluster.
+    let Some(db) = TestDatabase::connect("LASR_ARCHIVE_TEST_REPLICA_SET_URI").await else {
+        return;
+    };
+    let store = db.builder().build().unwrap();
+    let blocks = ArchiveRecordType::Custom("atomic_blocks".to_string());
+    let receipts = ArchiveRecordType::Custom("atomic_receipts".to_string());
+    store
+        .ensure_indexes(receipts.clone(), vec![IndexSpec::new("tx_hash").unique()])
+        .await
+        .unwrap();
+
+    let ids = store
+        .create_atomic(vec![
+            (blocks.clone(), doc! { "height": 1 }),
+            (receipts.clone(), doc! { "tx_hash": "a" }),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(ids.len(), 2);
+    let block: Option<Document> = store.find_by_id(blocks.clone(), &ids[0]).await.unwrap();
+    assert_eq!(block.unwrap().get_i32("height").unwrap(), 1);
+
+    // The duplicate receipt fails the whole batch, including the records before it.
+    let error = store
+        .create_atomic(vec![
+            (blocks.clone(), doc! { "height": 2 }),
+            (receipts.clone(), doc! { "tx_hash": "b" }),
+            (receipts.clone(), doc! { "tx_hash": "a" }),
+        ])
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), ArchiveErrorKind::DuplicateKey, "{:#}", error);
+    assert_eq!(store.count(blocks, Filter::All).await.unwrap(), 1);
+    assert_eq!(store.count(receipts, Filter::All).await.unwrap(), 1);
+    db.drop().await;
+}
+
+#[tokio::test]
+async fn pages_through_mixed_string_and_object_ids() {
+    let Some(db) = TestDatabase::connect("LASR_ARCHIVE_TEST_MONGODB_URI").await else {
+        return;
+    };
+    let store = db.builder().build().unwrap();
+    let rec_type = ArchiveRecordType::Custom("mixed_ids".to_string());
+    let mut expected = Vec::new();
+    for id in ["a", "b", "c"] {
+        store
+            .create_with_id(rec_type.clone(), id, doc! { "id": id })
+            .await
+            .unwrap();
+        expected.push(id.to_string());
+    }
+    for _ in 0..3 {
+        let id = ObjectId::new();
+        store
+            .create_with_id(rec_type.clone(), &id.to_hex(), doc! { "id": id.to_hex() })
+            .await
+            .unwrap();
+        expected.push(id.to_hex());
+    }
+
+    // Strings sort before ObjectIds, and pages run on from one type to the next.
+    let mut request = PageRequest::first(2);
+    let mut seen = Vec::new();
+    loop {
+        let page = store
+            .find_page::<Document>(rec_type.clone(), request)
+            .await
+            .unwrap();
+        seen.extend(
+            page.items
+                .iter()
+                .map(|doc| doc.get_str("id").unwrap().to_string()),
+        );
+        match page.next_token {
+            Some(token) => request = PageRequest::after(2, token),
+            None => break,
+        }
+    }
+    assert_eq!(seen, expected);
+    db.drop().await;
+}
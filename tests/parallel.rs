@@ -0,0 +1,69 @@
+use bson::{doc, oid::ObjectId, Document};
+use futures::TryStreamExt;
+use lasr_archive::{ArchiveRecordType, ArchiveStore};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PARTITIONS: usize = 4;
+
+/// An object id generated `days_ago` days ago.
+fn object_id(days_ago: u32, n: u32) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let secs = now.as_secs() as u32 - days_ago * 86_400;
+    let mut bytes = [0; 12];
+    bytes[..4].copy_from_slice(&secs.to_be_bytes());
+    bytes[8..].copy_from_slice(&n.to_be_bytes());
+    ObjectId::from_bytes(bytes).to_hex()
+}
+
+/// Reads every record in parallel, counting how often each `n` was read.
+async fn read_counts(store: &ArchiveStore) -> HashMap<i32, usize> {
+    let recs: Vec<Document> = store
+        .find_all_parallel(ArchiveRecordType::Account, PARTITIONS)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    let mut counts = HashMap::new();
+    for rec in recs {
+        *counts.entry(rec.get_i32("n").unwrap()).or_default() += 1;
+    }
+    counts
+}
+
+#[tokio::test]
+async fn reads_every_record_exactly_once() {
+    let store = ArchiveStore::in_memory();
+    for n in 0..40 {
+        store
+            .create_with_id(
+                ArchiveRecordType::Account,
+                &object_id(n as u32 % 10, n as u32),
+                doc! { "n": n },
+            )
+            .await
+            .unwrap();
+    }
+    let counts = read_counts(&store).await;
+    assert_eq!(counts.len(), 40);
+    assert!(counts.values().all(|count| *count == 1), "{:?}", counts);
+}
+
+#[tokio::test]
+async fn reads_every_record_exactly_once_when_not_every_id_is_an_object_id() {
+    let store = ArchiveStore::in_memory();
+    for n in 0..40 {
+        let id = match n % 2 {
+            0 => object_id(n as u32 % 10, n as u32),
+            _ => format!("{:x}-tx", n * 7),
+        };
+        store
+            .create_with_id(ArchiveRecordType::Account, &id, doc! { "n": n })
+            .await
+            .unwrap();
+    }
+    let counts = read_counts(&store).await;
+    assert_eq!(counts.len(), 40);
+    assert!(counts.values().all(|count| *count == 1), "{:?}", counts);
+}
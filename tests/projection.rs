@@ -0,0 +1,80 @@
+use bson::doc;
+use lasr_archive::{
+    ArchiveBackends, ArchiveErrorKind, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder,
+    Compression, Filter,
+};
+
+const ACCOUNT: ArchiveRecordType = ArchiveRecordType::Account;
+
+/// Archives an account with a large field reconciliation doesn't need.
+async fn archive_account(store: &ArchiveStore, nonce: i32) {
+    store
+        .create_with_id(
+            ACCOUNT,
+            &format!("account-{}", nonce),
+            doc! {
+                "address": format!("0x{:02x}", nonce),
+                "nonce": nonce,
+                "balance": { "token": "vrrb", "amount": 100 * nonce },
+                "code": "ab".repeat(1_000),
+            },
+        )
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn keeps_only_the_requested_fields() {
+    let store = ArchiveStore::in_memory();
+    for nonce in 1..=3 {
+        archive_account(&store, nonce).await;
+    }
+
+    let recs = store
+        .find_projected(
+            ACCOUNT,
+            Filter::gte("nonce", 2),
+            &["address", "balance.amount", "missing"],
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        recs,
+        [
+            doc! { "_id": "account-2", "address": "0x02", "balance": { "amount": 200 } },
+            doc! { "_id": "account-3", "address": "0x03", "balance": { "amount": 300 } },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn decodes_compressed_records_before_projecting_them() {
+    let store = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::InMemory)
+        .datastore("projection".to_string())
+        .compression(Compression::Zstd)
+        .build()
+        .unwrap();
+    archive_account(&store, 1).await;
+
+    let recs = store
+        .find_projected(ACCOUNT, Filter::All, &["address", "nonce"])
+        .await
+        .unwrap();
+    assert_eq!(
+        recs,
+        [doc! { "_id": "account-1", "address": "0x01", "nonce": 1 }]
+    );
+}
+
+#[tokio::test]
+async fn rejects_invalid_fields() {
+    let store = ArchiveStore::in_memory();
+    for fields in [&[][..], &["balance..amount"], &["$where"]] {
+        let error = store
+            .find_projected(ACCOUNT, Filter::All, fields)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), ArchiveErrorKind::InvalidInput, "{:?}", fields);
+    }
+}
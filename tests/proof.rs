@@ -0,0 +1,108 @@
+use bson::{doc, Document};
+use lasr_archive::{
+    verify_proof, ArchiveErrorKind, ArchiveRecordType, ArchiveStore, InclusionProof, MerkleRoot,
+};
+
+const ACCOUNT: ArchiveRecordType = ArchiveRecordType::Account;
+
+/// A store holding `count` records, with ids in the order they were created.
+async fn store(count: usize) -> ArchiveStore {
+    let store = ArchiveStore::in_memory();
+    for n in 0..count {
+        store
+            .create_with_id(ACCOUNT, &format!("{:02}", n), doc! { "nonce": n as i32 })
+            .await
+            .unwrap();
+    }
+    store
+}
+
+/// The hex string with its first digit changed.
+fn tamper(hex: &str) -> String {
+    let first = match hex.starts_with('0') {
+        true => '1',
+        false => '0',
+    };
+    format!("{}{}", first, &hex[1..])
+}
+
+#[tokio::test]
+async fn verifies_proofs_of_every_record() {
+    // Odd leaf counts promote the last node of some levels without a sibling.
+    for count in 1..=9 {
+        let store = store(count).await;
+        let root = store.merkle_root(ACCOUNT).await.unwrap();
+        assert_eq!(root.leaf_count, count);
+        for n in 0..count {
+            let id = format!("{:02}", n);
+            let proof = store.prove(ACCOUNT, &id).await.unwrap();
+            assert_eq!(proof.index, n);
+            assert!(proof.verify(&root), "{} of {}", n, count);
+            assert!(verify_proof(&root, &proof));
+
+            let record: Document = store.find_by_id(ACCOUNT, &id).await.unwrap().unwrap();
+            assert!(proof.matches(&record));
+            let mut altered = record.clone();
+            altered.insert("nonce", 100);
+            assert!(!proof.matches(&altered));
+        }
+    }
+}
+
+#[tokio::test]
+async fn rejects_tampered_proofs_and_roots() {
+    for count in [2, 5, 8] {
+        let store = store(count).await;
+        let root = store.merkle_root(ACCOUNT).await.unwrap();
+        for n in 0..count {
+            let proof = store.prove(ACCOUNT, &format!("{:02}", n)).await.unwrap();
+            let rejects = |proof: &InclusionProof, root: &MerkleRoot| !proof.verify(root);
+
+            let mut tampered = proof.clone();
+            tampered.leaf = tamper(&proof.leaf);
+            assert!(rejects(&tampered, &root), "leaf {} of {}", n, count);
+
+            for i in 0..proof.siblings.len() {
+                let mut tampered = proof.clone();
+                tampered.siblings[i] = tamper(&proof.siblings[i]);
+                assert!(rejects(&tampered, &root), "sibling {} of {}", i, count);
+            }
+            let mut tampered = proof.clone();
+            tampered.siblings.push(proof.leaf.clone());
+            assert!(rejects(&tampered, &root));
+            let mut tampered = proof.clone();
+            tampered.siblings.pop();
+            assert!(rejects(&tampered, &root));
+
+            let mut tampered = proof.clone();
+            tampered.index = (n + 1) % count;
+            assert!(rejects(&tampered, &root), "index {} of {}", n, count);
+
+            let mut tampered = root.clone();
+            tampered.hash = tamper(&root.hash);
+            assert!(rejects(&proof, &tampered));
+            let mut tampered = root.clone();
+            tampered.leaf_count += 1;
+            assert!(rejects(&proof, &tampered));
+        }
+    }
+}
+
+#[tokio::test]
+async fn proofs_go_stale_as_records_change() {
+    let store = store(3).await;
+    let root = store.merkle_root(ACCOUNT).await.unwrap();
+    let proof = store.prove(ACCOUNT, "01").await.unwrap();
+
+    store
+        .update_by_id(ACCOUNT, "01", doc! { "nonce": 10 })
+        .await
+        .unwrap();
+    let updated = store.prove(ACCOUNT, "01").await.unwrap();
+    assert_ne!(updated.leaf, proof.leaf);
+    assert!(!updated.verify(&root));
+    assert!(updated.verify(&store.merkle_root(ACCOUNT).await.unwrap()));
+
+    let error = store.prove(ACCOUNT, "missing").await.unwrap_err();
+    assert_eq!(error.kind(), ArchiveErrorKind::NotFound);
+}
@@ -0,0 +1,76 @@
+use bson::{doc, Document};
+use lasr_archive::{
+    ArchiveBackends, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder, RateLimits,
+};
+use std::time::{Duration, Instant};
+
+const ACCOUNT: ArchiveRecordType = ArchiveRecordType::Account;
+
+fn store(limits: RateLimits) -> ArchiveStore {
+    ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::InMemory)
+        .datastore("rate_limit".to_string())
+        .rate_limits(limits)
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn limits_operations_per_second() {
+    let store = store(RateLimits {
+        ops_per_sec: Some(20),
+        ..RateLimits::default()
+    });
+    // A second's worth of operations runs at once, and the rest at the rate.
+    let started = Instant::now();
+    for _ in 0..30 {
+        let _: Option<Document> = store.find_by_id(ACCOUNT, "missing").await.unwrap();
+    }
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(400), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+}
+
+#[tokio::test]
+async fn limits_bytes_written_per_second() {
+    let store = store(RateLimits {
+        bytes_per_sec: Some(2_000),
+        ..RateLimits::default()
+    });
+    let padding = "x".repeat(1_000);
+    let started = Instant::now();
+    for nonce in 0..4 {
+        store
+            .create(ACCOUNT, doc! { "nonce": nonce, "padding": &padding })
+            .await
+            .unwrap();
+    }
+    // The third write overdraws the bucket, so the fourth waits for it to refill.
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(400), "{:?}", elapsed);
+}
+
+#[test]
+fn rejects_limits_that_cant_be_enforced() {
+    for limits in [
+        RateLimits {
+            max_in_flight: Some(0),
+            ..RateLimits::default()
+        },
+        RateLimits {
+            ops_per_sec: Some(0),
+            ..RateLimits::default()
+        },
+        RateLimits {
+            bytes_per_sec: Some(0),
+            ..RateLimits::default()
+        },
+    ] {
+        let result = ArchiveStoreBuilder::default()
+            .backend(ArchiveBackends::InMemory)
+            .datastore("rate_limit".to_string())
+            .rate_limits(limits.clone())
+            .build();
+        assert!(result.is_err(), "{:?}", limits);
+    }
+}
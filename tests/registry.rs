@@ -0,0 +1,36 @@
+#![cfg(feature = "mongodb")]
+
+use bson::Document;
+use lasr_archive::{ArchiveBackends, ArchiveRecordType, ArchiveRegistry, ArchiveStoreBuilder};
+
+// Nothing needs to listen here: shutting a client down fails every later operation through it
+// without selecting a server, so a store sharing the client fails at once.
+const URI: &str = "mongodb://127.0.0.1:1/?serverSelectionTimeoutMS=100";
+
+#[tokio::test]
+async fn stores_vended_by_a_registry_share_its_client() {
+    let registry = ArchiveRegistry::connect(URI.to_string()).await.unwrap();
+    let accounts = registry.store("accounts").unwrap();
+    let blocks = registry.store("blocks").unwrap();
+    let separate = ArchiveStoreBuilder::default()
+        .uri(URI.to_string())
+        .backend(ArchiveBackends::MongoDB)
+        .datastore("accounts".to_string())
+        .build()
+        .unwrap();
+
+    registry.client().clone().shutdown_immediate().await;
+
+    for store in [&accounts, &blocks] {
+        let error = store
+            .find_by_id::<Document>(ArchiveRecordType::Account, "1")
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("shut down"), "{:#}", error);
+    }
+    let error = separate
+        .find_by_id::<Document>(ArchiveRecordType::Account, "1")
+        .await
+        .unwrap_err();
+    assert!(!format!("{:#}", error).contains("shut down"), "{:#}", error);
+}
@@ -0,0 +1,65 @@
+use bson::{doc, DateTime};
+use lasr_archive::{ArchiveRecordType, ArchiveStore};
+
+const ACCOUNT: ArchiveRecordType = ArchiveRecordType::Account;
+
+#[tokio::test]
+async fn totals_the_stats_of_the_store_and_its_namespaces() {
+    let store = ArchiveStore::in_memory();
+    let testnet = store.namespace("testnet").unwrap();
+    for archived_at in [2_000_i64, 3_000] {
+        store
+            .create(ACCOUNT, doc! { "_archived_at": archived_at })
+            .await
+            .unwrap();
+    }
+    testnet
+        .create(ACCOUNT, doc! { "_archived_at": 1_000_i64 })
+        .await
+        .unwrap();
+
+    let stats = store.archive_stats(ACCOUNT).await.unwrap();
+    assert_eq!(stats.document_count, 3);
+    assert!(stats.storage_bytes > 0);
+    assert_eq!(stats.avg_doc_bytes(), stats.storage_bytes / 3);
+    assert_eq!(stats.oldest_archived_at, Some(DateTime::from_millis(1_000)));
+    assert_eq!(stats.newest_archived_at, Some(DateTime::from_millis(3_000)));
+    let namespaces: Vec<&String> = stats.namespaces.keys().collect();
+    assert_eq!(namespaces, ["testnet"]);
+    assert_eq!(stats.namespaces["testnet"].document_count, 1);
+
+    // A namespace's handle only covers the namespace.
+    let stats = testnet.archive_stats(ACCOUNT).await.unwrap();
+    assert_eq!(stats.document_count, 1);
+    assert_eq!(stats.newest_archived_at, Some(DateTime::from_millis(1_000)));
+    assert!(stats.namespaces.is_empty());
+}
+
+#[tokio::test]
+async fn reports_every_record_type() {
+    let store = ArchiveStore::in_memory();
+    store.create(ACCOUNT, doc! { "nonce": 1 }).await.unwrap();
+    store
+        .create(ArchiveRecordType::Block, doc! { "block_height": 1 })
+        .await
+        .unwrap();
+
+    let report = store.stats_all().await.unwrap();
+    let counts: Vec<(ArchiveRecordType, u64)> = report
+        .record_types
+        .iter()
+        .map(|(rec_type, stats)| (rec_type.clone(), stats.document_count))
+        .collect();
+    assert_eq!(
+        counts,
+        [
+            (ACCOUNT, 1),
+            (ArchiveRecordType::TransactionBatch, 0),
+            (ArchiveRecordType::Block, 1),
+            (ArchiveRecordType::Receipt, 0),
+        ]
+    );
+    assert_eq!(report.total.document_count, 2);
+    // Records without a recorded archive time are stamped when created.
+    assert!(report.total.oldest_archived_at.is_some());
+}
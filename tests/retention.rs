@@ -0,0 +1,133 @@
+use bson::{doc, Document};
+use lasr_archive::{
+    ArchiveBackends, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder, ExpiryAction,
+    RetentionPolicy, TieringPolicy,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const ACCOUNT: ArchiveRecordType = ArchiveRecordType::Account;
+const DAY: Duration = Duration::from_secs(86_400);
+
+/// When it was `days` ago, in milliseconds since the epoch.
+fn days_ago(days: u32) -> i64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    (now - days * DAY).as_millis() as i64
+}
+
+fn store(policy: RetentionPolicy) -> ArchiveStore {
+    ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::InMemory)
+        .datastore("retention".to_string())
+        .retention(ACCOUNT, policy)
+        .build()
+        .unwrap()
+}
+
+/// Archives a record per age in days under the ids `0`, `1`, ...
+async fn archive(store: &ArchiveStore, ages: &[u32]) {
+    for (n, days) in ages.iter().enumerate() {
+        store
+            .create_with_id(
+                ACCOUNT,
+                &n.to_string(),
+                doc! { "nonce": n as i32, "_archived_at": days_ago(*days) },
+            )
+            .await
+            .unwrap();
+    }
+}
+
+/// The ids of the records the store holds, including those it can't read.
+fn stored_ids(store: &ArchiveStore) -> Vec<String> {
+    store
+        .memory_records(ACCOUNT)
+        .unwrap()
+        .iter()
+        .map(|rec| rec.get_str("_id").unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn deletes_records_past_their_max_age() {
+    let store = store(RetentionPolicy::max_age(7 * DAY));
+    archive(&store, &[30, 10, 1]).await;
+    // Records whose archive time is unknown are kept.
+    store
+        .seed_memory(ACCOUNT, vec![doc! { "_id": "unknown", "nonce": 3 }])
+        .unwrap();
+
+    assert_eq!(store.prune().await.unwrap(), 2);
+    assert_eq!(stored_ids(&store), ["2", "unknown"]);
+    assert_eq!(store.prune().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn moves_records_past_their_max_age_to_the_cold_tier() {
+    let cold = ArchiveStore::in_memory();
+    let store = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::InMemory)
+        .datastore("retention".to_string())
+        .tiering(TieringPolicy::new(cold.clone(), 365 * DAY))
+        .retention(
+            ACCOUNT,
+            RetentionPolicy {
+                action: ExpiryAction::MoveToColdTier,
+                ..RetentionPolicy::max_age(7 * DAY)
+            },
+        )
+        .build()
+        .unwrap();
+    archive(&store, &[30, 1]).await;
+
+    assert_eq!(store.prune().await.unwrap(), 1);
+    assert_eq!(stored_ids(&store), ["1"]);
+    assert_eq!(stored_ids(&cold), ["0"]);
+    // Moved records are still read through the store.
+    let moved: Document = store.find_by_id(ACCOUNT, "0").await.unwrap().unwrap();
+    assert_eq!(moved.get_i32("nonce"), Ok(0));
+}
+
+#[tokio::test]
+async fn keeps_the_most_recently_archived_records() {
+    let store = store(RetentionPolicy::max_count(2));
+    archive(&store, &[3, 1, 4, 2]).await;
+
+    assert_eq!(store.prune().await.unwrap(), 2);
+    assert_eq!(stored_ids(&store), ["1", "3"]);
+}
+
+/// Archives a month old live record, records tombstoned a month and a day ago, and a record
+/// pending purge.
+async fn archive_removed(store: &ArchiveStore) {
+    let recs = [
+        ("live", doc! { "_archived_at": days_ago(30) }),
+        (
+            "old-tombstone",
+            doc! { "_lifecycle": "tombstoned", "_lifecycle_changed_at": days_ago(30) },
+        ),
+        (
+            "new-tombstone",
+            doc! { "_lifecycle": "tombstoned", "_lifecycle_changed_at": days_ago(1) },
+        ),
+        ("pending", doc! { "_lifecycle": "pending_purge" }),
+    ];
+    for (id, rec) in recs {
+        store.create_with_id(ACCOUNT, id, rec).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn purges_tombstones_once_their_window_has_passed() {
+    let policy = RetentionPolicy::purge_tombstones_after(7 * DAY);
+
+    let pruned = store(policy.clone());
+    archive_removed(&pruned).await;
+    assert_eq!(pruned.prune().await.unwrap(), 2);
+    assert_eq!(stored_ids(&pruned), ["live", "new-tombstone"]);
+
+    // Compacting purges the same records.
+    let compacted = store(policy);
+    archive_removed(&compacted).await;
+    assert_eq!(compacted.compact(ACCOUNT).await.unwrap(), 2);
+    assert_eq!(stored_ids(&compacted), ["live", "new-tombstone"]);
+}
@@ -0,0 +1,171 @@
+use async_trait::async_trait;
+use bson::Document;
+use lasr_archive::{
+    ArchiveBackend, ArchiveBackends, ArchiveError, ArchiveErrorKind, ArchiveRecordType,
+    ArchiveStore, ArchiveStoreBuilder, Filter, RetryPolicy,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::borrow::Borrow;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const ACCOUNT: ArchiveRecordType = ArchiveRecordType::Account;
+
+/// A backend failing every operation with an error of the given kind, counting the attempts.
+struct Failing {
+    kind: ArchiveErrorKind,
+    attempts: Arc<AtomicU32>,
+}
+
+impl Failing {
+    fn fail<T>(&self) -> Result<T, ArchiveError> {
+        self.attempts.fetch_add(1, Ordering::SeqCst);
+        let e = anyhow::anyhow!("Failing as asked");
+        Err(match self.kind {
+            ArchiveErrorKind::ConnectionFailed => ArchiveError::ConnectionFailed(e),
+            ArchiveErrorKind::BackendUnavailable => ArchiveError::BackendUnavailable(e),
+            ArchiveErrorKind::InvalidInput => ArchiveError::InvalidInput(e),
+            ArchiveErrorKind::DuplicateKey => ArchiveError::DuplicateKey(e),
+            _ => ArchiveError::Other(e),
+        })
+    }
+}
+
+#[async_trait]
+impl ArchiveBackend for Failing {
+    async fn create<T: Serialize>(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _rec: T,
+    ) -> Result<String, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        self.fail()
+    }
+
+    async fn find_all<T: DeserializeOwned>(
+        &self,
+        _rec_type: ArchiveRecordType,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        self.fail()
+    }
+
+    async fn query<T: DeserializeOwned>(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _filter: &Filter,
+    ) -> Result<Vec<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        self.fail()
+    }
+
+    async fn find_by_id<T: DeserializeOwned>(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _id: &str,
+    ) -> Result<Option<T>, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync + std::clone::Clone + Unpin,
+    {
+        self.fail()
+    }
+
+    async fn delete_by_id(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _id: &str,
+    ) -> Result<bool, ArchiveError> {
+        self.fail()
+    }
+
+    async fn update_by_id<T: Serialize>(
+        &self,
+        _rec_type: ArchiveRecordType,
+        _id: &str,
+        _rec: T,
+    ) -> Result<bool, ArchiveError>
+    where
+        T: Borrow<T> + std::marker::Send + std::marker::Sync,
+    {
+        self.fail()
+    }
+}
+
+/// A store on a backend registered as `name` that fails with errors of the given kind, retrying
+/// up to `max_attempts` times, and the count of attempts made.
+fn store(name: &str, kind: ArchiveErrorKind, max_attempts: u32) -> (ArchiveStore, Arc<AtomicU32>) {
+    let attempts = Arc::new(AtomicU32::new(0));
+    let counted = attempts.clone();
+    ArchiveStore::register_backend(name, move |_uri, _datastore| {
+        Ok(Failing {
+            kind,
+            attempts: counted.clone(),
+        })
+    })
+    .unwrap();
+    let store = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::Custom {
+            name: name.to_string(),
+        })
+        .datastore("retry".to_string())
+        .retry_policy(RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+            jitter: false,
+            ..RetryPolicy::default()
+        })
+        .build()
+        .unwrap();
+    (store, attempts)
+}
+
+#[tokio::test]
+async fn retries_transient_errors_until_the_attempts_run_out() {
+    for (name, kind) in [
+        ("retry-connection", ArchiveErrorKind::ConnectionFailed),
+        ("retry-unavailable", ArchiveErrorKind::BackendUnavailable),
+    ] {
+        let (store, attempts) = store(name, kind, 4);
+        let error = store
+            .find_by_id::<Document>(ACCOUNT, "a")
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), kind);
+        assert_eq!(attempts.load(Ordering::SeqCst), 4, "{:?}", kind);
+    }
+}
+
+#[tokio::test]
+async fn doesnt_retry_other_errors() {
+    for (name, kind) in [
+        ("retry-invalid", ArchiveErrorKind::InvalidInput),
+        ("retry-duplicate", ArchiveErrorKind::DuplicateKey),
+        ("retry-other", ArchiveErrorKind::Other),
+    ] {
+        let (store, attempts) = store(name, kind, 4);
+        let error = store
+            .find_by_id::<Document>(ACCOUNT, "a")
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), kind);
+        assert_eq!(attempts.load(Ordering::SeqCst), 1, "{:?}", kind);
+    }
+}
+
+#[tokio::test]
+async fn a_single_attempt_disables_retries() {
+    let (store, attempts) = store("retry-once", ArchiveErrorKind::BackendUnavailable, 1);
+    store
+        .find_by_id::<Document>(ACCOUNT, "a")
+        .await
+        .unwrap_err();
+    assert_eq!(attempts.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,62 @@
+use bson::{doc, Document};
+use lasr_archive::{ArchiveErrorKind, ArchiveRecordType, ArchiveStore};
+
+const RECORDS: i32 = 10_000;
+
+async fn store() -> ArchiveStore {
+    let store = ArchiveStore::in_memory();
+    let records = (0..RECORDS).map(|i| doc! { "nonce": i }).collect();
+    store
+        .seed_memory(ArchiveRecordType::Account, records)
+        .unwrap();
+    store
+}
+
+#[tokio::test]
+async fn samples_roughly_the_requested_fraction() {
+    let store = store().await;
+    for rate in [0.05, 0.2, 0.5] {
+        let sample: Vec<Document> = store
+            .find_sampled(ArchiveRecordType::Account, rate)
+            .await
+            .unwrap();
+        // Within 6 standard deviations of the expected sample size, so it never fails by chance.
+        let expected = rate * RECORDS as f64;
+        let tolerance = 6.0 * (expected * (1.0 - rate)).sqrt();
+        let size = sample.len() as f64;
+        assert!(
+            (size - expected).abs() <= tolerance,
+            "sampled {} of {} records at rate {}",
+            size,
+            RECORDS,
+            rate
+        );
+    }
+}
+
+#[tokio::test]
+async fn samples_nothing_or_everything_at_the_bounds() {
+    let store = store().await;
+    let none: Vec<Document> = store
+        .find_sampled(ArchiveRecordType::Account, 0.0)
+        .await
+        .unwrap();
+    assert!(none.is_empty());
+    let all: Vec<Document> = store
+        .find_sampled(ArchiveRecordType::Account, 1.0)
+        .await
+        .unwrap();
+    assert_eq!(all.len(), RECORDS as usize);
+}
+
+#[tokio::test]
+async fn rejects_rates_outside_zero_to_one() {
+    let store = store().await;
+    for rate in [-0.1, 1.5, f64::NAN] {
+        let error = store
+            .find_sampled::<Document>(ArchiveRecordType::Account, rate)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), ArchiveErrorKind::InvalidInput, "{}", rate);
+    }
+}
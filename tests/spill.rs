@@ -0,0 +1,113 @@
+use bson::{doc, Document};
+use lasr_archive::{
+    ArchiveBackends, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder, CreateOutcome, Filter,
+};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A scratch directory holding a filesystem archive whose root starts out as a plain file, so
+/// writes fail until [Scratch::recover] replaces it with a directory, and a spill directory.
+struct Scratch {
+    dir: PathBuf,
+}
+
+impl Scratch {
+    fn new(name: &str) -> Scratch {
+        let dir =
+            std::env::temp_dir().join(format!("lasr-archive-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("archive"), b"").unwrap();
+        Scratch { dir }
+    }
+
+    fn store(&self, drain_interval: Option<Duration>) -> ArchiveStore {
+        let mut builder = ArchiveStoreBuilder::default();
+        builder
+            .backend(ArchiveBackends::Filesystem {
+                root: self.dir.join("archive"),
+            })
+            .datastore("spill".to_string())
+            .spill_dir(self.dir.join("spill"));
+        if let Some(interval) = drain_interval {
+            builder.spill_drain_interval(interval);
+        }
+        builder.build().unwrap()
+    }
+
+    fn recover(&self) {
+        std::fs::remove_file(self.dir.join("archive")).unwrap();
+        std::fs::create_dir(self.dir.join("archive")).unwrap();
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Creates records while the backend is failing, checking every one is spilled.
+async fn spill(store: &ArchiveStore) {
+    for nonce in 0..3 {
+        let outcome = store
+            .create(ArchiveRecordType::Account, doc! { "nonce": nonce })
+            .await
+            .unwrap();
+        assert!(matches!(outcome, CreateOutcome::Spilled(_)), "{}", outcome);
+    }
+}
+
+async fn nonces(store: &ArchiveStore) -> Vec<i32> {
+    let mut nonces: Vec<i32> = store
+        .find_all::<Document>(ArchiveRecordType::Account)
+        .await
+        .unwrap()
+        .iter()
+        .map(|doc| doc.get_i32("nonce").unwrap())
+        .collect();
+    nonces.sort();
+    nonces
+}
+
+#[tokio::test]
+async fn drains_spilled_writes_once_the_backend_recovers() {
+    let scratch = Scratch::new("spill");
+    let store = scratch.store(None);
+    spill(&store).await;
+
+    // Records are kept while the backend is still failing.
+    assert!(store.drain_spill().await.is_err());
+    scratch.recover();
+    assert_eq!(store.drain_spill().await.unwrap(), 3);
+    assert_eq!(nonces(&store).await, vec![0, 1, 2]);
+    assert_eq!(store.drain_spill().await.unwrap(), 0);
+
+    // Later writes go straight to the backend.
+    let outcome = store
+        .create(ArchiveRecordType::Account, doc! { "nonce": 3 })
+        .await
+        .unwrap();
+    assert!(matches!(outcome, CreateOutcome::Created(_)), "{}", outcome);
+}
+
+#[tokio::test]
+async fn drains_spilled_writes_in_the_background() {
+    let scratch = Scratch::new("spill-background");
+    let store = scratch.store(Some(Duration::from_millis(10)));
+    spill(&store).await;
+    scratch.recover();
+
+    for _ in 0..100 {
+        let count = store
+            .count(ArchiveRecordType::Account, Filter::All)
+            .await
+            .unwrap();
+        if count == 3 {
+            assert_eq!(nonces(&store).await, vec![0, 1, 2]);
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("Spilled records weren't drained");
+}
@@ -0,0 +1,142 @@
+use bson::{doc, oid::ObjectId, Document};
+use lasr_archive::{
+    ArchiveBackends, ArchiveErrorKind, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder,
+    Filter, TieringPolicy,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const ACCOUNT: ArchiveRecordType = ArchiveRecordType::Account;
+const DAY: Duration = Duration::from_secs(86_400);
+
+/// An object id generated `days_ago`, distinguished by `n`.
+fn object_id(days_ago: u32, n: u32) -> ObjectId {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let secs = now.as_secs() as u32 - days_ago * 86_400;
+    let mut bytes = [0; 12];
+    bytes[..4].copy_from_slice(&secs.to_be_bytes());
+    bytes[8..].copy_from_slice(&n.to_be_bytes());
+    ObjectId::from_bytes(bytes)
+}
+
+/// A store keeping records in its hot tier for a week, and its cold tier.
+fn tiered() -> (ArchiveStore, ArchiveStore) {
+    let cold = ArchiveStore::in_memory();
+    let hot = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::InMemory)
+        .datastore("tiering".to_string())
+        .tiering(TieringPolicy::new(cold.clone(), 7 * DAY))
+        .build()
+        .unwrap();
+    (hot, cold)
+}
+
+/// Archives a record per age in days, returning their ids.
+async fn archive(store: &ArchiveStore, ages: &[u32]) -> Vec<String> {
+    let mut ids = Vec::new();
+    for (n, days_ago) in ages.iter().enumerate() {
+        let id = object_id(*days_ago, n as u32);
+        store
+            .create(ACCOUNT, doc! { "_id": id, "nonce": n as i32 })
+            .await
+            .unwrap();
+        ids.push(id.to_hex());
+    }
+    ids
+}
+
+async fn find(store: &ArchiveStore, id: &str) -> Option<Document> {
+    store.find_by_id(ACCOUNT, id).await.unwrap()
+}
+
+#[tokio::test]
+async fn moves_aged_records_to_the_cold_tier() {
+    let (hot, cold) = tiered();
+    let ids = archive(&hot, &[30, 10, 1, 0]).await;
+    let archived_at = hot
+        .find_envelope_by_id::<Document>(ACCOUNT, &ids[0])
+        .await
+        .unwrap()
+        .unwrap()
+        .archived_at;
+
+    assert_eq!(hot.migrate_to_cold_tier().await.unwrap(), 2);
+    for id in &ids[..2] {
+        assert!(find(&cold, id).await.is_some(), "{}", id);
+    }
+    for id in &ids[2..] {
+        assert!(find(&cold, id).await.is_none(), "{}", id);
+    }
+    // Records keep when they were archived rather than when they were moved.
+    let moved = cold
+        .find_envelope_by_id::<Document>(ACCOUNT, &ids[0])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(moved.archived_at, archived_at);
+
+    // Moved records are only moved once, and records with other ids are never moved.
+    hot.create_with_id(ACCOUNT, "custom", doc! { "nonce": 4 })
+        .await
+        .unwrap();
+    assert_eq!(hot.migrate_to_cold_tier().await.unwrap(), 0);
+    assert!(find(&cold, "custom").await.is_none());
+}
+
+#[tokio::test]
+async fn reads_records_from_both_tiers() {
+    let (hot, cold) = tiered();
+    let ids = archive(&hot, &[30, 10, 1, 0]).await;
+    hot.migrate_to_cold_tier().await.unwrap();
+
+    for (nonce, id) in ids.iter().enumerate() {
+        let rec = find(&hot, id).await.unwrap();
+        assert_eq!(rec.get_i32("nonce"), Ok(nonce as i32), "{}", id);
+    }
+    assert_eq!(hot.count(ACCOUNT, Filter::All).await.unwrap(), 4);
+    let cold_recs: Vec<Document> = hot.query(ACCOUNT, Filter::lt("nonce", 2)).await.unwrap();
+    assert_eq!(cold_recs.len(), 2);
+
+    // Updates and deletes reach records in the cold tier.
+    assert!(hot
+        .update_by_id(ACCOUNT, &ids[0], doc! { "nonce": 10 })
+        .await
+        .unwrap());
+    assert_eq!(find(&cold, &ids[0]).await.unwrap().get_i32("nonce"), Ok(10));
+    assert!(hot.delete_by_id(ACCOUNT, &ids[1]).await.unwrap());
+    assert!(find(&hot, &ids[1]).await.is_none());
+    assert!(find(&cold, &ids[1]).await.is_none());
+}
+
+#[tokio::test]
+async fn finds_records_moved_to_the_cold_tier_when_archived_again() {
+    let (hot, cold) = tiered();
+    let id = object_id(30, 0).to_hex();
+    hot.create_with_id(ACCOUNT, &id, doc! { "nonce": 1 })
+        .await
+        .unwrap();
+    assert_eq!(hot.migrate_to_cold_tier().await.unwrap(), 1);
+
+    // Archiving the record again finds it in the cold tier rather than storing a second copy.
+    assert_eq!(
+        hot.create_with_id(ACCOUNT, &id, doc! { "nonce": 1 })
+            .await
+            .unwrap(),
+        id
+    );
+    assert!(find(&cold, &id).await.is_some());
+    let recs: Vec<Document> = hot.find_all(ACCOUNT).await.unwrap();
+    assert_eq!(recs.len(), 1);
+
+    let strict = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::InMemory)
+        .datastore("tiering-strict".to_string())
+        .tiering(TieringPolicy::new(cold.clone(), 7 * DAY))
+        .ignore_duplicate_ids(false)
+        .build()
+        .unwrap();
+    let err = strict
+        .create_with_id(ACCOUNT, &id, doc! { "nonce": 1 })
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), ArchiveErrorKind::DuplicateKey);
+}
@@ -0,0 +1,152 @@
+#![cfg(feature = "tracing")]
+
+use bson::{doc, Document};
+use lasr_archive::{
+    ArchiveBackends, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder, ExpiryAction,
+    RetentionPolicy, TieringPolicy,
+};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{Layer, Registry};
+
+/// The fields recorded on a span.
+#[derive(Debug, Clone, Default)]
+struct Fields(HashMap<&'static str, String>);
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.insert(field.name(), format!("{:?}", value));
+    }
+}
+
+/// A layer recording the fields of every archive operation span, in the order they started.
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<(Id, Fields)>>>);
+
+impl Recorder {
+    /// The fields of the spans of the given archive operation.
+    fn operations(&self, op: &str) -> Vec<HashMap<&'static str, String>> {
+        let spans = self.0.lock().unwrap();
+        spans
+            .iter()
+            .filter(|(_, fields)| fields.0.get("op").map(String::as_str) == Some(op))
+            .map(|(_, fields)| fields.0.clone())
+            .collect()
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Recorder {
+    fn on_new_span(&self, attributes: &Attributes, id: &Id, _ctx: Context<S>) {
+        if attributes.metadata().name() == "archive_operation" {
+            let mut fields = Fields::default();
+            attributes.record(&mut fields);
+            self.0.lock().unwrap().push((id.clone(), fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record, _ctx: Context<S>) {
+        let mut spans = self.0.lock().unwrap();
+        if let Some((_, fields)) = spans.iter_mut().rev().find(|(span, _)| span == id) {
+            values.record(fields);
+        }
+    }
+}
+
+/// Records the spans of operations run on the current thread until the guard is dropped.
+fn record() -> (Recorder, tracing::subscriber::DefaultGuard) {
+    let recorder = Recorder::default();
+    let guard = tracing::subscriber::set_default(Registry::default().with(recorder.clone()));
+    (recorder, guard)
+}
+
+#[tokio::test]
+async fn operations_run_in_spans() {
+    let (recorder, _guard) = record();
+    let store = ArchiveStore::in_memory().with_labels(&[("tenant", "acme")]);
+
+    let id = store
+        .create(ArchiveRecordType::Account, doc! { "nonce": 1 })
+        .await
+        .unwrap()
+        .id()
+        .unwrap()
+        .to_string();
+    let created = recorder.operations("create");
+    assert_eq!(created.len(), 1);
+    let fields = &created[0];
+    assert_eq!(fields["rec_type"], "Account");
+    assert_eq!(fields["backend"], "In-memory");
+    assert_eq!(fields["datastore"], "memory");
+    assert_eq!(fields["labels"], "tenant=acme");
+    assert_eq!(fields["outcome"], "success");
+    assert_eq!(fields["ids"], id);
+    assert!(fields["bytes"].parse::<u64>().unwrap() > 0);
+    assert!(fields.contains_key("duration_ms"));
+    assert!(!fields.contains_key("error"));
+}
+
+#[tokio::test]
+async fn failed_operations_record_their_error() {
+    let (recorder, _guard) = record();
+    let store = ArchiveStore::in_memory();
+
+    store
+        .find_sampled::<Document>(ArchiveRecordType::Account, 2.0)
+        .await
+        .unwrap_err();
+    let sampled = recorder.operations("find_sampled");
+    assert_eq!(sampled.len(), 1);
+    let fields = &sampled[0];
+    assert_eq!(fields["outcome"], "failure");
+    assert_eq!(fields["bytes"], "0");
+    assert!(
+        fields["error"].contains("Sample rate must be between 0.0 and 1.0"),
+        "{}",
+        fields["error"]
+    );
+}
+
+#[tokio::test]
+async fn pruning_runs_nested_operations_in_spans() {
+    let (recorder, _guard) = record();
+    let day = Duration::from_secs(86_400);
+    let cold = ArchiveStore::in_memory();
+    let store = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::InMemory)
+        .datastore("tracing".to_string())
+        .tiering(TieringPolicy::new(cold.clone(), 365 * day))
+        .retention(
+            ArchiveRecordType::Account,
+            RetentionPolicy {
+                action: ExpiryAction::MoveToColdTier,
+                ..RetentionPolicy::max_age(7 * day)
+            },
+        )
+        .build()
+        .unwrap();
+    // Archived at the epoch, so long past its max age.
+    store
+        .create_with_id(
+            ArchiveRecordType::Account,
+            "old",
+            doc! { "nonce": 1, "_archived_at": 0_i64 },
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(store.prune().await.unwrap(), 1);
+    let deleted = recorder.operations("delete_by_id");
+    assert_eq!(deleted.len(), 1);
+    assert_eq!(deleted[0]["outcome"], "success");
+}
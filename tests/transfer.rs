@@ -0,0 +1,161 @@
+use bson::{doc, Document};
+use lasr_archive::{
+    ArchiveBackends, ArchiveEnvelope, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder,
+    ExportFormat, Filter, LifecycleState,
+};
+use std::time::Duration;
+
+const ACCOUNT: ArchiveRecordType = ArchiveRecordType::Account;
+
+fn store(node_id: &str) -> ArchiveStore {
+    ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::InMemory)
+        .datastore("transfer".to_string())
+        .node_id(node_id.to_string())
+        .build()
+        .unwrap()
+}
+
+/// A store archived by one node, holding records with generated and caller-supplied ids, some
+/// of them tagged, returning it and the records' ids.
+async fn source() -> (ArchiveStore, Vec<String>) {
+    let store = store("source");
+    let mut ids = Vec::new();
+    for nonce in 0..3 {
+        let outcome = store
+            .create(
+                ACCOUNT,
+                doc! { "nonce": nonce, "owner": { "address": "a" } },
+            )
+            .await
+            .unwrap();
+        ids.push(outcome.id().unwrap().to_string());
+    }
+    let tagged = store.with_tags(&["genesis"]);
+    ids.push(
+        tagged
+            .create_with_id(ACCOUNT, "custom", doc! { "nonce": 3 })
+            .await
+            .unwrap(),
+    );
+    (store, ids)
+}
+
+async fn envelope(store: &ArchiveStore, id: &str) -> ArchiveEnvelope<Document> {
+    store
+        .find_envelope_by_id(ACCOUNT, id)
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+/// Exports every record of `from` and imports them into `to` in the given format.
+async fn transfer(from: &ArchiveStore, to: &ArchiveStore, format: ExportFormat) {
+    let mut dump = Vec::new();
+    let exported = from
+        .export(ACCOUNT, Filter::All, format, &mut dump)
+        .await
+        .unwrap();
+    let progress = to.import(ACCOUNT, format, dump.as_slice()).await.unwrap();
+    assert_eq!(progress.read, exported);
+    assert_eq!(progress.imported, exported);
+}
+
+#[tokio::test]
+async fn exported_records_are_imported_with_their_ids_and_provenance() {
+    let (from, ids) = source().await;
+    let to = store("destination");
+    transfer(&from, &to, ExportFormat::JsonLines).await;
+
+    for id in &ids {
+        let original = envelope(&from, id).await;
+        let imported = envelope(&to, id).await;
+        assert_eq!(imported.record, original.record, "{}", id);
+        assert_eq!(imported.archived_at, original.archived_at, "{}", id);
+        assert_eq!(imported.node_id.as_deref(), Some("source"), "{}", id);
+        assert_eq!(imported.tags, original.tags, "{}", id);
+    }
+    assert_eq!(envelope(&to, "custom").await.tags, vec!["genesis"]);
+}
+
+#[tokio::test]
+async fn imports_tabular_exports_with_their_ids_and_provenance() {
+    let from = store("source");
+    let mut ids = Vec::new();
+    for nonce in 0..3 {
+        let outcome = from
+            .create(
+                ACCOUNT,
+                doc! { "nonce": nonce, "owner": { "address": "a" } },
+            )
+            .await
+            .unwrap();
+        ids.push(outcome.id().unwrap().to_string());
+    }
+    let to = store("destination");
+    transfer(&from, &to, ExportFormat::Csv).await;
+
+    for id in &ids {
+        let original = envelope(&from, id).await;
+        let imported = envelope(&to, id).await;
+        assert_eq!(imported.record, original.record, "{}", id);
+        assert_eq!(imported.archived_at, original.archived_at, "{}", id);
+        assert_eq!(imported.node_id.as_deref(), Some("source"), "{}", id);
+    }
+}
+
+#[tokio::test]
+async fn reimporting_skips_the_records_already_imported() {
+    let (from, ids) = source().await;
+    let to = store("destination");
+    transfer(&from, &to, ExportFormat::JsonLines).await;
+
+    let mut dump = Vec::new();
+    from.export(ACCOUNT, Filter::All, ExportFormat::JsonLines, &mut dump)
+        .await
+        .unwrap();
+    let progress = to
+        .import(ACCOUNT, ExportFormat::JsonLines, dump.as_slice())
+        .await
+        .unwrap();
+    assert_eq!(progress.read, ids.len() as u64);
+    assert_eq!(progress.imported, 0);
+    assert_eq!(progress.duplicates, ids.len() as u64);
+    assert_eq!(
+        to.count(ACCOUNT, Filter::All).await.unwrap(),
+        ids.len() as u64
+    );
+}
+
+#[tokio::test]
+async fn restores_snapshots_with_ids_provenance_and_tombstones() {
+    let (from, ids) = source().await;
+    from.set_lifecycle(ACCOUNT, &ids[0], LifecycleState::Tombstoned, Some("reorg"))
+        .await
+        .unwrap();
+    // Records archived in the millisecond the snapshot starts are left out of it.
+    tokio::time::sleep(Duration::from_millis(2)).await;
+    let dir = std::env::temp_dir().join(format!("lasr-archive-snapshot-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let manifest = from.snapshot(&[ACCOUNT], &dir).await.unwrap();
+    assert_eq!(manifest.record_types[0].records, ids.len() as u64);
+
+    let to = store("destination");
+    assert_eq!(to.restore(&dir).await.unwrap(), ids.len() as u64);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    for id in &ids {
+        let original = envelope(&from.with_tombstones(), id).await;
+        let restored = envelope(&to.with_tombstones(), id).await;
+        assert_eq!(restored.record, original.record, "{}", id);
+        assert_eq!(restored.archived_at, original.archived_at, "{}", id);
+        assert_eq!(restored.node_id.as_deref(), Some("source"), "{}", id);
+        assert_eq!(restored.tags, original.tags, "{}", id);
+        assert_eq!(restored.lifecycle, original.lifecycle, "{}", id);
+    }
+    let tombstone = envelope(&to.with_tombstones(), &ids[0]).await;
+    assert_eq!(tombstone.lifecycle.state, LifecycleState::Tombstoned);
+    assert_eq!(tombstone.lifecycle.reason.as_deref(), Some("reorg"));
+    let found: Option<Document> = to.find_by_id(ACCOUNT, &ids[0]).await.unwrap();
+    assert!(found.is_none());
+}
@@ -0,0 +1,106 @@
+use bson::Document;
+use lasr_archive::{
+    ArchiveBackends, ArchiveErrorKind, ArchiveRecord, ArchiveRecordType, ArchiveStore,
+    ArchiveStoreBuilder, Filter, IndexSpec, ACCOUNT_ADDRESS_FIELD,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Account {
+    owner_address: String,
+    nonce: u64,
+}
+
+impl ArchiveRecord for Account {
+    const KEY_FIELD: Option<&'static str> = Some(ACCOUNT_ADDRESS_FIELD);
+    const SCHEMA_VERSION: u32 = 2;
+
+    fn record_type() -> ArchiveRecordType {
+        ArchiveRecordType::Account
+    }
+
+    fn indexes() -> Vec<IndexSpec> {
+        vec![IndexSpec::new(ACCOUNT_ADDRESS_FIELD).unique()]
+    }
+}
+
+/// A record without a key field.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Receipt {
+    status: String,
+}
+
+impl ArchiveRecord for Receipt {
+    fn record_type() -> ArchiveRecordType {
+        ArchiveRecordType::Receipt
+    }
+}
+
+fn account(address: &str, nonce: u64) -> Account {
+    Account {
+        owner_address: address.to_string(),
+        nonce,
+    }
+}
+
+#[tokio::test]
+async fn archives_records_as_their_record_type() {
+    let store = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::InMemory)
+        .datastore("typed".to_string())
+        .record::<Account>()
+        .build()
+        .unwrap();
+    let accounts = store.typed::<Account>();
+    accounts.ensure_indexes().await.unwrap();
+
+    let outcome = accounts.create(account("0x01", 0)).await.unwrap();
+    let id = outcome.id().unwrap();
+    assert_eq!(
+        accounts.find_by_id(id).await.unwrap(),
+        Some(account("0x01", 0))
+    );
+    // Records are archived as their type's record type, with its schema version.
+    let stored = store.memory_records(ArchiveRecordType::Account).unwrap();
+    assert_eq!(stored.len(), 1);
+    assert!(store
+        .memory_records(ArchiveRecordType::Receipt)
+        .unwrap()
+        .is_empty());
+    let found: Vec<Document> = store
+        .query(ArchiveRecordType::Account, Filter::schema_version(2))
+        .await
+        .unwrap();
+    assert_eq!(found.len(), 1);
+
+    // The unique index of the type is enforced.
+    let error = accounts.create(account("0x01", 1)).await.unwrap_err();
+    assert_eq!(error.kind(), ArchiveErrorKind::DuplicateKey);
+}
+
+#[tokio::test]
+async fn finds_and_upserts_records_by_key() {
+    let store = ArchiveStore::in_memory();
+    let accounts = store.typed::<Account>();
+    let id = accounts.upsert(account("0x01", 0)).await.unwrap();
+    accounts.upsert(account("0x02", 0)).await.unwrap();
+
+    // Upserting a record with the same key replaces it.
+    assert_eq!(accounts.upsert(account("0x01", 1)).await.unwrap(), id);
+    assert_eq!(accounts.count(Filter::All).await.unwrap(), 2);
+    assert_eq!(
+        accounts.find_by_key("0x01").await.unwrap(),
+        Some(account("0x01", 1))
+    );
+    assert_eq!(accounts.find_by_key("0x03").await.unwrap(), None);
+
+    // Types without a key field can't be found or upserted by key.
+    let receipts = store.typed::<Receipt>();
+    let receipt = Receipt {
+        status: "ok".to_string(),
+    };
+    let error = receipts.upsert(receipt).await.unwrap_err();
+    assert_eq!(error.kind(), ArchiveErrorKind::InvalidInput);
+    let error = receipts.find_by_key("ok").await.unwrap_err();
+    assert_eq!(error.kind(), ArchiveErrorKind::InvalidInput);
+}
@@ -0,0 +1,106 @@
+use bson::doc;
+use lasr_archive::{
+    ArchiveBackends, ArchiveErrorKind, ArchiveRecordType, ArchiveStore, ArchiveStoreBuilder,
+    Filter, WriterOptions,
+};
+use std::time::Duration;
+
+const ACCOUNT: ArchiveRecordType = ArchiveRecordType::Account;
+
+/// Options that only write records when flushed, shut down or a batch fills.
+fn options(capacity: usize) -> WriterOptions {
+    WriterOptions {
+        capacity,
+        batch_size: 500,
+        flush_interval: Duration::from_secs(3600),
+    }
+}
+
+async fn count(store: &ArchiveStore) -> u64 {
+    store.count(ACCOUNT, Filter::All).await.unwrap()
+}
+
+#[tokio::test]
+async fn flushing_writes_the_queued_records() {
+    let store = ArchiveStore::in_memory();
+    let writer = store.writer(options(100)).unwrap();
+    for nonce in 0..3 {
+        writer
+            .write(ACCOUNT, doc! { "nonce": nonce })
+            .await
+            .unwrap();
+    }
+    writer.flush().await.unwrap();
+    assert_eq!(count(&store).await, 3);
+
+    writer.write(ACCOUNT, doc! { "nonce": 3 }).await.unwrap();
+    writer.flush().await.unwrap();
+    assert_eq!(count(&store).await, 4);
+}
+
+#[tokio::test]
+async fn shutting_down_writes_the_queued_records() {
+    let store = ArchiveStore::in_memory();
+    let writer = store.writer(options(100)).unwrap();
+    let clone = writer.clone();
+    for nonce in 0..5 {
+        writer
+            .write(ACCOUNT, doc! { "nonce": nonce })
+            .await
+            .unwrap();
+    }
+    writer.shutdown().await.unwrap();
+    assert_eq!(count(&store).await, 5);
+
+    let error = clone.write(ACCOUNT, doc! { "nonce": 5 }).await.unwrap_err();
+    assert_eq!(error.kind(), ArchiveErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn waits_for_room_when_the_queue_is_full() {
+    let store = ArchiveStore::in_memory();
+    let writer = store.writer(options(2)).unwrap();
+    // The background task doesn't run until this test awaits, so the queue fills up.
+    writer.try_write(ACCOUNT, doc! { "nonce": 0 }).unwrap();
+    writer.try_write(ACCOUNT, doc! { "nonce": 1 }).unwrap();
+    let error = writer.try_write(ACCOUNT, doc! { "nonce": 2 }).unwrap_err();
+    assert_eq!(error.kind(), ArchiveErrorKind::BackendUnavailable);
+
+    // Writing waits for the task to take records off the queue instead.
+    writer.write(ACCOUNT, doc! { "nonce": 2 }).await.unwrap();
+    writer.shutdown().await.unwrap();
+    assert_eq!(count(&store).await, 3);
+}
+
+#[tokio::test]
+async fn reports_failed_batches_on_the_next_flush_or_shutdown() {
+    // A filesystem archive whose root is a plain file fails every write.
+    let root = std::env::temp_dir().join(format!("lasr-archive-writer-{}", std::process::id()));
+    std::fs::write(&root, b"").unwrap();
+    let store = ArchiveStoreBuilder::default()
+        .backend(ArchiveBackends::Filesystem { root: root.clone() })
+        .datastore("writer".to_string())
+        .build()
+        .unwrap();
+    let writer = store.writer(options(100)).unwrap();
+
+    writer.write(ACCOUNT, doc! { "nonce": 0 }).await.unwrap();
+    writer.write(ACCOUNT, doc! { "nonce": 1 }).await.unwrap();
+    let error = writer.flush().await.unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("2 buffered records failed to be written"),
+        "{:#}",
+        error
+    );
+    // Each failure is only reported once.
+    writer.flush().await.unwrap();
+
+    writer.write(ACCOUNT, doc! { "nonce": 2 }).await.unwrap();
+    let error = writer.shutdown().await.unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("1 buffered records failed to be written"),
+        "{:#}",
+        error
+    );
+    std::fs::remove_file(root).unwrap();
+}